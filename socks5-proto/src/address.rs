@@ -1,14 +1,16 @@
-use bytes::BufMut;
+use bytes::{Buf, BufMut};
 use std::{
     fmt::{Display, Formatter, Result as FmtResult},
-    io::Error as IoError,
+    io::{Error as IoError, ErrorKind},
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
-    vec,
+    str::FromStr,
 };
 use thiserror::Error;
 use tokio::io::{AsyncRead, AsyncReadExt};
 
 /// SOCKS5 address
+///
+/// An IPv6 [`Address::SocketAddress`] may carry a zone, i.e. the scope id of a link-local address such as `[fe80::1%3]:80`, which is parsed by [`Address::from_str()`](std::str::FromStr) and rendered by [`Display`]. The SOCKS5 wire format has no room for it though, so the zone (and the flow info) is dropped when the address is encoded, and addresses decoded from the wire never have one. A server relaying to link-local destinations has to pick the zone itself, e.g. a default interface configured by the operator.
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Address {
     SocketAddress(SocketAddr),
@@ -20,7 +22,21 @@ impl Address {
     const ATYP_FQDN: u8 = 0x03;
     const ATYP_IPV6: u8 = 0x04;
 
+    /// The length of the longest address, a domain name of 255 bytes
+    pub const MAX_SERIALIZED_LEN: usize = 1 + 1 + 255 + 2;
+
+    #[inline]
     pub(crate) async fn read_from<R>(stream: &mut R) -> Result<Self, AddressError>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::read_from_with_max_domain_len(stream, u8::MAX).await
+    }
+
+    pub(crate) async fn read_from_with_max_domain_len<R>(
+        stream: &mut R,
+        max_domain_len: u8,
+    ) -> Result<Self, AddressError>
     where
         R: AsyncRead + Unpin,
     {
@@ -38,10 +54,17 @@ impl Address {
                 Ok(Self::SocketAddress(SocketAddr::from((addr, port))))
             }
             Self::ATYP_FQDN => {
-                let len = stream.read_u8().await? as usize;
+                let len = stream.read_u8().await?;
 
-                let mut buf = vec![0; len + 2];
-                stream.read_exact(&mut buf).await?;
+                if len > max_domain_len {
+                    return Err(AddressError::DomainTooLong {
+                        len,
+                        max: max_domain_len,
+                    });
+                }
+
+                let len = len as usize;
+                let mut buf = read_vec(stream, len + 2).await?;
 
                 let port = u16::from_be_bytes([buf[len], buf[len + 1]]);
                 buf.truncate(len);
@@ -71,6 +94,47 @@ impl Address {
         }
     }
 
+    pub(crate) fn read_from_buf<B: Buf>(buf: &mut B) -> Result<Self, AddressError> {
+        let atyp = read_bytes(buf, 1)?[0];
+
+        match atyp {
+            Self::ATYP_IPV4 => {
+                let buf = read_bytes(buf, 6)?;
+
+                let addr = Ipv4Addr::new(buf[0], buf[1], buf[2], buf[3]);
+
+                let port = u16::from_be_bytes([buf[4], buf[5]]);
+
+                Ok(Self::SocketAddress(SocketAddr::from((addr, port))))
+            }
+            Self::ATYP_FQDN => {
+                let len = read_bytes(buf, 1)?[0] as usize;
+
+                let mut addr = read_bytes(buf, len + 2)?;
+
+                let port = u16::from_be_bytes([addr[len], addr[len + 1]]);
+                addr.truncate(len);
+
+                Ok(Self::DomainAddress(addr, port))
+            }
+            Self::ATYP_IPV6 => {
+                let buf = read_bytes(buf, 18)?;
+
+                let mut segments = [0; 8];
+                for (i, seg) in segments.iter_mut().enumerate() {
+                    *seg = u16::from_be_bytes([buf[i * 2], buf[i * 2 + 1]]);
+                }
+
+                let addr = Ipv6Addr::from(segments);
+
+                let port = u16::from_be_bytes([buf[16], buf[17]]);
+
+                Ok(Self::SocketAddress(SocketAddr::from((addr, port))))
+            }
+            atyp => Err(AddressError::InvalidType(atyp)),
+        }
+    }
+
     pub(crate) fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         match self {
             Self::SocketAddress(SocketAddr::V4(addr)) => {
@@ -94,6 +158,14 @@ impl Address {
         }
     }
 
+    /// Fails with [`ErrorKind::InvalidInput`] if the address is a domain name longer than 255 bytes, which cannot be encoded
+    pub(crate) fn check_encodable(&self) -> Result<(), IoError> {
+        match self {
+            Self::SocketAddress(_) => Ok(()),
+            Self::DomainAddress(addr, _) => check_field_len("domain name", addr.len()),
+        }
+    }
+
     pub fn unspecified() -> Self {
         Address::SocketAddress(SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0))
     }
@@ -120,10 +192,121 @@ impl Display for Address {
     }
 }
 
+impl FromStr for Address {
+    type Err = AddressParseError;
+
+    /// Parses `ip:port`, `[ipv6]:port` or `domain:port`.
+    ///
+    /// An IPv6 address may have a zone given as the numeric interface index, e.g. `[fe80::1%3]:80`. Interface names are not resolved.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Self::SocketAddress(addr));
+        }
+
+        let (host, port) = s.rsplit_once(':').ok_or(AddressParseError::MissingPort)?;
+        let port = port.parse().map_err(|_| AddressParseError::InvalidPort)?;
+
+        if host.starts_with('[') || host.contains(':') {
+            return Err(match host.split_once('%') {
+                Some((_, zone)) if zone.parse::<u32>().is_err() => {
+                    AddressParseError::UnsupportedZone(zone.trim_end_matches(']').to_owned())
+                }
+                _ => AddressParseError::InvalidIpv6,
+            });
+        }
+
+        if host.is_empty() || host.len() > u8::MAX as usize {
+            return Err(AddressParseError::InvalidDomain);
+        }
+
+        Ok(Self::DomainAddress(host.as_bytes().to_vec(), port))
+    }
+}
+
+/// Errors parsing an [`Address`] from a string
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+pub enum AddressParseError {
+    #[error("Missing port")]
+    MissingPort,
+    #[error("Invalid port")]
+    InvalidPort,
+    #[error("Invalid IPv6 address")]
+    InvalidIpv6,
+    #[error("Unsupported zone {0:?}, use the numeric interface index")]
+    UnsupportedZone(String),
+    #[error("Domain name is empty or longer than 255 bytes")]
+    InvalidDomain,
+}
+
+fn read_bytes<B: Buf>(buf: &mut B, len: usize) -> Result<Vec<u8>, IoError> {
+    if buf.remaining() < len {
+        return Err(IoError::from(ErrorKind::UnexpectedEof));
+    }
+
+    let mut bytes = Vec::with_capacity(len);
+    bytes.put(buf.take(len));
+
+    Ok(bytes)
+}
+
+/// Reads exactly `len` bytes from `r` into a new `Vec`, without zeroing it before reading into it.
+/// Fails with [`ErrorKind::InvalidInput`] if a field of `len` bytes does not fit its one-byte length prefix
+pub(crate) fn check_field_len(field: &str, len: usize) -> Result<(), IoError> {
+    if len > u8::MAX as usize {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!("{field} of {len} bytes is longer than 255 bytes"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Writes a message of `len` bytes to the front of `buf` with `write`, failing with [`ErrorKind::InvalidInput`] if `buf` is shorter
+pub(crate) fn encode_into(
+    buf: &mut [u8],
+    len: usize,
+    write: impl FnOnce(&mut &mut [u8]),
+) -> Result<&[u8], IoError> {
+    if buf.len() < len {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "buffer of {} bytes is shorter than the {len} bytes message",
+                buf.len()
+            ),
+        ));
+    }
+
+    let dst = &mut buf[..len];
+    write(&mut &mut *dst);
+    Ok(dst)
+}
+
+pub(crate) async fn read_vec<R>(r: &mut R, len: usize) -> Result<Vec<u8>, IoError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buf = Vec::with_capacity(len);
+
+    while buf.len() < len {
+        let remaining = len - buf.len();
+
+        // limited so the `Vec` never grows past `len`, and only its spare capacity is read into
+        if r.read_buf(&mut (&mut buf).limit(remaining)).await? == 0 {
+            return Err(IoError::from(ErrorKind::UnexpectedEof));
+        }
+    }
+
+    Ok(buf)
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum AddressError {
     #[error(transparent)]
     Io(#[from] IoError),
     #[error("Invalid address type {0:#04x}")]
     InvalidType(u8),
+    #[error("Domain name of length {len} too long, at most {max} accepted")]
+    DomainTooLong { len: u8, max: u8 },
 }
@@ -1,13 +1,14 @@
 //! Error types for the SOCKS5 protocol
 
 use crate::{handshake::Method, Command, Reply};
-use std::io::{Error as IoError, ErrorKind};
+use std::io::Error as IoError;
 use thiserror::Error;
 
 /// Errors may occured during protocol header parsing
 ///
 /// Since the process of parsing the protocol header follows certain steps, some sub-types contain other previously parsed data for better error reporting.
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum ProtocolError {
     #[error("Unsupported SOCKS version {version:#04x}")]
     ProtocolVersion { version: u8 },
@@ -19,6 +20,9 @@ pub enum ProtocolError {
         methods: Vec<Method>,
     },
 
+    #[error("Too many handshake methods {nmethods}, at most {max} accepted")]
+    TooManyHandshakeMethods { version: u8, nmethods: u8, max: u8 },
+
     #[error("Unsupported command {command:#04x}")]
     InvalidCommand { version: u8, command: u8 },
 
@@ -32,6 +36,14 @@ pub enum ProtocolError {
         address_type: u8,
     },
 
+    #[error("Domain name of length {len} in request too long, at most {max} accepted")]
+    DomainTooLongInRequest {
+        version: u8,
+        command: Command,
+        len: u8,
+        max: u8,
+    },
+
     #[error("Unsupported address type in response {address_type:#04x}")]
     InvalidAddressTypeInResponse {
         version: u8,
@@ -45,7 +57,7 @@ pub enum ProtocolError {
 
 impl From<ProtocolError> for IoError {
     fn from(err: ProtocolError) -> Self {
-        IoError::new(ErrorKind::Other, err)
+        IoError::other(err)
     }
 }
 
@@ -62,7 +74,7 @@ impl From<Error> for IoError {
     fn from(err: Error) -> Self {
         match err {
             Error::Io(err) => err,
-            err => IoError::new(ErrorKind::Other, err),
+            err => IoError::other(err),
         }
     }
 }
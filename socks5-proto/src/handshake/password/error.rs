@@ -1,8 +1,9 @@
-use std::io::{Error as IoError, ErrorKind};
+use std::io::Error as IoError;
 use thiserror::Error;
 
 /// Errors may occured during SOCKS5 password authentication
 #[derive(Debug, Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error(transparent)]
     Io(#[from] IoError),
@@ -12,13 +13,19 @@ pub enum Error {
 
     #[error("Unsupported sub-negotiation status {status:#04x}")]
     SubNegotiationStatus { version: u8, status: u8 },
+
+    #[error("Username of length {len} too long, at most {max} accepted")]
+    UsernameTooLong { len: u8, max: u8 },
+
+    #[error("Password of length {len} too long, at most {max} accepted")]
+    PasswordTooLong { len: u8, max: u8 },
 }
 
 impl From<Error> for IoError {
     fn from(err: Error) -> Self {
         match err {
             Error::Io(err) => err,
-            err => IoError::new(ErrorKind::Other, err),
+            err => IoError::other(err),
         }
     }
 }
@@ -1,5 +1,6 @@
 use super::Error;
-use bytes::{BufMut, BytesMut};
+use crate::ParseLimits;
+use bytes::BufMut;
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -20,11 +21,23 @@ pub struct Request {
 }
 
 impl Request {
+    /// The length of the longest request, one with a username and a password of 255 bytes each
+    pub const MAX_SERIALIZED_LEN: usize = 1 + 1 + 255 + 1 + 255;
+
     pub const fn new(username: Vec<u8>, password: Vec<u8>) -> Self {
         Self { username, password }
     }
 
+    #[inline]
     pub async fn read_from<R>(r: &mut R) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::read_from_with_limits(r, &ParseLimits::default()).await
+    }
+
+    /// Reads a password request as [`Request::read_from()`] does, failing with [`Error::UsernameTooLong`] or [`Error::PasswordTooLong`] before reading a field longer than [`ParseLimits::max_username_len`] or [`ParseLimits::max_password_len`].
+    pub async fn read_from_with_limits<R>(r: &mut R, limits: &ParseLimits) -> Result<Self, Error>
     where
         R: AsyncRead + Unpin,
     {
@@ -35,12 +48,26 @@ impl Request {
         }
 
         let ulen = r.read_u8().await?;
-        let mut username = vec![0; ulen as usize];
-        r.read_exact(&mut username).await?;
+
+        if ulen > limits.max_username_len {
+            return Err(Error::UsernameTooLong {
+                len: ulen,
+                max: limits.max_username_len,
+            });
+        }
+
+        let username = crate::address::read_vec(r, ulen as usize).await?;
 
         let plen = r.read_u8().await?;
-        let mut password = vec![0; plen as usize];
-        r.read_exact(&mut password).await?;
+
+        if plen > limits.max_password_len {
+            return Err(Error::PasswordTooLong {
+                len: plen,
+                max: limits.max_password_len,
+            });
+        }
+
+        let password = crate::address::read_vec(r, plen as usize).await?;
 
         Ok(Self::new(username, password))
     }
@@ -49,13 +76,23 @@ impl Request {
     where
         W: AsyncWrite + Unpin,
     {
-        let mut buf = BytesMut::with_capacity(self.serialized_len());
-        self.write_to_buf(&mut buf);
-        w.write_all(&buf).await?;
+        let mut buf = [0; Self::MAX_SERIALIZED_LEN];
+        w.write_all(self.encode_into(&mut buf)?).await?;
 
         Ok(())
     }
 
+    /// Encodes the request to the front of `buf` without allocating, returning the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if the username or the password is longer than 255 bytes, or if `buf` is shorter than [`Request::serialized_len()`]. [`Request::MAX_SERIALIZED_LEN`] bytes are always enough.
+    pub fn encode_into<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], IoError> {
+        crate::address::check_field_len("username", self.username.len())?;
+        crate::address::check_field_len("password", self.password.len())?;
+        crate::address::encode_into(buf, self.serialized_len(), |buf| self.write_to_buf(buf))
+    }
+
     pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_u8(super::SUBNEGOTIATION_VERSION);
 
@@ -1,5 +1,5 @@
 use super::Error;
-use bytes::{BufMut, BytesMut};
+use bytes::BufMut;
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -19,6 +19,9 @@ pub struct Response {
 }
 
 impl Response {
+    /// The length of every response
+    pub const MAX_SERIALIZED_LEN: usize = 1 + 1;
+
     const FAILED: u8 = 0xff;
     const SUCCEEDED: u8 = 0x00;
 
@@ -54,13 +57,21 @@ impl Response {
     where
         W: AsyncWrite + Unpin,
     {
-        let mut buf = BytesMut::with_capacity(self.serialized_len());
-        self.write_to_buf(&mut buf);
-        w.write_all(&buf).await?;
+        let mut buf = [0; Self::MAX_SERIALIZED_LEN];
+        w.write_all(self.encode_into(&mut buf)?).await?;
 
         Ok(())
     }
 
+    /// Encodes the response to the front of `buf` without allocating, returning the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if `buf` is shorter than [`Response::serialized_len()`]. [`Response::MAX_SERIALIZED_LEN`] bytes are always enough.
+    pub fn encode_into<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], IoError> {
+        crate::address::encode_into(buf, self.serialized_len(), |buf| self.write_to_buf(buf))
+    }
+
     pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_u8(super::SUBNEGOTIATION_VERSION);
 
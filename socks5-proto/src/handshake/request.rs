@@ -1,6 +1,6 @@
 use super::Method;
-use crate::{Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
+use crate::{Error, ParseLimits, ProtocolError};
+use bytes::BufMut;
 use std::{
     io::Error as IoError,
     mem::{self, ManuallyDrop},
@@ -22,11 +22,23 @@ pub struct Request {
 }
 
 impl Request {
+    /// The length of the longest request, one offering 255 methods
+    pub const MAX_SERIALIZED_LEN: usize = 1 + 1 + 255;
+
     pub const fn new(methods: Vec<Method>) -> Self {
         Self { methods }
     }
 
+    #[inline]
     pub async fn read_from<R>(r: &mut R) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::read_from_with_limits(r, &ParseLimits::default()).await
+    }
+
+    /// Reads a handshake request as [`Request::read_from()`] does, failing with [`ProtocolError::TooManyHandshakeMethods`] before reading the methods if there are more than [`ParseLimits::max_methods`].
+    pub async fn read_from_with_limits<R>(r: &mut R, limits: &ParseLimits) -> Result<Self, Error>
     where
         R: AsyncRead + Unpin,
     {
@@ -39,8 +51,16 @@ impl Request {
         }
 
         let mlen = r.read_u8().await?;
-        let mut methods = vec![0; mlen as usize];
-        r.read_exact(&mut methods).await?;
+
+        if mlen > limits.max_methods {
+            return Err(Error::Protocol(ProtocolError::TooManyHandshakeMethods {
+                version: ver,
+                nmethods: mlen,
+                max: limits.max_methods,
+            }));
+        }
+
+        let methods = crate::address::read_vec(r, mlen as usize).await?;
 
         let methods = unsafe {
             let mut methods = ManuallyDrop::new(methods);
@@ -59,18 +79,27 @@ impl Request {
     where
         W: AsyncWrite + Unpin,
     {
-        let mut buf = BytesMut::with_capacity(self.serialized_len());
-        self.write_to_buf(&mut buf);
-        w.write_all(&buf).await?;
+        let mut buf = [0; Self::MAX_SERIALIZED_LEN];
+        w.write_all(self.encode_into(&mut buf)?).await?;
 
         Ok(())
     }
 
+    /// Encodes the request to the front of `buf` without allocating, returning the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if there are more than 255 methods, or if `buf` is shorter than [`Request::serialized_len()`]. [`Request::MAX_SERIALIZED_LEN`] bytes are always enough.
+    pub fn encode_into<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], IoError> {
+        crate::address::check_field_len("method list", self.methods.len())?;
+        crate::address::encode_into(buf, self.serialized_len(), |buf| self.write_to_buf(buf))
+    }
+
     pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_u8(crate::SOCKS_VERSION);
         buf.put_u8(self.methods.len() as u8);
 
-        let methods = unsafe { mem::transmute(self.methods.as_slice()) };
+        let methods = unsafe { mem::transmute::<&[Method], &[u8]>(self.methods.as_slice()) };
         buf.put_slice(methods);
     }
 
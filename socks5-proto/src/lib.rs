@@ -3,6 +3,7 @@
 mod address;
 mod command;
 mod error;
+mod limits;
 mod reply;
 mod request;
 mod response;
@@ -11,9 +12,10 @@ mod udp;
 pub mod handshake;
 
 pub use self::{
-    address::Address,
+    address::{Address, AddressParseError},
     command::Command,
     error::{Error, ProtocolError},
+    limits::ParseLimits,
     reply::Reply,
     request::Request,
     response::Response,
@@ -0,0 +1,29 @@
+/// Limits on the variable-length fields of the negotiation messages, checked as their lengths are read, before the fields themselves
+///
+/// The defaults are the maximums of the protocol, so [`ParseLimits::default()`] accepts exactly what the plain `read_from()` methods do.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ParseLimits {
+    /// The maximum `NMETHODS` of a handshake request
+    pub max_methods: u8,
+
+    /// The maximum `ULEN` of a password sub-negotiation request
+    pub max_username_len: u8,
+
+    /// The maximum `PLEN` of a password sub-negotiation request
+    pub max_password_len: u8,
+
+    /// The maximum length of a domain name in a request
+    pub max_domain_len: u8,
+}
+
+impl Default for ParseLimits {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            max_methods: u8::MAX,
+            max_username_len: u8::MAX,
+            max_password_len: u8::MAX,
+            max_domain_len: u8::MAX,
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use crate::{address::AddressError, Address, Command, Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
+use crate::{address::AddressError, Address, Command, Error, ParseLimits, ProtocolError};
+use bytes::BufMut;
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -19,11 +19,23 @@ pub struct Request {
 }
 
 impl Request {
+    /// The length of the longest request, one with a domain name of 255 bytes
+    pub const MAX_SERIALIZED_LEN: usize = 1 + 1 + 1 + Address::MAX_SERIALIZED_LEN;
+
     pub const fn new(command: Command, address: Address) -> Self {
         Self { command, address }
     }
 
+    #[inline]
     pub async fn read_from<R>(r: &mut R) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        Self::read_from_with_limits(r, &ParseLimits::default()).await
+    }
+
+    /// Reads a request as [`Request::read_from()`] does, failing with [`ProtocolError::DomainTooLongInRequest`] before reading a domain name longer than [`ParseLimits::max_domain_len`].
+    pub async fn read_from_with_limits<R>(r: &mut R, limits: &ParseLimits) -> Result<Self, Error>
     where
         R: AsyncRead + Unpin,
     {
@@ -43,16 +55,26 @@ impl Request {
 
         let _ = r.read_u8().await?;
 
-        let addr = Address::read_from(r).await.map_err(|err| match err {
-            AddressError::Io(err) => Error::Io(err),
-            AddressError::InvalidType(code) => {
-                Error::Protocol(ProtocolError::InvalidAddressTypeInRequest {
-                    version: ver,
-                    command: cmd,
-                    address_type: code,
-                })
-            }
-        })?;
+        let addr = Address::read_from_with_max_domain_len(r, limits.max_domain_len)
+            .await
+            .map_err(|err| match err {
+                AddressError::Io(err) => Error::Io(err),
+                AddressError::InvalidType(code) => {
+                    Error::Protocol(ProtocolError::InvalidAddressTypeInRequest {
+                        version: ver,
+                        command: cmd,
+                        address_type: code,
+                    })
+                }
+                AddressError::DomainTooLong { len, max } => {
+                    Error::Protocol(ProtocolError::DomainTooLongInRequest {
+                        version: ver,
+                        command: cmd,
+                        len,
+                        max,
+                    })
+                }
+            })?;
 
         Ok(Self::new(cmd, addr))
     }
@@ -61,13 +83,22 @@ impl Request {
     where
         W: AsyncWrite + Unpin,
     {
-        let mut buf = BytesMut::with_capacity(self.serialized_len());
-        self.write_to_buf(&mut buf);
-        w.write_all(&buf).await?;
+        let mut buf = [0; Self::MAX_SERIALIZED_LEN];
+        w.write_all(self.encode_into(&mut buf)?).await?;
 
         Ok(())
     }
 
+    /// Encodes the request to the front of `buf` without allocating, returning the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if the address is a domain name longer than 255 bytes, or if `buf` is shorter than [`Request::serialized_len()`]. [`Request::MAX_SERIALIZED_LEN`] bytes are always enough.
+    pub fn encode_into<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], IoError> {
+        self.address.check_encodable()?;
+        crate::address::encode_into(buf, self.serialized_len(), |buf| self.write_to_buf(buf))
+    }
+
     pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_u8(crate::SOCKS_VERSION);
         buf.put_u8(u8::from(self.command));
@@ -1,6 +1,6 @@
 use crate::{address::AddressError, Address, Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
-use std::io::Error as IoError;
+use bytes::{Buf, BufMut};
+use std::io::{Error as IoError, ErrorKind};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// SOCKS5 UDP packet header
@@ -19,6 +19,9 @@ pub struct UdpHeader {
 }
 
 impl UdpHeader {
+    /// The length of the longest header, one with a domain name of 255 bytes
+    pub const MAX_SERIALIZED_LEN: usize = 2 + 1 + Address::MAX_SERIALIZED_LEN;
+
     pub const fn new(frag: u8, address: Address) -> Self {
         Self { frag, address }
     }
@@ -39,6 +42,33 @@ impl UdpHeader {
                     address_type: code,
                 })
             }
+            AddressError::DomainTooLong { .. } => unreachable!("domain length not limited"),
+        })?;
+
+        Ok(Self::new(frag, addr))
+    }
+
+    /// Reads a SOCKS5 UDP header from the front of a buffer, advancing it past the header.
+    ///
+    /// This is the synchronous counterpart of [`UdpHeader::read_from()`] for datagrams that are already received in whole. If the buffer is too short, an IO error of kind [`ErrorKind::UnexpectedEof`] is returned.
+    pub fn read_from_buf<B: Buf>(buf: &mut B) -> Result<Self, Error> {
+        if buf.remaining() < 3 {
+            return Err(Error::Io(IoError::from(ErrorKind::UnexpectedEof)));
+        }
+
+        buf.advance(2);
+
+        let frag = buf.get_u8();
+
+        let addr = Address::read_from_buf(buf).map_err(|err| match err {
+            AddressError::Io(err) => Error::Io(err),
+            AddressError::InvalidType(code) => {
+                Error::Protocol(ProtocolError::InvalidAddressTypeInUdpHeader {
+                    frag,
+                    address_type: code,
+                })
+            }
+            AddressError::DomainTooLong { .. } => unreachable!("domain length not limited"),
         })?;
 
         Ok(Self::new(frag, addr))
@@ -48,13 +78,22 @@ impl UdpHeader {
     where
         W: AsyncWrite + Unpin,
     {
-        let mut buf = BytesMut::with_capacity(self.serialized_len());
-        self.write_to_buf(&mut buf);
-        w.write_all(&buf).await?;
+        let mut buf = [0; Self::MAX_SERIALIZED_LEN];
+        w.write_all(self.encode_into(&mut buf)?).await?;
 
         Ok(())
     }
 
+    /// Encodes the header to the front of `buf` without allocating, returning the bytes written.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) if the address is a domain name longer than 255 bytes, or if `buf` is shorter than [`UdpHeader::serialized_len()`]. [`UdpHeader::MAX_SERIALIZED_LEN`] bytes are always enough.
+    pub fn encode_into<'a>(&self, buf: &'a mut [u8]) -> Result<&'a [u8], IoError> {
+        self.address.check_encodable()?;
+        crate::address::encode_into(buf, self.serialized_len(), |buf| self.write_to_buf(buf))
+    }
+
     pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         buf.put_bytes(0x00, 2);
         buf.put_u8(self.frag);
@@ -0,0 +1,62 @@
+use bytes::BytesMut;
+use socks5_proto::{Address, AddressParseError, UdpHeader};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV6};
+
+#[test]
+fn zoned_ipv6_is_parsed_and_rendered() {
+    let addr = "[fe80::1%3]:80".parse::<Address>().unwrap();
+
+    let Address::SocketAddress(SocketAddr::V6(v6)) = addr else {
+        panic!("unexpected address {addr:?}")
+    };
+    assert_eq!(v6.ip().segments(), [0xfe80, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!((v6.port(), v6.scope_id()), (80, 3));
+
+    assert_eq!(addr.to_string(), "[fe80::1%3]:80");
+    assert_eq!(addr.to_string().parse::<Address>().unwrap(), addr);
+}
+
+#[test]
+fn addresses_are_parsed() {
+    assert_eq!(
+        "127.0.0.1:1080".parse::<Address>().unwrap(),
+        Address::SocketAddress(SocketAddr::from((Ipv4Addr::LOCALHOST, 1080)))
+    );
+    assert_eq!(
+        "example.com:443".parse::<Address>().unwrap(),
+        Address::DomainAddress(b"example.com".to_vec(), 443)
+    );
+
+    assert_eq!(
+        "[fe80::1%eth0]:80".parse::<Address>(),
+        Err(AddressParseError::UnsupportedZone("eth0".to_owned()))
+    );
+    assert_eq!(
+        "[fe80::1:80".parse::<Address>(),
+        Err(AddressParseError::InvalidIpv6)
+    );
+    assert_eq!(
+        "example.com".parse::<Address>(),
+        Err(AddressParseError::MissingPort)
+    );
+    assert_eq!(
+        "example.com:http".parse::<Address>(),
+        Err(AddressParseError::InvalidPort)
+    );
+    assert_eq!(
+        ":80".parse::<Address>(),
+        Err(AddressParseError::InvalidDomain)
+    );
+}
+
+#[test]
+fn zone_is_lost_on_the_wire() {
+    let addr = "[fe80::1%3]:80".parse::<Address>().unwrap();
+
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, addr).write_to_buf(&mut buf);
+
+    let header = UdpHeader::read_from_buf(&mut buf).unwrap();
+    let expected = SocketAddrV6::new("fe80::1".parse().unwrap(), 80, 0, 0);
+    assert_eq!(header.address, Address::SocketAddress(expected.into()));
+}
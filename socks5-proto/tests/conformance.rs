@@ -0,0 +1,457 @@
+//! Conformance vectors of the wire formats of RFC 1928 (SOCKS5) and RFC 1929 (username / password authentication).
+//!
+//! Every well-formed vector is parsed, checked against the expected message, and re-serialized byte-for-byte. Every malformed one is checked against the error variant it must be rejected with. The clients and servers named are those a vector is typical of, e.g. `curl` offering no authentication and username / password together.
+
+use bytes::BytesMut;
+use socks5_proto::{
+    handshake::{self, password, Method},
+    Address, Command, Error, ProtocolError, Reply, Request, Response, UdpHeader,
+};
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+fn v4(ip: [u8; 4], port: u16) -> Address {
+    Address::SocketAddress(SocketAddr::from((Ipv4Addr::from(ip), port)))
+}
+
+fn v6(ip: Ipv6Addr, port: u16) -> Address {
+    Address::SocketAddress(SocketAddr::from((ip, port)))
+}
+
+fn domain(name: &str, port: u16) -> Address {
+    Address::DomainAddress(name.as_bytes().to_vec(), port)
+}
+
+/// Asserts `vector` is fully consumed by `read`, yielding a message that `write` serializes back to `vector`.
+async fn round_trip<T, E, F>(vector: &[u8], read: F, write: impl Fn(&T, &mut BytesMut)) -> T
+where
+    E: std::fmt::Debug,
+    F: AsyncFnOnce(&mut &[u8]) -> Result<T, E>,
+{
+    let mut rest = vector;
+    let msg = read(&mut rest).await.unwrap();
+    assert!(rest.is_empty(), "{} trailing bytes", rest.len());
+
+    let mut buf = BytesMut::new();
+    write(&msg, &mut buf);
+    assert_eq!(&buf[..], vector);
+
+    msg
+}
+
+async fn handshake_request(vector: &[u8]) -> handshake::Request {
+    round_trip(
+        vector,
+        async |r| handshake::Request::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await
+}
+
+async fn request(vector: &[u8]) -> Request {
+    round_trip(
+        vector,
+        async |r| Request::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await
+}
+
+async fn response(vector: &[u8]) -> Response {
+    round_trip(
+        vector,
+        async |r| Response::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await
+}
+
+async fn udp_header(vector: &[u8]) -> UdpHeader {
+    let header = round_trip(
+        vector,
+        async |r| UdpHeader::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await;
+
+    let mut buf = vector;
+    let parsed = UdpHeader::read_from_buf(&mut buf).unwrap();
+    assert!(buf.is_empty());
+    assert_eq!(
+        (parsed.frag, &parsed.address),
+        (header.frag, &header.address)
+    );
+
+    header
+}
+
+fn assert_eof(err: Error) {
+    assert!(
+        matches!(&err, Error::Io(err) if err.kind() == ErrorKind::UnexpectedEof),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn handshake_requests() {
+    // RFC 1928 section 3, no authentication only, e.g. `curl --socks5` without credentials
+    let req = handshake_request(&[0x05, 0x01, 0x00]).await;
+    assert_eq!(req.methods, [Method::NONE]);
+
+    // no authentication and username / password, e.g. `curl --socks5` with credentials
+    let req = handshake_request(&[0x05, 0x02, 0x00, 0x02]).await;
+    assert_eq!(req.methods, [Method::NONE, Method::PASSWORD]);
+
+    // GSSAPI, username / password and no authentication, in the order of preference of the client
+    let req = handshake_request(&[0x05, 0x03, 0x01, 0x02, 0x00]).await;
+    assert_eq!(
+        req.methods,
+        [Method::GSSAPI, Method::PASSWORD, Method::NONE]
+    );
+
+    // a private method, X'80' to X'FE' being reserved for them
+    let req = handshake_request(&[0x05, 0x01, 0x80]).await;
+    assert_eq!(req.methods, [Method(0x80)]);
+}
+
+#[tokio::test]
+async fn handshake_responses() {
+    for (vector, method) in [
+        ([0x05, 0x00], Method::NONE),
+        ([0x05, 0x02], Method::PASSWORD),
+        // RFC 1928 section 3, no acceptable methods
+        ([0x05, 0xff], Method::UNACCEPTABLE),
+    ] {
+        let resp = round_trip(
+            &vector,
+            async |r| handshake::Response::read_from(r).await,
+            |msg, buf| msg.write_to_buf(buf),
+        )
+        .await;
+        assert_eq!(resp.method, method);
+    }
+}
+
+#[tokio::test]
+async fn password_requests() {
+    // RFC 1929 section 2
+    let vector = b"\x01\x04user\x06secret";
+    let req = round_trip(
+        vector,
+        async |r| password::Request::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await;
+    assert_eq!(
+        (&req.username[..], &req.password[..]),
+        (&b"user"[..], &b"secret"[..])
+    );
+
+    // the fields are opaque bytes, not necessarily UTF-8
+    let vector = b"\x01\x02\xc3\x28\x01\xff";
+    let req = round_trip(
+        vector,
+        async |r| password::Request::read_from(r).await,
+        |msg, buf| msg.write_to_buf(buf),
+    )
+    .await;
+    assert_eq!(
+        (&req.username[..], &req.password[..]),
+        (&b"\xc3\x28"[..], &b"\xff"[..])
+    );
+}
+
+#[tokio::test]
+async fn password_responses() {
+    // RFC 1929 section 2, X'00' is success, and this crate sends X'FF' on failure
+    for (vector, status) in [([0x01, 0x00], true), ([0x01, 0xff], false)] {
+        let resp = round_trip(
+            &vector,
+            async |r| password::Response::read_from(r).await,
+            |msg, buf| msg.write_to_buf(buf),
+        )
+        .await;
+        assert_eq!(resp.status, status);
+    }
+}
+
+#[tokio::test]
+async fn requests() {
+    // RFC 1928 section 4, `Connect` to an IPv4 address, e.g. `curl --socks5`
+    let req = request(&[0x05, 0x01, 0x00, 0x01, 93, 184, 216, 34, 0x00, 0x50]).await;
+    assert_eq!(req.command, Command::Connect);
+    assert_eq!(req.address, v4([93, 184, 216, 34], 80));
+
+    // `Connect` to a domain name, resolved by the proxy, e.g. `curl --socks5-hostname` or `ssh -D` clients
+    let mut vector = vec![0x05, 0x01, 0x00, 0x03, 11];
+    vector.extend_from_slice(b"example.com");
+    vector.extend_from_slice(&[0x01, 0xbb]);
+    let req = request(&vector).await;
+    assert_eq!(req.command, Command::Connect);
+    assert_eq!(req.address, domain("example.com", 443));
+
+    // `Connect` to an IPv6 address, in network byte order
+    let mut vector = vec![0x05, 0x01, 0x00, 0x04];
+    vector.extend_from_slice(&Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1).octets());
+    vector.extend_from_slice(&[0x1f, 0x90]);
+    let req = request(&vector).await;
+    assert_eq!(
+        req.address,
+        v6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 8080)
+    );
+
+    // `Bind`, with the address the client expects the inbound connection from, e.g. FTP active mode
+    let req = request(&[0x05, 0x02, 0x00, 0x01, 198, 51, 100, 7, 0x00, 0x14]).await;
+    assert_eq!(req.command, Command::Bind);
+    assert_eq!(req.address, v4([198, 51, 100, 7], 20));
+
+    // `Associate` from a client that does not know its address and port yet, e.g. dante clients
+    let req = request(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await;
+    assert_eq!(req.command, Command::Associate);
+    assert_eq!(req.address, Address::unspecified());
+}
+
+#[tokio::test]
+async fn responses() {
+    // RFC 1928 section 6, the address the server bound for a `Connect`
+    let resp = response(&[0x05, 0x00, 0x00, 0x01, 10, 0, 0, 2, 0xc3, 0x50]).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    assert_eq!(resp.address, v4([10, 0, 0, 2], 50000));
+
+    // a failure reply with the unspecified address, as servers not telling the bound address send, e.g. `ssh -D`
+    for (code, reply) in [
+        (0x01, Reply::GeneralFailure),
+        (0x02, Reply::ConnectionNotAllowed),
+        (0x03, Reply::NetworkUnreachable),
+        (0x04, Reply::HostUnreachable),
+        (0x05, Reply::ConnectionRefused),
+        (0x06, Reply::TtlExpired),
+        (0x07, Reply::CommandNotSupported),
+        (0x08, Reply::AddressTypeNotSupported),
+    ] {
+        let resp = response(&[0x05, code, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await;
+        assert_eq!(resp.reply, reply);
+        assert_eq!(resp.address, Address::unspecified());
+    }
+
+    // the relay address of an `Associate`, as IPv6
+    let mut vector = vec![0x05, 0x00, 0x00, 0x04];
+    vector.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+    vector.extend_from_slice(&[0x04, 0x38]);
+    let resp = response(&vector).await;
+    assert_eq!(resp.address, v6(Ipv6Addr::LOCALHOST, 1080));
+
+    // a domain name as the bound address, which RFC 1928 allows
+    let mut vector = vec![0x05, 0x00, 0x00, 0x03, 9];
+    vector.extend_from_slice(b"proxy.lan");
+    vector.extend_from_slice(&[0x04, 0x38]);
+    let resp = response(&vector).await;
+    assert_eq!(resp.address, domain("proxy.lan", 1080));
+}
+
+#[tokio::test]
+async fn udp_headers() {
+    // RFC 1928 section 7, a datagram to an IPv4 destination, e.g. a DNS query
+    let header = udp_header(&[0x00, 0x00, 0x00, 0x01, 8, 8, 8, 8, 0x00, 0x35]).await;
+    assert_eq!(header.frag, 0);
+    assert_eq!(header.address, v4([8, 8, 8, 8], 53));
+
+    // a fragment, with the high-order bit marking the end of the sequence
+    let mut vector = vec![0x00, 0x00, 0x81, 0x03, 7];
+    vector.extend_from_slice(b"ntp.org");
+    vector.extend_from_slice(&[0x00, 0x7b]);
+    let header = udp_header(&vector).await;
+    assert_eq!(header.frag, 0x81);
+    assert_eq!(header.address, domain("ntp.org", 123));
+
+    let mut vector = vec![0x00, 0x00, 0x00, 0x04];
+    vector.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+    vector.extend_from_slice(&[0x00, 0x35]);
+    let header = udp_header(&vector).await;
+    assert_eq!(header.address, v6(Ipv6Addr::LOCALHOST, 53));
+}
+
+#[tokio::test]
+async fn reserved_bytes_are_ignored_and_sent_as_zero() {
+    // RSV of a request is not checked, as RFC 1928 gives no way to report it
+    let req = Request::read_from(&mut &[0x05, 0x01, 0xaa, 0x01, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap();
+    let mut buf = BytesMut::new();
+    req.write_to_buf(&mut buf);
+    assert_eq!(&buf[..], [0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0, 80]);
+
+    let resp = Response::read_from(&mut &[0x05, 0x00, 0xaa, 0x01, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap();
+    let mut buf = BytesMut::new();
+    resp.write_to_buf(&mut buf);
+    assert_eq!(&buf[..], [0x05, 0x00, 0x00, 0x01, 1, 2, 3, 4, 0, 80]);
+
+    let header = UdpHeader::read_from(&mut &[0xaa, 0xbb, 0x00, 0x01, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap();
+    let mut buf = BytesMut::new();
+    header.write_to_buf(&mut buf);
+    assert_eq!(&buf[..], [0x00, 0x00, 0x00, 0x01, 1, 2, 3, 4, 0, 80]);
+}
+
+#[tokio::test]
+async fn malformed_handshakes() {
+    // SOCKS4 `Connect`
+    let err = handshake::Request::read_from(&mut &[0x04, 0x01, 0x00, 0x50, 1, 2, 3, 4, 0][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::ProtocolVersion { version: 0x04 })
+    ));
+
+    // NMETHODS larger than the methods sent
+    let err = handshake::Request::read_from(&mut &[0x05, 0x03, 0x00, 0x02][..])
+        .await
+        .unwrap_err();
+    assert_eof(err);
+
+    let err = handshake::Response::read_from(&mut &[0x04, 0x00][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::ProtocolVersion { version: 0x04 })
+    ));
+}
+
+#[tokio::test]
+async fn malformed_password_sub_negotiations() {
+    // the SOCKS version instead of the sub-negotiation version
+    let err = password::Request::read_from(&mut &b"\x05\x04user\x04pass"[..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        password::Error::SubNegotiationVersion { version: 0x05 }
+    ));
+
+    // PLEN larger than the password sent
+    let err = password::Request::read_from(&mut &b"\x01\x04user\x08pass"[..])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, password::Error::Io(err) if err.kind() == ErrorKind::UnexpectedEof));
+
+    // RFC 1929 treats any status but X'00' as a failure, while this parser only accepts X'00' and X'FF'
+    let err = password::Response::read_from(&mut &[0x01, 0x01][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        password::Error::SubNegotiationStatus {
+            version: 0x01,
+            status: 0x01
+        }
+    ));
+}
+
+#[tokio::test]
+async fn malformed_requests() {
+    let err = Request::read_from(&mut &[0x04, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::ProtocolVersion { version: 0x04 })
+    ));
+
+    let err = Request::read_from(&mut &[0x05, 0x04, 0x00, 0x01, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidCommand {
+            version: 0x05,
+            command: 0x04
+        })
+    ));
+
+    // X'02' was never assigned as an address type
+    let err = Request::read_from(&mut &[0x05, 0x01, 0x00, 0x02, 1, 2, 3, 4, 0, 80][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidAddressTypeInRequest {
+            version: 0x05,
+            command: Command::Connect,
+            address_type: 0x02
+        })
+    ));
+
+    // the port missing
+    let err = Request::read_from(&mut &[0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4][..])
+        .await
+        .unwrap_err();
+    assert_eof(err);
+
+    // the domain name shorter than its length prefix
+    let err = Request::read_from(&mut &b"\x05\x01\x00\x03\x0bexample\x00\x50"[..])
+        .await
+        .unwrap_err();
+    assert_eof(err);
+}
+
+#[tokio::test]
+async fn malformed_responses() {
+    let err = Response::read_from(&mut &[0x05, 0x09, 0x00, 0x01, 0, 0, 0, 0, 0, 0][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidReply {
+            version: 0x05,
+            reply: 0x09
+        })
+    ));
+
+    let err = Response::read_from(&mut &[0x05, 0x00, 0x00, 0x05, 0, 0, 0, 0, 0, 0][..])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidAddressTypeInResponse {
+            version: 0x05,
+            reply: Reply::Succeeded,
+            address_type: 0x05
+        })
+    ));
+}
+
+#[tokio::test]
+async fn malformed_udp_headers() {
+    let vector = [0x00, 0x00, 0x00, 0x02, 1, 2, 3, 4, 0, 53];
+
+    let err = UdpHeader::read_from(&mut &vector[..]).await.unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidAddressTypeInUdpHeader {
+            frag: 0,
+            address_type: 0x02
+        })
+    ));
+
+    let err = UdpHeader::read_from_buf(&mut &vector[..]).unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::InvalidAddressTypeInUdpHeader {
+            frag: 0,
+            address_type: 0x02
+        })
+    ));
+
+    // truncated datagrams
+    assert_eof(UdpHeader::read_from_buf(&mut &[0x00, 0x00][..]).unwrap_err());
+    assert_eof(
+        UdpHeader::read_from_buf(&mut &[0x00, 0x00, 0x00, 0x01, 1, 2, 3, 4][..]).unwrap_err(),
+    );
+}
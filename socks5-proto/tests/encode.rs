@@ -0,0 +1,167 @@
+use bytes::BytesMut;
+use socks5_proto::{
+    handshake::{self, password, Method},
+    Address, Command, Reply, Request, Response, UdpHeader,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    future::Future,
+};
+use tokio::io;
+
+/// Counts the allocations of the current thread, as the tests run in parallel.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Awaits `fut`, returning the number of allocations it made.
+async fn allocations<F: Future>(fut: F) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    fut.await;
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+fn longest_domain() -> Address {
+    Address::DomainAddress(vec![b'a'; 255], 443)
+}
+
+#[test]
+fn encoded_as_by_write_to_buf() {
+    let mut scratch = [0; Response::MAX_SERIALIZED_LEN];
+
+    for addr in [
+        Address::unspecified(),
+        "[::1]:80".parse().unwrap(),
+        longest_domain(),
+    ] {
+        let resp = Response::new(Reply::Succeeded, addr.clone());
+        let mut buf = BytesMut::new();
+        resp.write_to_buf(&mut buf);
+        assert_eq!(resp.encode_into(&mut scratch).unwrap(), &buf[..]);
+
+        let req = Request::new(Command::Connect, addr.clone());
+        let mut buf = BytesMut::new();
+        req.write_to_buf(&mut buf);
+        assert_eq!(req.encode_into(&mut scratch).unwrap(), &buf[..]);
+
+        let header = UdpHeader::new(0, addr);
+        let mut buf = BytesMut::new();
+        header.write_to_buf(&mut buf);
+        assert_eq!(header.encode_into(&mut scratch).unwrap(), &buf[..]);
+    }
+}
+
+#[test]
+fn longest_messages_fit() {
+    assert_eq!(
+        Response::new(Reply::Succeeded, longest_domain()).serialized_len(),
+        Response::MAX_SERIALIZED_LEN
+    );
+    assert_eq!(
+        Request::new(Command::Connect, longest_domain()).serialized_len(),
+        Request::MAX_SERIALIZED_LEN
+    );
+    assert_eq!(
+        UdpHeader::new(0, longest_domain()).serialized_len(),
+        UdpHeader::MAX_SERIALIZED_LEN
+    );
+    assert_eq!(
+        handshake::Request::new(vec![Method::NONE; 255]).serialized_len(),
+        handshake::Request::MAX_SERIALIZED_LEN
+    );
+    assert_eq!(
+        password::Request::new(vec![b'u'; 255], vec![b'p'; 255]).serialized_len(),
+        password::Request::MAX_SERIALIZED_LEN
+    );
+}
+
+#[test]
+fn short_buffer_is_rejected() {
+    let resp = Response::new(Reply::Succeeded, Address::unspecified());
+    let err = resp.encode_into(&mut [0; 9]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn over_long_fields_are_rejected() {
+    let domain = Address::DomainAddress(vec![b'a'; 256], 443);
+    let mut scratch = [0; password::Request::MAX_SERIALIZED_LEN * 2];
+    let mut sink = io::sink();
+
+    let resp = Response::new(Reply::Succeeded, domain.clone());
+    let err = resp.encode_into(&mut scratch).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = resp.write_to(&mut sink).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let req = Request::new(Command::Connect, domain.clone());
+    let err = req.encode_into(&mut scratch).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = req.write_to(&mut sink).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let header = UdpHeader::new(0, domain);
+    let err = header.encode_into(&mut scratch).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = header.write_to(&mut sink).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let req = handshake::Request::new(vec![Method::NONE; 256]);
+    let err = req.encode_into(&mut scratch).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = req.write_to(&mut sink).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    for req in [
+        password::Request::new(vec![b'u'; 256], b"pass".to_vec()),
+        password::Request::new(b"user".to_vec(), vec![b'p'; 256]),
+    ] {
+        let err = req.encode_into(&mut scratch).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = req.write_to(&mut sink).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[tokio::test]
+async fn writes_do_not_allocate() {
+    let mut sink = io::sink();
+
+    let resp = Response::new(Reply::Succeeded, longest_domain());
+    assert_eq!(allocations(resp.write_to(&mut sink)).await, 0);
+
+    let req = Request::new(Command::Connect, longest_domain());
+    assert_eq!(allocations(req.write_to(&mut sink)).await, 0);
+
+    let header = UdpHeader::new(0, longest_domain());
+    assert_eq!(allocations(header.write_to(&mut sink)).await, 0);
+
+    let resp = handshake::Response::new(Method::NONE);
+    assert_eq!(allocations(resp.write_to(&mut sink)).await, 0);
+
+    let req = handshake::Request::new(vec![Method::NONE, Method::PASSWORD]);
+    assert_eq!(allocations(req.write_to(&mut sink)).await, 0);
+
+    let resp = password::Response::new(true);
+    assert_eq!(allocations(resp.write_to(&mut sink)).await, 0);
+
+    let req = password::Request::new(b"user".to_vec(), b"pass".to_vec());
+    assert_eq!(allocations(req.write_to(&mut sink)).await, 0);
+}
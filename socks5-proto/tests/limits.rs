@@ -0,0 +1,115 @@
+use socks5_proto::{
+    handshake::{password, Request as HandshakeRequest},
+    Address, Command, Error, ParseLimits, ProtocolError, Request,
+};
+
+fn limits() -> ParseLimits {
+    ParseLimits {
+        max_methods: 3,
+        max_username_len: 4,
+        max_password_len: 5,
+        max_domain_len: 6,
+    }
+}
+
+fn request(name: &[u8]) -> Vec<u8> {
+    let mut buf = vec![0x05, 0x01, 0x00, 0x03, name.len() as u8];
+    buf.extend_from_slice(name);
+    buf.extend_from_slice(&[0, 80]);
+    buf
+}
+
+#[tokio::test]
+async fn handshake_methods_are_limited() {
+    let req = HandshakeRequest::read_from_with_limits(&mut &[0x05, 3, 0, 1, 2][..], &limits())
+        .await
+        .unwrap();
+    assert_eq!(req.methods.len(), 3);
+
+    // failed right after NMETHODS, without waiting on the methods
+    let err = HandshakeRequest::read_from_with_limits(&mut &[0x05, 4][..], &limits())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::TooManyHandshakeMethods {
+            nmethods: 4,
+            max: 3,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn password_fields_are_limited() {
+    let req =
+        password::Request::read_from_with_limits(&mut &b"\x01\x04user\x05passw"[..], &limits())
+            .await
+            .unwrap();
+    assert_eq!(
+        (&*req.username, &*req.password),
+        (&b"user"[..], &b"passw"[..])
+    );
+
+    let err = password::Request::read_from_with_limits(&mut &b"\x01\x05"[..], &limits())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        password::Error::UsernameTooLong { len: 5, max: 4 }
+    ));
+
+    let err = password::Request::read_from_with_limits(&mut &b"\x01\x04user\x06"[..], &limits())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        password::Error::PasswordTooLong { len: 6, max: 5 }
+    ));
+}
+
+#[tokio::test]
+async fn domain_names_are_limited() {
+    let req = Request::read_from_with_limits(&mut &request(b"abcdef")[..], &limits())
+        .await
+        .unwrap();
+    assert_eq!(req.address, Address::DomainAddress(b"abcdef".to_vec(), 80));
+
+    let err = Request::read_from_with_limits(&mut &request(b"abcdefg")[..6], &limits())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::DomainTooLongInRequest {
+            command: Command::Connect,
+            len: 7,
+            max: 6,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn default_limits_are_the_protocol_maximums() {
+    let limits = ParseLimits::default();
+
+    let mut handshake = vec![0x05, 255];
+    handshake.extend(0..=254);
+    let req = HandshakeRequest::read_from_with_limits(&mut &handshake[..], &limits)
+        .await
+        .unwrap();
+    assert_eq!(req.methods.len(), 255);
+
+    let long = [b'a'; 255];
+    let mut sub = vec![0x01, 255];
+    sub.extend_from_slice(&long);
+    sub.push(255);
+    sub.extend_from_slice(&long);
+    let req = password::Request::read_from_with_limits(&mut &sub[..], &limits)
+        .await
+        .unwrap();
+    assert_eq!((req.username.len(), req.password.len()), (255, 255));
+
+    let req = Request::read_from(&mut &request(&long)[..]).await.unwrap();
+    assert_eq!(req.address, Address::DomainAddress(long.to_vec(), 80));
+}
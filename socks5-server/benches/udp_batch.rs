@@ -0,0 +1,86 @@
+//! Loopback benchmark comparing per-datagram receiving with batched receiving.
+//!
+//! Run with `cargo bench -p socks5-server --bench udp_batch`.
+
+use bytes::BytesMut;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::AssociatedUdpSocket;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::net::UdpSocket;
+
+const PACKETS: usize = 200_000;
+const PAYLOAD: &[u8] = &[0; 64];
+
+#[tokio::main]
+async fn main() {
+    let (calls, elapsed) = run(false).await;
+    report("recv_from_buf", calls, elapsed);
+
+    let (calls, elapsed) = run(true).await;
+    report("recv_batch", calls, elapsed);
+}
+
+async fn run(batched: bool) -> (usize, Duration) {
+    let socket = Arc::new(AssociatedUdpSocket::new(
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        1500,
+    ));
+    let addr = socket.get_ref().local_addr().unwrap();
+
+    let sender = tokio::spawn(async move {
+        let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dst = SocketAddr::from(([127, 0, 0, 1], 9));
+        let header = UdpHeader::new(0, Address::SocketAddress(dst));
+
+        let mut pkt = BytesMut::new();
+        header.write_to_buf(&mut pkt);
+        pkt.extend_from_slice(PAYLOAD);
+
+        // keep sending until the receiver is done, as loopback UDP may drop under load
+        loop {
+            if peer.send_to(&pkt, addr).await.is_err() {
+                tokio::task::yield_now().await;
+            }
+        }
+    });
+
+    let start = Instant::now();
+    let mut received = 0;
+    let mut calls = 0;
+
+    if batched {
+        let mut bufs = vec![BytesMut::new(); 32];
+
+        while received < PACKETS {
+            received += socket.recv_batch(&mut bufs).await.unwrap().len();
+            calls += 1;
+            bufs.iter_mut().for_each(BytesMut::clear);
+        }
+    } else {
+        let mut buf = BytesMut::new();
+
+        while received < PACKETS {
+            socket.recv_from_buf(&mut buf).await.unwrap();
+            received += 1;
+            calls += 1;
+            buf.clear();
+        }
+    }
+
+    let elapsed = start.elapsed();
+    sender.abort();
+
+    (calls, elapsed)
+}
+
+fn report(name: &str, calls: usize, elapsed: Duration) {
+    println!(
+        "{name}: {PACKETS} datagrams in {elapsed:?} with {calls} receive calls ({:.0} datagrams/s, {:.1} datagrams/call)",
+        PACKETS as f64 / elapsed.as_secs_f64(),
+        PACKETS as f64 / calls as f64,
+    );
+}
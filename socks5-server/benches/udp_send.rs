@@ -0,0 +1,130 @@
+//! Loopback benchmark comparing sending with a fresh buffer per packet, with the scratch buffer of the socket, with a vectored send, and with the header written into headroom.
+//!
+//! Run with `cargo bench -p socks5-server --bench udp_send`.
+
+use bytes::BytesMut;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, DatagramSocket};
+use std::{
+    io::Error,
+    net::SocketAddr,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+const PACKETS: usize = 200_000;
+const PAYLOAD: &[u8] = &[0; 1200];
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Alloc,
+    Scratch,
+    Vectored,
+    Headroom,
+}
+
+/// A `UdpSocket` without vectored sends, so `send_to` copies the payload into the scratch buffer of the socket
+struct Copying(UdpSocket);
+
+impl DatagramSocket for Copying {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>> {
+        self.0.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        self.0.poll_send_to(cx, buf, target)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.0.local_addr()
+    }
+}
+
+/// Modes are run in turns for this many rounds and the best round of each is reported, so drifting system load affects them alike
+const ROUNDS: usize = 5;
+
+#[tokio::main]
+async fn main() {
+    let modes = [
+        ("fresh buffer", Mode::Alloc),
+        ("send_to, copying into scratch", Mode::Scratch),
+        ("send_to, vectored", Mode::Vectored),
+        ("send_to_with_headroom", Mode::Headroom),
+    ];
+
+    let mut best = [Duration::MAX; 4];
+
+    for _ in 0..ROUNDS {
+        for (best, (_, mode)) in best.iter_mut().zip(modes) {
+            *best = (*best).min(run(mode).await);
+        }
+    }
+
+    for ((name, _), elapsed) in modes.into_iter().zip(best) {
+        report(name, elapsed);
+    }
+}
+
+async fn run(mode: Mode) -> Duration {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let copying =
+        AssociatedUdpSocket::new(Copying(UdpSocket::bind("127.0.0.1:0").await.unwrap()), 1500);
+
+    // the sink never reads, so the kernel drops datagrams once its buffer is full and only the sending side is measured
+    let sink = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dst = sink.local_addr().unwrap();
+    let header = UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    );
+
+    let mut buf = vec![0; AssociatedUdpSocket::MAX_HEADER_LEN + PAYLOAD.len()];
+    let start = Instant::now();
+
+    for _ in 0..PACKETS {
+        match mode {
+            Mode::Alloc => {
+                let mut pkt = BytesMut::with_capacity(header.serialized_len() + PAYLOAD.len());
+                header.write_to_buf(&mut pkt);
+                pkt.extend_from_slice(PAYLOAD);
+                socket.get_ref().send_to(&pkt, dst).await.unwrap();
+            }
+            Mode::Scratch => {
+                copying.send_to(PAYLOAD, &header, dst).await.unwrap();
+            }
+            Mode::Vectored => {
+                socket.send_to(PAYLOAD, &header, dst).await.unwrap();
+            }
+            Mode::Headroom => {
+                socket
+                    .send_to_with_headroom(
+                        &mut buf,
+                        AssociatedUdpSocket::MAX_HEADER_LEN,
+                        &header,
+                        dst,
+                    )
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    start.elapsed()
+}
+
+fn report(name: &str, elapsed: Duration) {
+    println!(
+        "{name}: {PACKETS} datagrams in {elapsed:?} ({:.0} datagrams/s)",
+        PACKETS as f64 / elapsed.as_secs_f64(),
+    );
+}
@@ -0,0 +1,414 @@
+//! End-to-end benchmarks of a SOCKS5 server on loopback: `Connect` negotiations per second, bulk throughput through a `Connect` relay, and UDP relay packets per second.
+//!
+//! Each benchmark runs a fixed workload and reports one line in a stable `key=value` format, so runs before and after a change can be compared directly:
+//!
+//! ```sh
+//! cargo run --release --example bench_harness
+//! cargo run --release --example bench_harness -- --short
+//! ```
+//!
+//! `--short` runs a small fraction of the workloads. It only checks that the benchmarks work, the numbers are not meaningful.
+
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, UdpRelayConfig},
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Reply, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    env,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::Error as IoError,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinSet,
+    time,
+};
+
+/// The size of the UDP payloads sent through the relay
+const UDP_PAYLOAD: usize = 1200;
+
+/// How many UDP packets are kept in flight through the relay
+const UDP_WINDOW: usize = 32;
+
+/// How long a UDP packet is waited for before it is counted as lost
+const UDP_LOSS_TIMEOUT: Duration = Duration::from_millis(200);
+
+#[tokio::main]
+async fn main() -> Result<(), IoError> {
+    let workload = if env::args().skip(1).any(|arg| arg == "--short") {
+        Workload::SHORT
+    } else {
+        Workload::FULL
+    };
+
+    for measurement in run(workload).await? {
+        println!("{measurement}");
+    }
+
+    Ok(())
+}
+
+/// The fixed amount of work each benchmark does
+#[derive(Clone, Copy, Debug)]
+pub struct Workload {
+    /// How many `Connect` negotiations are run, each on a connection of its own
+    pub negotiations: usize,
+    /// How many connections run negotiations at the same time
+    pub concurrency: usize,
+    /// How many bytes are sent through a single `Connect` relay
+    pub bulk_bytes: usize,
+    /// How many UDP packets are sent through a single association
+    pub udp_packets: usize,
+}
+
+impl Workload {
+    /// The workload the numbers are meaningful for
+    pub const FULL: Self = Self {
+        negotiations: 20_000,
+        concurrency: 32,
+        bulk_bytes: 1 << 30,
+        udp_packets: 200_000,
+    };
+
+    /// A small workload checking that the benchmarks run
+    pub const SHORT: Self = Self {
+        negotiations: 200,
+        concurrency: 8,
+        bulk_bytes: 1 << 20,
+        udp_packets: 1_000,
+    };
+}
+
+/// The result of a benchmark
+#[derive(Clone, Debug)]
+pub struct Measurement {
+    /// The name of the benchmark
+    pub name: &'static str,
+    /// How many operations completed
+    pub ops: u64,
+    /// The unit of an operation
+    pub unit: &'static str,
+    /// How many operations were attempted but did not complete, e.g. lost UDP packets
+    pub lost: u64,
+    /// How long the benchmark took
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /// Completed operations per second
+    pub fn rate(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "bench={} {}={} lost={} elapsed_ms={:.3} rate={:.1}/s",
+            self.name,
+            self.unit,
+            self.ops,
+            self.lost,
+            self.elapsed.as_secs_f64() * 1000.0,
+            self.rate(),
+        )
+    }
+}
+
+/// Runs every benchmark with `workload` against a server of its own, returning the measurements in a fixed order.
+pub async fn run(workload: Workload) -> Result<Vec<Measurement>, IoError> {
+    let server = start_server().await?;
+
+    Ok(vec![
+        negotiations(server, workload).await?,
+        bulk(server, workload).await?,
+        udp(server, workload).await?,
+    ])
+}
+
+/// Starts a server without authentication on an ephemeral port of the loopback address, relaying `Connect` commands with [`io::copy_bidirectional()`] and `Associate` commands with [`run_relay()`].
+async fn start_server() -> Result<SocketAddr, IoError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    let addr = server.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(async move {
+                let Ok((conn, ())) = conn.authenticate().await else {
+                    return;
+                };
+
+                match conn.wait().await {
+                    Ok(Command::Connect(connect, Address::SocketAddress(addr))) => {
+                        let Ok(mut target) = TcpStream::connect(addr).await else {
+                            let _ = connect
+                                .reply(Reply::HostUnreachable, Address::unspecified())
+                                .await;
+                            return;
+                        };
+
+                        let replied = connect
+                            .reply(Reply::Succeeded, Address::unspecified())
+                            .await;
+
+                        if let Ok(mut conn) = replied {
+                            let _ = io::copy_bidirectional(&mut conn, &mut target).await;
+                        }
+                    }
+                    Ok(Command::Associate(associate, _)) => {
+                        let _ = run_relay(associate, UdpRelayConfig::default()).await;
+                    }
+                    Ok(Command::Connect(connect, _)) => {
+                        let _ = connect
+                            .reply(Reply::AddressTypeNotSupported, Address::unspecified())
+                            .await;
+                    }
+                    Ok(Command::Bind(bind, _)) => {
+                        let _ = bind
+                            .reply(Reply::CommandNotSupported, Address::unspecified())
+                            .await;
+                    }
+                    Err(_) => {}
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/// Connects to the server, offers no authentication and sends a request, returning the stream once the server replied with success.
+async fn request(
+    server: SocketAddr,
+    command: ProtoCommand,
+    address: Address,
+) -> Result<(TcpStream, Address), IoError> {
+    let mut stream = TcpStream::connect(server).await?;
+    stream.set_nodelay(true)?;
+
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await?;
+    let resp = HandshakeResponse::read_from(&mut stream).await?;
+
+    if resp.method != Method::NONE {
+        return Err(IoError::other("no authentication refused"));
+    }
+
+    Request::new(command, address).write_to(&mut stream).await?;
+    let resp = Response::read_from(&mut stream).await?;
+
+    if resp.reply != Reply::Succeeded {
+        return Err(IoError::other(format!(
+            "request failed with {:?}",
+            resp.reply
+        )));
+    }
+
+    Ok((stream, resp.address))
+}
+
+/// Opens `workload.negotiations` connections, each negotiating a `Connect` to a TCP target and exchanging a single byte through it.
+async fn negotiations(server: SocketAddr, workload: Workload) -> Result<Measurement, IoError> {
+    let target = tcp_echo().await?;
+    let workers = workload.concurrency.max(1);
+    let start = Instant::now();
+
+    let mut tasks = JoinSet::new();
+
+    for worker in 0..workers {
+        let count =
+            workload.negotiations / workers + usize::from(worker < workload.negotiations % workers);
+
+        tasks.spawn(async move {
+            for _ in 0..count {
+                let (mut stream, _) = request(
+                    server,
+                    ProtoCommand::Connect,
+                    Address::SocketAddress(target),
+                )
+                .await?;
+
+                stream.write_all(&[0]).await?;
+                stream.read_exact(&mut [0]).await?;
+            }
+
+            Ok::<_, IoError>(())
+        });
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        res.map_err(IoError::other)??;
+    }
+
+    Ok(Measurement {
+        name: "connect_negotiations",
+        ops: workload.negotiations as u64,
+        unit: "connections",
+        lost: 0,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Sends `workload.bulk_bytes` bytes through a single `Connect` relay to a TCP target, which acknowledges them once the relay closes.
+async fn bulk(server: SocketAddr, workload: Workload) -> Result<Measurement, IoError> {
+    let target = tcp_counter().await?;
+    let (stream, _) = request(
+        server,
+        ProtoCommand::Connect,
+        Address::SocketAddress(target),
+    )
+    .await?;
+    let (mut rx, mut tx) = stream.into_split();
+
+    let chunk = vec![0; 64 * 1024];
+    let start = Instant::now();
+
+    let mut left = workload.bulk_bytes;
+
+    while left > 0 {
+        let len = left.min(chunk.len());
+        tx.write_all(&chunk[..len]).await?;
+        left -= len;
+    }
+
+    tx.shutdown().await?;
+    let received = rx.read_u64().await?;
+    let elapsed = start.elapsed();
+
+    if received != workload.bulk_bytes as u64 {
+        return Err(IoError::other(format!(
+            "target received {received} of {} bytes",
+            workload.bulk_bytes
+        )));
+    }
+
+    Ok(Measurement {
+        name: "connect_throughput",
+        ops: received,
+        unit: "bytes",
+        lost: 0,
+        elapsed,
+    })
+}
+
+/// Sends `workload.udp_packets` packets through a single association to a UDP echo target, keeping up to [`UDP_WINDOW`] of them in flight, and counts the echoes received.
+async fn udp(server: SocketAddr, workload: Workload) -> Result<Measurement, IoError> {
+    let target = udp_echo().await?;
+    let (stream, relay) = request(server, ProtoCommand::Associate, Address::unspecified()).await?;
+
+    let Address::SocketAddress(mut relay) = relay else {
+        return Err(IoError::other("relay replied with a domain"));
+    };
+
+    if relay.ip().is_unspecified() {
+        relay.set_ip(server.ip());
+    }
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+
+    let header = UdpHeader::new(0, Address::SocketAddress(target));
+    let mut pkt = BytesMut::with_capacity(header.serialized_len() + UDP_PAYLOAD);
+    header.write_to_buf(&mut pkt);
+    pkt.resize(header.serialized_len() + UDP_PAYLOAD, 0);
+
+    let mut buf = vec![0; pkt.len() + 64];
+    let (mut sent, mut received, mut in_flight) = (0, 0, 0);
+    let start = Instant::now();
+
+    while sent < workload.udp_packets || in_flight > 0 {
+        while sent < workload.udp_packets && in_flight < UDP_WINDOW {
+            socket.send_to(&pkt, relay).await?;
+            sent += 1;
+            in_flight += 1;
+        }
+
+        match time::timeout(UDP_LOSS_TIMEOUT, socket.recv_from(&mut buf)).await {
+            Ok(res) => {
+                res?;
+                received += 1;
+                // an echo may arrive after its window was given up on
+                in_flight = in_flight.saturating_sub(1);
+            }
+            // the whole window is counted as lost, so a stalled relay can not hang the benchmark
+            Err(_) => in_flight = 0,
+        }
+    }
+
+    let elapsed = start.elapsed();
+    drop(stream);
+
+    Ok(Measurement {
+        name: "udp_relay",
+        ops: received as u64,
+        unit: "packets",
+        lost: (sent - received) as u64,
+        elapsed,
+    })
+}
+
+/// Starts a TCP target echoing everything it receives on every connection, returning its address.
+async fn tcp_echo() -> Result<SocketAddr, IoError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let _ = stream.set_nodelay(true);
+                let (mut rx, mut tx) = stream.split();
+                let _ = io::copy(&mut rx, &mut tx).await;
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/// Starts a TCP target discarding everything it receives on every connection, and replying with how many bytes it received once the peer closes its side.
+async fn tcp_counter() -> Result<SocketAddr, IoError> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let addr = listener.local_addr()?;
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut rx, mut tx) = stream.split();
+
+                if let Ok(received) = io::copy(&mut rx, &mut io::sink()).await {
+                    let _ = tx.write_u64(received).await;
+                }
+            });
+        }
+    });
+
+    Ok(addr)
+}
+
+/// Starts a UDP target echoing every datagram back to its source, returning its address.
+async fn udp_echo() -> Result<SocketAddr, IoError> {
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+    let addr = socket.local_addr()?;
+
+    tokio::spawn(async move {
+        let mut buf = [0; 65535];
+
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..len], src).await;
+        }
+    });
+
+    Ok(addr)
+}
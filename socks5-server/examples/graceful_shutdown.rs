@@ -0,0 +1,177 @@
+//! A SOCKS5 server relaying `Connect` commands which shuts down gracefully.
+//!
+//! On shutdown, the server stops accepting connections, and in-flight connections are given a grace period to finish before they are aborted. Shutdown is triggered by closing the standard input (e.g. `ctrl-d`) here, so the example runs with tokio's default features. With tokio's `signal` feature, trigger it on `ctrl-c` instead:
+//!
+//! ```rust,ignore
+//! serve(listener, async { tokio::signal::ctrl_c().await.unwrap() }, GRACE_PERIOD).await
+//! ```
+//!
+//! The trigger is cancellation shared through a [`watch`] channel, which every connection also listens to, e.g. to stop waiting for a command. In-flight connections are tracked in a [`JoinSet`], drained with a deadline, then aborted.
+
+use socks5_server::{
+    auth::NoAuth,
+    connection::state::NeedAuthenticate,
+    proto::{Address, Reply},
+    Command, Error, IncomingConnection, Server,
+};
+use std::{future::Future, io::Error as IoError, sync::Arc, time::Duration};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task::JoinSet,
+    time,
+};
+
+/// How long in-flight connections are given to finish on shutdown
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+#[tokio::main]
+async fn main() -> Result<(), IoError> {
+    let listener = TcpListener::bind("127.0.0.1:5000").await?;
+
+    let shutdown = async {
+        let _ = io::stdin().read_to_end(&mut Vec::new()).await;
+    };
+
+    let aborted = serve(listener, shutdown, GRACE_PERIOD).await?;
+    eprintln!("shut down, {aborted} connections aborted");
+    Ok(())
+}
+
+/// Serves connections on `listener` until `shutdown` completes, then waits for in-flight connections for up to `grace`, returning how many were aborted after it.
+pub async fn serve<F>(listener: TcpListener, shutdown: F, grace: Duration) -> Result<usize, IoError>
+where
+    F: Future<Output = ()>,
+{
+    let auth = Arc::new(NoAuth) as Arc<_>;
+    let server = Server::new(listener, auth);
+
+    let (cancel, cancelled) = watch::channel(false);
+    let mut conns = JoinSet::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            res = server.accept() => {
+                let (conn, _) = res?;
+                let cancelled = cancelled.clone();
+
+                conns.spawn(async move {
+                    match handle(conn, cancelled).await {
+                        Ok(()) => {}
+                        Err(err) => eprintln!("{err}"),
+                    }
+                });
+            }
+            // finished connections are reaped as they go, so the set only holds in-flight ones
+            Some(_) = conns.join_next(), if !conns.is_empty() => {}
+            () = &mut shutdown => break,
+        }
+    }
+
+    // stop accepting, and tell connections still negotiating to give up
+    drop(server);
+    let _ = cancel.send(true);
+
+    let drained = time::timeout(grace, async { while conns.join_next().await.is_some() {} }).await;
+
+    match drained {
+        Ok(()) => Ok(0),
+        Err(_) => {
+            let aborted = conns.len();
+            conns.shutdown().await;
+            Ok(aborted)
+        }
+    }
+}
+
+async fn handle(
+    conn: IncomingConnection<(), NeedAuthenticate>,
+    mut cancelled: watch::Receiver<bool>,
+) -> Result<(), Error> {
+    // a connection still negotiating is not worth waiting for
+    let negotiate = async {
+        let conn = match conn.authenticate().await {
+            Ok((conn, _)) => conn,
+            Err(failed) => return Err(failed.shutdown_and_err().await),
+        };
+
+        match conn.wait().await {
+            Ok(cmd) => Ok(cmd),
+            Err(failed) => Err(failed.shutdown_and_err().await),
+        }
+    };
+
+    let cmd = tokio::select! {
+        res = negotiate => res?,
+        _ = cancelled.wait_for(|cancelled| *cancelled) => return Ok(()),
+    };
+
+    match cmd {
+        Command::Associate(associate, _) => {
+            let replied = associate
+                .reply(Reply::CommandNotSupported, Address::unspecified())
+                .await;
+
+            let mut conn = match replied {
+                Ok(conn) => conn,
+                Err(failed) => return Err(failed.shutdown_and_err().await),
+            };
+
+            let _ = conn.close().await;
+        }
+        Command::Bind(bind, _) => {
+            let replied = bind
+                .reply(Reply::CommandNotSupported, Address::unspecified())
+                .await;
+
+            let mut conn = match replied {
+                Ok(conn) => conn,
+                Err(failed) => return Err(failed.shutdown_and_err().await),
+            };
+
+            let _ = conn.close().await;
+        }
+        Command::Connect(connect, addr) => {
+            let target = match addr {
+                Address::DomainAddress(domain, port) => {
+                    let domain = String::from_utf8_lossy(&domain);
+                    TcpStream::connect((domain.as_ref(), port)).await
+                }
+                Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+            };
+
+            if let Ok(mut target) = target {
+                let replied = connect
+                    .reply(Reply::Succeeded, Address::unspecified())
+                    .await;
+
+                let mut conn = match replied {
+                    Ok(conn) => conn,
+                    Err(failed) => return Err(failed.shutdown_and_err().await),
+                };
+
+                // a relay in flight is left running through the grace period
+                let res = io::copy_bidirectional(&mut target, &mut conn).await;
+                let _ = conn.shutdown().await;
+                let _ = target.shutdown().await;
+
+                res?;
+            } else {
+                let replied = connect
+                    .reply(Reply::HostUnreachable, Address::unspecified())
+                    .await;
+
+                let mut conn = match replied {
+                    Ok(conn) => conn,
+                    Err(failed) => return Err(failed.shutdown_and_err().await),
+                };
+
+                let _ = conn.shutdown().await;
+            }
+        }
+    }
+
+    Ok(())
+}
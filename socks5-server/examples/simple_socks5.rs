@@ -1,21 +1,19 @@
 use socks5_server::{
     auth::NoAuth,
     connection::state::NeedAuthenticate,
-    proto::{Address, Error, Reply},
-    Command, IncomingConnection, Server,
+    proto::{Address, Reply},
+    Command, Error, IncomingConnection, Server,
 };
 use std::{io::Error as IoError, sync::Arc};
 use tokio::{
     io::{self, AsyncWriteExt},
-    net::{TcpListener, TcpStream},
+    net::TcpStream,
 };
 
 #[tokio::main]
 async fn main() -> Result<(), IoError> {
-    let listener = TcpListener::bind("127.0.0.1:5000").await?;
     let auth = Arc::new(NoAuth) as Arc<_>;
-
-    let server = Server::new(listener, auth);
+    let server = Server::bind("127.0.0.1:5000", auth).await?;
 
     while let Ok((conn, _)) = server.accept().await {
         tokio::spawn(async move {
@@ -32,10 +30,7 @@ async fn main() -> Result<(), IoError> {
 async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Error> {
     let conn = match conn.authenticate().await {
         Ok((conn, _)) => conn,
-        Err((err, mut conn)) => {
-            let _ = conn.shutdown().await;
-            return Err(err);
-        }
+        Err(failed) => return Err(failed.shutdown_and_err().await),
     };
 
     match conn.wait().await {
@@ -46,10 +41,7 @@ async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Er
 
             let mut conn = match replied {
                 Ok(conn) => conn,
-                Err((err, mut conn)) => {
-                    let _ = conn.shutdown().await;
-                    return Err(Error::Io(err));
-                }
+                Err(failed) => return Err(failed.shutdown_and_err().await),
             };
 
             let _ = conn.close().await;
@@ -61,10 +53,7 @@ async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Er
 
             let mut conn = match replied {
                 Ok(conn) => conn,
-                Err((err, mut conn)) => {
-                    let _ = conn.shutdown().await;
-                    return Err(Error::Io(err));
-                }
+                Err(failed) => return Err(failed.shutdown_and_err().await),
             };
 
             let _ = conn.close().await;
@@ -85,10 +74,7 @@ async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Er
 
                 let mut conn = match replied {
                     Ok(conn) => conn,
-                    Err((err, mut conn)) => {
-                        let _ = conn.shutdown().await;
-                        return Err(Error::Io(err));
-                    }
+                    Err(failed) => return Err(failed.shutdown_and_err().await),
                 };
 
                 let res = io::copy_bidirectional(&mut target, &mut conn).await;
@@ -103,19 +89,13 @@ async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Er
 
                 let mut conn = match replied {
                     Ok(conn) => conn,
-                    Err((err, mut conn)) => {
-                        let _ = conn.shutdown().await;
-                        return Err(Error::Io(err));
-                    }
+                    Err(failed) => return Err(failed.shutdown_and_err().await),
                 };
 
                 let _ = conn.shutdown().await;
             }
         }
-        Err((err, mut conn)) => {
-            let _ = conn.shutdown().await;
-            return Err(err);
-        }
+        Err(failed) => return Err(failed.shutdown_and_err().await),
     }
 
     Ok(())
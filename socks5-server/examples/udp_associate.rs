@@ -0,0 +1,190 @@
+//! A SOCKS5 server supporting `CONNECT` and `UDP ASSOCIATE`, with the UDP relay written out step by step.
+//!
+//! Try `CONNECT` with `curl --socks5-hostname 127.0.0.1:5000 http://example.com`, and `UDP ASSOCIATE` with any SOCKS5 client supporting UDP, e.g. PySocks:
+//!
+//! ```python
+//! import socks
+//! s = socks.socksocket(socks.socket.AF_INET, socks.socket.SOCK_DGRAM)
+//! s.set_proxy(socks.SOCKS5, "127.0.0.1", 5000)
+//! s.sendto(b"ping", ("127.0.0.1", 7))
+//! print(s.recvfrom(1500))
+//! ```
+//!
+//! [`run_relay()`](socks5_server::connection::associate::run_relay) does everything `handle_associate()` below does, plus idle timeouts, destination policies, batching and statistics, in one call.
+
+use socks5_server::{
+    auth::NoAuth,
+    connection::{
+        associate::{
+            state::{NeedReply, Ready},
+            AssociatedUdpSocket,
+        },
+        state::NeedAuthenticate,
+    },
+    dns,
+    proto::{Address, Reply, UdpHeader},
+    Associate, Command, Error, IncomingConnection, Server,
+};
+use std::{
+    io::Error as IoError,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+};
+
+/// The maximum UDP packet size, with SOCKS5 UDP header included
+const MAX_PKT_SIZE: usize = 1500;
+
+#[tokio::main]
+async fn main() -> Result<(), IoError> {
+    let listener = TcpListener::bind("127.0.0.1:5000").await?;
+    serve(listener).await
+}
+
+pub async fn serve(listener: TcpListener) -> Result<(), IoError> {
+    let auth = Arc::new(NoAuth) as Arc<_>;
+    let server = Server::new(listener, auth);
+
+    loop {
+        let (conn, _) = server.accept().await?;
+
+        tokio::spawn(async move {
+            match handle(conn).await {
+                Ok(()) => {}
+                Err(err) => eprintln!("{err}"),
+            }
+        });
+    }
+}
+
+async fn handle(conn: IncomingConnection<(), NeedAuthenticate>) -> Result<(), Error> {
+    let conn = match conn.authenticate().await {
+        Ok((conn, _)) => conn,
+        Err(failed) => return Err(failed.shutdown_and_err().await),
+    };
+
+    match conn.wait().await {
+        Ok(Command::Associate(associate, _)) => handle_associate(associate).await?,
+        Ok(Command::Bind(bind, _)) => {
+            let replied = bind
+                .reply(Reply::CommandNotSupported, Address::unspecified())
+                .await;
+
+            let mut conn = match replied {
+                Ok(conn) => conn,
+                Err(failed) => return Err(failed.shutdown_and_err().await),
+            };
+
+            let _ = conn.close().await;
+        }
+        Ok(Command::Connect(connect, addr)) => {
+            let target = match addr {
+                Address::DomainAddress(domain, port) => {
+                    let domain = String::from_utf8_lossy(&domain);
+                    TcpStream::connect((domain.as_ref(), port)).await
+                }
+                Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+            };
+
+            let Ok(mut target) = target else {
+                let replied = connect
+                    .reply(Reply::HostUnreachable, Address::unspecified())
+                    .await;
+
+                if let Ok(mut conn) = replied {
+                    let _ = conn.shutdown().await;
+                }
+
+                return Ok(());
+            };
+
+            let replied = connect
+                .reply(Reply::Succeeded, Address::unspecified())
+                .await;
+
+            let mut conn = match replied {
+                Ok(conn) => conn,
+                Err(failed) => return Err(failed.shutdown_and_err().await),
+            };
+
+            let res = io::copy_bidirectional(&mut target, &mut conn).await;
+            let _ = conn.shutdown().await;
+            let _ = target.shutdown().await;
+
+            res?;
+        }
+        Err(failed) => return Err(failed.shutdown_and_err().await),
+    }
+
+    Ok(())
+}
+
+async fn handle_associate(associate: Associate<NeedReply>) -> Result<(), IoError> {
+    // The outbound socket sends payloads to their destinations. It must be of the same address family as the destinations, so bind it on the unspecified address of the family of the TCP connection.
+    let local_ip = associate.local_addr()?.ip().to_canonical();
+    let unspecified = match local_ip {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let outbound = UdpSocket::bind(SocketAddr::new(unspecified, 0)).await?;
+
+    // Gotcha: the reply address. Replying with `0.0.0.0` (what the client-facing socket is often bound on) leaves the client nowhere to send to. `reply_with_socket()` binds the client-facing socket on the local IP address of the TCP connection, which the client is known to be able to route to, and replies with the actual bound address.
+    //
+    // Gotchas: client port 0 and source filtering. RFC 1928 says the relay should only accept datagrams from the address the client declared, but most clients do not know their UDP endpoint yet and declare `0.0.0.0:0`. `reply_with_socket()` enforces a fully specified address, and otherwise learns the endpoint from the first datagram coming from the IP address of the TCP connection. Datagrams from anyone else are dropped. Call `set_declared_client()` with `ClientMatch::Rebind` to follow clients whose NAT changes their port mid-association.
+    let (mut associate, client) = associate.reply_with_socket(None, MAX_PKT_SIZE).await?;
+
+    let res = relay(&mut associate, &client, &outbound).await;
+
+    // The association ends with the TCP connection, either way.
+    let _ = associate.close().await;
+
+    res
+}
+
+async fn relay(
+    associate: &mut Associate<Ready>,
+    client: &AssociatedUdpSocket,
+    outbound: &UdpSocket,
+) -> Result<(), IoError> {
+    let mut buf = vec![0; MAX_PKT_SIZE];
+
+    loop {
+        tokio::select! {
+            // Tear down when the client closes the control connection. `wait_close()` is cancel safe, so it can be polled in `select!` over and over.
+            res = associate.wait_close() => return res,
+
+            // Client -> destination: strip the SOCKS5 UDP header, resolve the destination if it is a domain name (e.g. DNS queries through `curl --socks5-hostname`), and forward the payload.
+            // Malformed datagrams are skipped by `recv_from_valid()`, so only an IO error on the socket ends the relay.
+            res = client.recv_from_valid() => {
+                let (pkt, header, _) = res?;
+
+                // Fragmentation is optional in RFC 1928, and dropping fragments is the recommended behavior if it is not supported.
+                if header.frag != 0 {
+                    continue;
+                }
+
+                let Ok(dst) = dns::resolve_address(&**client.resolver(), &header.address).await else {
+                    continue;
+                };
+
+                let _ = outbound.send_to(&pkt, dst).await;
+            }
+
+            // Destination -> client: add the SOCKS5 UDP header carrying the source of the reply, and send it to the learned client endpoint.
+            res = outbound.recv_from(&mut buf) => {
+                let (len, src) = res?;
+
+                // Nothing can be routed back before the client endpoint is known.
+                let Some(client_addr) = client.expected_client() else {
+                    continue;
+                };
+
+                let header = UdpHeader::new(0, Address::SocketAddress(src));
+                let _ = client.send_to(&buf[..len], &header, client_addr).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Serves SOCKS5 over a unix domain socket, e.g. for a daemon on the same host.
+//!
+//! Try it with e.g. `curl -x socks5h://localhost/tmp/socks5.sock https://example.com`, curl reaching SOCKS5 proxies over unix sockets given their path.
+
+#[cfg(unix)]
+#[tokio::main]
+async fn main() -> Result<(), std::io::Error> {
+    use socks5_server::{auth::NoAuth, Server};
+    use std::sync::Arc;
+    use tokio::net::UnixListener;
+
+    let path = "/tmp/socks5.sock";
+    let _ = std::fs::remove_file(path);
+
+    let listener = UnixListener::bind(path)?;
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+
+    while let Ok((conn, _)) = server.accept().await {
+        tokio::spawn(async move {
+            if let Err(err) = unix::handle(conn).await {
+                eprintln!("{err}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("unix domain sockets are not supported on this platform");
+}
+
+#[cfg(unix)]
+mod unix {
+    use socks5_server::{
+        connection::state::NeedAuthenticate,
+        proto::{Address, Reply},
+        Command, Error, IncomingConnection,
+    };
+    use tokio::{
+        io::{self, AsyncWriteExt},
+        net::{TcpStream, UnixStream},
+    };
+
+    pub async fn handle(
+        conn: IncomingConnection<(), NeedAuthenticate, UnixStream>,
+    ) -> Result<(), Error> {
+        // the unix address of the peer, usually unnamed
+        let peer = conn.peer_addr()?;
+
+        let conn = match conn.authenticate().await {
+            Ok((conn, _)) => conn,
+            Err(failed) => return Err(failed.shutdown_and_err().await),
+        };
+
+        let (connect, addr) = match conn.wait().await {
+            Ok(Command::Connect(connect, addr)) => (connect, addr),
+            Ok(Command::Bind(bind, _)) => {
+                let replied = bind
+                    .reply(Reply::CommandNotSupported, Address::unspecified())
+                    .await;
+
+                if let Ok(mut conn) = replied {
+                    let _ = conn.close().await;
+                }
+
+                return Ok(());
+            }
+            Ok(Command::Associate(associate, _)) => {
+                let replied = associate
+                    .reply(Reply::CommandNotSupported, Address::unspecified())
+                    .await;
+
+                if let Ok(mut conn) = replied {
+                    let _ = conn.close().await;
+                }
+
+                return Ok(());
+            }
+            Err(failed) => return Err(failed.shutdown_and_err().await),
+        };
+
+        let target = match &addr {
+            Address::DomainAddress(domain, port) => {
+                let domain = String::from_utf8_lossy(domain);
+                TcpStream::connect((domain.as_ref(), *port)).await
+            }
+            Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+        };
+
+        let Ok(mut target) = target else {
+            let replied = connect
+                .reply(Reply::HostUnreachable, Address::unspecified())
+                .await;
+
+            if let Ok(mut conn) = replied {
+                let _ = conn.shutdown().await;
+            }
+
+            return Ok(());
+        };
+
+        let replied = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await;
+
+        let mut conn = match replied {
+            Ok(conn) => conn,
+            Err(failed) => return Err(failed.shutdown_and_err().await),
+        };
+
+        eprintln!("{peer:?} connected to {addr}");
+
+        let res = io::copy_bidirectional(&mut target, &mut conn).await;
+        let _ = conn.shutdown().await;
+        let _ = target.shutdown().await;
+
+        res?;
+        Ok(())
+    }
+}
@@ -2,23 +2,33 @@
 //!
 //! The process of SOCKS5 authentication can be customized by implementing [`Auth`] trait on your own types.
 
+use crate::connection::BufferedStream;
 use async_trait::async_trait;
-use socks5_proto::handshake::{
-    password::{Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse},
-    Method,
-};
+use socks5_proto::handshake::Method;
+use std::any::Any;
 use tokio::net::TcpStream;
 
+#[cfg(feature = "password")]
+use socks5_proto::handshake::password::{
+    Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse,
+};
+#[cfg(feature = "password")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
 /// This trait is for defining the customized process of SOCKS5 authentication.
 ///
 /// You can create your own authentication method by implementing this trait. Associate type `Output` indicates the result of authenticating. Note that this library will not implicitly close any connection even if the authentication failed.
 ///
+/// The stream is handed over as a [`BufferedStream`], which may already hold the bytes of the sub-negotiation if the client pipelined them with the handshake, so read from it rather than from the underlying [`TcpStream`].
+///
+/// Generic `<S>` is the stream the connection is carried over, [`TcpStream`] by default. The pre-defined adaptors work over any stream, so they also authenticate e.g. the in-memory connections of the `test-util` cargo feature.
+///
 /// # Example
 /// ```rust
 /// use async_trait::async_trait;
 /// use std::io::Result;
 /// use socks5_proto::handshake::Method;
-/// use socks5_server::Auth;
+/// use socks5_server::{Auth, BufferedStream};
 /// use tokio::net::TcpStream;
 ///
 /// pub struct MyAuth;
@@ -31,18 +41,18 @@ use tokio::net::TcpStream;
 ///         Method(0xfe)
 ///     }
 ///
-///     async fn execute(&self, stream: &mut TcpStream) -> Self::Output {
+///     async fn execute(&self, stream: &mut BufferedStream<TcpStream>) -> Self::Output {
 ///         // do something on stream
 ///         Ok(1145141919810)
 ///     }
 /// }
 /// ```
 #[async_trait]
-pub trait Auth {
+pub trait Auth<S = TcpStream> {
     type Output;
 
     fn as_handshake_method(&self) -> Method;
-    async fn execute(&self, stream: &mut TcpStream) -> Self::Output;
+    async fn execute(&self, stream: &mut BufferedStream<S>) -> Self::Output;
 }
 
 /// Not authenticate at all.
@@ -57,25 +67,31 @@ impl NoAuth {
 }
 
 #[async_trait]
-impl Auth for NoAuth {
+impl<S: Send> Auth<S> for NoAuth {
     type Output = ();
 
     fn as_handshake_method(&self) -> Method {
         Method::NONE
     }
 
-    async fn execute(&self, _: &mut TcpStream) -> Self::Output {}
+    async fn execute(&self, _: &mut BufferedStream<S>) -> Self::Output {}
 }
 
 /// Using username and password to authenticate.
 ///
 /// The boolean value in associate type `Auth::Output` indicates whether the authentication is successful.
+///
+/// The request is read with the [`ParseLimits`](socks5_proto::ParseLimits) of the stream, see [`BufferedStream::parse_limits()`]. A username or password longer than allowed is replied to as a failure without being read, returning [`PasswordError::UsernameTooLong`] or [`PasswordError::PasswordTooLong`].
+///
+/// Enabled by the `password` cargo feature.
+#[cfg(feature = "password")]
 #[derive(Clone, Debug)]
 pub struct Password {
     pub username: Vec<u8>,
     pub password: Vec<u8>,
 }
 
+#[cfg(feature = "password")]
 impl Password {
     /// Create a new `Password` authentication adaptor.
     pub fn new(username: Vec<u8>, password: Vec<u8>) -> Self {
@@ -83,16 +99,32 @@ impl Password {
     }
 }
 
+#[cfg(feature = "password")]
 #[async_trait]
-impl Auth for Password {
+impl<S> Auth<S> for Password
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
     type Output = Result<bool, PasswordError>;
 
     fn as_handshake_method(&self) -> Method {
         Method::PASSWORD
     }
 
-    async fn execute(&self, stream: &mut TcpStream) -> Self::Output {
-        let req = PasswordRequest::read_from(stream).await?;
+    async fn execute(&self, stream: &mut BufferedStream<S>) -> Self::Output {
+        let limits = *stream.parse_limits();
+
+        let req = match PasswordRequest::read_from_with_limits(stream, &limits).await {
+            Ok(req) => req,
+            Err(
+                err @ (PasswordError::UsernameTooLong { .. }
+                | PasswordError::PasswordTooLong { .. }),
+            ) => {
+                let _ = PasswordResponse::new(false).write_to(stream).await;
+                return Err(err);
+            }
+            Err(err) => return Err(err),
+        };
 
         if (&req.username, &req.password) == (&self.username, &self.password) {
             let resp = PasswordResponse::new(true);
@@ -105,3 +137,82 @@ impl Auth for Password {
         }
     }
 }
+
+/// The output of a [`DynAuth`], i.e. the output of the adaptor it wraps, boxed.
+///
+/// Downcast it to the output type of the adaptor selected, e.g. `()` for [`NoAuth`] or `Result<bool, PasswordError>` for [`Password`].
+pub type DynOutput = Box<dyn Any + Send>;
+
+/// An authentication adaptor erasing the output type of the adaptor it wraps into a [`DynOutput`].
+///
+/// [`Server<A>`](crate::Server) is generic over the output of its adaptor, so adaptors of different outputs can not be swapped in it. Wrapping them in [`DynAuth`] gives them all the same output, so the adaptor can be selected at runtime, e.g. from a configuration, behind the same [`DynServer`](crate::DynServer). See [`Server::new_dyn()`](crate::Server::new_dyn).
+///
+/// Handlers needing the concrete output take it back with [`Box::downcast()`], trying each type the server may have been configured with.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     auth::DynOutput, connection::state::NeedAuthenticate,
+///     proto::handshake::password::Error as PasswordError, Error, IncomingConnection,
+/// };
+///
+/// async fn authenticate(conn: IncomingConnection<DynOutput, NeedAuthenticate>) -> Result<(), Error> {
+///     let (conn, output) = conn.authenticate().await?;
+///
+///     let allowed = match output.downcast::<Result<bool, PasswordError>>() {
+///         Ok(res) => matches!(*res, Ok(true)),
+///         // not `Password`, so `NoAuth` and its output `()`
+///         Err(output) => output.is::<()>(),
+///     };
+///
+///     if !allowed {
+///         return Err(Error::AuthFailed);
+///     }
+///
+///     todo!();
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DynAuth<A> {
+    inner: A,
+}
+
+impl<A> DynAuth<A> {
+    /// Wraps the `inner` adaptor.
+    #[inline]
+    pub fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    /// Returns a shared reference to the wrapped adaptor.
+    #[inline]
+    pub fn get_ref(&self) -> &A {
+        &self.inner
+    }
+
+    /// Consumes the [`DynAuth`] and returns the wrapped adaptor.
+    #[inline]
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<S, A> Auth<S> for DynAuth<A>
+where
+    S: Send,
+    A: Auth<S> + Sync,
+    A::Output: Send + 'static,
+{
+    type Output = DynOutput;
+
+    #[inline]
+    fn as_handshake_method(&self) -> Method {
+        self.inner.as_handshake_method()
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<S>) -> Self::Output {
+        Box::new(self.inner.execute(stream).await)
+    }
+}
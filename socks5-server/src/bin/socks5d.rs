@@ -0,0 +1,752 @@
+//! `socks5d`, a SOCKS5 server covering the common cases without writing any Rust.
+//!
+//! ```sh
+//! cargo install socks5-server --features bin
+//! socks5d --listen 0.0.0.0:1080 --listen [::]:1080 --htpasswd /etc/socks5d/users --deny 10.0.0.0/8
+//! ```
+//!
+//! Run with `--help` for every flag. The flags of the `ServerConfig` section map 1:1 onto the fields of [`ServerConfig`], named after their paths, e.g. `--udp-relay.idle-timeout` sets [`UdpRelayConfig::idle_timeout`], and the problems found by [`ServerConfig::validate()`] are reported by those paths. Among them, `--outbound.bind-ip` is the local IP address outbound `Connect` connections and `Bind` listeners are bound on, and `--log.format` / `--log.verbose` log errors, then every lifecycle event through an [`EventHandler`], as text or JSON lines. The other flags wire the remaining parts of the crate together:
+//!
+//! - `--listen`: one [`Server`] per address, sharing a [`ConnectionTracker`](socks5_server::tracker::ConnectionTracker)
+//! - `--username` / `--password` / `--htpasswd`: a custom [`Auth`] adaptor reading the sub-negotiation with the [`ParseLimits`] of the stream
+//! - `--no-udp`: replying [`Reply::CommandNotSupported`] to `Associate` commands instead of running [`run_relay()`]
+//! - `--allow` / `--deny`: destination ACLs checked against resolved addresses, for `Connect` and as the [`DestinationPolicy`] of the UDP relays
+
+use async_trait::async_trait;
+use socks5_server::{
+    config::{LogFormat, ServerConfig},
+    connection::{
+        associate::{run_relay, ControlKeepalive, DestinationPolicy, UdpRelayConfig},
+        state::NeedAuthenticate,
+    },
+    dns,
+    event::{AuthOutcome, CloseReason, SessionStats},
+    hostname::HostnamePolicy,
+    proto::{
+        handshake::{
+            password::{Request as PasswordRequest, Response as PasswordResponse},
+            Method,
+        },
+        Address, Command as ProtoCommand, Reply,
+    },
+    relay::{self, AbortReason, HalfClose},
+    Auth, BindAcceptor, BufferedStream, Command, Error, EventHandler, IncomingConnection, Server,
+};
+use std::{
+    collections::HashMap,
+    env,
+    fmt::Write as _,
+    fs, future,
+    io::{Error as IoError, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    process::ExitCode,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::{
+    net::{TcpListener, TcpSocket, TcpStream},
+    task::JoinSet,
+};
+
+const USAGE: &str = "\
+usage: socks5d [options]
+
+options:
+    --listen <addr>                         address to listen on, repeatable [default: 127.0.0.1:1080]
+    --username <name>                       require username / password authentication with this username
+    --password <password>                   the password, required with --username
+    --htpasswd <file>                       require username / password authentication with the `user:password` lines of a file
+    --no-udp                                refuse UDP associate commands
+    --allow <net>                           only allow destinations in this network, e.g. 192.0.2.0/24, repeatable
+    --deny <net>                            deny destinations in this network, repeatable, checked before --allow
+    -h, --help                              print this message
+
+ServerConfig:
+    --session-setup-deadline <secs>         deadline from accepting a connection to its first reply
+    --hostname-policy.max-len <n>           enable the hostname policy, with the maximum length of names [default: 255]
+    --hostname-policy.max-label-len <n>     enable the hostname policy, with the maximum length of labels [default: 63]
+    --hostname-policy.allow-underscore      enable the hostname policy, allowing underscores in names
+    --hostname-policy.allow-utf8            enable the hostname policy, allowing UTF-8 in names
+    --hostname-policy.reject-ip-literals    enable the hostname policy, rejecting IP addresses sent as names
+    --parse-limits.max-methods <n>          maximum number of handshake methods [default: 255]
+    --parse-limits.max-username-len <n>     maximum username length [default: 255]
+    --parse-limits.max-password-len <n>     maximum password length [default: 255]
+    --parse-limits.max-domain-len <n>       maximum domain name length in requests [default: 255]
+    --outbound.bind-ip <ip>                 local IP address of outbound connections and bind listeners
+    --relay.half-close <mode>               propagate or close-both [default: propagate]
+    --udp-relay.bind-ip <ip>                IP address UDP relays are bound on
+    --udp-relay.max-pkt-size <n>            maximum UDP packet size, header included [default: 1500]
+    --udp-relay.idle-timeout <secs|none>    idle timeout of associations [default: 300]
+    --udp-relay.control-keepalive <secs>    detect vanished UDP clients, probing after this long a silence
+    --udp-relay.max-destinations <n>        maximum number of destinations per association
+    --udp-relay.destination-timeout <secs>  idle timeout of a destination [default: 300]
+    --udp-relay.allow-multicast             allow datagrams to multicast groups
+    --udp-relay.restrict-return             only relay datagrams coming back from destinations sent to
+    --log.format <format>                   text or json [default: text]
+    --log.verbose, -v                       log every connection event, not only errors";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{USAGE}");
+            return ExitCode::SUCCESS;
+        }
+        Err(err) => {
+            eprintln!("socks5d: {err}\n\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Err(err) = options.config.validate() {
+        for problem in err.problems() {
+            eprintln!("socks5d: --{}: {}", flag_of(problem.field), problem.reason);
+        }
+
+        return ExitCode::FAILURE;
+    }
+
+    match serve(options).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("socks5d: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns the flag of a field path of [`ServerConfig`], e.g. `udp-relay.max-pkt-size` for `udp_relay.max_pkt_size`.
+fn flag_of(field: &str) -> String {
+    field.replace('_', "-")
+}
+
+/// The options of the daemon, parsed from the command line
+#[derive(Debug)]
+struct Options {
+    listen: Vec<SocketAddr>,
+    users: Option<Users>,
+    udp: bool,
+    acl: Acl,
+    config: ServerConfig,
+}
+
+impl Options {
+    /// Parses the command line arguments, without the program name. `Ok(None)` is returned if help was asked for.
+    fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Self>, String> {
+        let mut options = Self {
+            listen: Vec::new(),
+            users: None,
+            udp: true,
+            acl: Acl::default(),
+            config: ServerConfig::default(),
+        };
+
+        let (mut username, mut password) = (None, None);
+        let mut hostnames = None::<HostnamePolicy>;
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{arg} needs a value"));
+            let config = &mut options.config;
+
+            match arg.as_str() {
+                "--listen" => options.listen.push(parse(&arg, value()?)?),
+                "--username" => username = Some(value()?),
+                "--password" => password = Some(value()?),
+                "--htpasswd" => {
+                    let users = read_htpasswd(&value()?)?;
+                    options.users.get_or_insert_with(HashMap::new).extend(users);
+                }
+                "--no-udp" => options.udp = false,
+                "--allow" => options.acl.allow.push(parse(&arg, value()?)?),
+                "--deny" => options.acl.deny.push(parse(&arg, value()?)?),
+                "-h" | "--help" => return Ok(None),
+
+                "--session-setup-deadline" => {
+                    config.session_setup_deadline = Some(parse_secs(&arg, value()?)?)
+                }
+                "--hostname-policy.max-len" => {
+                    hostnames
+                        .get_or_insert_with(HostnamePolicy::default)
+                        .max_len = parse(&arg, value()?)?
+                }
+                "--hostname-policy.max-label-len" => {
+                    hostnames
+                        .get_or_insert_with(HostnamePolicy::default)
+                        .max_label_len = parse(&arg, value()?)?
+                }
+                "--hostname-policy.allow-underscore" => {
+                    hostnames
+                        .get_or_insert_with(HostnamePolicy::default)
+                        .allow_underscore = true
+                }
+                "--hostname-policy.allow-utf8" => {
+                    hostnames
+                        .get_or_insert_with(HostnamePolicy::default)
+                        .allow_utf8 = true
+                }
+                "--hostname-policy.reject-ip-literals" => {
+                    hostnames
+                        .get_or_insert_with(HostnamePolicy::default)
+                        .reject_ip_literals = true
+                }
+                "--parse-limits.max-methods" => {
+                    config.parse_limits.max_methods = parse(&arg, value()?)?
+                }
+                "--parse-limits.max-username-len" => {
+                    config.parse_limits.max_username_len = parse(&arg, value()?)?
+                }
+                "--parse-limits.max-password-len" => {
+                    config.parse_limits.max_password_len = parse(&arg, value()?)?
+                }
+                "--parse-limits.max-domain-len" => {
+                    config.parse_limits.max_domain_len = parse(&arg, value()?)?
+                }
+                "--outbound.bind-ip" => config.outbound.bind_ip = Some(parse(&arg, value()?)?),
+                "--relay.half-close" => {
+                    config.relay.half_close = match value()?.as_str() {
+                        "propagate" => HalfClose::Propagate,
+                        "close-both" => HalfClose::CloseBoth,
+                        mode => return Err(format!("unknown {arg} mode `{mode}`")),
+                    }
+                }
+                "--udp-relay.bind-ip" => config.udp_relay.bind_ip = Some(parse(&arg, value()?)?),
+                "--udp-relay.max-pkt-size" => {
+                    config.udp_relay.max_pkt_size = parse(&arg, value()?)?
+                }
+                "--udp-relay.idle-timeout" => {
+                    config.udp_relay.idle_timeout = match value()?.as_str() {
+                        "none" => None,
+                        secs => Some(parse_secs(&arg, secs.to_owned())?),
+                    }
+                }
+                "--udp-relay.control-keepalive" => {
+                    config.udp_relay.control_keepalive = Some(ControlKeepalive {
+                        time: parse_secs(&arg, value()?)?,
+                        ..ControlKeepalive::default()
+                    })
+                }
+                "--udp-relay.max-destinations" => {
+                    config.udp_relay.max_destinations = Some(parse(&arg, value()?)?)
+                }
+                "--udp-relay.destination-timeout" => {
+                    config.udp_relay.destination_timeout = parse_secs(&arg, value()?)?
+                }
+                "--udp-relay.allow-multicast" => config.udp_relay.allow_multicast = true,
+                "--udp-relay.restrict-return" => config.udp_relay.restrict_return = true,
+                "--log.format" => {
+                    config.log.format = match value()?.as_str() {
+                        "text" => LogFormat::Text,
+                        "json" => LogFormat::Json,
+                        format => return Err(format!("unknown {arg} `{format}`")),
+                    }
+                }
+                "--log.verbose" | "-v" => config.log.verbose = true,
+
+                arg => return Err(format!("unknown argument `{arg}`")),
+            }
+        }
+
+        if options.listen.is_empty() {
+            options
+                .listen
+                .push(SocketAddr::from(([127, 0, 0, 1], 1080)));
+        }
+
+        match (username, password) {
+            (Some(username), Some(password)) => {
+                options
+                    .users
+                    .get_or_insert_with(HashMap::new)
+                    .insert(username.into_bytes(), password.into_bytes());
+            }
+            (None, None) => {}
+            _ => return Err("--username and --password must be given together".to_owned()),
+        }
+
+        options.config.hostname_policy = hostnames;
+
+        Ok(Some(options))
+    }
+}
+
+fn parse<T: FromStr>(flag: &str, value: String) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value `{value}` for {flag}"))
+}
+
+fn parse_secs(flag: &str, value: String) -> Result<Duration, String> {
+    parse::<f64>(flag, value.clone()).and_then(|secs| {
+        Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid value `{value}` for {flag}"))
+    })
+}
+
+/// Reads the `user:password` lines of a htpasswd-style file, skipping empty lines and `#` comments.
+///
+/// Only plaintext passwords are supported, so hashed entries are rejected rather than compared as plaintext.
+fn read_htpasswd(path: &str) -> Result<Users, String> {
+    let content = fs::read_to_string(path).map_err(|err| format!("reading {path}: {err}"))?;
+    let mut users = HashMap::new();
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((username, password)) = line.split_once(':') else {
+            return Err(format!("{path}:{}: expected `user:password`", idx + 1));
+        };
+
+        if ["$apr1$", "$2a$", "$2b$", "$2y$", "$5$", "$6$", "{SHA}"]
+            .iter()
+            .any(|prefix| password.starts_with(prefix))
+        {
+            return Err(format!(
+                "{path}:{}: hashed passwords are not supported",
+                idx + 1
+            ));
+        }
+
+        users.insert(username.as_bytes().to_vec(), password.as_bytes().to_vec());
+    }
+
+    Ok(users)
+}
+
+/// A network of destinations, e.g. `192.0.2.0/24`, or a single address without a prefix length
+#[derive(Clone, Copy, Debug)]
+struct Net {
+    ip: IpAddr,
+    prefix: u8,
+}
+
+impl Net {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+
+        match (self.ip, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Net {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ip, prefix): (IpAddr, _) = match s.split_once('/') {
+            Some((ip, prefix)) => (ip.parse().map_err(|_| ())?, Some(prefix)),
+            None => (s.parse().map_err(|_| ())?, None),
+        };
+
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.map_or(Ok(max), |prefix| prefix.parse().map_err(|_| ()))?;
+
+        if prefix > max {
+            return Err(());
+        }
+
+        Ok(Self { ip, prefix })
+    }
+}
+
+/// Destination ACLs, checked against resolved addresses so domain names can not be used to bypass them
+#[derive(Clone, Debug, Default)]
+struct Acl {
+    allow: Vec<Net>,
+    deny: Vec<Net>,
+}
+
+impl Acl {
+    fn allows(&self, addr: &SocketAddr) -> bool {
+        let ip = addr.ip();
+
+        !self.deny.iter().any(|net| net.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|net| net.contains(ip)))
+    }
+}
+
+/// Prints events to the standard error, in the format of [`ServerConfig::log`]
+#[derive(Clone, Copy, Debug)]
+struct Log {
+    json: bool,
+}
+
+impl Log {
+    /// Prints an event of `peer`, with `fields` as the message in text, or as extra keys in JSON.
+    fn event(&self, peer: Option<SocketAddr>, event: &str, fields: &[(&str, &dyn ToString)]) {
+        let mut line = String::new();
+
+        if self.json {
+            let ts = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+
+            let _ = write!(line, "{{\"ts\":{ts:.3},\"event\":{}", json_str(event));
+
+            if let Some(peer) = peer {
+                let _ = write!(line, ",\"peer\":{}", json_str(&peer.to_string()));
+            }
+
+            for (key, value) in fields {
+                let _ = write!(line, ",{}:{}", json_str(key), json_str(&value.to_string()));
+            }
+
+            line.push('}');
+        } else {
+            if let Some(peer) = peer {
+                let _ = write!(line, "{peer}: ");
+            }
+
+            line.push_str(event);
+
+            for (key, value) in fields {
+                let _ = write!(line, " {key}={}", value.to_string());
+            }
+        }
+
+        eprintln!("{line}");
+    }
+}
+
+/// Quotes and escapes `s` as a JSON string.
+fn json_str(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+
+    quoted.push('"');
+    quoted
+}
+
+impl EventHandler for Log {
+    fn on_accept(&self, peer: SocketAddr) {
+        self.event(Some(peer), "accept", &[]);
+    }
+
+    fn on_auth(&self, peer: SocketAddr, method: Method, outcome: AuthOutcome) {
+        let method = method.0;
+        let outcome = format!("{outcome:?}");
+        self.event(
+            Some(peer),
+            "auth",
+            &[("method", &method), ("outcome", &outcome)],
+        );
+    }
+
+    fn on_command(&self, peer: SocketAddr, command: ProtoCommand, dest: &Address) {
+        let command = format!("{command:?}");
+        self.event(
+            Some(peer),
+            "command",
+            &[("command", &command), ("dest", dest)],
+        );
+    }
+
+    fn on_reply(&self, peer: SocketAddr, reply: Reply) {
+        let reply = format!("{reply:?}");
+        self.event(Some(peer), "reply", &[("reply", &reply)]);
+    }
+
+    fn on_close(&self, peer: SocketAddr, reason: CloseReason, stats: SessionStats) {
+        let reason = format!("{reason:?}");
+        let duration = stats.duration.as_secs_f64();
+
+        self.event(
+            Some(peer),
+            "close",
+            &[
+                ("reason", &reason),
+                ("duration", &duration),
+                ("received", &stats.received),
+                ("sent", &stats.sent),
+            ],
+        );
+    }
+}
+
+/// The passwords of the users, by username
+type Users = HashMap<Vec<u8>, Vec<u8>>;
+
+/// No authentication, or username / password authentication against a set of users. The output tells whether the client is allowed in.
+struct Credentials(Option<Users>);
+
+#[async_trait]
+impl Auth for Credentials {
+    type Output = bool;
+
+    fn as_handshake_method(&self) -> Method {
+        match self.0 {
+            Some(_) => Method::PASSWORD,
+            None => Method::NONE,
+        }
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<TcpStream>) -> Self::Output {
+        let Some(users) = &self.0 else {
+            return true;
+        };
+
+        let limits = *stream.parse_limits();
+
+        let Ok(req) = PasswordRequest::read_from_with_limits(stream, &limits).await else {
+            let _ = PasswordResponse::new(false).write_to(stream).await;
+            return false;
+        };
+
+        let allowed = users.get(&req.username) == Some(&req.password);
+        PasswordResponse::new(allowed)
+            .write_to(stream)
+            .await
+            .is_ok()
+            && allowed
+    }
+}
+
+/// What every connection needs from the options
+struct Context {
+    udp: bool,
+    acl: Acl,
+    log: Log,
+    config: ServerConfig,
+}
+
+/// Serves connections on every listen address until accepting fails on one of them.
+async fn serve(options: Options) -> Result<(), IoError> {
+    let auth = Arc::new(Credentials(options.users));
+
+    let mut config = options.config;
+    let acl = options.acl.clone();
+    config.udp_relay.destination_policy =
+        DestinationPolicy::Filter(Arc::new(move |addr| acl.allows(addr)));
+
+    let ctx = Arc::new(Context {
+        udp: options.udp,
+        acl: options.acl,
+        log: Log {
+            json: config.log.format == LogFormat::Json,
+        },
+        config,
+    });
+
+    let mut servers = Vec::with_capacity(options.listen.len());
+    let mut tracker = None;
+
+    for addr in options.listen {
+        let listener = TcpListener::bind(addr).await?;
+        let mut server = Server::with_config(listener, auth.clone(), ctx.config.clone())
+            .map_err(|err| IoError::new(ErrorKind::InvalidInput, err))?;
+
+        // one tracker for every listener, counting all the connections of the daemon
+        match &tracker {
+            Some(tracker) => server.set_connection_tracker(Clone::clone(tracker)),
+            None => tracker = Some(server.connection_tracker().clone()),
+        }
+
+        if ctx.config.log.verbose {
+            server.set_event_handler(Arc::new(ctx.log));
+        }
+
+        let local = server.local_addr()?;
+        ctx.log.event(None, "listening", &[("addr", &local)]);
+        servers.push(server);
+    }
+
+    let mut tasks = JoinSet::new();
+
+    for server in servers {
+        tasks.spawn(accept_loop(server, ctx.clone()));
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        res.map_err(IoError::other)??;
+    }
+
+    Ok(())
+}
+
+/// Accepts connections on `server`, handling each in a task of its own, until accepting fails.
+async fn accept_loop(server: Server<bool>, ctx: Arc<Context>) -> Result<(), IoError> {
+    loop {
+        let (conn, peer) = server.accept().await?;
+        let ctx = ctx.clone();
+
+        tokio::spawn(async move {
+            if let Err(err) = handle(conn, &ctx).await {
+                ctx.log.event(Some(peer), "error", &[("error", &err)]);
+            }
+        });
+    }
+}
+
+async fn handle(
+    conn: IncomingConnection<bool, NeedAuthenticate>,
+    ctx: &Context,
+) -> Result<(), Error> {
+    let conn = match conn.authenticate().await {
+        Ok((conn, true)) => conn,
+        Ok((mut conn, false)) => {
+            let _ = conn.close().await;
+            return Err(Error::AuthFailed);
+        }
+        Err(failed) => return Err(failed.shutdown_and_err().await),
+    };
+
+    let cmd = match conn.wait().await {
+        Ok(cmd) => cmd,
+        Err(failed) => return Err(failed.shutdown_and_err().await),
+    };
+
+    match cmd {
+        Command::Connect(connect, addr) => {
+            let mut target = match connect_to_target(ctx, &addr).await {
+                Ok(target) => target,
+                Err(err) => {
+                    let mut conn = connect
+                        .reply(reply_for(&err), Address::unspecified())
+                        .await?;
+
+                    let _ = conn.close().await;
+                    return Err(Error::Io(err));
+                }
+            };
+
+            let bound = Address::SocketAddress(target.local_addr()?);
+
+            let mut conn = match connect.reply(Reply::Succeeded, bound).await {
+                Ok(conn) => conn,
+                Err(failed) => return Err(failed.shutdown_and_err().await),
+            };
+
+            let (_, _, reason) = relay::copy_bidirectional_with_config(
+                &mut conn,
+                &mut target,
+                &ctx.config.relay,
+                future::pending::<()>(),
+            )
+            .await;
+
+            if let AbortReason::Error(err) = reason {
+                return Err(Error::Io(err));
+            }
+        }
+        Command::Bind(bind, _) => {
+            // by default, listen on the address the client reached the server on, which it is known to be able to route to
+            let ip = match ctx.config.outbound.bind_ip {
+                Some(ip) => ip,
+                None => bind.local_addr()?.ip(),
+            };
+
+            let acceptor = match BindAcceptor::bind(SocketAddr::new(ip, 0)).await {
+                Ok(acceptor) => acceptor,
+                Err(err) => {
+                    let _ = bind.reply(reply_for(&err), Address::unspecified()).await;
+                    return Err(Error::Io(err));
+                }
+            };
+
+            let bound = Address::SocketAddress(acceptor.local_addr()?);
+            let mut bind = bind.reply(Reply::Succeeded, bound).await?;
+
+            let (mut inbound, inbound_addr) = tokio::select! {
+                res = acceptor.accept() => res?,
+                // the client gave up waiting for the inbound connection
+                _ = bind.wait_close() => return Ok(()),
+            };
+
+            let mut bind = bind
+                .reply(Reply::Succeeded, Address::SocketAddress(inbound_addr))
+                .await?;
+
+            let (_, _, reason) = relay::copy_bidirectional_with_config(
+                &mut bind,
+                &mut inbound,
+                &ctx.config.relay,
+                future::pending::<()>(),
+            )
+            .await;
+
+            if let AbortReason::Error(err) = reason {
+                return Err(Error::Io(err));
+            }
+        }
+        Command::Associate(associate, _) if !ctx.udp => {
+            let mut conn = associate
+                .reply(Reply::CommandNotSupported, Address::unspecified())
+                .await?;
+
+            let _ = conn.close().await;
+        }
+        Command::Associate(associate, _) => {
+            run_relay(associate, UdpRelayConfig::clone(&ctx.config.udp_relay)).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Connects to the destination of a `Connect` command if the ACLs allow it, from the configured outbound IP address if any.
+async fn connect_to_target(ctx: &Context, addr: &Address) -> Result<TcpStream, IoError> {
+    let target = dns::resolve_address(&*ctx.config.udp_relay.resolver, addr).await?;
+
+    if !ctx.acl.allows(&target) {
+        return Err(IoError::new(
+            ErrorKind::PermissionDenied,
+            format!("destination {target} denied"),
+        ));
+    }
+
+    let socket = match target {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    if let Some(ip) = ctx.config.outbound.bind_ip {
+        socket.bind(SocketAddr::new(ip, 0))?;
+    }
+
+    socket.connect(target).await
+}
+
+/// Maps an error connecting to or binding for a destination to the reply telling the client why.
+fn reply_for(err: &IoError) -> Reply {
+    match err.kind() {
+        ErrorKind::ConnectionRefused => Reply::ConnectionRefused,
+        ErrorKind::HostUnreachable => Reply::HostUnreachable,
+        ErrorKind::NetworkUnreachable => Reply::NetworkUnreachable,
+        ErrorKind::PermissionDenied => Reply::ConnectionNotAllowed,
+        ErrorKind::TimedOut => Reply::TtlExpired,
+        ErrorKind::Unsupported => Reply::AddressTypeNotSupported,
+        _ => Reply::GeneralFailure,
+    }
+}
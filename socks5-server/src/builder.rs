@@ -0,0 +1,292 @@
+//! This module defines [`ServerBuilder`], binding the listener of a [`Server`] with socket options [`TcpListener::bind()`] does not expose.
+
+use crate::{
+    listener::{DualStackListener, Listener},
+    AuthAdaptor, Server,
+};
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs};
+
+/// A builder of a [`Server`], creating its [`TcpListener`] with socket options, see [`Server::builder()`].
+///
+/// The defaults are the ones of [`TcpListener::bind()`]: `SO_REUSEADDR` set on unix platforms, a backlog of 1024, and the system defaults for the rest.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, Server};
+/// use std::sync::Arc;
+///
+/// async fn listen() {
+///     let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+///         .bind_device("eth1")
+///         .backlog(4096)
+///         .bind("0.0.0.0:1080")
+///         .await
+///         .unwrap();
+///
+///     while let Ok((conn, _)) = server.accept().await {
+///         tokio::spawn(async move {
+///             todo!();
+///         });
+///     }
+/// }
+/// ```
+pub struct ServerBuilder<A> {
+    auth: AuthAdaptor<A>,
+    reuse_address: bool,
+    reuse_port: bool,
+    device: Option<String>,
+    backlog: u32,
+    only_v6: Option<bool>,
+    keepalive: Option<TcpKeepalive>,
+}
+
+impl<A> ServerBuilder<A> {
+    #[inline]
+    pub(crate) fn new(auth: AuthAdaptor<A>) -> Self {
+        Self {
+            auth,
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            device: None,
+            backlog: 1024,
+            only_v6: None,
+            keepalive: None,
+        }
+    }
+
+    /// Sets whether `SO_REUSEADDR` is set on the listener, allowing to bind the address while connections of a previous listener on it linger in `TIME_WAIT`. Set by default on unix platforms.
+    #[inline]
+    pub fn reuse_address(mut self, reuse: bool) -> Self {
+        self.reuse_address = reuse;
+        self
+    }
+
+    /// Sets whether `SO_REUSEPORT` is set on the listener, allowing other sockets with it set to bind the same address, the system balancing the incoming connections between them. Not set by default. See [`ServerBuilder::bind_reuseport()`] to bind several listeners at once.
+    ///
+    /// This is only supported on unix platforms other than Solaris, illumos and Cygwin. Elsewhere, binding fails with an error of kind [`ErrorKind::Unsupported`] when set.
+    #[inline]
+    pub fn reuse_port(mut self, reuse: bool) -> Self {
+        self.reuse_port = reuse;
+        self
+    }
+
+    /// Binds the listener to the network interface named `device` with `SO_BINDTODEVICE`, so it only accepts connections arriving on it, e.g. `eth1`.
+    ///
+    /// This is only supported on Linux, Android and Fuchsia, and usually requires `CAP_NET_RAW`. Elsewhere, [`ServerBuilder::bind()`] fails with an error of kind [`ErrorKind::Unsupported`].
+    #[inline]
+    pub fn bind_device<D: Into<String>>(mut self, device: D) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+
+    /// Sets the maximum number of connections queued for [`Server::accept()`], capped by the system, e.g. by `net.core.somaxconn` on Linux. 1024 by default.
+    #[inline]
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Sets whether a listener bound to an IPv6 address only accepts IPv6 connections with `IPV6_V6ONLY`, rather than IPv4 ones too as IPv4-mapped addresses. Ignored when binding an IPv4 address. The system default is used if not set.
+    #[inline]
+    pub fn only_v6(mut self, only_v6: bool) -> Self {
+        self.only_v6 = Some(only_v6);
+        self
+    }
+
+    /// Enables TCP keepalive with the parameters of `keepalive` on every connection the server accepts, see [`Server::set_tcp_keepalive()`].
+    #[inline]
+    pub fn tcp_keepalive(mut self, keepalive: TcpKeepalive) -> Self {
+        self.keepalive = Some(keepalive);
+        self
+    }
+
+    /// Binds the listener to `addr` with the socket options set and creates the [`Server`].
+    ///
+    /// Like [`TcpListener::bind()`], each address `addr` resolves to is tried in turn until one is bound, returning the error of the last one otherwise.
+    pub async fn bind<T: ToSocketAddrs>(self, addr: T) -> Result<Server<A>, Error> {
+        let mut last_err = None;
+
+        for addr in lookup_host(addr).await? {
+            match self.bind_addr(addr) {
+                Ok(listener) => return Ok(self.server(listener)),
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "could not resolve to any address")
+        }))
+    }
+
+    /// Binds `count` listeners to `addr` with `SO_REUSEPORT` set, whatever [`ServerBuilder::reuse_port()`] is, and the other socket options set, and creates a [`Server`] for each of them, sharing the authentication adaptor. Drive each from a task of its own, e.g. one per worker thread, so accepting connections scales across threads.
+    ///
+    /// Every listener is bound before the servers are returned, so no connection is refused in between. With port 0, the listeners are bound to the port picked for the first one. Otherwise, each address `addr` resolves to is tried in turn as in [`ServerBuilder::bind()`]. The servers have their own [`ConnectionTracker`](crate::tracker::ConnectionTracker) and [`ShutdownHandle`](crate::shutdown::ShutdownHandle), set the same ones on all of them to track or shut them down together.
+    ///
+    /// Fails with an error of kind [`ErrorKind::Unsupported`] on platforms without `SO_REUSEPORT`, see [`ServerBuilder::reuse_port()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0.
+    pub async fn bind_reuseport<T: ToSocketAddrs>(
+        self,
+        addr: T,
+        count: usize,
+    ) -> Result<Vec<Server<A>>, Error> {
+        assert!(count > 0, "no listener to bind");
+
+        let builder = Self {
+            reuse_port: true,
+            ..self
+        };
+        let mut last_err = None;
+
+        for addr in lookup_host(addr).await? {
+            match builder.bind_reuseport_addr(addr, count) {
+                Ok(listeners) => {
+                    return Ok(listeners
+                        .into_iter()
+                        .map(|listener| builder.server(listener))
+                        .collect())
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "could not resolve to any address")
+        }))
+    }
+
+    /// Binds a [`DualStackListener`] to `port` on both `0.0.0.0` and `[::]` with the socket options set, and creates the [`Server`].
+    ///
+    /// The IPv6 listener always has `IPV6_V6ONLY` set, whatever [`ServerBuilder::only_v6()`] is. With `port` 0, the IPv6 listener is bound to the port picked for the IPv4 one.
+    pub async fn bind_dual_stack(self, port: u16) -> Result<Server<A, DualStackListener>, Error> {
+        let v4 = self.bind_addr(SocketAddr::from((Ipv4Addr::UNSPECIFIED, port)))?;
+        let port = v4.local_addr()?.port();
+
+        let builder = Self {
+            only_v6: Some(true),
+            ..self
+        };
+        let v6 = builder.bind_addr(SocketAddr::from((Ipv6Addr::UNSPECIFIED, port)))?;
+
+        Ok(builder.server(DualStackListener::new(v4, v6)))
+    }
+
+    fn server<L: Listener<Stream = TcpStream>>(&self, listener: L) -> Server<A, L> {
+        let mut server = Server::new(listener, self.auth.clone());
+
+        if let Some(keepalive) = &self.keepalive {
+            server.set_tcp_keepalive(keepalive.clone());
+        }
+
+        server
+    }
+
+    fn bind_reuseport_addr(
+        &self,
+        addr: SocketAddr,
+        count: usize,
+    ) -> Result<Vec<TcpListener>, Error> {
+        let first = self.bind_addr(addr)?;
+        let addr = first.local_addr()?;
+        let mut listeners = vec![first];
+
+        for _ in 1..count {
+            listeners.push(self.bind_addr(addr)?);
+        }
+
+        Ok(listeners)
+    }
+
+    fn bind_addr(&self, addr: SocketAddr) -> Result<TcpListener, Error> {
+        let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+        socket.set_nonblocking(true)?;
+        socket.set_reuse_address(self.reuse_address)?;
+
+        if self.reuse_port {
+            sys::reuse_port(&socket)?;
+        }
+
+        if let (Some(only_v6), true) = (self.only_v6, addr.is_ipv6()) {
+            socket.set_only_v6(only_v6)?;
+        }
+
+        if let Some(device) = &self.device {
+            sys::bind_device(&socket, device)?;
+        }
+
+        socket.bind(&addr.into())?;
+        socket.listen(self.backlog.min(i32::MAX as u32) as i32)?;
+
+        TcpListener::from_std(socket.into())
+    }
+}
+
+impl<A> Debug for ServerBuilder<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ServerBuilder")
+            .field("reuse_address", &self.reuse_address)
+            .field("reuse_port", &self.reuse_port)
+            .field("device", &self.device)
+            .field("backlog", &self.backlog)
+            .field("only_v6", &self.only_v6)
+            .field("keepalive", &self.keepalive)
+            .finish()
+    }
+}
+
+/// `SO_BINDTODEVICE` and `SO_REUSEPORT` are only available on some platforms, following `socket2`. Elsewhere, an error of kind [`ErrorKind::Unsupported`] is returned.
+mod sys {
+    use socket2::Socket;
+    use std::io::Error;
+
+    #[cfg(all(
+        unix,
+        not(any(
+            target_os = "cygwin",
+            target_os = "illumos",
+            target_os = "nuttx",
+            target_os = "solaris"
+        ))
+    ))]
+    pub(super) fn reuse_port(socket: &Socket) -> Result<(), Error> {
+        socket.set_reuse_port(true)
+    }
+
+    #[cfg(not(all(
+        unix,
+        not(any(
+            target_os = "cygwin",
+            target_os = "illumos",
+            target_os = "nuttx",
+            target_os = "solaris"
+        ))
+    )))]
+    pub(super) fn reuse_port(_: &Socket) -> Result<(), Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is not supported on this platform",
+        ))
+    }
+
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub(super) fn bind_device(socket: &Socket, device: &str) -> Result<(), Error> {
+        socket.bind_device(Some(device.as_bytes()))
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "fuchsia", target_os = "linux")))]
+    pub(super) fn bind_device(_: &Socket, _: &str) -> Result<(), Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_BINDTODEVICE is not supported on this platform",
+        ))
+    }
+}
@@ -0,0 +1,215 @@
+//! Client side of the Socks5 command type `Associate`
+//!
+//! This module also provides a [`tokio::net::UdpSocket`] wrapper [`ClientUdpSocket`], the counterpart of [`AssociatedUdpSocket`](crate::AssociatedUdpSocket): it adds the SOCKS5 UDP header to packets sent through the relay, and strips it from packets received from it. It is enabled by the `udp` cargo feature.
+
+use socks5_proto::Address;
+use std::{io::Error, net::SocketAddr};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[cfg(feature = "udp")]
+use crate::dns::{self, SystemResolver};
+#[cfg(feature = "udp")]
+use bytes::{Bytes, BytesMut};
+#[cfg(feature = "udp")]
+use socks5_proto::UdpHeader;
+#[cfg(feature = "udp")]
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+#[cfg(feature = "udp")]
+use tokio::net::UdpSocket;
+
+/// An `Associate` command accepted by the server.
+///
+/// The association lasts as long as this TCP connection, so keep it open while relaying UDP packets, e.g. through a [`ClientUdpSocket`] bound with [`Associate::bind_socket()`].
+#[derive(Debug)]
+pub struct Associate {
+    stream: TcpStream,
+    relay: Address,
+}
+
+impl Associate {
+    #[inline]
+    pub(super) fn new(stream: TcpStream, relay: Address) -> Self {
+        Self { stream, relay }
+    }
+
+    /// Returns the address of the UDP relay the server replied with.
+    #[inline]
+    pub fn relay_addr(&self) -> &Address {
+        &self.relay
+    }
+
+    /// Binds a [`ClientUdpSocket`] sending to the UDP relay of the association, receiving UDP packets of up to `buf_size` bytes, SOCKS5 UDP header included.
+    ///
+    /// The socket is bound on an ephemeral port of the local IP address of this TCP connection. Servers commonly reply with an unspecified IP address, e.g. `0.0.0.0`, meaning the relay is on the same host as the server, in which case the IP address of the server is used. A domain name is resolved with [`SystemResolver`].
+    ///
+    /// Enabled by the `udp` cargo feature.
+    #[cfg(feature = "udp")]
+    pub async fn bind_socket(&self, buf_size: usize) -> Result<ClientUdpSocket, Error> {
+        let mut relay = dns::resolve_address(&SystemResolver, &self.relay).await?;
+
+        if relay.ip().is_unspecified() {
+            relay.set_ip(self.stream.peer_addr()?.ip());
+        }
+
+        let local = match (self.stream.local_addr()?.ip(), relay.ip()) {
+            (ip @ IpAddr::V4(_), IpAddr::V4(_)) | (ip @ IpAddr::V6(_), IpAddr::V6(_)) => ip,
+            (_, IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (_, IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        Ok(ClientUdpSocket::new(socket, relay, buf_size))
+    }
+
+    /// Wait until the SOCKS5 server closes this TCP connection, which ends the association.
+    ///
+    /// `Ok(())` is returned when the server closes the connection cleanly, while an error reading the connection (e.g. a reset) is returned as `Err`. Any data the server sends on this connection is discarded, as the protocol does not define any.
+    ///
+    /// This method is cancel safe.
+    pub async fn wait_close(&mut self) -> Result<(), Error> {
+        loop {
+            match self.stream.read(&mut [0]).await {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
+    ///
+    /// The server ends the association when the connection is closed.
+    #[inline]
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await
+    }
+
+    /// Returns the local address that this stream is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to, i.e. the address of the server.
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Consumes the [`Associate`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    #[inline]
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+/// A wrapper of a tokio UDP socket sending and receiving UDP packets through the UDP relay of an association.
+///
+/// Packets are sent to the relay with a SOCKS5 UDP header carrying their destination, and packets received from the relay have it stripped, returning the address of their origin instead. Packets from anyone but the relay are discarded, as are fragmented packets, which are not supported.
+///
+/// Enabled by the `udp` cargo feature.
+#[cfg(feature = "udp")]
+#[derive(Debug)]
+pub struct ClientUdpSocket {
+    socket: UdpSocket,
+    relay: SocketAddr,
+    buf_size: usize,
+}
+
+#[cfg(feature = "udp")]
+impl ClientUdpSocket {
+    /// Creates a new [`ClientUdpSocket`] with a [`UdpSocket`](tokio::net::UdpSocket), sending to the UDP relay at `relay` and receiving UDP packets of up to `buf_size` bytes, SOCKS5 UDP header included.
+    ///
+    /// If the client declared its UDP endpoint in the associate command, `socket` should be bound on it.
+    #[inline]
+    pub fn new(socket: UdpSocket, relay: SocketAddr, buf_size: usize) -> Self {
+        Self {
+            socket,
+            relay,
+            buf_size,
+        }
+    }
+
+    /// Sends a UDP packet to `dst` through the relay, returning the length of the packet sent, SOCKS5 UDP header excluded.
+    pub async fn send_to<P: AsRef<[u8]>>(&self, pkt: P, dst: Address) -> Result<usize, Error> {
+        let pkt = pkt.as_ref();
+        let header = UdpHeader::new(0, dst);
+
+        let mut buf = BytesMut::with_capacity(header.serialized_len() + pkt.len());
+        header.write_to_buf(&mut buf);
+        buf.extend_from_slice(pkt);
+
+        let len = self.socket.send_to(&buf, self.relay).await?;
+        Ok(len.saturating_sub(header.serialized_len()))
+    }
+
+    /// Receives a UDP packet from the relay, returning it with the address of its origin.
+    ///
+    /// An error is returned for a packet with a malformed SOCKS5 UDP header, which is then discarded, so the next call receives the next packet.
+    pub async fn recv_from(&self) -> Result<(Bytes, Address), Error> {
+        loop {
+            let mut buf = BytesMut::with_capacity(self.buf_size);
+            let (_, src) = self.socket.recv_buf_from(&mut buf).await?;
+
+            if src.ip().to_canonical() != self.relay.ip().to_canonical()
+                || src.port() != self.relay.port()
+            {
+                continue;
+            }
+
+            let mut pkt = buf.freeze();
+            let header = UdpHeader::read_from_buf(&mut pkt)?;
+
+            if header.frag != 0 {
+                continue;
+            }
+
+            return Ok((pkt, header.address));
+        }
+    }
+
+    /// Returns the address of the UDP relay this socket sends to.
+    #[inline]
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay
+    }
+
+    /// Returns the local address that this socket is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.socket.local_addr()
+    }
+
+    /// Returns a shared reference to the underlying socket.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 UDP relay and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Consumes the [`ClientUdpSocket`] and returns the underlying [`UdpSocket`](tokio::net::UdpSocket).
+    #[inline]
+    pub fn into_inner(self) -> UdpSocket {
+        self.socket
+    }
+}
@@ -0,0 +1,79 @@
+use crate::auth::NoAuth;
+use async_trait::async_trait;
+use socks5_proto::handshake::Method;
+use tokio::net::TcpStream;
+
+#[cfg(feature = "password")]
+use crate::auth::Password;
+#[cfg(feature = "password")]
+use socks5_proto::handshake::password::{
+    Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse,
+};
+
+/// This trait is for defining the client side of a SOCKS5 authentication method, the counterpart of [`Auth`](crate::Auth).
+///
+/// The pre-defined adaptors in [`auth`](crate::auth) implement both, so the same credentials can be used on both ends. Associate type `Output` indicates the result of authenticating. Note that this library will not implicitly close any connection even if the authentication failed.
+///
+/// # Example
+/// ```rust
+/// use async_trait::async_trait;
+/// use std::io::Result;
+/// use socks5_proto::handshake::Method;
+/// use socks5_server::client::ClientAuth;
+/// use tokio::net::TcpStream;
+///
+/// pub struct MyAuth;
+///
+/// #[async_trait]
+/// impl ClientAuth for MyAuth {
+///     type Output = Result<()>;
+///
+///     fn as_handshake_method(&self) -> Method {
+///         Method(0xfe)
+///     }
+///
+///     async fn execute(&self, stream: &mut TcpStream) -> Self::Output {
+///         // do something on stream
+///         Ok(())
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait ClientAuth {
+    type Output;
+
+    fn as_handshake_method(&self) -> Method;
+    async fn execute(&self, stream: &mut TcpStream) -> Self::Output;
+}
+
+#[async_trait]
+impl ClientAuth for NoAuth {
+    type Output = ();
+
+    fn as_handshake_method(&self) -> Method {
+        Method::NONE
+    }
+
+    async fn execute(&self, _: &mut TcpStream) -> Self::Output {}
+}
+
+/// Sends the username and password to the server.
+///
+/// The boolean value in associate type `ClientAuth::Output` indicates whether the server accepted them.
+#[cfg(feature = "password")]
+#[async_trait]
+impl ClientAuth for Password {
+    type Output = Result<bool, PasswordError>;
+
+    fn as_handshake_method(&self) -> Method {
+        Method::PASSWORD
+    }
+
+    async fn execute(&self, stream: &mut TcpStream) -> Self::Output {
+        let req = PasswordRequest::new(self.username.clone(), self.password.clone());
+        req.write_to(stream).await?;
+
+        let resp = PasswordResponse::read_from(stream).await?;
+        Ok(resp.status)
+    }
+}
@@ -0,0 +1,145 @@
+//! Client side of the Socks5 command type `Bind`
+
+use crate::Failed;
+use socks5_proto::Address;
+use std::{
+    io::Error,
+    marker::PhantomData,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::TcpStream,
+};
+
+/// Connection state types
+pub mod state {
+    #[derive(Debug)]
+    pub struct NeedSecondReply;
+
+    #[derive(Debug)]
+    pub struct Ready;
+}
+
+/// A `Bind` command accepted by the server.
+///
+/// The first reply carries the address the server listens on, which is usually passed to the inbound peer through another connection. Wait for the second reply with [`Bind::wait()`], sent once the inbound peer connected, after which the connection relays to it.
+#[derive(Debug)]
+pub struct Bind<S> {
+    stream: TcpStream,
+    addr: Address,
+    _state: PhantomData<S>,
+}
+
+impl Bind<state::NeedSecondReply> {
+    /// Returns the address the server listens on for the inbound connection, from the first reply.
+    #[inline]
+    pub fn bound_addr(&self) -> &Address {
+        &self.addr
+    }
+
+    /// Waits for the second reply of the server, sent once the inbound peer connected.
+    ///
+    /// If it is [`Reply::Succeeded`](socks5_proto::Reply::Succeeded), a [`Bind<state::Ready>`] relaying to the inbound peer is returned. A failure reply is returned as an error wrapping a [`ReplyError`](super::ReplyError), alongside the underlying [`TcpStream`] as a [`Failed`].
+    ///
+    /// This method is not cancel safe, as a partially read reply is lost.
+    pub async fn wait(mut self) -> Result<Bind<state::Ready>, Failed<TcpStream>> {
+        match super::read_reply(&mut self.stream).await {
+            Ok(inbound) => Ok(Bind::new(self.stream, inbound)),
+            Err(err) => Err(Failed::new(err, self.stream)),
+        }
+    }
+}
+
+impl Bind<state::Ready> {
+    /// Returns the address of the inbound peer, from the second reply.
+    #[inline]
+    pub fn inbound_addr(&self) -> &Address {
+        &self.addr
+    }
+}
+
+impl<S> Bind<S> {
+    #[inline]
+    pub(super) fn new(stream: TcpStream, addr: Address) -> Self {
+        Self {
+            stream,
+            addr,
+            _state: PhantomData,
+        }
+    }
+
+    /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
+    #[inline]
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await
+    }
+
+    /// Returns the local address that this stream is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to, i.e. the address of the server.
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Consumes the [`Bind<S>`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    #[inline]
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl AsyncRead for Bind<state::Ready> {
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Bind<state::Ready> {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,107 @@
+//! Client side of the Socks5 command type `Connect`
+
+use socks5_proto::Address;
+use std::{
+    io::Error,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::TcpStream,
+};
+
+/// A `Connect` command accepted by the server, tunnelling the TCP connection to the target.
+///
+/// Read and write it like the connection to the target.
+#[derive(Debug)]
+pub struct Connect {
+    stream: TcpStream,
+    bound: Address,
+}
+
+impl Connect {
+    #[inline]
+    pub(super) fn new(stream: TcpStream, bound: Address) -> Self {
+        Self { stream, bound }
+    }
+
+    /// Returns the address the server replied with, i.e. the address the server connects to the target from.
+    #[inline]
+    pub fn bound_addr(&self) -> &Address {
+        &self.bound
+    }
+
+    /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
+    #[inline]
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await
+    }
+
+    /// Returns the local address that this stream is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to, i.e. the address of the server.
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Consumes the [`Connect`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    #[inline]
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+impl AsyncRead for Connect {
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Connect {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
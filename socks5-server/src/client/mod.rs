@@ -0,0 +1,269 @@
+//! The client side of the SOCKS5 protocol
+//!
+//! This module mirrors [`connection`](crate::connection) for the other end of the connection: [`ClientConnection`] negotiates with a SOCKS5 server over a [`TcpStream`](tokio::net::TcpStream) using the same protocol types, and the authentication adaptors in [`auth`](crate::auth) can be used on both ends (see [`ClientAuth`]).
+//!
+//! Enabled by the `client` cargo feature.
+
+use self::{associate::Associate, bind::Bind, connect::Connect};
+use crate::Failed;
+use socks5_proto::{
+    handshake::{Request as HandshakeRequest, Response as HandshakeResponse},
+    Address, Command as ProtocolCommand, Error, ProtocolError, Reply, Request, Response,
+};
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{Error as IoError, ErrorKind},
+    marker::PhantomData,
+    net::SocketAddr,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+mod auth;
+
+pub mod associate;
+pub mod bind;
+pub mod connect;
+
+pub use self::auth::ClientAuth;
+
+/// Client connection state types
+pub mod state {
+    #[derive(Debug)]
+    pub struct NeedAuthenticate;
+
+    #[derive(Debug)]
+    pub struct NeedCommand;
+}
+
+/// A connection to a SOCKS5 server.
+///
+/// Call [`ClientConnection::authenticate()`] and one of the command methods, e.g. [`ClientConnection::connect()`], to perform a SOCKS5 connection negotiation.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, client::ClientConnection, proto::Address};
+/// use tokio::{io::AsyncWriteExt, net::TcpStream};
+///
+/// async fn connect() {
+///     let stream = TcpStream::connect("127.0.0.1:5000").await.unwrap();
+///
+///     let (conn, ()) = ClientConnection::new(stream)
+///         .authenticate(&NoAuth)
+///         .await
+///         .unwrap();
+///
+///     let addr = Address::DomainAddress(b"example.com".to_vec(), 80);
+///     let mut connect = conn.connect(addr).await.unwrap();
+///     connect.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ClientConnection<S> {
+    stream: TcpStream,
+    _state: PhantomData<S>,
+}
+
+impl ClientConnection<state::NeedAuthenticate> {
+    /// Creates a new [`ClientConnection`] over a TCP connection to a SOCKS5 server.
+    #[inline]
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            _state: PhantomData,
+        }
+    }
+
+    /// Perform a SOCKS5 authentication handshake using the given [`ClientAuth`] adapter.
+    ///
+    /// The method of the adapter is the only one offered. If the server accepts it, the adapter is executed and its output is returned alongside a [`ClientConnection<state::NeedCommand>`]. If the server chooses another method, e.g. [`Method::UNACCEPTABLE`](socks5_proto::handshake::Method::UNACCEPTABLE), a [`ProtocolError::NoAcceptableHandshakeMethod`] with the method chosen by the server is returned. Otherwise, the error and the underlying [`TcpStream`] are returned as a [`Failed`].
+    ///
+    /// Note that this method will not implicitly close the connection even if the handshake failed.
+    pub async fn authenticate<A>(
+        mut self,
+        auth: &A,
+    ) -> Result<(ClientConnection<state::NeedCommand>, A::Output), Failed<TcpStream>>
+    where
+        A: ClientAuth + ?Sized,
+    {
+        let method = auth.as_handshake_method();
+        let req = HandshakeRequest::new(vec![method]);
+
+        if let Err(err) = req.write_to(&mut self.stream).await {
+            return Err(Failed::new(err, self.stream));
+        }
+
+        let resp = match HandshakeResponse::read_from(&mut self.stream).await {
+            Ok(resp) => resp,
+            Err(err) => return Err(Failed::new(err, self.stream)),
+        };
+
+        if resp.method != method {
+            return Err(Failed::new(
+                ProtocolError::NoAcceptableHandshakeMethod {
+                    version: socks5_proto::SOCKS_VERSION,
+                    chosen_method: resp.method,
+                    methods: vec![method],
+                },
+                self.stream,
+            ));
+        }
+
+        let output = auth.execute(&mut self.stream).await;
+
+        Ok((ClientConnection::from_stream(self.stream), output))
+    }
+}
+
+impl ClientConnection<state::NeedCommand> {
+    /// Sends a `Connect` command to the server, asking it to connect to `addr`.
+    ///
+    /// If the server replies with [`Reply::Succeeded`], a [`Connect`] tunnelling to the target is returned. A failure reply is returned as an error wrapping a [`ReplyError`], alongside the underlying [`TcpStream`] as a [`Failed`].
+    pub async fn connect(mut self, addr: Address) -> Result<Connect, Failed<TcpStream>> {
+        match self.request(ProtocolCommand::Connect, addr).await {
+            Ok(bound) => Ok(Connect::new(self.stream, bound)),
+            Err(err) => Err(Failed::new(err, self.stream)),
+        }
+    }
+
+    /// Sends a `Bind` command to the server, asking it to accept an inbound connection from `addr`.
+    ///
+    /// If the first reply of the server is [`Reply::Succeeded`], a [`Bind`] waiting for the second reply is returned, with the address the server listens on. A failure reply is returned as an error wrapping a [`ReplyError`], alongside the underlying [`TcpStream`] as a [`Failed`].
+    pub async fn bind(
+        mut self,
+        addr: Address,
+    ) -> Result<Bind<bind::state::NeedSecondReply>, Failed<TcpStream>> {
+        match self.request(ProtocolCommand::Bind, addr).await {
+            Ok(bound) => Ok(Bind::new(self.stream, bound)),
+            Err(err) => Err(Failed::new(err, self.stream)),
+        }
+    }
+
+    /// Sends an `Associate` command to the server, declaring `addr` as the UDP endpoint the client is going to send from. Most clients do not know it yet and declare [`Address::unspecified()`].
+    ///
+    /// If the server replies with [`Reply::Succeeded`], an [`Associate`] holding the association is returned, with the address of the UDP relay. A failure reply is returned as an error wrapping a [`ReplyError`], alongside the underlying [`TcpStream`] as a [`Failed`].
+    pub async fn associate(mut self, addr: Address) -> Result<Associate, Failed<TcpStream>> {
+        match self.request(ProtocolCommand::Associate, addr).await {
+            Ok(relay) => Ok(Associate::new(self.stream, relay)),
+            Err(err) => Err(Failed::new(err, self.stream)),
+        }
+    }
+
+    async fn request(&mut self, command: ProtocolCommand, addr: Address) -> Result<Address, Error> {
+        Request::new(command, addr)
+            .write_to(&mut self.stream)
+            .await?;
+        read_reply(&mut self.stream).await
+    }
+}
+
+impl<S> ClientConnection<S> {
+    #[inline]
+    fn from_stream(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            _state: PhantomData,
+        }
+    }
+
+    /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
+    #[inline]
+    pub async fn close(&mut self) -> Result<(), IoError> {
+        self.stream.shutdown().await
+    }
+
+    /// Returns the local address that this stream is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.stream.local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to.
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr, IoError> {
+        self.stream.peer_addr()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &TcpStream {
+        &self.stream
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Consumes the [`ClientConnection<S>`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    #[inline]
+    pub fn into_inner(self) -> TcpStream {
+        self.stream
+    }
+}
+
+/// Reads a reply of the server, returning its address if it is [`Reply::Succeeded`].
+async fn read_reply(stream: &mut TcpStream) -> Result<Address, Error> {
+    let resp = Response::read_from(stream).await?;
+
+    match resp.reply {
+        Reply::Succeeded => Ok(resp.address),
+        reply => Err(Error::Io(
+            ReplyError {
+                reply,
+                address: resp.address,
+            }
+            .into(),
+        )),
+    }
+}
+
+/// Error of a SOCKS5 server replying to a command with a failure.
+///
+/// It is returned wrapped in an [`std::io::Error`], of a kind matching the reply where there is one, e.g. [`ErrorKind::ConnectionRefused`] for [`Reply::ConnectionRefused`], and can be recovered with [`ReplyError::from_io_error()`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ReplyError {
+    /// The reply of the server
+    pub reply: Reply,
+
+    /// The address in the reply, usually meaningless on a failure
+    pub address: Address,
+}
+
+impl ReplyError {
+    /// Returns the [`ReplyError`] wrapped in `err`, if any.
+    #[inline]
+    pub fn from_io_error(err: &IoError) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for ReplyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "SOCKS5 server replied {:?}", self.reply)
+    }
+}
+
+impl StdError for ReplyError {}
+
+impl From<ReplyError> for IoError {
+    fn from(err: ReplyError) -> Self {
+        let kind = match err.reply {
+            Reply::ConnectionNotAllowed => ErrorKind::PermissionDenied,
+            Reply::NetworkUnreachable => ErrorKind::NetworkUnreachable,
+            Reply::HostUnreachable => ErrorKind::HostUnreachable,
+            Reply::ConnectionRefused => ErrorKind::ConnectionRefused,
+            Reply::TtlExpired => ErrorKind::TimedOut,
+            Reply::CommandNotSupported | Reply::AddressTypeNotSupported => ErrorKind::Unsupported,
+            Reply::Succeeded | Reply::GeneralFailure => ErrorKind::Other,
+        };
+
+        IoError::new(kind, err)
+    }
+}
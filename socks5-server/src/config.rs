@@ -0,0 +1,273 @@
+//! This module defines [`ServerConfig`], gathering the tunables of this crate in one place, e.g. to build a [`Server`](crate::Server) from a configuration file with [`Server::with_config()`](crate::Server::with_config).
+
+use crate::{hostname::HostnamePolicy, relay::RelayConfig};
+use socks5_proto::ParseLimits;
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    net::IpAddr,
+    time::Duration,
+};
+
+#[cfg(feature = "udp")]
+use crate::connection::associate::{FragmentPolicy, UdpRelayConfig};
+
+/// The tunables of a SOCKS5 server
+///
+/// The settings of the [`Server`](crate::Server) itself are applied by [`Server::with_config()`](crate::Server::with_config). The nested relay configurations are kept for the handlers to pass to the relay helpers, see [`Server::config()`](crate::Server::config).
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     auth::NoAuth, config::ServerConfig, hostname::HostnamePolicy, relay::HalfClose, Server,
+/// };
+/// use std::{sync::Arc, time::Duration};
+/// use tokio::net::TcpListener;
+///
+/// async fn listen() -> Server<()> {
+///     let mut config = ServerConfig::default();
+///     config.session_setup_deadline = Some(Duration::from_secs(10));
+///     config.hostname_policy = Some(HostnamePolicy::default());
+///     config.relay.half_close = HalfClose::CloseBoth;
+///
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     Server::with_config(listener, Arc::new(NoAuth) as Arc<_>, config).unwrap()
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ServerConfig {
+    /// How long a connection may take from being accepted to its first reply being sent, see [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline). `None` by default, without a deadline.
+    pub session_setup_deadline: Option<Duration>,
+
+    /// The rules domain names requested by clients must follow, see [`Server::set_hostname_policy()`](crate::Server::set_hostname_policy). `None` by default, passing every name through.
+    pub hostname_policy: Option<HostnamePolicy>,
+
+    /// How [`Server::accept_retrying()`](crate::Server::accept_retrying) backs off on transient errors, see [`AcceptBackoff`].
+    pub accept_backoff: AcceptBackoff,
+
+    /// The limits the negotiation messages are read with, see [`Server::set_parse_limits()`](crate::Server::set_parse_limits). The maximums of the protocol by default.
+    pub parse_limits: ParseLimits,
+
+    /// Where the connections and listeners the handlers open towards destinations are bound, see [`OutboundConfig`].
+    pub outbound: OutboundConfig,
+
+    /// The configuration of relaying the TCP connections of `Connect` and `Bind` commands, for [`relay::copy_bidirectional_with_config()`](crate::relay::copy_bidirectional_with_config)
+    pub relay: RelayConfig,
+
+    /// The configuration of relaying the UDP associations of `Associate` commands, for [`run_relay()`](crate::connection::associate::run_relay)
+    ///
+    /// Enabled by the `udp` cargo feature.
+    #[cfg(feature = "udp")]
+    pub udp_relay: UdpRelayConfig,
+
+    /// How the events of the server are logged, see [`LogConfig`].
+    pub log: LogConfig,
+}
+
+/// How long [`Server::accept_retrying()`](crate::Server::accept_retrying) waits before accepting again when the process or the system is out of file descriptors or memory
+///
+/// The delay starts at `initial` and doubles on every consecutive failure, up to `max`, so accepting does not spin while the resources stay exhausted. A connection accepted resets it.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct AcceptBackoff {
+    /// The delay after the first failure. 5 ms by default.
+    pub initial: Duration,
+
+    /// The longest delay. 1 second by default.
+    pub max: Duration,
+}
+
+impl Default for AcceptBackoff {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(5),
+            max: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Where the handlers bind the outbound side of the commands they serve
+///
+/// The server does not open outbound connections itself, so the settings are kept for the handlers, like the relay configurations.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct OutboundConfig {
+    /// The local IP address the connections of `Connect` commands and the listeners of `Bind` commands are bound on. `None` by default, leaving the choice to the OS for connections, and usually binding listeners on the address the client reached the server on.
+    pub bind_ip: Option<IpAddr>,
+}
+
+/// How the events of a server are logged
+///
+/// The server does not log by itself. The settings are kept for the [`EventHandler`](crate::EventHandler) printing them, e.g. the one of `socks5d`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct LogConfig {
+    /// The format of the log lines. [`LogFormat::Text`] by default.
+    pub format: LogFormat,
+
+    /// Whether every event of a connection is logged, rather than only errors. `false` by default.
+    pub verbose: bool,
+}
+
+/// The format of log lines, see [`LogConfig::format`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum LogFormat {
+    /// Human-readable text
+    #[default]
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+impl ServerConfig {
+    /// Checks the configuration, returning every problem found rather than just the first one.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+        let mut problem = |field, reason: &str| {
+            problems.push(ConfigProblem {
+                field,
+                reason: reason.to_owned(),
+            })
+        };
+
+        if self.session_setup_deadline == Some(Duration::ZERO) {
+            problem("session_setup_deadline", "must be positive");
+        }
+
+        if let Some(policy) = &self.hostname_policy {
+            if policy.max_len == 0 || policy.max_len > 255 {
+                problem("hostname_policy.max_len", "must be between 1 and 255");
+            }
+
+            if policy.max_label_len == 0 {
+                problem("hostname_policy.max_label_len", "must be positive");
+            }
+        }
+
+        if self.accept_backoff.initial.is_zero() {
+            problem("accept_backoff.initial", "must be positive");
+        }
+
+        if self.accept_backoff.max < self.accept_backoff.initial {
+            problem(
+                "accept_backoff.max",
+                "must be at least accept_backoff.initial",
+            );
+        }
+
+        if self.parse_limits.max_methods == 0 {
+            problem(
+                "parse_limits.max_methods",
+                "must be positive, or no handshake is accepted",
+            );
+        }
+
+        #[cfg(feature = "udp")]
+        self.validate_udp_relay(&mut problem);
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError { problems })
+        }
+    }
+
+    #[cfg(feature = "udp")]
+    fn validate_udp_relay(&self, problem: &mut impl FnMut(&'static str, &str)) {
+        let udp = &self.udp_relay;
+
+        // the smallest SOCKS5 UDP header, of an IPv4 address
+        if udp.max_pkt_size <= 10 || udp.max_pkt_size > 65535 {
+            problem(
+                "udp_relay.max_pkt_size",
+                "must leave room for a payload after the SOCKS5 UDP header, and fit in a UDP datagram",
+            );
+        }
+
+        if udp.buffer_pool.slab_size() < udp.max_pkt_size {
+            problem(
+                "udp_relay.buffer_pool",
+                "slab size must be at least udp_relay.max_pkt_size",
+            );
+        }
+
+        if udp.idle_timeout == Some(Duration::ZERO) {
+            problem("udp_relay.idle_timeout", "must be positive");
+        }
+
+        if let Some(keepalive) = &udp.control_keepalive {
+            if keepalive.time.as_secs() == 0 || keepalive.interval.as_secs() == 0 {
+                problem(
+                    "udp_relay.control_keepalive",
+                    "time and interval must be at least 1 second, the granularity of TCP keepalive",
+                );
+            }
+
+            if keepalive.retries == 0 {
+                problem("udp_relay.control_keepalive", "retries must be positive");
+            }
+        }
+
+        if udp.destination_timeout.is_zero() {
+            problem("udp_relay.destination_timeout", "must be positive");
+        }
+
+        if udp.max_destinations == Some(0) {
+            problem("udp_relay.max_destinations", "must be positive");
+        }
+
+        if let FragmentPolicy::Reassemble { timeout, .. } = udp.fragment_policy {
+            if timeout < Duration::from_secs(5) {
+                problem(
+                    "udp_relay.fragment_policy",
+                    "reassembly timeout must be at least 5 seconds, as RFC 1928 requires",
+                );
+            }
+        }
+    }
+}
+
+/// The problems found by [`ServerConfig::validate()`]
+#[derive(Clone, Debug)]
+pub struct ConfigError {
+    problems: Vec<ConfigProblem>,
+}
+
+impl ConfigError {
+    /// Returns every problem found, in the order of the fields.
+    #[inline]
+    pub fn problems(&self) -> &[ConfigProblem] {
+        &self.problems
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("invalid server configuration")?;
+
+        for problem in &self.problems {
+            write!(f, "; {problem}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StdError for ConfigError {}
+
+/// A problem of a field of a [`ServerConfig`]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigProblem {
+    /// The path of the field, e.g. `udp_relay.max_pkt_size`
+    pub field: &'static str,
+
+    /// What is wrong with it
+    pub reason: String,
+}
+
+impl Display for ConfigProblem {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "{}: {}", self.field, self.reason)
+    }
+}
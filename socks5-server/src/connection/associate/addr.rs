@@ -0,0 +1,26 @@
+use std::net::SocketAddr;
+
+/// Converts a v4-mapped IPv6 socket address to the IPv4 socket address it maps, leaving other addresses untouched.
+///
+/// A dual-stack socket reports IPv4 peers as v4-mapped IPv6 addresses, e.g. `[::ffff:127.0.0.1]:5000`, which never compare equal to the IPv4 address of the same peer, e.g. as recorded from the TCP connection. Addresses of clients and destinations in the UDP path are compared in this form, so they match whichever socket they come from.
+#[inline]
+pub(super) fn canonical(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(v6) => match v6.ip().to_ipv4_mapped() {
+            Some(ip) => SocketAddr::new(ip.into(), v6.port()),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+/// A socket address in canonical form, see [`canonical()`]. Used as a key, so an entry is found whichever form of the address it is looked up with.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(super) struct CanonicalAddr(SocketAddr);
+
+impl From<SocketAddr> for CanonicalAddr {
+    #[inline]
+    fn from(addr: SocketAddr) -> Self {
+        Self(canonical(addr))
+    }
+}
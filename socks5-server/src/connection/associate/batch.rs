@@ -0,0 +1,477 @@
+//! Batched UDP IO, built on `recvmmsg` / `sendmmsg` on Linux and falling back to one datagram per call elsewhere
+
+use bytes::BytesMut;
+use std::{
+    borrow::BorrowMut,
+    io::Error,
+    net::SocketAddr,
+    ops::Range,
+    task::{Context, Poll},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+/// The number of datagrams the relay tries to receive / send in one system call
+pub(super) const BATCH_SIZE: usize = 32;
+
+/// A datagram to be sent in a batch, made of a header and a payload sent back to back.
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Datagram<'a> {
+    pub(super) header: &'a [u8],
+    pub(super) payload: &'a [u8],
+    pub(super) addr: SocketAddr,
+}
+
+/// A datagram received by [`recv_batch()`] or [`try_recv_batch()`]
+#[derive(Clone, Debug)]
+pub(super) struct Received {
+    /// The index of the buffer the datagram is appended to
+    pub(super) idx: usize,
+
+    /// The range of the datagram in the buffer
+    pub(super) range: Range<usize>,
+
+    /// The source address
+    pub(super) addr: SocketAddr,
+
+    /// The original length of the datagram, or `None` if the datagram was truncated and the platform does not report its original length
+    pub(super) len: Option<usize>,
+}
+
+impl Received {
+    /// Returns whether the datagram is larger than `max`, i.e. truncated or about to be.
+    #[inline]
+    pub(super) fn is_oversize(&self, max: usize) -> bool {
+        is_oversize(self.len, max)
+    }
+}
+
+/// Returns whether a datagram of the original length `len` is larger than `max`, an unknown length meaning it was truncated.
+#[inline]
+pub(super) fn is_oversize(len: Option<usize>, max: usize) -> bool {
+    len.is_none_or(|len| len > max)
+}
+
+/// Receives at least one datagram, appending the `i`-th received datagram to `bufs[i]` after reserving more than `buf_size` bytes in it, so datagrams larger than `buf_size` can be told apart.
+pub(super) async fn recv_batch<B: BorrowMut<BytesMut>>(
+    socket: &UdpSocket,
+    bufs: &mut [B],
+    buf_size: usize,
+) -> Result<Vec<Received>, Error> {
+    if bufs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    reserve(bufs, buf_size);
+    sys::recv_batch(socket, bufs).await
+}
+
+/// Tries to receive datagrams without waiting, like [`recv_batch()`]. Returns [`ErrorKind::WouldBlock`](std::io::ErrorKind::WouldBlock) if no datagram is ready.
+pub(super) fn try_recv_batch<B: BorrowMut<BytesMut>>(
+    socket: &UdpSocket,
+    bufs: &mut [B],
+    buf_size: usize,
+) -> Result<Vec<Received>, Error> {
+    if bufs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    reserve(bufs, buf_size);
+    sys::try_recv_batch(socket, bufs)
+}
+
+/// Attempts to receive a datagram into the unfilled portion of `buf`. Returns the source address and the original length of the datagram, or `None` if it was truncated and the platform does not report its original length.
+pub(super) fn poll_recv_from(
+    socket: &UdpSocket,
+    cx: &mut Context<'_>,
+    buf: &mut ReadBuf<'_>,
+) -> Poll<Result<(SocketAddr, Option<usize>), Error>> {
+    sys::poll_recv_from(socket, cx, buf)
+}
+
+fn reserve<B: BorrowMut<BytesMut>>(bufs: &mut [B], buf_size: usize) {
+    for buf in bufs.iter_mut() {
+        buf.borrow_mut().reserve(buf_size + 1);
+    }
+}
+
+/// Sends datagrams from the start of `dgrams`, returning the number of datagrams sent.
+///
+/// This may send fewer datagrams than given, e.g. if one of them fails to be sent. An error is only returned if the first datagram fails to be sent.
+pub(super) async fn send_batch(
+    socket: &UdpSocket,
+    dgrams: &[Datagram<'_>],
+) -> Result<usize, Error> {
+    if dgrams.is_empty() {
+        return Ok(0);
+    }
+
+    sys::send_batch(socket, dgrams).await
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::{Datagram, Received};
+    use bytes::BytesMut;
+    use std::{
+        borrow::BorrowMut,
+        io::{Error, ErrorKind},
+        mem::{self, MaybeUninit},
+        net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+        os::fd::{AsRawFd, RawFd},
+        ptr,
+        task::{ready, Context, Poll},
+    };
+    use tokio::{
+        io::{Interest, ReadBuf},
+        net::UdpSocket,
+    };
+
+    pub(super) async fn recv_batch<B: BorrowMut<BytesMut>>(
+        socket: &UdpSocket,
+        bufs: &mut [B],
+    ) -> Result<Vec<Received>, Error> {
+        let fd = socket.as_raw_fd();
+
+        socket
+            .async_io(Interest::READABLE, || recv_into_bufs(fd, bufs))
+            .await
+    }
+
+    pub(super) fn try_recv_batch<B: BorrowMut<BytesMut>>(
+        socket: &UdpSocket,
+        bufs: &mut [B],
+    ) -> Result<Vec<Received>, Error> {
+        let fd = socket.as_raw_fd();
+        socket.try_io(Interest::READABLE, || recv_into_bufs(fd, bufs))
+    }
+
+    pub(super) fn poll_recv_from(
+        socket: &UdpSocket,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, Option<usize>), Error>> {
+        let fd = socket.as_raw_fd();
+
+        loop {
+            ready!(socket.poll_recv_ready(cx))?;
+
+            let res = socket.try_io(Interest::READABLE, || {
+                // SAFETY: the kernel only writes initialized bytes into the unfilled portion
+                let mut spares = [unsafe { buf.unfilled_mut() }];
+                let cap = spares[0].len();
+
+                let received = recvmmsg(fd, &mut spares)?.pop();
+                Ok(received.map(|(_, len, addr)| (cap, len, addr)))
+            });
+
+            match res {
+                Ok(Some((cap, len, addr))) => {
+                    let filled = len.min(cap);
+
+                    // SAFETY: the kernel initialized `filled` bytes of the unfilled portion
+                    unsafe { buf.assume_init(filled) };
+                    buf.advance(filled);
+
+                    return Poll::Ready(Ok((addr, Some(len))));
+                }
+                // a datagram from an address of an unknown family was skipped
+                Ok(None) => continue,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                Err(err) => return Poll::Ready(Err(err)),
+            }
+        }
+    }
+
+    fn recv_into_bufs<B: BorrowMut<BytesMut>>(
+        fd: RawFd,
+        bufs: &mut [B],
+    ) -> Result<Vec<Received>, Error> {
+        let mut spares = bufs
+            .iter_mut()
+            .map(|buf| buf.borrow_mut().spare_capacity_mut())
+            .collect::<Vec<_>>();
+
+        let caps = spares.iter().map(|spare| spare.len()).collect::<Vec<_>>();
+        let received = recvmmsg(fd, &mut spares)?;
+        drop(spares);
+
+        Ok(received
+            .into_iter()
+            .map(|(idx, len, addr)| {
+                let buf = bufs[idx].borrow_mut();
+                let start = buf.len();
+                let filled = len.min(caps[idx]);
+
+                // SAFETY: the kernel initialized `filled` bytes of the spare capacity
+                unsafe { buf.set_len(start + filled) };
+
+                Received {
+                    idx,
+                    range: start..start + filled,
+                    addr,
+                    len: Some(len),
+                }
+            })
+            .collect())
+    }
+
+    pub(super) async fn send_batch(
+        socket: &UdpSocket,
+        dgrams: &[Datagram<'_>],
+    ) -> Result<usize, Error> {
+        let fd = socket.as_raw_fd();
+
+        socket
+            .async_io(Interest::WRITABLE, || sendmmsg(fd, dgrams))
+            .await
+    }
+
+    /// Receives datagrams into `spares` with `MSG_TRUNC`, returning the index of the buffer, the original length and the source address of each datagram.
+    fn recvmmsg(
+        fd: RawFd,
+        spares: &mut [&mut [MaybeUninit<u8>]],
+    ) -> Result<Vec<(usize, usize, SocketAddr)>, Error> {
+        // SAFETY: all-zero is a valid `sockaddr_storage`
+        let mut addrs = vec![unsafe { mem::zeroed::<libc::sockaddr_storage>() }; spares.len()];
+
+        let mut iovs = spares
+            .iter_mut()
+            .map(|spare| libc::iovec {
+                iov_base: spare.as_mut_ptr().cast(),
+                iov_len: spare.len(),
+            })
+            .collect::<Vec<_>>();
+
+        let mut msgs = iovs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, addr)| {
+                // SAFETY: all-zero is a valid `msghdr`
+                let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+                hdr.msg_name = ptr::from_mut(addr).cast();
+                hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+                hdr.msg_iov = iov;
+                hdr.msg_iovlen = 1;
+
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // SAFETY: every message points to a valid address buffer and a valid iovec over a spare buffer, all outliving the call. With `MSG_TRUNC`, `msg_len` is the original length of the datagram, while the kernel still writes no more than the iovec length
+        let res = unsafe {
+            libc::recvmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                (libc::MSG_DONTWAIT | libc::MSG_TRUNC) as _,
+                ptr::null_mut(),
+            )
+        };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let mut received = Vec::with_capacity(res as usize);
+
+        for (idx, (msg, addr)) in msgs.iter().zip(addrs.iter()).take(res as usize).enumerate() {
+            let Some(addr) = from_sockaddr(addr) else {
+                continue;
+            };
+
+            received.push((idx, msg.msg_len as usize, addr));
+        }
+
+        Ok(received)
+    }
+
+    fn sendmmsg(fd: RawFd, dgrams: &[Datagram<'_>]) -> Result<usize, Error> {
+        let mut addrs = dgrams
+            .iter()
+            .map(|dgram| to_sockaddr(dgram.addr))
+            .collect::<Vec<_>>();
+
+        let mut iovs = dgrams
+            .iter()
+            .map(|dgram| {
+                [
+                    libc::iovec {
+                        iov_base: dgram.header.as_ptr() as *mut _,
+                        iov_len: dgram.header.len(),
+                    },
+                    libc::iovec {
+                        iov_base: dgram.payload.as_ptr() as *mut _,
+                        iov_len: dgram.payload.len(),
+                    },
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut msgs = iovs
+            .iter_mut()
+            .zip(addrs.iter_mut())
+            .map(|(iov, (addr, addr_len))| {
+                // SAFETY: all-zero is a valid `msghdr`
+                let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+                hdr.msg_name = ptr::from_mut(addr).cast();
+                hdr.msg_namelen = *addr_len;
+                hdr.msg_iov = iov.as_mut_ptr();
+                hdr.msg_iovlen = iov.len() as _;
+
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // SAFETY: every message points to a valid address and valid iovecs over the borrowed datagrams, all outliving the call. The kernel does not write through the iovecs
+        let res = unsafe {
+            libc::sendmmsg(
+                fd,
+                msgs.as_mut_ptr(),
+                msgs.len() as _,
+                libc::MSG_DONTWAIT as _,
+            )
+        };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(res as usize)
+    }
+
+    fn from_sockaddr(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+        match addr.ss_family as libc::c_int {
+            libc::AF_INET => {
+                // SAFETY: the address family is checked
+                let addr = unsafe { &*ptr::from_ref(addr).cast::<libc::sockaddr_in>() };
+
+                Some(SocketAddr::V4(SocketAddrV4::new(
+                    Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)),
+                    u16::from_be(addr.sin_port),
+                )))
+            }
+            libc::AF_INET6 => {
+                // SAFETY: the address family is checked
+                let addr = unsafe { &*ptr::from_ref(addr).cast::<libc::sockaddr_in6>() };
+
+                Some(SocketAddr::V6(SocketAddrV6::new(
+                    Ipv6Addr::from(addr.sin6_addr.s6_addr),
+                    u16::from_be(addr.sin6_port),
+                    addr.sin6_flowinfo,
+                    addr.sin6_scope_id,
+                )))
+            }
+            _ => None,
+        }
+    }
+
+    fn to_sockaddr(addr: SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: all-zero is a valid `sockaddr_storage`
+        let mut storage = unsafe { mem::zeroed::<libc::sockaddr_storage>() };
+
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: `sockaddr_storage` is large enough and suitably aligned for `sockaddr_in`
+                let sin = unsafe { &mut *ptr::from_mut(&mut storage).cast::<libc::sockaddr_in>() };
+                sin.sin_family = libc::AF_INET as _;
+                sin.sin_port = addr.port().to_be();
+                sin.sin_addr.s_addr = u32::from(*addr.ip()).to_be();
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: `sockaddr_storage` is large enough and suitably aligned for `sockaddr_in6`
+                let sin6 =
+                    unsafe { &mut *ptr::from_mut(&mut storage).cast::<libc::sockaddr_in6>() };
+                sin6.sin6_family = libc::AF_INET6 as _;
+                sin6.sin6_port = addr.port().to_be();
+                sin6.sin6_addr.s6_addr = addr.ip().octets();
+                sin6.sin6_flowinfo = addr.flowinfo();
+                sin6.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, len as _)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use super::{Datagram, Received};
+    use bytes::BytesMut;
+    use std::{
+        borrow::BorrowMut,
+        io::Error,
+        net::SocketAddr,
+        task::{ready, Context, Poll},
+    };
+    use tokio::{io::ReadBuf, net::UdpSocket};
+
+    pub(super) async fn recv_batch<B: BorrowMut<BytesMut>>(
+        socket: &UdpSocket,
+        bufs: &mut [B],
+    ) -> Result<Vec<Received>, Error> {
+        let buf = bufs[0].borrow_mut();
+        let start = buf.len();
+        let cap = buf.capacity() - start;
+        let (len, addr) = socket.recv_buf_from(buf).await?;
+
+        Ok(vec![received(start, cap, len, addr)])
+    }
+
+    pub(super) fn try_recv_batch<B: BorrowMut<BytesMut>>(
+        socket: &UdpSocket,
+        bufs: &mut [B],
+    ) -> Result<Vec<Received>, Error> {
+        let buf = bufs[0].borrow_mut();
+        let start = buf.len();
+        let cap = buf.capacity() - start;
+        let (len, addr) = socket.try_recv_buf_from(buf)?;
+
+        Ok(vec![received(start, cap, len, addr)])
+    }
+
+    pub(super) fn poll_recv_from(
+        socket: &UdpSocket,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, Option<usize>), Error>> {
+        let start = buf.filled().len();
+        let cap = buf.remaining();
+        let addr = ready!(socket.poll_recv_from(cx, buf))?;
+        let len = buf.filled().len() - start;
+
+        Poll::Ready(Ok((addr, (len < cap).then_some(len))))
+    }
+
+    /// The datagram may have been truncated if it filled the whole buffer.
+    fn received(start: usize, cap: usize, len: usize, addr: SocketAddr) -> Received {
+        Received {
+            idx: 0,
+            range: start..start + len,
+            addr,
+            len: (len < cap).then_some(len),
+        }
+    }
+
+    pub(super) async fn send_batch(
+        socket: &UdpSocket,
+        dgrams: &[Datagram<'_>],
+    ) -> Result<usize, Error> {
+        let dgram = &dgrams[0];
+
+        let mut buf = BytesMut::with_capacity(dgram.header.len() + dgram.payload.len());
+        buf.extend_from_slice(dgram.header);
+        buf.extend_from_slice(dgram.payload);
+
+        socket.send_to(&buf, dgram.addr).await?;
+
+        Ok(1)
+    }
+}
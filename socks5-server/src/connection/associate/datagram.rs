@@ -0,0 +1,178 @@
+use super::batch;
+use std::{
+    io::{Error, ErrorKind, IoSlice},
+    net::SocketAddr,
+    task::{ready, Context, Poll},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+#[cfg(not(any(target_os = "redox", target_os = "wasi", target_os = "horizon")))]
+use {socket2::SockRef, tokio::io::Interest};
+
+/// A datagram transport that an [`AssociatedUdpSocket`](super::AssociatedUdpSocket) sends and receives SOCKS5 UDP packets over.
+///
+/// It is implemented for tokio's [`UdpSocket`], the default transport. Implement it to carry the SOCKS5 UDP flow over something other than a kernel UDP socket, e.g. a QUIC datagram channel, or an in-memory channel in tests. Encoding and decoding SOCKS5 UDP headers, client filtering and size checks are all done by [`AssociatedUdpSocket`](super::AssociatedUdpSocket) on top of these methods.
+///
+/// Batched IO and [`run_relay()`](super::run_relay) are only available over [`UdpSocket`], and socket options over transports backed by one, see [`DatagramSocket::as_udp_socket()`].
+pub trait DatagramSocket {
+    /// Attempts to receive a datagram into the unfilled portion of `buf`, returning the source address.
+    ///
+    /// A datagram larger than the unfilled portion of `buf` is cut to fit, like with a UDP socket. If no datagram is available, `Poll::Pending` is returned and the current task will be notified by a waker.
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>>;
+
+    /// Attempts to send a datagram to `target`, returning the number of bytes sent.
+    ///
+    /// If the transport is not ready for sending, `Poll::Pending` is returned and the current task will be notified by a waker.
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>>;
+
+    /// Returns the local address of the transport.
+    fn local_addr(&self) -> Result<SocketAddr, Error>;
+
+    /// Attempts to send one datagram made of `bufs` back to back to `target`, returning the number of bytes sent.
+    ///
+    /// [`AssociatedUdpSocket`](super::AssociatedUdpSocket) only uses this if [`DatagramSocket::is_write_vectored()`] returns `true`, sending the SOCKS5 UDP header and the payload as two buffers so the payload is not copied. The default implementation concatenates `bufs` and calls [`DatagramSocket::poll_send_to()`].
+    fn poll_send_to_vectored(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        let buf = bufs
+            .iter()
+            .flat_map(|buf| buf.iter().copied())
+            .collect::<Vec<_>>();
+        self.poll_send_to(cx, &buf, target)
+    }
+
+    /// Returns whether the transport has an efficient [`DatagramSocket::poll_send_to_vectored()`] implementation.
+    ///
+    /// The default implementation returns `false`.
+    fn is_write_vectored(&self) -> bool {
+        false
+    }
+
+    /// Attempts to send a datagram to the remote address which the transport is connected.
+    ///
+    /// The default implementation returns an error of kind [`ErrorKind::NotConnected`].
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        let _ = (cx, buf);
+        Poll::Ready(Err(Error::from(ErrorKind::NotConnected)))
+    }
+
+    /// Returns the kernel UDP socket behind the transport, if any.
+    ///
+    /// Socket options of [`AssociatedUdpSocket`](super::AssociatedUdpSocket), e.g. [`AssociatedUdpSocket::set_recv_buffer_size()`](super::AssociatedUdpSocket::set_recv_buffer_size), are set on it, and fail with an error of kind [`ErrorKind::Unsupported`] if there is none. The default implementation returns `None`.
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        None
+    }
+
+    /// Attempts to receive a datagram like [`DatagramSocket::poll_recv_from()`], also returning the original length of the datagram, or `None` if it was cut and its original length is unknown.
+    ///
+    /// This is how datagrams larger than `buf` are told apart. The default implementation assumes that a datagram filling the whole unfilled portion of `buf` was cut. Over [`UdpSocket`] on Linux, the original length is reported by the kernel.
+    fn poll_recv_from_len(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, Option<usize>), Error>> {
+        let start = buf.filled().len();
+        let cap = buf.remaining();
+        let addr = ready!(self.poll_recv_from(cx, buf))?;
+        let len = buf.filled().len() - start;
+
+        Poll::Ready(Ok((addr, (len < cap).then_some(len))))
+    }
+}
+
+impl DatagramSocket for UdpSocket {
+    #[inline]
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>> {
+        UdpSocket::poll_recv_from(self, cx, buf)
+    }
+
+    #[inline]
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        UdpSocket::poll_send_to(self, cx, buf, target)
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        UdpSocket::local_addr(self)
+    }
+
+    /// Sends with `sendmsg` (`WSASendTo` on Windows) and one iovec per buffer.
+    #[cfg(not(any(target_os = "redox", target_os = "wasi", target_os = "horizon")))]
+    fn poll_send_to_vectored(
+        &self,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        loop {
+            ready!(self.poll_send_ready(cx))?;
+
+            match try_send_to_vectored(self, bufs, target) {
+                Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                res => return Poll::Ready(res),
+            }
+        }
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        cfg!(not(any(
+            target_os = "redox",
+            target_os = "wasi",
+            target_os = "horizon"
+        )))
+    }
+
+    #[inline]
+    fn poll_send(&self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, Error>> {
+        UdpSocket::poll_send(self, cx, buf)
+    }
+
+    #[inline]
+    fn as_udp_socket(&self) -> Option<&UdpSocket> {
+        Some(self)
+    }
+
+    #[inline]
+    fn poll_recv_from_len(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(SocketAddr, Option<usize>), Error>> {
+        batch::poll_recv_from(self, cx, buf)
+    }
+}
+
+/// Tries to send `bufs` as one datagram without waiting, with `sendmsg` (`WSASendTo` on Windows) and one iovec per buffer. Returns [`ErrorKind::WouldBlock`] if the socket is not ready.
+#[cfg(not(any(target_os = "redox", target_os = "wasi", target_os = "horizon")))]
+pub(super) fn try_send_to_vectored(
+    socket: &UdpSocket,
+    bufs: &[IoSlice<'_>],
+    target: SocketAddr,
+) -> Result<usize, Error> {
+    let target = target.into();
+    socket.try_io(Interest::WRITABLE, || {
+        SockRef::from(socket).send_to_vectored(bufs, &target)
+    })
+}
@@ -0,0 +1,88 @@
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind},
+};
+
+/// Error of sending a UDP packet larger than the maximum sending UDP packet size, with SOCKS5 UDP header included.
+///
+/// It is returned wrapped in an [`std::io::Error`] of kind [`ErrorKind::InvalidInput`], and can be recovered with [`PacketTooLarge::from_io_error()`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct PacketTooLarge {
+    /// The length of the packet, with SOCKS5 UDP header included
+    pub len: usize,
+
+    /// The maximum sending UDP packet size
+    pub max: usize,
+}
+
+impl PacketTooLarge {
+    /// Returns the [`PacketTooLarge`] wrapped in `err`, if any.
+    #[inline]
+    pub fn from_io_error(err: &Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for PacketTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(
+            f,
+            "UDP packet of {} bytes exceeds the maximum of {} bytes",
+            self.len, self.max
+        )
+    }
+}
+
+impl StdError for PacketTooLarge {}
+
+impl From<PacketTooLarge> for Error {
+    fn from(err: PacketTooLarge) -> Self {
+        Error::new(ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Error of receiving a UDP packet larger than the maximum receiving UDP packet size, with SOCKS5 UDP header included.
+///
+/// The part of the packet fitting in the buffer may still be received, but it is not a complete packet. It is returned wrapped in an [`std::io::Error`] of kind [`ErrorKind::InvalidData`], and can be recovered with [`Truncated::from_io_error()`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Truncated {
+    /// The original length of the packet. This is always known on Linux, where it is reported with `MSG_TRUNC`. On other platforms, it is only known if the packet fit in the receiving buffer.
+    pub len: Option<usize>,
+
+    /// The maximum receiving UDP packet size
+    pub max: usize,
+}
+
+impl Truncated {
+    /// Returns the [`Truncated`] wrapped in `err`, if any.
+    #[inline]
+    pub fn from_io_error(err: &Error) -> Option<&Self> {
+        err.get_ref()?.downcast_ref()
+    }
+}
+
+impl Display for Truncated {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self.len {
+            Some(len) => write!(
+                f,
+                "received UDP packet of {len} bytes exceeds the maximum of {} bytes",
+                self.max
+            ),
+            None => write!(
+                f,
+                "received UDP packet exceeds the maximum of {} bytes",
+                self.max
+            ),
+        }
+    }
+}
+
+impl StdError for Truncated {}
+
+impl From<Truncated> for Error {
+    fn from(err: Truncated) -> Self {
+        Error::new(ErrorKind::InvalidData, err)
+    }
+}
@@ -0,0 +1,84 @@
+use super::{DropReason, RelayClose};
+use socks5_proto::Address;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Error,
+    net::SocketAddr,
+};
+
+/// An event in the lifetime of a UDP association relayed by [`run_relay()`](super::run_relay), reported to the [`RelayObserver`] of [`UdpRelayConfig::observer`](super::UdpRelayConfig::observer).
+///
+/// Events of one association are reported in order from the task running its relay. They are meant to answer why the UDP traffic of a given client does not go through: whether the relay started, whether a datagram from the client ever arrived, which destinations it reached, what was dropped and how the association ended.
+#[derive(Debug)]
+pub enum RelayEvent<'a> {
+    /// The client-facing socket was bound and its address replied to the client.
+    Started {
+        /// The address of the TCP connection carrying the associate command
+        peer: SocketAddr,
+
+        /// The UDP endpoint the client declared in the associate command
+        declared: &'a Address,
+
+        /// The address of the client-facing UDP socket
+        relay: SocketAddr,
+    },
+
+    /// A datagram from the client was accepted for the first time, or from a new endpoint after NAT rebinding, telling the endpoint of the client.
+    ClientLearned(SocketAddr),
+
+    /// The client sent to a destination that was not tracked yet, with the resolved address.
+    NewDestination(SocketAddr),
+
+    /// A datagram was dropped.
+    Dropped(DropReason),
+
+    /// A failing outbound socket was replaced with a freshly bound one, with the local address of the new socket. See [`RebindPolicy`](super::RebindPolicy).
+    Rebound(SocketAddr),
+
+    /// The relay finished.
+    Closed(RelayClose),
+
+    /// The relay failed with an I/O error, or the client-facing socket could not be bound.
+    Failed(&'a Error),
+}
+
+impl RelayEvent<'_> {
+    /// Returns a stable name of the event, e.g. to use as the name of a log event or a metric.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Started { .. } => "udp_association_started",
+            Self::ClientLearned(_) => "udp_client_learned",
+            Self::NewDestination(_) => "udp_destination_added",
+            Self::Dropped(_) => "udp_datagram_dropped",
+            Self::Rebound(_) => "udp_socket_rebound",
+            Self::Closed(_) => "udp_association_closed",
+            Self::Failed(_) => "udp_association_failed",
+        }
+    }
+}
+
+/// An observer of the [`RelayEvent`]s of UDP associations, e.g. to log them, or to feed them into a metrics system.
+///
+/// The observer is called synchronously from the relay task, so it should not block. It is implemented for closures taking a `&RelayEvent`.
+///
+/// Counters of relayed and dropped datagrams are also kept in [`UdpRelayStats`](super::UdpRelayStats) whether an observer is set or not. An observer is about telling what happened to a given association.
+pub trait RelayObserver {
+    /// Called on every event of an association.
+    fn on_event(&self, event: &RelayEvent<'_>);
+}
+
+impl<F> RelayObserver for F
+where
+    F: Fn(&RelayEvent<'_>),
+{
+    #[inline]
+    fn on_event(&self, event: &RelayEvent<'_>) {
+        self(event)
+    }
+}
+
+impl Debug for dyn RelayObserver + Send + Sync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("RelayObserver")
+    }
+}
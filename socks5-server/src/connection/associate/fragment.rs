@@ -0,0 +1,215 @@
+use super::addr;
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Address, UdpHeader};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+use tokio::time::Instant;
+
+/// How the relay handles fragmented datagrams, i.e. datagrams with a `FRAG` field other than `0`.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum FragmentPolicy {
+    /// Drop fragmented datagrams. This is what RFC 1928 recommends for implementations that do not support fragmentation.
+    #[default]
+    Drop,
+
+    /// Reassemble fragmented datagrams with a [`FragmentReassembler`] created from the given limits.
+    Reassemble {
+        /// The reassembly timeout of a fragment sequence. RFC 1928 requires this to be no less than 5 seconds.
+        timeout: Duration,
+
+        /// The maximum length of a reassembled payload.
+        max_len: usize,
+
+        /// The maximum number of fragment sequences being reassembled at the same time.
+        max_sequences: usize,
+    },
+}
+
+impl FragmentPolicy {
+    /// Creates the reassembler described by this policy, if any.
+    pub fn reassembler(&self) -> Option<FragmentReassembler> {
+        match *self {
+            Self::Drop => None,
+            Self::Reassemble {
+                timeout,
+                max_len,
+                max_sequences,
+            } => Some(FragmentReassembler::new(timeout, max_len, max_sequences)),
+        }
+    }
+}
+
+/// Reassembler of fragmented SOCKS5 UDP datagrams.
+///
+/// RFC 1928 defines the `FRAG` field as the position of a fragment in a sequence (`1` to `127`), with the high-order bit set on the last fragment. A `FRAG` of `0` marks a standalone datagram.
+///
+/// Sequences are kept per (client address, destination address) pair. Since fragments can come from anyone who reaches the relay socket, the reassembler is strictly bounded:
+///
+/// - a sequence is discarded if it is not completed within the reassembly timeout
+/// - a sequence is discarded if its reassembled payload grows larger than `max_len`
+/// - at most `max_sequences` sequences are kept at the same time. When a new sequence would exceed this, expired sequences are evicted first, then the oldest one
+/// - fragments must arrive in order. A fragment that does not directly follow the previous one resets its sequence, as does a standalone datagram
+///
+/// Memory used for buffering is thus bounded by `max_len * max_sequences`.
+#[derive(Debug)]
+pub struct FragmentReassembler {
+    sequences: HashMap<(SocketAddr, Address), Sequence>,
+    timeout: Duration,
+    max_len: usize,
+    max_sequences: usize,
+    dropped: u64,
+}
+
+#[derive(Debug)]
+struct Sequence {
+    position: u8,
+    fragments: Vec<Bytes>,
+    len: usize,
+    started: Instant,
+}
+
+impl FragmentReassembler {
+    const END_OF_SEQUENCE: u8 = 0x80;
+
+    /// Creates a new [`FragmentReassembler`] with the reassembly timeout, the maximum length of a reassembled payload and the maximum number of sequences being reassembled at the same time.
+    pub fn new(timeout: Duration, max_len: usize, max_sequences: usize) -> Self {
+        Self {
+            sequences: HashMap::new(),
+            timeout,
+            max_len,
+            max_sequences,
+            dropped: 0,
+        }
+    }
+
+    /// Pushes a datagram received from `src` into the reassembler.
+    ///
+    /// Returns the complete payload if the datagram is standalone or completes a sequence. Otherwise the fragment is buffered (or discarded if it violates the limits) and `None` is returned.
+    pub fn push(&mut self, src: SocketAddr, header: &UdpHeader, pkt: Bytes) -> Option<Bytes> {
+        let key = (addr::canonical(src), header.address.clone());
+
+        if header.frag == 0 {
+            self.discard(&key);
+            return Some(pkt);
+        }
+
+        let now = Instant::now();
+        let position = header.frag & !Self::END_OF_SEQUENCE;
+        let is_end = header.frag & Self::END_OF_SEQUENCE != 0;
+
+        if let Some(seq) = self.sequences.get(&key) {
+            if seq.position + 1 != position || now.duration_since(seq.started) > self.timeout {
+                self.discard(&key);
+            }
+        }
+
+        if !self.sequences.contains_key(&key) {
+            if position != 1 {
+                self.dropped += 1;
+                return None;
+            }
+
+            self.make_room(now);
+
+            self.sequences.insert(
+                key.clone(),
+                Sequence {
+                    position: 0,
+                    fragments: Vec::new(),
+                    len: 0,
+                    started: now,
+                },
+            );
+        }
+
+        let seq = self.sequences.get_mut(&key).unwrap();
+
+        if seq.len + pkt.len() > self.max_len {
+            self.discard(&key);
+            self.dropped += 1;
+            return None;
+        }
+
+        seq.position = position;
+        seq.len += pkt.len();
+        seq.fragments.push(pkt);
+
+        if !is_end {
+            return None;
+        }
+
+        let seq = self.sequences.remove(&key).unwrap();
+        let mut buf = BytesMut::with_capacity(seq.len);
+
+        for frag in seq.fragments {
+            buf.extend_from_slice(&frag);
+        }
+
+        Some(buf.freeze())
+    }
+
+    /// Discards all sequences that are not completed within the reassembly timeout.
+    pub fn evict_expired(&mut self) {
+        let now = Instant::now();
+        self.evict_expired_at(now);
+    }
+
+    /// Returns the number of sequences being reassembled.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    /// Returns whether no sequence is being reassembled.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// Returns the number of fragments discarded so far, either dropped directly or as part of a discarded sequence.
+    #[inline]
+    pub fn dropped_count(&self) -> u64 {
+        self.dropped
+    }
+
+    fn discard(&mut self, key: &(SocketAddr, Address)) {
+        if let Some(seq) = self.sequences.remove(key) {
+            self.dropped += seq.fragments.len() as u64;
+        }
+    }
+
+    fn evict_expired_at(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let mut dropped = 0;
+
+        self.sequences.retain(|_, seq| {
+            let alive = now.duration_since(seq.started) <= timeout;
+            if !alive {
+                dropped += seq.fragments.len() as u64;
+            }
+            alive
+        });
+
+        self.dropped += dropped;
+    }
+
+    fn make_room(&mut self, now: Instant) {
+        if self.sequences.len() < self.max_sequences {
+            return;
+        }
+
+        self.evict_expired_at(now);
+
+        while self.sequences.len() >= self.max_sequences.max(1) {
+            let oldest = self
+                .sequences
+                .iter()
+                .min_by_key(|(_, seq)| seq.started)
+                .map(|(key, _)| key.clone());
+
+            match oldest {
+                Some(key) => self.discard(&key),
+                None => break,
+            }
+        }
+    }
+}
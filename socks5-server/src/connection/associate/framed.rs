@@ -0,0 +1,142 @@
+use super::{AssociatedUdpSocket, DatagramSocket, Truncated};
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use futures_sink::Sink;
+use socks5_proto::{Error as Socks5Error, UdpHeader};
+use std::{
+    io::Error,
+    net::SocketAddr,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+impl<T: DatagramSocket> AssociatedUdpSocket<T> {
+    /// Wraps the socket into a [`UdpFramed`], a [`Stream`] of received packets and a [`Sink`] of packets to send.
+    ///
+    /// This method requires the `framed` feature.
+    #[inline]
+    pub fn framed(self) -> UdpFramed<T> {
+        UdpFramed::new(self)
+    }
+}
+
+/// A [`Stream`] and [`Sink`] adapter over an [`AssociatedUdpSocket`], created by [`AssociatedUdpSocket::framed()`].
+///
+/// The stream yields the SOCKS5 UDP header, the payload and the source address of each received packet. Datagrams that would be dropped by [`AssociatedUdpSocket::recv_batch()`], i.e. malformed, truncated or from a source other than the expected client, are skipped and recorded in the statistics of the socket. Only IO errors of the socket are yielded, and the stream never ends.
+///
+/// The sink sends each packet to its address, adding the SOCKS5 UDP header. It buffers at most one packet: [`Sink::poll_ready()`] waits until the previous packet is sent, so a slow socket pushes back on the producer. No task is spawned.
+///
+/// Like any UDP socket, packets may still be dropped by the OS or the network without notice, on both directions. Packets larger than the maximum sending UDP packet size are rejected with a [`PacketTooLarge`](super::PacketTooLarge) error.
+///
+/// This type requires the `framed` feature.
+#[derive(Debug)]
+pub struct UdpFramed<T = UdpSocket> {
+    socket: AssociatedUdpSocket<T>,
+    rd: BytesMut,
+    wr: Option<(UdpHeader, Bytes, SocketAddr)>,
+}
+
+impl<T: DatagramSocket> UdpFramed<T> {
+    /// Creates a new [`UdpFramed`] over an [`AssociatedUdpSocket`].
+    pub fn new(socket: AssociatedUdpSocket<T>) -> Self {
+        Self {
+            socket,
+            rd: BytesMut::new(),
+            wr: None,
+        }
+    }
+
+    /// Returns a shared reference to the underlying [`AssociatedUdpSocket`].
+    #[inline]
+    pub fn get_ref(&self) -> &AssociatedUdpSocket<T> {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the underlying [`AssociatedUdpSocket`].
+    ///
+    /// Note that this may break the encapsulation of the framed adapter and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut AssociatedUdpSocket<T> {
+        &mut self.socket
+    }
+
+    /// Consumes the [`UdpFramed`] and returns the underlying [`AssociatedUdpSocket`]. A packet buffered in the sink and not yet flushed is dropped.
+    #[inline]
+    pub fn into_inner(self) -> AssociatedUdpSocket<T> {
+        self.socket
+    }
+}
+
+impl<T: DatagramSocket + Unpin> Stream for UdpFramed<T> {
+    type Item = Result<(UdpHeader, Bytes, SocketAddr), Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        loop {
+            this.rd.clear();
+            this.rd.reserve(this.socket.get_max_pkt_size() + 1);
+
+            let mut buf = ReadBuf::uninit(this.rd.spare_capacity_mut());
+            let res = this.socket.poll_recv_from(cx, &mut buf);
+            let len = buf.filled().len();
+
+            // SAFETY: `len` bytes of the spare capacity were initialized by the socket
+            unsafe { this.rd.set_len(len) };
+
+            match ready!(res) {
+                Ok((header, offset, addr)) => {
+                    let pkt = this.rd.split().freeze().slice(offset..);
+                    return Poll::Ready(Some(Ok((header, pkt, addr))));
+                }
+                Err(Socks5Error::Io(err)) if Truncated::from_io_error(&err).is_none() => {
+                    return Poll::Ready(Some(Err(err)))
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+}
+
+impl<T: DatagramSocket> UdpFramed<T> {
+    fn poll_flush_pending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        let Some((header, pkt, addr)) = &self.wr else {
+            return Poll::Ready(Ok(()));
+        };
+
+        let res = ready!(self.socket.poll_send_to(cx, pkt, header, *addr));
+        self.wr = None;
+
+        Poll::Ready(res.map(|_| ()))
+    }
+}
+
+impl<T: DatagramSocket + Unpin> Sink<(UdpHeader, Bytes, SocketAddr)> for UdpFramed<T> {
+    type Error = Error;
+
+    #[inline]
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush_pending(cx)
+    }
+
+    #[inline]
+    fn start_send(
+        mut self: Pin<&mut Self>,
+        item: (UdpHeader, Bytes, SocketAddr),
+    ) -> Result<(), Error> {
+        debug_assert!(self.wr.is_none(), "`poll_ready()` must be called first");
+        self.wr = Some(item);
+        Ok(())
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush_pending(cx)
+    }
+
+    #[inline]
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.poll_flush_pending(cx)
+    }
+}
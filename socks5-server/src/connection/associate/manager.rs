@@ -0,0 +1,709 @@
+//! A relay multiplexing many UDP associations over a shared pool of outbound sockets
+
+use super::{
+    addr::{canonical, CanonicalAddr},
+    relay::{notify, record_drop, record_drops},
+    sockopt, state, Associate, AssociatedUdpSocket, DatagramSocket, DropReason, RateLimiter,
+    RelayClose, RelayEvent, RelayResult, RelaySession, ReturnAddress, UdpAssociationTable,
+    UdpRelayConfig, UdpRelayStats,
+};
+use crate::dns;
+use socks5_proto::{Address, UdpHeader};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::{poll_fn, Future},
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::ReadBuf,
+    net::UdpSocket,
+    time::{self, Instant},
+};
+
+/// How a [`UdpRelayManager`] maps the destinations of its associations to the sockets of its pool, i.e. the NAT mapping behavior of the relay in terms of RFC 4787.
+///
+/// Return traffic arriving on a shared socket is told apart by its source alone, so a destination can only be talked to by one association per socket. The association which claimed a destination on a socket first keeps it until no datagram is relayed to or from it for [`UdpRelayConfig::destination_timeout`], or its relay finishes. What happens when another association sends to the same destination depends on the mapping.
+///
+/// There is no default, as neither behavior is what a relay with a socket per association does: with [`run_relay()`](super::run_relay), any number of clients can talk to the same destination, each from its own port.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SharedMapping {
+    /// Each association is pinned to one socket of the pool, picked round-robin in the order the associations are started, and sends to all its destinations from it, so destinations see the same endpoint of the association (endpoint-independent mapping), which peer-to-peer applications rely on.
+    ///
+    /// A datagram to a destination claimed on the socket by another association is dropped as [`DropReason::Denied`].
+    Pinned,
+
+    /// Each destination of an association is sent to from the first socket of the pool, in pool order, on which the destination is not claimed by another association, so up to as many associations as there are sockets can talk to the same destination. Destinations see endpoints of the association depending on who they are (address-dependent mapping).
+    ///
+    /// An association keeps the socket of a destination as long as its claim lives. A datagram to a destination claimed on all sockets by other associations is dropped as [`DropReason::Denied`].
+    PerDestination,
+}
+
+/// A UDP relay multiplexing many associations over a shared pool of outbound sockets.
+///
+/// [`run_relay()`](super::run_relay) binds two outbound sockets per association, which does not scale to tens of thousands of clients: every association takes an ephemeral port and file descriptors for its whole lifetime. A manager sends the traffic of all its associations through a fixed pool of sockets instead, and demultiplexes return traffic by the (socket, source) pair, claimed by the association which sent to it. See [`SharedMapping`] for how destinations are mapped to sockets and how two associations talking to the same destination are handled.
+///
+/// Each association still has its own client-facing socket, filtering its client the same way as with [`run_relay()`](super::run_relay). Return traffic is only relayed from destinations the association sent to (address and port dependent filtering), as a datagram from elsewhere can not be told apart, so [`UdpRelayConfig::restrict_return`] is implied. Return traffic matching no live claim is dropped and counted in [`UdpRelayManagerStats::unmatched`].
+///
+/// The return traffic of all associations is received by [`UdpRelayManager::run()`], which must be running, e.g. in a task of its own, for associations to get any reply. Associations are relayed with [`UdpRelayManager::run_relay()`], or [`UdpRelayManager::relay()`] over a client-facing socket of any transport.
+///
+/// The fields [`UdpRelayConfig::outbound_options`], [`UdpRelayConfig::rebind`], [`UdpRelayConfig::buffer_pool`] and [`UdpRelayConfig::restrict_return`] are not used, as the pool is shared. Neither is [`UdpRelayConfig::quota`], as return traffic is dispatched to the associations by the shared sockets. Multicast destinations are always dropped as [`DropReason::Denied`], as replies of group members could not be matched to a claim.
+///
+/// Cloning a [`UdpRelayManager`] gives another handle to the same manager.
+pub struct UdpRelayManager<T = UdpSocket> {
+    inner: Arc<ManagerInner<T>>,
+}
+
+struct ManagerInner<T> {
+    sockets: Box<[T]>,
+    mapping: SharedMapping,
+
+    /// The indices of the sockets destinations of each address family are sent to from, with whether v4-mapped addresses are used
+    routes: [(Vec<usize>, bool); 2],
+
+    claims: Mutex<HashMap<(usize, CanonicalAddr), Claim>>,
+    associations: Mutex<HashMap<u64, Arc<Association<T>>>>,
+    next_token: AtomicU64,
+    unmatched: AtomicU64,
+    conflicts: AtomicU64,
+}
+
+/// The claim of an association on a destination on one socket of the pool
+struct Claim {
+    token: u64,
+
+    /// The address the client requested the destination with, if [`ReturnAddress::Requested`] is used
+    requested: Option<Address>,
+    ttl: Duration,
+    last_used: Instant,
+}
+
+impl Claim {
+    #[inline]
+    fn is_live(&self, now: Instant) -> bool {
+        self.last_used + self.ttl > now
+    }
+}
+
+/// An association registered in a [`UdpRelayManager`], shared between its relay and [`UdpRelayManager::run()`]
+struct Association<T> {
+    client: AssociatedUdpSocket<T>,
+    client_addr: Mutex<Option<SocketAddr>>,
+    config: UdpRelayConfig,
+    stats: UdpRelayStats,
+    downlink_limiter: Mutex<RateLimiter>,
+    last_active: Mutex<Instant>,
+}
+
+impl<T: DatagramSocket> Association<T> {
+    fn touch(&self) {
+        *self
+            .last_active
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Instant::now();
+    }
+
+    /// Sends a datagram received from `src` to the client, dropping it if the socket of the client is not ready to send.
+    fn forward(
+        &self,
+        cx: &mut Context<'_>,
+        src: SocketAddr,
+        requested: Option<Address>,
+        pkt: &[u8],
+    ) {
+        let Some(client_addr) = *self
+            .client_addr
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+        else {
+            return;
+        };
+
+        if pkt.len() > self.config.max_pkt_size {
+            record_drop(&self.config, &self.stats, DropReason::Oversize);
+            return;
+        }
+
+        if !self
+            .downlink_limiter
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .try_acquire(pkt.len(), Instant::now())
+        {
+            record_drop(&self.config, &self.stats, DropReason::RateLimited);
+            return;
+        }
+
+        let header = UdpHeader::new(0, requested.unwrap_or(Address::SocketAddress(src)));
+
+        if let Poll::Ready(Ok(_)) = self.client.poll_send_to(cx, pkt, &header, client_addr) {
+            self.touch();
+        }
+    }
+}
+
+/// Unregisters an association from its manager when its relay finishes or is dropped, releasing its claims.
+struct Registration<'a, T> {
+    inner: &'a ManagerInner<T>,
+    token: u64,
+}
+
+impl<T> Drop for Registration<'_, T> {
+    fn drop(&mut self) {
+        self.inner
+            .associations
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&self.token);
+
+        let now = Instant::now();
+        self.inner
+            .claims
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|_, claim| claim.token != self.token && claim.is_live(now));
+    }
+}
+
+/// A point-in-time copy of the counters of a [`UdpRelayManager`]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UdpRelayManagerStats {
+    /// Associations being relayed
+    pub associations: usize,
+
+    /// Claims of associations on destinations, including expired ones which have not been removed yet
+    pub claims: usize,
+
+    /// Datagrams received on the pool matching no live claim
+    pub unmatched: u64,
+
+    /// Datagrams from clients dropped because their destination was claimed by other associations
+    pub conflicts: u64,
+}
+
+/// The size of the buffer datagrams are received from the pool into, the largest UDP payload
+const RECV_BUF_SIZE: usize = 65535;
+
+impl UdpRelayManager {
+    /// Binds a pool of `size` outbound sockets for each of IPv4 and IPv6, on ephemeral ports of the unspecified addresses. Only one of the address families is required to be bound, e.g. on hosts without IPv6.
+    pub async fn bind(size: usize, mapping: SharedMapping) -> Result<Self, Error> {
+        let mut sockets = Vec::with_capacity(size * 2);
+        let mut last_err = None;
+
+        for ip in [
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        ] {
+            for _ in 0..size {
+                match UdpSocket::bind(SocketAddr::new(ip, 0)).await {
+                    Ok(socket) => sockets.push(socket),
+                    Err(err) => {
+                        last_err = Some(err);
+                        break;
+                    }
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if sockets.is_empty() => Err(err),
+            _ => Self::new(sockets, mapping),
+        }
+    }
+
+    /// Runs a UDP relay for an `Associate` command through the pool, like [`run_relay()`](super::run_relay).
+    ///
+    /// It binds a client-facing UDP socket and replies with its address using [`Associate::reply_with_socket()`], enforces the declared client endpoint according to [`UdpRelayConfig::client_match`], enables [`UdpRelayConfig::control_keepalive`], and relays until the client closes the TCP connection, which is shut down before returning. See [`UdpRelayManager::relay()`].
+    pub async fn run_relay(
+        &self,
+        associate: Associate<state::NeedReply>,
+        config: UdpRelayConfig,
+    ) -> RelayResult {
+        let (mut associate, client) = match associate
+            .reply_with_socket(config.bind_ip, config.max_pkt_size)
+            .await
+        {
+            Ok(res) => res,
+            Err(failed) => {
+                let err = failed.into();
+                notify(&config, RelayEvent::Failed(&err));
+                return Err(err);
+            }
+        };
+
+        let res = associate
+            .expect_declared_client(&client, config.client_match)
+            .and_then(|()| match &config.control_keepalive {
+                Some(keepalive) => associate.set_keepalive(keepalive),
+                None => Ok(()),
+            });
+
+        if let Err(err) = res {
+            notify(&config, RelayEvent::Failed(&err));
+            let _ = associate.close().await;
+            return Err(err);
+        }
+
+        if let (Ok(peer), Ok(relay)) = (associate.peer_addr(), client.local_addr()) {
+            notify(
+                &config,
+                RelayEvent::Started {
+                    peer,
+                    declared: associate.client_declared_addr(),
+                    relay,
+                },
+            );
+        }
+
+        let res = self.relay(client, config, associate.wait_close()).await;
+        let _ = associate.close().await;
+        res
+    }
+}
+
+impl<T: DatagramSocket> UdpRelayManager<T> {
+    /// Creates a manager over a pool of outbound sockets, mapping destinations to them according to `mapping`.
+    ///
+    /// Destinations are sent to from the sockets of their address family. If there is no IPv4 socket, IPv4 destinations are sent to from the IPv6 sockets as v4-mapped addresses, which works if they are dual-stack (the default on Linux).
+    ///
+    /// An error of kind [`ErrorKind::InvalidInput`] is returned if `sockets` is empty.
+    pub fn new(sockets: Vec<T>, mapping: SharedMapping) -> Result<Self, Error> {
+        if sockets.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "the socket pool of a UDP relay manager can not be empty",
+            ));
+        }
+
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+
+        for (idx, socket) in sockets.iter().enumerate() {
+            if let Some(socket) = socket.as_udp_socket() {
+                let _ = sockopt::suppress_connection_reset(socket);
+            }
+
+            if socket.local_addr()?.is_ipv4() {
+                v4.push(idx);
+            } else {
+                v6.push(idx);
+            }
+        }
+
+        let routes = if v4.is_empty() {
+            [(v6.clone(), true), (v6, false)]
+        } else {
+            [(v4, false), (v6, false)]
+        };
+
+        Ok(Self {
+            inner: Arc::new(ManagerInner {
+                sockets: sockets.into_boxed_slice(),
+                mapping,
+                routes,
+                claims: Mutex::new(HashMap::new()),
+                associations: Mutex::new(HashMap::new()),
+                next_token: AtomicU64::new(0),
+                unmatched: AtomicU64::new(0),
+                conflicts: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Receives the return traffic of all associations on the pool and sends it to their clients. It only returns on an error of a socket of the pool.
+    ///
+    /// Datagrams matching no live claim of a running association are dropped and counted in [`UdpRelayManagerStats::unmatched`]. Datagrams to a client whose socket is not ready to send are dropped too rather than waited for, so a slow client does not hold up the return traffic of the other associations.
+    pub async fn run(&self) -> Result<(), Error> {
+        let mut buf = vec![0; RECV_BUF_SIZE];
+        let mut next = 0;
+
+        loop {
+            let (idx, src, len) =
+                match poll_fn(|cx| self.inner.poll_recv(cx, &mut buf, &mut next)).await {
+                    Ok(res) => res,
+                    // an earlier datagram hit a closed port, which does not concern the socket
+                    Err(err) if sockopt::is_connection_reset(&err) => continue,
+                    Err(err) => return Err(err),
+                };
+
+            poll_fn(|cx| {
+                self.inner.dispatch(cx, idx, canonical(src), &buf[..len]);
+                Poll::Ready(())
+            })
+            .await;
+        }
+    }
+
+    /// Relays the association of `client` through the pool until `closed` completes, typically when the client closes the TCP connection of the association, e.g. with [`Associate::wait_close()`].
+    ///
+    /// The client-facing socket is used as is, so its expected client should be set beforehand, e.g. with [`AssociatedUdpSocket::set_declared_client()`]. Datagrams from the client are handled like with [`run_relay()`](super::run_relay), according to `config`, and sent from the pool according to the [`SharedMapping`] of the manager. Every event but [`RelayEvent::Started`] is reported to [`UdpRelayConfig::observer`].
+    ///
+    /// The association is unregistered and its claims are released when the relay finishes, or the future is dropped.
+    pub async fn relay<F>(
+        &self,
+        mut client: AssociatedUdpSocket<T>,
+        config: UdpRelayConfig,
+        closed: F,
+    ) -> RelayResult
+    where
+        F: Future<Output = Result<(), Error>>,
+    {
+        let stats = config.stats.clone().unwrap_or_default();
+        client.set_stats(stats.clone());
+
+        if let Some(observer) = config.observer.clone() {
+            client.on_drop(move |reason| observer.on_event(&RelayEvent::Dropped(reason)));
+        }
+
+        let token = self.inner.next_token.fetch_add(1, Ordering::Relaxed);
+        let assoc = Arc::new(Association {
+            client,
+            client_addr: Mutex::new(None),
+            downlink_limiter: Mutex::new(RateLimiter::new(&config.downlink_limit)),
+            config,
+            stats,
+            last_active: Mutex::new(Instant::now()),
+        });
+
+        self.inner
+            .associations
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(token, assoc.clone());
+
+        let registration = Registration {
+            inner: &self.inner,
+            token,
+        };
+
+        let res = self.inner.relay(token, &assoc, closed).await;
+        drop(registration);
+
+        match &res {
+            Ok(close) => notify(&assoc.config, RelayEvent::Closed(*close)),
+            Err(err) => notify(&assoc.config, RelayEvent::Failed(err)),
+        }
+
+        res.map(|close| RelaySession {
+            close,
+            stats: assoc.stats.snapshot(),
+        })
+    }
+
+    /// Returns the mapping of destinations to the sockets of the pool.
+    #[inline]
+    pub fn mapping(&self) -> SharedMapping {
+        self.inner.mapping
+    }
+
+    /// Returns the sockets of the pool.
+    #[inline]
+    pub fn sockets(&self) -> &[T] {
+        &self.inner.sockets
+    }
+
+    /// Returns a snapshot of the counters of the manager.
+    pub fn stats(&self) -> UdpRelayManagerStats {
+        UdpRelayManagerStats {
+            associations: self
+                .inner
+                .associations
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .len(),
+            claims: self
+                .inner
+                .claims
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .len(),
+            unmatched: self.inner.unmatched.load(Ordering::Relaxed),
+            conflicts: self.inner.conflicts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<T: DatagramSocket> ManagerInner<T> {
+    async fn relay<F>(
+        &self,
+        token: u64,
+        assoc: &Association<T>,
+        closed: F,
+    ) -> Result<RelayClose, Error>
+    where
+        F: Future<Output = Result<(), Error>>,
+    {
+        let config = &assoc.config;
+        let client = &assoc.client;
+
+        let destinations = UdpAssociationTable::<()>::with_shards(
+            config.max_destinations.unwrap_or(1024),
+            config.destination_timeout,
+            config.max_destinations,
+            1,
+        );
+        let mut reassembler = config.fragment_policy.reassembler();
+        client.allow_fragments(reassembler.is_some());
+
+        let mut uplink_limiter = RateLimiter::new(&config.uplink_limit);
+        let mut client_addr = None;
+
+        // without an idle timeout, the sleep is never polled, so no timer is registered
+        let idle = time::sleep(config.idle_timeout.unwrap_or_default());
+        tokio::pin!(idle);
+        tokio::pin!(closed);
+
+        loop {
+            tokio::select! {
+                res = &mut closed => return res.map(|()| RelayClose::ClientClosed),
+                res = client.recv_from_valid() => {
+                    let (pkt, header, src) = res?;
+
+                    if client_addr != Some(src) {
+                        client_addr = Some(src);
+                        *assoc.client_addr.lock().unwrap_or_else(|err| err.into_inner()) = Some(src);
+                        notify(config, RelayEvent::ClientLearned(src));
+                    }
+
+                    let pkt = match reassembler.as_mut() {
+                        Some(reassembler) => {
+                            let dropped = reassembler.dropped_count();
+                            let pkt = reassembler.push(src, &header, pkt);
+                            let dropped = reassembler.dropped_count() - dropped;
+                            record_drops(config, &assoc.stats, DropReason::Fragment, dropped);
+
+                            match pkt {
+                                Some(pkt) => pkt,
+                                None => continue,
+                            }
+                        }
+                        None => pkt,
+                    };
+
+                    let Ok(dst) = dns::resolve_address(&*config.resolver, &header.address).await
+                    else {
+                        record_drop(config, &assoc.stats, DropReason::Unresolved);
+                        continue;
+                    };
+
+                    // checked after unmapping, so a v4-mapped IPv6 address can not bypass a policy on the IPv4 address
+                    let dst = canonical(dst);
+
+                    let new = !destinations.contains(src, dst);
+
+                    if dst.ip().is_multicast()
+                        || !config.destination_policy.allows(&dst)
+                        || !destinations.insert(src, dst, ())
+                    {
+                        record_drop(config, &assoc.stats, DropReason::Denied);
+                        continue;
+                    }
+
+                    let requested = match config.return_address {
+                        ReturnAddress::Source => None,
+                        ReturnAddress::Requested => Some(header.address),
+                    };
+
+                    // checked before claiming, so a datagram over the limit does not take the destination from other associations
+                    if !uplink_limiter.try_acquire(pkt.len(), Instant::now()) {
+                        if new {
+                            destinations.remove(src, dst);
+                        }
+
+                        record_drop(config, &assoc.stats, DropReason::RateLimited);
+                        continue;
+                    }
+
+                    let Some((idx, target)) = self.claim(token, dst, requested, config.destination_timeout) else {
+                        if new {
+                            destinations.remove(src, dst);
+                        }
+
+                        record_drop(config, &assoc.stats, DropReason::Denied);
+                        continue;
+                    };
+
+                    if new {
+                        notify(config, RelayEvent::NewDestination(dst));
+                    }
+
+                    let socket = &self.sockets[idx];
+
+                    match poll_fn(|cx| socket.poll_send_to(cx, &pkt, target)).await {
+                        Ok(_) => {
+                            assoc.stats.record_uplink(pkt.len());
+                            assoc.touch();
+                        }
+                        Err(_) => record_drop(config, &assoc.stats, DropReason::Unresolved),
+                    }
+                }
+                () = &mut idle, if config.idle_timeout.is_some() => {
+                    // the return traffic is relayed by the manager, so the relay only learns about it here
+                    let deadline = *assoc.last_active.lock().unwrap_or_else(|err| err.into_inner()) + config.idle_timeout.unwrap_or_default();
+
+                    if deadline <= Instant::now() {
+                        return Ok(RelayClose::IdleTimeout);
+                    }
+
+                    idle.as_mut().reset(deadline);
+                }
+            }
+        }
+    }
+
+    /// Claims `dst` for the association of `token` on a socket of the pool according to the mapping, returning the index of the socket and the address to send to, or `None` if it is claimed by other associations.
+    fn claim(
+        &self,
+        token: u64,
+        dst: SocketAddr,
+        requested: Option<Address>,
+        ttl: Duration,
+    ) -> Option<(usize, SocketAddr)> {
+        let (route, mapped) = &self.routes[usize::from(dst.is_ipv6())];
+
+        if route.is_empty() {
+            return None;
+        }
+
+        let candidates = match self.mapping {
+            SharedMapping::Pinned => {
+                let pinned = (token % route.len() as u64) as usize;
+                &route[pinned..=pinned]
+            }
+            SharedMapping::PerDestination => &route[..],
+        };
+
+        let key = CanonicalAddr::from(dst);
+        let now = Instant::now();
+        let mut claims = self.claims.lock().unwrap_or_else(|err| err.into_inner());
+
+        // a socket already claimed by the association is kept, so its mapping does not change
+        let owned = candidates.iter().copied().find(|idx| {
+            claims
+                .get(&(*idx, key))
+                .is_some_and(|claim| claim.token == token && claim.is_live(now))
+        });
+
+        let free = || {
+            candidates.iter().copied().find(|idx| {
+                claims
+                    .get(&(*idx, key))
+                    .is_none_or(|claim| !claim.is_live(now))
+            })
+        };
+
+        let Some(idx) = owned.or_else(free) else {
+            self.conflicts.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let claim = Claim {
+            token,
+            requested,
+            ttl,
+            last_used: now,
+        };
+
+        match claims.entry((idx, key)) {
+            Entry::Occupied(mut entry) => *entry.get_mut() = claim,
+            Entry::Vacant(entry) => {
+                entry.insert(claim);
+            }
+        }
+
+        let target = match dst {
+            SocketAddr::V4(addr) if *mapped => {
+                SocketAddr::new(IpAddr::V6(addr.ip().to_ipv6_mapped()), addr.port())
+            }
+            dst => dst,
+        };
+
+        Some((idx, target))
+    }
+
+    /// Looks up the claim of the datagram from `src` received on the socket `idx`, returning the association it goes to and the address the client requested it with, and marking the claim as used now.
+    fn lookup(
+        &self,
+        idx: usize,
+        src: SocketAddr,
+    ) -> Option<(Arc<Association<T>>, Option<Address>)> {
+        let now = Instant::now();
+        let mut claims = self.claims.lock().unwrap_or_else(|err| err.into_inner());
+
+        let Entry::Occupied(mut entry) = claims.entry((idx, CanonicalAddr::from(src))) else {
+            return None;
+        };
+
+        if !entry.get().is_live(now) {
+            entry.remove();
+            return None;
+        }
+
+        let claim = entry.get_mut();
+        claim.last_used = now;
+
+        let assoc = self
+            .associations
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&claim.token)
+            .cloned()?;
+
+        Some((assoc, claim.requested.clone()))
+    }
+
+    /// Sends a datagram received from `src` on the socket `idx` to the association which claimed `src` on it.
+    fn dispatch(&self, cx: &mut Context<'_>, idx: usize, src: SocketAddr, pkt: &[u8]) {
+        match self.lookup(idx, src) {
+            Some((assoc, requested)) => assoc.forward(cx, src, requested, pkt),
+            None => {
+                self.unmatched.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Attempts to receive a datagram on any socket of the pool, starting from `next` so busy sockets do not starve the others.
+    fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+        next: &mut usize,
+    ) -> Poll<Result<(usize, SocketAddr, usize), Error>> {
+        for offset in 0..self.sockets.len() {
+            let idx = (*next + offset) % self.sockets.len();
+            let mut read = ReadBuf::new(buf);
+
+            if let Poll::Ready(res) = self.sockets[idx].poll_recv_from(cx, &mut read) {
+                *next = idx + 1;
+                return Poll::Ready(res.map(|src| (idx, src, read.filled().len())));
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<T> Clone for UdpRelayManager<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Debug for UdpRelayManager<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("UdpRelayManager")
+            .field("sockets", &self.inner.sockets.len())
+            .field("mapping", &self.inner.mapping)
+            .finish()
+    }
+}
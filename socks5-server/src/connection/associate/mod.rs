@@ -0,0 +1,377 @@
+//! Socks5 command type `Associate`
+//!
+//! This module also provides an [`tokio::net::UdpSocket`] wrapper [`AssociatedUdpSocket`], which can be used to send and receive UDP packets without dealing with the SOCKS5 protocol UDP header, a complete UDP relay [`run_relay()`] built on top of it, and [`UdpRelayManager`] relaying many associations over a shared pool of sockets. These are enabled by the `udp` cargo feature, without which only the TCP side of the command is available.
+
+use crate::{
+    connection::{failed, BufferedStream},
+    error::Failed,
+    event::{CloseReason, Session},
+    tracker::Tracked,
+};
+use bytes::Bytes;
+use socks5_proto::{Address, Reply, Response};
+use std::{future::Future, io::Error, marker::PhantomData, net::SocketAddr};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+#[cfg(unix)]
+use tokio::net::{unix::SocketAddr as UnixSocketAddr, UnixStream};
+
+#[cfg(feature = "udp")]
+use std::{io::ErrorKind, net::IpAddr};
+#[cfg(feature = "udp")]
+use tokio::net::UdpSocket;
+
+#[cfg(feature = "udp")]
+mod addr;
+#[cfg(feature = "udp")]
+mod batch;
+#[cfg(feature = "udp")]
+mod datagram;
+#[cfg(feature = "udp")]
+mod error;
+#[cfg(feature = "udp")]
+mod event;
+#[cfg(feature = "udp")]
+mod fragment;
+#[cfg(feature = "framed")]
+mod framed;
+#[cfg(feature = "udp")]
+mod manager;
+#[cfg(all(feature = "gso", target_os = "linux"))]
+mod offload;
+#[cfg(feature = "udp")]
+mod pool;
+#[cfg(feature = "udp")]
+mod rate;
+#[cfg(feature = "udp")]
+mod relay;
+#[cfg(feature = "udp")]
+mod socket;
+#[cfg(feature = "udp")]
+mod socket_pool;
+#[cfg(feature = "udp")]
+mod sockopt;
+#[cfg(feature = "udp")]
+mod split;
+#[cfg(feature = "udp")]
+mod stats;
+#[cfg(feature = "udp")]
+mod table;
+
+#[cfg(feature = "udp")]
+pub use self::{
+    datagram::DatagramSocket,
+    error::{PacketTooLarge, Truncated},
+    event::{RelayEvent, RelayObserver},
+    fragment::{FragmentPolicy, FragmentReassembler},
+    manager::{SharedMapping, UdpRelayManager, UdpRelayManagerStats},
+    pool::{BufferPool, PooledBuf},
+    rate::{RateLimit, RateLimiter},
+    relay::{
+        run_relay, run_relay_until, DestinationPolicy, RebindPolicy, RelayClose, RelayResult,
+        RelaySession, ReturnAddress, UdpRelayConfig,
+    },
+    socket::{AssociatedUdpSocket, ClientMatch},
+    socket_pool::{PooledUdpSocket, UdpSocketPool, UdpSocketPoolStats},
+    sockopt::{ControlKeepalive, UdpSocketOptions},
+    split::{RecvHalf, ReuniteError, SendHalf},
+    stats::{DropReason, UdpRelayStats, UdpRelayStatsSnapshot},
+    table::{UdpAssociationTable, UdpAssociationTableStats},
+};
+
+#[cfg(feature = "framed")]
+pub use self::framed::UdpFramed;
+
+/// Connection state types
+pub mod state {
+    #[derive(Debug)]
+    pub struct NeedReply;
+
+    #[derive(Debug)]
+    pub struct Ready;
+}
+
+/// Socks5 command type `Associate`
+///
+/// Reply the client with [`Associate::reply()`] to complete the command negotiation.
+///
+/// The address the client declared in the associate command, i.e. the UDP endpoint it is going to send from, is kept as [`Associate::client_declared_addr()`]. [`Associate::reply_with_socket()`] and [`run_relay()`] filter the source of datagrams by it.
+///
+/// Generic `<T>` is the stream the connection is carried over, see [`IncomingConnection`](crate::IncomingConnection). Binding and relaying the UDP side needs the addresses of a [`TcpStream`], so it is only available on one.
+#[derive(Debug)]
+pub struct Associate<S, T = TcpStream> {
+    stream: BufferedStream<T>,
+    declared: Address,
+    session: Session,
+    _state: PhantomData<S>,
+}
+
+impl<T: AsyncWrite + Unpin> Associate<state::NeedReply, T> {
+    /// Reply to the SOCKS5 client with the given reply and address.
+    ///
+    /// If encountered an error while writing the reply, it is returned as a [`Failed`] alongside the original stream. So is [`Error::SetupDeadlineExceeded`](crate::Error::SetupDeadlineExceeded) if the reply is not sent by the session setup deadline, see [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline).
+    pub async fn reply(
+        mut self,
+        reply: Reply,
+        addr: Address,
+    ) -> Result<Associate<state::Ready, T>, Failed<BufferedStream<T>>> {
+        let resp = Response::new(reply, addr);
+
+        if let Err(err) = self.session.setup(resp.write_to(&mut self.stream)).await {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
+        }
+
+        self.session.reply(reply);
+        self.session.setup_done();
+        self.stream.end_wire_tap();
+
+        Ok(Associate::new(self.stream, self.declared, self.session))
+    }
+}
+
+impl Associate<state::NeedReply> {
+    /// Binds a UDP socket for the association and replies [`Reply::Succeeded`] with its address in one step.
+    ///
+    /// The socket is bound on an ephemeral port of `bind_ip`, which defaults to the local IP address of this TCP connection since that is an address the client is known to be able to route to. The reply always carries the actual bound address. If the socket is bound on an unspecified address, the local IP address of this TCP connection is replied instead, as the client can not send anything to `0.0.0.0` or `::`.
+    ///
+    /// A v4-mapped IPv6 local address (on a dual-stack listener) is treated as the IPv4 address it maps, so IPv4 clients get an IPv4 reply. If `bind_ip` is of a different address family than the TCP connection, the client may not be able to parse or reach it, so an error of kind [`ErrorKind::InvalidInput`] is returned.
+    ///
+    /// The expected client of the returned socket is set from [`Associate::client_declared_addr()`] with [`ClientMatch::Strict`], see [`AssociatedUdpSocket::set_declared_client()`]. A declared IP address other than the one of the TCP connection is most likely the private address of a client behind NAT, so only the IP address of the TCP connection is enforced then, and the client endpoint is learned from the first datagram coming from it. Call [`AssociatedUdpSocket::set_expected_client()`] or [`AssociatedUdpSocket::clear_expected_client()`] on the socket to override this.
+    ///
+    /// If binding the socket fails, [`Reply::GeneralFailure`] is replied to the client. The error alongside the original `TcpStream` is returned as a [`Failed`] on any failure. Once the session setup deadline has passed, see [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline), nothing is replied and [`Error::SetupDeadlineExceeded`](crate::Error::SetupDeadlineExceeded) is returned.
+    ///
+    /// Enabled by the `udp` cargo feature.
+    #[cfg(feature = "udp")]
+    pub async fn reply_with_socket(
+        mut self,
+        bind_ip: Option<IpAddr>,
+        buf_size: usize,
+    ) -> Result<(Associate<state::Ready>, AssociatedUdpSocket), Failed> {
+        let (socket, addr) = match self.session.setup(self.bind_socket(bind_ip)).await {
+            Ok(res) => res,
+            Err(err) => {
+                let resp = Response::new(Reply::GeneralFailure, Address::unspecified());
+
+                if !matches!(err, crate::Error::SetupDeadlineExceeded)
+                    && resp.write_to(&mut self.stream).await.is_ok()
+                {
+                    self.session.reply(Reply::GeneralFailure);
+                }
+
+                self.session.set_close_reason(CloseReason::Failed);
+                return Err(failed(err, self.stream));
+            }
+        };
+
+        let resp = Response::new(Reply::Succeeded, Address::SocketAddress(addr));
+
+        if let Err(err) = self.session.setup(resp.write_to(&mut self.stream)).await {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
+        }
+
+        self.session.reply(Reply::Succeeded);
+        self.session.setup_done();
+        self.stream.end_wire_tap();
+
+        let socket = AssociatedUdpSocket::new(socket, buf_size);
+
+        if let Err(err) = self.expect_declared_client(&socket, ClientMatch::Strict) {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
+        }
+
+        Ok((
+            Associate::new(self.stream, self.declared, self.session),
+            socket,
+        ))
+    }
+
+    #[cfg(feature = "udp")]
+    async fn bind_socket(&self, bind_ip: Option<IpAddr>) -> Result<(UdpSocket, SocketAddr), Error> {
+        let local_ip = self.stream.get_ref().local_addr()?.ip().to_canonical();
+
+        let ip = match bind_ip.map(|ip| ip.to_canonical()) {
+            Some(ip) if ip.is_ipv4() != local_ip.is_ipv4() => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("UDP bind address {ip} does not match the address family of the TCP connection {local_ip}"),
+                ));
+            }
+            Some(ip) => ip,
+            None => local_ip,
+        };
+
+        let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await?;
+        let mut addr = socket.local_addr()?;
+
+        if addr.ip().is_unspecified() {
+            addr.set_ip(local_ip);
+        }
+
+        Ok((socket, addr))
+    }
+}
+
+impl<T: AsyncRead + Unpin> Associate<state::Ready, T> {
+    /// Wait until the SOCKS5 client closes this TCP connection.
+    ///
+    /// Socks5 protocol defines that when the client closes the TCP connection used to send the associate command, the server should release the associated UDP socket.
+    ///
+    /// `Ok(())` is returned when the client closes the connection cleanly, while an error reading the connection (e.g. a reset) is returned as `Err`. Any data the client sends on this connection is discarded, as the protocol does not define any.
+    ///
+    /// This method is cancel safe. Dropping it at any `.await` point does not lose anything other than the discarded data, so it can be used in `tokio::select!` and called again.
+    pub async fn wait_close(&mut self) -> Result<(), Error> {
+        loop {
+            match self.stream.read(&mut [0]).await {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            }
+        }
+    }
+
+    /// Wait until either the SOCKS5 client closes this TCP connection, or the given future completes.
+    ///
+    /// This is useful to tie the lifetime of the association to both the client and e.g. a server shutdown signal. [`WaitClose`] tells which one happened. Errors reading the connection are returned as `Err`, the same as [`Associate::wait_close()`].
+    ///
+    /// This method is cancel safe as long as `until` is.
+    pub async fn wait_close_or<F: Future>(
+        &mut self,
+        until: F,
+    ) -> Result<WaitClose<F::Output>, Error> {
+        tokio::select! {
+            res = self.wait_close() => res.map(|()| WaitClose::Closed),
+            output = until => Ok(WaitClose::Until(output)),
+        }
+    }
+}
+
+/// The event that completed [`Associate::wait_close_or()`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WaitClose<T> {
+    /// The client closed the TCP connection.
+    Closed,
+
+    /// The given future completed first, with its output.
+    Until(T),
+}
+
+impl<S, T> Associate<S, T> {
+    #[inline]
+    pub(crate) fn new(stream: BufferedStream<T>, declared: Address, session: Session) -> Self {
+        Self {
+            stream,
+            declared,
+            session,
+            _state: PhantomData,
+        }
+    }
+
+    /// Returns the address the client declared in the associate command as the UDP endpoint it is going to send from.
+    ///
+    /// Most clients do not know their UDP endpoint yet and declare `0.0.0.0:0` or a port of `0`. See [`AssociatedUdpSocket::set_declared_client()`].
+    #[inline]
+    pub fn client_declared_addr(&self) -> &Address {
+        &self.declared
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing. Reading from it directly skips the bytes already buffered, see [`BufferedStream`].
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the [`Associate<S>`] and returns the underlying stream, in a [`Tracked`] counting the connection until it is dropped.
+    ///
+    /// Bytes the client already sent that were buffered but not read yet are dropped. Use [`Associate::into_parts()`] to keep them.
+    #[inline]
+    pub fn into_inner(self) -> Tracked<T> {
+        self.into_parts().0
+    }
+
+    /// Consumes the [`Associate<S>`] and returns the underlying stream alongside the bytes the client already sent that were buffered but not read yet. The stream comes in a [`Tracked`], as with [`Associate::into_inner()`].
+    #[inline]
+    pub fn into_parts(mut self) -> (Tracked<T>, Bytes) {
+        self.session.set_close_reason(CloseReason::Detached);
+        self.stream.into_parts()
+    }
+}
+
+impl<S, T: AsyncWrite + Unpin> Associate<S, T> {
+    /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
+    #[inline]
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.stream.shutdown().await
+    }
+}
+
+impl<S> Associate<S> {
+    /// Sets the expected client of `socket` from the declared address, enforcing only the IP address of the TCP connection if the declared one is different.
+    #[cfg(feature = "udp")]
+    fn expect_declared_client<D: DatagramSocket>(
+        &self,
+        socket: &AssociatedUdpSocket<D>,
+        matching: ClientMatch,
+    ) -> Result<(), Error> {
+        let peer_ip = self.stream.get_ref().peer_addr()?.ip();
+
+        match &self.declared {
+            Address::SocketAddress(addr) if addr.ip().to_canonical() != peer_ip.to_canonical() => {
+                socket.learn_client(peer_ip, matching)
+            }
+            declared => socket.set_declared_client(declared, peer_ip, matching),
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection right away with a reset rather than the orderly shutdown of [`Associate::close()`], e.g. for an abusive client.
+    ///
+    /// `SO_LINGER` is set to 0 on the socket before it is dropped, so the client receives an RST and the state of the socket is freed at once, rather than the connection lingering half-open until the client closes it too. Data still pending to be sent is discarded. The stream is dropped either way, so if setting the option fails, the error is returned and the connection is closed with a regular shutdown.
+    #[inline]
+    pub fn abort(self) -> Result<(), Error> {
+        self.stream.abort()
+    }
+
+    /// Returns the local address that this stream is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.get_ref().local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to.
+    #[inline]
+    pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
+        self.stream.get_ref().peer_addr()
+    }
+}
+
+#[cfg(unix)]
+impl<S> Associate<S, UnixStream> {
+    /// Returns the local address that this stream is bound to, for a connection over a [`UnixStream`].
+    #[inline]
+    pub fn local_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to, for a connection over a [`UnixStream`].
+    #[inline]
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().peer_addr()
+    }
+}
@@ -0,0 +1,449 @@
+//! UDP segmentation offload on Linux, `UDP_SEGMENT` (GSO) on send and `UDP_GRO` on receive
+
+use super::{
+    batch::{self, Datagram},
+    AssociatedUdpSocket, DropReason,
+};
+use bytes::BytesMut;
+use socks5_proto::UdpHeader;
+use std::{
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    ops::Range,
+    os::fd::AsRawFd,
+    sync::atomic::{AtomicU8, Ordering},
+};
+use tokio::io::Interest;
+
+/// The maximum number of segments the kernel accepts in one GSO send, `UDP_MAX_SEGMENTS`
+const MAX_SEGMENTS: usize = 64;
+
+/// The maximum length of the data sent in one GSO send, the largest payload of an IPv4 UDP datagram
+const MAX_GSO_LEN: usize = 65507;
+
+/// The buffer size needed to receive a full GRO-coalesced datagram
+const GRO_BUF_SIZE: usize = u16::MAX as usize;
+
+const GSO_UNKNOWN: u8 = 0;
+const GSO_SUPPORTED: u8 = 1;
+const GSO_UNSUPPORTED: u8 = 2;
+
+/// Whether GSO works on a socket, probed on first use and turned off if the kernel or the device rejects it at send time
+#[derive(Debug, Default)]
+pub(super) struct GsoState(AtomicU8);
+
+impl AssociatedUdpSocket {
+    /// Returns the number of segments [`AssociatedUdpSocket::send_segments_to()`] can hand to the kernel in one system call, or `1` if the kernel does not support `UDP_SEGMENT` (before Linux 4.18) or sending with it failed before.
+    pub fn max_gso_segments(&self) -> usize {
+        let state = match self.gso.0.load(Ordering::Acquire) {
+            GSO_UNKNOWN => {
+                let state = if sys::gso_supported(self.get_ref()) {
+                    GSO_SUPPORTED
+                } else {
+                    GSO_UNSUPPORTED
+                };
+
+                self.gso.0.store(state, Ordering::Release);
+                state
+            }
+            state => state,
+        };
+
+        if state == GSO_SUPPORTED {
+            MAX_SEGMENTS
+        } else {
+            1
+        }
+    }
+
+    /// Sends a batch of equal-size payloads sharing one SOCKS5 UDP header to a specified remote address, using UDP segmentation offload if available.
+    ///
+    /// `pkts` is split into payloads of `segment_size` bytes, the last one possibly being shorter, and each of them is sent as a datagram of its own with `header` in front of it. With GSO, up to [`AssociatedUdpSocket::max_gso_segments()`] datagrams are handed to the kernel in one system call, which splits them. Without it, or if the kernel rejects a GSO send, the datagrams are sent in batches with `sendmmsg` instead. A GSO send failing with `EIO`, e.g. because the network device can not checksum segments, turns GSO off for this socket.
+    ///
+    /// On success, it returns the number of payload bytes sent, which may be fewer than `pkts.len()`, but always at a segment boundary, if sending fails part way. An error is only returned if nothing was sent.
+    ///
+    /// A [`PacketTooLarge`](super::PacketTooLarge) error is returned if a segment with the SOCKS5 UDP header is larger than the maximum sending UDP packet size.
+    pub async fn send_segments_to(
+        &self,
+        pkts: &[u8],
+        segment_size: usize,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        if segment_size == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "segment size must not be zero",
+            ));
+        }
+
+        if pkts.is_empty() {
+            return Ok(0);
+        }
+
+        self.check_send_size(&pkts[..segment_size.min(pkts.len())], header)?;
+
+        let dgram_len = header.serialized_len() + segment_size;
+        let per_call = (MAX_GSO_LEN / dgram_len).clamp(1, MAX_SEGMENTS);
+        let mut sent = 0;
+
+        for chunk in pkts.chunks(segment_size * per_call) {
+            match self
+                .send_segments_once(chunk, segment_size, header, addr)
+                .await
+            {
+                Ok(len) => {
+                    sent += len;
+
+                    if len < chunk.len() {
+                        break;
+                    }
+                }
+                Err(err) if sent == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        Ok(sent)
+    }
+
+    async fn send_segments_once(
+        &self,
+        pkts: &[u8],
+        segment_size: usize,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        if pkts.len() > segment_size && self.max_gso_segments() > 1 {
+            let mut buf = self.take_scratch();
+            buf.reserve(pkts.len().div_ceil(segment_size) * header.serialized_len() + pkts.len());
+
+            for pkt in pkts.chunks(segment_size) {
+                header.write_to_buf(&mut buf);
+                buf.extend_from_slice(pkt);
+            }
+
+            let dgram_len = header.serialized_len() + segment_size;
+            let fd = self.get_ref().as_raw_fd();
+            let res = self
+                .get_ref()
+                .async_io(Interest::WRITABLE, || {
+                    sys::send_gso(fd, &buf, dgram_len as u16, addr)
+                })
+                .await;
+
+            self.recycle(buf);
+
+            match res {
+                Ok(_) => {
+                    for pkt in pkts.chunks(segment_size) {
+                        self.stats().record_downlink(pkt.len());
+                    }
+
+                    return Ok(pkts.len());
+                }
+                Err(err) if err.raw_os_error() == Some(libc::EIO) => {
+                    self.gso.0.store(GSO_UNSUPPORTED, Ordering::Release);
+                }
+                // e.g. a segment larger than the path MTU, which can still be sent as a separate datagram
+                Err(err) if err.raw_os_error() == Some(libc::EINVAL) => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut headers = BytesMut::with_capacity(header.serialized_len());
+        header.write_to_buf(&mut headers);
+
+        let dgrams = pkts
+            .chunks(segment_size)
+            .map(|pkt| Datagram {
+                header: &headers,
+                payload: pkt,
+                addr,
+            })
+            .collect::<Vec<_>>();
+
+        let mut sent = 0;
+        let mut sent_dgrams = 0;
+
+        while sent_dgrams < dgrams.len() {
+            match batch::send_batch(self.get_ref(), &dgrams[sent_dgrams..]).await {
+                Ok(n) => {
+                    for dgram in &dgrams[sent_dgrams..sent_dgrams + n] {
+                        self.stats().record_downlink(dgram.payload.len());
+                        sent += dgram.payload.len();
+                    }
+
+                    sent_dgrams += n;
+                }
+                Err(err) if sent_dgrams == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Gets whether UDP generic receive offload (`UDP_GRO`) is enabled on the socket.
+    #[inline]
+    pub fn gro(&self) -> Result<bool, Error> {
+        sys::gro(self.get_ref())
+    }
+
+    /// Enables or disables UDP generic receive offload (`UDP_GRO`) on the socket, letting the kernel coalesce datagrams of a flow into one buffer for [`AssociatedUdpSocket::recv_segments_from()`].
+    ///
+    /// This fails on kernels without `UDP_GRO` (before Linux 5.0), in which case [`AssociatedUdpSocket::recv_segments_from()`] keeps receiving one datagram at a time. Only enable it on sockets received from with [`AssociatedUdpSocket::recv_segments_from()`], as other receiving methods would see coalesced datagrams as one.
+    #[inline]
+    pub fn set_gro(&self, enable: bool) -> Result<(), Error> {
+        sys::set_gro(self.get_ref(), enable)
+    }
+
+    /// Receives SOCKS5 UDP packets on the socket from a remote address, appending them to a caller-provided buffer, with UDP generic receive offload if enabled with [`AssociatedUdpSocket::set_gro()`].
+    ///
+    /// With GRO, one call may return several datagrams of the same source, coalesced by the kernel. Each of them carries a SOCKS5 UDP header of its own, so the header and the range of the payload in `buf` are returned for every datagram, alongside the source address. Without GRO, exactly one datagram is returned per call.
+    ///
+    /// Like [`AssociatedUdpSocket::recv_from_valid()`], datagrams whose header can not be parsed or that are larger than the maximum receiving UDP packet size are discarded and counted in [`UdpRelayStats`](super::UdpRelayStats), so only an error of the underlying socket is returned. If an expected client is set, datagrams from other sources are dropped and this method keeps waiting.
+    pub async fn recv_segments_from(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(Vec<(UdpHeader, Range<usize>)>, SocketAddr), Error> {
+        let max = self.get_max_pkt_size();
+        let fd = self.get_ref().as_raw_fd();
+
+        loop {
+            buf.reserve(GRO_BUF_SIZE.max(max) + 1);
+
+            let start = buf.len();
+            let cap = buf.capacity() - start;
+
+            let (len, addr, segment_size) = self
+                .get_ref()
+                .async_io(Interest::READABLE, || {
+                    sys::recv_gro(fd, buf.spare_capacity_mut())
+                })
+                .await?;
+
+            let filled = len.min(cap);
+
+            // SAFETY: the kernel initialized `filled` bytes of the spare capacity
+            unsafe { buf.set_len(start + filled) };
+
+            let segment_size = segment_size.unwrap_or(len).max(1);
+            let count = len.div_ceil(segment_size).max(1);
+
+            if !self.is_expected_client(addr) {
+                buf.truncate(start);
+
+                for _ in 0..count {
+                    self.record_drop(DropReason::Filtered);
+                }
+
+                continue;
+            }
+
+            if len > cap {
+                buf.truncate(start);
+
+                for _ in 0..count {
+                    self.record_drop(DropReason::Oversize);
+                }
+
+                continue;
+            }
+
+            let mut pkts = Vec::with_capacity(count);
+
+            for offset in (0..len).step_by(segment_size) {
+                let raw = start + offset..start + (offset + segment_size).min(len);
+
+                if raw.len() > max {
+                    self.record_drop(DropReason::Oversize);
+                    continue;
+                }
+
+                match self.parse_header(buf, raw) {
+                    Ok(pkt) if !self.drops_fragment(&pkt.0, addr) => pkts.push(pkt),
+                    _ => {}
+                }
+            }
+
+            if pkts.is_empty() {
+                buf.truncate(start);
+                continue;
+            }
+
+            return Ok((pkts, addr));
+        }
+    }
+}
+
+mod sys {
+    use socket2::SockAddr;
+    use std::{
+        io::Error,
+        mem::{self, MaybeUninit},
+        net::SocketAddr,
+        os::fd::{AsRawFd, RawFd},
+        ptr,
+    };
+    use tokio::net::UdpSocket;
+
+    /// A control message buffer large enough for one `UDP_SEGMENT` / `UDP_GRO` message, aligned for `cmsghdr`
+    #[repr(C, align(8))]
+    struct Cmsg([u8; 64]);
+
+    pub(super) fn gso_supported(socket: &UdpSocket) -> bool {
+        getsockopt(socket.as_raw_fd(), libc::UDP_SEGMENT).is_ok()
+    }
+
+    pub(super) fn gro(socket: &UdpSocket) -> Result<bool, Error> {
+        getsockopt(socket.as_raw_fd(), libc::UDP_GRO).map(|val| val != 0)
+    }
+
+    pub(super) fn set_gro(socket: &UdpSocket, enable: bool) -> Result<(), Error> {
+        let val = libc::c_int::from(enable);
+
+        // SAFETY: `val` is a valid `c_int` outliving the call
+        let res = unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_UDP,
+                libc::UDP_GRO,
+                ptr::from_ref(&val).cast(),
+                mem::size_of::<libc::c_int>() as _,
+            )
+        };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    fn getsockopt(fd: RawFd, opt: libc::c_int) -> Result<libc::c_int, Error> {
+        let mut val: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+        // SAFETY: `val` and `len` are valid for writes and outlive the call
+        let res = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_UDP,
+                opt,
+                ptr::from_mut(&mut val).cast(),
+                &mut len,
+            )
+        };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(val)
+    }
+
+    /// Sends `buf` with a `UDP_SEGMENT` control message, so the kernel splits it into datagrams of `segment_size` bytes.
+    pub(super) fn send_gso(
+        fd: RawFd,
+        buf: &[u8],
+        segment_size: u16,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        let addr = SockAddr::from(addr);
+
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut _,
+            iov_len: buf.len(),
+        };
+
+        let mut cmsg = Cmsg([0; 64]);
+
+        // SAFETY: all-zero is a valid `msghdr`
+        let mut hdr = unsafe { mem::zeroed::<libc::msghdr>() };
+        hdr.msg_name = addr.as_ptr() as *mut _;
+        hdr.msg_namelen = addr.len();
+        hdr.msg_iov = &mut iov;
+        hdr.msg_iovlen = 1;
+        hdr.msg_control = cmsg.0.as_mut_ptr().cast();
+
+        // SAFETY: the control buffer is large enough and aligned for one control message carrying a `u16`, which is written within it
+        unsafe {
+            hdr.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as _) as _;
+
+            let msg = libc::CMSG_FIRSTHDR(&hdr);
+            (*msg).cmsg_level = libc::SOL_UDP;
+            (*msg).cmsg_type = libc::UDP_SEGMENT;
+            (*msg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as _) as _;
+            ptr::write_unaligned(libc::CMSG_DATA(msg).cast::<u16>(), segment_size);
+        }
+
+        // SAFETY: the message points to a valid address, a valid iovec over `buf` and a valid control buffer, all outliving the call. The kernel does not write through the iovec
+        let res = unsafe { libc::sendmsg(fd, &hdr, libc::MSG_DONTWAIT) };
+
+        if res < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(res as usize)
+    }
+
+    /// Receives into `spare` with `MSG_TRUNC`, returning the original length, the source address and the segment size from a `UDP_GRO` control message if the datagrams were coalesced.
+    pub(super) fn recv_gro(
+        fd: RawFd,
+        spare: &mut [MaybeUninit<u8>],
+    ) -> Result<(usize, SocketAddr, Option<usize>), Error> {
+        loop {
+            let mut iov = libc::iovec {
+                iov_base: spare.as_mut_ptr().cast(),
+                iov_len: spare.len(),
+            };
+
+            let mut cmsg = Cmsg([0; 64]);
+            let mut len = 0;
+            let mut segment_size = None;
+
+            // SAFETY: the message points to a valid address buffer given by `try_init`, a valid iovec over `spare` and a valid control buffer, all outliving the call. With `MSG_TRUNC`, the returned length is the original length of the datagram, while the kernel still writes no more than the iovec length
+            let (_, addr) = unsafe {
+                SockAddr::try_init(|storage, storage_len| {
+                    // SAFETY: all-zero is a valid `msghdr`
+                    let mut hdr = mem::zeroed::<libc::msghdr>();
+                    hdr.msg_name = storage.cast();
+                    hdr.msg_namelen = *storage_len;
+                    hdr.msg_iov = &mut iov;
+                    hdr.msg_iovlen = 1;
+                    hdr.msg_control = cmsg.0.as_mut_ptr().cast();
+                    hdr.msg_controllen = cmsg.0.len() as _;
+
+                    let res = libc::recvmsg(fd, &mut hdr, libc::MSG_DONTWAIT | libc::MSG_TRUNC);
+
+                    if res < 0 {
+                        return Err(Error::last_os_error());
+                    }
+
+                    len = res as usize;
+                    *storage_len = hdr.msg_namelen;
+
+                    let mut msg = libc::CMSG_FIRSTHDR(&hdr);
+
+                    while !msg.is_null() {
+                        if (*msg).cmsg_level == libc::SOL_UDP && (*msg).cmsg_type == libc::UDP_GRO {
+                            let size =
+                                ptr::read_unaligned(libc::CMSG_DATA(msg).cast::<libc::c_int>());
+                            segment_size = Some(size as usize);
+                        }
+
+                        msg = libc::CMSG_NXTHDR(&hdr, msg);
+                    }
+
+                    Ok(())
+                })?
+            };
+
+            // a datagram from an address of an unknown family is skipped
+            if let Some(addr) = addr.as_socket() {
+                return Ok((len, addr, segment_size));
+            }
+        }
+    }
+}
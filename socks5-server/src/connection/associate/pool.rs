@@ -0,0 +1,207 @@
+use bytes::{Bytes, BytesMut};
+use std::{
+    borrow::{Borrow, BorrowMut},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, TryLockError,
+    },
+    thread,
+};
+
+/// A pool of receive buffers, shared by UDP associations.
+///
+/// Buffers are handed out as [`PooledBuf`]s with a capacity of at least the slab size, and go back to the pool when dropped. A [`PooledBuf`] can be frozen into a [`Bytes`], in which case the buffer goes back to the pool once the last slice of it is dropped, so payloads handed to user code keep the buffer alive.
+///
+/// At most `cap` idle buffers are kept. The pool is split into mutex-guarded shards to reduce contention, and a buffer is allocated or discarded instead of waiting for a busy shard.
+///
+/// Cloning a [`BufferPool`] gives another handle to the same pool.
+#[derive(Clone)]
+pub struct BufferPool {
+    inner: Arc<PoolInner>,
+}
+
+struct PoolInner {
+    shards: Box<[Mutex<Vec<BytesMut>>]>,
+    next: AtomicUsize,
+    slab_size: usize,
+    shard_cap: usize,
+}
+
+impl BufferPool {
+    /// Creates a new [`BufferPool`] handing out buffers of at least `slab_size` bytes and keeping at most `cap` idle buffers.
+    pub fn new(slab_size: usize, cap: usize) -> Self {
+        let shards = thread::available_parallelism()
+            .map_or(4, |n| n.get())
+            .clamp(1, 64)
+            .min(cap.max(1));
+
+        Self {
+            inner: Arc::new(PoolInner {
+                shards: (0..shards).map(|_| Mutex::new(Vec::new())).collect(),
+                next: AtomicUsize::new(0),
+                slab_size,
+                shard_cap: cap.div_ceil(shards),
+            }),
+        }
+    }
+
+    /// Takes a buffer from the pool, or allocates a new one if the pool is empty.
+    ///
+    /// The returned buffer is empty, with a capacity of at least the slab size.
+    pub fn get(&self) -> PooledBuf {
+        let buf = self
+            .inner
+            .try_each_shard(|shard| shard.pop())
+            .unwrap_or_else(|| BytesMut::with_capacity(self.inner.slab_size));
+
+        PooledBuf {
+            buf,
+            pool: Some(self.inner.clone()),
+        }
+    }
+
+    /// Returns the minimum capacity of buffers handed out by this pool.
+    #[inline]
+    pub fn slab_size(&self) -> usize {
+        self.inner.slab_size
+    }
+
+    /// Returns the number of idle buffers in the pool.
+    pub fn idle(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|err| err.into_inner()).len())
+            .sum()
+    }
+}
+
+impl PoolInner {
+    fn try_each_shard<T>(&self, mut f: impl FnMut(&mut Vec<BytesMut>) -> Option<T>) -> Option<T> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        (0..self.shards.len()).find_map(|i| {
+            let mut shard = match self.shards[(start + i) % self.shards.len()].try_lock() {
+                Ok(shard) => shard,
+                Err(TryLockError::Poisoned(err)) => err.into_inner(),
+                Err(TryLockError::WouldBlock) => return None,
+            };
+            f(&mut shard)
+        })
+    }
+
+    fn put(&self, mut buf: BytesMut) {
+        if buf.capacity() < self.slab_size {
+            return;
+        }
+
+        buf.clear();
+        let mut buf = Some(buf);
+
+        self.try_each_shard(|shard| {
+            if shard.len() < self.shard_cap {
+                shard.push(buf.take().unwrap());
+                Some(())
+            } else {
+                None
+            }
+        });
+    }
+}
+
+impl Default for BufferPool {
+    /// Creates a [`BufferPool`] of 2048-byte buffers, keeping at most 1024 idle buffers.
+    fn default() -> Self {
+        Self::new(2048, 1024)
+    }
+}
+
+impl Debug for BufferPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("BufferPool")
+            .field("slab_size", &self.inner.slab_size)
+            .field("shards", &self.inner.shards.len())
+            .field("shard_cap", &self.inner.shard_cap)
+            .finish()
+    }
+}
+
+/// A buffer taken from a [`BufferPool`], going back to the pool when dropped.
+///
+/// It dereferences to a [`BytesMut`], so it can be used with e.g. [`AssociatedUdpSocket::recv_batch()`](super::AssociatedUdpSocket::recv_batch).
+pub struct PooledBuf {
+    buf: BytesMut,
+    pool: Option<Arc<PoolInner>>,
+}
+
+impl PooledBuf {
+    /// Converts the buffer into an immutable [`Bytes`]. The buffer goes back to the pool once the [`Bytes`] and all slices of it are dropped.
+    #[inline]
+    pub fn freeze(self) -> Bytes {
+        Bytes::from_owner(self)
+    }
+
+    /// Detaches the buffer from its pool and returns it.
+    #[inline]
+    pub fn into_inner(mut self) -> BytesMut {
+        self.pool = None;
+        mem::take(&mut self.buf)
+    }
+}
+
+impl Deref for PooledBuf {
+    type Target = BytesMut;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.buf
+    }
+}
+
+impl DerefMut for PooledBuf {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.buf
+    }
+}
+
+impl Borrow<BytesMut> for PooledBuf {
+    #[inline]
+    fn borrow(&self) -> &BytesMut {
+        &self.buf
+    }
+}
+
+impl BorrowMut<BytesMut> for PooledBuf {
+    #[inline]
+    fn borrow_mut(&mut self) -> &mut BytesMut {
+        &mut self.buf
+    }
+}
+
+impl AsRef<[u8]> for PooledBuf {
+    #[inline]
+    fn as_ref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl Drop for PooledBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.put(mem::take(&mut self.buf));
+        }
+    }
+}
+
+impl Debug for PooledBuf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("PooledBuf")
+            .field("len", &self.buf.len())
+            .field("capacity", &self.buf.capacity())
+            .finish()
+    }
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A rate limit of one direction of a UDP association, in packets and / or bytes per second.
+///
+/// Limits are enforced with token buckets holding `burst` worth of the rate, so short bursts above the rate are let through as long as the average stays below it. Datagrams over the limit are dropped rather than queued, as queueing UDP only adds latency to traffic that is going to be dropped anyway.
+///
+/// The burst should allow at least one datagram of the maximum size, otherwise larger datagrams are never let through the byte limit.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct RateLimit {
+    /// The maximum number of datagrams per second. `None` disables the packet limit.
+    pub packets_per_sec: Option<u64>,
+
+    /// The maximum number of payload bytes per second, e.g. `6_250_000` for 50 Mbit/s. `None` disables the byte limit.
+    pub bytes_per_sec: Option<u64>,
+
+    /// How much of the rate can be used at once after an idle period.
+    pub burst: Duration,
+}
+
+impl RateLimit {
+    /// A [`RateLimit`] without any limit.
+    pub const UNLIMITED: Self = Self {
+        packets_per_sec: None,
+        bytes_per_sec: None,
+        burst: Duration::from_millis(100),
+    };
+
+    /// Returns whether this limit limits anything.
+    #[inline]
+    pub fn is_unlimited(&self) -> bool {
+        self.packets_per_sec.is_none() && self.bytes_per_sec.is_none()
+    }
+}
+
+impl Default for RateLimit {
+    /// Creates a [`RateLimit`] without any limit, with a burst of 100 milliseconds once a rate is set.
+    fn default() -> Self {
+        Self::UNLIMITED
+    }
+}
+
+/// The token buckets enforcing a [`RateLimit`], for one direction of a UDP association.
+///
+/// The limiter is meant to be owned by the task relaying the direction, so it keeps no lock nor atomic, and takes the current time from the caller so it can be read once per batch. [`run_relay()`](super::run_relay) keeps one per direction, see [`UdpRelayConfig::uplink_limit`](super::UdpRelayConfig::uplink_limit).
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    packets: Option<Bucket>,
+    bytes: Option<Bucket>,
+    last: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a new [`RateLimiter`] enforcing `limit`, with full buckets.
+    pub fn new(limit: &RateLimit) -> Self {
+        Self {
+            packets: limit
+                .packets_per_sec
+                .map(|rate| Bucket::new(rate, limit.burst)),
+            bytes: limit
+                .bytes_per_sec
+                .map(|rate| Bucket::new(rate, limit.burst)),
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes the tokens for a datagram with `len` bytes of payload at `now`, returning whether it is within the limit. A datagram over the limit takes no token.
+    pub fn try_acquire(&mut self, len: usize, now: Instant) -> bool {
+        if self.packets.is_none() && self.bytes.is_none() {
+            return true;
+        }
+
+        let elapsed = now.saturating_duration_since(self.last);
+        self.last = self.last.max(now);
+
+        for bucket in self.packets.iter_mut().chain(&mut self.bytes) {
+            bucket.refill(elapsed);
+        }
+
+        let packets_ok = self.packets.as_ref().is_none_or(|bucket| bucket.has(1));
+        let bytes_ok = self
+            .bytes
+            .as_ref()
+            .is_none_or(|bucket| bucket.has(len as u64));
+
+        if !(packets_ok && bytes_ok) {
+            return false;
+        }
+
+        if let Some(bucket) = &mut self.packets {
+            bucket.take(1);
+        }
+
+        if let Some(bucket) = &mut self.bytes {
+            bucket.take(len as u64);
+        }
+
+        true
+    }
+}
+
+/// A token bucket counting in token-nanoseconds, so refilling is exact integer arithmetic
+#[derive(Clone, Debug)]
+struct Bucket {
+    rate: u128,
+    capacity: u128,
+    credit: u128,
+}
+
+impl Bucket {
+    const NANOS_PER_TOKEN: u128 = 1_000_000_000;
+
+    fn new(rate: u64, burst: Duration) -> Self {
+        let capacity = rate as u128 * burst.as_nanos();
+
+        Self {
+            rate: rate as u128,
+            capacity,
+            credit: capacity,
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.credit = self
+            .credit
+            .saturating_add(self.rate.saturating_mul(elapsed.as_nanos()))
+            .min(self.capacity);
+    }
+
+    fn has(&self, tokens: u64) -> bool {
+        self.credit >= tokens as u128 * Self::NANOS_PER_TOKEN
+    }
+
+    fn take(&mut self, tokens: u64) {
+        self.credit -= tokens as u128 * Self::NANOS_PER_TOKEN;
+    }
+}
@@ -0,0 +1,1058 @@
+//! A batteries-included UDP relay for the `Associate` command
+
+use super::{
+    addr::canonical,
+    batch::{self, Datagram, BATCH_SIZE},
+    sockopt, state, Associate, AssociatedUdpSocket, BufferPool, ClientMatch, ControlKeepalive,
+    DropReason, FragmentPolicy, PooledBuf, PooledUdpSocket, RateLimit, RateLimiter, RelayEvent,
+    RelayObserver, UdpAssociationTable, UdpRelayStats, UdpRelayStatsSnapshot, UdpSocketOptions,
+    UdpSocketPool, WaitClose,
+};
+use crate::{
+    dns::{self, Resolver},
+    quota::{Budget, Quota},
+};
+use bytes::Bytes;
+use socks5_proto::{Address, Reply, UdpHeader};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::{self, Future},
+    io::{Error, ErrorKind},
+    iter,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    ops::Deref,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::UdpSocket,
+    time::{self, Instant},
+};
+
+/// Configuration of [`run_relay()`]
+#[derive(Clone, Debug)]
+pub struct UdpRelayConfig {
+    /// The IP address to bind the client-facing UDP socket on. If `None`, the local IP address of the TCP connection is used, which is usually the one the client can route to.
+    pub bind_ip: Option<IpAddr>,
+
+    /// The maximum UDP packet size, with SOCKS5 UDP header included.
+    pub max_pkt_size: usize,
+
+    /// The association is torn down if no datagram is relayed in either direction for this duration. `None` disables the idle expiry.
+    pub idle_timeout: Option<Duration>,
+
+    /// The TCP keepalive enabled on the control connection once the association is set up, so a client vanishing without closing it is detected even while datagrams to it keep the association from idling out. If `None`, which is the default, a vanished client is only detected by [`UdpRelayConfig::idle_timeout`].
+    pub control_keepalive: Option<ControlKeepalive>,
+
+    /// How the source address of datagrams is matched against the learned client endpoint. [`ClientMatch::Rebind`] keeps the association working across NAT rebinding of the client.
+    pub client_match: ClientMatch,
+
+    /// Which destinations the client is allowed to send datagrams to.
+    pub destination_policy: DestinationPolicy,
+
+    /// Whether the client may send datagrams to multicast groups, e.g. for SSDP or mDNS discovery through the proxy. Datagrams to multicast destinations are dropped as [`DropReason::Denied`] otherwise, before [`UdpRelayConfig::destination_policy`] is checked.
+    ///
+    /// This is off by default, as a single datagram to a group can make every member on the network of the proxy answer, which amplifies the traffic of the client. Replies of members come from their own unicast addresses, and are matched to the group the client sent to, see [`UdpRelayConfig::restrict_return`]. The outbound interface of multicast datagrams can be set in [`UdpRelayConfig::outbound_options`].
+    pub allow_multicast: bool,
+
+    /// The maximum number of destinations the client can talk to at the same time. Datagrams to new destinations over it are dropped as [`DropReason::Denied`] until a destination expires. If `None`, the 1024 most recently used destinations are tracked.
+    ///
+    /// Destinations are tracked in a [`UdpAssociationTable`] of the association.
+    pub max_destinations: Option<usize>,
+
+    /// A destination stops being tracked once no datagram is relayed to or from it for this duration.
+    pub destination_timeout: Duration,
+
+    /// Whether to only relay datagrams coming back from tracked destinations, i.e. address and port dependent filtering in terms of RFC 4787. Others are dropped as [`DropReason::Filtered`].
+    ///
+    /// If `false`, datagrams from any source are relayed to the client (endpoint independent filtering), which peer-to-peer applications behind the proxy may rely on.
+    ///
+    /// A multicast group the client sent to is tracked like any destination, and while it is, datagrams from any source of its address family are relayed as replies of group members.
+    pub restrict_return: bool,
+
+    /// How the address in the SOCKS5 UDP header of datagrams sent back to the client is built.
+    pub return_address: ReturnAddress,
+
+    /// IP-level options of the outbound sockets sending datagrams to destinations, e.g. a DSCP class for relayed traffic. The client-facing socket is left untouched.
+    ///
+    /// Each relay is started with its own configuration, so this can be overridden per association on a clone of a shared configuration.
+    ///
+    /// These are not applied to sockets checked out of [`UdpRelayConfig::outbound_pool`], which keep the options of the pool.
+    pub outbound_options: UdpSocketOptions,
+
+    /// The pool the outbound socket of the address family of its IP address is checked out of, instead of binding a fresh one for each association. The outbound socket of the other address family is still bound by the relay. If `None`, both are bound by the relay.
+    ///
+    /// Share it across associations, e.g. by cloning this configuration. Starting a relay waits for a socket of the pool if all of them are checked out.
+    pub outbound_pool: Option<UdpSocketPool>,
+
+    /// How fragmented datagrams from the client are handled.
+    pub fragment_policy: FragmentPolicy,
+
+    /// How the relay recovers from errors of its outbound sockets, e.g. after an interface flap. If `None`, an error receiving from an outbound socket tears the association down, and an error sending drops the datagram.
+    pub rebind: Option<RebindPolicy>,
+
+    /// The rate limit of datagrams forwarded from the client to destinations. Datagrams over it are dropped as [`DropReason::RateLimited`].
+    pub uplink_limit: RateLimit,
+
+    /// The rate limit of datagrams sent back to the client. Datagrams over it are dropped as [`DropReason::RateLimited`].
+    pub downlink_limit: RateLimit,
+
+    /// The resolver for domain destinations. Share it across associations to share its cache.
+    pub resolver: Arc<dyn Resolver + Send + Sync>,
+
+    /// The pool of receive buffers. Share it across associations, e.g. by cloning this configuration, so buffers are reused between them. Its slab size should be at least [`UdpRelayConfig::max_pkt_size`].
+    pub buffer_pool: BufferPool,
+
+    /// The statistics handle of the association. Keep a clone of it to read the counters while the relay is running. If `None`, a new handle is created for each relay.
+    ///
+    /// Note that a handle set here is shared by all relays started with clones of this configuration.
+    pub stats: Option<UdpRelayStats>,
+
+    /// The observer the events of the association are reported to, e.g. to log why the traffic of a client does not go through. If `None`, no event is reported.
+    ///
+    /// Events carry no identifier of the association besides the addresses in [`RelayEvent::Started`], so set an observer capturing its own context, e.g. the authenticated user, on a clone of a shared configuration to tell associations apart.
+    pub observer: Option<Arc<dyn RelayObserver + Send + Sync>>,
+
+    /// The quota of the user of the association, see [`QuotaEnforcer::quota()`](crate::quota::QuotaEnforcer::quota). The payload of datagrams relayed in both directions is counted against it, and the relay finishes with [`RelayClose::QuotaExhausted`] once it is exhausted. If `None`, the association is not limited.
+    ///
+    /// Set it on a clone of a shared configuration, as each association has its own user.
+    pub quota: Option<Quota>,
+}
+
+impl Default for UdpRelayConfig {
+    fn default() -> Self {
+        Self {
+            bind_ip: None,
+            max_pkt_size: 1500,
+            idle_timeout: Some(Duration::from_secs(300)),
+            control_keepalive: None,
+            client_match: ClientMatch::Strict,
+            destination_policy: DestinationPolicy::AllowAll,
+            allow_multicast: false,
+            max_destinations: None,
+            destination_timeout: Duration::from_secs(300),
+            restrict_return: false,
+            return_address: ReturnAddress::Source,
+            outbound_options: UdpSocketOptions::default(),
+            outbound_pool: None,
+            fragment_policy: FragmentPolicy::Drop,
+            rebind: None,
+            uplink_limit: RateLimit::UNLIMITED,
+            downlink_limit: RateLimit::UNLIMITED,
+            resolver: dns::shared_resolver(),
+            buffer_pool: BufferPool::default(),
+            stats: None,
+            observer: None,
+            quota: None,
+        }
+    }
+}
+
+/// Policy deciding which destinations a relayed datagram may be sent to.
+///
+/// The policy is checked against the resolved destination address, so domain destinations can not be used to bypass it.
+#[derive(Clone, Default)]
+pub enum DestinationPolicy {
+    /// Allow all destinations.
+    #[default]
+    AllowAll,
+
+    /// Only allow destinations for which the function returns `true`.
+    Filter(Arc<dyn Fn(&SocketAddr) -> bool + Send + Sync>),
+}
+
+impl DestinationPolicy {
+    /// Returns whether the destination is allowed by this policy.
+    #[inline]
+    pub fn allows(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::AllowAll => true,
+            Self::Filter(f) => f(addr),
+        }
+    }
+}
+
+impl Debug for DestinationPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::AllowAll => f.write_str("AllowAll"),
+            Self::Filter(_) => f.write_str("Filter"),
+        }
+    }
+}
+
+/// How [`run_relay()`] recovers from errors of its outbound sockets by rebinding them.
+///
+/// On some VPN or interface flap events, a socket keeps failing with e.g. [`ErrorKind::NetworkDown`] or [`ErrorKind::AddrNotAvailable`] even after the interface is back. On one of [`RebindPolicy::errors`], the relay replaces the failing outbound socket with a freshly bound one, with the same options, and keeps forwarding. Rebinding in a row is retried with an exponential backoff, during which the relay is paused, up to [`RebindPolicy::max_attempts`] times until a datagram goes through the socket again.
+///
+/// The fresh socket is bound on a new ephemeral port, so destinations see datagrams of the association coming from a new port, just like after a NAT rebinding. The client is not affected, as the client-facing socket is kept. Errors of the client-facing socket still tear the association down, as the client could not be told about a new port.
+///
+/// Rebinds are counted in [`UdpRelayStats`] and reported as [`RelayEvent::Rebound`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RebindPolicy {
+    /// The errors an outbound socket is rebound on. Other errors are handled as without a policy.
+    pub errors: Vec<ErrorKind>,
+
+    /// The maximum number of rebinds in a row, without any datagram going through the socket in between.
+    pub max_attempts: u32,
+
+    /// The delay before the second rebind in a row, doubled for each following one.
+    pub backoff: Duration,
+
+    /// The maximum delay between rebinds in a row.
+    pub max_backoff: Duration,
+
+    /// Whether to tear the association down once [`RebindPolicy::max_attempts`] is reached. Otherwise, the association is kept without the outbound socket, and destinations of its address family are reached through the other one if possible, e.g. IPv4 destinations through a dual-stack IPv6 socket.
+    pub strict: bool,
+}
+
+impl RebindPolicy {
+    /// Returns the delay before the rebind following `rebinds` rebinds in a row.
+    fn backoff(&self, rebinds: u32) -> Duration {
+        match rebinds {
+            0 => Duration::ZERO,
+            n => self
+                .backoff
+                .saturating_mul(1 << (n - 1).min(31))
+                .min(self.max_backoff),
+        }
+    }
+}
+
+impl Default for RebindPolicy {
+    /// Creates a [`RebindPolicy`] rebinding on [`ErrorKind::NetworkDown`], [`ErrorKind::NetworkUnreachable`] and [`ErrorKind::AddrNotAvailable`] up to 5 times in a row, with a backoff from 100 milliseconds to 2 seconds, keeping the association once exhausted.
+    fn default() -> Self {
+        Self {
+            errors: vec![
+                ErrorKind::NetworkDown,
+                ErrorKind::NetworkUnreachable,
+                ErrorKind::AddrNotAvailable,
+            ],
+            max_attempts: 5,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            strict: false,
+        }
+    }
+}
+
+/// How [`run_relay()`] builds the address in the SOCKS5 UDP header of a datagram sent back to the client, i.e. the origin of the reply as the client sees it.
+///
+/// [`ReturnAddress::Source`] is what RFC 1928 describes and what SOCKS5 clients generally expect, as most of them resolve domain names themselves or match replies against IP addresses, so it maximizes compatibility and is the default. [`ReturnAddress::Requested`] suits clients that only know the domain names they sent to, and match replies against them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ReturnAddress {
+    /// The source address of the reply, with v4-mapped IPv6 addresses converted to the IPv4 address they map, so IPv4-only clients never see an IPv6 address they did not send to.
+    #[default]
+    Source,
+
+    /// The address the client sent to the source of the reply, e.g. the domain name, echoed as requested.
+    ///
+    /// The requested address is kept in the destination table of the association. Replies from destinations that are not tracked, e.g. if [`UdpRelayConfig::restrict_return`] is not set, fall back to [`ReturnAddress::Source`]. If the client sent to the same destination through several addresses, e.g. two domain names resolving to the same IP address, the most recently used one is echoed.
+    Requested,
+}
+
+/// The reason why a relay started by [`run_relay()`] finished.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RelayClose {
+    /// The client closed the TCP connection used to send the associate command.
+    ClientClosed,
+
+    /// No datagram was relayed within the configured idle timeout.
+    IdleTimeout,
+
+    /// The shutdown future given to [`run_relay_until()`] completed.
+    Shutdown,
+
+    /// The quota of the user of the association was exhausted, see [`UdpRelayConfig::quota`].
+    QuotaExhausted,
+}
+
+/// The record of a relay finished by [`run_relay()`]
+#[derive(Clone, Copy, Debug)]
+pub struct RelaySession {
+    /// The reason why the relay finished
+    pub close: RelayClose,
+
+    /// The final statistics of the association
+    pub stats: UdpRelayStatsSnapshot,
+}
+
+/// The result of [`run_relay()`]
+pub type RelayResult = Result<RelaySession, Error>;
+
+/// Runs a complete UDP relay for an `Associate` command.
+///
+/// This function:
+///
+/// - binds an outbound UDP socket for each of IPv4 and IPv6, or checks one out of [`UdpRelayConfig::outbound_pool`], then binds a client-facing UDP socket and replies with its address using [`Associate::reply_with_socket()`]. If binding fails, [`Reply::GeneralFailure`] is replied instead
+/// - enforces the UDP endpoint the client declared in the associate command, or learns it from the first datagram coming from the IP address of the TCP connection if the client declared none (or an address behind NAT), and drops datagrams from any other source, matching them according to [`UdpRelayConfig::client_match`]. See [`Associate::reply_with_socket()`]
+/// - forwards the payload of datagrams from the client to their destinations, resolving domain names with [`UdpRelayConfig::resolver`] if needed
+/// - tracks the destinations of the client, capped by [`UdpRelayConfig::max_destinations`], and sends datagrams coming back from destinations to the client with the SOCKS5 UDP header added, built according to [`UdpRelayConfig::return_address`], only from tracked ones if [`UdpRelayConfig::restrict_return`] is set
+/// - tears everything down when the client closes the TCP connection or the association idles out, detecting clients that vanished without closing it with [`UdpRelayConfig::control_keepalive`]
+///
+/// Fragmented datagrams are dropped or reassembled according to [`UdpRelayConfig::fragment_policy`].
+///
+/// Outbound sockets failing with transient errors are rebound according to [`UdpRelayConfig::rebind`].
+///
+/// Destinations are sent to through the outbound socket of their address family, so an IPv4 client can reach IPv6 destinations and vice versa. v4-mapped IPv6 destinations are treated as IPv4 ones, and the SOCKS5 UDP header of replies from them carries the plain IPv4 address.
+///
+/// Datagrams to multicast groups are only relayed if [`UdpRelayConfig::allow_multicast`] is set.
+///
+/// Datagrams that can not be parsed, resolved, or are denied by [`UdpRelayConfig::destination_policy`] are dropped without affecting the association, the same way [`AssociatedUdpSocket::recv_from_valid()`] skips them. So are receive errors of kind [`ErrorKind::ConnectionReset`], which Windows reports after a datagram hit a closed port, counted in [`UdpRelayStatsSnapshot::connection_resets`].
+///
+/// Each direction is rate limited according to [`UdpRelayConfig::uplink_limit`] and [`UdpRelayConfig::downlink_limit`], dropping datagrams over the limits. The limits are checked against the same clock as the idle expiry, once per batch.
+///
+/// If [`UdpRelayConfig::quota`] is set, the payload relayed is reserved from its provider in chunks, the first one before the first datagram is relayed. A datagram over the quota is dropped as [`DropReason::Quota`], with the rest of its batch, and the relay finishes with [`RelayClose::QuotaExhausted`], after giving the part of the last chunk not relayed back.
+///
+/// Datagrams ready at the same time are received and sent in batches, using `recvmmsg` / `sendmmsg` on Linux.
+///
+/// Forwarded and dropped datagrams are recorded in [`UdpRelayConfig::stats`], and a final snapshot of them is returned in the [`RelaySession`]. The start, the learned client endpoint, new destinations, drops and the end of the association are reported to [`UdpRelayConfig::observer`] as [`RelayEvent`]s.
+///
+/// The TCP connection is shut down before returning.
+pub async fn run_relay(
+    associate: Associate<state::NeedReply>,
+    config: UdpRelayConfig,
+) -> RelayResult {
+    run_relay_until(associate, config, future::pending()).await
+}
+
+/// Runs a complete UDP relay for an `Associate` command, like [`run_relay()`], but also tears it down when `shutdown` completes.
+///
+/// This is useful to stop relaying on e.g. a server shutdown signal. The relay then finishes with [`RelayClose::Shutdown`].
+pub async fn run_relay_until<F>(
+    associate: Associate<state::NeedReply>,
+    config: UdpRelayConfig,
+    shutdown: F,
+) -> RelayResult
+where
+    F: Future<Output = ()>,
+{
+    let mut outbound = match Outbound::bind(&config).await {
+        Ok(outbound) => outbound,
+        Err(err) => {
+            notify(&config, RelayEvent::Failed(&err));
+
+            let mut associate = associate
+                .reply(Reply::GeneralFailure, Address::unspecified())
+                .await?;
+            let _ = associate.close().await;
+            return Err(err);
+        }
+    };
+
+    let (mut associate, mut client) = match associate
+        .reply_with_socket(config.bind_ip, config.max_pkt_size)
+        .await
+    {
+        Ok(res) => res,
+        Err(failed) => {
+            let err = failed.into();
+            notify(&config, RelayEvent::Failed(&err));
+            return Err(err);
+        }
+    };
+
+    if let Some(keepalive) = &config.control_keepalive {
+        if let Err(err) = associate.set_keepalive(keepalive) {
+            notify(&config, RelayEvent::Failed(&err));
+            let _ = associate.close().await;
+            return Err(err);
+        }
+    }
+
+    let stats = config.stats.clone().unwrap_or_default();
+    client.set_stats(stats.clone());
+
+    if let Some(observer) = config.observer.clone() {
+        client.on_drop(move |reason| observer.on_event(&RelayEvent::Dropped(reason)));
+    }
+
+    if let (Ok(peer), Ok(relay)) = (associate.peer_addr(), client.local_addr()) {
+        notify(
+            &config,
+            RelayEvent::Started {
+                peer,
+                declared: associate.client_declared_addr(),
+                relay,
+            },
+        );
+    }
+
+    let mut budget = Budget::new(config.quota.as_ref());
+
+    let res = relay(
+        &mut associate,
+        &client,
+        &mut outbound,
+        &config,
+        &stats,
+        &mut budget,
+        shutdown,
+    )
+    .await;
+    budget.release().await;
+    let _ = associate.close().await;
+
+    match &res {
+        Ok(close) => notify(&config, RelayEvent::Closed(*close)),
+        Err(err) => notify(&config, RelayEvent::Failed(err)),
+    }
+
+    res.map(|close| RelaySession {
+        close,
+        stats: stats.snapshot(),
+    })
+}
+
+async fn relay<F>(
+    associate: &mut Associate<state::Ready>,
+    client: &AssociatedUdpSocket,
+    outbound: &mut Outbound,
+    config: &UdpRelayConfig,
+    stats: &UdpRelayStats,
+    budget: &mut Budget<'_>,
+    shutdown: F,
+) -> Result<RelayClose, Error>
+where
+    F: Future<Output = ()>,
+{
+    associate.expect_declared_client(client, config.client_match)?;
+
+    let mut client_addr = None;
+    let mut destinations = Destinations::new(config);
+    let mut reassembler = config.fragment_policy.reassembler();
+    client.allow_fragments(reassembler.is_some());
+
+    let mut uplink_limiter = RateLimiter::new(&config.uplink_limit);
+    let mut downlink_limiter = RateLimiter::new(&config.downlink_limit);
+
+    // without an idle timeout, the sleep is never polled, so no timer is registered
+    let idle = time::sleep(config.idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            res = associate.wait_close_or(shutdown.as_mut()) => match res? {
+                WaitClose::Closed => return Ok(RelayClose::ClientClosed),
+                WaitClose::Until(()) => return Ok(RelayClose::Shutdown),
+            },
+            res = client.get_ref().readable() => {
+                res?;
+
+                let mut bufs = take_bufs(&config.buffer_pool);
+                let pkts = match client.try_recv_batch(&mut bufs) {
+                    Ok(pkts) => pkts,
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                    Err(err) if sockopt::is_connection_reset(&err) => {
+                        stats.record_connection_reset();
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+
+                let mut forward = [Vec::new(), Vec::new()];
+                let now = Instant::now();
+
+                let mut pkts = pkts.into_iter().zip(bufs);
+
+                for ((header, range, src), buf) in pkts.by_ref() {
+                    let pkt = buf.freeze().slice(range);
+
+                    if client_addr != Some(src) {
+                        client_addr = Some(src);
+                        notify(config, RelayEvent::ClientLearned(src));
+                    }
+
+                    let pkt = match reassembler.as_mut() {
+                        Some(reassembler) => {
+                            let dropped = reassembler.dropped_count();
+                            let pkt = reassembler.push(src, &header, pkt);
+                            let dropped = reassembler.dropped_count() - dropped;
+                            record_drops(config, stats, DropReason::Fragment, dropped);
+
+                            match pkt {
+                                Some(pkt) => pkt,
+                                None => continue,
+                            }
+                        }
+                        None => pkt,
+                    };
+
+                    let Ok(dst) = dns::resolve_address(&*config.resolver, &header.address).await
+                    else {
+                        record_drop(config, stats, DropReason::Unresolved);
+                        continue;
+                    };
+
+                    // checked after unmapping, so a v4-mapped IPv6 address can not bypass a policy on the IPv4 address
+                    let dst = canonical(dst);
+
+                    let requested = match config.return_address {
+                        ReturnAddress::Source => None,
+                        ReturnAddress::Requested => Some(header.address),
+                    };
+
+                    if (dst.ip().is_multicast() && !config.allow_multicast)
+                        || !config.destination_policy.allows(&dst)
+                    {
+                        record_drop(config, stats, DropReason::Denied);
+                        continue;
+                    }
+
+                    // limited before the destination is tracked, so datagrams over the limit do not take up the table
+                    if !uplink_limiter.try_acquire(pkt.len(), now) {
+                        record_drop(config, stats, DropReason::RateLimited);
+                        continue;
+                    }
+
+                    let new = config.observer.is_some() && !destinations.contains(src, dst);
+
+                    if !destinations.insert(src, dst, requested) {
+                        record_drop(config, stats, DropReason::Denied);
+                        continue;
+                    }
+
+                    if new {
+                        notify(config, RelayEvent::NewDestination(dst));
+                    }
+
+                    // what was let through before is still sent
+                    if !budget.take(pkt.len() as u64).await {
+                        record_drop(config, stats, DropReason::Quota);
+                        break;
+                    }
+
+                    match outbound.route(dst) {
+                        Some((family, dst)) => forward[family].push((pkt, dst)),
+                        None => record_drop(config, stats, DropReason::Unresolved),
+                    }
+                }
+
+                // the rest of a batch cut short by the quota is dropped as well
+                record_drops(config, stats, DropReason::Quota, pkts.len() as u64);
+
+                let mut sent = false;
+
+                for (family, pkts) in forward.iter().enumerate() {
+                    if !pkts.is_empty() {
+                        sent |= outbound.send_uplink(family, pkts, config, stats).await?;
+                    }
+                }
+
+                if sent {
+                    reset_idle(idle.as_mut(), config.idle_timeout);
+                }
+
+                if budget.is_exhausted() {
+                    return Ok(RelayClose::QuotaExhausted);
+                }
+            }
+            res = readable(outbound.sockets[V4].as_deref()) => {
+                let res = match res {
+                    Ok(socket) => {
+                        forward_downlink(
+                            socket,
+                            client,
+                            client_addr,
+                            &mut destinations,
+                            &mut downlink_limiter,
+                            budget,
+                            config,
+                            stats,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                if outbound.handle_downlink(V4, res, config, stats).await? {
+                    reset_idle(idle.as_mut(), config.idle_timeout);
+                }
+
+                if budget.is_exhausted() {
+                    return Ok(RelayClose::QuotaExhausted);
+                }
+            }
+            res = readable(outbound.sockets[V6].as_deref()) => {
+                let res = match res {
+                    Ok(socket) => {
+                        forward_downlink(
+                            socket,
+                            client,
+                            client_addr,
+                            &mut destinations,
+                            &mut downlink_limiter,
+                            budget,
+                            config,
+                            stats,
+                        )
+                        .await
+                    }
+                    Err(err) => Err(err),
+                };
+
+                if outbound.handle_downlink(V6, res, config, stats).await? {
+                    reset_idle(idle.as_mut(), config.idle_timeout);
+                }
+
+                if budget.is_exhausted() {
+                    return Ok(RelayClose::QuotaExhausted);
+                }
+            }
+            () = &mut idle, if config.idle_timeout.is_some() => return Ok(RelayClose::IdleTimeout),
+        }
+    }
+}
+
+/// The index of the IPv4 outbound socket in [`Outbound::sockets`]
+const V4: usize = 0;
+
+/// The index of the IPv6 outbound socket in [`Outbound::sockets`]
+const V6: usize = 1;
+
+/// The outbound sockets of a relay, one per address family, so destinations of either family can be reached whatever the address family of the client is.
+struct Outbound {
+    sockets: [Option<OutboundSocket>; 2],
+
+    /// The number of rebinds in a row of each socket, reset once a datagram goes through it
+    rebinds: [u32; 2],
+}
+
+impl Outbound {
+    /// The addresses the sockets are bound on, indexed like [`Outbound::sockets`]
+    const BIND_ADDRS: [SocketAddr; 2] = [
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0),
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 0),
+    ];
+
+    /// Binds an IPv4 and an IPv6 outbound socket with [`UdpRelayConfig::outbound_options`] applied, or checks the one of the address family of [`UdpRelayConfig::outbound_pool`] out of it. Only one of them is required to be bound, e.g. on hosts without IPv6, but failing to apply the options to a bound socket is an error.
+    async fn bind(config: &UdpRelayConfig) -> Result<Self, Error> {
+        let v4 = Self::bind_socket(V4, config).await;
+        let v6 = Self::bind_socket(V6, config).await;
+
+        for socket in [&v4, &v6].into_iter().flatten() {
+            if let OutboundSocket::Bound(socket) = socket {
+                config.outbound_options.apply(socket)?;
+            }
+        }
+
+        match (v4, v6) {
+            (Err(err), Err(_)) => Err(err),
+            (v4, v6) => Ok(Self {
+                sockets: [v4.ok(), v6.ok()],
+                rebinds: [0; 2],
+            }),
+        }
+    }
+
+    /// Binds the socket of `family`, or checks it out of [`UdpRelayConfig::outbound_pool`] if the pool is of `family`. Options are left to the caller.
+    async fn bind_socket(family: usize, config: &UdpRelayConfig) -> Result<OutboundSocket, Error> {
+        match &config.outbound_pool {
+            Some(pool) if Self::family(pool.bind_ip()) == family => {
+                pool.checkout().await.map(OutboundSocket::Pooled)
+            }
+            _ => {
+                let socket = UdpSocket::bind(Self::BIND_ADDRS[family]).await?;
+                sockopt::suppress_connection_reset(&socket)?;
+                Ok(OutboundSocket::Bound(socket))
+            }
+        }
+    }
+
+    #[inline]
+    fn family(ip: IpAddr) -> usize {
+        if ip.is_ipv4() {
+            V4
+        } else {
+            V6
+        }
+    }
+
+    /// Drops the socket of `family`, not giving it back to the pool it was checked out of as it failed.
+    fn discard(&mut self, family: usize) {
+        if let Some(OutboundSocket::Pooled(socket)) = self.sockets[family].take() {
+            socket.discard();
+        }
+    }
+
+    /// Handles the result of forwarding a batch received on the socket of `family`, recovering from its error. Returns whether any packet was sent to the client.
+    async fn handle_downlink(
+        &mut self,
+        family: usize,
+        res: Result<bool, Error>,
+        config: &UdpRelayConfig,
+        stats: &UdpRelayStats,
+    ) -> Result<bool, Error> {
+        match res {
+            Ok(sent) => {
+                self.rebinds[family] = 0;
+                Ok(sent)
+            }
+            Err(err) => self
+                .recover(family, err, config, stats)
+                .await
+                .map(|()| false),
+        }
+    }
+
+    /// Sends all payloads to their destinations through the socket of `family` in batches, dropping the ones failing to be sent. Returns whether any payload was sent.
+    ///
+    /// On an error to rebind the socket on, the failing payload is dropped and the rest is sent through the fresh socket.
+    async fn send_uplink(
+        &mut self,
+        family: usize,
+        pkts: &[(Bytes, SocketAddr)],
+        config: &UdpRelayConfig,
+        stats: &UdpRelayStats,
+    ) -> Result<bool, Error> {
+        let dgrams = pkts
+            .iter()
+            .map(|(pkt, addr)| Datagram {
+                header: &[],
+                payload: pkt,
+                addr: *addr,
+            })
+            .collect::<Vec<_>>();
+
+        let mut pending = &dgrams[..];
+        let mut any_sent = false;
+
+        while !pending.is_empty() {
+            // the socket is gone if rebinding it was given up
+            let Some(socket) = self.sockets[family].as_ref() else {
+                record_drops(config, stats, DropReason::Unresolved, pending.len() as u64);
+                break;
+            };
+
+            match batch::send_batch(socket, pending).await {
+                Err(err) if is_rebind_error(config, &err) => {
+                    record_drop(config, stats, DropReason::Unresolved);
+                    pending = &pending[1..];
+                    self.recover(family, err, config, stats).await?;
+                }
+                Ok(0) | Err(_) => {
+                    record_drop(config, stats, DropReason::Unresolved);
+                    pending = &pending[1..];
+                }
+                Ok(sent) => {
+                    for dgram in &pending[..sent] {
+                        stats.record_uplink(dgram.payload.len());
+                    }
+
+                    self.rebinds[family] = 0;
+                    any_sent = true;
+                    pending = &pending[sent..];
+                }
+            }
+        }
+
+        Ok(any_sent)
+    }
+
+    /// Recovers from `err` of the socket of `family` according to [`UdpRelayConfig::rebind`], returning `err` back if it is not to be recovered from.
+    async fn recover(
+        &mut self,
+        family: usize,
+        err: Error,
+        config: &UdpRelayConfig,
+        stats: &UdpRelayStats,
+    ) -> Result<(), Error> {
+        let Some(policy) = config
+            .rebind
+            .as_ref()
+            .filter(|_| is_rebind_error(config, &err))
+        else {
+            return Err(err);
+        };
+
+        let mut err = err;
+
+        while self.rebinds[family] < policy.max_attempts {
+            time::sleep(policy.backoff(self.rebinds[family])).await;
+            self.rebinds[family] += 1;
+
+            match self.rebind(family, config).await {
+                Ok(addr) => {
+                    stats.record_socket_rebind();
+                    notify(config, RelayEvent::Rebound(addr));
+                    return Ok(());
+                }
+                Err(bind_err) => err = bind_err,
+            }
+        }
+
+        if policy.strict {
+            return Err(err);
+        }
+
+        self.discard(family);
+        Ok(())
+    }
+
+    /// Replaces the socket of `family` with a freshly bound one, or one checked out of the pool it was checked out of, returning its local address.
+    async fn rebind(
+        &mut self,
+        family: usize,
+        config: &UdpRelayConfig,
+    ) -> Result<SocketAddr, Error> {
+        self.discard(family);
+
+        let socket = Self::bind_socket(family, config).await?;
+
+        if let OutboundSocket::Bound(socket) = &socket {
+            config.outbound_options.apply(socket)?;
+        }
+
+        let addr = socket.local_addr()?;
+        self.sockets[family] = Some(socket);
+        Ok(addr)
+    }
+
+    /// Returns the index of the socket to send to `dst` through, and the address to send to.
+    ///
+    /// If no IPv4 socket could be bound, IPv4 destinations are sent to through the IPv6 socket as v4-mapped addresses, which works if the socket is dual-stack (the default on Linux).
+    fn route(&self, dst: SocketAddr) -> Option<(usize, SocketAddr)> {
+        match dst {
+            SocketAddr::V4(_) if self.sockets[V4].is_some() => Some((V4, dst)),
+            SocketAddr::V4(addr) if self.sockets[V6].is_some() => Some((
+                V6,
+                SocketAddr::new(IpAddr::V6(addr.ip().to_ipv6_mapped()), addr.port()),
+            )),
+            SocketAddr::V6(_) if self.sockets[V6].is_some() => Some((V6, dst)),
+            _ => None,
+        }
+    }
+}
+
+/// An outbound socket of a relay, bound by the relay itself or checked out of [`UdpRelayConfig::outbound_pool`]
+enum OutboundSocket {
+    Bound(UdpSocket),
+    Pooled(PooledUdpSocket),
+}
+
+impl Deref for OutboundSocket {
+    type Target = UdpSocket;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Bound(socket) => socket,
+            Self::Pooled(socket) => socket,
+        }
+    }
+}
+
+/// The destinations the client of a relay talks to.
+struct Destinations {
+    /// The tracked destinations, with the address the client requested each of them with if [`ReturnAddress::Requested`] is used
+    table: UdpAssociationTable<Option<Address>>,
+
+    /// The multicast groups the client sent to, which may have expired from the table since
+    groups: Vec<SocketAddr>,
+}
+
+impl Destinations {
+    fn new(config: &UdpRelayConfig) -> Self {
+        Self {
+            table: UdpAssociationTable::with_shards(
+                config.max_destinations.unwrap_or(1024),
+                config.destination_timeout,
+                config.max_destinations,
+                1,
+            ),
+            groups: Vec::new(),
+        }
+    }
+
+    #[inline]
+    fn contains(&self, client: SocketAddr, dst: SocketAddr) -> bool {
+        self.table.contains(client, dst)
+    }
+
+    /// Tracks `dst` as a destination of `client`, returning `false` if the client has too many destinations.
+    fn insert(&mut self, client: SocketAddr, dst: SocketAddr, requested: Option<Address>) -> bool {
+        if !self.table.insert(client, dst, requested) {
+            return false;
+        }
+
+        if dst.ip().is_multicast() && !self.groups.contains(&dst) {
+            self.groups.push(dst);
+        }
+
+        true
+    }
+
+    /// Looks up the destination a datagram from `src` comes back from, returning the address the client requested it with, if any, or `None` if it is not tracked.
+    ///
+    /// A datagram from an untracked source of the address family of a tracked multicast group is taken as a reply of a group member, with no requested address, as the client did not send to the member itself.
+    fn reply(&mut self, client: SocketAddr, src: SocketAddr) -> Option<Option<Address>> {
+        if let Some(requested) = self.table.get(client, src) {
+            return Some(requested);
+        }
+
+        let mut member = false;
+
+        self.groups.retain(|group| {
+            if member || group.is_ipv4() != src.is_ipv4() {
+                return true;
+            }
+
+            member = self.table.get(client, *group).is_some();
+            member
+        });
+
+        member.then_some(None)
+    }
+}
+
+/// Waits for `socket` to be readable, or forever if there is no socket.
+async fn readable(socket: Option<&UdpSocket>) -> Result<&UdpSocket, Error> {
+    match socket {
+        Some(socket) => socket.readable().await.map(|()| socket),
+        None => future::pending().await,
+    }
+}
+
+/// Receives a batch of datagrams from destinations on `outbound` and sends them to the client. Returns whether any packet was sent.
+#[allow(clippy::too_many_arguments)]
+async fn forward_downlink(
+    outbound: &UdpSocket,
+    client: &AssociatedUdpSocket,
+    client_addr: Option<SocketAddr>,
+    destinations: &mut Destinations,
+    limiter: &mut RateLimiter,
+    budget: &mut Budget<'_>,
+    config: &UdpRelayConfig,
+    stats: &UdpRelayStats,
+) -> Result<bool, Error> {
+    let mut bufs = take_bufs(&config.buffer_pool);
+    let received = match batch::try_recv_batch(outbound, &mut bufs, config.max_pkt_size) {
+        Ok(received) => received,
+        Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+        Err(err) if sockopt::is_connection_reset(&err) => {
+            stats.record_connection_reset();
+            return Ok(false);
+        }
+        Err(err) => return Err(err),
+    };
+
+    // nothing can be routed back before the client endpoint is known
+    let Some(client_addr) = client_addr else {
+        return Ok(false);
+    };
+
+    let now = Instant::now();
+    let pkts = received
+        .into_iter()
+        .filter_map(|received| {
+            if received.is_oversize(config.max_pkt_size) {
+                record_drop(config, stats, DropReason::Oversize);
+                return None;
+            }
+
+            let src = canonical(received.addr);
+
+            // looked up even if not restricting, so destinations the client only hears from stay tracked
+            let requested = destinations.reply(client_addr, src);
+
+            if config.restrict_return && requested.is_none() {
+                record_drop(config, stats, DropReason::Filtered);
+                return None;
+            }
+
+            if !limiter.try_acquire(received.range.len(), now) {
+                record_drop(config, stats, DropReason::RateLimited);
+                return None;
+            }
+
+            // replies to v4-mapped destinations carry the IPv4 address the client sent to
+            let addr = requested.flatten().unwrap_or(Address::SocketAddress(src));
+            let header = UdpHeader::new(0, addr);
+            Some((&bufs[received.idx][received.range], header, client_addr))
+        })
+        .collect::<Vec<_>>();
+
+    // the datagrams within the quota are still sent
+    let mut allowed = 0;
+
+    while allowed < pkts.len() && budget.take(pkts[allowed].0.len() as u64).await {
+        allowed += 1;
+    }
+
+    record_drops(
+        config,
+        stats,
+        DropReason::Quota,
+        (pkts.len() - allowed) as u64,
+    );
+
+    Ok(send_downlink(client, &pkts[..allowed]).await)
+}
+
+/// Takes buffers for receiving a batch from the pool. They go back to the pool as soon as the batch is handled, so idle associations hold no buffer.
+fn take_bufs(pool: &BufferPool) -> Vec<PooledBuf> {
+    iter::repeat_with(|| pool.get()).take(BATCH_SIZE).collect()
+}
+
+/// Returns whether `err` is one to rebind a socket on according to [`UdpRelayConfig::rebind`].
+fn is_rebind_error(config: &UdpRelayConfig, err: &Error) -> bool {
+    config
+        .rebind
+        .as_ref()
+        .is_some_and(|policy| policy.errors.contains(&err.kind()))
+}
+
+/// Sends all packets to the client in batches, dropping the ones failing to be sent. Returns whether any packet was sent.
+async fn send_downlink(
+    client: &AssociatedUdpSocket,
+    pkts: &[(&[u8], UdpHeader, SocketAddr)],
+) -> bool {
+    let mut pending = pkts;
+    let mut any_sent = false;
+
+    while !pending.is_empty() {
+        match client.send_batch(pending).await {
+            Ok(0) | Err(_) => pending = &pending[1..],
+            Ok(sent) => {
+                any_sent = true;
+                pending = &pending[sent..];
+            }
+        }
+    }
+
+    any_sent
+}
+
+/// Reports `event` to the observer of the relay, if any.
+pub(super) fn notify(config: &UdpRelayConfig, event: RelayEvent<'_>) {
+    if let Some(observer) = &config.observer {
+        observer.on_event(&event);
+    }
+}
+
+/// Records a datagram dropped by the relay in its statistics, and reports it to the observer.
+pub(super) fn record_drop(config: &UdpRelayConfig, stats: &UdpRelayStats, reason: DropReason) {
+    record_drops(config, stats, reason, 1);
+}
+
+/// Records `count` datagrams dropped by the relay in its statistics, and reports them to the observer.
+pub(super) fn record_drops(
+    config: &UdpRelayConfig,
+    stats: &UdpRelayStats,
+    reason: DropReason,
+    count: u64,
+) {
+    stats.record_drops(reason, count);
+
+    for _ in 0..count {
+        notify(config, RelayEvent::Dropped(reason));
+    }
+}
+
+fn reset_idle(idle: Pin<&mut time::Sleep>, timeout: Option<Duration>) {
+    if let Some(timeout) = timeout {
+        idle.reset(Instant::now() + timeout);
+    }
+}
@@ -0,0 +1,1145 @@
+#[cfg(all(feature = "gso", target_os = "linux"))]
+use super::offload::GsoState;
+use super::{
+    addr,
+    batch::{self, Datagram, Received},
+    sockopt, DatagramSocket, DropReason, PacketTooLarge, Truncated, UdpRelayStats,
+};
+use crate::dns::{self, Resolver};
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Address, Error as Socks5Error, UdpHeader};
+use std::{
+    borrow::BorrowMut,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::poll_fn,
+    io::{Error, ErrorKind, IoSlice},
+    mem,
+    net::{IpAddr, SocketAddr},
+    ops::Range,
+    slice,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{io::ReadBuf, net::UdpSocket, time::Instant};
+
+/// A wrapper of a tokio UDP socket dealing with SOCKS5 UDP header.
+///
+/// It only provides handful of methods to send / receive UDP packets with SOCKS5 UDP header. The underlying `UdpSocket` can be accessed with [`AssociatedUdpSocket::get_ref()`] and [`AssociatedUdpSocket::get_mut()`].
+///
+/// The transport defaults to tokio's [`UdpSocket`], and can be any [`DatagramSocket`], e.g. to tunnel the SOCKS5 UDP flow over a QUIC datagram channel. Batched IO and the relay engine are only available over [`UdpSocket`], and socket options over transports backed by one.
+///
+/// RFC 1928 requires the relay to only accept datagrams from the client address recorded at association time. Once an expected client is set with [`AssociatedUdpSocket::set_expected_client()`], [`AssociatedUdpSocket::recv_from()`] silently drops datagrams from any other source and counts them in [`AssociatedUdpSocket::filtered_count()`].
+///
+/// Clients often do not know their UDP endpoint when sending the associate command and declare `0.0.0.0:0` or a port of `0` instead. [`AssociatedUdpSocket::set_declared_client()`] handles this convention, learning the client endpoint from the first datagram coming from the IP address of the TCP connection.
+///
+/// Fragmented datagrams (with a `FRAG` field other than `0`) are dropped by default, as RFC 1928 requires of implementations that do not support fragmentation. See [`AssociatedUdpSocket::allow_fragments()`].
+///
+/// Packets larger than the maximum sizes are never silently cut. Sending a packet larger than [`AssociatedUdpSocket::get_max_send_pkt_size()`] fails with [`PacketTooLarge`], and receiving a datagram larger than [`AssociatedUdpSocket::get_max_pkt_size()`] fails with [`Truncated`], both wrapped in an [`std::io::Error`]. A header to a domain name longer than 255 bytes cannot be encoded, and sending it fails with [`ErrorKind::InvalidInput`].
+///
+/// Datagrams sent to the client and datagrams dropped by the socket are recorded in a [`UdpRelayStats`] handle, see [`AssociatedUdpSocket::stats()`].
+///
+/// Domain destinations can be sent to with [`AssociatedUdpSocket::send_to_address()`], which resolves them with a pluggable [`Resolver`](crate::dns::Resolver). By default, a [`CachingResolver`](crate::dns::CachingResolver) over the system resolver is used, so domains are not looked up for every packet.
+pub struct AssociatedUdpSocket<T = UdpSocket> {
+    socket: T,
+    buf_size: AtomicUsize,
+    send_max: AtomicUsize,
+    client: Mutex<Option<ClientFilter>>,
+    fragments: AtomicBool,
+    fragment_hook: Mutex<Option<FragmentHook>>,
+    drop_hook: Mutex<Option<DropHook>>,
+    scratch: Mutex<BytesMut>,
+    stats: UdpRelayStats,
+    resolver: Arc<dyn Resolver + Send + Sync>,
+    #[cfg(all(feature = "gso", target_os = "linux"))]
+    pub(super) gso: GsoState,
+}
+
+/// A callback called on every fragmented datagram dropped by an [`AssociatedUdpSocket`]
+type FragmentHook = Arc<dyn Fn(&UdpHeader, SocketAddr) + Send + Sync>;
+
+/// A callback called on every datagram dropped by an [`AssociatedUdpSocket`]
+type DropHook = Arc<dyn Fn(DropReason) + Send + Sync>;
+
+/// The result of [`AssociatedUdpSocket::try_recv_from()`]
+pub(super) type TryRecvFrom =
+    Result<Option<(Bytes, UdpHeader, SocketAddr)>, (Socks5Error, Option<Vec<u8>>)>;
+
+/// The result of [`AssociatedUdpSocket::try_recv_from_buf()`]
+pub(super) type TryRecvFromBuf =
+    Result<Option<(UdpHeader, Range<usize>, SocketAddr)>, (Socks5Error, Option<Range<usize>>)>;
+
+/// The default maximum sending UDP packet size, the largest payload of an IPv4 UDP datagram
+const MAX_SEND_PKT_SIZE: usize = 65507;
+
+/// The maximum length of a SOCKS5 UDP header, reached with a domain address of 255 bytes
+const MAX_HEADER_LEN: usize = 2 + 1 + 1 + 1 + 255 + 2;
+
+impl<T: DatagramSocket> AssociatedUdpSocket<T> {
+    /// Creates a new [`AssociatedUdpSocket`] with a transport, usually a [`UdpSocket`](tokio::net::UdpSocket), and a maximum receiving UDP packet size, with SOCKS5 UDP header included.
+    ///
+    /// The maximum sending UDP packet size defaults to 65507 bytes, the largest payload of an IPv4 UDP datagram. See [`AssociatedUdpSocket::set_max_send_pkt_size()`].
+    pub fn new(socket: T, buf_size: usize) -> Self {
+        // best-effort, as receiving skips the resets anyway
+        if let Some(socket) = socket.as_udp_socket() {
+            let _ = sockopt::suppress_connection_reset(socket);
+        }
+
+        Self {
+            socket,
+            buf_size: AtomicUsize::new(buf_size),
+            send_max: AtomicUsize::new(MAX_SEND_PKT_SIZE),
+            client: Mutex::new(None),
+            fragments: AtomicBool::new(false),
+            fragment_hook: Mutex::new(None),
+            drop_hook: Mutex::new(None),
+            scratch: Mutex::new(BytesMut::new()),
+            stats: UdpRelayStats::new(),
+            resolver: dns::shared_resolver(),
+            #[cfg(all(feature = "gso", target_os = "linux"))]
+            gso: GsoState::default(),
+        }
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected.
+    ///
+    /// On success, it returns the packet payload and the SOCKS5 UDP header. On error, it returns the error alongside an `Option<Vec<u8>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Vec<u8>>` will be `None`. Otherwise, it will be `Some(Vec<u8>)` containing the received raw UDP packet.
+    ///
+    /// An error only concerns the datagram it is returned for and does not poison the socket, so keep receiving after it rather than ending a `while let Ok(..)` loop. To skip invalid datagrams altogether, use [`AssociatedUdpSocket::recv_from_valid()`].
+    pub async fn recv(&self) -> Result<(Bytes, UdpHeader), (Socks5Error, Option<Vec<u8>>)> {
+        let mut buf = BytesMut::new();
+
+        match self.recv_buf(&mut buf).await {
+            Ok((header, range)) => Ok((buf.freeze().slice(range), header)),
+            Err((err, range)) => Err((err, range.map(|range| buf[range].to_vec()))),
+        }
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from a remote address.
+    ///
+    /// On success, it returns the packet payload, the SOCKS5 UDP header and the source address. On error, it returns the error alongside an `Option<Vec<u8>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Vec<u8>>` will be `None`. Otherwise, it will be `Some(Vec<u8>)` containing the received raw UDP packet.
+    ///
+    /// If an expected client is set, datagrams from other sources are dropped and this method keeps waiting.
+    ///
+    /// An error only concerns the datagram it is returned for and does not poison the socket, so keep receiving after it rather than ending a `while let Ok(..)` loop. To skip invalid datagrams altogether, use [`AssociatedUdpSocket::recv_from_valid()`].
+    pub async fn recv_from(
+        &self,
+    ) -> Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Vec<u8>>)> {
+        let mut buf = BytesMut::new();
+
+        match self.recv_from_buf(&mut buf).await {
+            Ok((header, range, addr)) => Ok((buf.freeze().slice(range), header, addr)),
+            Err((err, range)) => Err((err, range.map(|range| buf[range].to_vec()))),
+        }
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected, appending it to a caller-provided buffer.
+    ///
+    /// The buffer is reserved for the maximum receiving UDP packet size and the datagram is read into its spare capacity, so it is not zero-initialized first. Reusing the same buffer (after `clear()`ing it or splitting the received part off) avoids any allocation per packet.
+    ///
+    /// On success, it returns the SOCKS5 UDP header and the range of the payload in `buf`. On error, it returns the error alongside an `Option<Range<usize>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Range<usize>>` will be `None`. Otherwise, it will be the range of the received raw UDP packet in `buf`.
+    ///
+    /// If the datagram is larger than the maximum receiving UDP packet size, a [`Truncated`] error is returned alongside the range of the part of the datagram that was received.
+    ///
+    /// An error only concerns the datagram it is returned for and does not poison the socket, so keep receiving after it rather than ending a `while let Ok(..)` loop. To skip invalid datagrams altogether, use [`AssociatedUdpSocket::recv_from_valid()`].
+    pub async fn recv_buf(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(UdpHeader, Range<usize>), (Socks5Error, Option<Range<usize>>)> {
+        loop {
+            let (received, max) = self
+                .recv_one(buf)
+                .await
+                .map_err(|err| (Socks5Error::Io(err), None))?;
+
+            match self.accept_one(buf, &received, max) {
+                Ok(Some((header, pkt))) => return Ok((header, pkt)),
+                Ok(None) => buf.truncate(received.range.start),
+                Err(err) => return Err((err, Some(received.range))),
+            }
+        }
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from a remote address, appending it to a caller-provided buffer.
+    ///
+    /// The buffer is reserved for the maximum receiving UDP packet size and the datagram is read into its spare capacity, so it is not zero-initialized first. Reusing the same buffer (after `clear()`ing it or splitting the received part off) avoids any allocation per packet.
+    ///
+    /// On success, it returns the SOCKS5 UDP header, the range of the payload in `buf` and the source address. On error, it returns the error alongside an `Option<Range<usize>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Range<usize>>` will be `None`. Otherwise, it will be the range of the received raw UDP packet in `buf`.
+    ///
+    /// If the datagram is larger than the maximum receiving UDP packet size, a [`Truncated`] error is returned alongside the range of the part of the datagram that was received.
+    ///
+    /// If an expected client is set, datagrams from other sources are dropped and this method keeps waiting.
+    ///
+    /// An error only concerns the datagram it is returned for and does not poison the socket, so keep receiving after it rather than ending a `while let Ok(..)` loop. To skip invalid datagrams altogether, use [`AssociatedUdpSocket::recv_from_valid()`].
+    pub async fn recv_from_buf(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(UdpHeader, Range<usize>, SocketAddr), (Socks5Error, Option<Range<usize>>)> {
+        loop {
+            let (received, max) = self
+                .recv_one_from_client(buf)
+                .await
+                .map_err(|err| (Socks5Error::Io(err), None))?;
+
+            match self.accept_one(buf, &received, max) {
+                Ok(Some((header, pkt))) => return Ok((header, pkt, received.addr)),
+                Ok(None) => buf.truncate(received.range.start),
+                Err(err) => return Err((err, Some(received.range))),
+            }
+        }
+    }
+
+    /// Receives a valid SOCKS5 UDP packet on the socket from a remote address.
+    ///
+    /// Unlike [`AssociatedUdpSocket::recv_from()`], datagrams whose header can not be parsed or that are larger than the maximum receiving UDP packet size are discarded and this method keeps waiting, so only an error of the underlying socket is returned. Discarded datagrams are counted in [`UdpRelayStats`](super::UdpRelayStats) (`dropped_malformed` / `dropped_oversize`).
+    ///
+    /// Errors of kind [`ErrorKind::ConnectionReset`] are skipped as well and counted in `connection_resets`, as on Windows they only tell that an earlier datagram hit a closed port. Over a [`UdpSocket`], [`AssociatedUdpSocket::new()`] already stops Windows from reporting them.
+    ///
+    /// If an expected client is set, datagrams from other sources are dropped and this method keeps waiting.
+    ///
+    /// This method is cancel safe: a valid datagram is returned as soon as it is received, so dropping the future only loses datagrams that would have been discarded anyway.
+    #[inline]
+    pub async fn recv_from_valid(&self) -> Result<(Bytes, UdpHeader, SocketAddr), Error> {
+        self.recv_from_valid_with(|_, _| {}).await
+    }
+
+    /// Receives a valid SOCKS5 UDP packet on the socket from a remote address, calling `on_invalid` with the error and the source address of every discarded datagram. See [`AssociatedUdpSocket::recv_from_valid()`].
+    pub async fn recv_from_valid_with<F>(
+        &self,
+        mut on_invalid: F,
+    ) -> Result<(Bytes, UdpHeader, SocketAddr), Error>
+    where
+        F: FnMut(Socks5Error, SocketAddr),
+    {
+        let mut buf = BytesMut::new();
+
+        loop {
+            let (received, max) = match self.recv_one_from_client(&mut buf).await {
+                Ok(res) => res,
+                Err(err) if sockopt::is_connection_reset(&err) => {
+                    self.stats.record_connection_reset();
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+
+            match self.accept_one(&buf, &received, max) {
+                Ok(Some((header, pkt))) => {
+                    return Ok((buf.freeze().slice(pkt), header, received.addr))
+                }
+                Ok(None) => buf.clear(),
+                Err(err) => {
+                    buf.clear();
+                    on_invalid(err, received.addr);
+                }
+            }
+        }
+    }
+
+    async fn recv_one_from_client(&self, buf: &mut BytesMut) -> Result<(Received, usize), Error> {
+        loop {
+            let (received, max) = self.recv_one(buf).await?;
+
+            if self.is_expected_client(received.addr) {
+                return Ok((received, max));
+            }
+
+            buf.truncate(received.range.start);
+            self.record_drop(DropReason::Filtered);
+        }
+    }
+
+    async fn recv_one(&self, buf: &mut BytesMut) -> Result<(Received, usize), Error> {
+        let max = self.buf_size.load(Ordering::Acquire);
+        buf.reserve(max + 1);
+
+        let start = buf.len();
+        let (addr, len, filled) = poll_fn(|cx| {
+            let mut spare = ReadBuf::uninit(buf.spare_capacity_mut());
+            let (addr, len) = ready!(self.socket.poll_recv_from_len(cx, &mut spare))?;
+            Poll::Ready(Ok::<_, Error>((addr, len, spare.filled().len())))
+        })
+        .await?;
+
+        // SAFETY: `filled` bytes of the spare capacity were initialized by the transport
+        unsafe { buf.set_len(start + filled) };
+
+        let received = Received {
+            idx: 0,
+            range: start..start + filled,
+            addr,
+            len,
+        };
+
+        Ok((received, max))
+    }
+
+    /// Checks and parses a received datagram, returning `None` if it is a fragment to drop.
+    fn accept_one(
+        &self,
+        buf: &[u8],
+        received: &Received,
+        max: usize,
+    ) -> Result<Option<(UdpHeader, Range<usize>)>, Socks5Error> {
+        self.check_truncated(received.len, max)?;
+        let (header, pkt) = self.parse_header(buf, received.range.clone())?;
+        Ok((!self.drops_fragment(&header, received.addr)).then_some((header, pkt)))
+    }
+
+    /// Attempts to receive a SOCKS5 UDP packet on the socket from the remote address which it is connected.
+    ///
+    /// The raw datagram is appended to the filled portion of `buf`. On success, it returns the SOCKS5 UDP header and the offset of the payload in `buf.filled()`, the payload spanning from there to the end of the filled portion. If the header can not be parsed, or the datagram is larger than the maximum receiving UDP packet size or the remaining space of `buf` (a [`Truncated`] error), the raw datagram is left in `buf` and the error is returned.
+    ///
+    /// If no datagram is available, `Poll::Pending` is returned and the current task will be notified by a waker. Note that on multiple calls to [`AssociatedUdpSocket::poll_recv()`] or [`AssociatedUdpSocket::poll_recv_from()`], only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup. No state is kept between calls, so dropping a pending poll loop loses nothing.
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(UdpHeader, usize), Socks5Error>> {
+        let start = buf.filled().len();
+        let max = self.buf_size.load(Ordering::Acquire).min(buf.remaining());
+
+        loop {
+            let (addr, len) = ready!(self.socket.poll_recv_from_len(cx, buf))?;
+
+            self.check_truncated(len, max)?;
+
+            let raw = start..buf.filled().len();
+            let (header, pkt) = self.parse_header(buf.filled(), raw)?;
+
+            if !self.drops_fragment(&header, addr) {
+                return Poll::Ready(Ok((header, pkt.start)));
+            }
+
+            buf.set_filled(start);
+        }
+    }
+
+    /// Attempts to receive a SOCKS5 UDP packet on the socket from a remote address.
+    ///
+    /// The raw datagram is appended to the filled portion of `buf`. On success, it returns the SOCKS5 UDP header, the offset of the payload in `buf.filled()` and the source address, the payload spanning from the offset to the end of the filled portion. If the header can not be parsed, or the datagram is larger than the maximum receiving UDP packet size or the remaining space of `buf` (a [`Truncated`] error), the raw datagram is left in `buf` and the error is returned.
+    ///
+    /// If an expected client is set, datagrams from other sources are dropped and removed from `buf`.
+    ///
+    /// If no datagram is available, `Poll::Pending` is returned and the current task will be notified by a waker. Note that on multiple calls to [`AssociatedUdpSocket::poll_recv()`] or [`AssociatedUdpSocket::poll_recv_from()`], only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup. No state is kept between calls, so dropping a pending poll loop loses nothing.
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(UdpHeader, usize, SocketAddr), Socks5Error>> {
+        let start = buf.filled().len();
+        let max = self.buf_size.load(Ordering::Acquire).min(buf.remaining());
+
+        loop {
+            let (addr, len) = ready!(self.socket.poll_recv_from_len(cx, buf))?;
+
+            if !self.is_expected_client(addr) {
+                buf.set_filled(start);
+                self.record_drop(DropReason::Filtered);
+                continue;
+            }
+
+            self.check_truncated(len, max)?;
+
+            let raw = start..buf.filled().len();
+            let (header, pkt) = self.parse_header(buf.filled(), raw)?;
+
+            if !self.drops_fragment(&header, addr) {
+                return Poll::Ready(Ok((header, pkt.start, addr)));
+            }
+
+            buf.set_filled(start);
+        }
+    }
+
+    fn accept_batch<B: BorrowMut<BytesMut>>(
+        &self,
+        bufs: &mut [B],
+        received: Vec<Received>,
+    ) -> Vec<(UdpHeader, Range<usize>, SocketAddr)> {
+        let max = self.buf_size.load(Ordering::Acquire);
+        let mut pkts = Vec::with_capacity(received.len());
+
+        for Received {
+            idx,
+            range,
+            addr,
+            len,
+        } in received
+        {
+            if !self.is_expected_client(addr) {
+                bufs[idx].borrow_mut().truncate(range.start);
+                self.record_drop(DropReason::Filtered);
+                continue;
+            }
+
+            if self.check_truncated(len, max).is_err() {
+                bufs[idx].borrow_mut().truncate(range.start);
+                continue;
+            }
+
+            match self.parse_header(bufs[idx].borrow_mut(), range.clone()) {
+                Ok((header, pkt)) if !self.drops_fragment(&header, addr) => {
+                    bufs.swap(pkts.len(), idx);
+                    pkts.push((header, pkt, addr));
+                }
+                _ => bufs[idx].borrow_mut().truncate(range.start),
+            }
+        }
+
+        pkts
+    }
+
+    fn check_truncated(&self, len: Option<usize>, max: usize) -> Result<(), Socks5Error> {
+        if batch::is_oversize(len, max) {
+            self.record_drop(DropReason::Oversize);
+            return Err(Socks5Error::Io(Truncated { len, max }.into()));
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn parse_header(
+        &self,
+        buf: &[u8],
+        raw: Range<usize>,
+    ) -> Result<(UdpHeader, Range<usize>), Socks5Error> {
+        let header = match UdpHeader::read_from_buf(&mut &buf[raw.clone()]) {
+            Ok(header) => header,
+            Err(err) => {
+                self.record_drop(DropReason::Malformed);
+                return Err(err);
+            }
+        };
+
+        self.stats.touch();
+
+        let header_len = header.serialized_len();
+        Ok((header, raw.start + header_len..raw.end))
+    }
+
+    /// Records a datagram dropped by the socket in its statistics, and reports it to the callback set with [`AssociatedUdpSocket::on_drop()`].
+    pub(super) fn record_drop(&self, reason: DropReason) {
+        self.stats.record_drop(reason);
+
+        let hook = self
+            .drop_hook
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+
+        if let Some(hook) = hook {
+            hook(reason);
+        }
+    }
+
+    /// Returns whether a received datagram is a fragment to drop, recording the drop if so.
+    pub(super) fn drops_fragment(&self, header: &UdpHeader, addr: SocketAddr) -> bool {
+        if header.frag == 0 || self.fragments.load(Ordering::Relaxed) {
+            return false;
+        }
+
+        self.record_drop(DropReason::Fragment);
+
+        let hook = self
+            .fragment_hook
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+
+        if let Some(hook) = hook {
+            hook(header, addr);
+        }
+
+        true
+    }
+
+    /// Sends a UDP packet to the remote address which it is connected. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// If the packet, with SOCKS5 UDP header included, is larger than the maximum sending UDP packet size, nothing is sent and a [`PacketTooLarge`] error is returned.
+    pub async fn send<P: AsRef<[u8]>>(&self, pkt: P, header: &UdpHeader) -> Result<usize, Error> {
+        let buf = self.encode(pkt.as_ref(), header)?;
+        let res = poll_fn(|cx| self.socket.poll_send(cx, &buf)).await;
+        self.recycle(buf);
+
+        res.map(|len| self.sent(len, header))
+    }
+
+    /// Sends a UDP packet to the remote address which it is connected, writing the SOCKS5 UDP header into the headroom in front of the payload instead of copying the payload. See [`AssociatedUdpSocket::send_to_with_headroom()`].
+    pub async fn send_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+    ) -> Result<usize, Error> {
+        let Some(start) = self.write_headroom(buf, headroom, header)? else {
+            return self.send(&buf[headroom..], header).await;
+        };
+
+        poll_fn(|cx| self.socket.poll_send(cx, &buf[start..]))
+            .await
+            .map(|len| self.sent(len, header))
+    }
+
+    /// Sends a UDP packet to a specified remote address. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// If the transport supports vectored sends (see [`DatagramSocket::is_write_vectored()`]), as a `UdpSocket` does on all major platforms, the header is serialized on the stack and sent alongside the borrowed payload with `sendmsg`, so nothing is copied. Otherwise, the packet is serialized into a scratch buffer kept by the socket, so sending does not allocate once the buffer has grown to the packet size.
+    ///
+    /// If the packet, with SOCKS5 UDP header included, is larger than the maximum sending UDP packet size, nothing is sent and a [`PacketTooLarge`] error is returned.
+    pub async fn send_to<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        let pkt = pkt.as_ref();
+
+        if self.socket.is_write_vectored() {
+            let (buf, len) = self.encode_header(pkt, header)?;
+            let bufs = [IoSlice::new(&buf[..len]), IoSlice::new(pkt)];
+
+            return poll_fn(|cx| self.socket.poll_send_to_vectored(cx, &bufs, addr))
+                .await
+                .map(|len| self.sent(len, header));
+        }
+
+        let buf = self.encode(pkt, header)?;
+        let res = poll_fn(|cx| self.socket.poll_send_to(cx, &buf, addr)).await;
+        self.recycle(buf);
+
+        res.map(|len| self.sent(len, header))
+    }
+
+    /// Sends a UDP packet to a specified remote address, writing the SOCKS5 UDP header into the headroom in front of the payload instead of copying the payload.
+    ///
+    /// The payload is `buf[headroom..]`. The header is written right in front of it, overwriting the end of `buf[..headroom]`, and the packet is sent straight from `buf`. If `headroom` is smaller than the header, the payload is copied as in [`AssociatedUdpSocket::send_to()`]. [`AssociatedUdpSocket::MAX_HEADER_LEN`] bytes of headroom always suffice, e.g. by receiving payloads at that offset of a buffer.
+    ///
+    /// It returns the number of payload bytes sent.
+    pub async fn send_to_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        let Some(start) = self.write_headroom(buf, headroom, header)? else {
+            return self.send_to(&buf[headroom..], header, addr).await;
+        };
+
+        poll_fn(|cx| self.socket.poll_send_to(cx, &buf[start..], addr))
+            .await
+            .map(|len| self.sent(len, header))
+    }
+
+    /// Sends a UDP packet to a SOCKS5 address. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// Domain addresses are resolved with the resolver of this socket. See [`AssociatedUdpSocket::set_resolver()`].
+    pub async fn send_to_address<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: &Address,
+    ) -> Result<usize, Error> {
+        let addr = dns::resolve_address(&*self.resolver, addr).await?;
+        self.send_to(pkt, header, addr).await
+    }
+
+    /// Attempts to send a UDP packet to the remote address which it is connected. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// On success, it returns the number of payload bytes sent. If the socket is not ready for writing, `Poll::Pending` is returned and the current task will be notified by a waker. Nothing is sent until `Poll::Ready` is returned, so the packet can be dropped or retried at any time. Note that on multiple calls to [`AssociatedUdpSocket::poll_send()`] or [`AssociatedUdpSocket::poll_send_to()`], only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        pkt: &[u8],
+        header: &UdpHeader,
+    ) -> Poll<Result<usize, Error>> {
+        let buf = match self.encode(pkt, header) {
+            Ok(buf) => buf,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+
+        let res = self.socket.poll_send(cx, &buf);
+        self.recycle(buf);
+
+        res.map_ok(|len| self.sent(len, header))
+    }
+
+    /// Attempts to send a UDP packet to a specified remote address. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// On success, it returns the number of payload bytes sent. If the socket is not ready for writing, `Poll::Pending` is returned and the current task will be notified by a waker. Nothing is sent until `Poll::Ready` is returned, so the packet can be dropped or retried at any time. Note that on multiple calls to [`AssociatedUdpSocket::poll_send()`] or [`AssociatedUdpSocket::poll_send_to()`], only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    pub fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        pkt: &[u8],
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        if self.socket.is_write_vectored() {
+            let (buf, len) = match self.encode_header(pkt, header) {
+                Ok(res) => res,
+                Err(err) => return Poll::Ready(Err(err)),
+            };
+
+            let bufs = [IoSlice::new(&buf[..len]), IoSlice::new(pkt)];
+
+            return self
+                .socket
+                .poll_send_to_vectored(cx, &bufs, addr)
+                .map_ok(|len| self.sent(len, header));
+        }
+
+        let buf = match self.encode(pkt, header) {
+            Ok(buf) => buf,
+            Err(err) => return Poll::Ready(Err(err)),
+        };
+
+        let res = self.socket.poll_send_to(cx, &buf, addr);
+        self.recycle(buf);
+
+        res.map_ok(|len| self.sent(len, header))
+    }
+
+    fn sent(&self, len: usize, header: &UdpHeader) -> usize {
+        let len = len - header.serialized_len();
+        self.stats.record_downlink(len);
+        len
+    }
+
+    pub(super) fn check_send_size(&self, pkt: &[u8], header: &UdpHeader) -> Result<(), Error> {
+        check_encodable(header)?;
+
+        let len = header.serialized_len() + pkt.len();
+        let max = self.send_max.load(Ordering::Acquire);
+
+        if len > max {
+            self.record_drop(DropReason::Oversize);
+            return Err(PacketTooLarge { len, max }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Serializes a packet into the scratch buffer of the socket, which is taken out until [`AssociatedUdpSocket::recycle()`] puts it back. Concurrent senders allocate a buffer of their own instead of waiting for it.
+    fn encode(&self, pkt: &[u8], header: &UdpHeader) -> Result<BytesMut, Error> {
+        self.check_send_size(pkt, header)?;
+
+        let mut buf = self.take_scratch();
+        buf.reserve(header.serialized_len() + pkt.len());
+        header.write_to_buf(&mut buf);
+        buf.extend_from_slice(pkt);
+        Ok(buf)
+    }
+
+    /// Takes the scratch buffer of the socket out, cleared, leaving an empty buffer in its place.
+    pub(super) fn take_scratch(&self) -> BytesMut {
+        let mut buf = mem::take(&mut *self.scratch.lock().unwrap_or_else(|err| err.into_inner()));
+        buf.clear();
+        buf
+    }
+
+    /// Serializes the header of a packet on the stack, for sending alongside the payload with a vectored send. It returns the buffer and the length of the header in it.
+    fn encode_header(
+        &self,
+        pkt: &[u8],
+        header: &UdpHeader,
+    ) -> Result<([u8; MAX_HEADER_LEN], usize), Error> {
+        self.check_send_size(pkt, header)?;
+
+        let mut buf = [0; MAX_HEADER_LEN];
+        let len = header.encode_into(&mut buf)?.len();
+        Ok((buf, len))
+    }
+
+    /// Puts a buffer back as the scratch buffer, keeping the larger one if another sender already did.
+    pub(super) fn recycle(&self, buf: BytesMut) {
+        let mut scratch = self.scratch.lock().unwrap_or_else(|err| err.into_inner());
+
+        if buf.capacity() > scratch.capacity() {
+            *scratch = buf;
+        }
+    }
+
+    /// Writes the header right in front of the payload at `buf[headroom..]`, returning where the packet starts, or `None` if the headroom is too small.
+    fn write_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+    ) -> Result<Option<usize>, Error> {
+        let header_len = header.serialized_len();
+
+        let Some(start) = headroom.checked_sub(header_len) else {
+            return Ok(None);
+        };
+
+        self.check_send_size(&buf[headroom..], header)?;
+
+        let mut headroom = &mut buf[start..headroom];
+        header.write_to_buf(&mut headroom);
+
+        Ok(Some(start))
+    }
+
+    /// Sets the client address that datagrams are accepted from in [`AssociatedUdpSocket::recv_from()`].
+    ///
+    /// `matching` decides how strictly the source address of a datagram is compared against `addr`. See [`ClientMatch`].
+    pub fn set_expected_client(&self, addr: SocketAddr, matching: ClientMatch) {
+        *self.client.lock().unwrap_or_else(|err| err.into_inner()) =
+            Some(ClientFilter::expect(addr, matching));
+    }
+
+    /// Learns the client address from the first datagram coming from `ip`.
+    ///
+    /// Datagrams from other IP addresses are dropped. Once a datagram from `ip` is received, its source address becomes the expected client, matched with `matching`.
+    pub fn learn_client(&self, ip: IpAddr, matching: ClientMatch) {
+        *self.client.lock().unwrap_or_else(|err| err.into_inner()) =
+            Some(ClientFilter::Learn(ip.to_canonical(), matching));
+    }
+
+    /// Sets the expected client from the address the client declared in the associate command, following the common convention of clients not knowing their UDP endpoint yet.
+    ///
+    /// If `declared` is a socket address with both a specified IP address and a non-zero port, it is enforced as the expected client. Otherwise (an unspecified IP address, a port of `0`, or a domain address), the client address is learned from the first datagram coming from `peer_ip`, the IP address of the TCP connection. See [`AssociatedUdpSocket::learn_client()`]. Either way, the client address is matched with `matching`.
+    pub fn set_declared_client(&self, declared: &Address, peer_ip: IpAddr, matching: ClientMatch) {
+        match declared {
+            // `::ffff:0.0.0.0` is as unspecified as `0.0.0.0`
+            Address::SocketAddress(addr)
+                if !addr::canonical(*addr).ip().is_unspecified() && addr.port() != 0 =>
+            {
+                self.set_expected_client(*addr, matching)
+            }
+            _ => self.learn_client(peer_ip, matching),
+        }
+    }
+
+    /// Sets whether fragmented datagrams, i.e. datagrams with a `FRAG` field other than `0`, are delivered by the receiving methods.
+    ///
+    /// By default they are dropped and counted as [`DropReason::Fragment`], as RFC 1928 requires of implementations that do not support fragmentation: delivering a fragment as if it were a whole datagram would corrupt the flow. Allow them if you reassemble fragments yourself, e.g. with a [`FragmentReassembler`](super::FragmentReassembler). They are then delivered with the `frag` field of their [`UdpHeader`] telling their position in the sequence.
+    #[inline]
+    pub fn allow_fragments(&self, allow: bool) {
+        self.fragments.store(allow, Ordering::Relaxed);
+    }
+
+    /// Returns whether fragmented datagrams are delivered. See [`AssociatedUdpSocket::allow_fragments()`].
+    #[inline]
+    pub fn fragments_allowed(&self) -> bool {
+        self.fragments.load(Ordering::Relaxed)
+    }
+
+    /// Sets a callback called with the header and source address of every fragmented datagram dropped by this socket, replacing the previous one.
+    pub fn on_dropped_fragment<F>(&self, f: F)
+    where
+        F: Fn(&UdpHeader, SocketAddr) + Send + Sync + 'static,
+    {
+        *self
+            .fragment_hook
+            .lock()
+            .unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(f));
+    }
+
+    /// Sets a callback called with the reason of every datagram dropped by this socket, replacing the previous one. Drops are counted in [`AssociatedUdpSocket::stats()`] either way.
+    ///
+    /// The callback is called without any lock of the socket held, so it may call back into the socket, e.g. to replace itself.
+    pub fn on_drop<F>(&self, f: F)
+    where
+        F: Fn(DropReason) + Send + Sync + 'static,
+    {
+        *self.drop_hook.lock().unwrap_or_else(|err| err.into_inner()) = Some(Arc::new(f));
+    }
+
+    /// Removes the expected client, accepting datagrams from any source again.
+    pub fn clear_expected_client(&self) {
+        *self.client.lock().unwrap_or_else(|err| err.into_inner()) = None;
+    }
+
+    /// Returns the expected client address, if set or learned.
+    ///
+    /// This returns `None` while the client address is still being learned. With [`ClientMatch::Rebind`], this is the current endpoint of the client, updated on every accepted rebinding.
+    pub fn expected_client(&self) -> Option<SocketAddr> {
+        match *self.client.lock().unwrap_or_else(|err| err.into_inner()) {
+            Some(ClientFilter::Expect { addr, .. }) => Some(addr),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of datagrams dropped because their source did not match the expected client.
+    #[inline]
+    pub fn filtered_count(&self) -> u64 {
+        self.stats.dropped(DropReason::Filtered)
+    }
+
+    /// Returns the statistics handle of this socket.
+    ///
+    /// The handle can be cloned to read the counters from another task while the socket is in use.
+    #[inline]
+    pub fn stats(&self) -> &UdpRelayStats {
+        &self.stats
+    }
+
+    /// Replaces the statistics handle of this socket, e.g. to share it with a relay loop recording forwarded datagrams.
+    #[inline]
+    pub fn set_stats(&mut self, stats: UdpRelayStats) {
+        self.stats = stats;
+    }
+
+    pub(super) fn is_expected_client(&self, src: SocketAddr) -> bool {
+        let mut client = self.client.lock().unwrap_or_else(|err| err.into_inner());
+
+        match client.as_mut() {
+            Some(ClientFilter::Expect {
+                addr,
+                matching: ClientMatch::Rebind { min_interval },
+                since,
+            }) => {
+                let (expected, canonical) = (addr::canonical(*addr), addr::canonical(src));
+
+                if expected == canonical {
+                    return true;
+                }
+
+                if expected.ip() != canonical.ip() || since.elapsed() < *min_interval {
+                    return false;
+                }
+
+                *addr = src;
+                *since = Instant::now();
+                self.stats.record_rebind();
+                true
+            }
+            Some(ClientFilter::Expect { addr, matching, .. }) => matching.matches(*addr, src),
+            Some(ClientFilter::Learn(ip, matching)) if addr::canonical(src).ip() == *ip => {
+                *client = Some(ClientFilter::expect(src, *matching));
+                true
+            }
+            Some(ClientFilter::Learn(..)) => false,
+            None => true,
+        }
+    }
+
+    /// Sets the resolver used for resolving domain addresses in [`AssociatedUdpSocket::send_to_address()`].
+    #[inline]
+    pub fn set_resolver(&mut self, resolver: Arc<dyn Resolver + Send + Sync>) {
+        self.resolver = resolver;
+    }
+
+    /// Returns the resolver used for resolving domain addresses.
+    #[inline]
+    pub fn resolver(&self) -> &Arc<dyn Resolver + Send + Sync> {
+        &self.resolver
+    }
+
+    /// Get the maximum receiving UDP packet size, with SOCKS5 UDP header included.
+    #[inline]
+    pub fn get_max_pkt_size(&self) -> usize {
+        self.buf_size.load(Ordering::Acquire)
+    }
+
+    /// Set the maximum receiving UDP packet size, with SOCKS5 UDP header included, for adjusting the receiving buffer size. Larger datagrams are reported as [`Truncated`].
+    #[inline]
+    pub fn set_max_pkt_size(&self, size: usize) {
+        self.buf_size.store(size, Ordering::Release);
+    }
+
+    /// Get the maximum sending UDP packet size, with SOCKS5 UDP header included.
+    #[inline]
+    pub fn get_max_send_pkt_size(&self) -> usize {
+        self.send_max.load(Ordering::Acquire)
+    }
+
+    /// Set the maximum sending UDP packet size, with SOCKS5 UDP header included. Larger packets are rejected with a [`PacketTooLarge`] error instead of being sent.
+    #[inline]
+    pub fn set_max_send_pkt_size(&self, size: usize) {
+        self.send_max.store(size, Ordering::Release);
+    }
+
+    /// Returns a shared reference to the underlying socket.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.socket
+    }
+
+    /// Returns a mutable reference to the underlying socket.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 UDP abstraction and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.socket
+    }
+
+    /// Consumes the [`AssociatedUdpSocket`] and returns the underlying socket.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.socket
+    }
+}
+
+impl AssociatedUdpSocket {
+    /// The maximum length of a SOCKS5 UDP header, reached with a domain address of 255 bytes. Reserve this much headroom in front of payloads to always have [`AssociatedUdpSocket::send_to_with_headroom()`] send without copying.
+    pub const MAX_HEADER_LEN: usize = MAX_HEADER_LEN;
+
+    /// Receives a batch of SOCKS5 UDP packets on the socket from remote addresses, appending each packet to one of the caller-provided buffers.
+    ///
+    /// On Linux, this receives as many datagrams as are ready, up to `bufs.len()`, with a single `recvmmsg` system call. On other platforms, it receives one datagram per call.
+    ///
+    /// Each buffer (a [`BytesMut`] or e.g. a [`PooledBuf`](super::PooledBuf)) is reserved for the maximum receiving UDP packet size and receives at most one datagram. Buffers holding the received packets are moved to the front of `bufs`, so the `i`-th returned entry, made of the SOCKS5 UDP header, the range of the payload and the source address, describes the packet in `bufs[i]`. The order of the other buffers is unspecified.
+    ///
+    /// Unlike [`AssociatedUdpSocket::recv_from_buf()`], datagrams whose header can not be parsed are dropped, as are datagrams larger than the maximum receiving UDP packet size and datagrams from sources other than the expected client. This method keeps waiting until at least one packet is received, unless `bufs` is empty.
+    pub async fn recv_batch<B: BorrowMut<BytesMut>>(
+        &self,
+        bufs: &mut [B],
+    ) -> Result<Vec<(UdpHeader, Range<usize>, SocketAddr)>, Error> {
+        if bufs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let buf_size = self.buf_size.load(Ordering::Acquire);
+
+        loop {
+            let received = batch::recv_batch(&self.socket, bufs, buf_size).await?;
+            let pkts = self.accept_batch(bufs, received);
+
+            if !pkts.is_empty() {
+                return Ok(pkts);
+            }
+        }
+    }
+
+    /// Tries to receive a batch of SOCKS5 UDP packets without waiting, like [`AssociatedUdpSocket::recv_batch()`]. The returned batch may be empty if all received datagrams are dropped.
+    pub(super) fn try_recv_batch<B: BorrowMut<BytesMut>>(
+        &self,
+        bufs: &mut [B],
+    ) -> Result<Vec<(UdpHeader, Range<usize>, SocketAddr)>, Error> {
+        let buf_size = self.buf_size.load(Ordering::Acquire);
+        let received = batch::try_recv_batch(&self.socket, bufs, buf_size)?;
+        Ok(self.accept_batch(bufs, received))
+    }
+
+    /// Tries to receive a SOCKS5 UDP packet on the socket from a remote address without waiting.
+    ///
+    /// It returns `Ok(None)` if no datagram is ready, and otherwise behaves like [`AssociatedUdpSocket::recv_from()`]. See [`AssociatedUdpSocket::try_recv_from_buf()`].
+    pub fn try_recv_from(&self) -> TryRecvFrom {
+        let mut buf = BytesMut::new();
+
+        match self.try_recv_from_buf(&mut buf) {
+            Ok(res) => {
+                Ok(res.map(|(header, range, addr)| (buf.freeze().slice(range), header, addr)))
+            }
+            Err((err, range)) => Err((err, range.map(|range| buf[range].to_vec()))),
+        }
+    }
+
+    /// Tries to receive a SOCKS5 UDP packet on the socket from a remote address without waiting, appending it to a caller-provided buffer.
+    ///
+    /// It returns `Ok(None)` if no datagram is ready, and otherwise behaves like [`AssociatedUdpSocket::recv_from_buf()`]: the header is parsed the same way, errors come with the range of the raw datagram in `buf`, and datagrams from sources other than the expected client or fragments to drop are skipped, trying the next ready datagram.
+    ///
+    /// The datagram is read right away without registering a waker, so a burst can be drained in a loop after waiting once for readiness, e.g. with [`UdpSocket::readable()`] on [`AssociatedUdpSocket::get_ref()`]. Once `Ok(None)` is returned, the readiness is cleared and the next wait blocks until a datagram arrives.
+    pub fn try_recv_from_buf(&self, buf: &mut BytesMut) -> TryRecvFromBuf {
+        let max = self.buf_size.load(Ordering::Acquire);
+
+        loop {
+            let received = match batch::try_recv_batch(&self.socket, slice::from_mut(buf), max) {
+                Ok(received) => received,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(err) => return Err((Socks5Error::Io(err), None)),
+            };
+
+            let Some(received) = received.into_iter().next() else {
+                return Ok(None);
+            };
+
+            if !self.is_expected_client(received.addr) {
+                buf.truncate(received.range.start);
+                self.record_drop(DropReason::Filtered);
+                continue;
+            }
+
+            match self.accept_one(buf, &received, max) {
+                Ok(Some((header, pkt))) => return Ok(Some((header, pkt, received.addr))),
+                Ok(None) => buf.truncate(received.range.start),
+                Err(err) => return Err((err, Some(received.range))),
+            }
+        }
+    }
+
+    /// Tries to send a UDP packet to a specified remote address without waiting. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// It returns `Ok(None)` if the socket is not ready to send, in which case nothing is sent, and otherwise behaves like [`AssociatedUdpSocket::send_to()`], including the vectored send of the header alongside the borrowed payload. Wait for readiness with [`UdpSocket::writable()`] on [`AssociatedUdpSocket::get_ref()`].
+    pub fn try_send_to<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<Option<usize>, Error> {
+        let pkt = pkt.as_ref();
+
+        #[cfg(not(any(target_os = "redox", target_os = "wasi", target_os = "horizon")))]
+        let res = {
+            let (buf, len) = self.encode_header(pkt, header)?;
+            let bufs = [IoSlice::new(&buf[..len]), IoSlice::new(pkt)];
+            super::datagram::try_send_to_vectored(&self.socket, &bufs, addr)
+        };
+
+        #[cfg(any(target_os = "redox", target_os = "wasi", target_os = "horizon"))]
+        let res = {
+            let buf = self.encode(pkt, header)?;
+            let res = self.socket.try_send_to(&buf, addr);
+            self.recycle(buf);
+            res
+        };
+
+        match res {
+            Ok(len) => Ok(Some(self.sent(len, header))),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Sends a batch of UDP packets to specified remote addresses. The SOCKS5 UDP header will be added to each packet.
+    ///
+    /// On Linux, the packets are sent with a single `sendmmsg` system call. On other platforms, only the first packet is sent per call. Headers of the whole batch are serialized into one buffer and payloads are not copied.
+    ///
+    /// On success, it returns the number of packets sent from the start of `pkts`, which may be fewer than given, e.g. if a packet fails to be sent. The remaining packets can be sent with another call. An error is only returned if the first packet fails to be sent.
+    ///
+    /// The batch stops before the first packet larger than the maximum sending UDP packet size, with SOCKS5 UDP header included, or before the first header that cannot be encoded. A [`PacketTooLarge`] or an [`ErrorKind::InvalidInput`] error is returned if it is the first one.
+    pub async fn send_batch<P: AsRef<[u8]>>(
+        &self,
+        pkts: &[(P, UdpHeader, SocketAddr)],
+    ) -> Result<usize, Error> {
+        let max = self.send_max.load(Ordering::Acquire);
+        let fits = pkts
+            .iter()
+            .take_while(|(pkt, header, _)| {
+                check_encodable(header).is_ok()
+                    && header.serialized_len() + pkt.as_ref().len() <= max
+            })
+            .count();
+
+        if let Some((pkt, header, _)) = pkts.first().filter(|_| fits == 0) {
+            self.check_send_size(pkt.as_ref(), header)?;
+        }
+
+        let pkts = &pkts[..fits];
+
+        let mut headers = BytesMut::with_capacity(
+            pkts.iter()
+                .map(|(_, header, _)| header.serialized_len())
+                .sum(),
+        );
+
+        for (_, header, _) in pkts {
+            header.write_to_buf(&mut headers);
+        }
+
+        let mut offset = 0;
+        let dgrams = pkts
+            .iter()
+            .map(|(pkt, header, addr)| {
+                let header_len = header.serialized_len();
+                let dgram = Datagram {
+                    header: &headers[offset..offset + header_len],
+                    payload: pkt.as_ref(),
+                    addr: *addr,
+                };
+                offset += header_len;
+                dgram
+            })
+            .collect::<Vec<_>>();
+
+        let sent = batch::send_batch(&self.socket, &dgrams).await?;
+
+        for dgram in &dgrams[..sent] {
+            self.stats.record_downlink(dgram.payload.len());
+        }
+
+        Ok(sent)
+    }
+}
+
+/// Fails with [`ErrorKind::InvalidInput`] if the header is to a domain name longer than 255 bytes, which its one-byte length cannot describe
+fn check_encodable(header: &UdpHeader) -> Result<(), Error> {
+    match &header.address {
+        Address::DomainAddress(domain, _) if domain.len() > u8::MAX as usize => Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "domain name of {} bytes is longer than 255 bytes",
+                domain.len()
+            ),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl<T: Debug> Debug for AssociatedUdpSocket<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("AssociatedUdpSocket")
+            .field("socket", &self.socket)
+            .field("buf_size", &self.buf_size)
+            .field("send_max", &self.send_max)
+            .field("client", &self.client)
+            .field("fragments", &self.fragments)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ClientFilter {
+    Learn(IpAddr, ClientMatch),
+    Expect {
+        addr: SocketAddr,
+        matching: ClientMatch,
+        since: Instant,
+    },
+}
+
+impl ClientFilter {
+    fn expect(addr: SocketAddr, matching: ClientMatch) -> Self {
+        Self::Expect {
+            addr,
+            matching,
+            since: Instant::now(),
+        }
+    }
+}
+
+/// How the source address of a received datagram is matched against the expected client address.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ClientMatch {
+    /// Both the IP address and the port must match.
+    #[default]
+    Strict,
+
+    /// Only the IP address must match.
+    ///
+    /// Many clients bind an ephemeral UDP port only after sending the associate command with port 0, so the port they actually send from is unknown at association time.
+    SameIpAnyPort,
+
+    /// Only the IP address must match, and a datagram from a new port of the same IP address moves the expected client to it, so replies follow the client.
+    ///
+    /// This keeps associations of clients behind a NAT alive when the NAT rebinds their port. To make it harder for other users of the same host to take the association over, a new port is only accepted once at least `min_interval` has passed since the expected client was set or last moved. Datagrams from other ports are dropped in the meantime.
+    Rebind {
+        /// The minimum time between two changes of the expected client
+        min_interval: Duration,
+    },
+}
+
+impl ClientMatch {
+    /// Returns whether `src` matches the expected client address `expected`.
+    ///
+    /// For [`ClientMatch::Rebind`], this only compares the IP addresses. Whether the expected client is moved to `src` is decided by the socket.
+    ///
+    /// A v4-mapped IPv6 address matches the IPv4 address it maps, as a dual-stack socket reports the source of an IPv4 client in that form, while the expected client is usually recorded from e.g. an IPv4 TCP connection.
+    #[inline]
+    pub fn matches(self, expected: SocketAddr, src: SocketAddr) -> bool {
+        let (expected, src) = (addr::canonical(expected), addr::canonical(src));
+
+        match self {
+            Self::Strict => expected == src,
+            Self::SameIpAnyPort | Self::Rebind { .. } => expected.ip() == src.ip(),
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use super::{sockopt, UdpSocketOptions};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{Error, ErrorKind},
+    mem::ManuallyDrop,
+    net::{IpAddr, SocketAddr},
+    ops::Deref,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::{
+    net::UdpSocket,
+    sync::{OwnedSemaphorePermit, Semaphore},
+};
+
+/// A pool of pre-bound outbound UDP sockets, shared by UDP associations.
+///
+/// The pool holds up to `size` sockets bound on the egress IP address, with [`UdpSocketOptions`] applied. Associations check a socket out with [`UdpSocketPool::checkout()`] and give it back by dropping the [`PooledUdpSocket`], so churny associations do not pay for binding and closing sockets, and the same ephemeral ports are reused.
+///
+/// At most `size` sockets are checked out at the same time. Checking out of an exhausted pool waits for a socket to be given back, applying backpressure on new associations instead of failing them.
+///
+/// A socket is health checked before being handed out: datagrams it received while idle are discarded, and it is dropped from the pool if it has a pending error, in which case a fresh one is bound in its place.
+///
+/// Cloning a [`UdpSocketPool`] gives another handle to the same pool.
+#[derive(Clone)]
+pub struct UdpSocketPool {
+    inner: Arc<SocketPoolInner>,
+}
+
+struct SocketPoolInner {
+    bind_addr: SocketAddr,
+    options: UdpSocketOptions,
+    size: usize,
+    idle: Mutex<Vec<UdpSocket>>,
+    permits: Arc<Semaphore>,
+    counters: SocketPoolCounters,
+}
+
+#[derive(Debug, Default)]
+struct SocketPoolCounters {
+    checkouts: AtomicU64,
+    waits: AtomicU64,
+    binds: AtomicU64,
+    discarded: AtomicU64,
+}
+
+/// A snapshot of the counters of a [`UdpSocketPool`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct UdpSocketPoolStats {
+    /// The maximum number of sockets of the pool
+    pub size: usize,
+
+    /// Sockets bound and waiting in the pool
+    pub idle: usize,
+
+    /// Sockets currently checked out
+    pub in_use: usize,
+
+    /// Sockets handed out by [`UdpSocketPool::checkout()`]
+    pub checkouts: u64,
+
+    /// Checkouts that had to wait for a socket to be given back as the pool was exhausted
+    pub waits: u64,
+
+    /// Sockets bound by the pool, including the pre-bound ones
+    pub binds: u64,
+
+    /// Sockets dropped from the pool as broken, or with [`PooledUdpSocket::discard()`]
+    pub discarded: u64,
+}
+
+impl UdpSocketPool {
+    /// Creates a new [`UdpSocketPool`] of `size` sockets bound on `bind_ip` with ephemeral ports, with `options` applied.
+    ///
+    /// All sockets are bound upfront, and the first error binding one is returned.
+    pub async fn new(
+        bind_ip: IpAddr,
+        size: usize,
+        options: UdpSocketOptions,
+    ) -> Result<Self, Error> {
+        let inner = SocketPoolInner {
+            bind_addr: SocketAddr::new(bind_ip, 0),
+            options,
+            size,
+            idle: Mutex::new(Vec::with_capacity(size)),
+            permits: Arc::new(Semaphore::new(size)),
+            counters: SocketPoolCounters::default(),
+        };
+
+        let mut sockets = Vec::with_capacity(size);
+
+        for _ in 0..size {
+            sockets.push(inner.bind().await?);
+        }
+
+        *inner.idle.lock().unwrap_or_else(|err| err.into_inner()) = sockets;
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Checks a socket out of the pool, waiting for one to be given back if all of them are checked out.
+    ///
+    /// An error is returned if no healthy socket is idle and binding a fresh one fails.
+    pub async fn checkout(&self) -> Result<PooledUdpSocket, Error> {
+        let permit = match self.inner.permits.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) => {
+                self.inner.counters.waits.fetch_add(1, Ordering::Relaxed);
+
+                self.inner
+                    .permits
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| Error::other("UDP socket pool closed"))?
+            }
+        };
+
+        let socket = loop {
+            let Some(socket) = self
+                .inner
+                .idle
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .pop()
+            else {
+                break self.inner.bind().await?;
+            };
+
+            if is_healthy(&socket) {
+                break socket;
+            }
+
+            self.inner
+                .counters
+                .discarded
+                .fetch_add(1, Ordering::Relaxed);
+        };
+
+        self.inner
+            .counters
+            .checkouts
+            .fetch_add(1, Ordering::Relaxed);
+
+        Ok(PooledUdpSocket {
+            socket: ManuallyDrop::new(socket),
+            pool: self.inner.clone(),
+            discard: false,
+            _permit: permit,
+        })
+    }
+
+    /// Returns the local IP address the sockets of the pool are bound on.
+    #[inline]
+    pub fn bind_ip(&self) -> IpAddr {
+        self.inner.bind_addr.ip()
+    }
+
+    /// Returns a snapshot of the counters of this pool.
+    pub fn stats(&self) -> UdpSocketPoolStats {
+        let counters = &self.inner.counters;
+
+        UdpSocketPoolStats {
+            size: self.inner.size,
+            idle: self
+                .inner
+                .idle
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .len(),
+            in_use: self.inner.size - self.inner.permits.available_permits(),
+            checkouts: counters.checkouts.load(Ordering::Relaxed),
+            waits: counters.waits.load(Ordering::Relaxed),
+            binds: counters.binds.load(Ordering::Relaxed),
+            discarded: counters.discarded.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl SocketPoolInner {
+    async fn bind(&self) -> Result<UdpSocket, Error> {
+        let socket = UdpSocket::bind(self.bind_addr).await?;
+        sockopt::suppress_connection_reset(&socket)?;
+        self.options.apply(&socket)?;
+
+        self.counters.binds.fetch_add(1, Ordering::Relaxed);
+        Ok(socket)
+    }
+}
+
+/// Discards the datagrams `socket` received while idle, so they do not leak into the next association, and checks it for a pending error.
+fn is_healthy(socket: &UdpSocket) -> bool {
+    let mut buf = [0; 1];
+
+    loop {
+        match socket.try_recv_from(&mut buf) {
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+            // e.g. an ICMP error of an earlier destination, which says nothing about the socket itself
+            Err(err) if err.kind() == ErrorKind::ConnectionRefused => {}
+            Err(_) => return false,
+        }
+    }
+
+    match socket.take_error() {
+        Ok(None) => true,
+        Ok(Some(err)) => err.kind() == ErrorKind::ConnectionRefused,
+        Err(_) => false,
+    }
+}
+
+impl Debug for UdpSocketPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("UdpSocketPool")
+            .field("bind_addr", &self.inner.bind_addr)
+            .field("size", &self.inner.size)
+            .finish()
+    }
+}
+
+/// A socket checked out of a [`UdpSocketPool`], going back to the pool when dropped.
+///
+/// It dereferences to a [`UdpSocket`]. The options of the pool are applied to it, so do not change socket options through it, as they would carry over to the next association.
+pub struct PooledUdpSocket {
+    socket: ManuallyDrop<UdpSocket>,
+    pool: Arc<SocketPoolInner>,
+    discard: bool,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledUdpSocket {
+    /// Drops the socket instead of giving it back to the pool, e.g. after it failed. The pool binds a fresh one when needed.
+    #[inline]
+    pub fn discard(mut self) {
+        self.discard = true;
+    }
+}
+
+impl Deref for PooledUdpSocket {
+    type Target = UdpSocket;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.socket
+    }
+}
+
+impl AsRef<UdpSocket> for PooledUdpSocket {
+    #[inline]
+    fn as_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+}
+
+impl Drop for PooledUdpSocket {
+    fn drop(&mut self) {
+        // SAFETY: the socket is not used again, as this is the last use of `self`
+        let socket = unsafe { ManuallyDrop::take(&mut self.socket) };
+
+        if self.discard {
+            self.pool.counters.discarded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.pool
+                .idle
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(socket);
+        }
+    }
+}
+
+impl Debug for PooledUdpSocket {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("PooledUdpSocket")
+            .field("socket", &*self.socket)
+            .finish()
+    }
+}
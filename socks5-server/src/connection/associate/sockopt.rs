@@ -0,0 +1,493 @@
+use super::{Associate, AssociatedUdpSocket, DatagramSocket};
+use socket2::SockRef;
+use std::{
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::{TcpStream, UdpSocket};
+
+/// IP-level options of a UDP socket, applied according to the address family of the socket.
+///
+/// For IPv4 sockets, `tos` sets `IP_TOS`, `ttl` sets `IP_TTL` and `multicast_if_v4` sets `IP_MULTICAST_IF`. For IPv6 sockets, `tos` sets `IPV6_TCLASS`, `ttl` sets `IPV6_UNICAST_HOPS` and `multicast_if_v6` sets `IPV6_MULTICAST_IF`. Options left `None` keep the OS default.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct UdpSocketOptions {
+    /// The type-of-service / traffic class byte of sent datagrams. The DSCP class is in its upper six bits, e.g. `46 << 2` for Expedited Forwarding.
+    pub tos: Option<u32>,
+
+    /// The time-to-live / hop limit of sent datagrams.
+    pub ttl: Option<u32>,
+
+    /// The address of the local interface multicast datagrams are sent from over IPv4. By default, the OS picks it from the routing table.
+    pub multicast_if_v4: Option<Ipv4Addr>,
+
+    /// The index of the local interface multicast datagrams are sent from over IPv6. By default, the OS picks it from the routing table.
+    pub multicast_if_v6: Option<u32>,
+}
+
+impl UdpSocketOptions {
+    /// Applies the options to `socket`.
+    ///
+    /// An error of kind [`ErrorKind::Unsupported`](std::io::ErrorKind::Unsupported) is returned if an option is not supported on this platform.
+    pub fn apply(&self, socket: &UdpSocket) -> Result<(), Error> {
+        if let Some(tos) = self.tos {
+            set_tos(socket, tos)?;
+        }
+
+        if let Some(ttl) = self.ttl {
+            set_ttl(socket, ttl)?;
+        }
+
+        if socket.local_addr()?.is_ipv4() {
+            if let Some(interface) = self.multicast_if_v4 {
+                SockRef::from(socket).set_multicast_if_v4(&interface)?;
+            }
+        } else if let Some(interface) = self.multicast_if_v6 {
+            SockRef::from(socket).set_multicast_if_v6(interface)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// TCP keepalive of the control connection of an association, detecting clients that vanished without closing it, e.g. after a pulled cable or a crash of the device.
+///
+/// Once nothing was received on the connection for `time`, a probe is sent every `interval`, and the connection fails after `retries` probes went unanswered, which makes [`Associate::wait_close()`] return an error of kind [`ErrorKind::TimedOut`] and the relay tear down. The client is then detected as gone within `time + interval * retries`.
+///
+/// `interval` and `retries` are only applied where `socket2` supports them, e.g. not on OpenBSD, where the system defaults are used instead.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ControlKeepalive {
+    /// How long the connection stays idle before the first probe is sent, `TCP_KEEPIDLE`.
+    pub time: Duration,
+
+    /// The interval between probes, `TCP_KEEPINTVL`.
+    pub interval: Duration,
+
+    /// The number of unanswered probes after which the connection fails, `TCP_KEEPCNT`.
+    pub retries: u32,
+}
+
+impl ControlKeepalive {
+    /// Enables the keepalive on `stream`.
+    pub fn apply(&self, stream: &TcpStream) -> Result<(), Error> {
+        SockRef::from(stream).set_tcp_keepalive(&sys::tcp_keepalive(self))
+    }
+}
+
+impl Default for ControlKeepalive {
+    /// Probes after 60 seconds of silence, every 10 seconds, giving up after 5 of them, so a vanished client is detected within 2 minutes.
+    fn default() -> Self {
+        Self {
+            time: Duration::from_secs(60),
+            interval: Duration::from_secs(10),
+            retries: 5,
+        }
+    }
+}
+
+impl<S> Associate<S> {
+    /// Enables TCP keepalive on the control connection, so [`Associate::wait_close()`] fails within a bounded time after the client vanished without closing it. See [`ControlKeepalive`].
+    ///
+    /// [`run_relay()`](super::run_relay) calls this with [`UdpRelayConfig::control_keepalive`](super::UdpRelayConfig::control_keepalive).
+    #[inline]
+    pub fn set_keepalive(&self, keepalive: &ControlKeepalive) -> Result<(), Error> {
+        keepalive.apply(self.get_ref())
+    }
+}
+
+/// Socket options, set on the kernel UDP socket of the transport. Over a transport without one (see [`DatagramSocket::as_udp_socket()`]), they fail with an error of kind [`ErrorKind::Unsupported`].
+impl<T: DatagramSocket> AssociatedUdpSocket<T> {
+    /// Returns the local address of the transport, e.g. to reply it to the client of the association.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.get_ref().local_addr()
+    }
+
+    /// Gets the size of the receive buffer of this socket, `SO_RCVBUF`.
+    #[inline]
+    pub fn recv_buffer_size(&self) -> Result<usize, Error> {
+        SockRef::from(self.udp_socket()?).recv_buffer_size()
+    }
+
+    /// Sets the size of the receive buffer of this socket, `SO_RCVBUF`. A larger buffer absorbs bursts of a high-rate relay instead of dropping datagrams.
+    ///
+    /// The OS may adjust the size, e.g. Linux doubles it to account for bookkeeping and caps it at `net.core.rmem_max`, so read it back with [`AssociatedUdpSocket::recv_buffer_size()`] if the actual size matters.
+    #[inline]
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), Error> {
+        SockRef::from(self.udp_socket()?).set_recv_buffer_size(size)
+    }
+
+    /// Gets the size of the send buffer of this socket, `SO_SNDBUF`.
+    #[inline]
+    pub fn send_buffer_size(&self) -> Result<usize, Error> {
+        SockRef::from(self.udp_socket()?).send_buffer_size()
+    }
+
+    /// Sets the size of the send buffer of this socket, `SO_SNDBUF`.
+    ///
+    /// The OS may adjust the size, e.g. Linux doubles it to account for bookkeeping and caps it at `net.core.wmem_max`, so read it back with [`AssociatedUdpSocket::send_buffer_size()`] if the actual size matters.
+    #[inline]
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), Error> {
+        SockRef::from(self.udp_socket()?).set_send_buffer_size(size)
+    }
+
+    /// Gets whether this socket may send to broadcast addresses, `SO_BROADCAST`.
+    #[inline]
+    pub fn broadcast(&self) -> Result<bool, Error> {
+        self.udp_socket()?.broadcast()
+    }
+
+    /// Sets whether this socket may send to broadcast addresses, `SO_BROADCAST`.
+    #[inline]
+    pub fn set_broadcast(&self, on: bool) -> Result<(), Error> {
+        self.udp_socket()?.set_broadcast(on)
+    }
+
+    /// Joins the IPv4 multicast group `multiaddr` on the local interface with the address `interface`, `IP_ADD_MEMBERSHIP`. If `interface` is [`Ipv4Addr::UNSPECIFIED`], the OS picks the interface.
+    #[inline]
+    pub fn join_multicast_v4(&self, multiaddr: Ipv4Addr, interface: Ipv4Addr) -> Result<(), Error> {
+        self.udp_socket()?.join_multicast_v4(multiaddr, interface)
+    }
+
+    /// Leaves the IPv4 multicast group `multiaddr` on the local interface with the address `interface`, `IP_DROP_MEMBERSHIP`. See [`AssociatedUdpSocket::join_multicast_v4()`].
+    #[inline]
+    pub fn leave_multicast_v4(
+        &self,
+        multiaddr: Ipv4Addr,
+        interface: Ipv4Addr,
+    ) -> Result<(), Error> {
+        self.udp_socket()?.leave_multicast_v4(multiaddr, interface)
+    }
+
+    /// Joins the IPv6 multicast group `multiaddr` on the local interface with the index `interface`, `IPV6_ADD_MEMBERSHIP`. If `interface` is `0`, the OS picks the interface.
+    #[inline]
+    pub fn join_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> Result<(), Error> {
+        self.udp_socket()?.join_multicast_v6(multiaddr, interface)
+    }
+
+    /// Leaves the IPv6 multicast group `multiaddr` on the local interface with the index `interface`, `IPV6_DROP_MEMBERSHIP`. See [`AssociatedUdpSocket::join_multicast_v6()`].
+    #[inline]
+    pub fn leave_multicast_v6(&self, multiaddr: &Ipv6Addr, interface: u32) -> Result<(), Error> {
+        self.udp_socket()?.leave_multicast_v6(multiaddr, interface)
+    }
+
+    /// Gets whether multicast datagrams sent from this socket are looped back to the local host, `IP_MULTICAST_LOOP` or `IPV6_MULTICAST_LOOP` depending on the address family of the socket.
+    #[inline]
+    pub fn multicast_loop(&self) -> Result<bool, Error> {
+        multicast_loop(self.udp_socket()?)
+    }
+
+    /// Sets whether multicast datagrams sent from this socket are looped back to the local host, `IP_MULTICAST_LOOP` or `IPV6_MULTICAST_LOOP` depending on the address family of the socket. It is on by default.
+    #[inline]
+    pub fn set_multicast_loop(&self, on: bool) -> Result<(), Error> {
+        set_multicast_loop(self.udp_socket()?, on)
+    }
+
+    /// Gets the type-of-service / traffic class byte of datagrams sent from this socket, `IP_TOS` or `IPV6_TCLASS` depending on the address family of the socket.
+    #[inline]
+    pub fn tos(&self) -> Result<u32, Error> {
+        tos(self.udp_socket()?)
+    }
+
+    /// Sets the type-of-service / traffic class byte of datagrams sent from this socket, `IP_TOS` or `IPV6_TCLASS` depending on the address family of the socket. Use it to mark relayed traffic with a DSCP class.
+    ///
+    /// An error of kind [`ErrorKind::Unsupported`](std::io::ErrorKind::Unsupported) is returned if the option is not supported on this platform.
+    #[inline]
+    pub fn set_tos(&self, tos: u32) -> Result<(), Error> {
+        set_tos(self.udp_socket()?, tos)
+    }
+
+    /// Gets the time-to-live / hop limit of datagrams sent from this socket, `IP_TTL` or `IPV6_UNICAST_HOPS` depending on the address family of the socket.
+    #[inline]
+    pub fn ttl(&self) -> Result<u32, Error> {
+        ttl(self.udp_socket()?)
+    }
+
+    /// Sets the time-to-live / hop limit of datagrams sent from this socket, `IP_TTL` or `IPV6_UNICAST_HOPS` depending on the address family of the socket.
+    #[inline]
+    pub fn set_ttl(&self, ttl: u32) -> Result<(), Error> {
+        set_ttl(self.udp_socket()?, ttl)
+    }
+
+    fn udp_socket(&self) -> Result<&UdpSocket, Error> {
+        self.get_ref().as_udp_socket().ok_or_else(|| {
+            Error::new(
+                ErrorKind::Unsupported,
+                "socket options are only available over a UDP socket transport",
+            )
+        })
+    }
+}
+
+fn multicast_loop(socket: &UdpSocket) -> Result<bool, Error> {
+    if socket.local_addr()?.is_ipv4() {
+        socket.multicast_loop_v4()
+    } else {
+        socket.multicast_loop_v6()
+    }
+}
+
+fn set_multicast_loop(socket: &UdpSocket, on: bool) -> Result<(), Error> {
+    if socket.local_addr()?.is_ipv4() {
+        socket.set_multicast_loop_v4(on)
+    } else {
+        socket.set_multicast_loop_v6(on)
+    }
+}
+
+fn tos(socket: &UdpSocket) -> Result<u32, Error> {
+    if socket.local_addr()?.is_ipv4() {
+        sys::tos_v4(socket)
+    } else {
+        sys::tclass_v6(socket)
+    }
+}
+
+fn set_tos(socket: &UdpSocket, tos: u32) -> Result<(), Error> {
+    if socket.local_addr()?.is_ipv4() {
+        sys::set_tos_v4(socket, tos)
+    } else {
+        sys::set_tclass_v6(socket, tos)
+    }
+}
+
+fn ttl(socket: &UdpSocket) -> Result<u32, Error> {
+    if socket.local_addr()?.is_ipv4() {
+        SockRef::from(socket).ttl_v4()
+    } else {
+        SockRef::from(socket).unicast_hops_v6()
+    }
+}
+
+fn set_ttl(socket: &UdpSocket, ttl: u32) -> Result<(), Error> {
+    if socket.local_addr()?.is_ipv4() {
+        SockRef::from(socket).set_ttl_v4(ttl)
+    } else {
+        SockRef::from(socket).set_unicast_hops_v6(ttl)
+    }
+}
+
+/// `IP_TOS` and `IPV6_TCLASS` are only available on some platforms, following `socket2`. Elsewhere, an error of kind [`ErrorKind::Unsupported`] is returned. So are the interval and retries of TCP keepalive, which are left to the system defaults elsewhere.
+mod sys {
+    use socket2::{SockRef, TcpKeepalive};
+    use std::io::{Error, ErrorKind};
+    use tokio::net::UdpSocket;
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "illumos",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    ))]
+    pub(super) fn tcp_keepalive(keepalive: &super::ControlKeepalive) -> TcpKeepalive {
+        TcpKeepalive::new()
+            .with_time(keepalive.time)
+            .with_interval(keepalive.interval)
+            .with_retries(keepalive.retries)
+    }
+
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "illumos",
+        target_os = "ios",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "windows",
+    )))]
+    pub(super) fn tcp_keepalive(keepalive: &super::ControlKeepalive) -> TcpKeepalive {
+        TcpKeepalive::new().with_time(keepalive.time)
+    }
+
+    // unused where all options are supported
+    #[allow(dead_code)]
+    fn unsupported(option: &str) -> Error {
+        Error::new(
+            ErrorKind::Unsupported,
+            format!("{option} is not supported on this platform"),
+        )
+    }
+
+    #[cfg(not(any(
+        target_os = "fuchsia",
+        target_os = "redox",
+        target_os = "solaris",
+        target_os = "haiku",
+        target_os = "wasi",
+    )))]
+    pub(super) fn tos_v4(socket: &UdpSocket) -> Result<u32, Error> {
+        SockRef::from(socket).tos_v4()
+    }
+
+    #[cfg(not(any(
+        target_os = "fuchsia",
+        target_os = "redox",
+        target_os = "solaris",
+        target_os = "haiku",
+        target_os = "wasi",
+    )))]
+    pub(super) fn set_tos_v4(socket: &UdpSocket, tos: u32) -> Result<(), Error> {
+        SockRef::from(socket).set_tos_v4(tos)
+    }
+
+    #[cfg(any(
+        target_os = "fuchsia",
+        target_os = "redox",
+        target_os = "solaris",
+        target_os = "haiku",
+        target_os = "wasi",
+    ))]
+    pub(super) fn tos_v4(_: &UdpSocket) -> Result<u32, Error> {
+        Err(unsupported("IP_TOS"))
+    }
+
+    #[cfg(any(
+        target_os = "fuchsia",
+        target_os = "redox",
+        target_os = "solaris",
+        target_os = "haiku",
+        target_os = "wasi",
+    ))]
+    pub(super) fn set_tos_v4(_: &UdpSocket, _: u32) -> Result<(), Error> {
+        Err(unsupported("IP_TOS"))
+    }
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+    ))]
+    pub(super) fn tclass_v6(socket: &UdpSocket) -> Result<u32, Error> {
+        SockRef::from(socket).tclass_v6()
+    }
+
+    #[cfg(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+    ))]
+    pub(super) fn set_tclass_v6(socket: &UdpSocket, tclass: u32) -> Result<(), Error> {
+        SockRef::from(socket).set_tclass_v6(tclass)
+    }
+
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+    )))]
+    pub(super) fn tclass_v6(_: &UdpSocket) -> Result<u32, Error> {
+        Err(unsupported("IPV6_TCLASS"))
+    }
+
+    #[cfg(not(any(
+        target_os = "android",
+        target_os = "dragonfly",
+        target_os = "freebsd",
+        target_os = "fuchsia",
+        target_os = "linux",
+        target_os = "macos",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "illumos",
+    )))]
+    pub(super) fn set_tclass_v6(_: &UdpSocket, _: u32) -> Result<(), Error> {
+        Err(unsupported("IPV6_TCLASS"))
+    }
+}
+
+/// Stops `socket` from failing receives with [`ErrorKind::ConnectionReset`] after a datagram it sent was answered with an ICMP port unreachable message, which Windows does by default. It does nothing on other platforms.
+pub(super) fn suppress_connection_reset(socket: &UdpSocket) -> Result<(), Error> {
+    #[cfg(windows)]
+    {
+        windows::disable_udp_connreset(socket)
+    }
+
+    #[cfg(not(windows))]
+    {
+        let _ = socket;
+        Ok(())
+    }
+}
+
+/// Whether `err` is the [`ErrorKind::ConnectionReset`] a UDP socket fails a receive with after one of its datagrams hit a closed port, which only concerns that datagram and not the socket.
+#[inline]
+pub(super) fn is_connection_reset(err: &Error) -> bool {
+    err.kind() == ErrorKind::ConnectionReset
+}
+
+#[cfg(windows)]
+mod windows {
+    use std::{ffi::c_void, io::Error, mem, os::windows::io::AsRawSocket, ptr};
+    use tokio::net::UdpSocket;
+
+    /// `_WSAIOW(IOC_VENDOR, 12)`
+    const SIO_UDP_CONNRESET: u32 = 0x9800_000c;
+
+    #[link(name = "ws2_32")]
+    extern "system" {
+        fn WSAIoctl(
+            socket: usize,
+            code: u32,
+            in_buf: *const c_void,
+            in_len: u32,
+            out_buf: *mut c_void,
+            out_len: u32,
+            returned: *mut u32,
+            overlapped: *mut c_void,
+            completion: *mut c_void,
+        ) -> i32;
+    }
+
+    pub(super) fn disable_udp_connreset(socket: &UdpSocket) -> Result<(), Error> {
+        let enable: u32 = 0;
+        let mut returned = 0;
+
+        // SAFETY: the socket handle is valid for the lifetime of `socket`, and the input buffer is a `BOOL` living across the call
+        let res = unsafe {
+            WSAIoctl(
+                socket.as_raw_socket() as usize,
+                SIO_UDP_CONNRESET,
+                &enable as *const u32 as *const c_void,
+                mem::size_of::<u32>() as u32,
+                ptr::null_mut(),
+                0,
+                &mut returned,
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(Error::last_os_error())
+        }
+    }
+}
@@ -0,0 +1,433 @@
+use super::{
+    socket::{TryRecvFrom, TryRecvFromBuf},
+    AssociatedUdpSocket, ClientMatch, DatagramSocket, DropReason, UdpRelayStats,
+};
+use crate::dns::Resolver;
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Address, Error as Socks5Error, UdpHeader};
+use std::{
+    borrow::BorrowMut,
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    io::Error,
+    net::{IpAddr, SocketAddr},
+    ops::Range,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+impl<T: DatagramSocket> AssociatedUdpSocket<T> {
+    /// Splits the socket into a receive half and a send half, which can be used from different tasks.
+    ///
+    /// Both halves share the socket through an `Arc` and can be cloned cheaply. The socket can be recovered with [`RecvHalf::reunite()`] once all clones but one of each half are dropped.
+    pub fn split(self) -> (RecvHalf<T>, SendHalf<T>) {
+        let socket = Arc::new(self);
+
+        (
+            RecvHalf {
+                socket: socket.clone(),
+            },
+            SendHalf { socket },
+        )
+    }
+}
+
+/// The receive half of an [`AssociatedUdpSocket`], created by [`AssociatedUdpSocket::split()`].
+///
+/// Besides receiving, it controls which client address datagrams are accepted from and the maximum receiving UDP packet size.
+#[derive(Debug)]
+pub struct RecvHalf<T = UdpSocket> {
+    socket: Arc<AssociatedUdpSocket<T>>,
+}
+
+impl<T: DatagramSocket> RecvHalf<T> {
+    /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected. See [`AssociatedUdpSocket::recv()`].
+    #[inline]
+    pub async fn recv(&self) -> Result<(Bytes, UdpHeader), (Socks5Error, Option<Vec<u8>>)> {
+        self.socket.recv().await
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from a remote address. See [`AssociatedUdpSocket::recv_from()`].
+    #[inline]
+    pub async fn recv_from(
+        &self,
+    ) -> Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Vec<u8>>)> {
+        self.socket.recv_from().await
+    }
+
+    /// Receives a valid SOCKS5 UDP packet on the socket from a remote address, discarding invalid datagrams. See [`AssociatedUdpSocket::recv_from_valid()`].
+    #[inline]
+    pub async fn recv_from_valid(&self) -> Result<(Bytes, UdpHeader, SocketAddr), Error> {
+        self.socket.recv_from_valid().await
+    }
+
+    /// Receives a valid SOCKS5 UDP packet on the socket from a remote address, calling `on_invalid` for every discarded datagram. See [`AssociatedUdpSocket::recv_from_valid_with()`].
+    #[inline]
+    pub async fn recv_from_valid_with<F>(
+        &self,
+        on_invalid: F,
+    ) -> Result<(Bytes, UdpHeader, SocketAddr), Error>
+    where
+        F: FnMut(Socks5Error, SocketAddr),
+    {
+        self.socket.recv_from_valid_with(on_invalid).await
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected, appending it to a caller-provided buffer. See [`AssociatedUdpSocket::recv_buf()`].
+    #[inline]
+    pub async fn recv_buf(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(UdpHeader, Range<usize>), (Socks5Error, Option<Range<usize>>)> {
+        self.socket.recv_buf(buf).await
+    }
+
+    /// Receives a SOCKS5 UDP packet on the socket from a remote address, appending it to a caller-provided buffer. See [`AssociatedUdpSocket::recv_from_buf()`].
+    #[inline]
+    pub async fn recv_from_buf(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(UdpHeader, Range<usize>, SocketAddr), (Socks5Error, Option<Range<usize>>)> {
+        self.socket.recv_from_buf(buf).await
+    }
+
+    /// Attempts to receive a SOCKS5 UDP packet on the socket from the remote address which it is connected. See [`AssociatedUdpSocket::poll_recv()`].
+    #[inline]
+    pub fn poll_recv(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(UdpHeader, usize), Socks5Error>> {
+        self.socket.poll_recv(cx, buf)
+    }
+
+    /// Attempts to receive a SOCKS5 UDP packet on the socket from a remote address. See [`AssociatedUdpSocket::poll_recv_from()`].
+    #[inline]
+    pub fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(UdpHeader, usize, SocketAddr), Socks5Error>> {
+        self.socket.poll_recv_from(cx, buf)
+    }
+
+    /// Sets the client address that datagrams are accepted from. See [`AssociatedUdpSocket::set_expected_client()`].
+    #[inline]
+    pub fn set_expected_client(&self, addr: SocketAddr, matching: ClientMatch) {
+        self.socket.set_expected_client(addr, matching);
+    }
+
+    /// Learns the client address from the first datagram coming from `ip`. See [`AssociatedUdpSocket::learn_client()`].
+    #[inline]
+    pub fn learn_client(&self, ip: IpAddr, matching: ClientMatch) {
+        self.socket.learn_client(ip, matching);
+    }
+
+    /// Sets the expected client from the address the client declared in the associate command. See [`AssociatedUdpSocket::set_declared_client()`].
+    #[inline]
+    pub fn set_declared_client(&self, declared: &Address, peer_ip: IpAddr, matching: ClientMatch) {
+        self.socket.set_declared_client(declared, peer_ip, matching);
+    }
+
+    /// Sets whether fragmented datagrams are delivered. See [`AssociatedUdpSocket::allow_fragments()`].
+    #[inline]
+    pub fn allow_fragments(&self, allow: bool) {
+        self.socket.allow_fragments(allow);
+    }
+
+    /// Returns whether fragmented datagrams are delivered. See [`AssociatedUdpSocket::allow_fragments()`].
+    #[inline]
+    pub fn fragments_allowed(&self) -> bool {
+        self.socket.fragments_allowed()
+    }
+
+    /// Sets a callback called on every dropped fragmented datagram. See [`AssociatedUdpSocket::on_dropped_fragment()`].
+    #[inline]
+    pub fn on_dropped_fragment<F>(&self, f: F)
+    where
+        F: Fn(&UdpHeader, SocketAddr) + Send + Sync + 'static,
+    {
+        self.socket.on_dropped_fragment(f);
+    }
+
+    /// Sets a callback called on every dropped datagram. See [`AssociatedUdpSocket::on_drop()`].
+    #[inline]
+    pub fn on_drop<F>(&self, f: F)
+    where
+        F: Fn(DropReason) + Send + Sync + 'static,
+    {
+        self.socket.on_drop(f);
+    }
+
+    /// Removes the expected client, accepting datagrams from any source again.
+    #[inline]
+    pub fn clear_expected_client(&self) {
+        self.socket.clear_expected_client();
+    }
+
+    /// Returns the expected client address, if set or learned.
+    #[inline]
+    pub fn expected_client(&self) -> Option<SocketAddr> {
+        self.socket.expected_client()
+    }
+
+    /// Returns the number of datagrams dropped because their source did not match the expected client.
+    #[inline]
+    pub fn filtered_count(&self) -> u64 {
+        self.socket.filtered_count()
+    }
+
+    /// Get the maximum receiving UDP packet size, with SOCKS5 UDP header included.
+    #[inline]
+    pub fn get_max_pkt_size(&self) -> usize {
+        self.socket.get_max_pkt_size()
+    }
+
+    /// Set the maximum receiving UDP packet size, with SOCKS5 UDP header included, for adjusting the receiving buffer size.
+    #[inline]
+    pub fn set_max_pkt_size(&self, size: usize) {
+        self.socket.set_max_pkt_size(size);
+    }
+
+    /// Returns the statistics handle of the socket.
+    #[inline]
+    pub fn stats(&self) -> &UdpRelayStats {
+        self.socket.stats()
+    }
+
+    /// Returns a shared reference to the underlying socket.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.socket.get_ref()
+    }
+
+    /// Reunites with a [`SendHalf`] to recover the original [`AssociatedUdpSocket`].
+    ///
+    /// This fails if the halves come from different sockets, or if clones of either half still exist. The halves are returned in the [`ReuniteError`] then.
+    pub fn reunite(self, other: SendHalf<T>) -> Result<AssociatedUdpSocket<T>, ReuniteError<T>> {
+        if !Arc::ptr_eq(&self.socket, &other.socket) {
+            return Err(ReuniteError(self, other));
+        }
+
+        drop(other);
+
+        Arc::try_unwrap(self.socket).map_err(|socket| {
+            ReuniteError(
+                Self {
+                    socket: socket.clone(),
+                },
+                SendHalf { socket },
+            )
+        })
+    }
+}
+
+impl RecvHalf {
+    /// Receives a batch of SOCKS5 UDP packets on the socket from remote addresses. See [`AssociatedUdpSocket::recv_batch()`].
+    #[inline]
+    pub async fn recv_batch<B: BorrowMut<BytesMut>>(
+        &self,
+        bufs: &mut [B],
+    ) -> Result<Vec<(UdpHeader, Range<usize>, SocketAddr)>, Error> {
+        self.socket.recv_batch(bufs).await
+    }
+
+    /// Tries to receive a SOCKS5 UDP packet on the socket from a remote address without waiting. See [`AssociatedUdpSocket::try_recv_from()`].
+    #[inline]
+    pub fn try_recv_from(&self) -> TryRecvFrom {
+        self.socket.try_recv_from()
+    }
+
+    /// Tries to receive a SOCKS5 UDP packet on the socket from a remote address without waiting, appending it to a caller-provided buffer. See [`AssociatedUdpSocket::try_recv_from_buf()`].
+    #[inline]
+    pub fn try_recv_from_buf(&self, buf: &mut BytesMut) -> TryRecvFromBuf {
+        self.socket.try_recv_from_buf(buf)
+    }
+}
+
+impl<T> Clone for RecvHalf<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            socket: self.socket.clone(),
+        }
+    }
+}
+
+/// The send half of an [`AssociatedUdpSocket`], created by [`AssociatedUdpSocket::split()`].
+///
+/// Besides sending, it controls the maximum sending UDP packet size.
+#[derive(Debug)]
+pub struct SendHalf<T = UdpSocket> {
+    socket: Arc<AssociatedUdpSocket<T>>,
+}
+
+impl<T: DatagramSocket> SendHalf<T> {
+    /// Sends a UDP packet to the remote address which it is connected. The SOCKS5 UDP header will be added to the packet.
+    #[inline]
+    pub async fn send<P: AsRef<[u8]>>(&self, pkt: P, header: &UdpHeader) -> Result<usize, Error> {
+        self.socket.send(pkt, header).await
+    }
+
+    /// Sends a UDP packet to a specified remote address. The SOCKS5 UDP header will be added to the packet.
+    #[inline]
+    pub async fn send_to<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        self.socket.send_to(pkt, header, addr).await
+    }
+
+    /// Sends a UDP packet to the remote address which it is connected, writing the SOCKS5 UDP header into the headroom in front of the payload. See [`AssociatedUdpSocket::send_with_headroom()`].
+    #[inline]
+    pub async fn send_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+    ) -> Result<usize, Error> {
+        self.socket.send_with_headroom(buf, headroom, header).await
+    }
+
+    /// Sends a UDP packet to a specified remote address, writing the SOCKS5 UDP header into the headroom in front of the payload. See [`AssociatedUdpSocket::send_to_with_headroom()`].
+    #[inline]
+    pub async fn send_to_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        self.socket
+            .send_to_with_headroom(buf, headroom, header, addr)
+            .await
+    }
+
+    /// Sends a UDP packet to a SOCKS5 address. See [`AssociatedUdpSocket::send_to_address()`].
+    #[inline]
+    pub async fn send_to_address<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: &Address,
+    ) -> Result<usize, Error> {
+        self.socket.send_to_address(pkt, header, addr).await
+    }
+
+    /// Attempts to send a UDP packet to the remote address which it is connected. See [`AssociatedUdpSocket::poll_send()`].
+    #[inline]
+    pub fn poll_send(
+        &self,
+        cx: &mut Context<'_>,
+        pkt: &[u8],
+        header: &UdpHeader,
+    ) -> Poll<Result<usize, Error>> {
+        self.socket.poll_send(cx, pkt, header)
+    }
+
+    /// Attempts to send a UDP packet to a specified remote address. See [`AssociatedUdpSocket::poll_send_to()`].
+    #[inline]
+    pub fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        pkt: &[u8],
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        self.socket.poll_send_to(cx, pkt, header, addr)
+    }
+
+    /// Returns the expected client address, if set or learned, which is usually where packets are sent to.
+    #[inline]
+    pub fn expected_client(&self) -> Option<SocketAddr> {
+        self.socket.expected_client()
+    }
+
+    /// Get the maximum sending UDP packet size, with SOCKS5 UDP header included.
+    #[inline]
+    pub fn get_max_send_pkt_size(&self) -> usize {
+        self.socket.get_max_send_pkt_size()
+    }
+
+    /// Set the maximum sending UDP packet size, with SOCKS5 UDP header included. See [`AssociatedUdpSocket::set_max_send_pkt_size()`].
+    #[inline]
+    pub fn set_max_send_pkt_size(&self, size: usize) {
+        self.socket.set_max_send_pkt_size(size);
+    }
+
+    /// Returns the resolver used for resolving domain addresses.
+    #[inline]
+    pub fn resolver(&self) -> &Arc<dyn Resolver + Send + Sync> {
+        self.socket.resolver()
+    }
+
+    /// Returns the statistics handle of the socket.
+    #[inline]
+    pub fn stats(&self) -> &UdpRelayStats {
+        self.socket.stats()
+    }
+
+    /// Returns a shared reference to the underlying socket.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.socket.get_ref()
+    }
+
+    /// Reunites with a [`RecvHalf`] to recover the original [`AssociatedUdpSocket`]. See [`RecvHalf::reunite()`].
+    #[inline]
+    pub fn reunite(self, other: RecvHalf<T>) -> Result<AssociatedUdpSocket<T>, ReuniteError<T>> {
+        other.reunite(self)
+    }
+}
+
+impl SendHalf {
+    /// Sends a batch of UDP packets to specified remote addresses. See [`AssociatedUdpSocket::send_batch()`].
+    #[inline]
+    pub async fn send_batch<P: AsRef<[u8]>>(
+        &self,
+        pkts: &[(P, UdpHeader, SocketAddr)],
+    ) -> Result<usize, Error> {
+        self.socket.send_batch(pkts).await
+    }
+
+    /// Tries to send a UDP packet to a specified remote address without waiting. See [`AssociatedUdpSocket::try_send_to()`].
+    #[inline]
+    pub fn try_send_to<P: AsRef<[u8]>>(
+        &self,
+        pkt: P,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<Option<usize>, Error> {
+        self.socket.try_send_to(pkt, header, addr)
+    }
+}
+
+impl<T> Clone for SendHalf<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            socket: self.socket.clone(),
+        }
+    }
+}
+
+/// Error returned when reuniting halves that are not from the same socket, or while clones of them still exist.
+#[derive(Debug)]
+pub struct ReuniteError<T = UdpSocket>(pub RecvHalf<T>, pub SendHalf<T>);
+
+impl<T> Display for ReuniteError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str(
+            "tried to reunite halves that are not from the same socket, or that are still shared",
+        )
+    }
+}
+
+impl<T: Debug> StdError for ReuniteError<T> {}
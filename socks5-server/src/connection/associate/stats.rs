@@ -0,0 +1,262 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A cloneable handle to the statistics of a UDP association.
+///
+/// [`AssociatedUdpSocket`](super::AssociatedUdpSocket) records datagrams sent to the client and datagrams it drops, while [`run_relay()`](super::run_relay) additionally records datagrams forwarded to destinations and the ones it drops by policy. Custom relay loops can feed the same counters with the `record_*` methods.
+///
+/// All counters are relaxed atomics, so the handle can be read from another task while the association is live with [`UdpRelayStats::snapshot()`].
+#[derive(Clone, Debug)]
+pub struct UdpRelayStats {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Debug)]
+struct StatsInner {
+    created: Instant,
+    last_activity: AtomicU64,
+    uplink_packets: AtomicU64,
+    uplink_bytes: AtomicU64,
+    downlink_packets: AtomicU64,
+    downlink_bytes: AtomicU64,
+    dropped_filtered: AtomicU64,
+    dropped_malformed: AtomicU64,
+    dropped_fragment: AtomicU64,
+    dropped_oversize: AtomicU64,
+    dropped_denied: AtomicU64,
+    dropped_unresolved: AtomicU64,
+    dropped_rate_limited: AtomicU64,
+    dropped_quota: AtomicU64,
+    client_rebinds: AtomicU64,
+    socket_rebinds: AtomicU64,
+    connection_resets: AtomicU64,
+}
+
+impl UdpRelayStats {
+    /// Creates a new [`UdpRelayStats`] with all counters set to zero.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(StatsInner {
+                created: Instant::now(),
+                last_activity: AtomicU64::new(0),
+                uplink_packets: AtomicU64::new(0),
+                uplink_bytes: AtomicU64::new(0),
+                downlink_packets: AtomicU64::new(0),
+                downlink_bytes: AtomicU64::new(0),
+                dropped_filtered: AtomicU64::new(0),
+                dropped_malformed: AtomicU64::new(0),
+                dropped_fragment: AtomicU64::new(0),
+                dropped_oversize: AtomicU64::new(0),
+                dropped_denied: AtomicU64::new(0),
+                dropped_unresolved: AtomicU64::new(0),
+                dropped_rate_limited: AtomicU64::new(0),
+                dropped_quota: AtomicU64::new(0),
+                client_rebinds: AtomicU64::new(0),
+                socket_rebinds: AtomicU64::new(0),
+                connection_resets: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Records a datagram with `len` bytes of payload forwarded from the client to its destination.
+    #[inline]
+    pub fn record_uplink(&self, len: usize) {
+        self.inner.uplink_packets.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .uplink_bytes
+            .fetch_add(len as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Records a datagram with `len` bytes of payload sent to the client.
+    #[inline]
+    pub fn record_downlink(&self, len: usize) {
+        self.inner.downlink_packets.fetch_add(1, Ordering::Relaxed);
+        self.inner
+            .downlink_bytes
+            .fetch_add(len as u64, Ordering::Relaxed);
+        self.touch();
+    }
+
+    /// Records a dropped datagram.
+    #[inline]
+    pub fn record_drop(&self, reason: DropReason) {
+        self.record_drops(reason, 1);
+    }
+
+    /// Records `count` dropped datagrams.
+    #[inline]
+    pub fn record_drops(&self, reason: DropReason, count: u64) {
+        self.drop_counter(reason)
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Returns the number of datagrams dropped for `reason`.
+    #[inline]
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.drop_counter(reason).load(Ordering::Relaxed)
+    }
+
+    /// Records a move of the expected client to a new port. See [`ClientMatch::Rebind`](super::ClientMatch::Rebind).
+    #[inline]
+    pub fn record_rebind(&self) {
+        self.inner.client_rebinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a failing socket of the relay replaced with a freshly bound one. See [`RebindPolicy`](super::RebindPolicy).
+    #[inline]
+    pub fn record_socket_rebind(&self) {
+        self.inner.socket_rebinds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a receive failed with [`ErrorKind::ConnectionReset`](std::io::ErrorKind::ConnectionReset) and skipped, as reported after a datagram hit a closed port.
+    #[inline]
+    pub fn record_connection_reset(&self) {
+        self.inner.connection_resets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Marks the association as active now.
+    #[inline]
+    pub fn touch(&self) {
+        let elapsed = self.inner.created.elapsed().as_millis() as u64;
+        self.inner
+            .last_activity
+            .fetch_max(elapsed, Ordering::Relaxed);
+    }
+
+    /// Returns the time of the last recorded activity, or the creation time of this handle if there is none.
+    #[inline]
+    pub fn last_activity(&self) -> Instant {
+        let elapsed = self.inner.last_activity.load(Ordering::Relaxed);
+        self.inner.created + Duration::from_millis(elapsed)
+    }
+
+    /// Returns a snapshot of all counters.
+    pub fn snapshot(&self) -> UdpRelayStatsSnapshot {
+        let inner = &*self.inner;
+
+        UdpRelayStatsSnapshot {
+            uplink_packets: inner.uplink_packets.load(Ordering::Relaxed),
+            uplink_bytes: inner.uplink_bytes.load(Ordering::Relaxed),
+            downlink_packets: inner.downlink_packets.load(Ordering::Relaxed),
+            downlink_bytes: inner.downlink_bytes.load(Ordering::Relaxed),
+            dropped_filtered: inner.dropped_filtered.load(Ordering::Relaxed),
+            dropped_malformed: inner.dropped_malformed.load(Ordering::Relaxed),
+            dropped_fragment: inner.dropped_fragment.load(Ordering::Relaxed),
+            dropped_oversize: inner.dropped_oversize.load(Ordering::Relaxed),
+            dropped_denied: inner.dropped_denied.load(Ordering::Relaxed),
+            dropped_unresolved: inner.dropped_unresolved.load(Ordering::Relaxed),
+            dropped_rate_limited: inner.dropped_rate_limited.load(Ordering::Relaxed),
+            dropped_quota: inner.dropped_quota.load(Ordering::Relaxed),
+            client_rebinds: inner.client_rebinds.load(Ordering::Relaxed),
+            socket_rebinds: inner.socket_rebinds.load(Ordering::Relaxed),
+            connection_resets: inner.connection_resets.load(Ordering::Relaxed),
+            last_activity: self.last_activity(),
+        }
+    }
+
+    fn drop_counter(&self, reason: DropReason) -> &AtomicU64 {
+        match reason {
+            DropReason::Filtered => &self.inner.dropped_filtered,
+            DropReason::Malformed => &self.inner.dropped_malformed,
+            DropReason::Fragment => &self.inner.dropped_fragment,
+            DropReason::Oversize => &self.inner.dropped_oversize,
+            DropReason::Denied => &self.inner.dropped_denied,
+            DropReason::Unresolved => &self.inner.dropped_unresolved,
+            DropReason::RateLimited => &self.inner.dropped_rate_limited,
+            DropReason::Quota => &self.inner.dropped_quota,
+        }
+    }
+}
+
+impl Default for UdpRelayStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The reason a datagram was dropped
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DropReason {
+    /// The source address did not match the expected client, or a returning datagram did not come from a destination of the client.
+    Filtered,
+
+    /// The SOCKS5 UDP header could not be parsed.
+    Malformed,
+
+    /// The datagram was a fragment that was not reassembled.
+    Fragment,
+
+    /// The datagram was too large.
+    Oversize,
+
+    /// The destination was denied by policy.
+    Denied,
+
+    /// The destination domain could not be resolved, or the datagram could not be sent to it.
+    Unresolved,
+
+    /// The datagram was over the rate limit of its direction.
+    RateLimited,
+
+    /// The datagram was over the quota of the user of the association.
+    Quota,
+}
+
+/// A point-in-time copy of [`UdpRelayStats`]
+#[derive(Clone, Copy, Debug)]
+pub struct UdpRelayStatsSnapshot {
+    /// Datagrams forwarded from the client to destinations
+    pub uplink_packets: u64,
+
+    /// Payload bytes forwarded from the client to destinations
+    pub uplink_bytes: u64,
+
+    /// Datagrams sent to the client
+    pub downlink_packets: u64,
+
+    /// Payload bytes sent to the client
+    pub downlink_bytes: u64,
+
+    /// Datagrams dropped because of a source address other than the expected client or its destinations
+    pub dropped_filtered: u64,
+
+    /// Datagrams dropped because of a malformed SOCKS5 UDP header
+    pub dropped_malformed: u64,
+
+    /// Fragments dropped
+    pub dropped_fragment: u64,
+
+    /// Datagrams dropped because they were too large
+    pub dropped_oversize: u64,
+
+    /// Datagrams dropped because the destination was denied by policy
+    pub dropped_denied: u64,
+
+    /// Datagrams dropped because the destination could not be resolved or reached
+    pub dropped_unresolved: u64,
+
+    /// Datagrams dropped because they were over the rate limit of their direction
+    pub dropped_rate_limited: u64,
+
+    /// Datagrams dropped because they were over the quota of the user of the association
+    pub dropped_quota: u64,
+
+    /// Times the expected client moved to a new port of the same IP address
+    pub client_rebinds: u64,
+
+    /// Times a failing socket of the relay was replaced with a freshly bound one
+    pub socket_rebinds: u64,
+
+    /// Receives failed with [`ErrorKind::ConnectionReset`](std::io::ErrorKind::ConnectionReset) and skipped, which Windows reports after a datagram hit a closed port if the socket was not set up to suppress it
+    pub connection_resets: u64,
+
+    /// The time of the last activity of the association
+    pub last_activity: Instant,
+}
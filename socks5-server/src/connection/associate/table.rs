@@ -0,0 +1,427 @@
+use super::addr::CanonicalAddr;
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    hash::{BuildHasher, RandomState},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// A table of the destinations each client of a UDP relay has talked to, keyed by (client endpoint, destination).
+///
+/// A relay inserts an entry with [`UdpAssociationTable::insert()`] when forwarding a datagram from a client to a destination, and looks it up with [`UdpAssociationTable::get()`] when a datagram comes back from that destination, so return traffic can be matched to the client which asked for it. A value of type `V` can be kept alongside each entry, e.g. the address the client originally sent to.
+///
+/// Addresses are compared with v4-mapped IPv6 addresses converted to the IPv4 addresses they map, so an entry inserted with the IPv4 address of a peer is found with the address a dual-stack socket reports for it, and vice versa.
+///
+/// Entries expire once they have not been inserted or looked up for the TTL of the table. When the table is full, the least recently used entry is evicted. The number of destinations of each client can be capped, in which case datagrams to new destinations over the cap should be dropped by the relay.
+///
+/// The table is split into mutex-guarded shards by client endpoint, one per 1024 entries of capacity up to the available parallelism. Capacity is split evenly across shards and the LRU order is kept per shard, so the entries of one client always leave in exact LRU order, but a busy shard may evict before the whole table is full.
+///
+/// Cloning a [`UdpAssociationTable`] gives another handle to the same table. [`run_relay()`](super::run_relay) keeps one per association, see [`UdpRelayConfig::max_destinations`](super::UdpRelayConfig::max_destinations).
+#[derive(Clone)]
+pub struct UdpAssociationTable<V = ()> {
+    inner: Arc<TableInner<V>>,
+}
+
+struct TableInner<V> {
+    shards: Box<[Mutex<Shard<V>>]>,
+    hasher: RandomState,
+    capacity: usize,
+    shard_cap: usize,
+    ttl: Duration,
+    max_per_client: Option<usize>,
+    inserted: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evicted: AtomicU64,
+    expired: AtomicU64,
+    rejected: AtomicU64,
+}
+
+struct Shard<V> {
+    entries: HashMap<(CanonicalAddr, CanonicalAddr), TableEntry<V>>,
+    lru: BTreeMap<u64, (CanonicalAddr, CanonicalAddr)>,
+    clients: HashMap<CanonicalAddr, usize>,
+    tick: u64,
+}
+
+struct TableEntry<V> {
+    value: V,
+    tick: u64,
+    last_used: Instant,
+}
+
+impl<V: Clone> UdpAssociationTable<V> {
+    /// Creates a new [`UdpAssociationTable`] holding at most `capacity` entries (at least one), expiring them after `ttl` without use, and allowing at most `max_per_client` destinations per client if set.
+    pub fn new(capacity: usize, ttl: Duration, max_per_client: Option<usize>) -> Self {
+        let shards = thread::available_parallelism()
+            .map_or(4, |n| n.get())
+            .clamp(1, 64)
+            .min(capacity / 1024)
+            .max(1);
+
+        Self::with_shards(capacity, ttl, max_per_client, shards)
+    }
+
+    /// Creates a new [`UdpAssociationTable`] like [`UdpAssociationTable::new()`], split into `shards` shards. A table used by a single client should have a single shard, so its whole capacity is available to that client.
+    pub(super) fn with_shards(
+        capacity: usize,
+        ttl: Duration,
+        max_per_client: Option<usize>,
+        shards: usize,
+    ) -> Self {
+        Self {
+            inner: Arc::new(TableInner {
+                shards: (0..shards).map(|_| Mutex::new(Shard::new())).collect(),
+                hasher: RandomState::new(),
+                capacity,
+                shard_cap: capacity.div_ceil(shards).max(1),
+                ttl,
+                max_per_client,
+                inserted: AtomicU64::new(0),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                evicted: AtomicU64::new(0),
+                expired: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Records that `client` sent a datagram to `dst`, keeping `value` alongside the entry.
+    ///
+    /// If the entry already exists, its value is replaced and it is marked as used now. If it is new and the table is full, the least recently used entry is evicted to make room.
+    ///
+    /// Returns `false`, leaving the table unchanged, if the entry is new and `client` already has the maximum number of destinations.
+    pub fn insert(&self, client: SocketAddr, dst: SocketAddr, value: V) -> bool {
+        let (client, dst) = (CanonicalAddr::from(client), CanonicalAddr::from(dst));
+        let now = Instant::now();
+        let mut shard = self.inner.shard(&client);
+        self.inner.purge(&mut shard, now);
+
+        let tick = shard.next_tick();
+
+        if let Some(entry) = shard.entries.get_mut(&(client, dst)) {
+            let old = entry.tick;
+            entry.value = value;
+            entry.tick = tick;
+            entry.last_used = now;
+
+            shard.lru.remove(&old);
+            shard.lru.insert(tick, (client, dst));
+            return true;
+        }
+
+        let destinations = shard.clients.get(&client).copied().unwrap_or(0);
+
+        if self
+            .inner
+            .max_per_client
+            .is_some_and(|max| destinations >= max)
+        {
+            self.inner.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        while shard.entries.len() >= self.inner.shard_cap {
+            let Some((_, key)) = shard.lru.pop_first() else {
+                break;
+            };
+
+            shard.remove(&key);
+            self.inner.evicted.fetch_add(1, Ordering::Relaxed);
+        }
+
+        shard.entries.insert(
+            (client, dst),
+            TableEntry {
+                value,
+                tick,
+                last_used: now,
+            },
+        );
+        shard.lru.insert(tick, (client, dst));
+        *shard.clients.entry(client).or_default() += 1;
+        self.inner.inserted.fetch_add(1, Ordering::Relaxed);
+
+        true
+    }
+
+    /// Looks up the entry of `client` and `dst`, e.g. when a datagram comes back from `dst`, returning its value and marking it as used now.
+    ///
+    /// Returns `None` if `client` did not send to `dst`, or the entry has expired or been evicted.
+    pub fn get(&self, client: SocketAddr, dst: SocketAddr) -> Option<V> {
+        let (client, dst) = (CanonicalAddr::from(client), CanonicalAddr::from(dst));
+        let now = Instant::now();
+        let mut shard = self.inner.shard(&client);
+        self.inner.purge(&mut shard, now);
+
+        let tick = shard.next_tick();
+
+        let Some(entry) = shard.entries.get_mut(&(client, dst)) else {
+            self.inner.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let old = entry.tick;
+        entry.tick = tick;
+        entry.last_used = now;
+        let value = entry.value.clone();
+
+        shard.lru.remove(&old);
+        shard.lru.insert(tick, (client, dst));
+        self.inner.hits.fetch_add(1, Ordering::Relaxed);
+
+        Some(value)
+    }
+
+    /// Returns whether there is a live entry for `client` and `dst`, without marking it as used.
+    pub fn contains(&self, client: SocketAddr, dst: SocketAddr) -> bool {
+        let (client, dst) = (CanonicalAddr::from(client), CanonicalAddr::from(dst));
+        let shard = self.inner.shard(&client);
+
+        shard
+            .entries
+            .get(&(client, dst))
+            .is_some_and(|entry| entry.last_used + self.inner.ttl > Instant::now())
+    }
+
+    /// Removes the entry of `client` and `dst`, returning its value if it was live.
+    pub fn remove(&self, client: SocketAddr, dst: SocketAddr) -> Option<V> {
+        let (client, dst) = (CanonicalAddr::from(client), CanonicalAddr::from(dst));
+        let now = Instant::now();
+        let mut shard = self.inner.shard(&client);
+        self.inner.purge(&mut shard, now);
+
+        shard.remove(&(client, dst)).map(|entry| {
+            shard.lru.remove(&entry.tick);
+            entry.value
+        })
+    }
+
+    /// Removes all entries of `client`, e.g. when its association is torn down, returning how many there were.
+    pub fn remove_client(&self, client: SocketAddr) -> usize {
+        let client = CanonicalAddr::from(client);
+        let mut shard = self.inner.shard(&client);
+
+        let Some(count) = shard.clients.remove(&client) else {
+            return 0;
+        };
+
+        let Shard { entries, lru, .. } = &mut *shard;
+
+        entries.retain(|(entry_client, _), entry| {
+            let keep = *entry_client != client;
+
+            if !keep {
+                lru.remove(&entry.tick);
+            }
+
+            keep
+        });
+
+        count
+    }
+
+    /// Returns the number of live destinations of `client`.
+    pub fn destinations(&self, client: SocketAddr) -> usize {
+        let client = CanonicalAddr::from(client);
+        let mut shard = self.inner.shard(&client);
+        self.inner.purge(&mut shard, Instant::now());
+        shard.clients.get(&client).copied().unwrap_or(0)
+    }
+
+    /// Removes all expired entries, returning how many were removed.
+    ///
+    /// Expired entries are also removed lazily as the table is used, so calling this is only needed to release their memory early.
+    pub fn purge_expired(&self) -> usize {
+        let now = Instant::now();
+
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| {
+                self.inner.purge(
+                    &mut shard.lock().unwrap_or_else(|err| err.into_inner()),
+                    now,
+                )
+            })
+            .sum()
+    }
+}
+
+impl<V> UdpAssociationTable<V> {
+    /// Returns the number of entries in the table, including expired ones which have not been removed yet.
+    pub fn len(&self) -> usize {
+        self.inner
+            .shards
+            .iter()
+            .map(|shard| {
+                shard
+                    .lock()
+                    .unwrap_or_else(|err| err.into_inner())
+                    .entries
+                    .len()
+            })
+            .sum()
+    }
+
+    /// Returns whether the table has no entry.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of entries in the table.
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Returns the time after which an unused entry expires.
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.inner.ttl
+    }
+
+    /// Returns the maximum number of destinations per client, if any.
+    #[inline]
+    pub fn max_per_client(&self) -> Option<usize> {
+        self.inner.max_per_client
+    }
+
+    /// Returns a snapshot of the size and counters of the table.
+    pub fn stats(&self) -> UdpAssociationTableStats {
+        let inner = &*self.inner;
+        let (entries, clients) = inner
+            .shards
+            .iter()
+            .fold((0, 0), |(entries, clients), shard| {
+                let shard = shard.lock().unwrap_or_else(|err| err.into_inner());
+                (entries + shard.entries.len(), clients + shard.clients.len())
+            });
+
+        UdpAssociationTableStats {
+            entries,
+            clients,
+            inserted: inner.inserted.load(Ordering::Relaxed),
+            hits: inner.hits.load(Ordering::Relaxed),
+            misses: inner.misses.load(Ordering::Relaxed),
+            evicted: inner.evicted.load(Ordering::Relaxed),
+            expired: inner.expired.load(Ordering::Relaxed),
+            rejected: inner.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<V> TableInner<V> {
+    fn shard(&self, client: &CanonicalAddr) -> MutexGuard<'_, Shard<V>> {
+        let idx = self.hasher.hash_one(client) as usize % self.shards.len();
+        self.shards[idx]
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Removes the expired entries of a shard. Entries expire in LRU order, as both are driven by the last use, so only the front of the LRU order has to be looked at.
+    fn purge(&self, shard: &mut Shard<V>, now: Instant) -> usize {
+        let mut purged = 0;
+
+        while let Some(entry) = shard.lru.first_entry() {
+            let key = *entry.get();
+
+            if shard.entries[&key].last_used + self.ttl > now {
+                break;
+            }
+
+            entry.remove();
+            shard.remove(&key);
+            purged += 1;
+        }
+
+        self.expired.fetch_add(purged as u64, Ordering::Relaxed);
+        purged
+    }
+}
+
+impl<V> Shard<V> {
+    fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            lru: BTreeMap::new(),
+            clients: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    /// Removes an entry and updates the destination count of its client. The LRU order is left to the caller.
+    fn remove(&mut self, key: &(CanonicalAddr, CanonicalAddr)) -> Option<TableEntry<V>> {
+        let entry = self.entries.remove(key)?;
+
+        if let Entry::Occupied(mut count) = self.clients.entry(key.0) {
+            *count.get_mut() -= 1;
+
+            if *count.get() == 0 {
+                count.remove();
+            }
+        }
+
+        Some(entry)
+    }
+}
+
+impl<V: Clone> Default for UdpAssociationTable<V> {
+    /// Creates a [`UdpAssociationTable`] of 65536 entries expiring after 5 minutes, as recommended for NAT UDP mappings by RFC 4787, without a per-client cap.
+    fn default() -> Self {
+        Self::new(65536, Duration::from_secs(300), None)
+    }
+}
+
+impl<V> Debug for UdpAssociationTable<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("UdpAssociationTable")
+            .field("capacity", &self.inner.capacity)
+            .field("shards", &self.inner.shards.len())
+            .field("ttl", &self.inner.ttl)
+            .field("max_per_client", &self.inner.max_per_client)
+            .finish()
+    }
+}
+
+/// A point-in-time copy of the size and counters of a [`UdpAssociationTable`]
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UdpAssociationTableStats {
+    /// Entries in the table, including expired ones which have not been removed yet
+    pub entries: usize,
+
+    /// Clients with at least one entry in the table
+    pub clients: usize,
+
+    /// New entries inserted
+    pub inserted: u64,
+
+    /// Lookups finding a live entry
+    pub hits: u64,
+
+    /// Lookups finding no live entry
+    pub misses: u64,
+
+    /// Entries evicted to make room for new ones
+    pub evicted: u64,
+
+    /// Entries removed after expiring
+    pub expired: u64,
+
+    /// New entries rejected because their client had the maximum number of destinations
+    pub rejected: u64,
+}
@@ -1,5 +1,14 @@
 //! Socks5 command type `Bind`
+//!
+//! This module also provides a [`tokio::net::TcpListener`] wrapper [`BindAcceptor`], which can be used to accept the inbound connection of a `Bind` command while monitoring the client connection.
 
+use crate::{
+    connection::{failed, BufferedStream},
+    error::Failed,
+    event::{CloseReason, Session},
+    tracker::Tracked,
+};
+use bytes::Bytes;
 use socks5_proto::{Address, Reply, Response};
 use std::{
     io::Error,
@@ -9,10 +18,13 @@ use std::{
     task::{Context, Poll},
 };
 use tokio::{
-    io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf},
-    net::TcpStream,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
 };
 
+#[cfg(unix)]
+use tokio::net::{unix::SocketAddr as UnixSocketAddr, UnixStream};
+
 /// Connection state types
 pub mod state {
     #[derive(Debug)]
@@ -28,119 +40,195 @@ pub mod state {
 /// Socks5 command type `Bind`
 ///
 /// Reply the client 2 times with [`Bind::reply()`] to complete the command negotiation.
+///
+/// Generic `<T>` is the stream the connection is carried over, see [`IncomingConnection`](crate::IncomingConnection).
 #[derive(Debug)]
-pub struct Bind<S> {
-    stream: TcpStream,
+pub struct Bind<S, T = TcpStream> {
+    stream: BufferedStream<T>,
+    session: Session,
     _state: PhantomData<S>,
 }
 
-impl Bind<state::NeedFirstReply> {
+impl<T: AsyncWrite + Unpin> Bind<state::NeedFirstReply, T> {
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, it is returned as a [`Failed`] alongside the original stream. So is [`Error::SetupDeadlineExceeded`](crate::Error::SetupDeadlineExceeded) if the reply is not sent by the session setup deadline, see [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline).
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Bind<state::NeedSecondReply>, (Error, TcpStream)> {
+    ) -> Result<Bind<state::NeedSecondReply, T>, Failed<BufferedStream<T>>> {
         let resp = Response::new(reply, addr);
 
-        if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+        if let Err(err) = self.session.setup(resp.write_to(&mut self.stream)).await {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
         }
 
-        Ok(Bind::new(self.stream))
+        self.session.reply(reply);
+        self.session.setup_done();
+
+        Ok(Bind::new(self.stream, self.session))
     }
 }
 
-impl Bind<state::NeedSecondReply> {
+impl<T: AsyncRead + AsyncWrite + Unpin> Bind<state::NeedSecondReply, T> {
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, it is returned as a [`Failed`] alongside the original stream.
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Bind<state::Ready>, (Error, TcpStream)> {
+    ) -> Result<Bind<state::Ready, T>, Failed<BufferedStream<T>>> {
         let resp = Response::new(reply, addr);
 
         if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
         }
 
-        Ok(Bind::new(self.stream))
+        self.session.reply(reply);
+        self.stream.end_wire_tap();
+
+        Ok(Bind::new(self.stream, self.session))
+    }
+
+    /// Wait until the SOCKS5 client closes this TCP connection.
+    ///
+    /// Socks5 protocol defines that the client should not send anything while waiting for the second reply, so a closed connection means the client aborted the `Bind` command and the server should stop waiting for the inbound connection.
+    ///
+    /// This method is cancel safe. It can be used in `tokio::select!` alongside [`BindAcceptor::accept()`] without losing any state.
+    pub async fn wait_close(&mut self) -> Result<(), Error> {
+        loop {
+            match self.stream.read(&mut [0]).await {
+                Ok(0) => break Ok(()),
+                Ok(_) => {}
+                Err(err) => break Err(err),
+            }
+        }
     }
 }
 
-impl<S> Bind<S> {
+impl<S, T> Bind<S, T> {
     #[inline]
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(crate) fn new(stream: BufferedStream<T>, session: Session) -> Self {
         Self {
             stream,
+            session,
             _state: PhantomData,
         }
     }
 
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing. Reading from it directly skips the bytes already buffered, see [`BufferedStream`].
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the [`Bind<S>`] and returns the underlying stream, in a [`Tracked`] counting the connection until it is dropped.
+    ///
+    /// Bytes the client already sent that were buffered but not read yet are dropped. Use [`Bind::into_parts()`] to keep them.
+    #[inline]
+    pub fn into_inner(self) -> Tracked<T> {
+        self.into_parts().0
+    }
+
+    /// Consumes the [`Bind<S>`] and returns the underlying stream alongside the bytes the client already sent that were buffered but not read yet. The stream comes in a [`Tracked`], as with [`Bind::into_inner()`].
+    #[inline]
+    pub fn into_parts(mut self) -> (Tracked<T>, Bytes) {
+        self.session.set_close_reason(CloseReason::Detached);
+        self.stream.into_parts()
+    }
+}
+
+impl<S, T: AsyncWrite + Unpin> Bind<S, T> {
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
     #[inline]
     pub async fn close(&mut self) -> Result<(), Error> {
         self.stream.shutdown().await
     }
+}
+
+impl<S> Bind<S> {
+    /// Closes the connection right away with a reset rather than the orderly shutdown of [`Bind::close()`], e.g. for an abusive client.
+    ///
+    /// `SO_LINGER` is set to 0 on the socket before it is dropped, so the client receives an RST and the state of the socket is freed at once, rather than the connection lingering half-open until the client closes it too. Data still pending to be sent is discarded. The stream is dropped either way, so if setting the option fails, the error is returned and the connection is closed with a regular shutdown.
+    #[inline]
+    pub fn abort(self) -> Result<(), Error> {
+        self.stream.abort()
+    }
 
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
-    }
-
-    /// Returns a shared reference to the underlying stream.
-    ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
-    #[inline]
-    pub fn get_ref(&self) -> &TcpStream {
-        &self.stream
+        self.stream.get_ref().peer_addr()
     }
+}
 
-    /// Returns a mutable reference to the underlying stream.
-    ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+#[cfg(unix)]
+impl<S> Bind<S, UnixStream> {
+    /// Returns the local address that this stream is bound to, for a connection over a [`UnixStream`].
     #[inline]
-    pub fn get_mut(&mut self) -> &mut TcpStream {
-        &mut self.stream
+    pub fn local_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().local_addr()
     }
 
-    /// Consumes the [`Bind<S>`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    /// Returns the remote address that this stream is connected to, for a connection over a [`UnixStream`].
     #[inline]
-    pub fn into_inner(self) -> TcpStream {
-        self.stream
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().peer_addr()
     }
 }
 
-impl AsyncRead for Bind<state::Ready> {
+impl<T: AsyncRead + Unpin> AsyncRead for Bind<state::Ready, T> {
     #[inline]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<(), Error>> {
-        Pin::new(&mut self.stream).poll_read(cx, buf)
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = res {
+            self.session.received(buf.filled().len() - filled);
+        }
+
+        res
     }
 }
 
-impl AsyncWrite for Bind<state::Ready> {
+impl<T: AsyncWrite + Unpin> AsyncWrite for Bind<state::Ready, T> {
     #[inline]
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
-        Pin::new(&mut self.stream).poll_write(cx, buf)
+        let res = Pin::new(&mut self.stream).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(len)) = res {
+            self.session.sent(len);
+        }
+
+        res
     }
 
     #[inline]
@@ -153,3 +241,96 @@ impl AsyncWrite for Bind<state::Ready> {
         Pin::new(&mut self.stream).poll_shutdown(cx)
     }
 }
+
+/// A wrapper of a tokio TCP listener for accepting the inbound connection of a `Bind` command.
+///
+/// Accepting is exposed as both [`BindAcceptor::accept()`] and [`BindAcceptor::poll_accept()`], so waiting for the inbound peer can be multiplexed with monitoring the client connection (see [`Bind::wait_close()`]) or any other event, e.g. a server shutdown signal.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     connection::bind::{state::NeedSecondReply, Bind, BindAcceptor},
+///     proto::{Address, Reply},
+/// };
+///
+/// async fn wait_inbound(mut bind: Bind<NeedSecondReply>, acceptor: BindAcceptor) {
+///     tokio::select! {
+///         res = acceptor.accept() => {
+///             let (inbound, addr) = res.unwrap();
+///             let bind = bind.reply(Reply::Succeeded, Address::SocketAddress(addr)).await;
+///             todo!();
+///         }
+///         _ = bind.wait_close() => {
+///             // the client aborted the `Bind` command
+///         }
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct BindAcceptor {
+    listener: TcpListener,
+}
+
+impl BindAcceptor {
+    /// Creates a new [`BindAcceptor`] with a [`TcpListener`](tokio::net::TcpListener).
+    #[inline]
+    pub fn new(listener: TcpListener) -> Self {
+        Self { listener }
+    }
+
+    /// Creates a new [`BindAcceptor`] listening on the given address.
+    ///
+    /// Binding to port 0 lets the OS pick a port, which can then be retrieved with [`BindAcceptor::local_addr()`] for the first reply.
+    #[inline]
+    pub async fn bind<T: ToSocketAddrs>(addr: T) -> Result<Self, Error> {
+        Ok(Self::new(TcpListener::bind(addr).await?))
+    }
+
+    /// Accepts an inbound connection.
+    ///
+    /// This method is cancel safe. If it is used in `tokio::select!` and another branch completes first, no inbound connection is lost.
+    #[inline]
+    pub async fn accept(&self) -> Result<(TcpStream, SocketAddr), Error> {
+        self.listener.accept().await
+    }
+
+    /// Polls to accept an inbound connection.
+    ///
+    /// If there is no connection to accept, Poll::Pending is returned and the current task will be notified by a waker. Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    #[inline]
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(TcpStream, SocketAddr), Error>> {
+        self.listener.poll_accept(cx)
+    }
+
+    /// Returns the local address that this acceptor is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listener.local_addr()
+    }
+
+    /// Returns a shared reference to the underlying listener.
+    ///
+    /// Note that this may break the encapsulation of the [`BindAcceptor`] and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &TcpListener {
+        &self.listener
+    }
+
+    /// Returns a mutable reference to the underlying listener.
+    ///
+    /// Note that this may break the encapsulation of the [`BindAcceptor`] and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut TcpListener {
+        &mut self.listener
+    }
+
+    /// Consumes the [`BindAcceptor`] and returns the underlying [`TcpListener`](tokio::net::TcpListener).
+    #[inline]
+    pub fn into_inner(self) -> TcpListener {
+        self.listener
+    }
+}
@@ -1,5 +1,12 @@
 //! Socks5 command type `Connect`
 
+use crate::{
+    connection::{failed, BufferedStream},
+    error::Failed,
+    event::{CloseReason, Session},
+    tracker::Tracked,
+};
+use bytes::Bytes;
 use socks5_proto::{Address, Reply, Response};
 use std::{
     io::Error,
@@ -13,6 +20,9 @@ use tokio::{
     net::TcpStream,
 };
 
+#[cfg(unix)]
+use tokio::net::{unix::SocketAddr as UnixSocketAddr, UnixStream};
+
 /// Connection state types
 pub mod state {
     #[derive(Debug)]
@@ -25,100 +35,158 @@ pub mod state {
 /// Socks5 command type `Connect`
 ///
 /// Reply the client with [`Connect::reply()`] to complete the command negotiation.
+///
+/// Generic `<T>` is the stream the connection is carried over, see [`IncomingConnection`](crate::IncomingConnection).
 #[derive(Debug)]
-pub struct Connect<S> {
-    stream: TcpStream,
+pub struct Connect<S, T = TcpStream> {
+    stream: BufferedStream<T>,
+    session: Session,
     _state: PhantomData<S>,
 }
 
-impl Connect<state::NeedReply> {
+impl<T: AsyncWrite + Unpin> Connect<state::NeedReply, T> {
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, it is returned as a [`Failed`] alongside the original stream. So is [`Error::SetupDeadlineExceeded`](crate::Error::SetupDeadlineExceeded) if the reply is not sent by the session setup deadline, see [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline).
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Connect<state::Ready>, (Error, TcpStream)> {
+    ) -> Result<Connect<state::Ready, T>, Failed<BufferedStream<T>>> {
         let resp = Response::new(reply, addr);
 
-        if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+        if let Err(err) = self.session.setup(resp.write_to(&mut self.stream)).await {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(failed(err, self.stream));
         }
 
-        Ok(Connect::new(self.stream))
+        self.session.reply(reply);
+        self.session.setup_done();
+        self.stream.end_wire_tap();
+
+        Ok(Connect::new(self.stream, self.session))
     }
 }
 
-impl<S> Connect<S> {
+impl<S, T> Connect<S, T> {
     #[inline]
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(crate) fn new(stream: BufferedStream<T>, session: Session) -> Self {
         Self {
             stream,
+            session,
             _state: PhantomData,
         }
     }
 
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing. Reading from it directly skips the bytes already buffered, see [`BufferedStream`].
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the [`Connect<S>`] and returns the underlying stream, in a [`Tracked`] counting the connection until it is dropped.
+    ///
+    /// Bytes the client already sent that were buffered but not read yet are dropped. Use [`Connect::into_parts()`] to keep them.
+    #[inline]
+    pub fn into_inner(self) -> Tracked<T> {
+        self.into_parts().0
+    }
+
+    /// Consumes the [`Connect<S>`] and returns the underlying stream alongside the bytes the client already sent that were buffered but not read yet. The stream comes in a [`Tracked`], as with [`Connect::into_inner()`].
+    #[inline]
+    pub fn into_parts(mut self) -> (Tracked<T>, Bytes) {
+        self.session.set_close_reason(CloseReason::Detached);
+        self.stream.into_parts()
+    }
+}
+
+impl<S, T: AsyncWrite + Unpin> Connect<S, T> {
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
     #[inline]
     pub async fn close(&mut self) -> Result<(), Error> {
         self.stream.shutdown().await
     }
+}
+
+impl<S> Connect<S> {
+    /// Closes the connection right away with a reset rather than the orderly shutdown of [`Connect::close()`], e.g. for an abusive client.
+    ///
+    /// `SO_LINGER` is set to 0 on the socket before it is dropped, so the client receives an RST and the state of the socket is freed at once, rather than the connection lingering half-open until the client closes it too. Data still pending to be sent is discarded. The stream is dropped either way, so if setting the option fails, the error is returned and the connection is closed with a regular shutdown.
+    #[inline]
+    pub fn abort(self) -> Result<(), Error> {
+        self.stream.abort()
+    }
 
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
-    }
-
-    /// Returns a shared reference to the underlying stream.
-    ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
-    #[inline]
-    pub fn get_ref(&self) -> &TcpStream {
-        &self.stream
+        self.stream.get_ref().peer_addr()
     }
+}
 
-    /// Returns a mutable reference to the underlying stream.
-    ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+#[cfg(unix)]
+impl<S> Connect<S, UnixStream> {
+    /// Returns the local address that this stream is bound to, for a connection over a [`UnixStream`].
     #[inline]
-    pub fn get_mut(&mut self) -> &mut TcpStream {
-        &mut self.stream
+    pub fn local_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().local_addr()
     }
 
-    /// Consumes the [`Connect<S>`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    /// Returns the remote address that this stream is connected to, for a connection over a [`UnixStream`].
     #[inline]
-    pub fn into_inner(self) -> TcpStream {
-        self.stream
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr, Error> {
+        self.stream.get_ref().peer_addr()
     }
 }
 
-impl AsyncRead for Connect<state::Ready> {
+impl<T: AsyncRead + Unpin> AsyncRead for Connect<state::Ready, T> {
     #[inline]
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<(), Error>> {
-        Pin::new(&mut self.stream).poll_read(cx, buf)
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = res {
+            self.session.received(buf.filled().len() - filled);
+        }
+
+        res
     }
 }
 
-impl AsyncWrite for Connect<state::Ready> {
+impl<T: AsyncWrite + Unpin> AsyncWrite for Connect<state::Ready, T> {
     #[inline]
     fn poll_write(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, Error>> {
-        Pin::new(&mut self.stream).poll_write(cx, buf)
+        let res = Pin::new(&mut self.stream).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(len)) = res {
+            self.session.sent(len);
+        }
+
+        res
     }
 
     #[inline]
@@ -1,20 +1,50 @@
 //! Connection abstraction of the SOCKS5 protocol
 
 use self::{associate::Associate, bind::Bind, connect::Connect};
-use crate::AuthAdaptor;
+use crate::{
+    error::{Error, Failed},
+    event::{AuthOutcome, CloseReason, Session},
+    hostname::HostnamePolicy,
+    tap::WireTap,
+    tracker::Tracked,
+    AuthAdaptor,
+};
+use bytes::Bytes;
+use socket2::{SockRef, TcpKeepalive};
 use socks5_proto::{
     handshake::{
         Method as HandshakeMethod, Request as HandshakeRequest, Response as HandshakeResponse,
     },
-    Address, Command as ProtocolCommand, Error, ProtocolError, Request,
+    Address, Command as ProtocolCommand, Error as Socks5Error, ProtocolError, Reply, Request,
+    Response,
+};
+use std::{
+    fmt::Debug,
+    io::{Error as IoError, ErrorKind},
+    marker::PhantomData,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::{self, Instant},
 };
-use std::{fmt::Debug, io::Error as IoError, marker::PhantomData, net::SocketAddr};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+#[cfg(unix)]
+use tokio::net::{unix::SocketAddr as UnixSocketAddr, UnixStream};
 
 pub mod associate;
 pub mod bind;
 pub mod connect;
 
+mod stream;
+
+pub use self::stream::BufferedStream;
+
+pub(crate) use self::stream::failed;
+
 /// Incoming connection state types
 pub mod state {
     #[derive(Debug)]
@@ -27,134 +57,786 @@ pub mod state {
 /// An incoming SOCKS5 connection.
 ///
 /// This may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`] and [`IncomingConnection::wait()`] to perform a SOCKS5 connection negotiation.
-pub struct IncomingConnection<A, S> {
-    stream: TcpStream,
-    auth: AuthAdaptor<A>,
+///
+/// Generic `<T>` is the stream the connection is carried over, a [`TcpStream`] unless created over another one with [`IncomingConnection::new()`], e.g. a TLS stream. The negotiation works over any stream, while the methods about socket addresses are only available on a [`TcpStream`].
+pub struct IncomingConnection<A, S, T = TcpStream> {
+    stream: BufferedStream<T>,
+    auth: AuthAdaptor<A, T>,
+    session: Session,
+    hostnames: Option<Arc<HostnamePolicy>>,
     _state: PhantomData<S>,
 }
 
-impl<A> IncomingConnection<A, state::NeedAuthenticate> {
+impl<A, T> IncomingConnection<A, state::NeedAuthenticate, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Creates a new [`IncomingConnection`] over `stream`, a connection accepted by other means than a [`Server`](crate::Server), e.g. a TLS stream terminated in front of the SOCKS5 negotiation.
+    ///
+    /// The connection is not subject to the settings of any [`Server`](crate::Server): it reports no events, has no session setup deadline nor [`HostnamePolicy`], is read with the default [`ParseLimits`](socks5_proto::ParseLimits), and is not counted by a [`ConnectionTracker`](crate::tracker::ConnectionTracker). A [`WireTap`] can still be set with [`IncomingConnection::set_wire_tap()`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use socks5_server::{auth::NoAuth, connection::state::NeedAuthenticate, IncomingConnection};
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncRead, AsyncWrite};
+    ///
+    /// fn incoming<T>(stream: T) -> IncomingConnection<(), NeedAuthenticate, T>
+    /// where
+    ///     T: AsyncRead + AsyncWrite + Unpin + Send,
+    /// {
+    ///     IncomingConnection::new(stream, Arc::new(NoAuth) as Arc<_>)
+    /// }
+    /// ```
+    #[inline]
+    pub fn new(stream: T, auth: AuthAdaptor<A, T>) -> Self {
+        Self::with_session(BufferedStream::new(stream), auth, Session::detached())
+    }
+
+    /// Waits until the handshake request of the client is fully received, without consuming the connection.
+    ///
+    /// [`IncomingConnection::authenticate()`] then parses it without waiting on the client. An error is returned if the client closes the connection, reading fails, or the bytes received are not a valid handshake request, in which case [`IncomingConnection::authenticate()`] fails the same way.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe. The bytes received before the future is dropped stay buffered, so it can be raced in `tokio::select!` and called again without losing anything.
+    pub async fn wait_handshake(&mut self) -> Result<(), Error> {
+        loop {
+            let limits = self.stream.parse_limits();
+            let mut buf = self.stream.buffer();
+
+            match HandshakeRequest::read_from_with_limits(&mut buf, limits).await {
+                Ok(_) => return Ok(()),
+                Err(err) if is_incomplete(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            if self.session.setup(self.stream.fill_more()).await? == 0 {
+                return Err(IoError::from(ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
     /// Perform a SOCKS5 authentication handshake using the given [`Auth`](crate::Auth) adapter.
     ///
-    /// If the handshake succeeds, an [`IncomingConnection<A, state::NeedCommand>`] alongs with the output of the [`Auth`](crate::Auth) adapter `A` is returned. Otherwise, the error and the underlying [`TcpStream`](tokio::net::TcpStream) is returned.
+    /// If the handshake succeeds, an [`IncomingConnection<A, state::NeedCommand>`] alongs with the output of the [`Auth`](crate::Auth) adapter `A` is returned. Otherwise, the error is returned alongside the connection as a [`Failed`], still holding the [`Auth`](crate::Auth) adaptor and the bytes received, e.g. to fall back to another protocol with [`IncomingConnection::into_parts()`].
+    ///
+    /// If the client offers more methods than the [`ParseLimits::max_methods`](socks5_proto::ParseLimits::max_methods) set with [`Server::set_parse_limits()`](crate::Server::set_parse_limits), [`Method::UNACCEPTABLE`](HandshakeMethod::UNACCEPTABLE) is replied without reading them, and [`ProtocolError::TooManyHandshakeMethods`] is returned.
+    ///
+    /// If the session setup deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline) passes before the handshake, including the sub-negotiation of the [`Auth`](crate::Auth) adapter, completes, [`Error::SetupDeadlineExceeded`] is returned.
     ///
     /// Note that this method will not implicitly close the connection even if the handshake failed.
+    ///
+    /// To pick the method per connection rather than by the adaptor, perform the handshake in two phases with [`IncomingConnection::read_methods()`] instead.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is not cancel safe, as it consumes the connection: dropping the future drops the connection with it. To race waiting on the client against e.g. a shutdown signal, wait with [`IncomingConnection::wait_handshake()`] first. The sub-negotiation of the [`Auth`](crate::Auth) adapter is not covered by it.
     pub async fn authenticate(
+        self,
+    ) -> Result<(IncomingConnection<A, state::NeedCommand, T>, A), Failed<Self>> {
+        let (read, methods) = self.read_methods().await?;
+        let chosen_method = read.auth_method();
+
+        if methods.contains(&chosen_method) {
+            read.negotiate(chosen_method).await
+        } else {
+            Err(read.reject().await)
+        }
+    }
+
+    /// Reads the methods offered by the client, the first phase of the handshake performed in one go by [`IncomingConnection::authenticate()`].
+    ///
+    /// The methods are returned alongside a [`MethodsRead`], the connection waiting for the server to pick one of them with [`MethodsRead::select()`], or to reject them all with [`MethodsRead::reject()`]. This e.g. allows to choose the method by the address of the client, or to log the methods clients offer.
+    ///
+    /// Errors are returned as by [`IncomingConnection::authenticate()`], including [`ProtocolError::TooManyHandshakeMethods`] replied to with [`Method::UNACCEPTABLE`](HandshakeMethod::UNACCEPTABLE).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use socks5_server::{
+    ///     connection::state::{NeedAuthenticate, NeedCommand},
+    ///     proto::handshake::Method,
+    ///     Error, IncomingConnection,
+    /// };
+    ///
+    /// // lets local clients in without a password
+    /// async fn authenticate(
+    ///     conn: IncomingConnection<(), NeedAuthenticate>,
+    /// ) -> Result<IncomingConnection<(), NeedCommand>, Error> {
+    ///     let is_local = conn.peer_addr()?.ip().is_loopback();
+    ///     let (conn, methods) = conn.read_methods().await?;
+    ///
+    ///     if is_local && methods.contains(&Method::NONE) {
+    ///         Ok(conn.select(Method::NONE).await?.0)
+    ///     } else if methods.contains(&conn.auth_method()) {
+    ///         let method = conn.auth_method();
+    ///         Ok(conn.select(method).await?.0)
+    ///     } else {
+    ///         Err(conn.reject().await.into())
+    ///     }
+    /// }
+    /// ```
+    pub async fn read_methods(
         mut self,
-    ) -> Result<(IncomingConnection<A, state::NeedCommand>, A), (Error, TcpStream)> {
-        let req = match HandshakeRequest::read_from(&mut self.stream).await {
-            Ok(req) => req,
-            Err(err) => return Err((err, self.stream)),
-        };
+    ) -> Result<(MethodsRead<A, T>, Vec<HandshakeMethod>), Failed<Self>> {
         let chosen_method = self.auth.as_handshake_method();
 
-        if req.methods.contains(&chosen_method) {
-            let resp = HandshakeResponse::new(chosen_method);
+        let limits = *self.stream.parse_limits();
+        let req = self
+            .session
+            .setup(HandshakeRequest::read_from_with_limits(
+                &mut self.stream,
+                &limits,
+            ))
+            .await;
+        self.stream.flush_wire_tap();
 
-            if let Err(err) = resp.write_to(&mut self.stream).await {
-                return Err((Error::Io(err), self.stream));
+        match req {
+            Ok(req) => Ok((
+                MethodsRead {
+                    conn: self,
+                    methods: req.methods.clone(),
+                },
+                req.methods,
+            )),
+            Err(err @ Error::Protocol(ProtocolError::TooManyHandshakeMethods { .. })) => {
+                Err(self.reject_handshake(chosen_method, err).await)
             }
+            Err(err) => Err(self.auth_failed(chosen_method, err)),
+        }
+    }
 
-            let output = self.auth.execute(&mut self.stream).await;
-
-            Ok((IncomingConnection::new(self.stream, self.auth), output))
-        } else {
-            let resp = HandshakeResponse::new(HandshakeMethod::UNACCEPTABLE);
+    /// Perform a SOCKS5 authentication handshake as [`IncomingConnection::authenticate()`] does, failing if it does not finish within `timeout`.
+    ///
+    /// The timeout covers the handshake as a whole: reading the methods offered by the client, replying the chosen one and the sub-negotiation of the [`Auth`](crate::Auth) adapter. Once it has elapsed, [`Error::AuthTimeout`] is returned alongside the connection as a [`Failed`]. The session setup deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline) still applies, failing with [`Error::SetupDeadlineExceeded`] if it passes first.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is not cancel safe, see [`IncomingConnection::authenticate()`].
+    pub async fn authenticate_with_timeout(
+        mut self,
+        timeout: Duration,
+    ) -> Result<(IncomingConnection<A, state::NeedCommand, T>, A), Failed<Self>> {
+        self.session
+            .set_auth_deadline(Some(Instant::now() + timeout));
 
-            if let Err(err) = resp.write_to(&mut self.stream).await {
-                return Err((Error::Io(err), self.stream));
+        let (mut conn, output) = self.authenticate().await.map_err(|mut failed| {
+            if let Some(conn) = &mut failed.stream {
+                conn.session.set_auth_deadline(None);
             }
 
-            Err((
-                Error::Protocol(ProtocolError::NoAcceptableHandshakeMethod {
-                    version: socks5_proto::SOCKS_VERSION,
-                    chosen_method,
-                    methods: req.methods,
-                }),
-                self.stream,
-            ))
+            failed
+        })?;
+        conn.session.set_auth_deadline(None);
+
+        Ok((conn, output))
+    }
+
+    async fn reject_handshake(mut self, method: HandshakeMethod, err: Error) -> Failed<Self> {
+        let resp = HandshakeResponse::new(HandshakeMethod::UNACCEPTABLE);
+        let _ = self.session.setup(resp.write_to(&mut self.stream)).await;
+        self.auth_failed(method, err)
+    }
+
+    fn auth_failed(mut self, method: HandshakeMethod, err: Error) -> Failed<Self> {
+        self.session.auth(method, AuthOutcome::Failed);
+        self.session.set_close_reason(CloseReason::Failed);
+        Failed::new(err, self)
+    }
+}
+
+/// A connection whose client offered its handshake methods, waiting for the server to pick one, see [`IncomingConnection::read_methods()`].
+///
+/// Note that dropping it closes the connection without replying to the client. Call [`MethodsRead::reject()`] to tell it none of its methods is acceptable first.
+pub struct MethodsRead<A, T = TcpStream> {
+    conn: IncomingConnection<A, state::NeedAuthenticate, T>,
+    methods: Vec<HandshakeMethod>,
+}
+
+impl<A, T> MethodsRead<A, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Returns the methods offered by the client.
+    #[inline]
+    pub fn methods(&self) -> &[HandshakeMethod] {
+        &self.methods
+    }
+
+    /// Returns the method of the [`Auth`](crate::Auth) adaptor of the connection, the one [`IncomingConnection::authenticate()`] selects.
+    #[inline]
+    pub fn auth_method(&self) -> HandshakeMethod {
+        self.conn.auth.as_handshake_method()
+    }
+
+    /// Replies `method` to the client and runs the sub-negotiation of it, completing the handshake.
+    ///
+    /// If `method` is the one of the [`Auth`](crate::Auth) adaptor of the connection, see [`MethodsRead::auth_method()`], the adaptor is executed and its output is returned alongside the [`IncomingConnection<A, state::NeedCommand>`]. Any other method is selected without a sub-negotiation, returning no output, which suits e.g. [`Method::NONE`](HandshakeMethod::NONE) for clients trusted by other means. `method` should be one the client offered, see [`MethodsRead::methods()`].
+    ///
+    /// Errors are returned alongside the connection as a [`Failed`], as by [`IncomingConnection::authenticate()`].
+    pub async fn select(
+        self,
+        method: HandshakeMethod,
+    ) -> Result<
+        (IncomingConnection<A, state::NeedCommand, T>, Option<A>),
+        Failed<IncomingConnection<A, state::NeedAuthenticate, T>>,
+    > {
+        if method == self.auth_method() {
+            let (conn, output) = self.negotiate(method).await?;
+            return Ok((conn, Some(output)));
+        }
+
+        let mut conn = self.conn;
+        let resp = HandshakeResponse::new(method);
+
+        if let Err(err) = conn.session.setup(resp.write_to(&mut conn.stream)).await {
+            return Err(conn.auth_failed(method, err));
+        }
+
+        conn.session.auth(method, AuthOutcome::Completed);
+
+        let conn = IncomingConnection::with_session(conn.stream, conn.auth, conn.session)
+            .with_hostname_policy(conn.hostnames);
+
+        Ok((conn, None))
+    }
+
+    /// Replies [`Method::UNACCEPTABLE`](HandshakeMethod::UNACCEPTABLE) to the client, refusing every method it offered.
+    ///
+    /// The connection is returned as a [`Failed`] with [`ProtocolError::NoAcceptableHandshakeMethod`], or with the error writing the reply, as by [`IncomingConnection::authenticate()`]. Note that this method will not implicitly close the connection.
+    pub async fn reject(self) -> Failed<IncomingConnection<A, state::NeedAuthenticate, T>> {
+        let chosen_method = self.auth_method();
+        let mut conn = self.conn;
+        let resp = HandshakeResponse::new(HandshakeMethod::UNACCEPTABLE);
+
+        if let Err(err) = conn.session.setup(resp.write_to(&mut conn.stream)).await {
+            return conn.auth_failed(chosen_method, err);
         }
+
+        conn.session.auth(chosen_method, AuthOutcome::Unacceptable);
+        conn.session.set_close_reason(CloseReason::Failed);
+
+        Failed::new(
+            ProtocolError::NoAcceptableHandshakeMethod {
+                version: socks5_proto::SOCKS_VERSION,
+                chosen_method,
+                methods: self.methods,
+            },
+            conn,
+        )
+    }
+
+    /// Replies `method`, the one of the [`Auth`](crate::Auth) adaptor, and executes the adaptor.
+    async fn negotiate(
+        self,
+        method: HandshakeMethod,
+    ) -> Result<
+        (IncomingConnection<A, state::NeedCommand, T>, A),
+        Failed<IncomingConnection<A, state::NeedAuthenticate, T>>,
+    > {
+        let mut conn = self.conn;
+        let resp = HandshakeResponse::new(method);
+
+        if let Err(err) = conn.session.setup(resp.write_to(&mut conn.stream)).await {
+            return Err(conn.auth_failed(method, err));
+        }
+
+        let tap = conn.stream.suspend_wire_tap();
+        let (auth, stream) = (&conn.auth, &mut conn.stream);
+        let output = conn
+            .session
+            .setup(async { Ok::<_, Error>(auth.execute(stream).await) })
+            .await;
+
+        if tap.is_some() {
+            conn.stream.set_wire_tap(tap);
+        }
+
+        let output = match output {
+            Ok(output) => output,
+            Err(err) => return Err(conn.auth_failed(method, err)),
+        };
+
+        conn.session.auth(method, AuthOutcome::Completed);
+
+        let conn = IncomingConnection::with_session(conn.stream, conn.auth, conn.session)
+            .with_hostname_policy(conn.hostnames);
+
+        Ok((conn, output))
+    }
+}
+
+impl<A, T> MethodsRead<A, T> {
+    /// Returns a shared reference to the underlying stream, e.g. to look up the address of the client.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.conn.get_ref()
     }
 }
 
-impl<A> IncomingConnection<A, state::NeedCommand> {
+impl<A, T: Debug> Debug for MethodsRead<A, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MethodsRead")
+            .field("stream", &self.conn.stream)
+            .field("methods", &self.methods)
+            .finish()
+    }
+}
+
+impl<A, T> IncomingConnection<A, state::NeedCommand, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Waits until the request of the client is fully received, without consuming the connection.
+    ///
+    /// [`IncomingConnection::wait()`] then parses it without waiting on the client. An error is returned if the client closes the connection, reading fails, or the bytes received are not a valid request, in which case [`IncomingConnection::wait()`] fails the same way.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe. The bytes received before the future is dropped stay buffered, so it can be raced in `tokio::select!` and called again without losing anything.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use socks5_server::{connection::state::NeedCommand, Command, Error, IncomingConnection};
+    /// use tokio::sync::watch::Receiver;
+    ///
+    /// async fn wait(
+    ///     mut conn: IncomingConnection<(), NeedCommand>,
+    ///     mut shutdown: Receiver<bool>,
+    /// ) -> Result<Option<Command>, Error> {
+    ///     tokio::select! {
+    ///         res = conn.wait_request() => res?,
+    ///         _ = shutdown.changed() => return Ok(None),
+    ///     }
+    ///
+    ///     Ok(Some(conn.wait().await?))
+    /// }
+    /// ```
+    pub async fn wait_request(&mut self) -> Result<(), Error> {
+        loop {
+            let limits = self.stream.parse_limits();
+            let mut buf = self.stream.buffer();
+
+            match Request::read_from_with_limits(&mut buf, limits).await {
+                Ok(_) => return Ok(()),
+                Err(err) if is_incomplete(&err) => {}
+                Err(err) => return Err(err.into()),
+            }
+
+            if self.session.setup(self.stream.fill_more()).await? == 0 {
+                return Err(IoError::from(ErrorKind::UnexpectedEof).into());
+            }
+        }
+    }
+
     /// Waits the SOCKS5 client to send a request.
     ///
     /// This method will return a [`Command`] if the client sends a valid command.
     ///
-    /// When encountering an error, the stream will be returned alongside the error.
+    /// When encountering an error, the connection will be returned alongside the error as a [`Failed`], e.g. to try another protocol on the bytes received with [`IncomingConnection::into_parts()`].
+    ///
+    /// Note that this method will not implicitly close the connection even if the client sends an invalid command. Nor does it reply to a malformed request, see [`IncomingConnection::wait_or_reject()`] for that.
     ///
-    /// Note that this method will not implicitly close the connection even if the client sends an invalid command.
-    pub async fn wait(mut self) -> Result<Command, (Error, TcpStream)> {
-        let req = match Request::read_from(&mut self.stream).await {
+    /// If a [`HostnamePolicy`] is registered with [`Server::set_hostname_policy()`](crate::Server::set_hostname_policy), a domain name requested in violation of it is replied to with [`Reply::AddressTypeNotSupported`], and [`Error::InvalidHostname`] is returned with the name attached.
+    ///
+    /// A domain name longer than the [`ParseLimits::max_domain_len`](socks5_proto::ParseLimits::max_domain_len) set with [`Server::set_parse_limits()`](crate::Server::set_parse_limits) is replied to the same way without being read, returning [`ProtocolError::DomainTooLongInRequest`].
+    ///
+    /// If the session setup deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline) passes before the request is received, [`Error::SetupDeadlineExceeded`] is returned. The deadline is carried on by the returned [`Command`] until its first reply is sent.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is not cancel safe, as it consumes the connection: dropping the future drops the connection with it. To race waiting on the client against e.g. a shutdown signal, wait with [`IncomingConnection::wait_request()`] first, after which this method does not wait on the client.
+    pub async fn wait(mut self) -> Result<Command<T>, Failed<Self>> {
+        let limits = *self.stream.parse_limits();
+        let req = self
+            .session
+            .setup(Request::read_from_with_limits(&mut self.stream, &limits))
+            .await;
+        self.stream.flush_wire_tap();
+
+        let req = match req {
             Ok(req) => req,
-            Err(err) => return Err((err, self.stream)),
+            Err(err @ Error::Protocol(ProtocolError::DomainTooLongInRequest { .. })) => {
+                return Err(self
+                    .reject_request(Reply::AddressTypeNotSupported, err)
+                    .await);
+            }
+            Err(err) => {
+                self.session.set_close_reason(CloseReason::Failed);
+                return Err(Failed::new(err, self));
+            }
         };
 
-        match req.command {
-            ProtocolCommand::Associate => {
-                Ok(Command::Associate(Associate::new(self.stream), req.address))
-            }
-            ProtocolCommand::Bind => Ok(Command::Bind(Bind::new(self.stream), req.address)),
-            ProtocolCommand::Connect => {
-                Ok(Command::Connect(Connect::new(self.stream), req.address))
+        self.session.command(req.command, &req.address);
+
+        if let (Some(policy), Address::DomainAddress(name, _)) = (&self.hostnames, &req.address) {
+            if let Err(violation) = policy.check(name) {
+                let name = name.clone();
+                return Err(self
+                    .reject_request(
+                        Reply::AddressTypeNotSupported,
+                        Error::InvalidHostname { name, violation },
+                    )
+                    .await);
             }
         }
+
+        match req.command {
+            ProtocolCommand::Associate => Ok(Command::Associate(
+                Associate::new(self.stream, req.address.clone(), self.session),
+                req.address,
+            )),
+            ProtocolCommand::Bind => Ok(Command::Bind(
+                Bind::new(self.stream, self.session),
+                req.address,
+            )),
+            ProtocolCommand::Connect => Ok(Command::Connect(
+                Connect::new(self.stream, self.session),
+                req.address,
+            )),
+        }
+    }
+
+    /// Waits the SOCKS5 client to send a request as [`IncomingConnection::wait()`] does, failing if it is not fully received within `timeout`.
+    ///
+    /// Once the timeout has elapsed, [`Error::RequestTimeout`] is returned alongside the connection as a [`Failed`], e.g. to reply [`Reply::GeneralFailure`] before shutting it down, or to just drop it. The session setup deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline) still applies, failing with [`Error::SetupDeadlineExceeded`] if it passes first.
+    ///
+    /// The request is read as [`IncomingConnection::wait_request()`] does, so if the timeout fires in the middle of it, no byte is lost: the part received so far stays buffered in the returned connection, see [`IncomingConnection::into_parts()`]. The negotiation can not be resumed from there though, so the connection is no longer usable but to reply to and close it.
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is not cancel safe, see [`IncomingConnection::wait()`].
+    pub async fn wait_with_timeout(
+        mut self,
+        timeout: Duration,
+    ) -> Result<Command<T>, Failed<Self>> {
+        // an error of `wait_request()` is returned by `wait()` as well, replying to the client if needed
+        if time::timeout(timeout, self.wait_request()).await.is_err() {
+            self.session.set_close_reason(CloseReason::Failed);
+            return Err(Failed::new(Error::RequestTimeout, self));
+        }
+
+        self.wait().await
+    }
+
+    /// Waits the SOCKS5 client to send a request as [`IncomingConnection::wait()`] does, replying to a malformed one before returning the error.
+    ///
+    /// On a [`Error::Protocol`] reading the request, the reply matching it is written with an unspecified address, so a well-behaved client is told why rather than left waiting: [`Reply::CommandNotSupported`] for an unknown command, [`Reply::AddressTypeNotSupported`] for an unknown address type, and [`Reply::GeneralFailure`] otherwise, e.g. for a request of another protocol version. Errors reading from the stream are returned without a reply, as the client is likely gone. Errors already replied to by [`IncomingConnection::wait()`] are not replied to again.
+    ///
+    /// The error is then returned alongside the connection as a [`Failed`], as by [`IncomingConnection::wait()`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is not cancel safe, see [`IncomingConnection::wait()`].
+    pub async fn wait_or_reject(self) -> Result<Command<T>, Failed<Self>> {
+        match self.wait().await {
+            Err(Failed {
+                error: Error::Protocol(err),
+                stream: Some(conn),
+            }) => match rejection(&err) {
+                Some(reply) => Err(conn.reject_request(reply, err.into()).await),
+                None => Err(Failed::new(err, conn)),
+            },
+            res => res,
+        }
+    }
+
+    async fn reject_request(mut self, reply: Reply, err: Error) -> Failed<Self> {
+        let resp = Response::new(reply, Address::unspecified());
+
+        if self
+            .session
+            .setup(resp.write_to(&mut self.stream))
+            .await
+            .is_ok()
+        {
+            self.session.reply(reply);
+        }
+
+        self.session.set_close_reason(CloseReason::Failed);
+        Failed::new(err, self)
     }
 }
 
-impl<A, S> IncomingConnection<A, S> {
+impl<A, S, T> IncomingConnection<A, S, T> {
     #[inline]
-    pub(crate) fn new(stream: TcpStream, auth: AuthAdaptor<A>) -> Self {
+    pub(crate) fn with_session(
+        stream: BufferedStream<T>,
+        auth: AuthAdaptor<A, T>,
+        session: Session,
+    ) -> Self {
         Self {
             stream,
             auth,
+            session,
+            hostnames: None,
             _state: PhantomData,
         }
     }
 
+    #[inline]
+    pub(crate) fn with_hostname_policy(mut self, policy: Option<Arc<HostnamePolicy>>) -> Self {
+        self.hostnames = policy;
+        self
+    }
+
+    #[cfg(feature = "serve")]
+    #[inline]
+    pub(crate) fn take_tracked(&mut self) -> Option<crate::tracker::ConnectionGuard> {
+        self.stream.take_guard()
+    }
+
+    /// Sets the instant the setup of this connection must finish by, replacing the deadline of [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline), if any, e.g. to give a connection over a slow transport more time, or to bound one created with [`IncomingConnection::new()`].
+    ///
+    /// The deadline is enforced as the one of the server is: [`IncomingConnection::authenticate()`] including the execution of the [`Auth`](crate::Auth) adaptor, [`IncomingConnection::wait()`] and the reply methods of the commands fail with [`Error::SetupDeadlineExceeded`] alongside the stream once it has passed, so a client trickling the negotiation byte by byte can not hold the connection forever.
+    #[inline]
+    pub fn set_setup_deadline(&mut self, deadline: Instant) {
+        self.session.set_setup_deadline(Some(deadline));
+    }
+
+    /// Removes the setup deadline of this connection, if any.
+    #[inline]
+    pub fn clear_setup_deadline(&mut self) {
+        self.session.set_setup_deadline(None);
+    }
+
+    /// Returns the instant the setup of this connection must finish by, if any.
+    #[inline]
+    pub fn setup_deadline(&self) -> Option<Instant> {
+        self.session.setup_deadline()
+    }
+
+    /// Sets the [`WireTap`] capturing the rest of the negotiation of this connection, replacing the one set with [`Server::set_wire_tap()`](crate::Server::set_wire_tap), if any.
+    #[inline]
+    pub fn set_wire_tap(&mut self, tap: impl Into<WireTap>) {
+        self.stream.set_wire_tap(Some(tap.into()));
+    }
+
+    /// Removes the [`WireTap`] of this connection, if any.
+    #[inline]
+    pub fn clear_wire_tap(&mut self) {
+        self.stream.end_wire_tap();
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing. Reading from it directly skips the bytes already buffered, see [`BufferedStream`].
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the [`IncomingConnection`] and returns the underlying stream, in a [`Tracked`] counting the connection until it is dropped.
+    ///
+    /// Bytes the client already sent that were buffered but not read yet are dropped. Use [`IncomingConnection::into_parts()`] to keep them.
+    #[inline]
+    pub fn into_inner(self) -> Tracked<T> {
+        self.into_parts().0
+    }
+
+    /// Consumes the [`IncomingConnection`] and returns the underlying stream alongside the bytes the client already sent that were buffered but not read yet. The stream comes in a [`Tracked`], as with [`IncomingConnection::into_inner()`].
+    #[inline]
+    pub fn into_parts(mut self) -> (Tracked<T>, Bytes) {
+        self.session.set_close_reason(CloseReason::Detached);
+        self.stream.into_parts()
+    }
+}
+
+impl<A, S, T: AsyncWrite + Unpin> IncomingConnection<A, S, T> {
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
     #[inline]
     pub async fn close(&mut self) -> Result<(), IoError> {
         self.stream.shutdown().await
     }
+}
+
+impl<A> IncomingConnection<A, state::NeedAuthenticate> {
+    /// Peeks at the first byte sent by the client to tell which protocol it speaks, without consuming it.
+    ///
+    /// This waits until the client sends something, then reports it as a [`Sniff`]. The byte is looked at with [`TcpStream::peek()`], so it stays queued on the socket: on [`Sniff::Socks5`], [`IncomingConnection::authenticate()`] can be called as usual, while on e.g. [`Sniff::HttpLike`] the untouched stream can be taken out with [`IncomingConnection::into_inner()`] and handed to another handler. Misconfigured clients are then turned away without a negotiation. If the handshake was already received by [`IncomingConnection::wait_handshake()`], the byte is looked at in the buffer instead.
+    ///
+    /// An error of kind [`ErrorKind::UnexpectedEof`] is returned if the client closes the connection before sending anything. The session setup deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline) applies, failing with [`Error::SetupDeadlineExceeded`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This method is cancel safe, as it consumes nothing.
+    pub async fn peek_version(&self) -> Result<Sniff, Error> {
+        if let Some(&byte) = self.stream.buffer().first() {
+            return Ok(Sniff::from_first_byte(byte));
+        }
+
+        let mut buf = [0];
+
+        match self
+            .session
+            .setup(self.stream.get_ref().peek(&mut buf))
+            .await?
+        {
+            0 => Err(IoError::from(ErrorKind::UnexpectedEof).into()),
+            _ => Ok(Sniff::from_first_byte(buf[0])),
+        }
+    }
+}
+
+/// The protocol a client speaks going by the first byte it sent, see [`IncomingConnection::peek_version()`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Sniff {
+    /// A SOCKS5 handshake, starting with `0x05`
+    Socks5,
+
+    /// A SOCKS4 or SOCKS4a request, starting with `0x04`
+    Socks4,
+
+    /// A request starting with an uppercase ASCII letter, e.g. the method of an HTTP request such as `GET` or `CONNECT`
+    HttpLike,
+
+    /// Anything else, with the first byte, e.g. `0x16` for a TLS ClientHello
+    Unknown(u8),
+}
+
+impl Sniff {
+    fn from_first_byte(byte: u8) -> Self {
+        match byte {
+            socks5_proto::SOCKS_VERSION => Self::Socks5,
+            0x04 => Self::Socks4,
+            b'A'..=b'Z' => Self::HttpLike,
+            byte => Self::Unknown(byte),
+        }
+    }
+}
+
+impl<A, S> IncomingConnection<A, S> {
+    /// Closes the connection right away with a reset rather than the orderly shutdown of [`IncomingConnection::close()`], e.g. for an abusive client.
+    ///
+    /// `SO_LINGER` is set to 0 on the socket before it is dropped, so the client receives an RST and the state of the socket is freed at once, rather than the connection lingering half-open until the client closes it too. Data still pending to be sent is discarded. The stream is dropped either way, so if setting the option fails, the error is returned and the connection is closed with a regular shutdown.
+    #[inline]
+    pub fn abort(self) -> Result<(), IoError> {
+        self.stream.abort()
+    }
 
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, IoError> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
-    /// Returns a shared reference to the underlying stream.
+    /// Returns whether TCP keepalive is enabled on the stream with `SO_KEEPALIVE`, e.g. by [`Server::set_tcp_keepalive()`](crate::Server::set_tcp_keepalive).
+    #[inline]
+    pub fn tcp_keepalive(&self) -> Result<bool, IoError> {
+        SockRef::from(self.stream.get_ref()).keepalive()
+    }
+
+    /// Enables TCP keepalive on the stream with the parameters of `keepalive`, as [`Server::set_tcp_keepalive()`](crate::Server::set_tcp_keepalive) does for every connection, e.g. for a client needing other settings than the others.
+    #[inline]
+    pub fn set_tcp_keepalive(&self, keepalive: &TcpKeepalive) -> Result<(), IoError> {
+        SockRef::from(self.stream.get_ref()).set_tcp_keepalive(keepalive)
+    }
+
+    /// Disables TCP keepalive on the stream.
+    #[inline]
+    pub fn clear_tcp_keepalive(&self) -> Result<(), IoError> {
+        SockRef::from(self.stream.get_ref()).set_keepalive(false)
+    }
+
+    /// Gets the value of the `TCP_NODELAY` option on the stream.
+    #[inline]
+    pub fn nodelay(&self) -> Result<bool, IoError> {
+        self.stream.get_ref().nodelay()
+    }
+
+    /// Sets the value of the `TCP_NODELAY` option on the stream, e.g. to send the replies of the negotiation without waiting on the acknowledgement of the previous segment.
     ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    /// Like the other socket options, it is kept by the command types the connection turns into, as they wrap the same stream.
     #[inline]
-    pub fn get_ref(&self) -> &TcpStream {
-        &self.stream
+    pub fn set_nodelay(&self, nodelay: bool) -> Result<(), IoError> {
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
-    /// Returns a mutable reference to the underlying stream.
+    /// Reads the linger duration of the stream, the value of the `SO_LINGER` option.
+    #[inline]
+    pub fn linger(&self) -> Result<Option<Duration>, IoError> {
+        SockRef::from(self.stream.get_ref()).linger()
+    }
+
+    /// Sets the linger duration of the stream, the value of the `SO_LINGER` option.
     ///
-    /// Note that this may break the encapsulation of the SOCKS5 connection and you should not use this method unless you know what you are doing.
+    /// Note that a non-zero linger blocks the thread dropping the stream until the data sent is acknowledged or the duration elapses, while a zero one resets the connection on drop rather than shutting it down.
     #[inline]
-    pub fn get_mut(&mut self) -> &mut TcpStream {
-        &mut self.stream
+    pub fn set_linger(&self, linger: Option<Duration>) -> Result<(), IoError> {
+        SockRef::from(self.stream.get_ref()).set_linger(linger)
     }
 
-    /// Consumes the [`IncomingConnection`] and returns the underlying [`TcpStream`](tokio::net::TcpStream).
+    /// Gets the value of the `IP_TTL` option on the stream.
     #[inline]
-    pub fn into_inner(self) -> TcpStream {
-        self.stream
+    pub fn ttl(&self) -> Result<u32, IoError> {
+        self.stream.get_ref().ttl()
+    }
+
+    /// Sets the value of the `IP_TTL` option on the stream, the time-to-live of the packets sent from it.
+    #[inline]
+    pub fn set_ttl(&self, ttl: u32) -> Result<(), IoError> {
+        self.stream.get_ref().set_ttl(ttl)
+    }
+
+    /// Returns the destination the client originally connected to, for a connection redirected to the server by netfilter, e.g. with an `iptables` `REDIRECT` or `TPROXY` rule, as `SO_ORIGINAL_DST` (`IP6T_SO_ORIGINAL_DST` over IPv6) reports it. This lets clients not speaking SOCKS5 at all be relayed as if they sent a `Connect` command to it.
+    ///
+    /// Fails with an error of kind [`ErrorKind::NotFound`] if the connection was not redirected, and of kind [`ErrorKind::Unsupported`] on platforms other than Linux and Android.
+    #[inline]
+    pub fn original_dst(&self) -> Result<SocketAddr, IoError> {
+        sys::original_dst(self.stream.get_ref())
+    }
+}
+
+#[cfg(unix)]
+impl<A, S> IncomingConnection<A, S, UnixStream> {
+    /// Returns the local address that this stream is bound to, for a connection over a [`UnixStream`].
+    #[inline]
+    pub fn local_addr(&self) -> Result<UnixSocketAddr, IoError> {
+        self.stream.get_ref().local_addr()
+    }
+
+    /// Returns the remote address that this stream is connected to, for a connection over a [`UnixStream`].
+    #[inline]
+    pub fn peer_addr(&self) -> Result<UnixSocketAddr, IoError> {
+        self.stream.get_ref().peer_addr()
+    }
+}
+
+/// The reply to a request rejected with `err` by [`IncomingConnection::wait_or_reject()`], if it is not replied to by [`IncomingConnection::wait()`] already.
+fn rejection(err: &ProtocolError) -> Option<Reply> {
+    match err {
+        ProtocolError::DomainTooLongInRequest { .. } => None,
+        ProtocolError::InvalidCommand { .. } => Some(Reply::CommandNotSupported),
+        ProtocolError::InvalidAddressTypeInRequest { .. } => Some(Reply::AddressTypeNotSupported),
+        _ => Some(Reply::GeneralFailure),
     }
 }
 
-impl<A, S> Debug for IncomingConnection<A, S> {
+/// Whether parsing the buffered bytes failed only because the message is not fully received yet.
+#[inline]
+fn is_incomplete(err: &Socks5Error) -> bool {
+    matches!(err, Socks5Error::Io(err) if err.kind() == ErrorKind::UnexpectedEof)
+}
+
+impl<A, S, T: Debug> Debug for IncomingConnection<A, S, T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IncomingConnection")
             .field("stream", &self.stream)
@@ -164,8 +846,57 @@ impl<A, S> Debug for IncomingConnection<A, S> {
 
 /// A command sent from the SOCKS5 client.
 #[derive(Debug)]
-pub enum Command {
-    Associate(Associate<associate::state::NeedReply>, Address),
-    Bind(Bind<bind::state::NeedFirstReply>, Address),
-    Connect(Connect<connect::state::NeedReply>, Address),
+pub enum Command<T = TcpStream> {
+    Associate(Associate<associate::state::NeedReply, T>, Address),
+    Bind(Bind<bind::state::NeedFirstReply, T>, Address),
+    Connect(Connect<connect::state::NeedReply, T>, Address),
+}
+
+/// `SO_ORIGINAL_DST` is only available on Linux and Android, following `socket2`. Elsewhere, an error of kind [`ErrorKind::Unsupported`] is returned.
+mod sys {
+    use std::{io::Error, net::SocketAddr};
+    use tokio::net::TcpStream;
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub(super) fn original_dst(stream: &TcpStream) -> Result<SocketAddr, Error> {
+        use socket2::SockRef;
+        use std::io::ErrorKind;
+
+        let socket = SockRef::from(stream);
+
+        // IPv4 clients of a dual-stack socket are tracked by the IPv4 table
+        let is_ipv6 = match stream.local_addr()? {
+            SocketAddr::V4(_) => false,
+            SocketAddr::V6(addr) => addr.ip().to_ipv4_mapped().is_none(),
+        };
+
+        let res = if is_ipv6 {
+            socket.original_dst_v6()
+        } else {
+            socket.original_dst_v4()
+        };
+
+        match res {
+            Ok(addr) => addr.as_socket().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "original destination is not an IP address",
+                )
+            }),
+            // `ENOENT`, no conntrack entry for the connection
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(Error::new(
+                ErrorKind::NotFound,
+                "connection was not redirected, it has no original destination",
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub(super) fn original_dst(_: &TcpStream) -> Result<SocketAddr, Error> {
+        Err(Error::new(
+            std::io::ErrorKind::Unsupported,
+            "SO_ORIGINAL_DST is not supported on this platform",
+        ))
+    }
 }
@@ -0,0 +1,314 @@
+use crate::{
+    error::{Error as Socks5ServerError, Failed},
+    tap::{Direction, WireTap},
+    tracker::{ConnectionGuard, Tracked},
+};
+use bytes::Bytes;
+use socket2::SockRef;
+use socks5_proto::ParseLimits;
+use std::{
+    future,
+    io::{Error, IoSlice},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufRead, AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+};
+
+/// The size of the read buffer, large enough for any single negotiation message.
+const CAPACITY: usize = 1024;
+
+/// A stream with a read buffer, whose unconsumed remainder can be handed forward with [`BufferedStream::into_parts()`].
+///
+/// This is the stream carried by [`IncomingConnection`](crate::IncomingConnection) through the negotiation, and by the command types afterwards. Reads go through the buffer, so a client pipelining e.g. the handshake, the request and the first payload bytes in one segment may have more bytes read than the current step consumes. Those bytes stay in the buffer across every state transition, are returned first by [`AsyncRead`], and are never dropped unless the stream is dropped.
+///
+/// Writes are passed through to the underlying stream unbuffered.
+///
+/// While the connection is negotiated, the bytes consumed from and written to the stream are captured by the [`WireTap`] of the connection, if any.
+#[derive(Debug)]
+pub struct BufferedStream<S> {
+    inner: S,
+    buf: Vec<u8>,
+    pos: usize,
+    filled: usize,
+    limits: ParseLimits,
+    tap: Option<WireTap>,
+    tapped: Vec<u8>,
+    guard: Option<ConnectionGuard>,
+}
+
+impl<S> BufferedStream<S> {
+    /// Creates a new [`BufferedStream`] with an empty buffer.
+    #[inline]
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            buf: Vec::new(),
+            pos: 0,
+            filled: 0,
+            limits: ParseLimits::default(),
+            tap: None,
+            tapped: Vec::new(),
+            guard: None,
+        }
+    }
+
+    /// Creates a new [`BufferedStream`] whose buffer holds `buffered`, e.g. the remainder returned by [`BufferedStream::into_parts()`] of a previous one. Those bytes are read before anything from `inner`.
+    pub fn from_parts(inner: S, buffered: Bytes) -> Self {
+        let filled = buffered.len();
+
+        Self {
+            inner,
+            buf: Vec::from(buffered),
+            pos: 0,
+            filled,
+            limits: ParseLimits::default(),
+            tap: None,
+            tapped: Vec::new(),
+            guard: None,
+        }
+    }
+
+    /// Makes the stream hold the guard counting its connection, handed forward by [`BufferedStream::into_parts()`].
+    #[inline]
+    pub(crate) fn with_guard(mut self, guard: ConnectionGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    #[cfg(feature = "serve")]
+    #[inline]
+    pub(crate) fn take_guard(&mut self) -> Option<ConnectionGuard> {
+        self.guard.take()
+    }
+
+    #[inline]
+    pub(crate) fn with_parse_limits(mut self, limits: ParseLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn set_wire_tap(&mut self, tap: Option<WireTap>) {
+        self.flush_wire_tap();
+        self.tap = tap;
+    }
+
+    /// Takes the wire tap out for the sub-negotiation of the [`Auth`](crate::Auth) adaptor, unless it captures credentials. Put it back with [`BufferedStream::set_wire_tap()`].
+    #[inline]
+    pub(crate) fn suspend_wire_tap(&mut self) -> Option<WireTap> {
+        match &self.tap {
+            Some(tap) if !tap.captures_credentials() => {
+                self.flush_wire_tap();
+                self.tap.take()
+            }
+            _ => None,
+        }
+    }
+
+    /// Stops capturing, once the negotiation is over.
+    #[inline]
+    pub(crate) fn end_wire_tap(&mut self) {
+        self.set_wire_tap(None);
+    }
+
+    /// Passes the bytes consumed since the last flush to the wire tap as one chunk, e.g. once a message is parsed, rather than field by field as they are read.
+    pub(crate) fn flush_wire_tap(&mut self) {
+        if let Some(tap) = &self.tap {
+            tap.capture(Direction::Inbound, &self.tapped);
+            self.tapped.clear();
+        }
+    }
+
+    /// Returns the [`ParseLimits`] the negotiation messages of this stream are read with, see [`Server::set_parse_limits()`](crate::Server::set_parse_limits). [`Auth`](crate::Auth) adaptors reading a sub-negotiation should honor them too.
+    #[inline]
+    pub fn parse_limits(&self) -> &ParseLimits {
+        &self.limits
+    }
+
+    /// Returns the bytes read from the underlying stream but not consumed yet.
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.pos..self.filled]
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    ///
+    /// Note that reading from the underlying stream directly skips the bytes in the buffer.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Consumes the [`BufferedStream`] and returns the underlying stream alongside the bytes read from it but not consumed yet.
+    ///
+    /// The stream comes in a [`Tracked`], so a connection accepted by a [`Server`](crate::Server) is still counted by its [`ConnectionTracker`](crate::tracker::ConnectionTracker) until it is dropped.
+    pub fn into_parts(self) -> (Tracked<S>, Bytes) {
+        let buf = Bytes::from(self.buf).slice(self.pos..self.filled);
+        (Tracked::new(self.inner, self.guard), buf)
+    }
+}
+
+impl BufferedStream<TcpStream> {
+    /// Drops the stream after setting `SO_LINGER` to 0, resetting the connection rather than shutting it down, see e.g. [`Connect::abort()`](crate::Connect::abort).
+    pub(crate) fn abort(self) -> Result<(), Error> {
+        SockRef::from(&self.inner).set_linger(Some(Duration::ZERO))
+    }
+}
+
+impl<S: AsyncRead + Unpin> BufferedStream<S> {
+    /// Reads more bytes from the underlying stream, appending them to the unconsumed ones. Returns the number of bytes read, `0` meaning EOF.
+    ///
+    /// This is cancel safe, as the buffer is only extended once a read completes.
+    pub(crate) async fn fill_more(&mut self) -> Result<usize, Error> {
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.filled, 0);
+            self.filled -= self.pos;
+            self.pos = 0;
+        }
+
+        if self.filled == self.buf.len() {
+            let len = (self.buf.len() * 2).max(CAPACITY);
+            self.buf.resize(len, 0);
+        }
+
+        let mut buf = ReadBuf::new(&mut self.buf[self.filled..]);
+        future::poll_fn(|cx| Pin::new(&mut self.inner).poll_read(cx, &mut buf)).await?;
+
+        let len = buf.filled().len();
+        self.filled += len;
+        Ok(len)
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for BufferedStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        // skip the buffer entirely for large reads when it is empty
+        if self.pos == self.filled && buf.remaining() >= CAPACITY {
+            let start = buf.filled().len();
+            ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+
+            if self.tap.is_some() {
+                self.tapped.extend_from_slice(&buf.filled()[start..]);
+            }
+
+            return Poll::Ready(Ok(()));
+        }
+
+        let rem = ready!(self.as_mut().poll_fill_buf(cx))?;
+        let len = rem.len().min(buf.remaining());
+        buf.put_slice(&rem[..len]);
+        self.consume(len);
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncBufRead for BufferedStream<S> {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<&[u8], Error>> {
+        let this = self.get_mut();
+
+        if this.pos == this.filled {
+            if this.buf.len() < CAPACITY {
+                this.buf.resize(CAPACITY, 0);
+            }
+
+            let mut buf = ReadBuf::new(&mut this.buf);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut buf))?;
+
+            this.filled = buf.filled().len();
+            this.pos = 0;
+        }
+
+        Poll::Ready(Ok(&this.buf[this.pos..this.filled]))
+    }
+
+    #[inline]
+    fn consume(self: Pin<&mut Self>, amt: usize) {
+        let this = self.get_mut();
+        let end = (this.pos + amt).min(this.filled);
+
+        if this.tap.is_some() {
+            this.tapped.extend_from_slice(&this.buf[this.pos..end]);
+        }
+
+        this.pos = end;
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for BufferedStream<S> {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        let len = ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.flush_wire_tap();
+
+        if let Some(tap) = &self.tap {
+            tap.capture(Direction::Outbound, &buf[..len]);
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, Error>> {
+        let len = ready!(Pin::new(&mut self.inner).poll_write_vectored(cx, bufs))?;
+        self.flush_wire_tap();
+
+        if let Some(tap) = &self.tap {
+            let mut rem = len;
+
+            for buf in bufs {
+                let part = rem.min(buf.len());
+                tap.capture(Direction::Outbound, &buf[..part]);
+                rem -= part;
+            }
+        }
+
+        Poll::Ready(Ok(len))
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Fails a negotiation step, handing the stream back to the caller without its wire tap.
+pub(crate) fn failed<S, E: Into<Socks5ServerError>>(
+    err: E,
+    mut stream: BufferedStream<S>,
+) -> Failed<BufferedStream<S>> {
+    stream.end_wire_tap();
+    Failed::new(err, stream)
+}
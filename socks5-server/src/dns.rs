@@ -0,0 +1,434 @@
+//! This module defines trait [`Resolver`] and some pre-defined resolvers.
+//!
+//! Domain name resolution used by this library, e.g. for domain destinations in SOCKS5 UDP headers, can be customized by implementing [`Resolver`] trait on your own types.
+
+use async_trait::async_trait;
+use socks5_proto::Address;
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future,
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    task::{Poll, Waker},
+    time::Duration,
+};
+use tokio::{net, time::Instant};
+
+/// This trait is for defining the customized process of domain name resolution.
+///
+/// # Example
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_server::dns::Resolver;
+/// use std::{
+///     io::Error,
+///     net::{IpAddr, Ipv4Addr},
+/// };
+///
+/// pub struct MyResolver;
+///
+/// #[async_trait]
+/// impl Resolver for MyResolver {
+///     async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+///         // look the name up somewhere
+///         Ok(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Resolver {
+    /// Resolves a domain name into IP addresses.
+    async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error>;
+
+    /// Resolves a domain name into IP addresses, alongside how long the result may be cached for if known, e.g. the smallest TTL of the DNS records.
+    ///
+    /// The default implementation calls [`Resolver::resolve()`] and knows no TTL. [`CachingResolver`] caches resolutions for at most this long.
+    async fn resolve_with_ttl(&self, name: &str) -> Result<(Vec<IpAddr>, Option<Duration>), Error> {
+        self.resolve(name).await.map(|addrs| (addrs, None))
+    }
+}
+
+impl Debug for dyn Resolver + Send + Sync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("Resolver")
+    }
+}
+
+/// Resolves domain names with the system resolver, using [`tokio::net::lookup_host()`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+        let addrs = net::lookup_host((name, 0)).await?;
+        Ok(addrs.map(|addr| addr.ip()).collect())
+    }
+}
+
+/// A resolver adaptor caching successful and failed resolutions of another resolver.
+///
+/// Successful resolutions are cached for `positive_ttl` and failures for `negative_ttl`, keyed by domain name. If the wrapped resolver knows the TTL of a resolution (see [`Resolver::resolve_with_ttl()`]), `positive_ttl` caps it. At most `capacity` names are cached. When the cache is full, expired entries are evicted first, then the least recently used one.
+///
+/// Concurrent lookups of a name that is not cached are deduplicated: the first one resolves it with the wrapped resolver, and the others wait for its result instead of resolving the name again.
+///
+/// Hits, misses and errors are counted, see [`CachingResolver::stats()`]. The cache is shared by everything holding the resolver, so share one across `Connect` handling and UDP relays to resolve every name once. [`shared_resolver()`] is the one this crate uses by default.
+#[derive(Debug)]
+pub struct CachingResolver<R> {
+    inner: R,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+    flights: Mutex<HashMap<String, Arc<Flight>>>,
+    positive_ttl: Duration,
+    negative_ttl: Duration,
+    capacity: usize,
+    counters: Counters,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    addrs: Option<Vec<IpAddr>>,
+    expires: Instant,
+    last_used: Instant,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    errors: AtomicU64,
+}
+
+/// A snapshot of the counters of a [`CachingResolver`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CachingResolverStats {
+    /// Lookups answered from the cache, failures included
+    pub hits: u64,
+
+    /// Lookups of names that were not cached
+    pub misses: u64,
+
+    /// Misses answered by waiting for a concurrent lookup of the same name instead of resolving it again
+    pub coalesced: u64,
+
+    /// Resolutions of the wrapped resolver that failed
+    pub errors: u64,
+
+    /// Names currently cached, expired ones included until they are evicted
+    pub entries: usize,
+}
+
+/// The result of a lookup shared with concurrent lookups of the same name. The error is kept as its kind and message, as [`Error`] can not be cloned.
+type FlightResult = Result<(Vec<IpAddr>, Duration), (ErrorKind, String)>;
+
+/// A lookup of a name in progress, which concurrent lookups of the same name wait for
+#[derive(Debug, Default)]
+struct Flight(Mutex<FlightState>);
+
+#[derive(Debug, Default)]
+struct FlightState {
+    /// `Some(None)` if the lookup was cancelled before it finished
+    done: Option<Option<FlightResult>>,
+    wakers: Vec<Waker>,
+}
+
+impl Flight {
+    fn finish(&self, res: Option<FlightResult>) {
+        let mut state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        state.done = Some(res);
+
+        for waker in state.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    async fn wait(&self) -> Option<FlightResult> {
+        future::poll_fn(|cx| {
+            let mut state = self.0.lock().unwrap_or_else(|err| err.into_inner());
+
+            match &state.done {
+                Some(res) => Poll::Ready(res.clone()),
+                None => {
+                    if !state.wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                        state.wakers.push(cx.waker().clone());
+                    }
+
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+/// Removes the flight of the lookup leading it when the lookup finishes or is cancelled, handing its result to the waiting ones.
+struct FlightGuard<'a> {
+    flights: &'a Mutex<HashMap<String, Arc<Flight>>>,
+    name: &'a str,
+    flight: Arc<Flight>,
+    res: Option<FlightResult>,
+}
+
+impl Drop for FlightGuard<'_> {
+    fn drop(&mut self) {
+        self.flights
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(self.name);
+        self.flight.finish(self.res.take());
+    }
+}
+
+impl<R> CachingResolver<R> {
+    /// Creates a new [`CachingResolver`] wrapping `inner`.
+    pub fn new(inner: R, positive_ttl: Duration, negative_ttl: Duration, capacity: usize) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+            flights: Mutex::new(HashMap::new()),
+            positive_ttl,
+            negative_ttl,
+            capacity,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Returns a shared reference to the wrapped resolver.
+    #[inline]
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns a snapshot of the counters of this resolver.
+    pub fn stats(&self) -> CachingResolverStats {
+        CachingResolverStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            errors: self.counters.errors.load(Ordering::Relaxed),
+            entries: self
+                .cache
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .len(),
+        }
+    }
+
+    /// Looks `name` up in the cache, returning the cached addresses with their remaining TTL, or `None` for a cached failure.
+    fn lookup(&self, name: &str) -> Option<Option<(Vec<IpAddr>, Duration)>> {
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+        let now = Instant::now();
+
+        match cache.get_mut(name) {
+            Some(entry) if entry.expires > now => {
+                entry.last_used = now;
+                let remaining = entry.expires - now;
+                Some(entry.addrs.clone().map(|addrs| (addrs, remaining)))
+            }
+            Some(_) => {
+                cache.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, name: &str, addrs: Option<Vec<IpAddr>>, ttl: Duration) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut cache = self.cache.lock().unwrap_or_else(|err| err.into_inner());
+
+        if cache.len() >= self.capacity && !cache.contains_key(name) {
+            cache.retain(|_, entry| entry.expires > now);
+        }
+
+        if cache.len() >= self.capacity && !cache.contains_key(name) {
+            let lru = cache
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+
+            if let Some(lru) = lru {
+                cache.remove(&lru);
+            }
+        }
+
+        cache.insert(
+            name.to_owned(),
+            CacheEntry {
+                addrs,
+                expires: now + ttl,
+                last_used: now,
+            },
+        );
+    }
+
+    fn hit(
+        &self,
+        cached: Option<(Vec<IpAddr>, Duration)>,
+        name: &str,
+    ) -> Result<(Vec<IpAddr>, Option<Duration>), Error> {
+        self.counters.hits.fetch_add(1, Ordering::Relaxed);
+
+        match cached {
+            Some((addrs, remaining)) => Ok((addrs, Some(remaining))),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("failed to resolve {name} (cached)"),
+            )),
+        }
+    }
+}
+
+impl<R: Default> Default for CachingResolver<R> {
+    /// Creates a [`CachingResolver`] caching successes for 60 seconds and failures for 5 seconds, with a capacity of 1024 names.
+    fn default() -> Self {
+        Self::new(
+            R::default(),
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+            1024,
+        )
+    }
+}
+
+#[async_trait]
+impl<R: Resolver + Send + Sync> Resolver for CachingResolver<R> {
+    async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+        self.resolve_with_ttl(name).await.map(|(addrs, _)| addrs)
+    }
+
+    /// Resolves `name` from the cache if possible, returning the remaining TTL of the cached addresses.
+    async fn resolve_with_ttl(&self, name: &str) -> Result<(Vec<IpAddr>, Option<Duration>), Error> {
+        if let Some(cached) = self.lookup(name) {
+            return self.hit(cached, name);
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+
+        loop {
+            let leading = {
+                let mut flights = self.flights.lock().unwrap_or_else(|err| err.into_inner());
+
+                match flights.get(name) {
+                    Some(flight) => Err(flight.clone()),
+                    None => {
+                        // a lookup may have finished since the cache was checked
+                        if let Some(cached) = self.lookup(name) {
+                            drop(flights);
+                            return self.hit(cached, name);
+                        }
+
+                        let flight = Arc::new(Flight::default());
+                        flights.insert(name.to_owned(), flight.clone());
+                        Ok(flight)
+                    }
+                }
+            };
+
+            let flight = match leading {
+                Ok(flight) => flight,
+                Err(flight) => match flight.wait().await {
+                    Some(res) => {
+                        self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+
+                        return res
+                            .map(|(addrs, ttl)| (addrs, Some(ttl)))
+                            .map_err(|(kind, msg)| Error::new(kind, msg));
+                    }
+                    // the leading lookup was cancelled, so lead one
+                    None => continue,
+                },
+            };
+
+            let mut guard = FlightGuard {
+                flights: &self.flights,
+                name,
+                flight,
+                res: None,
+            };
+
+            return match self.inner.resolve_with_ttl(name).await {
+                Ok((addrs, ttl)) => {
+                    let ttl = ttl.map_or(self.positive_ttl, |ttl| ttl.min(self.positive_ttl));
+                    self.insert(name, Some(addrs.clone()), ttl);
+                    guard.res = Some(Ok((addrs.clone(), ttl)));
+                    Ok((addrs, Some(ttl)))
+                }
+                Err(err) => {
+                    self.counters.errors.fetch_add(1, Ordering::Relaxed);
+                    self.insert(name, None, self.negative_ttl);
+                    guard.res = Some(Err((err.kind(), err.to_string())));
+                    Err(err)
+                }
+            };
+        }
+    }
+}
+
+/// Returns the [`CachingResolver`] over [`SystemResolver`] shared by everything in this crate resolving domain names by default, e.g. [`UdpRelayConfig`](crate::connection::associate::UdpRelayConfig) and [`AssociatedUdpSocket`](crate::AssociatedUdpSocket).
+///
+/// Pass it to your own resolution paths, e.g. when connecting to the destination of a `Connect` command, so they share the cache with the UDP relays. It has the settings of [`CachingResolver::default()`].
+pub fn shared_resolver() -> Arc<CachingResolver<SystemResolver>> {
+    static SHARED: OnceLock<Arc<CachingResolver<SystemResolver>>> = OnceLock::new();
+    SHARED.get_or_init(Default::default).clone()
+}
+
+/// Resolves a SOCKS5 address into a socket address, resolving the domain name with `resolver` if needed.
+///
+/// The first address returned by the resolver is used. A domain name that is an IP address literal is used as is, without the resolver. This includes IPv6 addresses with a numeric zone, e.g. `fe80::1%3`, which keeps the zone for dialing.
+///
+/// Note that IPv6 socket addresses decoded from the SOCKS5 wire format never have a zone, as the format has no room for one. Link-local destinations received that way can only be reached through the interface the operating system picks, so servers relaying to them should set a default zone configured by the operator, e.g. with [`SocketAddrV6::set_scope_id()`].
+pub async fn resolve_address<R>(resolver: &R, addr: &Address) -> Result<SocketAddr, Error>
+where
+    R: Resolver + ?Sized,
+{
+    match addr {
+        Address::SocketAddress(addr) => Ok(*addr),
+        Address::DomainAddress(name, port) => {
+            let name = std::str::from_utf8(name)
+                .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+
+            if let Some(addr) = parse_ip_literal(name, *port) {
+                return Ok(addr);
+            }
+
+            let ip = resolver
+                .resolve(name)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    Error::new(ErrorKind::NotFound, format!("no address found for {name}"))
+                })?;
+
+            Ok(SocketAddr::new(ip, *port))
+        }
+    }
+}
+
+/// Parses an IP address literal, optionally in brackets and with a numeric IPv6 zone.
+fn parse_ip_literal(name: &str, port: u16) -> Option<SocketAddr> {
+    let name = name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+        .unwrap_or(name);
+
+    if let Ok(ip) = name.parse::<IpAddr>() {
+        return Some(SocketAddr::new(ip, port));
+    }
+
+    let (ip, zone) = name.split_once('%')?;
+    let ip = ip.parse::<Ipv6Addr>().ok()?;
+    let scope_id = zone.parse().ok()?;
+
+    Some(SocketAddrV6::new(ip, port, 0, scope_id).into())
+}
@@ -0,0 +1,276 @@
+//! This module defines [`Error`], the error type of the negotiation methods of this crate, and [`Failed`], which they return it in alongside the recovered stream.
+
+use crate::{connection::BufferedStream, hostname::HostnameViolation, IncomingConnection};
+use socks5_proto::{Error as Socks5Error, ProtocolError};
+use std::{
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    io::{Error as IoError, ErrorKind},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+    time::error::Elapsed,
+};
+
+/// Errors of negotiating a SOCKS5 connection
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The peer violated the SOCKS5 protocol, e.g. sent a message of another protocol version.
+    Protocol(ProtocolError),
+
+    /// Reading from or writing to the stream failed.
+    Io(IoError),
+
+    /// A step of the negotiation did not finish in time.
+    Timeout,
+
+    /// The client failed to authenticate.
+    AuthFailed,
+
+    /// The request of the client was denied by a policy.
+    PolicyDenied,
+
+    /// The connection was not set up, i.e. replied to, by the deadline set with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline).
+    SetupDeadlineExceeded,
+
+    /// The handshake, including the sub-negotiation of the [`Auth`](crate::Auth) adaptor, did not finish within the timeout given to [`IncomingConnection::authenticate_with_timeout()`](crate::IncomingConnection::authenticate_with_timeout).
+    AuthTimeout,
+
+    /// The client did not send its request within the timeout given to [`IncomingConnection::wait_with_timeout()`](crate::IncomingConnection::wait_with_timeout).
+    RequestTimeout,
+
+    /// The client requested a domain name violating the [`HostnamePolicy`](crate::hostname::HostnamePolicy) of the server.
+    InvalidHostname {
+        /// The name requested, e.g. for logging
+        name: Vec<u8>,
+
+        /// The rule the name violates
+        violation: HostnameViolation,
+    },
+}
+
+impl Error {
+    /// Returns the [`ErrorKind`] of the [`std::io::Error`] this error converts into.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(err) => err.kind(),
+            Self::Protocol(_) => ErrorKind::InvalidData,
+            Self::Timeout
+            | Self::SetupDeadlineExceeded
+            | Self::AuthTimeout
+            | Self::RequestTimeout => ErrorKind::TimedOut,
+            Self::InvalidHostname { .. } => ErrorKind::InvalidInput,
+            Self::AuthFailed | Self::PolicyDenied => ErrorKind::PermissionDenied,
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::Protocol(err) => Display::fmt(err, f),
+            Self::Io(err) => Display::fmt(err, f),
+            Self::Timeout => f.write_str("timed out"),
+            Self::AuthFailed => f.write_str("authentication failed"),
+            Self::PolicyDenied => f.write_str("denied by policy"),
+            Self::SetupDeadlineExceeded => f.write_str("session setup deadline exceeded"),
+            Self::AuthTimeout => f.write_str("authentication timed out"),
+            Self::RequestTimeout => f.write_str("request timed out"),
+            Self::InvalidHostname { name, violation } => write!(
+                f,
+                "invalid hostname {:?}: {violation}",
+                String::from_utf8_lossy(name)
+            ),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Protocol(err) => err.source(),
+            Self::Io(err) => err.source(),
+            Self::InvalidHostname { violation, .. } => Some(violation),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProtocolError> for Error {
+    #[inline]
+    fn from(err: ProtocolError) -> Self {
+        Self::Protocol(err)
+    }
+}
+
+impl From<IoError> for Error {
+    #[inline]
+    fn from(err: IoError) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<Socks5Error> for Error {
+    #[inline]
+    fn from(err: Socks5Error) -> Self {
+        match err {
+            Socks5Error::Protocol(err) => Self::Protocol(err),
+            Socks5Error::Io(err) => Self::Io(err),
+        }
+    }
+}
+
+impl From<Elapsed> for Error {
+    #[inline]
+    fn from(_: Elapsed) -> Self {
+        Self::Timeout
+    }
+}
+
+impl From<Error> for IoError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::Protocol(err) => IoError::new(ErrorKind::InvalidData, err),
+            err => IoError::new(err.kind(), err),
+        }
+    }
+}
+
+/// A failed negotiation step, carrying the [`Error`] and the stream it failed on, if it could be recovered.
+///
+/// [`IncomingConnection::authenticate()`](crate::IncomingConnection::authenticate) and [`IncomingConnection::wait()`](crate::IncomingConnection::wait) recover the [`IncomingConnection`] itself, in the state it failed in, rather than its bare stream, while the reply methods of the commands recover a [`BufferedStream`].
+///
+/// The negotiation methods of this crate never close the stream on failure, so the client can e.g. be replied to before it is shut down. Get it back with [`Failed::into_stream()`], or destructure the public fields. [`Failed::shutdown_and_err()`] does the usual cleanup in one call.
+///
+/// [`Failed`] converts into [`Error`] and [`std::io::Error`], dropping the stream, so `?` works in functions returning either.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     connection::state::NeedCommand,
+///     proto::{Address, Reply, Response},
+///     Command, Error, IncomingConnection,
+/// };
+///
+/// async fn wait(conn: IncomingConnection<(), NeedCommand>) -> Result<Command, Error> {
+///     match conn.wait().await {
+///         Ok(command) => Ok(command),
+///         Err(mut failed) => {
+///             // tell the client before closing the connection
+///             if let Some(conn) = failed.stream() {
+///                 let resp = Response::new(Reply::GeneralFailure, Address::unspecified());
+///                 let _ = resp.write_to(conn.get_mut()).await;
+///             }
+///
+///             Err(failed.shutdown_and_err().await)
+///         }
+///     }
+/// }
+/// ```
+pub struct Failed<S = BufferedStream<TcpStream>> {
+    /// Why the step failed
+    pub error: Error,
+
+    /// The stream the step failed on
+    pub stream: Option<S>,
+}
+
+impl<S> Failed<S> {
+    /// Creates a new [`Failed`] with the stream recovered.
+    #[inline]
+    pub fn new<E: Into<Error>>(error: E, stream: S) -> Self {
+        Self {
+            error: error.into(),
+            stream: Some(stream),
+        }
+    }
+
+    /// Consumes the [`Failed`] and returns the recovered stream, if any.
+    #[inline]
+    pub fn into_stream(self) -> Option<S> {
+        self.stream
+    }
+
+    /// Consumes the [`Failed`] and returns the error, dropping the stream.
+    #[inline]
+    pub fn into_error(self) -> Error {
+        self.error
+    }
+
+    /// Returns a mutable reference to the recovered stream, if any, e.g. to write a reply to the client before shutting it down.
+    #[inline]
+    pub fn stream(&mut self) -> Option<&mut S> {
+        self.stream.as_mut()
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Failed<S> {
+    /// Shuts the recovered stream down, if any, and returns the error.
+    ///
+    /// Shutting down is best-effort, as the stream may be broken already, so its error is ignored. This is the cleanup most handlers want on a failed step, e.g. `Err(failed) => return Err(failed.shutdown_and_err().await)`.
+    pub async fn shutdown_and_err(self) -> Error {
+        if let Some(mut stream) = self.stream {
+            let _ = stream.shutdown().await;
+        }
+
+        self.error
+    }
+}
+
+impl<A, S, T: AsyncWrite + Unpin> Failed<IncomingConnection<A, S, T>> {
+    /// Shuts the stream of the recovered connection down, if any, and returns the error, as [`Failed::shutdown_and_err()`] does for a stream.
+    pub async fn shutdown_and_err(self) -> Error {
+        if let Some(mut conn) = self.stream {
+            let _ = conn.close().await;
+        }
+
+        self.error
+    }
+}
+
+impl<S> Debug for Failed<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Failed")
+            .field("error", &self.error)
+            .field("stream", &self.stream.is_some())
+            .finish()
+    }
+}
+
+impl<S> Display for Failed<S> {
+    #[inline]
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        Display::fmt(&self.error, f)
+    }
+}
+
+impl<S> StdError for Failed<S> {
+    #[inline]
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.error.source()
+    }
+}
+
+impl<S> From<Failed<S>> for Error {
+    #[inline]
+    fn from(failed: Failed<S>) -> Self {
+        failed.error
+    }
+}
+
+impl<S> From<Failed<S>> for IoError {
+    #[inline]
+    fn from(failed: Failed<S>) -> Self {
+        failed.error.into()
+    }
+}
+
+/// The error shape of the negotiation methods before [`Failed`].
+///
+/// Kept for one release to ease migrating, destructure a [`Failed`] instead.
+#[deprecated(since = "0.11.0", note = "negotiation methods return `Failed` instead")]
+pub type ErrorWithStream = (Socks5Error, TcpStream);
@@ -0,0 +1,311 @@
+//! This module defines trait [`EventHandler`], receiving callbacks on the lifecycle of every connection of a [`Server`](crate::Server).
+//!
+//! Register a handler with [`Server::set_event_handler()`](crate::Server::set_event_handler). Connections accepted afterwards report to it as they are negotiated, replied to and closed, e.g. to feed an audit pipeline.
+
+use crate::{error::Error, stats::ServerStats};
+use socks5_proto::{handshake::Method, Address, Command, Reply};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::Future,
+    io::Error as IoError,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::time::{self, Instant};
+
+pub(crate) type EventHandlerRef = Arc<dyn EventHandler + Send + Sync>;
+
+/// Callbacks on the lifecycle of a SOCKS5 connection.
+///
+/// Every method defaults to doing nothing, so implement only the ones of interest. The methods are called synchronously from the task driving the connection, so they should not block. Events of one connection are reported in order, each with the address of the client.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{event::EventHandler, proto::Reply};
+/// use std::net::SocketAddr;
+///
+/// struct Audit;
+///
+/// impl EventHandler for Audit {
+///     fn on_reply(&self, peer: SocketAddr, reply: Reply) {
+///         eprintln!("{peer}: {reply:?}");
+///     }
+/// }
+/// ```
+pub trait EventHandler {
+    /// Called when a connection is accepted by [`Server::accept()`](crate::Server::accept) or [`Server::poll_accept()`](crate::Server::poll_accept).
+    fn on_accept(&self, _peer: SocketAddr) {}
+
+    /// Called when the authentication handshake of [`IncomingConnection::authenticate()`](crate::IncomingConnection::authenticate) finishes, with the method of the [`Auth`](crate::Auth) adaptor.
+    fn on_auth(&self, _peer: SocketAddr, _method: Method, _outcome: AuthOutcome) {}
+
+    /// Called when [`IncomingConnection::wait()`](crate::IncomingConnection::wait) receives a request, with its command and destination.
+    fn on_command(&self, _peer: SocketAddr, _command: Command, _dest: &Address) {}
+
+    /// Called when a reply is written to the client. `Bind` commands are replied to twice.
+    fn on_reply(&self, _peer: SocketAddr, _reply: Reply) {}
+
+    /// Called once the connection is no longer handled by this crate, see [`CloseReason`].
+    fn on_close(&self, _peer: SocketAddr, _reason: CloseReason, _stats: SessionStats) {}
+
+    /// Called when [`Server::accept_retrying()`](crate::Server::accept_retrying) retries after a transient error, with the error, e.g. to log that the process is running out of file descriptors.
+    fn on_accept_error(&self, _err: &IoError) {}
+}
+
+impl Debug for dyn EventHandler + Send + Sync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("EventHandler")
+    }
+}
+
+/// The outcome of an authentication handshake reported to [`EventHandler::on_auth()`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum AuthOutcome {
+    /// The client offered the method and the [`Auth`](crate::Auth) adaptor ran.
+    ///
+    /// This does not mean the client is allowed in, e.g. [`Password`](crate::auth::Password) also completes on wrong credentials. The adaptor output tells that.
+    Completed,
+
+    /// The client did not offer the method of the adaptor.
+    Unacceptable,
+
+    /// Reading or writing the handshake failed.
+    Failed,
+}
+
+/// Why a connection stopped being handled by this crate, reported to [`EventHandler::on_close()`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum CloseReason {
+    /// The connection was dropped, e.g. when the relay of it finished.
+    Closed,
+
+    /// A negotiation step failed, returning the error alongside the underlying `TcpStream`.
+    Failed,
+
+    /// The underlying `TcpStream` was taken out with `into_inner()`.
+    Detached,
+}
+
+/// Statistics of a connection reported to [`EventHandler::on_close()`]
+///
+/// Only the payload relayed through [`Connect<Ready>`](crate::Connect) and [`Bind<Ready>`](crate::Bind) as `AsyncRead` / `AsyncWrite` is counted, not the negotiation. UDP traffic of an association is counted in [`UdpRelayStats`](crate::connection::associate::UdpRelayStats).
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SessionStats {
+    /// Bytes read from the client
+    pub received: u64,
+
+    /// Bytes written to the client
+    pub sent: u64,
+
+    /// How long the connection lasted since it was accepted
+    pub duration: Duration,
+}
+
+/// The events, statistics and setup deadline of a connection, carried through its state types and reporting [`EventHandler::on_close()`] when dropped
+///
+/// The events are empty if no handler is registered, and so are the statistics without a [`ServerStats`], so they cost nothing then.
+pub(crate) struct Session {
+    events: Option<Box<SessionInner>>,
+    stats: Option<ServerStats>,
+    /// Whether the connection was counted as established or failed in the statistics
+    settled: bool,
+    deadline: Option<Instant>,
+    /// The deadline of [`IncomingConnection::authenticate_with_timeout()`](crate::IncomingConnection::authenticate_with_timeout), while it runs
+    auth_deadline: Option<Instant>,
+}
+
+struct SessionInner {
+    handler: EventHandlerRef,
+    peer: SocketAddr,
+    started: Instant,
+    stats: SessionStats,
+    reason: CloseReason,
+}
+
+impl Session {
+    /// Starts the session of a connection accepted from `peer`, reporting [`EventHandler::on_accept()`] and counting it in `stats`, if any. The setup must finish by `deadline`, if any, see [`Session::setup()`].
+    pub(crate) fn accept(
+        handler: Option<&EventHandlerRef>,
+        stats: Option<&ServerStats>,
+        peer: SocketAddr,
+        deadline: Option<Instant>,
+    ) -> Self {
+        let events = handler.map(|handler| {
+            handler.on_accept(peer);
+
+            Box::new(SessionInner {
+                handler: handler.clone(),
+                peer,
+                started: Instant::now(),
+                stats: SessionStats::default(),
+                reason: CloseReason::Closed,
+            })
+        });
+
+        if let Some(stats) = stats {
+            stats.accepted();
+        }
+
+        Self {
+            events,
+            stats: stats.cloned(),
+            settled: false,
+            deadline,
+            auth_deadline: None,
+        }
+    }
+
+    /// A session reporting to no handler, of a connection not accepted by a [`Server`](crate::Server).
+    #[inline]
+    pub(crate) fn detached() -> Self {
+        Self {
+            events: None,
+            stats: None,
+            settled: false,
+            deadline: None,
+            auth_deadline: None,
+        }
+    }
+
+    /// Replaces the setup deadline of the session.
+    #[inline]
+    pub(crate) fn set_setup_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    #[inline]
+    pub(crate) fn setup_deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Replaces the authentication deadline of the session, enforced by [`Session::setup()`] alongside the setup deadline.
+    #[inline]
+    pub(crate) fn set_auth_deadline(&mut self, deadline: Option<Instant>) {
+        self.auth_deadline = deadline;
+    }
+
+    /// Runs a step of the setup of the connection, i.e. anything until the first reply is sent.
+    ///
+    /// If the setup deadline has passed before the step completes, [`Error::SetupDeadlineExceeded`] is returned instead, even if the step would complete right after. The deadline is checked before the step is polled, so a step ready right away still fails once the deadline has passed. The authentication deadline is enforced the same way, failing with [`Error::AuthTimeout`], whichever of the two passes first.
+    pub(crate) async fn setup<T, E, F>(&self, step: F) -> Result<T, Error>
+    where
+        E: Into<Error>,
+        F: Future<Output = Result<T, E>>,
+    {
+        let (deadline, exceeded) = match (self.deadline, self.auth_deadline) {
+            (None, None) => return step.await.map_err(Into::into),
+            (Some(setup), Some(auth)) if auth < setup => (auth, Error::AuthTimeout),
+            (Some(setup), _) => (setup, Error::SetupDeadlineExceeded),
+            (None, Some(auth)) => (auth, Error::AuthTimeout),
+        };
+
+        if Instant::now() >= deadline {
+            return Err(exceeded);
+        }
+
+        match time::timeout_at(deadline, step).await {
+            Ok(res) => res.map_err(Into::into),
+            Err(_) => Err(exceeded),
+        }
+    }
+
+    /// Finishes the setup once the first reply is sent, lifting the setup deadline.
+    #[inline]
+    pub(crate) fn setup_done(&mut self) {
+        self.deadline = None;
+    }
+
+    #[inline]
+    pub(crate) fn auth(&self, method: Method, outcome: AuthOutcome) {
+        if let Some(inner) = &self.events {
+            inner.handler.on_auth(inner.peer, method, outcome);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn command(&self, command: Command, dest: &Address) {
+        if let Some(inner) = &self.events {
+            inner.handler.on_command(inner.peer, command, dest);
+        }
+    }
+
+    /// Reports a reply sent, counting the connection as established on its first [`Reply::Succeeded`].
+    #[inline]
+    pub(crate) fn reply(&mut self, reply: Reply) {
+        if let Some(inner) = &self.events {
+            inner.handler.on_reply(inner.peer, reply);
+        }
+
+        if let (Some(stats), false, Reply::Succeeded) = (&self.stats, self.settled, reply) {
+            stats.established();
+            self.settled = true;
+        }
+    }
+
+    #[inline]
+    pub(crate) fn received(&mut self, len: usize) {
+        if let Some(inner) = &mut self.events {
+            inner.stats.received += len as u64;
+        }
+
+        if let Some(stats) = &self.stats {
+            stats.received(len);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn sent(&mut self, len: usize) {
+        if let Some(inner) = &mut self.events {
+            inner.stats.sent += len as u64;
+        }
+
+        if let Some(stats) = &self.stats {
+            stats.sent(len);
+        }
+    }
+
+    /// Sets the reason reported when the session is dropped. A failure before the connection is established is counted as a failed negotiation.
+    #[inline]
+    pub(crate) fn set_close_reason(&mut self, reason: CloseReason) {
+        if let Some(inner) = &mut self.events {
+            inner.reason = reason;
+        }
+
+        if let (Some(stats), false, CloseReason::Failed) = (&self.stats, self.settled, reason) {
+            stats.failed();
+            self.settled = true;
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(stats) = &self.stats {
+            stats.closed();
+        }
+
+        if let Some(inner) = self.events.take() {
+            let stats = SessionStats {
+                duration: inner.started.elapsed(),
+                ..inner.stats
+            };
+
+            inner.handler.on_close(inner.peer, inner.reason, stats);
+        }
+    }
+}
+
+impl Debug for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match &self.events {
+            Some(inner) => f
+                .debug_struct("Session")
+                .field("peer", &inner.peer)
+                .field("stats", &inner.stats)
+                .finish(),
+            None => f.write_str("Session"),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! This module defines [`Verdict`], the decision of the accept filter of a [`Server`](crate::Server) on a new connection, see [`Server::set_accept_filter()`](crate::Server::set_accept_filter).
+
+use std::{net::SocketAddr, sync::Arc};
+
+pub(crate) type AcceptFilter = Arc<dyn Fn(SocketAddr) -> Verdict + Send + Sync>;
+
+/// What a [`Server`](crate::Server) does with a connection its accept filter was called on
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Verdict {
+    /// Hand the connection out from [`Server::accept()`](crate::Server::accept).
+    #[default]
+    Allow,
+
+    /// Close the connection right away, without reading anything from it.
+    Drop,
+
+    /// Close the connection right away with a reset rather than the orderly shutdown of [`Verdict::Drop`], dropping its state at once, as far as the [`Listener`](crate::listener::Listener) supports it, see [`Listener::reset()`](crate::listener::Listener::reset).
+    DropWithRst,
+}
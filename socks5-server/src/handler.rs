@@ -0,0 +1,142 @@
+//! This module defines trait [`ConnectionHandler`], handling the commands of the connections served by [`Server::serve()`](crate::Server::serve).
+//!
+//! It is enabled by the `serve` cargo feature, which spawns a task per connection on the tokio runtime.
+
+use crate::{
+    connection::{associate, bind, connect, state::NeedAuthenticate},
+    Associate, Bind, Command, Connect, Error, IncomingConnection,
+};
+use async_trait::async_trait;
+use socks5_proto::{Address, Reply};
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+/// This trait is for handling the commands of the connections served by [`Server::serve()`](crate::Server::serve).
+///
+/// The server accepts, authenticates and waits for the command of every connection in a task of its own, then routes the command to the method handling it, alongside its destination and the output of the [`Auth`](crate::Auth) adaptor. Only [`ConnectionHandler::handle_connect()`] must be implemented: `Bind` and `Associate` commands are replied [`Reply::CommandNotSupported`] by default.
+///
+/// Errors returned by the methods, and the ones of connections failing their negotiation, whose stream is shut down first, are passed to [`ConnectionHandler::on_error()`], which ignores them by default.
+///
+/// Generic `<A>` is the output type of the authentication adaptor, and `<T>` the stream the connections are carried over, [`TcpStream`] by default.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_server::{
+///     auth::NoAuth,
+///     connection::connect::state::NeedReply,
+///     proto::{Address, Reply},
+///     Connect, ConnectionHandler, Error, Server,
+/// };
+/// use std::{net::SocketAddr, sync::Arc};
+/// use tokio::{
+///     io,
+///     net::{TcpListener, TcpStream},
+/// };
+///
+/// struct Relay;
+///
+/// #[async_trait]
+/// impl ConnectionHandler<()> for Relay {
+///     async fn handle_connect(
+///         &self,
+///         connect: Connect<NeedReply>,
+///         addr: Address,
+///         _: (),
+///     ) -> Result<(), Error> {
+///         let Address::SocketAddress(addr) = addr else {
+///             let _ = connect.reply(Reply::AddressTypeNotSupported, Address::unspecified()).await;
+///             return Ok(());
+///         };
+///
+///         let mut target = TcpStream::connect(addr).await?;
+///         let mut conn = connect.reply(Reply::Succeeded, Address::unspecified()).await?;
+///         io::copy_bidirectional(&mut conn, &mut target).await?;
+///         Ok(())
+///     }
+///
+///     async fn on_error(&self, peer: SocketAddr, err: Error) {
+///         eprintln!("{peer}: {err}");
+///     }
+/// }
+///
+/// async fn listen() {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+///     server.serve(Arc::new(Relay)).await.unwrap();
+/// }
+/// ```
+#[async_trait]
+pub trait ConnectionHandler<A, T = TcpStream>: Send + Sync
+where
+    A: Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    /// Handles a `Connect` command to `addr`, replying to it.
+    async fn handle_connect(
+        &self,
+        connect: Connect<connect::state::NeedReply, T>,
+        addr: Address,
+        auth: A,
+    ) -> Result<(), Error>;
+
+    /// Handles a `Bind` command for `addr`, replying to it. Replies [`Reply::CommandNotSupported`] by default.
+    async fn handle_bind(
+        &self,
+        bind: Bind<bind::state::NeedFirstReply, T>,
+        _addr: Address,
+        _auth: A,
+    ) -> Result<(), Error> {
+        let mut bind = bind
+            .reply(Reply::CommandNotSupported, Address::unspecified())
+            .await?;
+        let _ = bind.close().await;
+        Ok(())
+    }
+
+    /// Handles an `Associate` command declaring the client to send from `addr`, replying to it. Replies [`Reply::CommandNotSupported`] by default.
+    async fn handle_associate(
+        &self,
+        associate: Associate<associate::state::NeedReply, T>,
+        _addr: Address,
+        _auth: A,
+    ) -> Result<(), Error> {
+        let mut associate = associate
+            .reply(Reply::CommandNotSupported, Address::unspecified())
+            .await?;
+        let _ = associate.close().await;
+        Ok(())
+    }
+
+    /// Called with the errors of the connection from `peer`, whether its negotiation failed or a method handling its command returned it. Does nothing by default.
+    async fn on_error(&self, _peer: SocketAddr, _err: Error) {}
+}
+
+/// Negotiates `conn` and routes its command to `handler`.
+pub(crate) async fn dispatch<A, T, H>(
+    conn: IncomingConnection<A, NeedAuthenticate, T>,
+    handler: &H,
+) -> Result<(), Error>
+where
+    A: Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    H: ConnectionHandler<A, T> + ?Sized,
+{
+    let (conn, auth) = match conn.authenticate().await {
+        Ok(authenticated) => authenticated,
+        Err(failed) => return Err(failed.shutdown_and_err().await),
+    };
+
+    match conn.wait().await {
+        Ok(Command::Connect(connect, addr)) => handler.handle_connect(connect, addr, auth).await,
+        Ok(Command::Bind(bind, addr)) => handler.handle_bind(bind, addr, auth).await,
+        Ok(Command::Associate(associate, addr)) => {
+            handler.handle_associate(associate, addr, auth).await
+        }
+        Err(failed) => Err(failed.shutdown_and_err().await),
+    }
+}
@@ -0,0 +1,171 @@
+//! This module defines [`HostnamePolicy`], the rules domain names requested by clients must follow.
+//!
+//! Register a policy with [`Server::set_hostname_policy()`](crate::Server::set_hostname_policy). [`IncomingConnection::wait()`](crate::IncomingConnection::wait) then checks the domain name of every request against it before the request is handed to the handler, answering violations with [`Reply::AddressTypeNotSupported`](socks5_proto::Reply::AddressTypeNotSupported). The policy can also be checked on its own with [`HostnamePolicy::check()`].
+
+use std::{
+    error::Error as StdError,
+    fmt::{Display, Formatter, Result as FmtResult},
+    net::IpAddr,
+};
+
+/// The rules a domain name must follow.
+///
+/// The default policy only allows LDH names, i.e. labels of ASCII letters, digits and hyphens, which do not start or end with a hyphen, of at most 63 bytes each and 255 bytes in total, the most a SOCKS5 request can carry. A single trailing dot of a fully qualified name is allowed.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::hostname::{HostnamePolicy, HostnameViolation};
+///
+/// let policy = HostnamePolicy {
+///     max_len: 128,
+///     allow_underscore: true,
+///     reject_ip_literals: true,
+///     ..HostnamePolicy::default()
+/// };
+///
+/// assert!(policy.check(b"_sip._udp.example.com").is_ok());
+/// assert_eq!(policy.check(b"127.0.0.1"), Err(HostnameViolation::IpLiteral));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HostnamePolicy {
+    /// The maximum length of the whole name in bytes, including the dots
+    pub max_len: usize,
+
+    /// The maximum length of a label in bytes
+    pub max_label_len: usize,
+
+    /// Whether labels may contain underscores, e.g. of SRV records like `_sip._udp.example.com`
+    pub allow_underscore: bool,
+
+    /// Whether labels may contain non-ASCII characters of a UTF-8 encoded name, i.e. internationalized names not converted to punycode. Their length is counted in bytes.
+    pub allow_utf8: bool,
+
+    /// Whether to reject IP addresses sent as domain names, e.g. `127.0.0.1` or `[::1]`, which bypass rules on IP addresses applied to the other address types
+    pub reject_ip_literals: bool,
+}
+
+impl HostnamePolicy {
+    /// Checks `name` against this policy, returning the first rule it violates.
+    pub fn check(&self, name: &[u8]) -> Result<(), HostnameViolation> {
+        if name.len() > self.max_len {
+            return Err(HostnameViolation::TooLong {
+                len: name.len(),
+                max: self.max_len,
+            });
+        }
+
+        if self.reject_ip_literals && is_ip_literal(name) {
+            return Err(HostnameViolation::IpLiteral);
+        }
+
+        if !name.is_ascii() && (!self.allow_utf8 || std::str::from_utf8(name).is_err()) {
+            return Err(HostnameViolation::InvalidCharacter);
+        }
+
+        // a fully qualified name ends with an empty root label
+        let name = name.strip_suffix(b".").unwrap_or(name);
+
+        for label in name.split(|&byte| byte == b'.') {
+            self.check_label(label)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_label(&self, label: &[u8]) -> Result<(), HostnameViolation> {
+        if label.is_empty() {
+            return Err(HostnameViolation::EmptyLabel);
+        }
+
+        if label.len() > self.max_label_len {
+            return Err(HostnameViolation::LabelTooLong {
+                len: label.len(),
+                max: self.max_label_len,
+            });
+        }
+
+        if label.starts_with(b"-") || label.ends_with(b"-") {
+            return Err(HostnameViolation::HyphenAtLabelEdge);
+        }
+
+        let allowed = |byte: &u8| {
+            byte.is_ascii_alphanumeric()
+                || *byte == b'-'
+                || (*byte == b'_' && self.allow_underscore)
+                // only reached for valid UTF-8 if allowed, see above
+                || !byte.is_ascii()
+        };
+
+        if label.iter().all(allowed) {
+            Ok(())
+        } else {
+            Err(HostnameViolation::InvalidCharacter)
+        }
+    }
+}
+
+impl Default for HostnamePolicy {
+    fn default() -> Self {
+        Self {
+            max_len: 255,
+            max_label_len: 63,
+            allow_underscore: false,
+            allow_utf8: false,
+            reject_ip_literals: false,
+        }
+    }
+}
+
+fn is_ip_literal(name: &[u8]) -> bool {
+    let Ok(name) = std::str::from_utf8(name) else {
+        return false;
+    };
+
+    let name = name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+        .unwrap_or(name);
+
+    name.parse::<IpAddr>().is_ok()
+}
+
+/// A rule of a [`HostnamePolicy`] a domain name violates
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum HostnameViolation {
+    /// The name is longer than [`HostnamePolicy::max_len`].
+    TooLong { len: usize, max: usize },
+
+    /// A label is longer than [`HostnamePolicy::max_label_len`].
+    LabelTooLong { len: usize, max: usize },
+
+    /// The name is empty or contains an empty label, e.g. `example..com`.
+    EmptyLabel,
+
+    /// A label starts or ends with a hyphen.
+    HyphenAtLabelEdge,
+
+    /// The name contains a character not allowed by the policy, or is not valid UTF-8.
+    InvalidCharacter,
+
+    /// The name is an IP address, rejected by [`HostnamePolicy::reject_ip_literals`].
+    IpLiteral,
+}
+
+impl Display for HostnameViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            Self::TooLong { len, max } => write!(f, "name of {len} bytes exceeds {max} bytes"),
+            Self::LabelTooLong { len, max } => {
+                write!(f, "label of {len} bytes exceeds {max} bytes")
+            }
+            Self::EmptyLabel => f.write_str("empty label"),
+            Self::HyphenAtLabelEdge => f.write_str("label starts or ends with a hyphen"),
+            Self::InvalidCharacter => f.write_str("character not allowed"),
+            Self::IpLiteral => f.write_str("IP address as a domain name"),
+        }
+    }
+}
+
+impl StdError for HostnameViolation {}
@@ -1,37 +1,90 @@
 #![doc = include_str!("../README.md")]
 
+use crate::{
+    auth::{DynAuth, DynOutput},
+    builder::ServerBuilder,
+    config::{AcceptBackoff, ConfigError, ServerConfig},
+    event::{EventHandlerRef, Session},
+    filter::{AcceptFilter, Verdict},
+    hostname::HostnamePolicy,
+    listener::{DualStackListener, Listener, MultiListener},
+    shutdown::{ServerShutdown, ShutdownHandle},
+    stats::ServerStats,
+    tap::WireTap,
+    tracker::{ConnectionLimit, ConnectionTracker, LimitPermit, LimitReached, OnLimit},
+};
+use socket2::TcpKeepalive;
+use socks5_proto::ParseLimits;
 use std::{
     fmt::Debug,
-    io::Error,
-    net::SocketAddr,
-    sync::Arc,
-    task::{Context, Poll},
+    future,
+    io::Error as IoError,
+    net::{SocketAddr, TcpListener as StdTcpListener},
+    sync::{Arc, RwLock},
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    time::{self, Instant},
 };
-use tokio::net::TcpListener;
 
 pub mod auth;
+pub mod builder;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
 pub mod connection;
+pub mod dns;
+pub mod error;
+pub mod event;
+pub mod filter;
+#[cfg(feature = "serve")]
+pub mod handler;
+pub mod hostname;
+pub mod listener;
+pub mod quota;
+pub mod relay;
+mod shared;
+pub mod shutdown;
+pub mod stats;
+pub mod tap;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+pub mod tracker;
 
 pub use crate::{
     auth::Auth,
     connection::{
-        associate::{Associate, AssociatedUdpSocket},
-        bind::Bind,
+        associate::Associate,
+        bind::{Bind, BindAcceptor},
         connect::Connect,
-        Command, IncomingConnection,
+        BufferedStream, Command, IncomingConnection, MethodsRead,
     },
+    error::{Error, Failed},
+    event::EventHandler,
+    shared::SharedServer,
 };
 
+#[cfg(feature = "udp")]
+pub use crate::connection::associate::AssociatedUdpSocket;
+
+#[cfg(feature = "serve")]
+pub use crate::handler::ConnectionHandler;
+
 pub use socks5_proto as proto;
 
-pub(crate) type AuthAdaptor<A> = Arc<dyn Auth<Output = A> + Send + Sync>;
+pub(crate) type AuthAdaptor<A, S = TcpStream> = Arc<dyn Auth<S, Output = A> + Send + Sync>;
 
-type ServerAcceptResult<A> = Result<
+/// A [`Server`] whose authentication adaptor is selected at runtime, see [`Server::new_dyn()`].
+pub type DynServer = Server<DynOutput>;
+
+type ServerAcceptResult<A, T = TcpStream> = Result<
     (
-        IncomingConnection<A, connection::state::NeedAuthenticate>,
+        IncomingConnection<A, connection::state::NeedAuthenticate, T>,
         SocketAddr,
     ),
-    Error,
+    IoError,
 >;
 
 /// A SOCKS5 server listener
@@ -40,6 +93,8 @@ type ServerAcceptResult<A> = Result<
 ///
 /// Generic `<A>` is the output type of the authentication adapter. See trait [`Auth`].
 ///
+/// Generic `<L>` is the [`Listener`] connections are accepted from, a [`TcpListener`] by default. The accepted [`IncomingConnection`]s are carried over its [`Listener::Stream`], e.g. a TLS stream.
+///
 /// # Example
 ///
 /// ```rust
@@ -60,44 +115,429 @@ type ServerAcceptResult<A> = Result<
 ///     }
 /// }
 /// ```
-pub struct Server<A> {
-    listener: TcpListener,
-    auth: AuthAdaptor<A>,
+pub struct Server<A, L: Listener = TcpListener> {
+    listener: L,
+    auth: RwLock<AuthAdaptor<A, L::Stream>>,
+    events: Option<EventHandlerRef>,
+    config: ServerConfig,
+    hostnames: Option<Arc<HostnamePolicy>>,
+    tap: Option<WireTap>,
+    tracker: ConnectionTracker,
+    limit: Option<Arc<ConnectionLimit>>,
+    keepalive: Option<TcpKeepalive>,
+    stats: Option<ServerStats>,
+    filter: Option<AcceptFilter>,
+    shutdown: ShutdownHandle,
 }
 
-impl<A> Server<A> {
-    /// Creates a new [`Server<A>`] with a [`TcpListener`](tokio::net::TcpListener) and an `Arc<dyn Auth<Output = A> + Send + Sync>`.
+impl<A, L: Listener> Server<A, L> {
+    /// Creates a new [`Server<A, L>`] with a [`Listener`], e.g. a [`TcpListener`](tokio::net::TcpListener), and an `Arc<dyn Auth<L::Stream, Output = A> + Send + Sync>`.
     #[inline]
-    pub fn new(listener: TcpListener, auth: AuthAdaptor<A>) -> Self {
-        Self { listener, auth }
+    pub fn new(listener: L, auth: AuthAdaptor<A, L::Stream>) -> Self {
+        Self {
+            listener,
+            auth: RwLock::new(auth),
+            events: None,
+            config: ServerConfig::default(),
+            hostnames: None,
+            tap: None,
+            tracker: ConnectionTracker::new(),
+            limit: None,
+            keepalive: None,
+            stats: None,
+            filter: None,
+            shutdown: ShutdownHandle::new(),
+        }
     }
 
-    /// Accept an [`IncomingConnection`].
+    /// Creates a new [`Server<A>`] like [`Server::new()`], applying the settings of `config` to it.
     ///
-    /// The connection is only a freshly created TCP connection and may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`] to perform a SOCKS5 authentication handshake.
+    /// `config` is validated first, returning every problem found, see [`ServerConfig::validate()`]. The nested relay configurations are kept for the handlers, see [`Server::config()`].
+    pub fn with_config(
+        listener: L,
+        auth: AuthAdaptor<A, L::Stream>,
+        config: ServerConfig,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+
+        let mut server = Self::new(listener, auth);
+        server.hostnames = config.hostname_policy.clone().map(Arc::new);
+        server.config = config;
+
+        Ok(server)
+    }
+
+    /// Returns the configuration of this server, reflecting the settings changed since it was created, e.g. to pass [`ServerConfig::relay`] to the relay helpers in the handler.
     #[inline]
-    pub async fn accept(&self) -> ServerAcceptResult<A> {
-        let (stream, addr) = self.listener.accept().await?;
-        Ok((IncomingConnection::new(stream, self.auth.clone()), addr))
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
     }
 
-    /// Polls to accept an [`IncomingConnection`].
+    /// Returns the authentication adaptor connections accepted now are authenticated with.
+    #[inline]
+    pub fn auth(&self) -> AuthAdaptor<A, L::Stream> {
+        self.auth
+            .read()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+    }
+
+    /// Replaces the authentication adaptor connections accepted afterwards are authenticated with, e.g. to rotate credentials without binding the listener again. It only takes a shared reference, so it can be called while the server is accepting.
     ///
-    /// The connection is only a freshly created TCP connection and may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`] to perform a SOCKS5 authentication handshake.
+    /// Connections accepted before keep the adaptor they were accepted with, including the ones still in their handshake.
+    #[inline]
+    pub fn set_auth(&self, auth: AuthAdaptor<A, L::Stream>) {
+        *self.auth.write().unwrap_or_else(|err| err.into_inner()) = auth;
+    }
+
+    /// Registers an [`EventHandler`] receiving callbacks on the lifecycle of every connection accepted afterwards, replacing any previous one.
     ///
-    /// If there is no connection to accept, Poll::Pending is returned and the current task will be notified by a waker. Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    /// Connections accepted before keep reporting to the handler registered when they were accepted.
     #[inline]
-    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<ServerAcceptResult<A>> {
-        self.listener
-            .poll_accept(cx)
-            .map_ok(|(stream, addr)| (IncomingConnection::new(stream, self.auth.clone()), addr))
+    pub fn set_event_handler(&mut self, handler: Arc<dyn EventHandler + Send + Sync>) {
+        self.events = Some(handler);
+    }
+
+    /// Removes the registered [`EventHandler`], if any.
+    #[inline]
+    pub fn clear_event_handler(&mut self) {
+        self.events = None;
+    }
+
+    /// Sets how long every connection accepted afterwards may take from being accepted to its first reply being sent, replacing any previous deadline.
+    ///
+    /// The deadline is absolute, recorded when the connection is accepted. It is checked by [`IncomingConnection::authenticate()`] including the execution of the [`Auth`] adaptor, [`IncomingConnection::wait()`] and the reply methods of the commands, each of which fails with [`Error::SetupDeadlineExceeded`] alongside the stream once it has passed, even if the step would have completed shortly after. The deadline is lifted once the first reply is sent, so e.g. the second reply of a `Bind` command is not subject to it.
+    ///
+    /// Time spent by the handler between the steps, e.g. connecting to the destination, counts towards the deadline.
+    #[inline]
+    pub fn set_session_setup_deadline(&mut self, deadline: Duration) {
+        self.config.session_setup_deadline = Some(deadline);
+    }
+
+    /// Removes the session setup deadline, if any.
+    #[inline]
+    pub fn clear_session_setup_deadline(&mut self) {
+        self.config.session_setup_deadline = None;
+    }
+
+    /// Sets the [`HostnamePolicy`] domain names requested by connections accepted afterwards must follow, replacing any previous one.
+    ///
+    /// [`IncomingConnection::wait()`] checks the name before the request reaches the handler, replying [`Reply::AddressTypeNotSupported`](proto::Reply::AddressTypeNotSupported) to violations and returning [`Error::InvalidHostname`]. Without a policy, which is the default, every name is passed through.
+    #[inline]
+    pub fn set_hostname_policy(&mut self, policy: HostnamePolicy) {
+        self.hostnames = Some(Arc::new(policy.clone()));
+        self.config.hostname_policy = Some(policy);
+    }
+
+    /// Removes the [`HostnamePolicy`], if any.
+    #[inline]
+    pub fn clear_hostname_policy(&mut self) {
+        self.hostnames = None;
+        self.config.hostname_policy = None;
+    }
+
+    /// Sets how [`Server::accept_retrying()`] backs off on transient errors, replacing the previous [`AcceptBackoff`].
+    #[inline]
+    pub fn set_accept_backoff(&mut self, backoff: AcceptBackoff) {
+        self.config.accept_backoff = backoff;
+    }
+
+    /// Sets the [`ParseLimits`] the negotiation messages of connections accepted afterwards are read with, replacing the previous ones.
+    ///
+    /// A length over a limit fails the negotiation before the field is read: too many methods in the handshake are replied [`Method::UNACCEPTABLE`](proto::handshake::Method::UNACCEPTABLE) by [`IncomingConnection::authenticate()`], a too long domain name [`Reply::AddressTypeNotSupported`](proto::Reply::AddressTypeNotSupported) by [`IncomingConnection::wait()`], and a too long username or password a failure by the [`Password`](auth::Password) adaptor. The defaults are the maximums of the protocol, limiting nothing.
+    #[inline]
+    pub fn set_parse_limits(&mut self, limits: ParseLimits) {
+        self.config.parse_limits = limits;
+    }
+
+    /// Sets the [`WireTap`] capturing the negotiation of every connection accepted afterwards, replacing any previous one, e.g. to debug a misbehaving client.
+    ///
+    /// Connections accepted before keep the tap they were accepted with. A single connection can be given its own with [`IncomingConnection::set_wire_tap()`].
+    #[inline]
+    pub fn set_wire_tap(&mut self, tap: impl Into<WireTap>) {
+        self.tap = Some(tap.into());
+    }
+
+    /// Removes the [`WireTap`], if any.
+    #[inline]
+    pub fn clear_wire_tap(&mut self) {
+        self.tap = None;
+    }
+
+    /// Returns the [`ConnectionTracker`] counting the connections accepted by this server that are still in flight. Clone it to wait for them after the server is dropped.
+    #[inline]
+    pub fn connection_tracker(&self) -> &ConnectionTracker {
+        &self.tracker
+    }
+
+    /// Replaces the [`ConnectionTracker`] connections accepted afterwards are counted by, e.g. to share one between several servers.
+    ///
+    /// Connections accepted before keep being counted by the tracker they were accepted with.
+    #[inline]
+    pub fn set_connection_tracker(&mut self, tracker: ConnectionTracker) {
+        self.tracker = tracker;
+    }
+
+    /// Returns the [`ServerStats`] connections accepted by this server are counted in, if any.
+    #[inline]
+    pub fn stats(&self) -> Option<&ServerStats> {
+        self.stats.as_ref()
+    }
+
+    /// Sets the [`ServerStats`] connections accepted afterwards are counted in, replacing any previous one. Clone it beforehand to read it from another task, or to share one between several servers.
+    ///
+    /// Connections accepted before keep being counted in the statistics they were accepted with.
+    #[inline]
+    pub fn set_stats(&mut self, stats: ServerStats) {
+        self.stats = Some(stats);
+    }
+
+    /// Stops counting the connections accepted afterwards, if they were.
+    #[inline]
+    pub fn clear_stats(&mut self) {
+        self.stats = None;
+    }
+
+    /// Sets the filter called with the address of the peer of every connection accepted afterwards, before anything is read from it, replacing any previous one, e.g. to refuse the networks of known scanners.
+    ///
+    /// Connections given [`Verdict::Drop`] or [`Verdict::DropWithRst`] are closed right away and counted in [`StatsSnapshot::filtered`](stats::StatsSnapshot::filtered), and [`Server::accept()`] goes on with the next connection rather than returning them. They take no slot of the limit on concurrent connections, are not counted by the [`ConnectionTracker`] and are not reported to the [`EventHandler`]. The filter is called from the task accepting, so it should not block.
+    #[inline]
+    pub fn set_accept_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(SocketAddr) -> Verdict + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+    }
+
+    /// Removes the accept filter, if any, allowing every connection.
+    #[inline]
+    pub fn clear_accept_filter(&mut self) {
+        self.filter = None;
+    }
+
+    /// Limits the number of connections accepted by this server in flight at once to `max`, replacing any previous limit. `on_limit` sets what [`Server::accept()`] does when the limit is reached, see [`OnLimit`].
+    ///
+    /// A connection takes its slot from being accepted until the [`IncomingConnection`], or the command type it turned into, is dropped, as it is counted by the [`ConnectionTracker`] of the server. So the slot is released when the task handling the connection ends. A connection handed back in a [`Failed`] keeps its slot until the [`Failed`] is dropped, and a stream taken out with e.g. `into_inner()` comes in a [`Tracked`](tracker::Tracked) keeping it until dropped, so the limit can not be bypassed by holding on to either. Connections accepted before keep counting towards the limit they were accepted with.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is 0.
+    #[inline]
+    pub fn set_max_connections(&mut self, max: usize, on_limit: OnLimit) {
+        assert!(max > 0, "the connection limit must be positive");
+        self.limit = Some(ConnectionLimit::new(max, on_limit));
+    }
+
+    /// Removes the limit on concurrent connections, if any.
+    #[inline]
+    pub fn clear_max_connections(&mut self) {
+        self.limit = None;
+    }
+
+    /// Enables TCP keepalive with the parameters of `keepalive` on every connection accepted afterwards, replacing any previous ones, so the relays of clients vanished without closing their connection, e.g. behind a NAT, eventually fail rather than lingering forever.
+    ///
+    /// The keepalive is set with [`Listener::set_tcp_keepalive()`] before the connection is returned by [`Server::accept()`], on a best-effort basis: a connection the keepalive can not be set on, e.g. one reset by its peer already, is returned without it rather than failing the accept, which would end an accept loop. It is ignored by listeners whose streams are not TCP ones, e.g. a [`UnixListener`](tokio::net::UnixListener). Without it, which is the default, the system defaults are kept, usually without keepalive.
+    #[inline]
+    pub fn set_tcp_keepalive(&mut self, keepalive: TcpKeepalive) {
+        self.keepalive = Some(keepalive);
+    }
+
+    /// Removes the TCP keepalive parameters, if any.
+    #[inline]
+    pub fn clear_tcp_keepalive(&mut self) {
+        self.keepalive = None;
+    }
+
+    /// Returns the [`ShutdownHandle`] of this server. Clone it to shut the server down from another task.
+    #[inline]
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        &self.shutdown
+    }
+
+    /// Replaces the [`ShutdownHandle`] of this server, e.g. to shut several servers down at once.
+    #[inline]
+    pub fn set_shutdown_handle(&mut self, shutdown: ShutdownHandle) {
+        self.shutdown = shutdown;
+    }
+
+    /// Shuts this server down, so it stops accepting connections, see [`ShutdownHandle`]. Pending and subsequent calls to [`Server::accept()`] fail with an error wrapping [`ServerShutdown`], while the connections accepted before are left to drain.
+    #[inline]
+    pub fn shutdown(&self) {
+        self.shutdown.shutdown();
+    }
+
+    #[inline]
+    fn incoming(
+        &self,
+        stream: L::Stream,
+        addr: SocketAddr,
+        permit: Option<LimitPermit>,
+    ) -> IncomingConnection<A, connection::state::NeedAuthenticate, L::Stream> {
+        let deadline = self
+            .config
+            .session_setup_deadline
+            .map(|deadline| Instant::now() + deadline);
+        let session = Session::accept(self.events.as_ref(), self.stats.as_ref(), addr, deadline);
+        let tracked = self.tracker.track().with_permit(permit);
+        let mut stream = BufferedStream::new(stream)
+            .with_parse_limits(self.config.parse_limits)
+            .with_guard(tracked);
+        stream.set_wire_tap(self.tap.clone());
+        IncomingConnection::with_session(stream, self.auth(), session)
+            .with_hostname_policy(self.hostnames.clone())
+    }
+
+    /// Accept an [`IncomingConnection`].
+    ///
+    /// The connection is only a freshly created connection of the [`Listener`] and may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`] to perform a SOCKS5 authentication handshake.
+    ///
+    /// Once the server is shut down, this fails with an error wrapping [`ServerShutdown`], see [`Server::shutdown()`]. With a limit on concurrent connections reached, this waits for a connection to finish or fails with an error wrapping [`LimitReached`], see [`Server::set_max_connections()`].
+    #[inline]
+    pub async fn accept(&self) -> ServerAcceptResult<A, L::Stream> {
+        if self.shutdown.is_shut_down() {
+            return Err(ServerShutdown.into());
+        }
+
+        let accept = async {
+            let permit = match &self.limit {
+                Some(limit) if limit.on_limit() == OnLimit::Wait => Some(limit.acquire().await),
+                _ => None,
+            };
+
+            loop {
+                let (stream, addr) = self.listener.accept().await?;
+
+                if let Some(stream) = self.filter(stream, addr) {
+                    break self.admit(stream, addr, permit);
+                }
+            }
+        };
+
+        tokio::select! {
+            res = accept => res,
+            () = self.shutdown.await_shutdown() => Err(ServerShutdown.into()),
+        }
+    }
+
+    /// Accept an [`IncomingConnection`] like [`Server::accept()`], retrying on transient errors rather than returning them, so an accept loop survives e.g. the process running out of file descriptors.
+    ///
+    /// Errors telling a connection failed before being accepted, e.g. `ECONNABORTED`, are retried right away. Errors telling the process or the system is out of file descriptors or memory, e.g. `EMFILE` or `ENFILE`, are retried after a delay growing on every consecutive failure, see [`Server::set_accept_backoff()`]. Each of them is reported to [`EventHandler::on_accept_error()`] and counted in [`StatsSnapshot::accept_errors`](stats::StatsSnapshot::accept_errors), see [`listener::is_transient_error()`]. Connections rejected over the limit of [`Server::set_max_connections()`] are skipped.
+    ///
+    /// Other errors, including the one of a server shut down, also during the delay, are returned.
+    pub async fn accept_retrying(&self) -> ServerAcceptResult<A, L::Stream> {
+        let backoff = self.config.accept_backoff;
+        let mut delay = backoff.initial;
+
+        loop {
+            let err = match self.accept().await {
+                Ok(accepted) => return Ok(accepted),
+                Err(err) if LimitReached::is(&err) => continue,
+                Err(err) if listener::is_transient_error(&err) => err,
+                Err(err) => return Err(err),
+            };
+
+            if let Some(events) = &self.events {
+                events.on_accept_error(&err);
+            }
+
+            if let Some(stats) = &self.stats {
+                stats.accept_error();
+            }
+
+            if listener::is_resource_exhausted(&err) {
+                tokio::select! {
+                    () = time::sleep(delay) => {}
+                    () = self.shutdown.await_shutdown() => return Err(ServerShutdown.into()),
+                }
+
+                delay = delay.saturating_mul(2).min(backoff.max);
+            }
+        }
+    }
+
+    fn poll_accept_with<F>(
+        &self,
+        cx: &mut Context<'_>,
+        mut poll: F,
+    ) -> Poll<ServerAcceptResult<A, L::Stream>>
+    where
+        F: FnMut(&L, &mut Context<'_>) -> Poll<Result<(L::Stream, SocketAddr), IoError>>,
+    {
+        if self.shutdown.poll_shutdown(cx).is_ready() {
+            return Poll::Ready(Err(ServerShutdown.into()));
+        }
+
+        let permit = match &self.limit {
+            Some(limit) if limit.on_limit() == OnLimit::Wait => {
+                Some(ready!(limit.poll_acquire(cx)))
+            }
+            _ => None,
+        };
+
+        loop {
+            match poll(&self.listener, cx) {
+                Poll::Ready(Ok((stream, addr))) => {
+                    if let Some(stream) = self.filter(stream, addr) {
+                        return Poll::Ready(self.admit(stream, addr, permit));
+                    }
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => {
+                    if let (Some(limit), Some(permit)) = (&self.limit, permit) {
+                        limit.reserve(permit);
+                    }
+
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+
+    /// Runs the accept filter on a stream accepted from `addr`, closing it and returning `None` unless it is allowed.
+    fn filter(&self, stream: L::Stream, addr: SocketAddr) -> Option<L::Stream> {
+        let verdict = match &self.filter {
+            Some(filter) => filter(addr),
+            None => return Some(stream),
+        };
+
+        match verdict {
+            Verdict::Allow => return Some(stream),
+            Verdict::Drop => drop(stream),
+            Verdict::DropWithRst => L::reset(stream),
+        }
+
+        if let Some(stats) = &self.stats {
+            stats.filtered();
+        }
+
+        None
+    }
+
+    /// Turns an accepted stream into an [`IncomingConnection`] with the keepalive set, holding `permit`, or a slot of the limit taken now if rejecting over it.
+    fn admit(
+        &self,
+        stream: L::Stream,
+        addr: SocketAddr,
+        permit: Option<LimitPermit>,
+    ) -> ServerAcceptResult<A, L::Stream> {
+        if let Some(keepalive) = &self.keepalive {
+            // best-effort, the connection is usable without keepalive, and failing here would end the accept loop of the caller
+            let _ = L::set_tcp_keepalive(&stream, keepalive);
+        }
+
+        let permit = match (&self.limit, permit) {
+            (Some(limit), None) => Some(limit.try_acquire().ok_or(LimitReached)?),
+            (_, permit) => permit,
+        };
+
+        Ok((self.incoming(stream, addr, permit), addr))
     }
 
     /// Returns the local address that this server is bound to.
     ///
     /// This can be useful, for example, when binding to port 0 to figure out which port was actually bound.
     #[inline]
-    pub fn local_addr(&self) -> Result<SocketAddr, Error> {
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
         self.listener.local_addr()
     }
 
@@ -105,7 +545,7 @@ impl<A> Server<A> {
     ///
     /// Note that this may break the encapsulation of the [`Server`] and you should not use this method unless you know what you are doing.
     #[inline]
-    pub fn get_ref(&self) -> &TcpListener {
+    pub fn get_ref(&self) -> &L {
         &self.listener
     }
 
@@ -113,18 +553,243 @@ impl<A> Server<A> {
     ///
     /// Note that this may break the encapsulation of the [`Server`] and you should not use this method unless you know what you are doing.
     #[inline]
-    pub fn get_mut(&mut self) -> &mut TcpListener {
+    pub fn get_mut(&mut self) -> &mut L {
         &mut self.listener
     }
 
-    /// Consumes the [`Server<A>`] and returns the underlying [`TcpListener`](tokio::net::TcpListener) and `Arc<dyn Auth<Output = A> + Send + Sync>`.
+    /// Turns the server into a [`SharedServer`], a cheaply cloneable handle to it, so connections can be accepted from several tasks at once.
+    #[inline]
+    pub fn into_shared(self) -> SharedServer<A, L> {
+        SharedServer::new(self)
+    }
+
+    /// Consumes the [`Server<A, L>`] and returns the underlying [`Listener`] and `Arc<dyn Auth<L::Stream, Output = A> + Send + Sync>`.
+    #[inline]
+    pub fn into_inner(self) -> (L, AuthAdaptor<A, L::Stream>) {
+        let auth = self
+            .auth
+            .into_inner()
+            .unwrap_or_else(|err| err.into_inner());
+        (self.listener, auth)
+    }
+}
+
+#[cfg(feature = "serve")]
+impl<A, L> Server<A, L>
+where
+    A: Send + 'static,
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    /// Serves connections with `handler` until the server is shut down, see [`ConnectionHandler`].
+    ///
+    /// Every connection accepted is negotiated and handled in a task of its own, spawned on the current tokio runtime and counted by the [`ConnectionTracker`] of the server until it ends, so [`ConnectionTracker::await_idle()`](tracker::ConnectionTracker::await_idle) waits for the handlers to finish too. Connections rejected over the limit of [`Server::set_max_connections()`] are skipped.
+    ///
+    /// Connections are accepted with [`Server::accept_retrying()`], so transient errors, e.g. the process running out of file descriptors, are retried. Returns `Ok(())` once the server is shut down, see [`Server::shutdown()`], or the first other error accepting a connection.
+    pub async fn serve<H>(&self, handler: Arc<H>) -> Result<(), IoError>
+    where
+        H: ConnectionHandler<A, L::Stream> + 'static,
+    {
+        loop {
+            let (mut conn, peer) = match self.accept_retrying().await {
+                Ok(accepted) => accepted,
+                Err(err) if ServerShutdown::is(&err) => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            let handler = handler.clone();
+            // the guard counting the connection moves to the task, so it is counted until the handler returns rather than until the command is dropped
+            let tracked = conn.take_tracked();
+
+            tokio::spawn(async move {
+                if let Err(err) = handler::dispatch(conn, &*handler).await {
+                    handler.on_error(peer, err).await;
+                }
+
+                drop(tracked);
+            });
+        }
+    }
+}
+
+impl<A> Server<A> {
+    /// Creates a new [`Server`] with a [`TcpListener`] bound to `addr`.
+    ///
+    /// Like [`TcpListener::bind()`], each address `addr` resolves to is tried in turn until one is bound, returning the error of the last one otherwise. Use [`Server::builder()`] to set socket options on the listener.
+    #[inline]
+    pub async fn bind<T: ToSocketAddrs>(addr: T, auth: AuthAdaptor<A>) -> Result<Self, IoError> {
+        Ok(Self::new(TcpListener::bind(addr).await?, auth))
+    }
+
+    /// Creates a [`ServerBuilder`] binding the [`TcpListener`](tokio::net::TcpListener) of the server with socket options, e.g. to bind it to a network interface or to tune its backlog.
+    #[inline]
+    pub fn builder(auth: AuthAdaptor<A>) -> ServerBuilder<A> {
+        ServerBuilder::new(auth)
+    }
+
+    /// Binds `count` listeners to `addr` with `SO_REUSEPORT` and creates a [`Server`] for each of them, sharing `auth`, so accepting connections scales across threads, see [`ServerBuilder::bind_reuseport()`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is 0.
+    #[inline]
+    pub async fn bind_reuseport<T: ToSocketAddrs>(
+        addr: T,
+        auth: AuthAdaptor<A>,
+        count: usize,
+    ) -> Result<Vec<Self>, IoError> {
+        ServerBuilder::new(auth).bind_reuseport(addr, count).await
+    }
+
+    /// Creates a new [`Server`] from a [`std::net::TcpListener`] already bound, e.g. one inherited from a supervisor, setting it to nonblocking mode as tokio requires.
+    ///
+    /// Fails if the listener can not be set to nonblocking mode, with an error of the same kind telling so, or if it can not be registered with the reactor.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime with IO enabled, as [`TcpListener::from_std()`] does.
+    pub fn from_std(listener: StdTcpListener, auth: AuthAdaptor<A>) -> Result<Self, IoError> {
+        listener.set_nonblocking(true).map_err(|err| {
+            IoError::new(
+                err.kind(),
+                format!("failed to set the listener to nonblocking mode: {err}"),
+            )
+        })?;
+
+        Ok(Self::new(TcpListener::from_std(listener)?, auth))
+    }
+
+    /// Consumes the [`Server<A>`] and returns the listener as a [`std::net::TcpListener`] alongside the `Arc<dyn Auth<Output = A> + Send + Sync>`, e.g. to hand it over to another process.
+    ///
+    /// The listener is left in nonblocking mode, set it back with [`std::net::TcpListener::set_nonblocking()`] if needed.
+    #[inline]
+    pub fn into_std(self) -> Result<(StdTcpListener, AuthAdaptor<A>), IoError> {
+        let (listener, auth) = self.into_inner();
+        Ok((listener.into_std()?, auth))
+    }
+
+    /// Polls to accept an [`IncomingConnection`].
+    ///
+    /// The connection is only a freshly created TCP connection and may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`] to perform a SOCKS5 authentication handshake.
+    ///
+    /// If there is no connection to accept, Poll::Pending is returned and the current task will be notified by a waker, including when the server is shut down, after which this fails with an error wrapping [`ServerShutdown`]. Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    #[inline]
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<ServerAcceptResult<A>> {
+        self.poll_accept_with(cx, TcpListener::poll_accept)
+    }
+}
+
+impl<A> Server<A, DualStackListener> {
+    /// Creates a new [`Server`] accepting both IPv4 and IPv6 connections on `port`, with a [`DualStackListener`] bound to `0.0.0.0` and `[::]`.
+    ///
+    /// Use [`ServerBuilder::bind_dual_stack()`] to set socket options on the listeners, and [`DualStackListener::local_addrs()`] through [`Server::get_ref()`] to get both bound addresses.
+    #[inline]
+    pub async fn bind_dual_stack(port: u16, auth: AuthAdaptor<A>) -> Result<Self, IoError> {
+        ServerBuilder::new(auth).bind_dual_stack(port).await
+    }
+
+    /// Polls to accept an [`IncomingConnection`] from either listener, see [`Server::poll_accept()`].
+    #[inline]
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<ServerAcceptResult<A>> {
+        self.poll_accept_with(cx, DualStackListener::poll_accept)
+    }
+}
+
+impl<A> Server<A, MultiListener> {
+    /// Creates a new [`Server`] accepting connections from every listener of `listeners`, with a [`MultiListener`], so the same settings and handlers serve several addresses.
+    ///
+    /// Use [`Server::accept_indexed()`] to know which listener a connection arrived on, and [`Server::local_addrs()`] to get the addresses of all of them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `listeners` is empty.
+    #[inline]
+    pub fn with_listeners(listeners: Vec<TcpListener>, auth: AuthAdaptor<A>) -> Self {
+        Self::new(MultiListener::new(listeners), auth)
+    }
+
+    /// Accept an [`IncomingConnection`] as [`Server::accept()`] does, alongside the index of the listener it arrived on in the ones the server was created with.
+    pub async fn accept_indexed(
+        &self,
+    ) -> Result<
+        (
+            IncomingConnection<A, connection::state::NeedAuthenticate>,
+            SocketAddr,
+            usize,
+        ),
+        IoError,
+    > {
+        if self.shutdown.is_shut_down() {
+            return Err(ServerShutdown.into());
+        }
+
+        future::poll_fn(|cx| {
+            let mut idx = 0;
+
+            self.poll_accept_with(cx, |listener, cx| {
+                listener.poll_accept(cx).map_ok(|(stream, addr, accepted)| {
+                    idx = accepted;
+                    (stream, addr)
+                })
+            })
+            .map_ok(|(conn, addr)| (conn, addr, idx))
+        })
+        .await
+    }
+
+    /// Polls to accept an [`IncomingConnection`] from any listener, see [`Server::poll_accept()`].
+    #[inline]
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<ServerAcceptResult<A>> {
+        self.poll_accept_with(cx, |listener, cx| {
+            listener
+                .poll_accept(cx)
+                .map_ok(|(stream, addr, _)| (stream, addr))
+        })
+    }
+
+    /// Returns the local addresses of the listeners, in the order they were given.
+    #[inline]
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, IoError> {
+        self.listener.local_addrs()
+    }
+}
+
+impl DynServer {
+    /// Creates a new [`DynServer`] with a [`TcpListener`](tokio::net::TcpListener) and an authentication adaptor of any output, wrapped in a [`DynAuth`].
+    ///
+    /// Servers created with adaptors of different outputs are all of the same type, so the adaptor can e.g. be selected from a configuration. The output of [`IncomingConnection::authenticate()`] is then a [`DynOutput`] to downcast, see [`DynAuth`].
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use socks5_server::{
+    ///     auth::{NoAuth, Password},
+    ///     DynServer, Server,
+    /// };
+    /// use tokio::net::TcpListener;
+    ///
+    /// async fn listen(credentials: Option<(Vec<u8>, Vec<u8>)>) -> DynServer {
+    ///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+    ///
+    ///     match credentials {
+    ///         Some((username, password)) => {
+    ///             Server::new_dyn(listener, Password::new(username, password))
+    ///         }
+    ///         None => Server::new_dyn(listener, NoAuth),
+    ///     }
+    /// }
+    /// ```
     #[inline]
-    pub fn into_inner(self) -> (TcpListener, AuthAdaptor<A>) {
-        (self.listener, self.auth)
+    pub fn new_dyn<A>(listener: TcpListener, auth: A) -> Self
+    where
+        A: Auth + Send + Sync + 'static,
+        A::Output: Send + 'static,
+    {
+        Self::new(listener, Arc::new(DynAuth::new(auth)))
     }
 }
 
-impl<A> Debug for Server<A> {
+impl<A, L: Listener + Debug> Debug for Server<A, L> {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Server")
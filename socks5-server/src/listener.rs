@@ -0,0 +1,367 @@
+//! This module defines trait [`Listener`], the source of the connections of a [`Server`](crate::Server).
+//!
+//! A [`TcpListener`] is used by default, and on unix platforms a [`UnixListener`](tokio::net::UnixListener) can be used too, e.g. to serve a daemon on the same host. Implement [`Listener`] on your own types to serve SOCKS5 over other transports, e.g. a TLS listener wrapping a [`TcpListener`].
+
+use async_trait::async_trait;
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    future,
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+
+#[cfg(unix)]
+use std::net::Ipv4Addr;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+
+/// Returns whether `err`, returned by accepting a connection, is transient, so accepting the next one may succeed, e.g. when the process is out of file descriptors, or a connection was reset by its peer before being accepted. Other errors are of the listener itself, which is unlikely to recover.
+///
+/// They are the errors [`Server::accept_retrying()`](crate::Server::accept_retrying) retries on.
+pub fn is_transient_error(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::OutOfMemory
+    ) || is_resource_exhausted(err)
+}
+
+/// Whether `err` tells the process or the system is out of file descriptors or memory, so accepting again right away would fail the same way.
+pub(crate) fn is_resource_exhausted(err: &Error) -> bool {
+    err.kind() == ErrorKind::OutOfMemory || sys::is_fd_exhausted(err)
+}
+
+/// This trait is for defining where a [`Server`](crate::Server) accepts its connections from.
+///
+/// Associate type `Stream` is the stream the connections are carried over, which the [`IncomingConnection`](crate::IncomingConnection)s accepted by the server are generic over. Any setup of the stream, e.g. a TLS handshake, is done in [`Listener::accept()`], so it must complete before the SOCKS5 negotiation starts. Note that the session setup deadline of the server only starts once the stream is returned.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_server::{auth::NoAuth, listener::Listener, Server};
+/// use std::{io::Result, net::SocketAddr, sync::Arc};
+/// use tokio::net::{TcpListener, TcpStream};
+///
+/// pub struct MyListener(TcpListener);
+///
+/// #[async_trait]
+/// impl Listener for MyListener {
+///     type Stream = TcpStream;
+///
+///     async fn accept(&self) -> Result<(Self::Stream, SocketAddr)> {
+///         let (stream, addr) = self.0.accept().await?;
+///         // e.g. perform a TLS handshake on stream
+///         Ok((stream, addr))
+///     }
+///
+///     fn local_addr(&self) -> Result<SocketAddr> {
+///         self.0.local_addr()
+///     }
+/// }
+///
+/// async fn listen() {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let server = Server::new(MyListener(listener), Arc::new(NoAuth) as Arc<_>);
+///
+///     while let Ok((conn, _)) = server.accept().await {
+///         tokio::spawn(async move {
+///             todo!();
+///         });
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Listener {
+    type Stream: AsyncRead + AsyncWrite + Unpin;
+
+    /// Accepts a new connection, returning its stream and the address of the peer.
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error>;
+
+    /// Returns the local address this listener is bound to.
+    fn local_addr(&self) -> Result<SocketAddr, Error>;
+
+    /// Enables TCP keepalive on an accepted stream with the parameters of `keepalive`, see [`Server::set_tcp_keepalive()`](crate::Server::set_tcp_keepalive).
+    ///
+    /// Does nothing by default, for streams TCP keepalive does not apply to. Listeners of streams wrapping a [`TcpStream`] should set it on the socket, e.g. with [`SockRef::set_tcp_keepalive()`].
+    #[inline]
+    fn set_tcp_keepalive(stream: &Self::Stream, keepalive: &TcpKeepalive) -> Result<(), Error> {
+        let _ = (stream, keepalive);
+        Ok(())
+    }
+
+    /// Closes an accepted stream right away with a reset, e.g. for a connection dropped with [`Verdict::DropWithRst`](crate::filter::Verdict::DropWithRst).
+    ///
+    /// Only drops the stream by default. Listeners of streams wrapping a [`TcpStream`] should set `SO_LINGER` to 0 on the socket first, e.g. with [`SockRef::set_linger()`].
+    #[inline]
+    fn reset(stream: Self::Stream) {
+        drop(stream);
+    }
+}
+
+#[async_trait]
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    #[inline]
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error> {
+        TcpListener::accept(self).await
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        TcpListener::local_addr(self)
+    }
+
+    #[inline]
+    fn set_tcp_keepalive(stream: &Self::Stream, keepalive: &TcpKeepalive) -> Result<(), Error> {
+        SockRef::from(stream).set_tcp_keepalive(keepalive)
+    }
+
+    #[inline]
+    fn reset(stream: Self::Stream) {
+        // nothing to do if it fails, the stream is closed anyway
+        let _ = SockRef::from(&stream).set_linger(Some(Duration::ZERO));
+    }
+}
+
+/// A [`Listener`] accepting both IPv4 and IPv6 connections on the same port, see [`Server::bind_dual_stack()`](crate::Server::bind_dual_stack).
+///
+/// It holds an IPv4 listener and an IPv6 one with `IPV6_V6ONLY` set, so it behaves the same whatever the default of the platform is. Connections are accepted from both in turn, so neither can starve the other.
+///
+/// [`Listener::local_addr()`] returns the address of the IPv4 listener, see [`DualStackListener::local_addrs()`] for both.
+#[derive(Debug)]
+pub struct DualStackListener {
+    v4: TcpListener,
+    v6: TcpListener,
+    v6_first: AtomicBool,
+}
+
+impl DualStackListener {
+    #[inline]
+    pub(crate) fn new(v4: TcpListener, v6: TcpListener) -> Self {
+        Self {
+            v4,
+            v6,
+            v6_first: AtomicBool::new(false),
+        }
+    }
+
+    /// Polls to accept a connection from either listener.
+    ///
+    /// Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(TcpStream, SocketAddr), Error>> {
+        let v6_first = self.v6_first.fetch_xor(true, Ordering::Relaxed);
+
+        let (first, second) = if v6_first {
+            (&self.v6, &self.v4)
+        } else {
+            (&self.v4, &self.v6)
+        };
+
+        match first.poll_accept(cx) {
+            Poll::Ready(res) => Poll::Ready(res),
+            Poll::Pending => second.poll_accept(cx),
+        }
+    }
+
+    /// Returns the local addresses of the IPv4 and IPv6 listeners.
+    pub fn local_addrs(&self) -> Result<[SocketAddr; 2], Error> {
+        Ok([self.v4.local_addr()?, self.v6.local_addr()?])
+    }
+
+    /// Returns the IPv4 and IPv6 listeners.
+    #[inline]
+    pub fn get_ref(&self) -> (&TcpListener, &TcpListener) {
+        (&self.v4, &self.v6)
+    }
+
+    /// Consumes the [`DualStackListener`] and returns the IPv4 and IPv6 listeners.
+    #[inline]
+    pub fn into_inner(self) -> (TcpListener, TcpListener) {
+        (self.v4, self.v6)
+    }
+}
+
+#[async_trait]
+impl Listener for DualStackListener {
+    type Stream = TcpStream;
+
+    #[inline]
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error> {
+        future::poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.v4.local_addr()
+    }
+
+    #[inline]
+    fn set_tcp_keepalive(stream: &Self::Stream, keepalive: &TcpKeepalive) -> Result<(), Error> {
+        TcpListener::set_tcp_keepalive(stream, keepalive)
+    }
+
+    #[inline]
+    fn reset(stream: Self::Stream) {
+        TcpListener::reset(stream);
+    }
+}
+
+/// A [`Listener`] accepting connections from several [`TcpListener`]s, e.g. bound to a public address, to localhost and to a VPN interface, see [`Server::with_listeners()`](crate::Server::with_listeners).
+///
+/// The listeners are polled in turn, starting from the one after the listener of the last connection accepted, so a busy listener can not starve the others. [`MultiListener::poll_accept()`] also returns the index of the listener the connection arrived on.
+///
+/// [`Listener::local_addr()`] returns the address of the first listener, see [`MultiListener::local_addrs()`] for all of them.
+#[derive(Debug)]
+pub struct MultiListener {
+    listeners: Vec<TcpListener>,
+    next: AtomicUsize,
+}
+
+impl MultiListener {
+    /// Creates a new [`MultiListener`] accepting from `listeners`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `listeners` is empty.
+    #[inline]
+    pub fn new(listeners: Vec<TcpListener>) -> Self {
+        assert!(!listeners.is_empty(), "no listener to accept from");
+
+        Self {
+            listeners,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Polls to accept a connection from any listener, returning it alongside the index of the listener in the ones the [`MultiListener`] was created with.
+    ///
+    /// Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(TcpStream, SocketAddr, usize), Error>> {
+        let len = self.listeners.len();
+        let start = self.next.load(Ordering::Relaxed) % len;
+
+        for offset in 0..len {
+            let idx = (start + offset) % len;
+
+            if let Poll::Ready(res) = self.listeners[idx].poll_accept(cx) {
+                self.next.store(idx + 1, Ordering::Relaxed);
+                return Poll::Ready(res.map(|(stream, addr)| (stream, addr, idx)));
+            }
+        }
+
+        Poll::Pending
+    }
+
+    /// Returns the local addresses of the listeners, in the order they were given.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, Error> {
+        self.listeners.iter().map(TcpListener::local_addr).collect()
+    }
+
+    /// Returns the listeners.
+    #[inline]
+    pub fn get_ref(&self) -> &[TcpListener] {
+        &self.listeners
+    }
+
+    /// Consumes the [`MultiListener`] and returns the listeners.
+    #[inline]
+    pub fn into_inner(self) -> Vec<TcpListener> {
+        self.listeners
+    }
+}
+
+impl From<Vec<TcpListener>> for MultiListener {
+    #[inline]
+    fn from(listeners: Vec<TcpListener>) -> Self {
+        Self::new(listeners)
+    }
+}
+
+#[async_trait]
+impl Listener for MultiListener {
+    type Stream = TcpStream;
+
+    #[inline]
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error> {
+        future::poll_fn(|cx| self.poll_accept(cx))
+            .await
+            .map(|(stream, addr, _)| (stream, addr))
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.listeners[0].local_addr()
+    }
+
+    #[inline]
+    fn set_tcp_keepalive(stream: &Self::Stream, keepalive: &TcpKeepalive) -> Result<(), Error> {
+        TcpListener::set_tcp_keepalive(stream, keepalive)
+    }
+
+    #[inline]
+    fn reset(stream: Self::Stream) {
+        TcpListener::reset(stream);
+    }
+}
+
+/// Connections accepted from a [`UnixListener`] have no [`SocketAddr`], so the unspecified address `0.0.0.0:0` is reported for their peers, including to the [`EventHandler`](crate::EventHandler). Use e.g. [`IncomingConnection::peer_addr()`](crate::IncomingConnection::peer_addr) on the accepted connection to get the unix address of the peer.
+///
+/// [`Listener::local_addr()`] fails with [`ErrorKind::Unsupported`], use [`Server::get_ref()`](crate::Server::get_ref) to get the unix address of the listener instead.
+#[cfg(unix)]
+#[async_trait]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    #[inline]
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error> {
+        let (stream, _) = UnixListener::accept(self).await?;
+        Ok((stream, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))))
+    }
+
+    #[inline]
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "a unix listener is not bound to a socket address",
+        ))
+    }
+}
+
+/// The codes telling file descriptors are exhausted differ between platforms and are not mapped to an [`ErrorKind`] by the standard library.
+mod sys {
+    use std::io::Error;
+
+    /// `ENFILE` and `EMFILE`, the same on every unix platform
+    #[cfg(unix)]
+    pub(super) fn is_fd_exhausted(err: &Error) -> bool {
+        matches!(err.raw_os_error(), Some(23 | 24))
+    }
+
+    /// `WSAEMFILE` and `WSAENOBUFS`
+    #[cfg(windows)]
+    pub(super) fn is_fd_exhausted(err: &Error) -> bool {
+        matches!(err.raw_os_error(), Some(10024 | 10055))
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    pub(super) fn is_fd_exhausted(_: &Error) -> bool {
+        false
+    }
+}
@@ -0,0 +1,318 @@
+//! This module defines trait [`QuotaProvider`] and [`QuotaEnforcer`], limiting the bytes each user may relay.
+//!
+//! The relay helpers of this crate, [`relay::copy_bidirectional_with_quota()`](crate::relay::copy_bidirectional_with_quota) for TCP and [`run_relay()`](crate::connection::associate::run_relay) for UDP through [`UdpRelayConfig::quota`](crate::connection::associate::UdpRelayConfig::quota), reserve the bytes they relay from a [`QuotaProvider`] in chunks, and terminate the session as soon as the quota of its user is exhausted.
+
+use async_trait::async_trait;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+};
+
+/// This trait is for defining where the byte quotas of users are kept, e.g. a database of monthly transfer caps.
+///
+/// # Example
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_server::quota::{QuotaDecision, QuotaProvider};
+/// use std::{collections::HashMap, sync::Mutex};
+///
+/// pub struct Caps(Mutex<HashMap<Vec<u8>, u64>>);
+///
+/// #[async_trait]
+/// impl QuotaProvider for Caps {
+///     async fn reserve(&self, user: &[u8], bytes: u64) -> QuotaDecision {
+///         let mut caps = self.0.lock().unwrap();
+///
+///         match caps.get_mut(user) {
+///             Some(left) if *left > 0 => {
+///                 let granted = bytes.min(*left);
+///                 *left -= granted;
+///                 QuotaDecision::Granted(granted)
+///             }
+///             _ => QuotaDecision::Exhausted,
+///         }
+///     }
+///
+///     async fn release(&self, user: &[u8], bytes: u64) {
+///         if let Some(left) = self.0.lock().unwrap().get_mut(user) {
+///             *left += bytes;
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait QuotaProvider {
+    /// Reserves `bytes` of the quota of `user` for a session about to relay them.
+    ///
+    /// Granting less than asked is fine, e.g. the last bytes of a quota. The session asks again once it relayed them.
+    async fn reserve(&self, user: &[u8], bytes: u64) -> QuotaDecision;
+
+    /// Gives back `bytes` reserved for a session of `user` that ended before relaying them.
+    ///
+    /// The default implementation does nothing, i.e. the remainder of the last chunk reserved by a session is charged to its user.
+    async fn release(&self, user: &[u8], bytes: u64) {
+        let _ = (user, bytes);
+    }
+}
+
+impl Debug for dyn QuotaProvider + Send + Sync {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.write_str("QuotaProvider")
+    }
+}
+
+/// The decision of a [`QuotaProvider`] on a reservation
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum QuotaDecision {
+    /// This many bytes are granted to the session. Granting `0` is the same as [`QuotaDecision::Exhausted`].
+    Granted(u64),
+
+    /// The quota of the user is exhausted, so the session is terminated.
+    Exhausted,
+}
+
+/// Configuration of a [`QuotaEnforcer`]
+#[derive(Clone, Debug)]
+pub struct QuotaConfig {
+    /// How many bytes a session reserves at a time. The provider is consulted before the first byte is relayed, then every time this many bytes were relayed.
+    ///
+    /// Larger chunks consult the provider less often, but let a session overrun the quota of its user by up to a chunk if the provider grants whole chunks, and need [`QuotaProvider::release()`] to give back the unused remainder.
+    pub chunk: u64,
+
+    /// The user reserved for by sessions without an authenticated identity.
+    ///
+    /// This is empty by default, which no username of the username / password authentication can be, so the anonymous bucket can not be confused with a user.
+    pub anonymous: Vec<u8>,
+}
+
+impl Default for QuotaConfig {
+    fn default() -> Self {
+        Self {
+            chunk: 1024 * 1024,
+            anonymous: Vec::new(),
+        }
+    }
+}
+
+/// A cloneable handle enforcing the quotas of a [`QuotaProvider`], shared by all sessions.
+///
+/// Hand a [`Quota`] of the user of each session to the relay helpers, created with [`QuotaEnforcer::quota()`] from the identity the [`Auth`](crate::Auth) adaptor authenticated.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     connection::connect::{state::Ready, Connect},
+///     quota::QuotaEnforcer,
+///     relay::{self, AbortReason},
+/// };
+/// use std::future;
+/// use tokio::net::TcpStream;
+///
+/// async fn relay(
+///     quotas: &QuotaEnforcer,
+///     user: Option<&[u8]>,
+///     mut connect: Connect<Ready>,
+///     mut target: TcpStream,
+/// ) {
+///     let quota = quotas.quota(user);
+///     let (_, _, reason) = relay::copy_bidirectional_with_quota(
+///         &mut connect,
+///         &mut target,
+///         &quota,
+///         future::pending::<()>(),
+///     )
+///     .await;
+///
+///     if let AbortReason::QuotaExhausted = reason {
+///         eprintln!("quota exhausted");
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct QuotaEnforcer {
+    inner: Arc<EnforcerInner>,
+}
+
+#[derive(Debug)]
+struct EnforcerInner {
+    provider: Arc<dyn QuotaProvider + Send + Sync>,
+    config: QuotaConfig,
+    reservations: AtomicU64,
+    reserved_bytes: AtomicU64,
+    released_bytes: AtomicU64,
+    exhausted_sessions: AtomicU64,
+}
+
+impl QuotaEnforcer {
+    /// Creates a new [`QuotaEnforcer`] reserving from `provider`.
+    pub fn new(provider: Arc<dyn QuotaProvider + Send + Sync>, config: QuotaConfig) -> Self {
+        Self {
+            inner: Arc::new(EnforcerInner {
+                provider,
+                config,
+                reservations: AtomicU64::new(0),
+                reserved_bytes: AtomicU64::new(0),
+                released_bytes: AtomicU64::new(0),
+                exhausted_sessions: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Returns the [`Quota`] a session of `user` relays under, or one of [`QuotaConfig::anonymous`] if the session has no authenticated identity.
+    pub fn quota(&self, user: Option<&[u8]>) -> Quota {
+        let user = user.unwrap_or(&self.inner.config.anonymous);
+
+        Quota {
+            enforcer: self.clone(),
+            user: Arc::from(user),
+        }
+    }
+
+    /// Returns the counters of all sessions so far.
+    pub fn stats(&self) -> QuotaStats {
+        QuotaStats {
+            reservations: self.inner.reservations.load(Ordering::Relaxed),
+            reserved_bytes: self.inner.reserved_bytes.load(Ordering::Relaxed),
+            released_bytes: self.inner.released_bytes.load(Ordering::Relaxed),
+            exhausted_sessions: self.inner.exhausted_sessions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A snapshot of the counters of a [`QuotaEnforcer`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct QuotaStats {
+    /// Reservations made from the provider
+    pub reservations: u64,
+
+    /// Bytes granted by the provider
+    pub reserved_bytes: u64,
+
+    /// Bytes given back to the provider by sessions ending before relaying them
+    pub released_bytes: u64,
+
+    /// Sessions terminated because the quota of their user was exhausted
+    pub exhausted_sessions: u64,
+}
+
+/// The quota of the user of a session, created by [`QuotaEnforcer::quota()`]
+#[derive(Clone, Debug)]
+pub struct Quota {
+    enforcer: QuotaEnforcer,
+    user: Arc<[u8]>,
+}
+
+impl Quota {
+    /// Returns the user reserved for, [`QuotaConfig::anonymous`] for a session without an authenticated identity.
+    #[inline]
+    pub fn user(&self) -> &[u8] {
+        &self.user
+    }
+}
+
+type Reservation<'a> = Pin<Box<dyn Future<Output = QuotaDecision> + Send + 'a>>;
+
+/// The bytes a relay session may still relay before reserving more, shared by all its directions
+pub(crate) struct Budget<'a> {
+    quota: Option<&'a Quota>,
+    allowance: u64,
+    pending: Option<Reservation<'a>>,
+    exhausted: bool,
+}
+
+impl<'a> Budget<'a> {
+    /// Creates the budget of a session, unlimited if `quota` is `None`.
+    pub(crate) fn new(quota: Option<&'a Quota>) -> Self {
+        Self {
+            quota,
+            allowance: 0,
+            pending: None,
+            exhausted: false,
+        }
+    }
+
+    /// Whether the quota of the session was exhausted, in which case the session should be terminated.
+    #[inline]
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+
+    /// Polls until at least `len` bytes are allowed, reserving chunks as needed. Returns how many bytes are allowed, or `0` if the quota is exhausted.
+    ///
+    /// An unlimited budget always allows `u64::MAX` bytes.
+    pub(crate) fn poll_allow(&mut self, cx: &mut Context<'_>, len: u64) -> Poll<u64> {
+        let Some(quota) = self.quota else {
+            return Poll::Ready(u64::MAX);
+        };
+
+        while !self.exhausted && self.allowance < len.max(1) {
+            let inner = &quota.enforcer.inner;
+
+            let pending = self
+                .pending
+                .get_or_insert_with(|| inner.provider.reserve(&quota.user, inner.config.chunk));
+
+            let decision = ready!(pending.as_mut().poll(cx));
+            self.pending = None;
+            inner.reservations.fetch_add(1, Ordering::Relaxed);
+
+            match decision {
+                QuotaDecision::Granted(granted) if granted > 0 => {
+                    inner.reserved_bytes.fetch_add(granted, Ordering::Relaxed);
+                    self.allowance += granted;
+                }
+                _ => {
+                    inner.exhausted_sessions.fetch_add(1, Ordering::Relaxed);
+                    self.exhausted = true;
+                }
+            }
+        }
+
+        if self.exhausted {
+            Poll::Ready(0)
+        } else {
+            Poll::Ready(self.allowance)
+        }
+    }
+
+    /// Waits until `len` bytes are allowed and takes them from the allowance. Returns `false` if the quota is exhausted.
+    #[cfg(feature = "udp")]
+    pub(crate) async fn take(&mut self, len: u64) -> bool {
+        if std::future::poll_fn(|cx| self.poll_allow(cx, len)).await == 0 {
+            return false;
+        }
+
+        self.consume(len);
+        true
+    }
+
+    /// Takes `len` relayed bytes from the allowance.
+    #[inline]
+    pub(crate) fn consume(&mut self, len: u64) {
+        if self.quota.is_some() {
+            self.allowance -= len.min(self.allowance);
+        }
+    }
+
+    /// Gives the allowance left back to the provider as the session ends.
+    pub(crate) async fn release(self) {
+        let Some(quota) = self.quota else {
+            return;
+        };
+
+        if self.allowance > 0 {
+            let inner = &quota.enforcer.inner;
+            inner.provider.release(&quota.user, self.allowance).await;
+            inner
+                .released_bytes
+                .fetch_add(self.allowance, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! This module provides [`copy_bidirectional_with_abort()`], relaying two streams to each other until both are closed or the relay is aborted, and [`copy_bidirectional_with_quota()`], which also enforces the quota of a user. [`copy_bidirectional_with_config()`] does either with the behavior on EOF configured by a [`RelayConfig`].
+
+use crate::quota::{Budget, Quota};
+use std::{
+    future::{self, Future},
+    io::{Error, ErrorKind},
+    pin::{pin, Pin},
+    task::{ready, Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const BUF_SIZE: usize = 8 * 1024;
+
+/// Configuration of [`copy_bidirectional_with_config()`]
+#[derive(Clone, Debug, Default)]
+pub struct RelayConfig {
+    /// What happens to the other direction when one of the streams reaches EOF
+    pub half_close: HalfClose,
+
+    /// The quota the bytes of both directions are counted against, see [`copy_bidirectional_with_quota()`]. `None` by default, relaying without a limit.
+    pub quota: Option<Quota>,
+}
+
+/// What a relay does when one of the streams reaches EOF
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum HalfClose {
+    /// Shut the write side of the other stream down right away, propagating the half-close, and keep relaying the opposite direction until it reaches EOF too.
+    ///
+    /// This is what protocols relying on half-close need, e.g. a client sending a request, shutting its write side down to mark the end of it, then reading the response. The relay returns once both directions reached EOF.
+    #[default]
+    Propagate,
+
+    /// Shut the write sides of both streams down on the first EOF, without reading anything more from the other stream.
+    ///
+    /// Data the other stream already sent but the relay did not read yet is dropped. This suits peers which never half-close on purpose, so that a connection closed by one side does not linger until the other side notices.
+    CloseBoth,
+}
+
+/// Why [`copy_bidirectional_with_abort()`] returned
+#[derive(Debug)]
+pub enum AbortReason {
+    /// Both streams reached EOF, and the half-close of each was propagated to the other.
+    Eof,
+
+    /// The abort future completed. Everything already read was written and flushed, but the streams were not shut down.
+    Aborted,
+
+    /// The quota of the user was exhausted, see [`copy_bidirectional_with_quota()`]. Everything already read was written and flushed, but the streams were not shut down.
+    QuotaExhausted,
+
+    /// Reading or writing one of the streams failed.
+    Error(Error),
+}
+
+/// Copies data in both directions between `a` and `b` until both reach EOF or `abort` completes, returning the bytes copied from `a` to `b`, the bytes copied from `b` to `a`, and why it returned.
+///
+/// When one of the streams reaches EOF, the other one is flushed and shut down right away, propagating the half-close, while the opposite direction keeps copying until it reaches EOF too, i.e. [`HalfClose::Propagate`]. Use [`copy_bidirectional_with_config()`] with [`HalfClose::CloseBoth`] to shut both down on the first EOF instead.
+///
+/// When `abort` completes, nothing more is read from either stream. Data already read from one stream is written to the other, and both are flushed before returning, so nothing read is lost. `abort` is not polled again once it completed.
+///
+/// # Cancel safety
+///
+/// Aborting with `abort` is the cancel-safe way of stopping the relay: it returns only once everything read was delivered. Dropping the returned future instead discards data read from one stream but not yet written to the other, as with [`tokio::io::copy_bidirectional()`]. Since flushing waits for the streams to accept the pending data, bound it with a timeout when a stream may stop making progress, e.g. when shutting down.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::relay::{self, AbortReason};
+/// use tokio::{net::TcpStream, sync::oneshot::Receiver};
+///
+/// async fn relay(mut client: TcpStream, mut target: TcpStream, kill: Receiver<()>) {
+///     let (up, down, reason) =
+///         relay::copy_bidirectional_with_abort(&mut client, &mut target, kill).await;
+///
+///     if let AbortReason::Aborted = reason {
+///         eprintln!("killed after relaying {up} / {down} bytes");
+///     }
+/// }
+/// ```
+pub async fn copy_bidirectional_with_abort<A, B, F>(
+    a: &mut A,
+    b: &mut B,
+    abort: F,
+) -> (u64, u64, AbortReason)
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    F: Future,
+{
+    copy_bidirectional(a, b, abort, HalfClose::Propagate, Budget::new(None)).await
+}
+
+/// Copies data in both directions between `a` and `b` like [`copy_bidirectional_with_abort()`], counting the bytes of both directions against `quota`.
+///
+/// The bytes are reserved from the [`QuotaProvider`](crate::quota::QuotaProvider) in chunks of [`QuotaConfig::chunk`](crate::quota::QuotaConfig::chunk), the first one before anything is read. Nothing is read beyond what was granted, so once the quota is exhausted, the relay stops reading from both streams right away and returns [`AbortReason::QuotaExhausted`] after delivering what was read. The streams are not shut down then, so the handler can tell the client before closing them.
+///
+/// The part of the last chunk not relayed is given back with [`QuotaProvider::release()`](crate::quota::QuotaProvider::release) before returning.
+///
+/// # Cancel safety
+///
+/// See [`copy_bidirectional_with_abort()`]. Dropping the returned future also skips releasing the part of the last chunk not relayed.
+pub async fn copy_bidirectional_with_quota<A, B, F>(
+    a: &mut A,
+    b: &mut B,
+    quota: &Quota,
+    abort: F,
+) -> (u64, u64, AbortReason)
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    F: Future,
+{
+    copy_bidirectional(a, b, abort, HalfClose::Propagate, Budget::new(Some(quota))).await
+}
+
+/// Copies data in both directions between `a` and `b` like [`copy_bidirectional_with_abort()`], handling EOF as [`RelayConfig::half_close`] describes, and enforcing [`RelayConfig::quota`] as [`copy_bidirectional_with_quota()`] does if set.
+///
+/// With [`HalfClose::CloseBoth`], the relay returns [`AbortReason::Eof`] once the first EOF was propagated to both streams.
+///
+/// # Cancel safety
+///
+/// See [`copy_bidirectional_with_abort()`] and [`copy_bidirectional_with_quota()`].
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::relay::{self, HalfClose, RelayConfig};
+/// use std::future;
+/// use tokio::net::TcpStream;
+///
+/// async fn relay(mut client: TcpStream, mut target: TcpStream) {
+///     let config = RelayConfig {
+///         half_close: HalfClose::CloseBoth,
+///         ..RelayConfig::default()
+///     };
+///
+///     let _ = relay::copy_bidirectional_with_config(
+///         &mut client,
+///         &mut target,
+///         &config,
+///         future::pending::<()>(),
+///     )
+///     .await;
+/// }
+/// ```
+pub async fn copy_bidirectional_with_config<A, B, F>(
+    a: &mut A,
+    b: &mut B,
+    config: &RelayConfig,
+    abort: F,
+) -> (u64, u64, AbortReason)
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    F: Future,
+{
+    let budget = Budget::new(config.quota.as_ref());
+    copy_bidirectional(a, b, abort, config.half_close, budget).await
+}
+
+async fn copy_bidirectional<A, B, F>(
+    a: &mut A,
+    b: &mut B,
+    abort: F,
+    half_close: HalfClose,
+    mut budget: Budget<'_>,
+) -> (u64, u64, AbortReason)
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    F: Future,
+{
+    let mut abort = pin!(abort);
+    let mut aborted = false;
+    let mut a_to_b = Direction::new();
+    let mut b_to_a = Direction::new();
+
+    let res = future::poll_fn(|cx| loop {
+        if !aborted && abort.as_mut().poll(cx).is_ready() {
+            aborted = true;
+            a_to_b.aborted = true;
+            b_to_a.aborted = true;
+        }
+
+        let a_to_b_res = a_to_b.poll_copy(cx, Pin::new(&mut *a), Pin::new(&mut *b), &mut budget)?;
+        let b_to_a_res = b_to_a.poll_copy(cx, Pin::new(&mut *b), Pin::new(&mut *a), &mut budget)?;
+
+        if a_to_b_res.is_ready() && b_to_a_res.is_ready() {
+            return Poll::Ready(Ok::<_, Error>(()));
+        }
+
+        // the other direction stops reading, and shuts its writer down as if it reached EOF
+        if half_close == HalfClose::CloseBoth && a_to_b.read_done != b_to_a.read_done {
+            a_to_b.read_done = true;
+            b_to_a.read_done = true;
+            continue;
+        }
+
+        // stop the other direction too, which may be waiting on its reader
+        if budget.is_exhausted() && !(a_to_b.aborted && b_to_a.aborted) {
+            a_to_b.aborted = true;
+            b_to_a.aborted = true;
+            continue;
+        }
+
+        return Poll::Pending;
+    })
+    .await;
+
+    let reason = match res {
+        Ok(()) if budget.is_exhausted() => AbortReason::QuotaExhausted,
+        Ok(()) if aborted => AbortReason::Aborted,
+        Ok(()) => AbortReason::Eof,
+        Err(err) => AbortReason::Error(err),
+    };
+
+    budget.release().await;
+
+    (a_to_b.amt, b_to_a.amt, reason)
+}
+
+/// The copy of one direction, from a reader to a writer
+struct Direction {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    amt: u64,
+    read_done: bool,
+    aborted: bool,
+    need_flush: bool,
+    done: bool,
+}
+
+impl Direction {
+    fn new() -> Self {
+        Self {
+            buf: vec![0; BUF_SIZE].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            amt: 0,
+            read_done: false,
+            aborted: false,
+            need_flush: false,
+            done: false,
+        }
+    }
+
+    fn poll_copy<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+        budget: &mut Budget<'_>,
+    ) -> Poll<Result<(), Error>>
+    where
+        R: AsyncRead + ?Sized,
+        W: AsyncWrite + ?Sized,
+    {
+        loop {
+            if self.done {
+                return Poll::Ready(Ok(()));
+            }
+
+            if self.pos == self.cap && !self.read_done && !self.aborted {
+                // nothing is read beyond what the quota allows
+                let allowed = match budget.poll_allow(cx, 1) {
+                    Poll::Ready(0) => {
+                        self.aborted = true;
+                        continue;
+                    }
+                    Poll::Ready(allowed) => allowed.min(self.buf.len() as u64) as usize,
+                    Poll::Pending => return self.poll_flush_pending(cx, writer),
+                };
+
+                let mut buf = ReadBuf::new(&mut self.buf[..allowed]);
+
+                match reader.as_mut().poll_read(cx, &mut buf) {
+                    Poll::Ready(Ok(())) => {
+                        let len = buf.filled().len();
+
+                        if len == 0 {
+                            self.read_done = true;
+                        } else {
+                            self.pos = 0;
+                            self.cap = len;
+                            budget.consume(len as u64);
+                        }
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                    Poll::Pending => return self.poll_flush_pending(cx, writer),
+                }
+            }
+
+            while self.pos < self.cap {
+                let len = ready!(writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap]))?;
+
+                if len == 0 {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::WriteZero,
+                        "write zero byte into writer",
+                    )));
+                }
+
+                self.pos += len;
+                self.amt += len as u64;
+                self.need_flush = true;
+            }
+
+            if self.read_done || self.aborted {
+                if self.need_flush {
+                    ready!(writer.as_mut().poll_flush(cx))?;
+                    self.need_flush = false;
+                }
+
+                if self.read_done {
+                    ready!(writer.as_mut().poll_shutdown(cx))?;
+                }
+
+                self.done = true;
+            }
+        }
+    }
+    /// Flushes what was written while waiting for more data, then returns [`Poll::Pending`].
+    fn poll_flush_pending<W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<(), Error>>
+    where
+        W: AsyncWrite + ?Sized,
+    {
+        if self.need_flush {
+            ready!(writer.as_mut().poll_flush(cx))?;
+            self.need_flush = false;
+        }
+
+        Poll::Pending
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    config::ServerConfig, listener::Listener, shutdown::ShutdownHandle, stats::ServerStats,
+    tracker::ConnectionTracker, AuthAdaptor, Server, ServerAcceptResult,
+};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Error as IoError,
+    net::SocketAddr,
+    sync::Arc,
+};
+use tokio::net::TcpListener;
+
+/// A cheaply cloneable handle to a [`Server`], accepting connections from several tasks at once, see [`Server::into_shared()`].
+///
+/// Every clone accepts from the same listener with the same settings, so e.g. a task per worker thread can accept and negotiate connections. [`SharedServer::accept()`] supports any number of concurrent callers, each woken up for a connection of its own. The single-waker [`Server::poll_accept()`] is not available from it.
+///
+/// The settings of the server are fixed once shared, except for the authentication adaptor, see [`SharedServer::set_auth()`]. Get the server back with [`SharedServer::try_into_server()`] to change them.
+///
+/// With a limit on concurrent connections set to [`OnLimit::Wait`](crate::tracker::OnLimit::Wait), every task waiting in [`SharedServer::accept()`] holds a slot of the limit until a connection arrives, so keep the number of accepting tasks well below it.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, Server};
+/// use std::sync::Arc;
+///
+/// async fn listen() {
+///     let server = Server::bind("127.0.0.1:5000", Arc::new(NoAuth) as Arc<_>)
+///         .await
+///         .unwrap()
+///         .into_shared();
+///
+///     for _ in 0..4 {
+///         let server = server.clone();
+///
+///         tokio::spawn(async move {
+///             while let Ok((conn, _)) = server.accept().await {
+///                 todo!();
+///             }
+///         });
+///     }
+/// }
+/// ```
+pub struct SharedServer<A, L: Listener = TcpListener> {
+    server: Arc<Server<A, L>>,
+}
+
+impl<A, L: Listener> SharedServer<A, L> {
+    #[inline]
+    pub(crate) fn new(server: Server<A, L>) -> Self {
+        Self {
+            server: Arc::new(server),
+        }
+    }
+
+    /// Accept an [`IncomingConnection`](crate::IncomingConnection), see [`Server::accept()`]. Each concurrent call is handed a connection of its own.
+    #[inline]
+    pub async fn accept(&self) -> ServerAcceptResult<A, L::Stream> {
+        self.server.accept().await
+    }
+
+    /// Accept an [`IncomingConnection`](crate::IncomingConnection), retrying on transient errors, see [`Server::accept_retrying()`].
+    #[inline]
+    pub async fn accept_retrying(&self) -> ServerAcceptResult<A, L::Stream> {
+        self.server.accept_retrying().await
+    }
+
+    /// Returns the authentication adaptor connections accepted now are authenticated with.
+    #[inline]
+    pub fn auth(&self) -> AuthAdaptor<A, L::Stream> {
+        self.server.auth()
+    }
+
+    /// Replaces the authentication adaptor connections accepted afterwards by any clone are authenticated with, see [`Server::set_auth()`].
+    #[inline]
+    pub fn set_auth(&self, auth: AuthAdaptor<A, L::Stream>) {
+        self.server.set_auth(auth);
+    }
+
+    /// Returns the configuration of the server, see [`Server::config()`].
+    #[inline]
+    pub fn config(&self) -> &ServerConfig {
+        self.server.config()
+    }
+
+    /// Returns the [`ConnectionTracker`] of the server, see [`Server::connection_tracker()`].
+    #[inline]
+    pub fn connection_tracker(&self) -> &ConnectionTracker {
+        self.server.connection_tracker()
+    }
+
+    /// Returns the [`ServerStats`] of the server, if any, see [`Server::stats()`].
+    #[inline]
+    pub fn stats(&self) -> Option<&ServerStats> {
+        self.server.stats()
+    }
+
+    /// Returns the [`ShutdownHandle`] of the server, see [`Server::shutdown_handle()`].
+    #[inline]
+    pub fn shutdown_handle(&self) -> &ShutdownHandle {
+        self.server.shutdown_handle()
+    }
+
+    /// Shuts the server down for every clone, see [`Server::shutdown()`].
+    #[inline]
+    pub fn shutdown(&self) {
+        self.server.shutdown();
+    }
+
+    /// Returns the local address that the server is bound to, see [`Server::local_addr()`].
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.server.local_addr()
+    }
+
+    /// Returns a shared reference to the listener.
+    ///
+    /// Note that this may break the encapsulation of the [`Server`] and you should not use this method unless you know what you are doing.
+    #[inline]
+    pub fn get_ref(&self) -> &L {
+        self.server.get_ref()
+    }
+
+    /// Returns the [`Server`] back if this is the last handle to it, or the handle otherwise.
+    #[inline]
+    pub fn try_into_server(self) -> Result<Server<A, L>, Self> {
+        Arc::try_unwrap(self.server).map_err(|server| Self { server })
+    }
+}
+
+#[cfg(feature = "serve")]
+impl<A, L> SharedServer<A, L>
+where
+    A: Send + 'static,
+    L: Listener,
+    L::Stream: Send + 'static,
+{
+    /// Serves connections with `handler` until the server is shut down, see [`Server::serve()`]. Calling it from several clones accepts from all of them.
+    #[inline]
+    pub async fn serve<H>(&self, handler: Arc<H>) -> Result<(), IoError>
+    where
+        H: crate::ConnectionHandler<A, L::Stream> + 'static,
+    {
+        self.server.serve(handler).await
+    }
+}
+
+impl<A, L: Listener> Clone for SharedServer<A, L> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self {
+            server: self.server.clone(),
+        }
+    }
+}
+
+impl<A, L: Listener + Debug> Debug for SharedServer<A, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_tuple("SharedServer").field(&self.server).finish()
+    }
+}
@@ -0,0 +1,158 @@
+//! This module defines [`ShutdownHandle`], stopping a [`Server`](crate::Server) from accepting connections, e.g. to drain the connections in flight before exiting.
+
+use std::{
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    io::Error as IoError,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+use tokio::sync::Notify;
+
+/// A cheaply cloneable handle shutting a [`Server`](crate::Server) down.
+///
+/// Every [`Server`](crate::Server) has one, see [`Server::shutdown_handle()`](crate::Server::shutdown_handle). Once [`ShutdownHandle::shutdown()`] is called, [`Server::accept()`](crate::Server::accept) and [`Server::poll_accept()`](crate::Server::poll_accept) fail with an error wrapping [`ServerShutdown`], including the calls pending at that moment, which are woken up. Connections accepted before are left untouched to drain, wait for them with the [`ConnectionTracker`](crate::tracker::ConnectionTracker) of the server.
+///
+/// The listener is only closed once the server is dropped, so connections queued by the system in the meantime are reset then.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, Server};
+/// use std::{sync::Arc, time::Duration};
+/// use tokio::net::TcpListener;
+///
+/// async fn listen() {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+///     let shutdown = server.shutdown_handle().clone();
+///     let tracker = server.connection_tracker().clone();
+///
+///     tokio::spawn(async move {
+///         tokio::time::sleep(Duration::from_secs(60)).await;
+///         shutdown.shutdown();
+///     });
+///
+///     // returns once shut down
+///     while let Ok((conn, _)) = server.accept().await {
+///         tokio::spawn(async move {
+///             todo!();
+///         });
+///     }
+///
+///     tracker.await_idle_timeout(Duration::from_secs(30)).await;
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ShutdownHandle {
+    inner: Arc<ShutdownInner>,
+}
+
+#[derive(Default)]
+struct ShutdownInner {
+    shut_down: AtomicBool,
+    notify: Notify,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl ShutdownHandle {
+    /// Creates a new [`ShutdownHandle`], not shut down.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Shuts the servers using this handle down. Calling it again does nothing.
+    pub fn shutdown(&self) {
+        let waker = {
+            let mut waker = self
+                .inner
+                .waker
+                .lock()
+                .unwrap_or_else(|err| err.into_inner());
+            self.inner.shut_down.store(true, Ordering::Release);
+            waker.take()
+        };
+
+        self.inner.notify.notify_waiters();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Returns whether [`ShutdownHandle::shutdown()`] was called.
+    #[inline]
+    pub fn is_shut_down(&self) -> bool {
+        self.inner.shut_down.load(Ordering::Acquire)
+    }
+
+    /// Waits until [`ShutdownHandle::shutdown()`] is called, returning right away if it already was, e.g. to make connections still negotiating give up.
+    pub async fn await_shutdown(&self) {
+        let mut notified = pin!(self.inner.notify.notified());
+        notified.as_mut().enable();
+
+        if !self.is_shut_down() {
+            notified.await;
+        }
+    }
+
+    /// Polls whether the handle is shut down, registering the waker of `cx` to be woken up on shutdown otherwise. Only the waker of the most recent call is registered.
+    pub(crate) fn poll_shutdown(&self, cx: &mut Context<'_>) -> Poll<()> {
+        let mut waker = self
+            .inner
+            .waker
+            .lock()
+            .unwrap_or_else(|err| err.into_inner());
+
+        if self.is_shut_down() {
+            return Poll::Ready(());
+        }
+
+        match &mut *waker {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => *waker = Some(cx.waker().clone()),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl Debug for ShutdownHandle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ShutdownHandle")
+            .field("shut_down", &self.is_shut_down())
+            .finish()
+    }
+}
+
+/// The error [`Server::accept()`](crate::Server::accept) fails with once the server is shut down, wrapped in an [`std::io::Error`] of kind [`ErrorKind::Other`](std::io::ErrorKind::Other). Tell it apart from errors of the listener with [`ServerShutdown::is()`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ServerShutdown;
+
+impl ServerShutdown {
+    /// Returns whether `err` is the error of a server shut down.
+    #[inline]
+    pub fn is(err: &IoError) -> bool {
+        err.get_ref().is_some_and(|err| err.is::<Self>())
+    }
+}
+
+impl Display for ServerShutdown {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "server is shut down")
+    }
+}
+
+impl StdError for ServerShutdown {}
+
+impl From<ServerShutdown> for IoError {
+    #[inline]
+    fn from(err: ServerShutdown) -> Self {
+        IoError::other(err)
+    }
+}
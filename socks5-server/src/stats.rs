@@ -0,0 +1,160 @@
+//! This module defines [`ServerStats`], counting the connections of a [`Server`](crate::Server) and the bytes they relayed, e.g. to export them to a monitoring system.
+
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+/// A cheaply cloneable handle counting the connections of the servers it is set on and the bytes they relayed.
+///
+/// Statistics are opt-in: set a handle on a server with [`Server::set_stats()`](crate::Server::set_stats), then read it with [`ServerStats::snapshot()`] at any time, e.g. from a metrics endpoint. The counters are relaxed atomics updated by the tasks driving the connections as they go, so leaving them enabled costs little more than an atomic addition per read and write of the relay. As they are updated independently, a snapshot taken while connections are in flight may be off by the events happening meanwhile.
+///
+/// Only connections accepted after the handle is set are counted. Share one handle between several servers to count them together.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, stats::ServerStats, Server};
+/// use std::{sync::Arc, time::Duration};
+/// use tokio::{net::TcpListener, time};
+///
+/// async fn listen() {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+///     let stats = ServerStats::new();
+///     server.set_stats(stats.clone());
+///
+///     tokio::spawn(async move {
+///         loop {
+///             time::sleep(Duration::from_secs(60)).await;
+///             eprintln!("{:?}", stats.snapshot());
+///         }
+///     });
+///
+///     while let Ok((conn, _)) = server.accept().await {
+///         tokio::spawn(async move {
+///             todo!();
+///         });
+///     }
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ServerStats {
+    inner: Arc<StatsInner>,
+}
+
+#[derive(Default)]
+struct StatsInner {
+    accepted: AtomicU64,
+    filtered: AtomicU64,
+    accept_errors: AtomicU64,
+    active: AtomicU64,
+    established: AtomicU64,
+    failed: AtomicU64,
+    received: AtomicU64,
+    sent: AtomicU64,
+}
+
+/// The values of the counters of a [`ServerStats`] at the time of [`ServerStats::snapshot()`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct StatsSnapshot {
+    /// Connections accepted
+    pub accepted: u64,
+
+    /// Connections dropped by the accept filter of the server, not counted in the other fields, see [`Server::set_accept_filter()`](crate::Server::set_accept_filter)
+    pub filtered: u64,
+
+    /// Transient errors accepting connections retried by [`Server::accept_retrying()`](crate::Server::accept_retrying)
+    pub accept_errors: u64,
+
+    /// Connections accepted and not dropped yet, across every state transition. Taking the stream out with e.g. `into_inner()` ends the connection, unlike for the [`ConnectionTracker`](crate::tracker::ConnectionTracker), which keeps counting the [`Tracked`](crate::tracker::Tracked) stream until it is dropped.
+    pub active: u64,
+
+    /// Connections whose command was first replied [`Reply::Succeeded`](socks5_proto::Reply::Succeeded)
+    pub established: u64,
+
+    /// Connections whose negotiation failed before being established, with the error returned alongside the stream, e.g. on a protocol error, a hostname rejected by the policy or the setup deadline passing
+    pub failed: u64,
+
+    /// Bytes read from the clients, counted as [`SessionStats::received`](crate::event::SessionStats::received) is
+    pub received: u64,
+
+    /// Bytes written to the clients, counted as [`SessionStats::sent`](crate::event::SessionStats::sent) is
+    pub sent: u64,
+}
+
+impl ServerStats {
+    /// Creates a new [`ServerStats`] with every counter at 0.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the current values of the counters.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let inner = &*self.inner;
+
+        StatsSnapshot {
+            accepted: inner.accepted.load(Ordering::Relaxed),
+            filtered: inner.filtered.load(Ordering::Relaxed),
+            accept_errors: inner.accept_errors.load(Ordering::Relaxed),
+            active: inner.active.load(Ordering::Relaxed),
+            established: inner.established.load(Ordering::Relaxed),
+            failed: inner.failed.load(Ordering::Relaxed),
+            received: inner.received.load(Ordering::Relaxed),
+            sent: inner.sent.load(Ordering::Relaxed),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn accepted(&self) {
+        self.inner.accepted.fetch_add(1, Ordering::Relaxed);
+        self.inner.active.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn filtered(&self) {
+        self.inner.filtered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn accept_error(&self) {
+        self.inner.accept_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn closed(&self) {
+        self.inner.active.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn established(&self) {
+        self.inner.established.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn failed(&self) {
+        self.inner.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn received(&self, len: usize) {
+        self.inner.received.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn sent(&self, len: usize) {
+        self.inner.sent.fetch_add(len as u64, Ordering::Relaxed);
+    }
+}
+
+impl Debug for ServerStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_tuple("ServerStats")
+            .field(&self.snapshot())
+            .finish()
+    }
+}
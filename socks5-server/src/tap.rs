@@ -0,0 +1,217 @@
+//! This module defines [`WireTap`], capturing the bytes of the negotiation of connections as they are exchanged, e.g. to debug a misbehaving client over a TLS-wrapped stream that `tcpdump` can not see into.
+
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult, Write as _},
+    fs::OpenOptions,
+    io::{self, Error, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// The direction bytes captured by a [`WireTap`] went in
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Direction {
+    /// Read from the client.
+    Inbound,
+
+    /// Written to the client.
+    Outbound,
+}
+
+type Hook = dyn Fn(Direction, &[u8]) + Send + Sync;
+
+/// A hook called with every chunk of bytes read or written by this crate during the negotiation of a connection.
+///
+/// Set it on a [`Server`](crate::Server) with [`Server::set_wire_tap()`](crate::Server::set_wire_tap), or on a single connection with [`IncomingConnection::set_wire_tap()`](crate::IncomingConnection::set_wire_tap). Any `Fn(Direction, &[u8]) + Send + Sync` closure converts into one.
+///
+/// The handshake, the request and every reply are captured, in one chunk per message. Inbound bytes are captured once parsed, so bytes the client pipelined after the request are not. Capturing ends once the final reply of the command is sent, or the negotiation fails and the stream is handed back in a [`Failed`](crate::Failed), so relayed payload is never captured. The sub-negotiation of the [`Auth`](crate::Auth) adaptor, e.g. the username and password, is not captured either, unless [`WireTap::capture_credentials()`] is set.
+///
+/// Without a tap, which is the default, nothing is copied or called.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     auth::NoAuth,
+///     tap::HexDumpTap,
+///     Server,
+/// };
+/// use std::sync::Arc;
+/// use tokio::net::TcpListener;
+///
+/// async fn listen() {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+///     let dump = HexDumpTap::stderr();
+///
+///     while let Ok((mut conn, addr)) = server.accept().await {
+///         conn.set_wire_tap(dump.clone().with_label(addr.to_string()));
+///
+///         tokio::spawn(async move {
+///             todo!();
+///         });
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct WireTap {
+    hook: Arc<Hook>,
+    credentials: bool,
+}
+
+impl WireTap {
+    /// Creates a new [`WireTap`] calling `hook`, not capturing credentials.
+    #[inline]
+    pub fn new<F>(hook: F) -> Self
+    where
+        F: Fn(Direction, &[u8]) + Send + Sync + 'static,
+    {
+        Self {
+            hook: Arc::new(hook),
+            credentials: false,
+        }
+    }
+
+    /// Sets whether the sub-negotiation of the [`Auth`](crate::Auth) adaptor is captured too. Off by default, as it carries the credentials of the client, e.g. its password in plaintext.
+    #[inline]
+    pub fn capture_credentials(mut self, capture: bool) -> Self {
+        self.credentials = capture;
+        self
+    }
+
+    /// Returns whether the sub-negotiation of the [`Auth`](crate::Auth) adaptor is captured.
+    #[inline]
+    pub fn captures_credentials(&self) -> bool {
+        self.credentials
+    }
+
+    #[inline]
+    pub(crate) fn capture(&self, direction: Direction, bytes: &[u8]) {
+        if !bytes.is_empty() {
+            (self.hook)(direction, bytes);
+        }
+    }
+}
+
+impl<F> From<F> for WireTap
+where
+    F: Fn(Direction, &[u8]) + Send + Sync + 'static,
+{
+    #[inline]
+    fn from(hook: F) -> Self {
+        Self::new(hook)
+    }
+}
+
+impl Debug for WireTap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("WireTap")
+            .field("credentials", &self.credentials)
+            .finish()
+    }
+}
+
+/// A [`WireTap`] writing a hex dump of every captured chunk, in the format of `hexdump -C`.
+///
+/// Each line carries the label, `<` for bytes read from the client or `>` for bytes written to it, the offset in the chunk, then 16 bytes in hex and as ASCII. Clones write to the same output, so give each connection a clone with its own label, e.g. the address of the client, to tell them apart. Errors writing the dump are ignored.
+#[derive(Clone)]
+pub struct HexDumpTap {
+    out: Arc<Mutex<Box<dyn Write + Send>>>,
+    label: Arc<str>,
+}
+
+impl HexDumpTap {
+    /// Creates a new [`HexDumpTap`] writing to `out`.
+    pub fn new<W>(out: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        Self {
+            out: Arc::new(Mutex::new(Box::new(out))),
+            label: Arc::from(""),
+        }
+    }
+
+    /// Creates a new [`HexDumpTap`] writing to the standard error.
+    #[inline]
+    pub fn stderr() -> Self {
+        Self::new(io::stderr())
+    }
+
+    /// Creates a new [`HexDumpTap`] appending to the file at `path`, creating it if it does not exist.
+    pub fn append_to_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+
+    /// Sets the label prefixed to every line, e.g. the address of the client.
+    #[inline]
+    pub fn with_label<L: Into<String>>(mut self, label: L) -> Self {
+        self.label = Arc::from(label.into());
+        self
+    }
+
+    /// Formats the dump of `bytes`, with a trailing newline.
+    pub fn format(&self, direction: Direction, bytes: &[u8]) -> String {
+        let arrow = match direction {
+            Direction::Inbound => '<',
+            Direction::Outbound => '>',
+        };
+
+        let mut dump = String::new();
+
+        for (idx, line) in bytes.chunks(16).enumerate() {
+            if !self.label.is_empty() {
+                let _ = write!(dump, "{} ", self.label);
+            }
+
+            let _ = write!(dump, "{arrow} {:08x} ", idx * 16);
+
+            for col in 0..16 {
+                if col == 8 {
+                    dump.push(' ');
+                }
+
+                match line.get(col) {
+                    Some(byte) => {
+                        let _ = write!(dump, " {byte:02x}");
+                    }
+                    None => dump.push_str("   "),
+                }
+            }
+
+            dump.push_str("  |");
+            dump.extend(line.iter().map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            }));
+            dump.push_str("|\n");
+        }
+
+        dump
+    }
+}
+
+impl From<HexDumpTap> for WireTap {
+    fn from(tap: HexDumpTap) -> Self {
+        WireTap::new(move |direction, bytes| {
+            let dump = tap.format(direction, bytes);
+
+            if let Ok(mut out) = tap.out.lock() {
+                let _ = out.write_all(dump.as_bytes());
+                let _ = out.flush();
+            }
+        })
+    }
+}
+
+impl Debug for HexDumpTap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("HexDumpTap")
+            .field("label", &self.label)
+            .finish()
+    }
+}
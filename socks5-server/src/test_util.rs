@@ -0,0 +1,375 @@
+//! In-memory connections for unit testing SOCKS5 handlers without any real socket.
+//!
+//! [`incoming()`] creates an [`IncomingConnection`] over a [`DuplexStream`], alongside the [`MockClient`] on the other end of it. The client writes scripted SOCKS5 messages and raw bytes, records everything the server sent, and asserts on the replies. [`connect()`], [`bind()`] and [`associate()`] skip the negotiation and start right at the reply of a command.
+//!
+//! Everything but the methods about socket addresses works over these connections, so the UDP side of an `Associate` command can not be tested with them.
+//!
+//! Enabled by the `test-util` cargo feature.
+//!
+//! # Example
+//!
+//! ```rust
+//! use socks5_server::{
+//!     auth::NoAuth,
+//!     proto::{handshake::Method, Address, Command as ProtoCommand, Reply},
+//!     test_util, Command,
+//! };
+//! use std::sync::Arc;
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! #[tokio::main(flavor = "current_thread")]
+//! async fn main() {
+//!     let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+//!     let target = Address::DomainAddress(b"example.com".to_vec(), 80);
+//!
+//!     client
+//!         .handshake(&[Method::NONE])
+//!         .request(ProtoCommand::Connect, target.clone())
+//!         .data(b"ping");
+//!
+//!     let server = tokio::spawn(async move {
+//!         let (conn, ()) = conn.authenticate().await.unwrap();
+//!
+//!         let Command::Connect(connect, addr) = conn.wait().await.unwrap() else {
+//!             unreachable!()
+//!         };
+//!         assert_eq!(addr, target);
+//!
+//!         let mut connect = connect
+//!             .reply(Reply::Succeeded, Address::unspecified())
+//!             .await
+//!             .unwrap();
+//!
+//!         let mut buf = [0; 4];
+//!         connect.read_exact(&mut buf).await.unwrap();
+//!         connect.write_all(b"pong").await.unwrap();
+//!     });
+//!
+//!     client.expect_method(Method::NONE).await;
+//!     assert_eq!(client.expect_reply(Reply::Succeeded).await, Address::unspecified());
+//!     assert_eq!(client.recv(4).await, b"pong");
+//!
+//!     server.await.unwrap();
+//! }
+//! ```
+
+use crate::{
+    connection::{
+        associate::{self, Associate},
+        bind::{self, Bind},
+        connect::{self, Connect},
+        state::NeedAuthenticate,
+        BufferedStream,
+    },
+    event::Session,
+    Auth, IncomingConnection,
+};
+use bytes::BytesMut;
+use socks5_proto::{
+    handshake::{
+        password::{Request as PasswordRequest, Response as PasswordResponse},
+        Method, Request as HandshakeRequest, Response as HandshakeResponse,
+    },
+    Address, Command, Reply, Request, Response,
+};
+use std::{
+    io::{Error, IoSlice},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    time::Instant,
+};
+
+pub use tokio::io::DuplexStream;
+
+/// The capacity of the in-memory streams in each direction, large enough for any script of a test
+const CAPACITY: usize = 64 * 1024;
+
+/// Creates an [`IncomingConnection`] authenticating with `auth` over an in-memory stream, alongside the [`MockClient`] on the other end of it.
+pub fn incoming<A>(
+    auth: Arc<dyn Auth<DuplexStream, Output = A> + Send + Sync>,
+) -> (
+    IncomingConnection<A, NeedAuthenticate, DuplexStream>,
+    MockClient,
+) {
+    let (client, stream) = MockClient::new();
+    let conn = IncomingConnection::new(stream, auth);
+    (conn, client)
+}
+
+/// Creates an [`IncomingConnection`] as [`incoming()`] does, whose setup must finish within `deadline` from now, as if accepted by a [`Server`](crate::Server) with [`Server::set_session_setup_deadline()`](crate::Server::set_session_setup_deadline).
+pub fn incoming_with_setup_deadline<A>(
+    auth: Arc<dyn Auth<DuplexStream, Output = A> + Send + Sync>,
+    deadline: Duration,
+) -> (
+    IncomingConnection<A, NeedAuthenticate, DuplexStream>,
+    MockClient,
+) {
+    let (client, stream) = MockClient::new();
+    let mut conn = IncomingConnection::new(stream, auth);
+    conn.set_setup_deadline(Instant::now() + deadline);
+    (conn, client)
+}
+
+/// Creates a [`Connect`] command waiting for its reply over an in-memory stream, alongside the [`MockClient`] on the other end of it, as if the client had just sent the request.
+pub fn connect() -> (Connect<connect::state::NeedReply, DuplexStream>, MockClient) {
+    let (client, stream) = MockClient::new();
+    let connect = Connect::new(BufferedStream::new(stream), Session::detached());
+    (connect, client)
+}
+
+/// Creates a [`Bind`] command waiting for its first reply over an in-memory stream, alongside the [`MockClient`] on the other end of it, as if the client had just sent the request.
+pub fn bind() -> (Bind<bind::state::NeedFirstReply, DuplexStream>, MockClient) {
+    let (client, stream) = MockClient::new();
+    let bind = Bind::new(BufferedStream::new(stream), Session::detached());
+    (bind, client)
+}
+
+/// Creates an [`Associate`] command waiting for its reply over an in-memory stream, alongside the [`MockClient`] on the other end of it, as if the client had just sent the request declaring `declared` as its UDP endpoint.
+pub fn associate(
+    declared: Address,
+) -> (
+    Associate<associate::state::NeedReply, DuplexStream>,
+    MockClient,
+) {
+    let (client, stream) = MockClient::new();
+    let associate = Associate::new(BufferedStream::new(stream), declared, Session::detached());
+    (associate, client)
+}
+
+/// The client end of an in-memory connection
+///
+/// Messages are scripted with the chaining methods, e.g. [`MockClient::handshake()`], and written together by [`MockClient::send()`], or implicitly by any of the `expect_*()` methods before waiting on the server. Every byte read from the server is recorded, see [`MockClient::received()`].
+///
+/// The `expect_*()` and `recv*()` methods panic with a description of the mismatch, as they are meant to be called in tests. They wait on the server without a timeout.
+///
+/// [`MockClient`] also implements [`AsyncRead`] and [`AsyncWrite`] to relay payload after the negotiation. Writing through it bypasses the script, while reading through it is recorded as well.
+#[derive(Debug)]
+pub struct MockClient {
+    stream: DuplexStream,
+    script: BytesMut,
+    received: Vec<u8>,
+}
+
+impl MockClient {
+    /// Creates a [`MockClient`] alongside the in-memory stream of the server end, to construct connection types of custom handlers from.
+    pub fn new() -> (Self, DuplexStream) {
+        let (client, server) = io::duplex(CAPACITY);
+
+        let client = Self {
+            stream: client,
+            script: BytesMut::new(),
+            received: Vec::new(),
+        };
+
+        (client, server)
+    }
+
+    /// Scripts a handshake request offering `methods`.
+    pub fn handshake(&mut self, methods: &[Method]) -> &mut Self {
+        HandshakeRequest::new(methods.to_vec()).write_to_buf(&mut self.script);
+        self
+    }
+
+    /// Scripts a username / password sub-negotiation request.
+    pub fn password(&mut self, username: &[u8], password: &[u8]) -> &mut Self {
+        PasswordRequest::new(username.to_vec(), password.to_vec()).write_to_buf(&mut self.script);
+        self
+    }
+
+    /// Scripts a request of `command` to `addr`.
+    pub fn request(&mut self, command: Command, addr: Address) -> &mut Self {
+        Request::new(command, addr).write_to_buf(&mut self.script);
+        self
+    }
+
+    /// Scripts raw bytes, e.g. a malformed message or the first payload.
+    pub fn data(&mut self, bytes: &[u8]) -> &mut Self {
+        self.script.extend_from_slice(bytes);
+        self
+    }
+
+    /// Writes everything scripted so far to the server in one write.
+    ///
+    /// An error is returned if the server end is dropped.
+    pub async fn send(&mut self) -> Result<(), Error> {
+        let script = self.script.split();
+        self.stream.write_all(&script).await
+    }
+
+    /// Returns every byte read from the server so far.
+    #[inline]
+    pub fn received(&self) -> &[u8] {
+        &self.received
+    }
+
+    /// Sends the script and reads exactly `len` bytes from the server.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server closes the connection before sending `len` bytes.
+    pub async fn recv(&mut self, len: usize) -> Vec<u8> {
+        self.send_before_recv().await;
+
+        let mut buf = vec![0; len];
+        let mut filled = 0;
+
+        while filled < len {
+            match self.read(&mut buf[filled..]).await {
+                Ok(0) => panic!("the server closed the connection after {filled} of {len} expected bytes: {buf:02x?}", buf = &buf[..filled]),
+                Ok(n) => filled += n,
+                Err(err) => panic!("failed to read from the server: {err}"),
+            }
+        }
+
+        buf
+    }
+
+    /// Sends the script and reads from the server until it closes the connection, returning the bytes read.
+    pub async fn recv_to_end(&mut self) -> Vec<u8> {
+        self.send_before_recv().await;
+
+        let mut buf = Vec::new();
+
+        if let Err(err) = self.read_to_end(&mut buf).await {
+            panic!("failed to read from the server: {err}");
+        }
+
+        buf
+    }
+
+    /// Sends the script and reads the handshake response, asserting it chose `method`.
+    pub async fn expect_method(&mut self, method: Method) {
+        let resp = self
+            .recv(HandshakeResponse::new(method).serialized_len())
+            .await;
+        assert_method(&resp, method);
+    }
+
+    /// Sends the script and reads the username / password sub-negotiation response, asserting its status.
+    pub async fn expect_password_status(&mut self, status: bool) {
+        let resp = self
+            .recv(PasswordResponse::new(status).serialized_len())
+            .await;
+        assert_password_status(&resp, status);
+    }
+
+    /// Sends the script and reads a reply, asserting it is `reply`. Returns the address replied.
+    pub async fn expect_reply(&mut self, reply: Reply) -> Address {
+        self.send_before_recv().await;
+
+        let resp = match Response::read_from(self).await {
+            Ok(resp) => resp,
+            Err(err) => panic!("failed to read a reply from the server: {err}"),
+        };
+
+        assert_eq!(resp.reply, reply, "unexpected reply from the server");
+        resp.address
+    }
+
+    /// Sends the script and asserts the server closes the connection without sending anything more.
+    pub async fn expect_eof(&mut self) {
+        let rem = self.recv_to_end().await;
+        assert!(
+            rem.is_empty(),
+            "expected the server to close the connection, but it sent {rem:02x?}"
+        );
+    }
+
+    async fn send_before_recv(&mut self) {
+        // the server may close the connection after replying, which the read reports
+        let _ = self.send().await;
+    }
+}
+
+impl AsyncRead for MockClient {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.stream).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = res {
+            self.received.extend_from_slice(&buf.filled()[filled..]);
+        }
+
+        res
+    }
+}
+
+impl AsyncWrite for MockClient {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Asserts that `bytes` are exactly a handshake response choosing `method`.
+#[track_caller]
+pub fn assert_method(bytes: &[u8], method: Method) {
+    let mut expected = BytesMut::new();
+    HandshakeResponse::new(method).write_to_buf(&mut expected);
+    assert_eq!(
+        bytes,
+        &expected[..],
+        "expected a handshake response choosing {method:?}"
+    );
+}
+
+/// Asserts that `bytes` are exactly a username / password sub-negotiation response of `status`.
+#[track_caller]
+pub fn assert_password_status(bytes: &[u8], status: bool) {
+    let mut expected = BytesMut::new();
+    PasswordResponse::new(status).write_to_buf(&mut expected);
+    assert_eq!(
+        bytes,
+        &expected[..],
+        "expected a password response of status {status}"
+    );
+}
+
+/// Asserts that `bytes` are exactly a reply of `reply` with `addr`.
+#[track_caller]
+pub fn assert_reply(bytes: &[u8], reply: Reply, addr: &Address) {
+    let mut expected = BytesMut::new();
+    Response::new(reply, addr.clone()).write_to_buf(&mut expected);
+    assert_eq!(
+        bytes,
+        &expected[..],
+        "expected the reply {reply:?} with {addr:?}"
+    );
+}
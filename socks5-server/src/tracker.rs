@@ -0,0 +1,429 @@
+//! This module defines [`ConnectionTracker`], counting the connections in flight, e.g. to wait for them to finish before exiting on a rolling restart, and the limit on concurrent connections of [`Server::set_max_connections()`](crate::Server::set_max_connections).
+
+use std::{
+    error::Error as StdError,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    io::{Error as IoError, IoSlice},
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+    time,
+};
+
+/// A cheaply cloneable handle counting the connections in flight.
+///
+/// Every [`Server`](crate::Server) tracks the connections it accepts with one, see [`Server::connection_tracker()`](crate::Server::connection_tracker). A connection is counted from being accepted until the [`IncomingConnection`](crate::IncomingConnection), or the command type it turned into, is dropped, across every state transition. A connection handed back in a [`Failed`](crate::Failed) is counted until the [`Failed`](crate::Failed) is dropped, and a stream taken out with e.g. `into_inner()` comes in a [`Tracked`] counting it until it is dropped, so a connection is never left uncounted while it is open.
+///
+/// With manual accept loops, track anything else, e.g. the tasks driving the connections, with [`ConnectionTracker::track()`].
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::NoAuth, Server};
+/// use std::{sync::Arc, time::Duration};
+/// use tokio::{net::TcpListener, sync::oneshot::Receiver};
+///
+/// async fn listen(handover: Receiver<()>) {
+///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
+///     let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+///     let tracker = server.connection_tracker().clone();
+///
+///     tokio::select! {
+///         _ = async {
+///             while let Ok((conn, _)) = server.accept().await {
+///                 tokio::spawn(async move {
+///                     todo!();
+///                 });
+///             }
+///         } => {}
+///         _ = handover => {}
+///     }
+///
+///     // the listener was handed to the new process, stop accepting and drain
+///     drop(server);
+///
+///     if !tracker.await_idle_timeout(Duration::from_secs(30)).await {
+///         eprintln!("{} connections still in flight", tracker.count());
+///     }
+/// }
+/// ```
+#[derive(Clone, Default)]
+pub struct ConnectionTracker {
+    inner: Arc<TrackerInner>,
+}
+
+#[derive(Default)]
+struct TrackerInner {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+impl ConnectionTracker {
+    /// Creates a new [`ConnectionTracker`] with no connection in flight.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a connection in flight until the returned [`ConnectionGuard`] is dropped.
+    #[inline]
+    pub fn track(&self) -> ConnectionGuard {
+        self.inner.count.fetch_add(1, Ordering::Relaxed);
+
+        ConnectionGuard {
+            tracker: self.clone(),
+            permit: None,
+        }
+    }
+
+    /// Returns the number of connections in flight.
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.inner.count.load(Ordering::Acquire)
+    }
+
+    /// Waits until no connection is in flight, returning right away if there is none.
+    ///
+    /// Connections tracked while waiting are waited for too, so stop accepting first when draining.
+    pub async fn await_idle(&self) {
+        loop {
+            let mut notified = pin!(self.inner.idle.notified());
+            notified.as_mut().enable();
+
+            if self.count() == 0 {
+                return;
+            }
+
+            notified.await;
+        }
+    }
+
+    /// Waits until no connection is in flight as [`ConnectionTracker::await_idle()`] does, for at most `timeout`. Returns whether it became idle in time.
+    #[inline]
+    pub async fn await_idle_timeout(&self, timeout: Duration) -> bool {
+        time::timeout(timeout, self.await_idle()).await.is_ok()
+    }
+}
+
+impl Debug for ConnectionTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ConnectionTracker")
+            .field("count", &self.count())
+            .finish()
+    }
+}
+
+/// A connection counted by a [`ConnectionTracker`] until this guard is dropped, created by [`ConnectionTracker::track()`]
+#[derive(Debug)]
+#[must_use = "the connection is only tracked until the guard is dropped"]
+pub struct ConnectionGuard {
+    tracker: ConnectionTracker,
+    permit: Option<LimitPermit>,
+}
+
+impl ConnectionGuard {
+    /// Returns the tracker counting this connection.
+    #[inline]
+    pub fn tracker(&self) -> &ConnectionTracker {
+        &self.tracker
+    }
+
+    /// Makes the guard hold the slot of the connection in the limit of its server too, releasing it when dropped.
+    #[inline]
+    pub(crate) fn with_permit(mut self, permit: Option<LimitPermit>) -> Self {
+        self.permit = permit;
+        self
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let inner = &self.tracker.inner;
+
+        if inner.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            inner.idle.notify_waiters();
+        }
+    }
+}
+
+/// What [`Server::accept()`](crate::Server::accept) does when the limit on concurrent connections is reached, see [`Server::set_max_connections()`](crate::Server::set_max_connections)
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OnLimit {
+    /// Waits for a connection to finish before accepting the next one, leaving new connections queued by the system in the backlog of the listener.
+    #[default]
+    Wait,
+
+    /// Accepts the connection, then closes it right away and fails with an error wrapping [`LimitReached`].
+    Reject,
+}
+
+/// The error [`Server::accept()`](crate::Server::accept) fails with when a connection is rejected by [`OnLimit::Reject`], wrapped in an [`std::io::Error`] of kind [`ErrorKind::Other`](std::io::ErrorKind::Other). The server can keep accepting after it, tell it apart from errors of the listener with [`LimitReached::is()`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LimitReached;
+
+impl LimitReached {
+    /// Returns whether `err` is the error of a connection rejected over the limit.
+    #[inline]
+    pub fn is(err: &IoError) -> bool {
+        err.get_ref().is_some_and(|err| err.is::<Self>())
+    }
+}
+
+impl Display for LimitReached {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "connection limit reached")
+    }
+}
+
+impl StdError for LimitReached {}
+
+impl From<LimitReached> for IoError {
+    #[inline]
+    fn from(err: LimitReached) -> Self {
+        IoError::other(err)
+    }
+}
+
+/// The slots of the concurrent connections of a server, handed out as [`LimitPermit`]s
+pub(crate) struct ConnectionLimit {
+    max: usize,
+    on_limit: OnLimit,
+    state: Mutex<LimitState>,
+    released: Notify,
+}
+
+struct LimitState {
+    used: usize,
+    // a slot taken by a `poll_accept()` the listener was pending for, kept for its next call
+    reserved: bool,
+    waker: Option<Waker>,
+}
+
+impl ConnectionLimit {
+    #[inline]
+    pub(crate) fn new(max: usize, on_limit: OnLimit) -> Arc<Self> {
+        Arc::new(Self {
+            max,
+            on_limit,
+            state: Mutex::new(LimitState {
+                used: 0,
+                reserved: false,
+                waker: None,
+            }),
+            released: Notify::new(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn on_limit(&self) -> OnLimit {
+        self.on_limit
+    }
+
+    #[inline]
+    fn state(&self) -> MutexGuard<'_, LimitState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+
+    /// Takes a free slot, if any.
+    pub(crate) fn try_acquire(self: &Arc<Self>) -> Option<LimitPermit> {
+        let mut state = self.state();
+
+        if state.reserved {
+            state.reserved = false;
+        } else if state.used < self.max {
+            state.used += 1;
+        } else {
+            return None;
+        }
+
+        Some(LimitPermit {
+            limit: Some(self.clone()),
+        })
+    }
+
+    /// Waits for a free slot and takes it.
+    pub(crate) async fn acquire(self: &Arc<Self>) -> LimitPermit {
+        loop {
+            let mut released = pin!(self.released.notified());
+            released.as_mut().enable();
+
+            if let Some(permit) = self.try_acquire() {
+                return permit;
+            }
+
+            released.await;
+        }
+    }
+
+    /// Polls to take a free slot, registering the waker of `cx` to be woken up when one is released otherwise. Only the waker of the most recent call is registered.
+    pub(crate) fn poll_acquire(self: &Arc<Self>, cx: &mut Context<'_>) -> Poll<LimitPermit> {
+        let mut state = self.state();
+
+        if state.reserved {
+            state.reserved = false;
+        } else if state.used < self.max {
+            state.used += 1;
+        } else {
+            match &mut state.waker {
+                Some(waker) => waker.clone_from(cx.waker()),
+                None => state.waker = Some(cx.waker().clone()),
+            }
+
+            return Poll::Pending;
+        }
+
+        Poll::Ready(LimitPermit {
+            limit: Some(self.clone()),
+        })
+    }
+
+    /// Keeps the slot of `permit` for the next call taking one, without releasing it, so the waker registered is not woken up for nothing.
+    pub(crate) fn reserve(&self, mut permit: LimitPermit) {
+        let mut state = self.state();
+
+        if !state.reserved {
+            state.reserved = true;
+            permit.limit = None;
+        }
+
+        drop(state);
+    }
+
+    fn release(&self) {
+        let waker = {
+            let mut state = self.state();
+            state.used -= 1;
+            state.waker.take()
+        };
+
+        self.released.notify_waiters();
+
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Debug for ConnectionLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("ConnectionLimit")
+            .field("max", &self.max)
+            .field("on_limit", &self.on_limit)
+            .field("used", &self.state().used)
+            .finish()
+    }
+}
+
+/// A slot taken in a [`ConnectionLimit`], released when dropped
+#[derive(Debug)]
+pub(crate) struct LimitPermit {
+    limit: Option<Arc<ConnectionLimit>>,
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        if let Some(limit) = self.limit.take() {
+            limit.release();
+        }
+    }
+}
+
+/// A stream taken out of a connection accepted by a [`Server`](crate::Server), e.g. with [`Connect::into_inner()`](crate::Connect::into_inner), still counted by its [`ConnectionTracker`] and holding its slot of the limit on concurrent connections until it is dropped.
+///
+/// It reads and writes through to the underlying stream. Take the stream out with [`Tracked::into_parts()`] to keep the [`ConnectionGuard`] around it, e.g. in the task driving the stream, or with [`Tracked::into_inner()`], which ends the tracking. The guard is `None` if the connection was not accepted by a [`Server`](crate::Server), e.g. one created with [`IncomingConnection::new()`](crate::IncomingConnection::new).
+#[derive(Debug)]
+pub struct Tracked<T> {
+    inner: T,
+    guard: Option<ConnectionGuard>,
+}
+
+impl<T> Tracked<T> {
+    #[inline]
+    pub(crate) fn new(inner: T, guard: Option<ConnectionGuard>) -> Self {
+        Self { inner, guard }
+    }
+
+    /// Returns the guard counting the stream, if any.
+    #[inline]
+    pub fn guard(&self) -> Option<&ConnectionGuard> {
+        self.guard.as_ref()
+    }
+
+    /// Returns a shared reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Consumes the [`Tracked`] and returns the underlying stream, which is no longer counted.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Consumes the [`Tracked`] and returns the underlying stream alongside the guard counting it, if any.
+    #[inline]
+    pub fn into_parts(self) -> (T, Option<ConnectionGuard>) {
+        (self.inner, self.guard)
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tracked<T> {
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tracked<T> {
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::Client;
+use socks5_server::{proto::handshake::Method, Command};
+use std::io::ErrorKind;
+use tokio::io::AsyncReadExt;
+
+async fn assert_reset(client: &mut Client) {
+    let mut buf = Vec::new();
+    let err = common::timeout(client.stream.read_to_end(&mut buf))
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+}
+
+#[tokio::test]
+async fn aborted_connection_is_reset() {
+    let server = common::server().await;
+    let mut client = Client::connect(server.local_addr().unwrap()).await;
+    let (conn, _) = server.accept().await.unwrap();
+
+    conn.abort().unwrap();
+    assert_reset(&mut client).await;
+}
+
+#[tokio::test]
+async fn aborted_command_is_reset() {
+    let server = common::server().await;
+    let mut client = Client::connect(server.local_addr().unwrap()).await;
+    let (conn, _) = server.accept().await.unwrap();
+
+    let task = tokio::spawn(async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        // e.g. the destination is banned, without telling the client
+        connect.abort().unwrap();
+    });
+
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    // a `Connect` request to `192.0.2.1:80`
+    client
+        .write(&[0x05, 0x01, 0x00, 0x01, 192, 0, 2, 1, 0, 80])
+        .await;
+    common::timeout(task).await.unwrap();
+    assert_reset(&mut client).await;
+}
@@ -0,0 +1,83 @@
+use socks5_server::{auth::NoAuth, filter::Verdict, stats::ServerStats, Server};
+use std::{
+    future,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{TcpListener, TcpSocket, TcpStream},
+};
+
+/// A source address on the loopback network the filter of [`server()`] refuses
+const BLOCKED: Ipv4Addr = Ipv4Addr::new(127, 0, 0, 2);
+
+async fn server(verdict: Verdict) -> (Server<()>, ServerStats) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    let stats = ServerStats::new();
+    server.set_stats(stats.clone());
+
+    server.set_accept_filter(move |peer| match peer.ip() {
+        IpAddr::V4(ip) if ip == BLOCKED => verdict,
+        _ => Verdict::Allow,
+    });
+
+    (server, stats)
+}
+
+async fn connect_from(ip: Ipv4Addr, server: SocketAddr) -> TcpStream {
+    let socket = TcpSocket::new_v4().unwrap();
+    socket.bind((ip, 0).into()).unwrap();
+    socket.connect(server).await.unwrap()
+}
+
+#[tokio::test]
+async fn dropped_connections_are_skipped() {
+    let (server, stats) = server(Verdict::Drop).await;
+    let addr = server.local_addr().unwrap();
+
+    let mut blocked = connect_from(BLOCKED, addr).await;
+    let allowed = connect_from(Ipv4Addr::LOCALHOST, addr).await;
+
+    let (_conn, peer) = server.accept().await.unwrap();
+    assert_eq!(peer, allowed.local_addr().unwrap());
+
+    // closed in order, without anything being read
+    let mut buf = [0; 1];
+    assert_eq!(blocked.read(&mut buf).await.unwrap(), 0);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.filtered, 1);
+    assert_eq!(snapshot.accepted, 1);
+    assert_eq!(server.connection_tracker().count(), 1);
+}
+
+#[tokio::test]
+async fn reset_connections_are_skipped() {
+    let (server, stats) = server(Verdict::DropWithRst).await;
+    let addr = server.local_addr().unwrap();
+
+    let mut blocked = connect_from(BLOCKED, addr).await;
+    let allowed = connect_from(Ipv4Addr::LOCALHOST, addr).await;
+
+    let (_, peer) = future::poll_fn(|cx| server.poll_accept(cx)).await.unwrap();
+    assert_eq!(peer, allowed.local_addr().unwrap());
+
+    let mut buf = [0; 1];
+    let err = blocked.read(&mut buf).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ConnectionReset);
+    assert_eq!(stats.snapshot().filtered, 1);
+}
+
+#[tokio::test]
+async fn cleared_filter_allows_every_connection() {
+    let (mut server, stats) = server(Verdict::Drop).await;
+    server.clear_accept_filter();
+
+    let blocked = connect_from(BLOCKED, server.local_addr().unwrap()).await;
+    let (_, peer) = server.accept().await.unwrap();
+    assert_eq!(peer, blocked.local_addr().unwrap());
+    assert_eq!(stats.snapshot().filtered, 0);
+}
@@ -0,0 +1,153 @@
+use async_trait::async_trait;
+use socks5_server::{
+    auth::NoAuth,
+    config::AcceptBackoff,
+    event::EventHandler,
+    listener::{self, Listener},
+    shutdown::ServerShutdown,
+    stats::ServerStats,
+    Server,
+};
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind},
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::{self, Instant},
+};
+
+const EMFILE: i32 = 24;
+
+/// A listener failing with the scripted errors before accepting from a real listener
+struct Flaky {
+    errors: Mutex<VecDeque<Error>>,
+    inner: TcpListener,
+}
+
+#[async_trait]
+impl Listener for Flaky {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), Error> {
+        if let Some(err) = self.errors.lock().unwrap().pop_front() {
+            return Err(err);
+        }
+
+        self.inner.accept().await
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.inner.local_addr()
+    }
+}
+
+#[derive(Default)]
+struct Errors(Mutex<Vec<ErrorKind>>);
+
+impl EventHandler for Errors {
+    fn on_accept_error(&self, err: &Error) {
+        self.0.lock().unwrap().push(err.kind());
+    }
+}
+
+async fn server(errors: Vec<Error>) -> (Server<(), Flaky>, Arc<Errors>, ServerStats) {
+    let listener = Flaky {
+        errors: Mutex::new(errors.into()),
+        inner: TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap(),
+    };
+
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    let events = Arc::new(Errors::default());
+    server.set_event_handler(events.clone());
+    let stats = ServerStats::new();
+    server.set_stats(stats.clone());
+
+    (server, events, stats)
+}
+
+#[test]
+fn errors_are_classified() {
+    assert!(listener::is_transient_error(&Error::from_raw_os_error(
+        EMFILE
+    )));
+    assert!(listener::is_transient_error(&Error::from(
+        ErrorKind::ConnectionAborted
+    )));
+    assert!(!listener::is_transient_error(&Error::from(
+        ErrorKind::InvalidInput
+    )));
+    assert!(!listener::is_transient_error(&ServerShutdown.into()));
+}
+
+#[tokio::test(start_paused = true)]
+async fn transient_errors_are_retried_with_backoff() {
+    let (mut server, events, stats) = server(vec![
+        Error::from_raw_os_error(EMFILE),
+        Error::from_raw_os_error(EMFILE),
+        Error::from_raw_os_error(EMFILE),
+        Error::from(ErrorKind::ConnectionAborted),
+    ])
+    .await;
+
+    server.set_accept_backoff(AcceptBackoff {
+        initial: Duration::from_millis(100),
+        max: Duration::from_millis(150),
+    });
+
+    let client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let start = Instant::now();
+    let (_, peer) = server.accept_retrying().await.unwrap();
+    assert_eq!(peer, client.local_addr().unwrap());
+
+    // 100 ms, then 150 ms twice as capped, and nothing for the aborted connection
+    assert_eq!(start.elapsed(), Duration::from_millis(400));
+
+    assert_eq!(
+        *events.0.lock().unwrap(),
+        [
+            Error::from_raw_os_error(EMFILE).kind(),
+            Error::from_raw_os_error(EMFILE).kind(),
+            Error::from_raw_os_error(EMFILE).kind(),
+            ErrorKind::ConnectionAborted,
+        ]
+    );
+    assert_eq!(stats.snapshot().accept_errors, 4);
+}
+
+#[tokio::test]
+async fn fatal_errors_are_returned() {
+    let (server, events, _) = server(vec![Error::from(ErrorKind::InvalidInput)]).await;
+
+    let err = server.accept_retrying().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    assert!(events.0.lock().unwrap().is_empty());
+}
+
+#[tokio::test(start_paused = true)]
+async fn shutdown_interrupts_the_backoff() {
+    let (mut server, _, _) = server(vec![Error::from_raw_os_error(EMFILE)]).await;
+
+    server.set_accept_backoff(AcceptBackoff {
+        initial: Duration::from_secs(3600),
+        max: Duration::from_secs(3600),
+    });
+
+    let shutdown = server.shutdown_handle().clone();
+
+    tokio::spawn(async move {
+        time::sleep(Duration::from_secs(1)).await;
+        shutdown.shutdown();
+    });
+
+    let start = Instant::now();
+    let err = server.accept_retrying().await.unwrap_err();
+    assert!(ServerShutdown::is(&err));
+    assert!(start.elapsed() < Duration::from_secs(3600));
+}
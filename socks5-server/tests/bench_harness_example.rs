@@ -0,0 +1,33 @@
+//! Runs `examples/bench_harness.rs` with the short workload, checking that every benchmark completes.
+
+#[allow(dead_code)]
+#[path = "../examples/bench_harness.rs"]
+mod example;
+
+use example::Workload;
+
+#[tokio::test(flavor = "multi_thread")]
+async fn short_workload_completes() {
+    let workload = Workload::SHORT;
+    let measurements = example::run(workload).await.unwrap();
+
+    let names = measurements.iter().map(|m| m.name).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["connect_negotiations", "connect_throughput", "udp_relay"]
+    );
+
+    assert_eq!(measurements[0].ops, workload.negotiations as u64);
+    assert_eq!(measurements[1].ops, workload.bulk_bytes as u64);
+    assert_eq!(
+        measurements[2].ops + measurements[2].lost,
+        workload.udp_packets as u64
+    );
+    assert!(measurements[2].ops > 0);
+
+    for measurement in &measurements {
+        let line = measurement.to_string();
+        assert!(line.starts_with(&format!("bench={} ", measurement.name)));
+        assert!(measurement.rate() > 0.0);
+    }
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::bind::BindAcceptor,
+    proto::{Address, Command as ProtoCommand, Reply},
+    Command,
+};
+use std::{
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+};
+use tokio::{net::TcpStream, sync::oneshot};
+
+#[tokio::test]
+async fn client_closing_wins_over_an_inbound_peer_never_arriving() {
+    let (tx, rx) = oneshot::channel();
+    let tx = Arc::new(Mutex::new(Some(tx)));
+
+    let addr = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let tx = tx.clone();
+
+        async move {
+            let (conn, ()) = conn.authenticate().await.unwrap();
+            let Command::Bind(bind, _) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let acceptor = BindAcceptor::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+            let local = Address::SocketAddress(acceptor.local_addr().unwrap());
+            let mut bind = bind.reply(Reply::Succeeded, local).await.unwrap();
+
+            let closed = tokio::select! {
+                _ = acceptor.accept() => false,
+                res = bind.wait_close() => res.is_ok(),
+            };
+
+            tx.lock()
+                .unwrap()
+                .take()
+                .unwrap()
+                .send((closed, acceptor))
+                .unwrap();
+        }
+    })
+    .await;
+
+    let (client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Bind, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    let Address::SocketAddress(inbound) = resp.address else {
+        unreachable!()
+    };
+
+    // the client gives up before any peer connects to the address of the first reply
+    drop(client);
+    let (closed, acceptor) = common::timeout(rx).await.unwrap();
+    assert!(closed);
+
+    // the accept cancelled by the race lost nothing, the acceptor still accepts
+    let _peer = TcpStream::connect(inbound).await.unwrap();
+    common::timeout(acceptor.accept()).await.unwrap();
+}
@@ -0,0 +1,49 @@
+use socks5_server::connection::associate::BufferPool;
+
+#[test]
+fn warm_pool_reuses_buffers() {
+    let pool = BufferPool::new(2048, 4);
+
+    let buf = pool.get();
+    assert!(buf.capacity() >= 2048);
+    let ptr = buf.as_ptr();
+    drop(buf);
+
+    for _ in 0..8 {
+        let mut buf = pool.get();
+        assert_eq!(buf.as_ptr(), ptr);
+        assert!(buf.is_empty());
+        buf.extend_from_slice(b"hello");
+    }
+}
+
+#[test]
+fn frozen_slices_keep_buffer_alive() {
+    let pool = BufferPool::new(2048, 4);
+
+    let mut buf = pool.get();
+    let ptr = buf.as_ptr();
+    buf.extend_from_slice(b"header payload");
+
+    let pkt = buf.freeze().slice(7..);
+    assert_eq!(pool.idle(), 0);
+    assert_eq!(&pkt[..], b"payload");
+
+    let clone = pkt.clone();
+    drop(pkt);
+    assert_eq!(pool.idle(), 0);
+
+    drop(clone);
+    assert_eq!(pool.idle(), 1);
+    assert_eq!(pool.get().as_ptr(), ptr);
+}
+
+#[test]
+fn pool_is_capped() {
+    let pool = BufferPool::new(64, 2);
+    let bufs = (0..4).map(|_| pool.get()).collect::<Vec<_>>();
+    drop(bufs);
+
+    assert!(pool.idle() <= 2);
+    assert!(pool.idle() > 0);
+}
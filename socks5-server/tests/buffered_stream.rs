@@ -0,0 +1,230 @@
+mod common;
+
+use bytes::{Bytes, BytesMut};
+use socks5_server::{
+    auth::Password,
+    proto::{
+        handshake::{
+            password::{Request as PasswordRequest, Response as PasswordResponse},
+            Method, Request as HandshakeRequest, Response as HandshakeResponse,
+        },
+        Address, Command as ProtoCommand, Reply, Request, Response,
+    },
+    Auth, BufferedStream, Command, IncomingConnection,
+};
+use std::{
+    io::Error as IoError,
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{
+        self, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream,
+        ReadBuf,
+    },
+    net::TcpStream,
+    sync::oneshot,
+};
+
+const PAYLOAD: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+/// Writes the handshake, the password sub-negotiation, a `Connect` request and [`PAYLOAD`] in a single write, as a client sending early data would.
+async fn pipelined_client(server: SocketAddr) -> TcpStream {
+    let mut buf = BytesMut::new();
+    HandshakeRequest::new(vec![Method::PASSWORD]).write_to_buf(&mut buf);
+    PasswordRequest::new(b"user".to_vec(), b"pass".to_vec()).write_to_buf(&mut buf);
+    Request::new(
+        ProtoCommand::Connect,
+        Address::SocketAddress(SocketAddr::from((Ipv4Addr::LOCALHOST, 1))),
+    )
+    .write_to_buf(&mut buf);
+    buf.extend_from_slice(PAYLOAD);
+
+    let mut stream = TcpStream::connect(server).await.unwrap();
+    stream.write_all(&buf).await.unwrap();
+
+    let resp = HandshakeResponse::read_from(&mut stream).await.unwrap();
+    assert_eq!(resp.method, Method::PASSWORD);
+    let resp = PasswordResponse::read_from(&mut stream).await.unwrap();
+    assert!(resp.status);
+    let resp = Response::read_from(&mut stream).await.unwrap();
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    stream
+}
+
+/// A stream counting the reads of bytes from it, each of which would be a `recv` call on a socket
+struct Counted {
+    inner: DuplexStream,
+    reads: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for Counted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if res.is_ready() {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+        }
+
+        res
+    }
+}
+
+impl AsyncWrite for Counted {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+fn auth() -> Arc<dyn Auth<Output = <Password as Auth>::Output> + Send + Sync> {
+    Arc::new(Password::new(b"user".to_vec(), b"pass".to_vec()))
+}
+
+#[tokio::test]
+async fn pipelined_payload_is_read_after_the_negotiation() {
+    let (tx, rx) = oneshot::channel();
+    let tx = std::sync::Mutex::new(Some(tx));
+
+    let server = common::serve(auth(), move |conn| {
+        let tx = tx.lock().unwrap().take().unwrap();
+
+        async move {
+            let (conn, res) = conn.authenticate().await.unwrap();
+            assert!(res.unwrap());
+
+            let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let mut connect = connect
+                .reply(Reply::Succeeded, Address::unspecified())
+                .await
+                .unwrap();
+
+            let mut buf = vec![0; PAYLOAD.len()];
+            connect.read_exact(&mut buf).await.unwrap();
+            let _ = tx.send(buf);
+        }
+    })
+    .await;
+
+    let _client = pipelined_client(server).await;
+    assert_eq!(common::timeout(rx).await.unwrap(), PAYLOAD);
+}
+
+#[tokio::test]
+async fn into_parts_hands_over_the_buffered_bytes() {
+    let (tx, rx) = oneshot::channel();
+    let tx = std::sync::Mutex::new(Some(tx));
+
+    let server = common::serve(auth(), move |conn| {
+        let tx = tx.lock().unwrap().take().unwrap();
+
+        async move {
+            let (conn, _) = conn.authenticate().await.unwrap();
+
+            let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let connect = connect
+                .reply(Reply::Succeeded, Address::unspecified())
+                .await
+                .unwrap();
+
+            // whatever was not buffered yet is still in the socket
+            let (mut stream, buffered) = connect.into_parts();
+            let mut buf = buffered.to_vec();
+            buf.resize(PAYLOAD.len(), 0);
+            stream.read_exact(&mut buf[buffered.len()..]).await.unwrap();
+            let _ = tx.send(buf);
+        }
+    })
+    .await;
+
+    let _client = pipelined_client(server).await;
+    assert_eq!(common::timeout(rx).await.unwrap(), PAYLOAD);
+}
+
+#[tokio::test]
+async fn buffered_bytes_are_read_before_the_stream() {
+    let (inner, mut peer) = io::duplex(64);
+    peer.write_all(b" world").await.unwrap();
+
+    let mut stream = BufferedStream::from_parts(inner, Bytes::from_static(b"hello"));
+    assert_eq!(stream.buffer(), b"hello");
+
+    let mut buf = [0; 11];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello world");
+
+    peer.write_all(b"line\nrest").await.unwrap();
+    let mut line = String::new();
+    stream.read_line(&mut line).await.unwrap();
+    assert_eq!(line, "line\n");
+
+    // the remainder of the last fill is handed over, and nothing else was read from the stream
+    let (mut inner, buffered) = stream.into_parts();
+    assert_eq!(buffered, &b"rest"[..]);
+
+    peer.write_all(b"!").await.unwrap();
+    assert_eq!(inner.read_u8().await.unwrap(), b'!');
+}
+
+#[tokio::test]
+async fn pipelined_negotiation_is_read_at_once() {
+    let (inner, mut client) = io::duplex(4096);
+    let reads = Arc::new(AtomicUsize::new(0));
+    let stream = Counted {
+        inner,
+        reads: reads.clone(),
+    };
+
+    let mut buf = BytesMut::new();
+    HandshakeRequest::new(vec![Method::PASSWORD]).write_to_buf(&mut buf);
+    PasswordRequest::new(b"user".to_vec(), b"pass".to_vec()).write_to_buf(&mut buf);
+    Request::new(ProtoCommand::Connect, Address::unspecified()).write_to_buf(&mut buf);
+    buf.extend_from_slice(PAYLOAD);
+    client.write_all(&buf).await.unwrap();
+
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"pass".to_vec()));
+    let conn = IncomingConnection::new(stream, auth);
+    let (conn, res) = conn.authenticate().await.unwrap();
+    assert!(res.unwrap());
+
+    let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+    let mut connect = connect
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap();
+
+    // the handshake, the sub-negotiation, the request and the payload all came in with the first read
+    let mut payload = vec![0; PAYLOAD.len()];
+    connect.read_exact(&mut payload).await.unwrap();
+    assert_eq!(payload, PAYLOAD);
+    assert_eq!(reads.load(Ordering::Relaxed), 1);
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use socks5_server::{
+    auth::{NoAuth, Password},
+    client::{state::NeedAuthenticate, ClientConnection, ReplyError},
+    connection::{
+        associate::{run_relay, UdpRelayConfig},
+        bind::BindAcceptor,
+    },
+    proto::{handshake::Method, Address, ProtocolError, Reply},
+    Command, Error, Failed,
+};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+};
+
+async fn client(server: SocketAddr) -> ClientConnection<NeedAuthenticate> {
+    ClientConnection::new(TcpStream::connect(server).await.unwrap())
+}
+
+#[tokio::test]
+async fn connect_tunnels_to_the_target() {
+    let target = common::tcp_echo().await;
+    let server = common::connect_server().await;
+
+    let (conn, ()) = client(server).await.authenticate(&NoAuth).await.unwrap();
+    let mut connect = conn.connect(Address::SocketAddress(target)).await.unwrap();
+
+    let Address::SocketAddress(bound) = connect.bound_addr() else {
+        unreachable!()
+    };
+    assert!(bound.ip().is_loopback());
+
+    connect.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    connect.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+}
+
+#[tokio::test]
+async fn password_is_checked_by_the_server() {
+    let server = common::serve(
+        Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec())) as Arc<_>,
+        |conn| async move {
+            let _ = conn.authenticate().await;
+        },
+    )
+    .await;
+
+    let right = Password::new(b"user".to_vec(), b"secret".to_vec());
+    let (_, accepted) = client(server).await.authenticate(&right).await.unwrap();
+    assert!(accepted.unwrap());
+
+    let wrong = Password::new(b"user".to_vec(), b"guess".to_vec());
+    let (_, accepted) = client(server).await.authenticate(&wrong).await.unwrap();
+    assert!(!accepted.unwrap());
+}
+
+#[tokio::test]
+async fn unacceptable_method_is_an_error() {
+    let server = common::serve(
+        Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec())) as Arc<_>,
+        |conn| async move {
+            let _ = conn.authenticate().await;
+        },
+    )
+    .await;
+
+    let Failed { error: err, .. } = client(server)
+        .await
+        .authenticate(&NoAuth)
+        .await
+        .unwrap_err();
+
+    let Error::Protocol(ProtocolError::NoAcceptableHandshakeMethod {
+        chosen_method,
+        methods,
+        ..
+    }) = err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(chosen_method, Method::UNACCEPTABLE);
+    assert_eq!(methods, [Method::NONE]);
+}
+
+#[tokio::test]
+async fn failure_reply_is_an_error() {
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, |conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let _ = connect
+            .reply(Reply::ConnectionRefused, Address::unspecified())
+            .await;
+    })
+    .await;
+
+    let (conn, ()) = client(server).await.authenticate(&NoAuth).await.unwrap();
+    let Failed { error: err, .. } = conn
+        .connect(Address::DomainAddress(b"example.com".to_vec(), 80))
+        .await
+        .unwrap_err();
+
+    let Error::Io(err) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    assert_eq!(
+        ReplyError::from_io_error(&err).unwrap().reply,
+        Reply::ConnectionRefused
+    );
+}
+
+#[tokio::test]
+async fn over_long_domain_is_an_error() {
+    let server = common::connect_server().await;
+
+    let (conn, ()) = client(server).await.authenticate(&NoAuth).await.unwrap();
+    let Failed { error: err, .. } = conn
+        .connect(Address::DomainAddress(vec![b'a'; 256], 80))
+        .await
+        .unwrap_err();
+
+    let Error::Io(err) = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn bind_reads_both_replies() {
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, |conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Bind(bind, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let acceptor = BindAcceptor::bind("127.0.0.1:0").await.unwrap();
+        let bound = Address::SocketAddress(acceptor.local_addr().unwrap());
+        let bind = bind.reply(Reply::Succeeded, bound).await.unwrap();
+
+        let (mut inbound, addr) = acceptor.accept().await.unwrap();
+        let mut bind = bind
+            .reply(Reply::Succeeded, Address::SocketAddress(addr))
+            .await
+            .unwrap();
+
+        let _ = io::copy_bidirectional(&mut bind, &mut inbound).await;
+    })
+    .await;
+
+    let (conn, ()) = client(server).await.authenticate(&NoAuth).await.unwrap();
+    let bind = conn.bind(Address::unspecified()).await.unwrap();
+
+    let Address::SocketAddress(bound) = *bind.bound_addr() else {
+        unreachable!()
+    };
+
+    let mut inbound = TcpStream::connect(bound).await.unwrap();
+    let mut bind = common::timeout(bind.wait()).await.unwrap();
+    assert_eq!(
+        bind.inbound_addr(),
+        &Address::SocketAddress(inbound.local_addr().unwrap())
+    );
+
+    inbound.write_all(b"from the peer").await.unwrap();
+    let mut buf = [0; 13];
+    bind.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"from the peer");
+
+    bind.write_all(b"to the peer").await.unwrap();
+    let mut buf = [0; 11];
+    inbound.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"to the peer");
+}
+
+#[tokio::test]
+async fn associate_relays_datagrams() {
+    let echo = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, |conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let _ = run_relay(associate, UdpRelayConfig::default()).await;
+    })
+    .await;
+
+    let (conn, ()) = client(server).await.authenticate(&NoAuth).await.unwrap();
+    let associate = conn.associate(Address::unspecified()).await.unwrap();
+    let socket = associate.bind_socket(1500).await.unwrap();
+
+    // packets from anyone but the relay are discarded
+    let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    stranger
+        .send_to(
+            b"\0\0\0\x01\x7f\0\0\x01\0\x01noise",
+            socket.local_addr().unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(
+        socket
+            .send_to(b"hello", Address::SocketAddress(echo))
+            .await
+            .unwrap(),
+        5
+    );
+
+    let (pkt, from) = common::timeout(socket.recv_from()).await.unwrap();
+    assert_eq!(&pkt[..], b"hello");
+    assert_eq!(from, Address::SocketAddress(echo));
+}
@@ -0,0 +1,219 @@
+//! A test harness shared by the integration tests: a scripted SOCKS5 client built on the protocol types, a server on an ephemeral port, and echo targets.
+
+// every test crate includes the whole harness, but uses only some of it
+#![allow(dead_code)]
+
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::state::NeedAuthenticate,
+    proto::{
+        handshake::{
+            password::{Request as PasswordRequest, Response as PasswordResponse},
+            Method, Request as HandshakeRequest, Response as HandshakeResponse,
+        },
+        Address, Command as ProtoCommand, Reply, Request, Response, UdpHeader,
+    },
+    Auth, Command, IncomingConnection, Server,
+};
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    time,
+};
+
+/// How long a step of a test may take before it is considered hung
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Awaits `fut`, panicking if it takes longer than [`TIMEOUT`].
+pub async fn timeout<F: Future>(fut: F) -> F::Output {
+    time::timeout(TIMEOUT, fut)
+        .await
+        .expect("timed out waiting on the future")
+}
+
+/// Creates a server without authentication on an ephemeral port of the loopback address, for tests accepting on it themselves.
+pub async fn server() -> Server<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    Server::new(listener, Arc::new(NoAuth) as Arc<_>)
+}
+
+/// Starts a server on an ephemeral port of the loopback address, handling every incoming connection with `handler` in a task of its own. Returns the address to reach the server on.
+pub async fn serve<A, F, Fut>(
+    auth: Arc<dyn Auth<Output = A> + Send + Sync>,
+    handler: F,
+) -> SocketAddr
+where
+    A: Send + 'static,
+    F: Fn(IncomingConnection<A, NeedAuthenticate>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let server = Server::new(
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap(),
+        auth,
+    );
+    let addr = server.local_addr().unwrap();
+    let handler = Arc::new(handler);
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(handler(conn));
+        }
+    });
+
+    addr
+}
+
+/// Starts a server without authentication relaying `Connect` commands to targets given as socket addresses, returning its address.
+pub async fn connect_server() -> SocketAddr {
+    serve(Arc::new(NoAuth) as Arc<_>, |conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(connect, Address::SocketAddress(addr)) = conn.wait().await.unwrap()
+        else {
+            unreachable!()
+        };
+
+        let mut target = TcpStream::connect(addr).await.unwrap();
+        let local = Address::SocketAddress(target.local_addr().unwrap());
+        let mut connect = connect.reply(Reply::Succeeded, local).await.unwrap();
+
+        let _ = io::copy_bidirectional(&mut connect, &mut target).await;
+    })
+    .await
+}
+
+/// Starts a TCP target echoing everything it receives on every connection, returning its address.
+pub async fn tcp_echo() -> SocketAddr {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut rx, mut tx) = stream.split();
+                let _ = io::copy(&mut rx, &mut tx).await;
+            });
+        }
+    });
+
+    addr
+}
+
+/// Starts a UDP target on `ip` echoing every datagram back to its source, returning its address.
+pub async fn udp_echo(ip: IpAddr) -> SocketAddr {
+    let socket = UdpSocket::bind(SocketAddr::new(ip, 0)).await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 65535];
+
+        while let Ok((len, src)) = socket.recv_from(&mut buf).await {
+            let _ = socket.send_to(&buf[..len], src).await;
+        }
+    });
+
+    addr
+}
+
+/// Encodes `pkt` behind the SOCKS5 UDP header of a datagram to `dst`, as a client sends it to a relay.
+pub fn encode_udp(pkt: &[u8], dst: SocketAddr) -> Vec<u8> {
+    encode_udp_with(pkt, &UdpHeader::new(0, Address::SocketAddress(dst)))
+}
+
+/// Encodes `pkt` behind `header`.
+pub fn encode_udp_with(pkt: &[u8], header: &UdpHeader) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    header.write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    buf.to_vec()
+}
+
+/// A scripted SOCKS5 client, driving the negotiation one step at a time so tests can stop or misbehave at any point
+pub struct Client {
+    pub stream: TcpStream,
+}
+
+impl Client {
+    /// Connects to the server at `addr`.
+    pub async fn connect(addr: SocketAddr) -> Self {
+        Self {
+            stream: timeout(TcpStream::connect(addr)).await.unwrap(),
+        }
+    }
+
+    /// Offers `methods` to the server, returning the one it chose.
+    pub async fn handshake(&mut self, methods: &[Method]) -> Method {
+        HandshakeRequest::new(methods.to_vec())
+            .write_to(&mut self.stream)
+            .await
+            .unwrap();
+
+        timeout(HandshakeResponse::read_from(&mut self.stream))
+            .await
+            .unwrap()
+            .method
+    }
+
+    /// Performs the username / password sub-negotiation, returning whether the server accepted the credentials.
+    pub async fn password(&mut self, username: &[u8], password: &[u8]) -> bool {
+        PasswordRequest::new(username.to_vec(), password.to_vec())
+            .write_to(&mut self.stream)
+            .await
+            .unwrap();
+
+        timeout(PasswordResponse::read_from(&mut self.stream))
+            .await
+            .unwrap()
+            .status
+    }
+
+    /// Sends a request and reads the reply of the server.
+    pub async fn request(&mut self, command: ProtoCommand, address: Address) -> Response {
+        Request::new(command, address)
+            .write_to(&mut self.stream)
+            .await
+            .unwrap();
+
+        timeout(Response::read_from(&mut self.stream))
+            .await
+            .unwrap()
+    }
+
+    /// Offers no authentication and sends a request, returning the reply of the server.
+    pub async fn no_auth_request(
+        addr: SocketAddr,
+        command: ProtoCommand,
+        address: Address,
+    ) -> (Self, Response) {
+        let mut client = Self::connect(addr).await;
+        assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+        let resp = client.request(command, address).await;
+        (client, resp)
+    }
+
+    /// Writes raw bytes, e.g. a malformed message.
+    pub async fn write(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).await.unwrap();
+    }
+
+    /// Reads exactly `len` raw bytes.
+    pub async fn read(&mut self, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        timeout(self.stream.read_exact(&mut buf)).await.unwrap();
+        buf
+    }
+
+    /// Reads until the server closes the connection, returning what was left unread.
+    pub async fn read_to_end(&mut self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let _ = timeout(self.stream.read_to_end(&mut buf)).await;
+        buf
+    }
+}
@@ -0,0 +1,214 @@
+mod common;
+
+use bytes::BytesMut;
+use socks5_server::{
+    connection::state::NeedAuthenticate,
+    proto::{
+        handshake::{Method, Request as HandshakeRequest},
+        Address, Command as ProtoCommand, Reply, Request,
+    },
+    tracker::ConnectionTracker,
+    Command, IncomingConnection, Server,
+};
+use std::time::Duration;
+use tokio::{io::AsyncReadExt, net::TcpStream};
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connects a client to `server` which already wrote the handshake and a request of `command`, returning the client alongside the accepted connection.
+async fn accept(
+    server: &Server<()>,
+    command: ProtoCommand,
+) -> (TcpStream, IncomingConnection<(), NeedAuthenticate>) {
+    let mut buf = BytesMut::new();
+    HandshakeRequest::new(vec![Method::NONE]).write_to_buf(&mut buf);
+    Request::new(command, Address::unspecified()).write_to_buf(&mut buf);
+
+    let client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    client.try_write(&buf).unwrap();
+
+    let (conn, _) = server.accept().await.unwrap();
+    (client, conn)
+}
+
+async fn assert_idle(tracker: &ConnectionTracker) {
+    assert!(tracker.await_idle_timeout(IDLE_TIMEOUT).await);
+    assert_eq!(tracker.count(), 0);
+}
+
+#[tokio::test]
+async fn dropped_before_authenticating() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+    assert_idle(&tracker).await;
+
+    let (_c1, conn1) = accept(&server, ProtoCommand::Connect).await;
+    let (_c2, conn2) = accept(&server, ProtoCommand::Connect).await;
+    assert_eq!(tracker.count(), 2);
+
+    drop(conn1);
+    assert_eq!(tracker.count(), 1);
+    drop(conn2);
+    assert_idle(&tracker).await;
+}
+
+#[tokio::test]
+async fn dropped_before_the_request() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+
+    let (_client, conn) = accept(&server, ProtoCommand::Connect).await;
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    assert_eq!(tracker.count(), 1);
+
+    drop(conn);
+    assert_idle(&tracker).await;
+}
+
+#[tokio::test]
+async fn dropped_before_the_reply() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+
+    for command in [
+        ProtoCommand::Connect,
+        ProtoCommand::Bind,
+        ProtoCommand::Associate,
+    ] {
+        let (_client, conn) = accept(&server, command).await;
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let command = conn.wait().await.unwrap();
+        assert_eq!(tracker.count(), 1);
+        drop(command);
+        assert_idle(&tracker).await;
+    }
+}
+
+#[tokio::test]
+async fn dropped_once_ready() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+
+    let (_client, conn) = accept(&server, ProtoCommand::Connect).await;
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+    let connect = connect
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap();
+    assert_eq!(tracker.count(), 1);
+    drop(connect);
+    assert_idle(&tracker).await;
+
+    let (_client, conn) = accept(&server, ProtoCommand::Bind).await;
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let Command::Bind(bind, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+    let bind = bind
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap();
+    assert_eq!(tracker.count(), 1);
+    let bind = bind
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap();
+    assert_eq!(tracker.count(), 1);
+    drop(bind);
+    assert_idle(&tracker).await;
+
+    let (_client, conn) = accept(&server, ProtoCommand::Associate).await;
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+    let associate = associate
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap();
+    assert_eq!(tracker.count(), 1);
+    drop(associate);
+    assert_idle(&tracker).await;
+}
+
+#[tokio::test]
+async fn failed_and_detached_connections_are_tracked_until_dropped() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+
+    // the client closes before sending anything
+    let client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (conn, _) = server.accept().await.unwrap();
+    drop(client);
+
+    // the connection is still open in the hands of the caller
+    let failed = conn.authenticate().await.unwrap_err();
+    assert!(failed.stream.is_some());
+    assert_eq!(tracker.count(), 1);
+    drop(failed);
+    assert_idle(&tracker).await;
+
+    let (mut client, conn) = accept(&server, ProtoCommand::Connect).await;
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+    let stream = connect.into_inner();
+    assert!(stream.guard().is_some());
+    assert_eq!(tracker.count(), 1);
+
+    // only the handshake response was sent before the stream was dropped
+    drop(stream);
+    assert_idle(&tracker).await;
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, [0x05, 0x00]);
+}
+
+#[tokio::test]
+async fn await_idle_waits_for_every_connection() {
+    let server = common::server().await;
+    let tracker = server.connection_tracker().clone();
+
+    let (_client, conn) = accept(&server, ProtoCommand::Connect).await;
+    assert!(!tracker.await_idle_timeout(Duration::from_millis(10)).await);
+
+    let task = tokio::spawn(async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let _ = conn.wait().await;
+    });
+
+    // the tracker outlives the server
+    drop(server);
+    assert_idle(&tracker).await;
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn tracker_can_be_shared_and_used_standalone() {
+    let tracker = ConnectionTracker::new();
+    let mut server1 = common::server().await;
+    let mut server2 = common::server().await;
+    server1.set_connection_tracker(tracker.clone());
+    server2.set_connection_tracker(tracker.clone());
+
+    let (_c1, conn1) = accept(&server1, ProtoCommand::Connect).await;
+    let (_c2, conn2) = accept(&server2, ProtoCommand::Connect).await;
+
+    // e.g. a task of a manual accept loop
+    let guard = tracker.track();
+    assert_eq!(tracker.count(), 3);
+    assert_eq!(server1.connection_tracker().count(), 3);
+
+    drop((conn1, conn2));
+    assert_eq!(tracker.count(), 1);
+    drop(guard);
+    assert_idle(&tracker).await;
+}
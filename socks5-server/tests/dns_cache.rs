@@ -0,0 +1,231 @@
+//! Exercises `CachingResolver` over a stub resolver counting its resolutions, with tokio's paused clock standing in for time.
+
+use async_trait::async_trait;
+use socks5_server::{
+    dns::{self, CachingResolver, CachingResolverStats, Resolver},
+    proto::Address,
+};
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::Semaphore, time};
+
+const ADDR: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+/// Resolves every name but `fail.test` to [`ADDR`] with the given record TTL, counting the resolutions. Resolutions wait for a permit of `gate` if set.
+struct Counting {
+    calls: AtomicUsize,
+    ttl: Option<Duration>,
+    gate: Option<Arc<Semaphore>>,
+}
+
+impl Counting {
+    fn new(ttl: Option<Duration>) -> Self {
+        Self {
+            calls: AtomicUsize::new(0),
+            ttl,
+            gate: None,
+        }
+    }
+
+    fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl Resolver for Counting {
+    async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+        self.resolve_with_ttl(name).await.map(|(addrs, _)| addrs)
+    }
+
+    async fn resolve_with_ttl(&self, name: &str) -> Result<(Vec<IpAddr>, Option<Duration>), Error> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+
+        if let Some(gate) = &self.gate {
+            let _permit = gate.acquire().await.unwrap();
+        }
+
+        if name == "fail.test" {
+            Err(Error::new(ErrorKind::NotFound, "no such name"))
+        } else {
+            Ok((vec![ADDR], self.ttl))
+        }
+    }
+}
+
+fn caching(inner: Counting, capacity: usize) -> CachingResolver<Counting> {
+    CachingResolver::new(
+        inner,
+        Duration::from_secs(60),
+        Duration::from_secs(5),
+        capacity,
+    )
+}
+
+#[tokio::test(start_paused = true)]
+async fn concurrent_lookups_resolve_once() {
+    let gate = Arc::new(Semaphore::new(0));
+    let resolver = Arc::new(caching(
+        Counting {
+            gate: Some(gate.clone()),
+            ..Counting::new(None)
+        },
+        16,
+    ));
+
+    let lookups = (0..8)
+        .map(|_| {
+            let resolver = resolver.clone();
+            tokio::spawn(async move { resolver.resolve("example.test").await })
+        })
+        .collect::<Vec<_>>();
+
+    // let every lookup reach the cache before the resolution finishes
+    tokio::task::yield_now().await;
+    time::sleep(Duration::from_millis(10)).await;
+    gate.add_permits(1);
+
+    for lookup in lookups {
+        assert_eq!(lookup.await.unwrap().unwrap(), [ADDR]);
+    }
+
+    assert_eq!(resolver.get_ref().calls(), 1);
+
+    let stats = resolver.stats();
+    assert_eq!((stats.hits, stats.misses, stats.coalesced), (0, 8, 7));
+}
+
+#[tokio::test(start_paused = true)]
+async fn cancelled_lookup_hands_over_to_a_waiting_one() {
+    let gate = Arc::new(Semaphore::new(0));
+    let resolver = Arc::new(caching(
+        Counting {
+            gate: Some(gate.clone()),
+            ..Counting::new(None)
+        },
+        16,
+    ));
+
+    let leader = {
+        let resolver = resolver.clone();
+        tokio::spawn(async move { resolver.resolve("example.test").await })
+    };
+    time::sleep(Duration::from_millis(10)).await;
+
+    let follower = {
+        let resolver = resolver.clone();
+        tokio::spawn(async move { resolver.resolve("example.test").await })
+    };
+    time::sleep(Duration::from_millis(10)).await;
+
+    leader.abort();
+    gate.add_permits(2);
+
+    assert_eq!(follower.await.unwrap().unwrap(), [ADDR]);
+    assert_eq!(resolver.get_ref().calls(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn entries_expire_after_their_ttl() {
+    let resolver = caching(Counting::new(None), 16);
+
+    resolver.resolve("example.test").await.unwrap();
+    time::advance(Duration::from_secs(59)).await;
+
+    let (_, remaining) = resolver.resolve_with_ttl("example.test").await.unwrap();
+    assert_eq!(remaining, Some(Duration::from_secs(1)));
+    assert_eq!(resolver.get_ref().calls(), 1);
+
+    time::advance(Duration::from_secs(1)).await;
+    resolver.resolve("example.test").await.unwrap();
+    assert_eq!(resolver.get_ref().calls(), 2);
+}
+
+#[tokio::test(start_paused = true)]
+async fn record_ttl_is_capped() {
+    // shorter than the cap, so the record TTL applies
+    let resolver = caching(Counting::new(Some(Duration::from_secs(2))), 16);
+
+    let (_, ttl) = resolver.resolve_with_ttl("example.test").await.unwrap();
+    assert_eq!(ttl, Some(Duration::from_secs(2)));
+
+    time::advance(Duration::from_secs(2)).await;
+    resolver.resolve("example.test").await.unwrap();
+    assert_eq!(resolver.get_ref().calls(), 2);
+
+    // longer than the cap, so the cap applies
+    let resolver = caching(Counting::new(Some(Duration::from_secs(3600))), 16);
+
+    let (_, ttl) = resolver.resolve_with_ttl("example.test").await.unwrap();
+    assert_eq!(ttl, Some(Duration::from_secs(60)));
+}
+
+#[tokio::test(start_paused = true)]
+async fn failures_are_cached_for_the_negative_ttl() {
+    let resolver = caching(Counting::new(None), 16);
+
+    assert!(resolver.resolve("fail.test").await.is_err());
+    assert!(resolver.resolve("fail.test").await.is_err());
+    assert_eq!(resolver.get_ref().calls(), 1);
+
+    time::advance(Duration::from_secs(5)).await;
+    assert!(resolver.resolve("fail.test").await.is_err());
+    assert_eq!(resolver.get_ref().calls(), 2);
+
+    assert_eq!(
+        resolver.stats(),
+        CachingResolverStats {
+            hits: 1,
+            misses: 2,
+            coalesced: 0,
+            errors: 2,
+            entries: 1,
+        }
+    );
+}
+
+#[tokio::test(start_paused = true)]
+async fn least_recently_used_entry_is_evicted() {
+    let resolver = caching(Counting::new(None), 2);
+
+    resolver.resolve("a.test").await.unwrap();
+    time::advance(Duration::from_secs(1)).await;
+    resolver.resolve("b.test").await.unwrap();
+    time::advance(Duration::from_secs(1)).await;
+
+    // `a.test` is now used more recently than `b.test`
+    resolver.resolve("a.test").await.unwrap();
+    resolver.resolve("c.test").await.unwrap();
+    assert_eq!(resolver.get_ref().calls(), 3);
+    assert_eq!(resolver.stats().entries, 2);
+
+    resolver.resolve("a.test").await.unwrap();
+    assert_eq!(resolver.get_ref().calls(), 3);
+
+    resolver.resolve("b.test").await.unwrap();
+    assert_eq!(resolver.get_ref().calls(), 4);
+}
+
+#[tokio::test]
+async fn ip_literals_keep_their_zone_without_resolving() {
+    let resolver = Counting::new(None);
+
+    let addr = Address::DomainAddress(b"fe80::1%3".to_vec(), 80);
+    let SocketAddr::V6(v6) = dns::resolve_address(&resolver, &addr).await.unwrap() else {
+        unreachable!()
+    };
+    assert_eq!((v6.port(), v6.scope_id()), (80, 3));
+
+    let addr = Address::DomainAddress(b"[2001:db8::1]".to_vec(), 443);
+    let resolved = dns::resolve_address(&resolver, &addr).await.unwrap();
+    assert_eq!(resolved, "[2001:db8::1]:443".parse().unwrap());
+
+    assert_eq!(resolver.calls(), 0);
+}
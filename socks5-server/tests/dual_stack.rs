@@ -0,0 +1,81 @@
+use socks5_server::{auth::NoAuth, Server};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn both_families_are_accepted_on_the_same_port() {
+    let server = Server::bind_dual_stack(0, Arc::new(NoAuth) as Arc<_>)
+        .await
+        .unwrap();
+
+    let [v4, v6] = server.get_ref().local_addrs().unwrap();
+    assert_eq!(v4.ip(), Ipv4Addr::UNSPECIFIED);
+    assert_eq!(v6.ip(), Ipv6Addr::UNSPECIFIED);
+    assert_eq!(v4.port(), v6.port());
+    assert_eq!(server.local_addr().unwrap(), v4);
+
+    let port = v4.port();
+    let _v4_client = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+        .await
+        .unwrap();
+    let (_, peer) = server.accept().await.unwrap();
+    assert!(peer.is_ipv4());
+
+    let _v6_client = TcpStream::connect((Ipv6Addr::LOCALHOST, port))
+        .await
+        .unwrap();
+    let (_, peer) = server.accept().await.unwrap();
+    assert!(peer.is_ipv6());
+}
+
+#[tokio::test]
+async fn neither_family_starves_the_other() {
+    let server = Server::bind_dual_stack(0, Arc::new(NoAuth) as Arc<_>)
+        .await
+        .unwrap();
+    let port = server.local_addr().unwrap().port();
+
+    // both listeners have a backlog of connections ready
+    let mut clients = Vec::new();
+
+    for _ in 0..4 {
+        clients.push(
+            TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+                .await
+                .unwrap(),
+        );
+        clients.push(
+            TcpStream::connect((Ipv6Addr::LOCALHOST, port))
+                .await
+                .unwrap(),
+        );
+    }
+
+    let mut peers = Vec::new();
+
+    for _ in 0..4 {
+        let (_, peer) = server.accept().await.unwrap();
+        peers.push(peer);
+    }
+
+    assert_eq!(peers.iter().filter(|peer| peer.is_ipv4()).count(), 2);
+    assert_eq!(peers.iter().filter(|peer| peer.is_ipv6()).count(), 2);
+}
+
+#[tokio::test]
+async fn v6_listener_is_v6_only() {
+    let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .only_v6(false)
+        .bind_dual_stack(0)
+        .await
+        .unwrap();
+
+    let (_, v6) = server.get_ref().get_ref();
+    assert!(socket2::SockRef::from(v6).only_v6().unwrap());
+
+    let addrs: [SocketAddr; 2] = server.get_ref().local_addrs().unwrap();
+    assert!(addrs[0].is_ipv4() && addrs[1].is_ipv6());
+}
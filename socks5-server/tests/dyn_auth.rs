@@ -0,0 +1,118 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::{DynOutput, NoAuth, Password},
+    proto::{
+        handshake::{password::Error as PasswordError, Method},
+        Address, Command as ProtoCommand, Reply, Request, Response,
+    },
+    Command, DynServer, Server,
+};
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, UnboundedReceiver},
+};
+
+/// Whether an output of a server configured with either [`NoAuth`] or [`Password`] lets the client in
+fn allowed(output: DynOutput) -> bool {
+    match output.downcast::<Result<bool, PasswordError>>() {
+        Ok(res) => matches!(*res, Ok(true)),
+        Err(output) => output.is::<()>(),
+    }
+}
+
+/// Creates the server from the configured credentials, as a server loading its configuration at runtime would.
+async fn server(credentials: Option<(&[u8], &[u8])>) -> DynServer {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+
+    match credentials {
+        Some((username, password)) => Server::new_dyn(
+            listener,
+            Password::new(username.to_vec(), password.to_vec()),
+        ),
+        None => Server::new_dyn(listener, NoAuth),
+    }
+}
+
+/// Runs `server`, reporting whether each client was let in by its output.
+fn run(server: DynServer) -> (SocketAddr, UnboundedReceiver<bool>) {
+    let addr = server.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                let Ok((conn, output)) = conn.authenticate().await else {
+                    return;
+                };
+                let allowed = allowed(output);
+                let _ = tx.send(allowed);
+
+                let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+                    unreachable!()
+                };
+
+                let reply = if allowed {
+                    Reply::Succeeded
+                } else {
+                    Reply::ConnectionNotAllowed
+                };
+                let _ = connect.reply(reply, Address::unspecified()).await;
+            });
+        }
+    });
+
+    (addr, rx)
+}
+
+async fn connect(client: &mut Client) -> Reply {
+    let req = Request::new(ProtoCommand::Connect, Address::unspecified());
+    req.write_to(&mut client.stream).await.unwrap();
+    common::timeout(Response::read_from(&mut client.stream))
+        .await
+        .unwrap()
+        .reply
+}
+
+#[tokio::test]
+async fn no_auth_is_selected_at_runtime() {
+    let (addr, mut outcomes) = run(server(None).await);
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    assert_eq!(connect(&mut client).await, Reply::Succeeded);
+    assert!(common::timeout(outcomes.recv()).await.unwrap());
+}
+
+#[tokio::test]
+async fn password_is_selected_at_runtime() {
+    let (addr, mut outcomes) = run(server(Some((b"user", b"secret"))).await);
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(
+        client.handshake(&[Method::NONE]).await,
+        Method::UNACCEPTABLE
+    );
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(!client.password(b"user", b"guess").await);
+    assert_eq!(connect(&mut client).await, Reply::ConnectionNotAllowed);
+    assert!(!common::timeout(outcomes.recv()).await.unwrap());
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(client.password(b"user", b"secret").await);
+    assert_eq!(connect(&mut client).await, Reply::Succeeded);
+    assert!(common::timeout(outcomes.recv()).await.unwrap());
+}
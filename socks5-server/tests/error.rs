@@ -0,0 +1,96 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    proto::{handshake::Method, Address, ProtocolError, Reply, Response},
+    Error, Failed,
+};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    sync::Arc,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::oneshot,
+};
+
+#[tokio::test]
+async fn failed_step_recovers_the_stream() {
+    let (tx, rx) = oneshot::channel();
+    let tx = std::sync::Mutex::new(Some(tx));
+
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let tx = tx.lock().unwrap().take().unwrap();
+
+        async move {
+            let (conn, ()) = conn.authenticate().await.unwrap();
+            let failed = conn.wait().await.unwrap_err();
+            let _ = tx.send(failed.error.kind());
+
+            // the client can still be told why before the connection is closed
+            let mut stream = failed.into_stream().unwrap();
+            let _ = Response::new(Reply::CommandNotSupported, Address::unspecified())
+                .write_to(stream.get_mut())
+                .await;
+        }
+    })
+    .await;
+
+    let mut client = Client::connect(server).await;
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+
+    // a request with an unknown command
+    client
+        .write(&[0x05, 0x7f, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await;
+    assert_eq!(common::timeout(rx).await.unwrap(), ErrorKind::InvalidData);
+
+    let resp = common::timeout(Response::read_from(&mut client.stream))
+        .await
+        .unwrap();
+    assert_eq!(resp.reply, Reply::CommandNotSupported);
+}
+
+#[test]
+fn conversions_keep_the_error_kind() {
+    let failed = Failed::<()>::new(IoError::from(ErrorKind::ConnectionReset), ());
+    assert_eq!(IoError::from(failed).kind(), ErrorKind::ConnectionReset);
+
+    let err = Error::from(ProtocolError::ProtocolVersion { version: 0x04 });
+    assert!(matches!(err, Error::Protocol(_)));
+    assert_eq!(IoError::from(err).kind(), ErrorKind::InvalidData);
+
+    assert_eq!(IoError::from(Error::Timeout).kind(), ErrorKind::TimedOut);
+    assert_eq!(
+        IoError::from(Error::AuthFailed).kind(),
+        ErrorKind::PermissionDenied
+    );
+
+    let failed = Failed::new(Error::PolicyDenied, ());
+    assert_eq!(failed.to_string(), "denied by policy");
+    assert_eq!(Error::from(failed).kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn shutdown_and_err_closes_the_stream() {
+    let (stream, mut peer) = tokio::io::duplex(64);
+    let mut failed = Failed::new(Error::PolicyDenied, stream);
+
+    failed.stream().unwrap().write_all(b"bye").await.unwrap();
+    assert!(matches!(
+        failed.shutdown_and_err().await,
+        Error::PolicyDenied
+    ));
+
+    // the peer sees what was written, then EOF
+    let mut buf = Vec::new();
+    common::timeout(peer.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"bye");
+
+    let failed = Failed::<()> {
+        error: Error::Timeout,
+        stream: None,
+    };
+    assert!(matches!(failed.into_error(), Error::Timeout));
+}
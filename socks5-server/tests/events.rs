@@ -0,0 +1,226 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::state::NeedAuthenticate,
+    event::{AuthOutcome, CloseReason, EventHandler, SessionStats},
+    proto::{handshake::Method, Address, Command as ProtoCommand, Reply},
+    Command, IncomingConnection, Server,
+};
+use std::{
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Accept,
+    Auth(Method, AuthOutcome),
+    Command(ProtoCommand, Address),
+    Reply(Reply),
+    Close(CloseReason, SessionStats),
+}
+
+/// Sends every event into a channel, checking that each carries the address of the client it was accepted from
+struct Recorder {
+    tx: UnboundedSender<(SocketAddr, Event)>,
+}
+
+impl EventHandler for Recorder {
+    fn on_accept(&self, peer: SocketAddr) {
+        let _ = self.tx.send((peer, Event::Accept));
+    }
+
+    fn on_auth(&self, peer: SocketAddr, method: Method, outcome: AuthOutcome) {
+        let _ = self.tx.send((peer, Event::Auth(method, outcome)));
+    }
+
+    fn on_command(&self, peer: SocketAddr, command: ProtoCommand, dest: &Address) {
+        let _ = self.tx.send((peer, Event::Command(command, dest.clone())));
+    }
+
+    fn on_reply(&self, peer: SocketAddr, reply: Reply) {
+        let _ = self.tx.send((peer, Event::Reply(reply)));
+    }
+
+    fn on_close(&self, peer: SocketAddr, reason: CloseReason, stats: SessionStats) {
+        let _ = self.tx.send((peer, Event::Close(reason, stats)));
+    }
+}
+
+/// Starts a server without authentication reporting to a [`Recorder`], handling every incoming connection with `handler`.
+async fn recording_server<F, Fut>(
+    handler: F,
+) -> (SocketAddr, UnboundedReceiver<(SocketAddr, Event)>)
+where
+    F: Fn(IncomingConnection<(), NeedAuthenticate>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let mut server = Server::new(
+        TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    server.set_event_handler(Arc::new(Recorder { tx }));
+
+    let addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(handler(conn));
+        }
+    });
+
+    (addr, rx)
+}
+
+/// Receives the events of one connection, up to and including its close.
+async fn session(
+    rx: &mut UnboundedReceiver<(SocketAddr, Event)>,
+    client: SocketAddr,
+) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    loop {
+        let (peer, event) = common::timeout(rx.recv()).await.unwrap();
+        assert_eq!(peer, client);
+
+        let closed = matches!(event, Event::Close(..));
+        events.push(event);
+
+        if closed {
+            return events;
+        }
+    }
+}
+
+#[tokio::test]
+async fn connect_session_reports_every_step() {
+    let target = common::tcp_echo().await;
+
+    let (server, mut rx) = recording_server(|conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(connect, Address::SocketAddress(addr)) = conn.wait().await.unwrap()
+        else {
+            unreachable!()
+        };
+
+        let mut target = TcpStream::connect(addr).await.unwrap();
+        let mut connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        let _ = io::copy_bidirectional(&mut connect, &mut target).await;
+    })
+    .await;
+
+    let (mut client, resp) = Client::no_auth_request(
+        server,
+        ProtoCommand::Connect,
+        Address::SocketAddress(target),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+    client.stream.shutdown().await.unwrap();
+    assert!(client.read_to_end().await.is_empty());
+
+    let mut events = session(&mut rx, client.stream.local_addr().unwrap()).await;
+
+    let Some(Event::Close(reason, stats)) = events.pop() else {
+        unreachable!()
+    };
+    assert_eq!(reason, CloseReason::Closed);
+    assert_eq!((stats.received, stats.sent), (5, 5));
+
+    assert_eq!(
+        events,
+        [
+            Event::Accept,
+            Event::Auth(Method::NONE, AuthOutcome::Completed),
+            Event::Command(ProtoCommand::Connect, Address::SocketAddress(target)),
+            Event::Reply(Reply::Succeeded),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unacceptable_method_closes_as_failed() {
+    let (server, mut rx) = recording_server(|conn| async move {
+        let _ = conn.authenticate().await;
+    })
+    .await;
+
+    let mut client = Client::connect(server).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::UNACCEPTABLE
+    );
+
+    let events = session(&mut rx, client.stream.local_addr().unwrap()).await;
+
+    assert!(matches!(
+        &events[..],
+        [
+            Event::Accept,
+            Event::Auth(Method::NONE, AuthOutcome::Unacceptable),
+            Event::Close(
+                CloseReason::Failed,
+                SessionStats {
+                    received: 0,
+                    sent: 0,
+                    ..
+                }
+            ),
+        ]
+    ));
+}
+
+#[tokio::test]
+async fn into_inner_closes_as_detached() {
+    let (server, mut rx) = recording_server(|conn| async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Bind(bind, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let bind = bind
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        // the application takes over the connection, e.g. to relay it on its own
+        let _stream = bind.into_inner();
+    })
+    .await;
+
+    let (client, resp) =
+        Client::no_auth_request(server, ProtoCommand::Bind, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let events = session(&mut rx, client.stream.local_addr().unwrap()).await;
+
+    assert!(matches!(
+        &events[..],
+        [
+            Event::Accept,
+            Event::Auth(Method::NONE, AuthOutcome::Completed),
+            Event::Command(ProtoCommand::Bind, _),
+            Event::Reply(Reply::Succeeded),
+            Event::Close(CloseReason::Detached, _),
+        ]
+    ));
+}
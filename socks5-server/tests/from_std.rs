@@ -0,0 +1,35 @@
+use socks5_server::{auth::NoAuth, Server};
+use std::{
+    net::{Ipv4Addr, TcpListener, TcpStream as StdTcpStream},
+    sync::Arc,
+};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn blocking_listener_is_served() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // left in blocking mode on purpose
+    let server = Server::from_std(listener, Arc::new(NoAuth) as Arc<_>).unwrap();
+    assert_eq!(server.local_addr().unwrap(), addr);
+
+    let client = TcpStream::connect(addr).await.unwrap();
+    let (_, peer) = server.accept().await.unwrap();
+    assert_eq!(peer, client.local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn listener_is_handed_back() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    let server = Server::from_std(listener, Arc::new(NoAuth) as Arc<_>).unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let (listener, _) = server.into_std().unwrap();
+    assert_eq!(listener.local_addr().unwrap(), addr);
+    listener.set_nonblocking(false).unwrap();
+
+    let client = StdTcpStream::connect(addr).unwrap();
+    let (_, peer) = listener.accept().unwrap();
+    assert_eq!(peer, client.local_addr().unwrap());
+}
@@ -0,0 +1,123 @@
+use socks5_server::{
+    auth::{NoAuth, Password},
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, ProtocolError, Reply, Request, Response,
+    },
+    Command, Error, IncomingConnection,
+};
+use std::{
+    io::Error as IoError,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{self, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+
+/// A stream wrapping another one, standing in for e.g. a TLS stream
+#[derive(Debug)]
+struct Wrapped(DuplexStream);
+
+impl AsyncRead for Wrapped {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Wrapped {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+fn pair() -> (Wrapped, DuplexStream) {
+    let (server, client) = io::duplex(4096);
+    (Wrapped(server), client)
+}
+
+#[tokio::test]
+async fn connect_over_a_wrapped_stream() {
+    let (stream, mut client) = pair();
+    let conn = IncomingConnection::new(stream, Arc::new(NoAuth) as Arc<_>);
+
+    let server = tokio::spawn(async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Connect(connect, addr) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(addr, Address::DomainAddress(b"example.com".to_vec(), 80));
+
+        let mut connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        // the ready command relays over the wrapped stream
+        let mut buf = [0; 4];
+        connect.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        connect.write_all(b"pong").await.unwrap();
+
+        let _wrapped: Wrapped = connect.into_inner().into_inner();
+    });
+
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut client)
+        .await
+        .unwrap();
+    let resp = HandshakeResponse::read_from(&mut client).await.unwrap();
+    assert_eq!(resp.method, Method::NONE);
+
+    let dst = Address::DomainAddress(b"example.com".to_vec(), 80);
+    Request::new(ProtoCommand::Connect, dst)
+        .write_to(&mut client)
+        .await
+        .unwrap();
+    let resp = Response::read_from(&mut client).await.unwrap();
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"pong");
+
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_negotiation_returns_the_wrapped_stream() {
+    let (stream, mut client) = pair();
+    let auth = Password::new(b"user".to_vec(), b"secret".to_vec());
+    let conn = IncomingConnection::new(stream, Arc::new(auth) as Arc<_>);
+
+    client.write_all(&[0x04, 0x01, 0x00]).await.unwrap();
+
+    let failed = conn.authenticate().await.unwrap_err();
+    assert!(matches!(
+        failed.error,
+        Error::Protocol(ProtocolError::ProtocolVersion { version: 0x04 })
+    ));
+
+    // the stream handed back is the one given, still usable
+    let mut stream = failed.into_stream().unwrap().into_parts().0;
+    stream.write_all(b"bye").await.unwrap();
+    let mut buf = [0; 3];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"bye");
+}
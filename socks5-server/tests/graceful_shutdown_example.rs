@@ -0,0 +1,103 @@
+//! Runs `examples/graceful_shutdown.rs` with a programmatic shutdown trigger instead of the standard input.
+
+#[allow(dead_code)]
+#[path = "../examples/graceful_shutdown.rs"]
+mod example;
+
+mod common;
+
+use common::Client;
+use socks5_server::proto::{Address, Command as ProtoCommand, Reply};
+use std::{net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::oneshot,
+    task::JoinHandle,
+};
+
+/// Starts the example server, returning its address, the shutdown trigger, and the task returning how many connections were aborted.
+async fn start(grace: Duration) -> (SocketAddr, oneshot::Sender<()>, JoinHandle<usize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (trigger, triggered) = oneshot::channel();
+
+    let task = tokio::spawn(async move {
+        let shutdown = async {
+            let _ = triggered.await;
+        };
+
+        example::serve(listener, shutdown, grace).await.unwrap()
+    });
+
+    (addr, trigger, task)
+}
+
+/// Opens a `Connect` tunnel to a TCP echo target through the server at `addr`.
+async fn tunnel(addr: SocketAddr) -> Client {
+    let target = common::tcp_echo().await;
+    let (client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(target)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    client
+}
+
+async fn echo(client: &mut Client, pkt: &[u8]) {
+    client.write(pkt).await;
+    assert_eq!(client.read(pkt.len()).await, pkt);
+}
+
+#[tokio::test]
+async fn in_flight_relay_finishes_within_the_grace_period() {
+    let (addr, trigger, task) = start(Duration::from_secs(5)).await;
+
+    let mut client = tunnel(addr).await;
+    echo(&mut client, b"before").await;
+
+    trigger.send(()).unwrap();
+
+    // new connections are refused once the listener is gone
+    common::timeout(async {
+        while TcpStream::connect(addr).await.is_ok() {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await;
+
+    // while the tunnel keeps relaying
+    echo(&mut client, b"during").await;
+
+    client.stream.shutdown().await.unwrap();
+    assert!(client.read_to_end().await.is_empty());
+
+    assert_eq!(common::timeout(task).await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn stuck_relay_is_aborted_after_the_grace_period() {
+    let (addr, trigger, task) = start(Duration::from_millis(100)).await;
+
+    let mut client = tunnel(addr).await;
+    echo(&mut client, b"idle").await;
+
+    trigger.send(()).unwrap();
+    assert_eq!(common::timeout(task).await.unwrap(), 1);
+
+    // the tunnel is torn down by the abort
+    let mut buf = [0; 1];
+    let read = common::timeout(client.stream.read(&mut buf)).await;
+    assert!(matches!(read, Ok(0) | Err(_)));
+}
+
+#[tokio::test]
+async fn negotiating_connection_does_not_hold_up_shutdown() {
+    let (addr, trigger, task) = start(Duration::from_secs(5)).await;
+
+    // connected, but never sends the handshake
+    let mut client = Client::connect(addr).await;
+    tokio::task::yield_now().await;
+
+    trigger.send(()).unwrap();
+    assert_eq!(common::timeout(task).await.unwrap(), 0);
+    assert!(client.read_to_end().await.is_empty());
+}
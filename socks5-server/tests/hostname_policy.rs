@@ -0,0 +1,175 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    hostname::{HostnamePolicy, HostnameViolation},
+    proto::{Address, Command as ProtoCommand, Reply},
+    Command, Error, Server,
+};
+use std::{net::Ipv4Addr, sync::Arc};
+use tokio::net::TcpListener;
+
+fn domain(name: &[u8]) -> Address {
+    Address::DomainAddress(name.to_vec(), 80)
+}
+
+#[test]
+fn default_policy_allows_ldh_names_only() {
+    let policy = HostnamePolicy::default();
+
+    assert_eq!(policy.check(b"example.com"), Ok(()));
+    assert_eq!(policy.check(b"xn--bcher-kva.example-1.com."), Ok(()));
+    assert_eq!(policy.check(b"localhost"), Ok(()));
+    assert_eq!(policy.check(b"127.0.0.1"), Ok(()));
+
+    assert_eq!(
+        policy.check(b"_sip._udp.example.com"),
+        Err(HostnameViolation::InvalidCharacter)
+    );
+    assert_eq!(
+        policy.check(b"exa mple.com"),
+        Err(HostnameViolation::InvalidCharacter)
+    );
+    assert_eq!(
+        policy.check("bücher.example".as_bytes()),
+        Err(HostnameViolation::InvalidCharacter)
+    );
+    assert_eq!(
+        policy.check(b"-example.com"),
+        Err(HostnameViolation::HyphenAtLabelEdge)
+    );
+    assert_eq!(
+        policy.check(b"example-.com"),
+        Err(HostnameViolation::HyphenAtLabelEdge)
+    );
+    assert_eq!(
+        policy.check(b"example..com"),
+        Err(HostnameViolation::EmptyLabel)
+    );
+    assert_eq!(policy.check(b""), Err(HostnameViolation::EmptyLabel));
+    assert_eq!(policy.check(b"."), Err(HostnameViolation::EmptyLabel));
+}
+
+#[test]
+fn lengths_are_limited() {
+    let policy = HostnamePolicy {
+        max_len: 20,
+        max_label_len: 8,
+        ..HostnamePolicy::default()
+    };
+
+    assert_eq!(policy.check(b"12345678.example"), Ok(()));
+    assert_eq!(
+        policy.check(b"123456789.example"),
+        Err(HostnameViolation::LabelTooLong { len: 9, max: 8 })
+    );
+    assert_eq!(
+        policy.check(b"abc.abc.abc.abc.abc.abc"),
+        Err(HostnameViolation::TooLong { len: 23, max: 20 })
+    );
+
+    let label = [b'a'; 64];
+    assert_eq!(
+        HostnamePolicy::default().check(&label),
+        Err(HostnameViolation::LabelTooLong { len: 64, max: 63 })
+    );
+}
+
+#[test]
+fn underscores_and_utf8_are_opt_in() {
+    let policy = HostnamePolicy {
+        allow_underscore: true,
+        allow_utf8: true,
+        ..HostnamePolicy::default()
+    };
+
+    assert_eq!(policy.check(b"_sip._udp.example.com"), Ok(()));
+    assert_eq!(policy.check("bücher.example".as_bytes()), Ok(()));
+
+    // still no arbitrary bytes, nor punctuation
+    assert_eq!(
+        policy.check(b"b\xfccher.example"),
+        Err(HostnameViolation::InvalidCharacter)
+    );
+    assert_eq!(
+        policy.check(b"exa*mple.com"),
+        Err(HostnameViolation::InvalidCharacter)
+    );
+}
+
+#[test]
+fn ip_literals_are_rejected_if_configured() {
+    let policy = HostnamePolicy {
+        reject_ip_literals: true,
+        ..HostnamePolicy::default()
+    };
+
+    assert_eq!(
+        policy.check(b"127.0.0.1"),
+        Err(HostnameViolation::IpLiteral)
+    );
+    assert_eq!(policy.check(b"::1"), Err(HostnameViolation::IpLiteral));
+    assert_eq!(policy.check(b"[::1]"), Err(HostnameViolation::IpLiteral));
+    assert_eq!(policy.check(b"127.0.0.1.example"), Ok(()));
+}
+
+#[tokio::test]
+async fn wait_rejects_names_violating_the_policy() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_hostname_policy(HostnamePolicy::default());
+    let addr = server.local_addr().unwrap();
+
+    let handler = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        conn.wait().await.map(|_| ()).unwrap_err()
+    });
+
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, domain(b"bad_name.example")).await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+
+    let failed = common::timeout(handler).await.unwrap();
+    assert!(failed.stream.is_some());
+
+    let Error::InvalidHostname { name, violation } = failed.error else {
+        panic!("unexpected error {:?}", failed.error)
+    };
+    assert_eq!(name, b"bad_name.example");
+    assert_eq!(violation, HostnameViolation::InvalidCharacter);
+
+    drop(failed.stream);
+    assert!(client.read_to_end().await.is_empty());
+}
+
+#[tokio::test]
+async fn names_are_passed_through_without_a_policy() {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let tx = std::sync::Mutex::new(Some(tx));
+
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let tx = tx.lock().unwrap().take().unwrap();
+
+        async move {
+            let (conn, ()) = conn.authenticate().await.unwrap();
+
+            let Command::Connect(connect, addr) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let _ = tx.send(addr);
+            let _ = connect
+                .reply(Reply::Succeeded, Address::unspecified())
+                .await;
+        }
+    })
+    .await;
+
+    let name = b"-not..a_valid name";
+    let (_client, resp) =
+        Client::no_auth_request(server, ProtoCommand::Connect, domain(name)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    assert_eq!(common::timeout(rx).await.unwrap(), domain(name));
+}
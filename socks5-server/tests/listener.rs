@@ -0,0 +1,141 @@
+mod common;
+
+use async_trait::async_trait;
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    listener::Listener,
+    proto::{handshake::Method, Address, Command as ProtoCommand, Reply},
+    Command, Server,
+};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{Ipv4Addr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{self, AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf},
+    net::{TcpListener, TcpStream},
+};
+
+const GREETING: &[u8] = b"HELLO\n";
+
+/// A stream set up by [`Greeting`], standing in for e.g. a TLS stream
+#[derive(Debug)]
+struct Greeted(TcpStream);
+
+impl AsyncRead for Greeted {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Greeted {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// A listener expecting a greeting before the SOCKS5 negotiation, standing in for e.g. a TLS listener
+#[derive(Debug)]
+struct Greeting(TcpListener);
+
+#[async_trait]
+impl Listener for Greeting {
+    type Stream = Greeted;
+
+    async fn accept(&self) -> Result<(Self::Stream, SocketAddr), IoError> {
+        let (mut stream, addr) = self.0.accept().await?;
+
+        let mut buf = [0; GREETING.len()];
+        stream.read_exact(&mut buf).await?;
+
+        if buf != GREETING {
+            return Err(IoError::new(ErrorKind::InvalidData, "unexpected greeting"));
+        }
+
+        Ok((Greeted(stream), addr))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.0.local_addr()
+    }
+}
+
+async fn server() -> Server<(), Greeting> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    Server::new(Greeting(listener), Arc::new(NoAuth) as Arc<_>)
+}
+
+#[tokio::test]
+async fn connections_are_accepted_from_the_listener() {
+    let server = server().await;
+    let addr = server.local_addr().unwrap();
+
+    let task = tokio::spawn(async move {
+        let (conn, peer) = server.accept().await.unwrap();
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Connect(connect, dst) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(dst, Address::DomainAddress(b"example.com".to_vec(), 443));
+
+        let connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        // the ready command is carried over the stream of the listener
+        let (mut rx, mut tx) = io::split(connect);
+        let _ = io::copy(&mut rx, &mut tx).await;
+
+        peer
+    });
+
+    let mut client = Client::connect(addr).await;
+    client.write(GREETING).await;
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    let resp = client
+        .request(
+            ProtoCommand::Connect,
+            Address::DomainAddress(b"example.com".to_vec(), 443),
+        )
+        .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+
+    let local = client.stream.local_addr().unwrap();
+    drop(client);
+    assert_eq!(task.await.unwrap(), local);
+}
+
+#[tokio::test]
+async fn listener_is_reachable_through_the_server() {
+    let mut server = server().await;
+    let addr = server.get_ref().0.local_addr().unwrap();
+    assert_eq!(server.local_addr().unwrap(), addr);
+    assert_eq!(server.get_mut().local_addr().unwrap(), addr);
+
+    let (Greeting(listener), _) = server.into_inner();
+    assert_eq!(listener.local_addr().unwrap(), addr);
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    tracker::{LimitReached, OnLimit},
+    Server,
+};
+use std::{
+    future,
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, time};
+
+async fn server(max: usize, on_limit: OnLimit) -> Arc<Server<()>> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_max_connections(max, on_limit);
+    Arc::new(server)
+}
+
+#[tokio::test]
+async fn accept_waits_for_a_free_slot() {
+    let server = server(1, OnLimit::Wait).await;
+    let addr = server.local_addr().unwrap();
+
+    let _first_client = Client::connect(addr).await;
+    let (first, _) = server.accept().await.unwrap();
+
+    let _second_client = Client::connect(addr).await;
+
+    let task = tokio::spawn({
+        let server = server.clone();
+        async move { server.accept().await.map(|_| ()) }
+    });
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!task.is_finished());
+
+    // releasing the slot while accept is parked on it
+    drop(first);
+    common::timeout(task).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn poll_accept_is_woken_by_a_free_slot() {
+    let server = server(1, OnLimit::Wait).await;
+    let addr = server.local_addr().unwrap();
+
+    let _first_client = Client::connect(addr).await;
+    let (first, _) = future::poll_fn(|cx| server.poll_accept(cx)).await.unwrap();
+
+    let task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            future::poll_fn(|cx| server.poll_accept(cx))
+                .await
+                .map(|_| ())
+        }
+    });
+
+    // woken by the released slot, it then waits on the listener with the slot reserved
+    time::sleep(Duration::from_millis(50)).await;
+    drop(first);
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!task.is_finished());
+
+    let _second_client = Client::connect(addr).await;
+    common::timeout(task).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn connections_over_the_limit_are_rejected() {
+    let server = server(1, OnLimit::Reject).await;
+    let addr = server.local_addr().unwrap();
+
+    let _first_client = Client::connect(addr).await;
+    let (first, _) = server.accept().await.unwrap();
+
+    let mut second_client = Client::connect(addr).await;
+    let err = server.accept().await.unwrap_err();
+    assert!(LimitReached::is(&err));
+    assert!(second_client.read_to_end().await.is_empty());
+
+    // the server keeps accepting once a slot is free
+    drop(first);
+    let _third_client = Client::connect(addr).await;
+    server.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_and_detached_connections_keep_their_slot() {
+    let server = server(1, OnLimit::Reject).await;
+    let addr = server.local_addr().unwrap();
+
+    // the client closes before sending anything, the failed connection is handed back
+    drop(Client::connect(addr).await);
+    let (conn, _) = server.accept().await.unwrap();
+    let failed = conn.authenticate().await.unwrap_err();
+
+    let _client = Client::connect(addr).await;
+    assert!(LimitReached::is(&server.accept().await.unwrap_err()));
+
+    // so is the stream taken out of it
+    let stream = failed.into_stream().unwrap().into_inner();
+    let _client = Client::connect(addr).await;
+    assert!(LimitReached::is(&server.accept().await.unwrap_err()));
+
+    drop(stream);
+    let _client = Client::connect(addr).await;
+    server.accept().await.unwrap();
+}
+
+#[tokio::test]
+async fn limit_is_never_exceeded() {
+    const MAX: usize = 3;
+    const CLIENTS: usize = 20;
+
+    let server = server(MAX, OnLimit::Wait).await;
+    let addr = server.local_addr().unwrap();
+    let active = Arc::new(AtomicUsize::new(0));
+    let peak = Arc::new(AtomicUsize::new(0));
+
+    let serve = tokio::spawn({
+        let (active, peak) = (active.clone(), peak.clone());
+
+        async move {
+            let mut handlers = Vec::new();
+
+            for _ in 0..CLIENTS {
+                let (conn, _) = server.accept().await.unwrap();
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                peak.fetch_max(now, Ordering::SeqCst);
+
+                let active = active.clone();
+                handlers.push(tokio::spawn(async move {
+                    time::sleep(Duration::from_millis(10)).await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    drop(conn);
+                }));
+            }
+
+            for handler in handlers {
+                handler.await.unwrap();
+            }
+        }
+    });
+
+    let mut clients = Vec::new();
+
+    for _ in 0..CLIENTS {
+        clients.push(Client::connect(addr).await);
+    }
+
+    common::timeout(serve).await.unwrap();
+    assert_eq!(peak.load(Ordering::SeqCst), MAX);
+}
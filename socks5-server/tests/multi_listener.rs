@@ -0,0 +1,70 @@
+use socks5_server::{auth::NoAuth, listener::MultiListener, shutdown::ServerShutdown, Server};
+use std::{net::Ipv4Addr, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn server(count: usize) -> Server<(), MultiListener> {
+    let mut listeners = Vec::new();
+
+    for _ in 0..count {
+        listeners.push(TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap());
+    }
+
+    Server::with_listeners(listeners, Arc::new(NoAuth) as Arc<_>)
+}
+
+#[tokio::test]
+async fn connections_report_their_listener() {
+    let server = server(3).await;
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 3);
+    assert_eq!(server.local_addr().unwrap(), addrs[0]);
+
+    for (idx, addr) in addrs.iter().enumerate().rev() {
+        let client = TcpStream::connect(addr).await.unwrap();
+        let (_, peer, accepted) = server.accept_indexed().await.unwrap();
+        assert_eq!(accepted, idx);
+        assert_eq!(peer, client.local_addr().unwrap());
+    }
+}
+
+#[tokio::test]
+async fn no_listener_starves_the_others() {
+    let server = server(2).await;
+    let addrs = server.local_addrs().unwrap();
+
+    // both listeners have a backlog of connections ready
+    let mut clients = Vec::new();
+
+    for _ in 0..4 {
+        for addr in &addrs {
+            clients.push(TcpStream::connect(addr).await.unwrap());
+        }
+    }
+
+    let mut accepted = [0; 2];
+
+    for _ in 0..4 {
+        let (_, _, idx) = server.accept_indexed().await.unwrap();
+        accepted[idx] += 1;
+    }
+
+    assert_eq!(accepted, [2, 2]);
+}
+
+#[tokio::test]
+async fn accept_indexed_stops_on_shutdown() {
+    let server = server(2).await;
+    let _client = TcpStream::connect(server.local_addrs().unwrap()[1])
+        .await
+        .unwrap();
+
+    server.shutdown();
+    let err = server.accept_indexed().await.unwrap_err();
+    assert!(ServerShutdown::is(&err));
+}
+
+#[test]
+#[should_panic]
+fn listeners_must_not_be_empty() {
+    let _ = Server::with_listeners(Vec::new(), Arc::new(NoAuth) as Arc<_>);
+}
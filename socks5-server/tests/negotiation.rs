@@ -0,0 +1,353 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::{NoAuth, Password},
+    connection::state::NeedAuthenticate,
+    proto::{handshake::Method, Address, Command as ProtoCommand, ProtocolError, Reply},
+    test_util::{self, DuplexStream},
+    Command, Error, IncomingConnection,
+};
+use std::{io::ErrorKind, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{oneshot, Notify},
+    task::JoinHandle,
+    time,
+};
+
+/// A `Connect` request to `127.0.0.1:80`
+const CONNECT_REQUEST: &[u8] = &[0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1, 0, 80];
+
+/// Negotiates `conn` in a task of its own, returning the outcome. The connection is shut down on failure.
+fn negotiate<A>(
+    conn: IncomingConnection<A, NeedAuthenticate, DuplexStream>,
+) -> JoinHandle<Result<A, Error>>
+where
+    A: Send + 'static,
+{
+    tokio::spawn(async move {
+        let (conn, output) = match conn.authenticate().await {
+            Ok(res) => res,
+            Err(failed) => return Err(failed.shutdown_and_err().await),
+        };
+
+        match conn.wait().await {
+            Ok(_) => Ok(output),
+            Err(failed) => Err(failed.shutdown_and_err().await),
+        }
+    })
+}
+
+#[tokio::test]
+async fn no_auth_connect_relays_data() {
+    let target = common::tcp_echo().await;
+    let server = common::connect_server().await;
+
+    let (mut client, resp) = Client::no_auth_request(
+        server,
+        ProtoCommand::Connect,
+        Address::SocketAddress(target),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(bound) = resp.address else {
+        unreachable!()
+    };
+    assert!(bound.ip().is_loopback());
+
+    client.write(b"hello through the proxy").await;
+    assert_eq!(client.read(23).await, b"hello through the proxy");
+
+    // the tunnel is torn down in both directions once the client is done
+    client.stream.shutdown().await.unwrap();
+    assert!(client.read_to_end().await.is_empty());
+}
+
+#[tokio::test]
+async fn correct_password_is_accepted() {
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec()));
+    let (conn, mut client) = test_util::incoming(auth);
+    let outcome = negotiate(conn);
+
+    client.handshake(&[Method::NONE, Method::PASSWORD]);
+    client.expect_method(Method::PASSWORD).await;
+    client.password(b"user", b"secret");
+    client.expect_password_status(true).await;
+
+    client.data(CONNECT_REQUEST).send().await.unwrap();
+    assert!(common::timeout(outcome).await.unwrap().unwrap().unwrap());
+}
+
+#[tokio::test]
+async fn wrong_password_is_rejected() {
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec()));
+    let (conn, mut client) = test_util::incoming(auth);
+    let outcome = negotiate(conn);
+
+    // the whole negotiation is pipelined, the server answers step by step
+    client
+        .handshake(&[Method::PASSWORD])
+        .password(b"user", b"guess")
+        .data(CONNECT_REQUEST);
+    client.expect_method(Method::PASSWORD).await;
+    client.expect_password_status(false).await;
+
+    // the outcome of the authentication is left to the server to act on
+    assert!(!common::timeout(outcome).await.unwrap().unwrap().unwrap());
+    test_util::assert_password_status(&client.received()[2..], false);
+}
+
+#[tokio::test]
+async fn unacceptable_methods_are_refused() {
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    let outcome = negotiate(conn);
+
+    client.handshake(&[Method::PASSWORD, Method(0x80)]);
+    client.expect_method(Method::UNACCEPTABLE).await;
+    client.expect_eof().await;
+
+    let err = common::timeout(outcome).await.unwrap().unwrap_err();
+    let Error::Protocol(ProtocolError::NoAcceptableHandshakeMethod {
+        chosen_method,
+        methods,
+        ..
+    }) = err
+    else {
+        panic!("unexpected error: {err}");
+    };
+    assert_eq!(chosen_method, Method::NONE);
+    assert_eq!(methods, [Method::PASSWORD, Method(0x80)]);
+}
+
+/// Whether an error is the one expected for a malformed request
+type Expected = fn(&ProtocolError) -> bool;
+
+#[tokio::test]
+async fn malformed_requests_are_reported() {
+    let requests: [(&[u8], Expected); 3] = [
+        (&[0x04, 0x01, 0x00, 0x01], |err| {
+            matches!(err, ProtocolError::ProtocolVersion { version: 0x04 })
+        }),
+        (&[0x05, 0x7f, 0x00, 0x01], |err| {
+            matches!(err, ProtocolError::InvalidCommand { command: 0x7f, .. })
+        }),
+        (&[0x05, 0x01, 0x00, 0x7f], |err| {
+            matches!(
+                err,
+                ProtocolError::InvalidAddressTypeInRequest {
+                    address_type: 0x7f,
+                    ..
+                }
+            )
+        }),
+    ];
+
+    for (request, expected) in requests {
+        let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+        let outcome = negotiate(conn);
+
+        client.handshake(&[Method::NONE]).data(request);
+        client.expect_method(Method::NONE).await;
+        client.expect_eof().await;
+
+        match common::timeout(outcome).await.unwrap() {
+            Err(Error::Protocol(err)) if expected(&err) => {}
+            res => panic!("unexpected outcome for {request:02x?}: {res:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn client_disconnecting_mid_handshake_is_an_eof() {
+    // 2 methods announced, only 1 sent
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    let outcome = negotiate(conn);
+
+    client.data(&[0x05, 0x02, 0x00]).send().await.unwrap();
+    drop(client);
+
+    match common::timeout(outcome).await.unwrap() {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+        res => panic!("unexpected outcome: {res:?}"),
+    }
+
+    // and between the handshake and the request
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    let outcome = negotiate(conn);
+
+    client.handshake(&[Method::NONE]).data(&[0x05, 0x01]);
+    client.expect_method(Method::NONE).await;
+    drop(client);
+
+    match common::timeout(outcome).await.unwrap() {
+        Err(err) => assert_eq!(err.kind(), ErrorKind::UnexpectedEof),
+        res => panic!("unexpected outcome: {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn dropped_waits_resume_without_losing_bytes() {
+    let dropped = Arc::new(Notify::new());
+    let (tx, rx) = oneshot::channel();
+    let (mut conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+
+    tokio::spawn({
+        let dropped = dropped.clone();
+
+        async move {
+            // the first waits are dropped with only part of the message received
+            let timeout = Duration::from_millis(50);
+            assert!(time::timeout(timeout, conn.wait_handshake()).await.is_err());
+            dropped.notify_one();
+            common::timeout(conn.wait_handshake()).await.unwrap();
+
+            let (mut conn, ()) = conn.authenticate().await.unwrap();
+            assert!(time::timeout(timeout, conn.wait_request()).await.is_err());
+            dropped.notify_one();
+            common::timeout(conn.wait_request()).await.unwrap();
+
+            let Command::Connect(connect, addr) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let _ = connect.reply(Reply::Succeeded, addr.clone()).await;
+            let _ = tx.send(addr);
+        }
+    });
+
+    client.data(&[0x05, 0x01]).send().await.unwrap();
+    common::timeout(dropped.notified()).await;
+    client.data(&[0x00]);
+    client.expect_method(Method::NONE).await;
+
+    client
+        .data(&[0x05, 0x01, 0x00, 0x01, 127, 0])
+        .send()
+        .await
+        .unwrap();
+    common::timeout(dropped.notified()).await;
+    client.data(&[0, 1, 0x1f, 0x90]);
+
+    let addr = Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 8080)));
+    assert_eq!(client.expect_reply(Reply::Succeeded).await, addr);
+    assert_eq!(common::timeout(rx).await.unwrap(), addr);
+}
+
+#[tokio::test]
+async fn bind_replies_are_sent_over_memory() {
+    let (bind, mut client) = test_util::bind();
+    let first = Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 1080)));
+    let second = Address::DomainAddress(b"peer".to_vec(), 443);
+
+    let bind = bind.reply(Reply::Succeeded, first.clone()).await.unwrap();
+    let mut bind = bind.reply(Reply::Succeeded, second.clone()).await.unwrap();
+    bind.write_all(b"inbound").await.unwrap();
+    drop(bind);
+
+    assert_eq!(client.expect_reply(Reply::Succeeded).await, first);
+    assert_eq!(client.expect_reply(Reply::Succeeded).await, second);
+    assert_eq!(client.recv_to_end().await, b"inbound");
+
+    // the replies are recorded as sent
+    let replies = &client.received()[..client.received().len() - 7];
+    let (a, b) = replies.split_at(10);
+    test_util::assert_reply(a, Reply::Succeeded, &first);
+    test_util::assert_reply(b, Reply::Succeeded, &second);
+}
+
+#[tokio::test]
+async fn methods_are_read_before_one_is_selected() {
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec()));
+
+    // a trusted client is let in without the sub-negotiation of the adaptor
+    let (conn, mut client) = test_util::incoming(auth.clone());
+    client.handshake(&[Method::NONE, Method::PASSWORD]);
+    client.send().await.unwrap();
+
+    let (read, methods) = conn.read_methods().await.unwrap();
+    assert_eq!(methods, [Method::NONE, Method::PASSWORD]);
+    assert_eq!(read.methods(), methods);
+    assert_eq!(read.auth_method(), Method::PASSWORD);
+
+    let (conn, output) = read.select(Method::NONE).await.unwrap();
+    assert!(output.is_none());
+    client.expect_method(Method::NONE).await;
+
+    client.data(CONNECT_REQUEST).send().await.unwrap();
+    assert!(matches!(conn.wait().await, Ok(Command::Connect(..))));
+
+    // the others go through the adaptor
+    let (conn, mut client) = test_util::incoming(auth);
+    client
+        .handshake(&[Method::NONE, Method::PASSWORD])
+        .password(b"user", b"secret");
+    client.send().await.unwrap();
+
+    let (read, _) = conn.read_methods().await.unwrap();
+    let (_, output) = read.select(Method::PASSWORD).await.unwrap();
+    assert!(output.unwrap().unwrap());
+    client.expect_method(Method::PASSWORD).await;
+    client.expect_password_status(true).await;
+}
+
+#[tokio::test]
+async fn read_methods_can_be_rejected() {
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    client.handshake(&[Method::PASSWORD]);
+    client.send().await.unwrap();
+
+    let (read, _) = conn.read_methods().await.unwrap();
+    let failed = read.reject().await;
+    assert!(matches!(
+        failed.error,
+        Error::Protocol(ProtocolError::NoAcceptableHandshakeMethod { .. })
+    ));
+
+    failed.shutdown_and_err().await;
+    client.expect_method(Method::UNACCEPTABLE).await;
+    client.expect_eof().await;
+}
+
+#[tokio::test]
+async fn failed_handshake_returns_the_connection() {
+    const HTTP_REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    client.data(HTTP_REQUEST).send().await.unwrap();
+
+    let failed = conn.authenticate().await.map(|_| ()).unwrap_err();
+    assert!(matches!(failed.error, Error::Protocol(_)));
+
+    // the connection is handed back in the state it failed in, with the rest of the bytes received
+    let conn: IncomingConnection<(), NeedAuthenticate, DuplexStream> = failed.stream.unwrap();
+    let (_, buffered) = conn.into_parts();
+    assert!(!buffered.is_empty());
+    assert!(HTTP_REQUEST.ends_with(&buffered));
+}
+
+#[tokio::test]
+async fn malformed_requests_are_replied_to_by_wait_or_reject() {
+    let requests: [(&[u8], Reply); 3] = [
+        (&[0x04, 0x01, 0x00, 0x01], Reply::GeneralFailure),
+        (&[0x05, 0x7f, 0x00, 0x01], Reply::CommandNotSupported),
+        (&[0x05, 0x01, 0x00, 0x7f], Reply::AddressTypeNotSupported),
+    ];
+
+    for (request, reply) in requests {
+        let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+
+        client.handshake(&[Method::NONE]).data(request);
+        client.send().await.unwrap();
+
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let failed = conn.wait_or_reject().await.map(|_| ()).unwrap_err();
+        assert!(matches!(failed.error, Error::Protocol(_)));
+        failed.shutdown_and_err().await;
+
+        client.expect_method(Method::NONE).await;
+        client.expect_reply(reply).await;
+        client.expect_eof().await;
+    }
+}
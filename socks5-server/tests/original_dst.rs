@@ -0,0 +1,33 @@
+#![cfg(any(target_os = "android", target_os = "linux"))]
+
+use socks5_server::{auth::NoAuth, Server};
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+async fn assert_not_redirected(addr: SocketAddr) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (conn, _) = server.accept().await.unwrap();
+
+    let err = conn.original_dst().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    assert!(err.to_string().contains("not redirected"));
+}
+
+#[tokio::test]
+async fn connection_not_redirected_has_no_original_dst_v4() {
+    assert_not_redirected((Ipv4Addr::LOCALHOST, 0).into()).await;
+}
+
+#[tokio::test]
+async fn connection_not_redirected_has_no_original_dst_v6() {
+    assert_not_redirected((Ipv6Addr::LOCALHOST, 0).into()).await;
+}
@@ -0,0 +1,164 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::{NoAuth, Password},
+    config::ServerConfig,
+    proto::{
+        handshake::{password::Error as PasswordError, Method},
+        Address, Command as ProtoCommand, ParseLimits, ProtocolError, Reply,
+    },
+    Command, Error, Server,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::TcpListener,
+    sync::mpsc::{self, UnboundedReceiver},
+};
+
+fn limits() -> ParseLimits {
+    ParseLimits {
+        max_methods: 2,
+        max_username_len: 4,
+        max_password_len: 4,
+        max_domain_len: 6,
+    }
+}
+
+fn domain(name: &[u8]) -> Address {
+    Address::DomainAddress(name.to_vec(), 80)
+}
+
+/// Starts a server without authentication reading with [`limits()`], replying `Succeeded` to every `Connect` command. Returns its address and the errors the negotiations failed with.
+async fn serve() -> (SocketAddr, UnboundedReceiver<Error>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_parse_limits(limits());
+    let addr = server.local_addr().unwrap();
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            let tx = tx.clone();
+
+            tokio::spawn(async move {
+                let conn = match conn.authenticate().await {
+                    Ok((conn, ())) => conn,
+                    Err(failed) => return tx.send(failed.error).unwrap(),
+                };
+
+                match conn.wait().await {
+                    Ok(Command::Connect(connect, _)) => {
+                        let _ = connect
+                            .reply(Reply::Succeeded, Address::unspecified())
+                            .await;
+                    }
+                    Ok(_) => unreachable!(),
+                    Err(failed) => tx.send(failed.error).unwrap(),
+                }
+            });
+        }
+    });
+
+    (addr, rx)
+}
+
+#[tokio::test]
+async fn too_many_methods_are_unacceptable() {
+    let (addr, mut errors) = serve().await;
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(
+        client.handshake(&[Method::NONE, Method::PASSWORD]).await,
+        Method::NONE
+    );
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(
+        client
+            .handshake(&[Method::PASSWORD, Method(0x80), Method::NONE])
+            .await,
+        Method::UNACCEPTABLE
+    );
+    assert!(client.read_to_end().await.is_empty());
+
+    let err = common::timeout(errors.recv()).await.unwrap();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::TooManyHandshakeMethods {
+            nmethods: 3,
+            max: 2,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn too_long_domain_names_are_not_supported() {
+    let (addr, mut errors) = serve().await;
+
+    let (_, resp) = Client::no_auth_request(addr, ProtoCommand::Connect, domain(b"abcdef")).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, domain(b"abcdefg")).await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+    assert!(client.read_to_end().await.is_empty());
+
+    let err = common::timeout(errors.recv()).await.unwrap();
+    assert!(matches!(
+        err,
+        Error::Protocol(ProtocolError::DomainTooLongInRequest {
+            command: ProtoCommand::Connect,
+            len: 7,
+            max: 6,
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn too_long_credentials_fail_the_password_adaptor() {
+    let config = ServerConfig {
+        parse_limits: limits(),
+        ..ServerConfig::default()
+    };
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"pass".to_vec())) as Arc<_>;
+    let server = Server::with_config(listener, auth, config).unwrap();
+    let addr = server.local_addr().unwrap();
+    let (tx, mut outputs) = mpsc::unbounded_channel();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            let (_conn, output) = conn.authenticate().await.unwrap();
+            tx.send(output).unwrap();
+        }
+    });
+
+    let cases: [(&[u8], &[u8], _); 3] = [
+        (b"user", b"pass", true),
+        (b"users", b"pass", false),
+        (b"user", b"passw", false),
+    ];
+
+    for (username, password, accepted) in cases {
+        let mut client = Client::connect(addr).await;
+        assert_eq!(
+            client.handshake(&[Method::PASSWORD]).await,
+            Method::PASSWORD
+        );
+        assert_eq!(client.password(username, password).await, accepted);
+    }
+
+    assert!(matches!(outputs.recv().await.unwrap(), Ok(true)));
+    assert!(matches!(
+        outputs.recv().await.unwrap(),
+        Err(PasswordError::UsernameTooLong { len: 5, max: 4 })
+    ));
+    assert!(matches!(
+        outputs.recv().await.unwrap(),
+        Err(PasswordError::PasswordTooLong { len: 5, max: 4 })
+    ));
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, RelayClose, RelaySession, UdpRelayConfig},
+    proto::{Address, Command as ProtoCommand, Reply, UdpHeader},
+    quota::{Quota, QuotaConfig, QuotaDecision, QuotaEnforcer, QuotaProvider, QuotaStats},
+    relay::{self, AbortReason},
+    Command,
+};
+use std::{
+    collections::HashMap,
+    future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::UdpSocket,
+    sync::oneshot,
+};
+
+/// The bytes left to each user, granting at most what is left
+#[derive(Default)]
+struct Caps(Mutex<HashMap<Vec<u8>, u64>>);
+
+impl Caps {
+    fn with(user: &[u8], bytes: u64) -> Arc<Self> {
+        let caps = Self::default();
+        caps.0.lock().unwrap().insert(user.to_vec(), bytes);
+        Arc::new(caps)
+    }
+
+    fn left(&self, user: &[u8]) -> u64 {
+        self.0.lock().unwrap()[user]
+    }
+}
+
+#[async_trait]
+impl QuotaProvider for Caps {
+    async fn reserve(&self, user: &[u8], bytes: u64) -> QuotaDecision {
+        let mut caps = self.0.lock().unwrap();
+
+        match caps.get_mut(user) {
+            Some(left) if *left > 0 => {
+                let granted = bytes.min(*left);
+                *left -= granted;
+                QuotaDecision::Granted(granted)
+            }
+            _ => QuotaDecision::Exhausted,
+        }
+    }
+
+    async fn release(&self, user: &[u8], bytes: u64) {
+        *self.0.lock().unwrap().get_mut(user).unwrap() += bytes;
+    }
+}
+
+fn enforcer(caps: Arc<Caps>, chunk: u64) -> QuotaEnforcer {
+    let config = QuotaConfig {
+        chunk,
+        ..QuotaConfig::default()
+    };
+
+    QuotaEnforcer::new(caps, config)
+}
+
+#[tokio::test]
+async fn transfer_is_cut_off_once_the_quota_is_exhausted() {
+    let caps = Caps::with(b"alice", 10_000);
+    let quotas = enforcer(caps.clone(), 4096);
+    let quota = quotas.quota(Some(b"alice"));
+
+    let (mut client, mut a) = io::duplex(64 * 1024);
+    let (mut b, mut target) = io::duplex(64 * 1024);
+
+    let relay = tokio::spawn(async move {
+        relay::copy_bidirectional_with_quota(&mut a, &mut b, &quota, future::pending::<()>()).await
+    });
+
+    // far more than the quota, without ever closing the stream
+    client.write_all(&[0x42; 32 * 1024]).await.unwrap();
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert!(matches!(reason, AbortReason::QuotaExhausted));
+    assert_eq!((up, down), (10_000, 0));
+
+    // exactly the quota went through, and the relay did not shut the target down
+    let mut buf = vec![0; 10_000];
+    target.read_exact(&mut buf).await.unwrap();
+    assert!(buf.iter().all(|&byte| byte == 0x42));
+    drop(client);
+    assert!(common::timeout(target.read_u8()).await.is_err());
+
+    assert_eq!(caps.left(b"alice"), 0);
+    assert_eq!(
+        quotas.stats(),
+        QuotaStats {
+            reservations: 4,
+            reserved_bytes: 10_000,
+            released_bytes: 0,
+            exhausted_sessions: 1,
+        }
+    );
+}
+
+#[tokio::test]
+async fn unused_reservation_is_released() {
+    let caps = Caps::with(b"", 1 << 20);
+    let quotas = enforcer(caps.clone(), 4096);
+
+    // the session has no identity, so the anonymous bucket is reserved from
+    let quota = quotas.quota(None);
+    assert_eq!(quota.user(), b"");
+
+    let (mut client, mut a) = io::duplex(1024);
+    let (mut b, mut target) = io::duplex(1024);
+
+    let relay = tokio::spawn(async move {
+        relay::copy_bidirectional_with_quota(&mut a, &mut b, &quota, future::pending::<()>()).await
+    });
+
+    client.write_all(b"ping").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut buf = [0; 4];
+    target.read_exact(&mut buf).await.unwrap();
+    target.write_all(b"pong!").await.unwrap();
+    target.shutdown().await.unwrap();
+
+    let mut buf = Vec::new();
+    client.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, b"pong!");
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert!(matches!(reason, AbortReason::Eof));
+    assert_eq!((up, down), (4, 5));
+
+    // only the bytes relayed are charged
+    assert_eq!(caps.left(b""), (1 << 20) - 9);
+    assert_eq!(quotas.stats().released_bytes, 4096 - 9);
+    assert_eq!(quotas.stats().exhausted_sessions, 0);
+}
+
+/// Serves an association relayed with `quota`, returning the control connection, the relay address and the outcome of the relay.
+async fn associate(quota: Quota) -> (Client, SocketAddr, oneshot::Receiver<RelaySession>) {
+    let (tx, rx) = oneshot::channel();
+    let tx = Mutex::new(Some(tx));
+    let config = UdpRelayConfig {
+        quota: Some(quota),
+        ..UdpRelayConfig::default()
+    };
+
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let config = config.clone();
+        let tx = tx.lock().unwrap().take().unwrap();
+
+        async move {
+            let (conn, ()) = conn.authenticate().await.unwrap();
+
+            let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let _ = tx.send(run_relay(associate, config).await.unwrap());
+        }
+    })
+    .await;
+
+    let (client, resp) =
+        Client::no_auth_request(server, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(relay) = resp.address else {
+        unreachable!()
+    };
+
+    (client, relay, rx)
+}
+
+#[tokio::test]
+async fn udp_relay_finishes_once_the_quota_is_exhausted() {
+    let caps = Caps::with(b"bob", 25);
+    let quotas = enforcer(caps.clone(), 10);
+    let echo = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+    let (mut client, relay, rx) = associate(quotas.quota(Some(b"bob"))).await;
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut pkt = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(echo)).write_to_buf(&mut pkt);
+    pkt.extend_from_slice(b"hello");
+
+    // 10 bytes per round trip, so the third one is cut off on its way back
+    let mut buf = [0; 64];
+
+    for _ in 0..2 {
+        socket.send_to(&pkt, relay).await.unwrap();
+        let (len, _) = common::timeout(socket.recv_from(&mut buf)).await.unwrap();
+        assert!(buf[..len].ends_with(b"hello"));
+    }
+
+    socket.send_to(&pkt, relay).await.unwrap();
+
+    let session = common::timeout(rx).await.unwrap();
+    assert_eq!(session.close, RelayClose::QuotaExhausted);
+    assert_eq!(session.stats.uplink_packets, 3);
+    assert_eq!(session.stats.downlink_packets, 2);
+    assert_eq!(session.stats.dropped_quota, 1);
+
+    // the TCP connection is closed with the association
+    assert!(client.read_to_end().await.is_empty());
+    assert_eq!(caps.left(b"bob"), 0);
+    assert_eq!(quotas.stats().exhausted_sessions, 1);
+}
+
+#[tokio::test]
+async fn udp_datagram_over_the_quota_is_dropped() {
+    let caps = Caps::with(b"bob", 3);
+    let quotas = enforcer(caps.clone(), 10);
+    let echo = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+    let (_client, relay, rx) = associate(quotas.quota(Some(b"bob"))).await;
+
+    let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut pkt = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(echo)).write_to_buf(&mut pkt);
+    pkt.extend_from_slice(b"hello");
+    socket.send_to(&pkt, relay).await.unwrap();
+
+    let session = common::timeout(rx).await.unwrap();
+    assert_eq!(session.close, RelayClose::QuotaExhausted);
+    assert_eq!(session.stats.uplink_packets, 0);
+    assert_eq!(session.stats.dropped_quota, 1);
+
+    // the 3 bytes granted are given back, as nothing was relayed
+    assert_eq!(caps.left(b"bob"), 3);
+}
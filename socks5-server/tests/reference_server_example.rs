@@ -0,0 +1,139 @@
+//! Runs `examples/reference_server.rs` with configurations parsed from command lines.
+
+#[allow(dead_code)]
+#[path = "../examples/reference_server.rs"]
+mod example;
+
+mod common;
+
+use common::Client;
+use example::{Config, DnsMode};
+use socks5_server::proto::{handshake::Method, Address, Command as ProtoCommand, Reply};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::TcpListener;
+
+fn parse(args: &[&str]) -> Result<Option<Config>, String> {
+    Config::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+/// Starts the example server with the configuration parsed from `args` on an ephemeral port, returning its address.
+async fn start(args: &[&str]) -> SocketAddr {
+    let config = parse(args).unwrap().unwrap();
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(example::serve(listener, config));
+    addr
+}
+
+#[test]
+fn flags_are_parsed() {
+    let config = parse(&[
+        "--listen",
+        "0.0.0.0:1080",
+        "--username",
+        "user",
+        "--password",
+        "secret",
+        "--outbound-ip",
+        "192.0.2.1",
+        "--dns",
+        "cached",
+        "-vv",
+    ])
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(config.listen, SocketAddr::from(([0, 0, 0, 0], 1080)));
+    assert_eq!(
+        config.credentials,
+        Some(("user".to_owned(), "secret".to_owned()))
+    );
+    assert_eq!(config.outbound_ip, Some(IpAddr::from([192, 0, 2, 1])));
+    assert_eq!(config.relay_ip, None);
+    assert_eq!(config.dns, DnsMode::Cached);
+    assert_eq!(config.verbosity, 2);
+
+    assert!(parse(&["--help"]).unwrap().is_none());
+    assert!(parse(&["--username", "user"]).is_err());
+    assert!(parse(&["--dns", "sometimes"]).is_err());
+    assert!(parse(&["--listen"]).is_err());
+}
+
+#[tokio::test]
+async fn password_protected_connect_from_the_outbound_ip() {
+    let target = common::tcp_echo().await;
+    let server = start(&[
+        "--username",
+        "user",
+        "--password",
+        "secret",
+        "--outbound-ip",
+        "127.0.0.1",
+    ])
+    .await;
+
+    let mut client = Client::connect(server).await;
+    assert_eq!(
+        client.handshake(&[Method::NONE, Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(client.password(b"user", b"secret").await);
+
+    let resp = client
+        .request(ProtoCommand::Connect, Address::SocketAddress(target))
+        .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(bound) = resp.address else {
+        unreachable!()
+    };
+    assert_eq!(bound.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+}
+
+#[tokio::test]
+async fn wrong_password_is_closed() {
+    let server = start(&["--username", "user", "--password", "secret"]).await;
+
+    let mut client = Client::connect(server).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(!client.password(b"user", b"guess").await);
+    assert!(client.read_to_end().await.is_empty());
+}
+
+#[tokio::test]
+async fn refused_dns_replies_address_type_not_supported() {
+    let server = start(&["--dns", "refuse"]).await;
+
+    let (_, resp) = Client::no_auth_request(
+        server,
+        ProtoCommand::Connect,
+        Address::DomainAddress(b"localhost".to_vec(), 80),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+}
+
+#[tokio::test]
+async fn unreachable_target_is_mapped_to_a_reply() {
+    let server = start(&[]).await;
+
+    // a port nothing listens on
+    let closed = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let target = closed.local_addr().unwrap();
+    drop(closed);
+
+    let (_, resp) = Client::no_auth_request(
+        server,
+        ProtoCommand::Connect,
+        Address::SocketAddress(target),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::ConnectionRefused);
+}
@@ -0,0 +1,197 @@
+mod common;
+
+use socks5_server::relay::{self, AbortReason, HalfClose, RelayConfig};
+use std::{future, time::Duration};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    sync::oneshot,
+    time,
+};
+
+#[tokio::test]
+async fn eof_propagates_half_closes() {
+    let (mut a, mut client) = io::duplex(64);
+    let (mut b, mut target) = io::duplex(64);
+
+    let relay = tokio::spawn(async move {
+        relay::copy_bidirectional_with_abort(&mut a, &mut b, future::pending::<()>()).await
+    });
+
+    client.write_all(b"hello").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    // the client closing its write half is seen by the target, which can still reply
+    let mut buf = Vec::new();
+    common::timeout(target.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"hello");
+
+    target.write_all(b"world!").await.unwrap();
+    target.shutdown().await.unwrap();
+
+    let mut buf = Vec::new();
+    common::timeout(client.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"world!");
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (5, 6));
+    assert!(matches!(reason, AbortReason::Eof));
+}
+
+#[tokio::test(start_paused = true)]
+async fn target_closing_first_keeps_the_client_direction_open() {
+    let (mut a, mut client) = io::duplex(64);
+    let (mut b, mut target) = io::duplex(64);
+
+    let relay = tokio::spawn(async move {
+        let config = RelayConfig::default();
+        relay::copy_bidirectional_with_config(&mut a, &mut b, &config, future::pending::<()>())
+            .await
+    });
+
+    target.write_all(b"banner").await.unwrap();
+    target.shutdown().await.unwrap();
+
+    // the client sees EOF right after the data, before closing anything itself
+    let mut buf = Vec::new();
+    common::timeout(client.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"banner");
+
+    // the target still reads what the client sends after a while, then sees EOF
+    time::sleep(Duration::from_secs(60)).await;
+    client.write_all(b"ack").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut buf = Vec::new();
+    common::timeout(target.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"ack");
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (3, 6));
+    assert!(matches!(reason, AbortReason::Eof));
+}
+
+#[tokio::test]
+async fn simultaneous_half_closes_finish_the_relay() {
+    let (mut a, mut client) = io::duplex(64);
+    let (mut b, mut target) = io::duplex(64);
+
+    let relay = tokio::spawn(async move {
+        relay::copy_bidirectional_with_abort(&mut a, &mut b, future::pending::<()>()).await
+    });
+
+    client.write_all(b"ping").await.unwrap();
+    target.write_all(b"pong").await.unwrap();
+    client.shutdown().await.unwrap();
+    target.shutdown().await.unwrap();
+
+    let mut buf = Vec::new();
+    common::timeout(client.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"pong");
+
+    let mut buf = Vec::new();
+    common::timeout(target.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"ping");
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (4, 4));
+    assert!(matches!(reason, AbortReason::Eof));
+}
+
+#[tokio::test(start_paused = true)]
+async fn close_both_shuts_both_down_on_the_first_eof() {
+    let (mut a, mut client) = io::duplex(64);
+    let (mut b, mut target) = io::duplex(64);
+
+    let relay = tokio::spawn(async move {
+        let config = RelayConfig {
+            half_close: HalfClose::CloseBoth,
+            ..RelayConfig::default()
+        };
+
+        let res =
+            relay::copy_bidirectional_with_config(&mut a, &mut b, &config, future::pending::<()>())
+                .await;
+        (res, b)
+    });
+
+    target.write_all(b"hello").await.unwrap();
+    time::sleep(Duration::from_millis(10)).await;
+    client.write_all(b"bye").await.unwrap();
+    client.shutdown().await.unwrap();
+
+    // the target sees EOF after the data of the client
+    let mut buf = Vec::new();
+    common::timeout(target.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"bye");
+
+    // and so does the client, without the target closing anything
+    let mut buf = Vec::new();
+    common::timeout(client.read_to_end(&mut buf)).await.unwrap();
+    assert_eq!(buf, b"hello");
+
+    let ((up, down, reason), mut b) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (3, 5));
+    assert!(matches!(reason, AbortReason::Eof));
+
+    // nothing the target sends afterwards is read by the relay
+    target.write_all(b"late").await.unwrap();
+    let mut buf = [0; 4];
+    b.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"late");
+}
+
+#[tokio::test(start_paused = true)]
+async fn abort_while_transferring_delivers_what_was_read() {
+    let (mut a, mut client) = io::duplex(64);
+    // the target accepts few bytes at a time, so the relay holds data it read when aborted
+    let (mut b, mut target) = io::duplex(4);
+    let (kill, killed) = oneshot::channel::<()>();
+
+    let relay = tokio::spawn(async move {
+        let res = relay::copy_bidirectional_with_abort(&mut a, &mut b, killed).await;
+        (res, a, b)
+    });
+
+    client.write_all(&[1; 16]).await.unwrap();
+    time::sleep(Duration::from_millis(10)).await;
+    kill.send(()).unwrap();
+    time::sleep(Duration::from_millis(10)).await;
+
+    // still flushing into the target
+    assert!(!relay.is_finished());
+
+    let mut buf = [0; 16];
+    common::timeout(target.read_exact(&mut buf)).await.unwrap();
+    assert_eq!(buf, [1; 16]);
+
+    let ((up, down, reason), mut a, _b) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (16, 0));
+    assert!(matches!(reason, AbortReason::Aborted));
+
+    // nothing is read after the abort, and the target was not shut down
+    client.write_all(b"late").await.unwrap();
+    assert!(time::timeout(Duration::from_millis(10), target.read_u8())
+        .await
+        .is_err());
+
+    let mut buf = [0; 4];
+    a.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"late");
+}
+
+#[tokio::test]
+async fn error_is_reported_with_the_bytes_copied() {
+    let (mut a, mut client) = io::duplex(64);
+    let (mut b, target) = io::duplex(64);
+
+    let relay = tokio::spawn(async move {
+        relay::copy_bidirectional_with_abort(&mut a, &mut b, future::pending::<()>()).await
+    });
+
+    drop(target);
+    client.write_all(b"hello").await.unwrap();
+
+    let (up, down, reason) = common::timeout(relay).await.unwrap();
+    assert_eq!((up, down), (0, 0));
+    assert!(matches!(reason, AbortReason::Error(err) if err.kind() == io::ErrorKind::BrokenPipe));
+}
@@ -0,0 +1,87 @@
+#![cfg(all(unix, not(any(target_os = "illumos", target_os = "solaris"))))]
+
+mod common;
+
+use socks5_server::{auth::NoAuth, Server};
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn listeners_share_the_address() {
+    const COUNT: usize = 4;
+    const CLIENTS: usize = 32;
+
+    let servers =
+        Server::bind_reuseport((Ipv4Addr::LOCALHOST, 0), Arc::new(NoAuth) as Arc<_>, COUNT)
+            .await
+            .unwrap();
+    assert_eq!(servers.len(), COUNT);
+
+    let addr = servers[0].local_addr().unwrap();
+    assert_ne!(addr.port(), 0);
+
+    for server in &servers {
+        assert_eq!(server.local_addr().unwrap(), addr);
+        assert!(socket2::SockRef::from(server.get_ref())
+            .reuse_port()
+            .unwrap());
+    }
+
+    let accepted = Arc::new(AtomicUsize::new(0));
+
+    for server in servers {
+        let accepted = accepted.clone();
+
+        tokio::spawn(async move {
+            // kept open until the end of the test
+            let mut conns = Vec::new();
+
+            while let Ok((conn, _)) = server.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                conns.push(conn);
+            }
+        });
+    }
+
+    let mut clients = Vec::new();
+
+    for _ in 0..CLIENTS {
+        clients.push(TcpStream::connect(addr).await.unwrap());
+    }
+
+    common::timeout(async {
+        while accepted.load(Ordering::SeqCst) < CLIENTS {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn builder_binds_with_reuse_port() {
+    let first = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .reuse_port(true)
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    let addr = first.local_addr().unwrap();
+
+    let second = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .reuse_port(true)
+        .bind(addr)
+        .await
+        .unwrap();
+    assert_eq!(second.local_addr().unwrap(), addr);
+
+    // a listener without it set can not join them
+    Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .bind(addr)
+        .await
+        .unwrap_err();
+}
@@ -0,0 +1,189 @@
+mod common;
+
+use async_trait::async_trait;
+use common::Client;
+use socket2::TcpKeepalive;
+use socks5_server::{
+    auth::NoAuth,
+    connection::connect::state::NeedReply,
+    listener::Listener,
+    proto::{Address, Command as ProtoCommand, Reply},
+    Connect, ConnectionHandler, Error, Server,
+};
+use std::{
+    io::Error as IoError,
+    net::{Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    io,
+    net::{TcpListener, TcpStream},
+};
+
+/// Relays `Connect` commands to socket addresses, recording the errors
+#[derive(Default)]
+struct Relay {
+    errors: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl ConnectionHandler<()> for Relay {
+    async fn handle_connect(
+        &self,
+        connect: Connect<NeedReply>,
+        addr: Address,
+        _: (),
+    ) -> Result<(), Error> {
+        let Address::SocketAddress(addr) = addr else {
+            connect
+                .reply(Reply::AddressTypeNotSupported, Address::unspecified())
+                .await?;
+            return Ok(());
+        };
+
+        let mut target = TcpStream::connect(addr).await?;
+        let mut conn = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await?;
+        io::copy_bidirectional(&mut conn, &mut target).await?;
+        Ok(())
+    }
+
+    async fn on_error(&self, _: SocketAddr, err: Error) {
+        self.errors.lock().unwrap().push(err.to_string());
+    }
+}
+
+#[tokio::test]
+async fn commands_are_routed_to_the_handler() {
+    let server = Arc::new(common::server().await);
+    let addr = server.local_addr().unwrap();
+    let relay = Arc::new(Relay::default());
+
+    tokio::spawn({
+        let (server, relay) = (server.clone(), relay.clone());
+        async move { server.serve(relay).await.unwrap() }
+    });
+
+    // Connect is handled by the handler
+    let echo = common::tcp_echo().await;
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(echo)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+
+    // Bind and Associate are not supported by default
+    for command in [ProtoCommand::Bind, ProtoCommand::Associate] {
+        let (_, resp) = Client::no_auth_request(addr, command, Address::unspecified()).await;
+        assert_eq!(resp.reply, Reply::CommandNotSupported);
+    }
+
+    assert!(relay.errors.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn negotiation_errors_reach_the_hook() {
+    let server = Arc::new(common::server().await);
+    let addr = server.local_addr().unwrap();
+    let relay = Arc::new(Relay::default());
+
+    tokio::spawn({
+        let (server, relay) = (server.clone(), relay.clone());
+        async move { server.serve(relay).await.unwrap() }
+    });
+
+    // not SOCKS5, so the stream is shut down
+    let mut client = Client::connect(addr).await;
+    client.write(&[0x04, 0x01, 0x00, 0x50]).await;
+    assert!(client.read_to_end().await.is_empty());
+
+    // the hook is called once the stream is shut down
+    common::timeout(async {
+        while relay.errors.lock().unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+
+    let errors = relay.errors.lock().unwrap();
+    assert_eq!(errors.len(), 1, "{errors:?}");
+}
+
+#[tokio::test]
+async fn serve_returns_on_shutdown_and_handlers_drain() {
+    let server = Arc::new(common::server().await);
+    let addr = server.local_addr().unwrap();
+    let tracker = server.connection_tracker().clone();
+
+    let serve = tokio::spawn({
+        let server = server.clone();
+        async move { server.serve(Arc::new(Relay::default())).await }
+    });
+
+    let echo = common::tcp_echo().await;
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(echo)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    server.shutdown();
+    common::timeout(serve).await.unwrap().unwrap();
+
+    // the relay in flight keeps running, and is waited for
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+    assert_eq!(tracker.count(), 1);
+
+    drop(client);
+    assert!(tracker.await_idle_timeout(Duration::from_secs(5)).await);
+}
+
+/// A TCP listener failing to set the keepalive of every connection it accepts
+struct NoKeepalive(TcpListener);
+
+#[async_trait]
+impl Listener for NoKeepalive {
+    type Stream = TcpStream;
+
+    async fn accept(&self) -> Result<(TcpStream, SocketAddr), IoError> {
+        self.0.accept().await
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.0.local_addr()
+    }
+
+    fn set_tcp_keepalive(_: &TcpStream, _: &TcpKeepalive) -> Result<(), IoError> {
+        Err(IoError::other("setsockopt failed"))
+    }
+}
+
+#[tokio::test]
+async fn failing_to_set_keepalive_does_not_end_serve() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(NoKeepalive(listener), Arc::new(NoAuth) as Arc<_>);
+    server.set_tcp_keepalive(TcpKeepalive::new());
+    let server = Arc::new(server);
+    let addr = server.local_addr().unwrap();
+
+    let serve = tokio::spawn({
+        let server = server.clone();
+        async move { server.serve(Arc::new(Relay::default())).await }
+    });
+
+    // every connection is still served, without keepalive
+    let echo = common::tcp_echo().await;
+    for _ in 0..2 {
+        let (mut client, resp) =
+            Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(echo))
+                .await;
+        assert_eq!(resp.reply, Reply::Succeeded);
+        client.write(b"hello").await;
+        assert_eq!(client.read(5).await, b"hello");
+    }
+
+    assert!(!serve.is_finished());
+    server.shutdown();
+    common::timeout(serve).await.unwrap().unwrap();
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use common::Client;
+use socket2::SockRef;
+use socks5_server::{
+    auth::NoAuth,
+    proto::{Address, Command as ProtoCommand, Reply},
+    Command, Server,
+};
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn options_are_set_on_the_listener() {
+    let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .reuse_address(false)
+        .backlog(16)
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    assert!(!SockRef::from(server.get_ref()).reuse_address().unwrap());
+
+    let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+    assert_eq!(
+        SockRef::from(server.get_ref()).reuse_address().unwrap(),
+        cfg!(unix)
+    );
+}
+
+#[tokio::test]
+async fn only_v6_is_set_on_ipv6_listeners() {
+    let Ok(server) = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .only_v6(true)
+        .bind((Ipv6Addr::LOCALHOST, 0))
+        .await
+    else {
+        // no IPv6 loopback
+        return;
+    };
+    assert!(SockRef::from(server.get_ref()).only_v6().unwrap());
+
+    // and ignored on IPv4 ones
+    Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .only_v6(true)
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn listener_is_bound_to_the_device() {
+    let err = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .bind_device("nonexistent0")
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap_err();
+    assert_ne!(err.kind(), ErrorKind::AddrInUse);
+
+    let server = match Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .bind_device("lo")
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+    {
+        Ok(server) => server,
+        // binding to a device requires CAP_NET_RAW
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => return,
+        Err(err) => panic!("{err}"),
+    };
+    assert_eq!(
+        SockRef::from(server.get_ref()).device().unwrap().as_deref(),
+        Some(&b"lo"[..])
+    );
+}
+
+#[tokio::test]
+async fn built_server_accepts_connections() {
+    let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .backlog(16)
+        .bind("localhost:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+
+    let task = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+    });
+
+    let (_client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    task.await.unwrap();
+}
+
+#[tokio::test]
+async fn unresolvable_addresses_are_rejected() {
+    let err = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .bind(&[][..] as &[std::net::SocketAddr])
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn bind_tries_every_address() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let taken = listener.local_addr().unwrap();
+    let free = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+
+    let server = Server::bind(&[taken, free][..], Arc::new(NoAuth) as Arc<_>)
+        .await
+        .unwrap();
+    assert_ne!(server.local_addr().unwrap(), taken);
+
+    // the error of binding is returned
+    let err = Server::bind(taken, Arc::new(NoAuth) as Arc<_>)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::AddrInUse);
+}
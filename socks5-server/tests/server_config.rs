@@ -0,0 +1,198 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    config::{OutboundConfig, ServerConfig},
+    hostname::HostnamePolicy,
+    proto::{Address, Command as ProtoCommand, Reply},
+    relay::{self, AbortReason, HalfClose},
+    Command, Server,
+};
+use std::{
+    future,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpListener, TcpSocket},
+};
+
+fn config() -> ServerConfig {
+    let mut config = ServerConfig {
+        session_setup_deadline: Some(Duration::from_secs(10)),
+        hostname_policy: Some(HostnamePolicy {
+            max_len: 64,
+            ..HostnamePolicy::default()
+        }),
+        outbound: OutboundConfig {
+            bind_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        },
+        ..ServerConfig::default()
+    };
+    config.relay.half_close = HalfClose::CloseBoth;
+    config
+}
+
+#[test]
+fn default_config_is_valid() {
+    assert!(ServerConfig::default().validate().is_ok());
+    assert!(config().validate().is_ok());
+}
+
+#[test]
+fn every_problem_is_reported() {
+    let mut config = config();
+    config.session_setup_deadline = Some(Duration::ZERO);
+    config.hostname_policy = Some(HostnamePolicy {
+        max_len: 300,
+        max_label_len: 0,
+        ..HostnamePolicy::default()
+    });
+    config.accept_backoff.max = config.accept_backoff.initial / 2;
+    config.parse_limits.max_methods = 0;
+
+    let err = config.validate().unwrap_err();
+    let fields = err
+        .problems()
+        .iter()
+        .map(|problem| problem.field)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        fields,
+        [
+            "session_setup_deadline",
+            "hostname_policy.max_len",
+            "hostname_policy.max_label_len",
+            "accept_backoff.max",
+            "parse_limits.max_methods",
+        ]
+    );
+
+    assert!(err
+        .to_string()
+        .contains("hostname_policy.max_len: must be between 1 and 255"));
+}
+
+#[cfg(feature = "udp")]
+#[test]
+fn udp_relay_problems_are_reported() {
+    use socks5_server::connection::associate::{ControlKeepalive, FragmentPolicy};
+
+    let mut config = ServerConfig::default();
+    config.udp_relay.max_pkt_size = 70_000;
+    config.udp_relay.idle_timeout = Some(Duration::ZERO);
+    config.udp_relay.control_keepalive = Some(ControlKeepalive {
+        retries: 0,
+        ..ControlKeepalive::default()
+    });
+    config.udp_relay.max_destinations = Some(0);
+    config.udp_relay.fragment_policy = FragmentPolicy::Reassemble {
+        timeout: Duration::from_secs(1),
+        max_len: 65535,
+        max_sequences: 16,
+    };
+
+    let err = config.validate().unwrap_err();
+    let fields = err
+        .problems()
+        .iter()
+        .map(|problem| problem.field)
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        fields,
+        [
+            "udp_relay.max_pkt_size",
+            "udp_relay.buffer_pool",
+            "udp_relay.idle_timeout",
+            "udp_relay.control_keepalive",
+            "udp_relay.max_destinations",
+            "udp_relay.fragment_policy",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn invalid_config_is_rejected() {
+    let mut config = config();
+    config.session_setup_deadline = Some(Duration::ZERO);
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let err = Server::with_config(listener, Arc::new(NoAuth) as Arc<_>, config).unwrap_err();
+    assert_eq!(err.problems().len(), 1);
+}
+
+#[tokio::test]
+async fn server_is_built_from_the_config() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::with_config(listener, Arc::new(NoAuth) as Arc<_>, config()).unwrap();
+    assert_eq!(
+        server.config().session_setup_deadline,
+        Some(Duration::from_secs(10))
+    );
+
+    let addr = server.local_addr().unwrap();
+    let echo = common::tcp_echo().await;
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            let relay_config = server.config().relay.clone();
+            let outbound = server.config().outbound;
+
+            tokio::spawn(async move {
+                let (conn, ()) = conn.authenticate().await.unwrap();
+
+                let Ok(Command::Connect(connect, Address::SocketAddress(dst))) = conn.wait().await
+                else {
+                    return;
+                };
+
+                let socket = TcpSocket::new_v4().unwrap();
+
+                if let Some(ip) = outbound.bind_ip {
+                    socket.bind(SocketAddr::new(ip, 0)).unwrap();
+                }
+
+                let mut target = socket.connect(dst).await.unwrap();
+                let bound = Address::SocketAddress(target.local_addr().unwrap());
+                let mut connect = connect.reply(Reply::Succeeded, bound).await.unwrap();
+
+                let (_, _, reason) = relay::copy_bidirectional_with_config(
+                    &mut connect,
+                    &mut target,
+                    &relay_config,
+                    future::pending::<()>(),
+                )
+                .await;
+
+                assert!(matches!(reason, AbortReason::Eof));
+            });
+        }
+    });
+
+    // the hostname policy is applied
+    let long = Address::DomainAddress(vec![b'a'; 65], 80);
+    let (_, resp) = Client::no_auth_request(addr, ProtoCommand::Connect, long).await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+
+    // the outbound connection is bound on the configured IP address
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(echo)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(bound) = resp.address else {
+        panic!("unexpected bound address {}", resp.address);
+    };
+    assert_eq!(bound.ip(), Ipv4Addr::LOCALHOST);
+
+    // and the relay configuration is applied, closing both directions on the first EOF
+
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+    client.stream.shutdown().await.unwrap();
+    assert!(client.read_to_end().await.is_empty());
+}
@@ -0,0 +1,286 @@
+use socks5_server::{
+    auth::{NoAuth, Password},
+    proto::{handshake::Method, Address, Command as ProtoCommand, Reply},
+    test_util, Command, Error, Server,
+};
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{self, Instant},
+};
+
+const DEADLINE: Duration = Duration::from_secs(1);
+
+fn target() -> Address {
+    Address::DomainAddress(b"example.com".to_vec(), 80)
+}
+
+#[tokio::test(start_paused = true)]
+async fn server_records_the_deadline_at_accept_time() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_session_setup_deadline(DEADLINE);
+
+    // the client connects, but never sends the handshake
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let (conn, _) = server.accept().await.unwrap();
+    let accepted = Instant::now();
+    let failed = conn.authenticate().await.unwrap_err();
+
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert!(failed.stream.is_some());
+    assert_eq!(accepted.elapsed(), DEADLINE);
+}
+
+#[tokio::test(start_paused = true)]
+async fn auth_execution_counts_towards_the_deadline() {
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"pass".to_vec())) as Arc<_>;
+    let (conn, mut client) = test_util::incoming_with_setup_deadline(auth, DEADLINE);
+
+    let server = tokio::spawn(async move { conn.authenticate().await.map(|_| ()).unwrap_err() });
+
+    // the method is negotiated in time, but the credentials are held back
+    client.handshake(&[Method::PASSWORD]);
+    client.expect_method(Method::PASSWORD).await;
+
+    let failed = server.await.unwrap();
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert!(failed.stream.is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn wait_fails_if_the_deadline_passes_between_stages() {
+    let (conn, mut client) =
+        test_util::incoming_with_setup_deadline(Arc::new(NoAuth) as Arc<_>, DEADLINE);
+
+    client
+        .handshake(&[Method::NONE])
+        .request(ProtoCommand::Connect, target());
+    client.send().await.unwrap();
+
+    let (conn, ()) = conn.authenticate().await.unwrap();
+
+    // the request is already buffered, but the handler is too slow to wait for it
+    time::sleep(DEADLINE).await;
+
+    let failed = conn.wait().await.map(|_| ()).unwrap_err();
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert!(failed.stream.is_some());
+}
+
+#[tokio::test(start_paused = true)]
+async fn reply_fails_if_the_deadline_passes_before_it_is_sent() {
+    let (conn, mut client) =
+        test_util::incoming_with_setup_deadline(Arc::new(NoAuth) as Arc<_>, DEADLINE);
+
+    client
+        .handshake(&[Method::NONE])
+        .request(ProtoCommand::Connect, target());
+    client.send().await.unwrap();
+
+    let (conn, ()) = conn.authenticate().await.unwrap();
+
+    let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+
+    // e.g. connecting to the destination takes too long
+    time::sleep(DEADLINE).await;
+
+    let failed = connect
+        .reply(Reply::Succeeded, Address::unspecified())
+        .await
+        .unwrap_err();
+
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    failed.shutdown_and_err().await;
+
+    // the connection is closed without a reply
+    client.expect_method(Method::NONE).await;
+    client.expect_eof().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn deadline_is_lifted_once_the_first_reply_is_sent() {
+    let (conn, mut client) =
+        test_util::incoming_with_setup_deadline(Arc::new(NoAuth) as Arc<_>, DEADLINE);
+
+    client
+        .handshake(&[Method::NONE])
+        .request(ProtoCommand::Bind, Address::unspecified())
+        .data(b"ping");
+
+    let server = tokio::spawn(async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Bind(bind, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let bind = bind
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        // the inbound connection of a `Bind` command may take any time
+        time::sleep(DEADLINE * 2).await;
+
+        let mut bind = bind
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        let mut buf = [0; 4];
+        bind.read_exact(&mut buf).await.unwrap();
+        bind.write_all(&buf).await.unwrap();
+    });
+
+    client.expect_method(Method::NONE).await;
+    client.expect_reply(Reply::Succeeded).await;
+    client.expect_reply(Reply::Succeeded).await;
+    assert_eq!(client.recv(4).await, b"ping");
+    server.await.unwrap();
+}
+
+#[tokio::test(start_paused = true)]
+async fn connection_deadline_replaces_the_server_one() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_session_setup_deadline(DEADLINE);
+
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let (mut conn, _) = server.accept().await.unwrap();
+    let accepted = Instant::now();
+    assert_eq!(conn.setup_deadline(), Some(accepted + DEADLINE));
+
+    conn.set_setup_deadline(accepted + DEADLINE * 3);
+    let failed = conn.authenticate().await.unwrap_err();
+
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert_eq!(accepted.elapsed(), DEADLINE * 3);
+}
+
+#[tokio::test(start_paused = true)]
+async fn trickled_handshake_is_cut_off() {
+    let (mut conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+    assert_eq!(conn.setup_deadline(), None);
+
+    let started = Instant::now();
+    conn.set_setup_deadline(started + DEADLINE);
+    let server = tokio::spawn(async move { conn.authenticate().await.map(|_| ()).unwrap_err() });
+
+    // a slowloris client sending one byte of the handshake at a time, each well within the deadline
+    for byte in [0x05, 0x01] {
+        client.data(&[byte]);
+        time::sleep(DEADLINE / 4).await;
+    }
+
+    let failed = server.await.unwrap();
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert!(failed.stream.is_some());
+    assert_eq!(started.elapsed(), DEADLINE);
+}
+
+#[tokio::test(start_paused = true)]
+async fn auth_timeout_covers_the_sub_negotiation() {
+    let auth = Arc::new(Password::new(b"user".to_vec(), b"pass".to_vec())) as Arc<_>;
+    let (conn, mut client) = test_util::incoming(auth);
+
+    let started = Instant::now();
+    let server = tokio::spawn(async move {
+        conn.authenticate_with_timeout(DEADLINE)
+            .await
+            .map(|_| ())
+            .unwrap_err()
+    });
+
+    // the method is negotiated right away, but the credentials are held back
+    client.handshake(&[Method::PASSWORD]);
+    client.expect_method(Method::PASSWORD).await;
+
+    let failed = server.await.unwrap();
+    assert!(matches!(failed.error, Error::AuthTimeout));
+    assert!(failed.stream.is_some());
+    assert_eq!(started.elapsed(), DEADLINE);
+}
+
+#[tokio::test(start_paused = true)]
+async fn auth_timeout_is_lifted_once_authenticated() {
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+
+    client.handshake(&[Method::NONE]);
+    client.send().await.unwrap();
+
+    let (conn, ()) = conn.authenticate_with_timeout(DEADLINE).await.unwrap();
+
+    // the request may take any time without a setup deadline
+    time::sleep(DEADLINE * 2).await;
+    client.request(ProtoCommand::Connect, target());
+    client.send().await.unwrap();
+
+    assert!(matches!(conn.wait().await, Ok(Command::Connect(..))));
+}
+
+#[tokio::test(start_paused = true)]
+async fn earlier_setup_deadline_wins_over_the_auth_timeout() {
+    let (conn, _client) =
+        test_util::incoming_with_setup_deadline(Arc::new(NoAuth) as Arc<_>, DEADLINE);
+
+    let started = Instant::now();
+    let failed = conn
+        .authenticate_with_timeout(DEADLINE * 2)
+        .await
+        .map(|_| ())
+        .unwrap_err();
+
+    assert!(matches!(failed.error, Error::SetupDeadlineExceeded));
+    assert_eq!(started.elapsed(), DEADLINE);
+}
+
+#[tokio::test(start_paused = true)]
+async fn request_timeout_keeps_the_bytes_received() {
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+
+    // a request cut off after its first bytes
+    client.handshake(&[Method::NONE]).data(&[0x05, 0x01]);
+    client.send().await.unwrap();
+
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let started = Instant::now();
+    let failed = conn
+        .wait_with_timeout(DEADLINE)
+        .await
+        .map(|_| ())
+        .unwrap_err();
+
+    assert!(matches!(failed.error, Error::RequestTimeout));
+    assert_eq!(started.elapsed(), DEADLINE);
+    assert_eq!(failed.stream.unwrap().into_parts().1, [0x05, 0x01][..]);
+}
+
+#[tokio::test(start_paused = true)]
+async fn request_within_the_timeout_is_parsed() {
+    let (conn, mut client) = test_util::incoming(Arc::new(NoAuth) as Arc<_>);
+
+    client.handshake(&[Method::NONE]);
+    client.send().await.unwrap();
+    let (conn, ()) = conn.authenticate().await.unwrap();
+
+    let server = tokio::spawn(async move { conn.wait_with_timeout(DEADLINE).await });
+
+    time::sleep(DEADLINE / 2).await;
+    client.request(ProtoCommand::Connect, target());
+    client.send().await.unwrap();
+
+    let Ok(Command::Connect(_, addr)) = server.await.unwrap() else {
+        panic!("expected a Connect command");
+    };
+    assert_eq!(addr, target());
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use socks5_server::{auth::NoAuth, shutdown::ServerShutdown, Server, SharedServer};
+use std::{collections::HashSet, net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::{net::TcpStream, sync::mpsc, time};
+
+async fn server() -> SharedServer<()> {
+    Server::bind((Ipv4Addr::LOCALHOST, 0), Arc::new(NoAuth) as Arc<_>)
+        .await
+        .unwrap()
+        .into_shared()
+}
+
+#[tokio::test]
+async fn concurrent_accepts_get_connections_of_their_own() {
+    const TASKS: usize = 4;
+    const CLIENTS: usize = 16;
+
+    let server = server().await;
+    let addr = server.local_addr().unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    for _ in 0..TASKS {
+        let (server, tx) = (server.clone(), tx.clone());
+
+        tokio::spawn(async move {
+            while let Ok((conn, peer)) = server.accept().await {
+                tx.send((conn, peer)).unwrap();
+            }
+        });
+    }
+
+    // every task is parked in accept before the first connection arrives
+    time::sleep(Duration::from_millis(50)).await;
+
+    let mut clients = Vec::new();
+
+    for _ in 0..CLIENTS {
+        clients.push(TcpStream::connect(addr).await.unwrap());
+    }
+
+    let mut peers = HashSet::new();
+    let mut conns = Vec::new();
+
+    for _ in 0..CLIENTS {
+        let (conn, peer) = common::timeout(rx.recv()).await.unwrap();
+        assert!(peers.insert(peer));
+        conns.push(conn);
+    }
+
+    let clients = clients
+        .iter()
+        .map(|client| client.local_addr().unwrap())
+        .collect::<HashSet<_>>();
+    assert_eq!(peers, clients);
+    assert_eq!(server.connection_tracker().count(), CLIENTS);
+}
+
+#[tokio::test]
+async fn shutdown_wakes_every_accept() {
+    let server = server().await;
+
+    let tasks = (0..4)
+        .map(|_| {
+            let server = server.clone();
+            tokio::spawn(async move { server.accept().await.map(|_| ()) })
+        })
+        .collect::<Vec<_>>();
+
+    time::sleep(Duration::from_millis(50)).await;
+    server.shutdown();
+
+    for task in tasks {
+        let err = common::timeout(task).await.unwrap().unwrap_err();
+        assert!(ServerShutdown::is(&err));
+    }
+}
+
+#[tokio::test]
+async fn server_is_given_back_by_the_last_handle() {
+    let server = server().await;
+    let clone = server.clone();
+
+    let server = server.try_into_server().unwrap_err();
+    drop(clone);
+
+    let mut server = server.try_into_server().unwrap();
+    server.clear_max_connections();
+}
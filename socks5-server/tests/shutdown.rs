@@ -0,0 +1,113 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    proto::{Address, Command as ProtoCommand, Reply},
+    shutdown::{ServerShutdown, ShutdownHandle},
+    Command,
+};
+use std::{future, sync::Arc, time::Duration};
+use tokio::{io, time};
+
+#[tokio::test]
+async fn pending_accept_is_woken_on_shutdown() {
+    let server = Arc::new(common::server().await);
+
+    let task = tokio::spawn({
+        let server = server.clone();
+        async move { server.accept().await.map(|_| ()) }
+    });
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!task.is_finished());
+    server.shutdown();
+
+    let err = common::timeout(task).await.unwrap().unwrap_err();
+    assert!(ServerShutdown::is(&err));
+
+    // and so are subsequent ones, even with connections to accept
+    let _client = Client::connect(server.local_addr().unwrap()).await;
+    let err = server.accept().await.unwrap_err();
+    assert!(ServerShutdown::is(&err));
+}
+
+#[tokio::test]
+async fn pending_poll_accept_is_woken_on_shutdown() {
+    let server = Arc::new(common::server().await);
+    let shutdown = server.shutdown_handle().clone();
+
+    let task = tokio::spawn({
+        let server = server.clone();
+        async move {
+            future::poll_fn(|cx| server.poll_accept(cx))
+                .await
+                .map(|_| ())
+        }
+    });
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!task.is_finished());
+    shutdown.shutdown();
+    assert!(server.shutdown_handle().is_shut_down());
+
+    let err = common::timeout(task).await.unwrap().unwrap_err();
+    assert!(ServerShutdown::is(&err));
+}
+
+#[tokio::test]
+async fn one_handle_shuts_several_servers_down() {
+    let shutdown = ShutdownHandle::new();
+    let mut servers = [common::server().await, common::server().await];
+
+    for server in &mut servers {
+        server.set_shutdown_handle(shutdown.clone());
+    }
+
+    shutdown.shutdown();
+    shutdown.await_shutdown().await;
+
+    for server in &servers {
+        assert!(ServerShutdown::is(&server.accept().await.unwrap_err()));
+    }
+}
+
+#[tokio::test]
+async fn accepted_connections_drain() {
+    let server = common::server().await;
+    let addr = server.local_addr().unwrap();
+    let tracker = server.connection_tracker().clone();
+    let shutdown = server.shutdown_handle().clone();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(async move {
+                let (conn, ()) = conn.authenticate().await.unwrap();
+                let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+                    unreachable!()
+                };
+
+                let connect = connect
+                    .reply(Reply::Succeeded, Address::unspecified())
+                    .await
+                    .unwrap();
+
+                let (mut rx, mut tx) = io::split(connect);
+                let _ = io::copy(&mut rx, &mut tx).await;
+            });
+        }
+    });
+
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    shutdown.shutdown();
+
+    // the relay keeps running after the shutdown
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+    assert_eq!(tracker.count(), 1);
+
+    drop(client);
+    assert!(tracker.await_idle_timeout(Duration::from_secs(5)).await);
+}
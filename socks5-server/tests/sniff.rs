@@ -0,0 +1,73 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::{state::NeedAuthenticate, Sniff},
+    proto::handshake::Method,
+    IncomingConnection, Server,
+};
+use std::{io::ErrorKind, net::Ipv4Addr, sync::Arc};
+use tokio::{io::AsyncReadExt, net::TcpListener};
+
+async fn accept(first: &[u8]) -> (Client, IncomingConnection<(), NeedAuthenticate>) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+
+    let mut client = Client::connect(server.local_addr().unwrap()).await;
+    let (conn, _) = server.accept().await.unwrap();
+
+    if !first.is_empty() {
+        client.write(first).await;
+    }
+
+    (client, conn)
+}
+
+#[tokio::test]
+async fn first_byte_is_classified() {
+    let samples: [(&[u8], Sniff); 4] = [
+        (&[0x05, 0x01, 0x00], Sniff::Socks5),
+        (&[0x04, 0x01, 0x00, 0x50], Sniff::Socks4),
+        (b"CONNECT example.com:443 HTTP/1.1\r\n", Sniff::HttpLike),
+        (&[0x16, 0x03, 0x01], Sniff::Unknown(0x16)),
+    ];
+
+    for (first, sniff) in samples {
+        let (_client, conn) = accept(first).await;
+        assert_eq!(common::timeout(conn.peek_version()).await.unwrap(), sniff);
+    }
+}
+
+#[tokio::test]
+async fn peeked_bytes_are_not_consumed() {
+    // a SOCKS5 client is negotiated with as usual
+    let (mut client, conn) = accept(&[]).await;
+    let server = tokio::spawn(async move {
+        assert_eq!(conn.peek_version().await.unwrap(), Sniff::Socks5);
+        assert_eq!(conn.peek_version().await.unwrap(), Sniff::Socks5);
+        conn.authenticate().await.unwrap();
+    });
+
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    common::timeout(server).await.unwrap();
+
+    // the stream of another client is handed over untouched
+    const HTTP_REQUEST: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+    let (client, conn) = accept(HTTP_REQUEST).await;
+    assert_eq!(conn.peek_version().await.unwrap(), Sniff::HttpLike);
+    drop(client);
+
+    let mut buf = Vec::new();
+    conn.into_inner().read_to_end(&mut buf).await.unwrap();
+    assert_eq!(buf, HTTP_REQUEST);
+}
+
+#[tokio::test]
+async fn closed_client_is_an_eof() {
+    let (client, conn) = accept(&[]).await;
+    drop(client);
+
+    let err = common::timeout(conn.peek_version()).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::Client;
+use socks5_server::proto::{handshake::Method, Address, Command as ProtoCommand, Reply};
+use std::{
+    io::{BufRead, BufReader},
+    net::{Ipv4Addr, SocketAddr},
+    process::{Child, ChildStderr, Command, Output, Stdio},
+};
+
+/// A running `socks5d`, killed on drop
+struct Daemon {
+    child: Child,
+    addr: SocketAddr,
+    stderr: BufReader<ChildStderr>,
+}
+
+impl Daemon {
+    /// Starts `socks5d` with `args` on an ephemeral port, logging JSON lines, and waits until it listens.
+    fn start(args: &[&str]) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_socks5d"))
+            .args(["--listen", "127.0.0.1:0", "--log.format", "json"])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let line = read_line(&mut stderr);
+        assert!(line.contains(r#""event":"listening""#), "{line}");
+
+        Self {
+            child,
+            addr: json_field(&line, "addr").parse().unwrap(),
+            stderr,
+        }
+    }
+
+    /// Reads log lines until one of `event`, returning it.
+    fn event(&mut self, event: &str) -> String {
+        let event = format!(r#""event":"{event}""#);
+
+        loop {
+            let line = read_line(&mut self.stderr);
+
+            if line.contains(&event) {
+                return line;
+            }
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn read_line(stderr: &mut BufReader<ChildStderr>) -> String {
+    let mut line = String::new();
+    assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "socks5d exited");
+    line
+}
+
+/// Returns the string value of `key` in a JSON log line, still escaped.
+fn json_field<'a>(line: &'a str, key: &str) -> &'a str {
+    let start = line.find(&format!(r#""{key}":""#)).unwrap() + key.len() + 4;
+    let mut escaped = false;
+
+    let len = line[start..]
+        .find(|c| {
+            let end = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            end
+        })
+        .unwrap();
+
+    &line[start..start + len]
+}
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_socks5d"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn relays_for_authenticated_users() {
+    let echo = common::tcp_echo().await;
+    let mut daemon = Daemon::start(&[
+        "--username",
+        "user",
+        "--password",
+        "secret",
+        "--outbound.bind-ip",
+        "127.0.0.1",
+        "--log.verbose",
+    ]);
+
+    let mut client = Client::connect(daemon.addr).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(client.password(b"user", b"secret").await);
+
+    let resp = client
+        .request(ProtoCommand::Connect, Address::SocketAddress(echo))
+        .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    // the outbound connection is bound on --outbound.bind-ip
+    let Address::SocketAddress(bound) = resp.address else {
+        unreachable!()
+    };
+    assert_eq!(bound.ip(), Ipv4Addr::LOCALHOST);
+
+    client.write(b"hello").await;
+    assert_eq!(client.read(5).await, b"hello");
+
+    let line = daemon.event("command");
+    assert_eq!(json_field(&line, "command"), "Connect");
+    assert_eq!(json_field(&line, "dest"), echo.to_string());
+
+    let mut client = Client::connect(daemon.addr).await;
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    assert!(!client.password(b"user", b"wrong").await);
+}
+
+#[tokio::test]
+async fn denied_destinations_are_not_allowed() {
+    let echo = common::tcp_echo().await;
+    let mut daemon = Daemon::start(&["--deny", "127.0.0.0/8", "--no-udp"]);
+
+    let (_, resp) = Client::no_auth_request(
+        daemon.addr,
+        ProtoCommand::Connect,
+        Address::SocketAddress(echo),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::ConnectionNotAllowed);
+    assert!(json_field(&daemon.event("error"), "error").contains("denied"));
+
+    let (_, resp) =
+        Client::no_auth_request(daemon.addr, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::CommandNotSupported);
+}
+
+#[tokio::test]
+async fn server_config_flags_are_applied() {
+    let mut daemon = Daemon::start(&[
+        "--hostname-policy.reject-ip-literals",
+        "--parse-limits.max-domain-len",
+        "16",
+    ]);
+
+    let name = Address::DomainAddress(b"127.0.0.1".to_vec(), 80);
+    let (_, resp) = Client::no_auth_request(daemon.addr, ProtoCommand::Connect, name).await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+    assert!(json_field(&daemon.event("error"), "error").contains("IP"));
+
+    let name = Address::DomainAddress(b"a-very-long-name.example".to_vec(), 80);
+    let (_, resp) = Client::no_auth_request(daemon.addr, ProtoCommand::Connect, name).await;
+    assert_eq!(resp.reply, Reply::AddressTypeNotSupported);
+    assert!(json_field(&daemon.event("error"), "error").contains("too long"));
+}
+
+#[test]
+fn invalid_configurations_are_reported_by_flag() {
+    let output = run(&[
+        "--session-setup-deadline",
+        "0",
+        "--udp-relay.max-pkt-size",
+        "70000",
+    ]);
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("--session-setup-deadline: must be positive"));
+    assert!(stderr.contains("--udp-relay.max-pkt-size: "));
+
+    let output = run(&["--listen"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr)
+        .unwrap()
+        .contains("--listen needs a value"));
+
+    let output = run(&["--help"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .contains("--udp-relay.idle-timeout"));
+}
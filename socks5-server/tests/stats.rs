@@ -0,0 +1,125 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    proto::{Address, Command as ProtoCommand, Reply},
+    stats::{ServerStats, StatsSnapshot},
+    tracker::ConnectionTracker,
+    Command, Server,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io,
+    net::{TcpListener, TcpStream},
+};
+
+/// Starts a server counted in the returned stats, relaying `Connect` commands to `127.0.0.1` only and refusing the others.
+async fn server() -> (SocketAddr, ServerStats, ConnectionTracker) {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    assert!(server.stats().is_none());
+
+    let stats = ServerStats::new();
+    server.set_stats(stats.clone());
+    let addr = server.local_addr().unwrap();
+    let tracker = server.connection_tracker().clone();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(async move {
+                let Ok((conn, ())) = conn.authenticate().await else {
+                    return;
+                };
+
+                let Ok(Command::Connect(connect, Address::SocketAddress(addr))) = conn.wait().await
+                else {
+                    return;
+                };
+
+                if !addr.ip().is_loopback() {
+                    let _ = connect
+                        .reply(Reply::ConnectionNotAllowed, Address::unspecified())
+                        .await;
+                    return;
+                }
+
+                let mut target = TcpStream::connect(addr).await.unwrap();
+                let mut connect = connect
+                    .reply(Reply::Succeeded, Address::unspecified())
+                    .await
+                    .unwrap();
+
+                let _ = io::copy_bidirectional(&mut connect, &mut target).await;
+            });
+        }
+    });
+
+    (addr, stats, tracker)
+}
+
+#[tokio::test]
+async fn relayed_connection_is_counted() {
+    let (addr, stats, tracker) = server().await;
+    let echo = common::tcp_echo().await;
+
+    let (mut client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Connect, Address::SocketAddress(echo)).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    client.write(b"hello world").await;
+    assert_eq!(client.read(11).await, b"hello world");
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.accepted, 1);
+    assert_eq!(snapshot.active, 1);
+    assert_eq!(snapshot.established, 1);
+
+    drop(client);
+    assert!(tracker.await_idle_timeout(Duration::from_secs(5)).await);
+
+    assert_eq!(
+        stats.snapshot(),
+        StatsSnapshot {
+            accepted: 1,
+            filtered: 0,
+            accept_errors: 0,
+            active: 0,
+            established: 1,
+            failed: 0,
+            received: 11,
+            sent: 11,
+        }
+    );
+}
+
+#[tokio::test]
+async fn failed_and_refused_negotiations_are_not_established() {
+    let (addr, stats, tracker) = server().await;
+
+    // a SOCKS4 request
+    let mut client = Client::connect(addr).await;
+    client.write(&[0x04, 0x01, 0x00, 0x50]).await;
+    client.read_to_end().await;
+
+    let (client, resp) = Client::no_auth_request(
+        addr,
+        ProtoCommand::Connect,
+        Address::SocketAddress((Ipv4Addr::new(192, 0, 2, 1), 80).into()),
+    )
+    .await;
+    assert_eq!(resp.reply, Reply::ConnectionNotAllowed);
+    drop(client);
+
+    assert!(tracker.await_idle_timeout(Duration::from_secs(5)).await);
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.accepted, 2);
+    assert_eq!(snapshot.active, 0);
+    assert_eq!(snapshot.established, 0);
+    assert_eq!(snapshot.failed, 1);
+}
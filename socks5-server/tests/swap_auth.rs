@@ -0,0 +1,63 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::Password,
+    proto::handshake::{password::Error as PasswordError, Method},
+    Server,
+};
+use std::{net::Ipv4Addr, ptr, sync::Arc};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+type PasswordServer = Server<Result<bool, PasswordError>>;
+
+fn password(password: &[u8]) -> Arc<Password> {
+    Arc::new(Password::new(b"user".to_vec(), password.to_vec()))
+}
+
+/// Accepts a connection and authenticates it in a task of its own, returning whether it was let in.
+async fn accept(server: &PasswordServer) -> JoinHandle<bool> {
+    let (conn, _) = server.accept().await.unwrap();
+
+    tokio::spawn(async move {
+        let (_, res) = conn.authenticate().await.unwrap();
+        matches!(res, Ok(true))
+    })
+}
+
+async fn login(client: &mut Client, password: &[u8]) -> bool {
+    assert_eq!(
+        client.handshake(&[Method::PASSWORD]).await,
+        Method::PASSWORD
+    );
+    client.password(b"user", password).await
+}
+
+#[tokio::test]
+async fn new_connections_use_the_new_adaptor() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::new(listener, password(b"old") as Arc<_>);
+    let addr = server.local_addr().unwrap();
+
+    // accepted before the swap, authenticated after it
+    let mut before = Client::connect(addr).await;
+    let before_task = accept(&server).await;
+
+    let new = password(b"new");
+    server.set_auth(new.clone());
+    assert!(ptr::addr_eq(Arc::as_ptr(&server.auth()), Arc::as_ptr(&new)));
+
+    let mut after = Client::connect(addr).await;
+    let after_task = accept(&server).await;
+    let mut stale = Client::connect(addr).await;
+    let stale_task = accept(&server).await;
+
+    assert!(login(&mut before, b"old").await);
+    assert!(common::timeout(before_task).await.unwrap());
+
+    assert!(login(&mut after, b"new").await);
+    assert!(common::timeout(after_task).await.unwrap());
+
+    assert!(!login(&mut stale, b"old").await);
+    assert!(!common::timeout(stale_task).await.unwrap());
+}
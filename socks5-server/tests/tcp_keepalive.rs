@@ -0,0 +1,90 @@
+mod common;
+
+use socket2::TcpKeepalive;
+use socks5_server::{auth::NoAuth, Server};
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::net::TcpStream;
+
+#[tokio::test]
+async fn keepalive_is_off_by_default() {
+    let server = common::server().await;
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+
+    let (conn, _) = server.accept().await.unwrap();
+    assert!(!conn.tcp_keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn keepalive_is_set_on_accepted_connections() {
+    let mut server = common::server().await;
+    let keepalive = TcpKeepalive::new().with_time(Duration::from_secs(42));
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    let keepalive = keepalive
+        .with_interval(Duration::from_secs(7))
+        .with_retries(3);
+    server.set_tcp_keepalive(keepalive);
+
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (conn, _) = server.accept().await.unwrap();
+    assert!(conn.tcp_keepalive().unwrap());
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    {
+        let socket = socket2::SockRef::from(conn.get_ref());
+        assert_eq!(
+            socket.tcp_keepalive_time().unwrap(),
+            Duration::from_secs(42)
+        );
+        assert_eq!(
+            socket.tcp_keepalive_interval().unwrap(),
+            Duration::from_secs(7)
+        );
+        assert_eq!(socket.tcp_keepalive_retries().unwrap(), 3);
+    }
+
+    // connections accepted afterwards are left alone once cleared
+    server.clear_tcp_keepalive();
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (conn, _) = server.accept().await.unwrap();
+    assert!(!conn.tcp_keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn builder_sets_keepalive() {
+    let server = Server::builder(Arc::new(NoAuth) as Arc<_>)
+        .tcp_keepalive(TcpKeepalive::new().with_time(Duration::from_secs(60)))
+        .bind((Ipv4Addr::LOCALHOST, 0))
+        .await
+        .unwrap();
+
+    let _client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (conn, _) = server.accept().await.unwrap();
+    assert!(conn.tcp_keepalive().unwrap());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn keepalive_is_ignored_by_unix_listeners() {
+    use tokio::net::{UnixListener, UnixStream};
+
+    let path = std::env::temp_dir().join(format!(
+        "socks5-server-{}-keepalive.sock",
+        std::process::id()
+    ));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_tcp_keepalive(TcpKeepalive::new());
+
+    let _client = UnixStream::connect(&path).await.unwrap();
+    server.accept().await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::Client;
+use socket2::TcpKeepalive;
+use socks5_server::{
+    auth::NoAuth,
+    proto::{handshake::Method, Address, Command as ProtoCommand, Reply},
+    Command, Server,
+};
+use std::{net::Ipv4Addr, sync::Arc, time::Duration};
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn options_are_set_on_the_stream() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    let _client = Client::connect(server.local_addr().unwrap()).await;
+    let (conn, _) = server.accept().await.unwrap();
+
+    conn.set_nodelay(true).unwrap();
+    assert!(conn.nodelay().unwrap());
+    conn.set_nodelay(false).unwrap();
+    assert!(!conn.nodelay().unwrap());
+
+    conn.set_ttl(42).unwrap();
+    assert_eq!(conn.ttl().unwrap(), 42);
+
+    assert_eq!(conn.linger().unwrap(), None);
+    conn.set_linger(Some(Duration::from_secs(3))).unwrap();
+    assert_eq!(conn.linger().unwrap(), Some(Duration::from_secs(3)));
+    conn.set_linger(None).unwrap();
+    assert_eq!(conn.linger().unwrap(), None);
+
+    conn.set_tcp_keepalive(&TcpKeepalive::new().with_time(Duration::from_secs(60)))
+        .unwrap();
+    assert!(conn.tcp_keepalive().unwrap());
+    conn.clear_tcp_keepalive().unwrap();
+    assert!(!conn.tcp_keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn options_survive_into_the_command_types() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    let mut client = Client::connect(server.local_addr().unwrap()).await;
+    let (conn, _) = server.accept().await.unwrap();
+
+    // set before the handshake
+    conn.set_nodelay(true).unwrap();
+    conn.set_ttl(42).unwrap();
+
+    let server = tokio::spawn(async move {
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        assert!(conn.nodelay().unwrap());
+
+        let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+        assert!(connect.get_ref().nodelay().unwrap());
+
+        let connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+        assert!(connect.get_ref().nodelay().unwrap());
+        assert_eq!(connect.get_ref().ttl().unwrap(), 42);
+    });
+
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    let resp = client
+        .request(ProtoCommand::Connect, Address::unspecified())
+        .await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    common::timeout(server).await.unwrap();
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use bytes::BytesMut;
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, UdpRelayConfig},
+    proto::{Address, Command as ProtoCommand, Reply, UdpHeader},
+    Command,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::{self, TcpStream, UdpSocket};
+
+/// Starts a server relaying `Associate` commands with [`run_relay()`], performs the handshake and the associate command as a client, returning the control connection and the relay address.
+async fn associate() -> (TcpStream, SocketAddr) {
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, |conn| async move {
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => {
+                run_relay(associate, UdpRelayConfig::default())
+                    .await
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        }
+    })
+    .await;
+
+    let (client, resp) =
+        Client::no_auth_request(server, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(relay) = resp.address else {
+        unreachable!()
+    };
+
+    (client.stream, relay)
+}
+
+async fn send(client: &UdpSocket, relay: SocketAddr, dst: Address, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, dst).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+}
+
+/// Receives a datagram from the relay, returning its decapsulated header and payload.
+async fn recv(client: &UdpSocket, relay: SocketAddr) -> (UdpHeader, Vec<u8>) {
+    let mut buf = [0; 1500];
+    let (len, src) = client.recv_from(&mut buf).await.unwrap();
+    assert_eq!(src, relay);
+
+    let mut pkt = &buf[..len];
+    let header = UdpHeader::read_from_buf(&mut pkt).unwrap();
+    (header, pkt.to_vec())
+}
+
+#[tokio::test]
+async fn datagram_is_echoed_through_the_relay() {
+    let echo = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+    let (_stream, relay) = associate().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    send(&client, relay, Address::SocketAddress(echo), b"hello").await;
+
+    let (header, pkt) = recv(&client, relay).await;
+    assert_eq!(header.frag, 0);
+    assert_eq!(header.address, Address::SocketAddress(echo));
+    assert_eq!(pkt, b"hello");
+}
+
+#[tokio::test]
+async fn domain_destination_is_resolved() {
+    // the echo server listens where the relay resolves `localhost` to, whatever the hosts file of the machine says
+    let ip = net::lookup_host(("localhost", 0))
+        .await
+        .unwrap()
+        .next()
+        .unwrap()
+        .ip();
+    let echo = common::udp_echo(ip).await;
+
+    let (_stream, relay) = associate().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    send(
+        &client,
+        relay,
+        Address::DomainAddress(b"localhost".to_vec(), echo.port()),
+        b"hello localhost",
+    )
+    .await;
+
+    let (header, pkt) = recv(&client, relay).await;
+    assert_eq!(header.frag, 0);
+    assert_eq!(header.address, Address::SocketAddress(echo));
+    assert_eq!(pkt, b"hello localhost");
+}
+
+#[tokio::test]
+async fn malformed_datagram_does_not_kill_the_relay() {
+    let echo = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+    let (_stream, relay) = associate().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // a header with an unknown address type, then one cut in the middle of the address
+    client.send_to(&[0, 0, 0, 0xff, 1, 2], relay).await.unwrap();
+    client.send_to(&[0, 0, 0, 0x01, 127], relay).await.unwrap();
+
+    send(&client, relay, Address::SocketAddress(echo), b"still alive").await;
+
+    let (header, pkt) = recv(&client, relay).await;
+    assert_eq!(header.address, Address::SocketAddress(echo));
+    assert_eq!(pkt, b"still alive");
+}
@@ -0,0 +1,123 @@
+//! Runs `examples/udp_associate.rs` against a programmatic SOCKS5 client.
+
+#[allow(dead_code)]
+#[path = "../examples/udp_associate.rs"]
+mod example;
+
+use bytes::BytesMut;
+use socks5_proto::{
+    handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+    Address, Command, Reply, Request, Response, UdpHeader,
+};
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+async fn start_echo() -> SocketAddr {
+    let echo = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = echo.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = echo.recv_from(&mut buf).await.unwrap();
+            echo.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+async fn associate(server: SocketAddr) -> (TcpStream, SocketAddr) {
+    let mut stream = TcpStream::connect(server).await.unwrap();
+
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    let resp = HandshakeResponse::read_from(&mut stream).await.unwrap();
+    assert_eq!(resp.method, Method::NONE);
+
+    Request::new(Command::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    let resp = Response::read_from(&mut stream).await.unwrap();
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(relay) = resp.address else {
+        panic!("relay address is a domain name");
+    };
+    assert!(!relay.ip().is_unspecified());
+
+    (stream, relay)
+}
+
+async fn roundtrip(socket: &UdpSocket, relay: SocketAddr, dst: Address, payload: &[u8]) -> Address {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, dst).write_to_buf(&mut buf);
+    buf.extend_from_slice(payload);
+    socket.send_to(&buf, relay).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(src, relay);
+
+    let mut pkt = &buf[..len];
+    let header = UdpHeader::read_from_buf(&mut pkt).unwrap();
+    assert_eq!(header.frag, 0);
+    assert_eq!(pkt, payload);
+
+    header.address
+}
+
+#[tokio::test]
+async fn udp_associate_example() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let server = listener.local_addr().unwrap();
+    tokio::spawn(example::serve(listener));
+
+    let echo = start_echo().await;
+    let (control, relay) = associate(server).await;
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let src = roundtrip(&socket, relay, Address::SocketAddress(echo), b"ping").await;
+    assert_eq!(src, Address::SocketAddress(echo));
+
+    // a numeric host still goes through the resolver, without depending on how `localhost` resolves
+    let domain = Address::DomainAddress(b"127.0.0.1".to_vec(), echo.port());
+    let src = roundtrip(&socket, relay, domain, b"pong").await;
+    assert_eq!(src, Address::SocketAddress(echo));
+
+    // datagrams from other endpoints are not relayed
+    let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(echo)).write_to_buf(&mut buf);
+    buf.extend_from_slice(b"stranger");
+    stranger.send_to(&buf, relay).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let res = tokio::time::timeout(
+        std::time::Duration::from_millis(200),
+        stranger.recv_from(&mut buf),
+    )
+    .await;
+    assert!(res.is_err());
+
+    // closing the control connection tears the association down
+    drop(control);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(echo)).write_to_buf(&mut buf);
+    buf.extend_from_slice(b"late");
+    let _ = socket.send_to(&buf, relay).await;
+
+    let mut buf = [0; 1500];
+    let res = tokio::time::timeout(
+        std::time::Duration::from_millis(200),
+        socket.recv_from(&mut buf),
+    )
+    .await;
+    assert!(!matches!(res, Ok(Ok(_))));
+}
@@ -0,0 +1,111 @@
+use socks5_server::connection::associate::UdpAssociationTable;
+use std::{net::SocketAddr, time::Duration};
+use tokio::time;
+
+fn addr(port: u16) -> SocketAddr {
+    SocketAddr::from(([10, 0, 0, 1], port))
+}
+
+#[tokio::test]
+async fn least_recently_used_is_evicted_first() {
+    let table = UdpAssociationTable::new(3, Duration::from_secs(60), None);
+    let client = addr(5000);
+
+    for port in 1..=3 {
+        assert!(table.insert(client, addr(port), port));
+    }
+
+    // a returning datagram counts as use, so 2 is now the least recently used
+    assert_eq!(table.get(client, addr(1)), Some(1));
+
+    assert!(table.insert(client, addr(4), 4));
+    assert!(!table.contains(client, addr(2)));
+
+    assert!(table.insert(client, addr(5), 5));
+    assert!(!table.contains(client, addr(3)));
+
+    for port in [1, 4, 5] {
+        assert_eq!(table.get(client, addr(port)), Some(port));
+    }
+
+    let stats = table.stats();
+    assert_eq!(stats.entries, 3);
+    assert_eq!(stats.clients, 1);
+    assert_eq!(stats.inserted, 5);
+    assert_eq!(stats.evicted, 2);
+}
+
+#[tokio::test]
+async fn destinations_are_capped_per_client() {
+    let table = UdpAssociationTable::new(1024, Duration::from_secs(60), Some(2));
+    let (alice, bob) = (addr(5000), addr(6000));
+
+    assert!(table.insert(alice, addr(1), ()));
+    assert!(table.insert(alice, addr(2), ()));
+    assert!(!table.insert(alice, addr(3), ()));
+
+    // known destinations are still accepted at the cap, and other clients are not affected
+    assert!(table.insert(alice, addr(1), ()));
+    assert!(table.insert(bob, addr(3), ()));
+    assert_eq!(table.destinations(alice), 2);
+    assert_eq!(table.destinations(bob), 1);
+
+    assert_eq!(table.remove(alice, addr(1)), Some(()));
+    assert!(table.insert(alice, addr(3), ()));
+
+    assert_eq!(table.remove_client(alice), 2);
+    assert_eq!(table.destinations(alice), 0);
+
+    let stats = table.stats();
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.clients, 1);
+    assert_eq!(stats.rejected, 1);
+    assert_eq!(stats.evicted, 0);
+}
+
+#[tokio::test(start_paused = true)]
+async fn unused_entries_expire() {
+    let table = UdpAssociationTable::new(1024, Duration::from_secs(10), Some(1));
+    let client = addr(5000);
+
+    assert!(table.insert(client, addr(1), ()));
+
+    time::advance(Duration::from_secs(6)).await;
+    assert!(table.get(client, addr(1)).is_some());
+
+    // refreshed by the lookup above
+    time::advance(Duration::from_secs(6)).await;
+    assert!(table.contains(client, addr(1)));
+    assert!(!table.insert(client, addr(2), ()));
+
+    time::advance(Duration::from_secs(10)).await;
+    assert!(!table.contains(client, addr(1)));
+
+    // the expired destination no longer counts against the cap
+    assert!(table.insert(client, addr(2), ()));
+    assert_eq!(table.get(client, addr(1)), None);
+
+    let stats = table.stats();
+    assert_eq!(stats.entries, 1);
+    assert_eq!(stats.expired, 1);
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn expired_entries_can_be_purged() {
+    let table = UdpAssociationTable::new(1024, Duration::from_secs(10), None);
+
+    for port in 1..=4 {
+        table.insert(addr(port), addr(53), ());
+    }
+
+    time::advance(Duration::from_secs(5)).await;
+    table.insert(addr(5), addr(53), ());
+
+    time::advance(Duration::from_secs(5)).await;
+    assert_eq!(table.len(), 5);
+    assert_eq!(table.purge_expired(), 4);
+    assert_eq!(table.len(), 1);
+    assert!(table.contains(addr(5), addr(53)));
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use bytes::BytesMut;
+use common::encode_udp;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::AssociatedUdpSocket;
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn recv_batch_drops_malformed() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&encode_udp(b"a", dst), addr).await.unwrap();
+    peer.send_to(&[0xff; 4], addr).await.unwrap();
+    peer.send_to(&encode_udp(b"bc", dst), addr).await.unwrap();
+    peer.send_to(&encode_udp(b"def", dst), addr).await.unwrap();
+
+    let mut bufs = vec![BytesMut::new(); 8];
+    let mut payloads = Vec::new();
+
+    while payloads.len() < 3 {
+        let pkts = socket.recv_batch(&mut bufs).await.unwrap();
+
+        for (i, (header, range, src)) in pkts.into_iter().enumerate() {
+            assert_eq!(header.address, Address::SocketAddress(dst));
+            assert_eq!(src, peer.local_addr().unwrap());
+            payloads.push(bufs[i][range].to_vec());
+        }
+
+        bufs.iter_mut().for_each(BytesMut::clear);
+    }
+
+    assert_eq!(payloads, [&b"a"[..], b"bc", b"def"]);
+    assert_eq!(socket.stats().snapshot().dropped_malformed, 1);
+}
+
+#[tokio::test]
+async fn send_batch_stops_at_failed_packet() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    let oversized = vec![0; 70000];
+    let pkts = [
+        (&b"first"[..], header.clone(), peer_addr),
+        (&oversized[..], header.clone(), peer_addr),
+        (&b"third"[..], header.clone(), peer_addr),
+    ];
+
+    assert_eq!(socket.send_batch(&pkts).await.unwrap(), 1);
+    assert!(socket.send_batch(&pkts[1..]).await.is_err());
+    assert_eq!(socket.send_batch(&pkts[2..]).await.unwrap(), 1);
+
+    let mut buf = [0; 1500];
+
+    for expected in [&b"first"[..], b"third"] {
+        let len = peer.recv(&mut buf).await.unwrap();
+        assert!(buf[..len].ends_with(expected));
+    }
+
+    let stats = socket.stats().snapshot();
+    assert_eq!(stats.downlink_packets, 2);
+    assert_eq!(stats.downlink_bytes, 10);
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::encode_udp;
+use socks5_server::connection::associate::{AssociatedUdpSocket, ClientMatch};
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time};
+
+/// The destination of the datagrams of the client, which are never relayed
+const DST: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(1, 2, 3, 4), 5));
+
+struct Flow {
+    socket: AssociatedUdpSocket,
+    addr: SocketAddr,
+    old: UdpSocket,
+    new: UdpSocket,
+}
+
+impl Flow {
+    async fn new(matching: ClientMatch) -> Self {
+        let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+        let addr = socket.get_ref().local_addr().unwrap();
+        socket.learn_client([127, 0, 0, 1].into(), matching);
+
+        let flow = Self {
+            socket,
+            addr,
+            old: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+            new: UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        };
+
+        flow.old
+            .send_to(&encode_udp(b"first", DST), addr)
+            .await
+            .unwrap();
+        assert_eq!(flow.recv().await, (b"first".to_vec(), flow.old_addr()));
+        assert_eq!(flow.socket.expected_client(), Some(flow.old_addr()));
+
+        flow
+    }
+
+    fn old_addr(&self) -> SocketAddr {
+        self.old.local_addr().unwrap()
+    }
+
+    fn new_addr(&self) -> SocketAddr {
+        self.new.local_addr().unwrap()
+    }
+
+    async fn recv(&self) -> (Vec<u8>, SocketAddr) {
+        let (pkt, _, src) = self.socket.recv_from().await.unwrap();
+        (pkt.to_vec(), src)
+    }
+}
+
+#[tokio::test]
+async fn strict_drops_new_port() {
+    let flow = Flow::new(ClientMatch::Strict).await;
+
+    flow.new
+        .send_to(&encode_udp(b"moved", DST), flow.addr)
+        .await
+        .unwrap();
+    flow.old
+        .send_to(&encode_udp(b"stayed", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(flow.recv().await, (b"stayed".to_vec(), flow.old_addr()));
+    assert_eq!(flow.socket.expected_client(), Some(flow.old_addr()));
+    assert_eq!(flow.socket.filtered_count(), 1);
+}
+
+#[tokio::test]
+async fn same_ip_any_port_accepts_new_port_without_moving() {
+    let flow = Flow::new(ClientMatch::SameIpAnyPort).await;
+
+    flow.new
+        .send_to(&encode_udp(b"moved", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(flow.recv().await, (b"moved".to_vec(), flow.new_addr()));
+    assert_eq!(flow.socket.expected_client(), Some(flow.old_addr()));
+    assert_eq!(flow.socket.stats().snapshot().client_rebinds, 0);
+}
+
+#[tokio::test]
+async fn rebind_moves_expected_client() {
+    let flow = Flow::new(ClientMatch::Rebind {
+        min_interval: Duration::ZERO,
+    })
+    .await;
+
+    flow.new
+        .send_to(&encode_udp(b"moved", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(flow.recv().await, (b"moved".to_vec(), flow.new_addr()));
+    assert_eq!(flow.socket.expected_client(), Some(flow.new_addr()));
+    assert_eq!(flow.socket.stats().snapshot().client_rebinds, 1);
+}
+
+#[tokio::test]
+async fn rebind_is_rate_limited() {
+    let flow = Flow::new(ClientMatch::Rebind {
+        min_interval: Duration::from_millis(200),
+    })
+    .await;
+
+    flow.new
+        .send_to(&encode_udp(b"too soon", DST), flow.addr)
+        .await
+        .unwrap();
+    flow.old
+        .send_to(&encode_udp(b"stayed", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(flow.recv().await, (b"stayed".to_vec(), flow.old_addr()));
+    assert_eq!(flow.socket.expected_client(), Some(flow.old_addr()));
+    assert_eq!(flow.socket.filtered_count(), 1);
+
+    time::sleep(Duration::from_millis(300)).await;
+    flow.new
+        .send_to(&encode_udp(b"moved", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(flow.recv().await, (b"moved".to_vec(), flow.new_addr()));
+    assert_eq!(flow.socket.expected_client(), Some(flow.new_addr()));
+
+    flow.old
+        .send_to(&encode_udp(b"back", DST), flow.addr)
+        .await
+        .unwrap();
+    flow.new
+        .send_to(&encode_udp(b"still moved", DST), flow.addr)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        flow.recv().await,
+        (b"still moved".to_vec(), flow.new_addr())
+    );
+    assert_eq!(flow.socket.stats().snapshot().client_rebinds, 1);
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::Client;
+use socket2::SockRef;
+use socks5_server::{
+    connection::associate::{run_relay, ControlKeepalive, RelayResult, UdpRelayConfig},
+    proto::{Address, Command as ProtoCommand, Reply},
+    Command,
+};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn keepalive_is_enabled_on_the_control_connection() {
+    let server = common::server().await;
+    let addr = server.local_addr().unwrap();
+
+    let keepalive = ControlKeepalive {
+        time: Duration::from_secs(30),
+        interval: Duration::from_secs(7),
+        retries: 3,
+    };
+
+    let task = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let associate = associate
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+        assert!(!SockRef::from(associate.get_ref()).keepalive().unwrap());
+
+        associate.set_keepalive(&keepalive).unwrap();
+        let socket = SockRef::from(associate.get_ref());
+        assert!(socket.keepalive().unwrap());
+
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.tcp_keepalive_time().unwrap(), keepalive.time);
+            assert_eq!(socket.tcp_keepalive_interval().unwrap(), keepalive.interval);
+            assert_eq!(socket.tcp_keepalive_retries().unwrap(), keepalive.retries);
+        }
+    });
+
+    let (_client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+    task.await.unwrap();
+}
+
+/// Runs a relay with a short keepalive, then makes the client vanish without a trace by closing it in `TCP_REPAIR` mode, which needs `CAP_NET_ADMIN`. Real keepalive timing is slow and privileged, so this only runs with `SOCKS5_SERVER_KEEPALIVE_TEST` set.
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn vanished_client_tears_the_relay_down() {
+    use std::os::fd::AsRawFd;
+
+    if std::env::var_os("SOCKS5_SERVER_KEEPALIVE_TEST").is_none() {
+        return;
+    }
+
+    let server = common::server().await;
+    let addr = server.local_addr().unwrap();
+    let (tx, rx) = oneshot::channel::<RelayResult>();
+
+    tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+
+        let config = UdpRelayConfig {
+            control_keepalive: Some(ControlKeepalive {
+                time: Duration::from_secs(1),
+                interval: Duration::from_secs(1),
+                retries: 2,
+            }),
+            ..UdpRelayConfig::default()
+        };
+
+        let _ = tx.send(run_relay(associate, config).await);
+    });
+
+    let (client, resp) =
+        Client::no_auth_request(addr, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    // let the reply be acknowledged, or its retransmission would find the client gone without any keepalive
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // closed in repair mode, the socket goes away without sending a FIN or RST, as with a crashed client
+    let on: libc::c_int = 1;
+
+    // SAFETY: the descriptor is owned by `client`, and the option value is a `c_int` living across the call
+    let res = unsafe {
+        libc::setsockopt(
+            client.stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_REPAIR,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    assert_eq!(res, 0, "{}", std::io::Error::last_os_error());
+    drop(client);
+
+    // well within the UDP idle timeout, detected by the first probe after 1 second
+    let res = tokio::time::timeout(Duration::from_secs(15), rx)
+        .await
+        .expect("the vanished client was not detected")
+        .unwrap();
+
+    assert!(res.is_err(), "{res:?}");
+}
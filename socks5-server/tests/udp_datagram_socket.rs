@@ -0,0 +1,215 @@
+mod common;
+
+use common::encode_udp_with;
+use socks5_proto::{Address, Error as Socks5Error, UdpHeader};
+use socks5_server::connection::associate::{
+    AssociatedUdpSocket, ClientMatch, DatagramSocket, Truncated,
+};
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tokio::{
+    io::ReadBuf,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+};
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// An in-memory network routing datagrams between the [`ChannelSocket`]s bound on it
+#[derive(Clone, Default)]
+struct Network {
+    routes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Datagram>>>>,
+}
+
+impl Network {
+    fn bind(&self, addr: &str) -> ChannelSocket {
+        let local = addr.parse().unwrap();
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.lock().unwrap().insert(local, tx);
+
+        ChannelSocket {
+            local,
+            rx: Mutex::new(rx),
+            network: self.clone(),
+            error: Mutex::new(None),
+        }
+    }
+
+    fn route(&self, pkt: &[u8], src: SocketAddr, target: SocketAddr) {
+        // like UDP, datagrams to nowhere are silently lost
+        if let Some(tx) = self.routes.lock().unwrap().get(&target) {
+            let _ = tx.send((pkt.to_vec(), src));
+        }
+    }
+}
+
+struct ChannelSocket {
+    local: SocketAddr,
+    rx: Mutex<UnboundedReceiver<Datagram>>,
+    network: Network,
+    /// An error the next receive fails with, before any datagram
+    error: Mutex<Option<ErrorKind>>,
+}
+
+impl DatagramSocket for ChannelSocket {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>> {
+        if let Some(kind) = self.error.lock().unwrap().take() {
+            return Poll::Ready(Err(Error::from(kind)));
+        }
+
+        match self.rx.lock().unwrap().poll_recv(cx) {
+            Poll::Ready(Some((pkt, src))) => {
+                let len = pkt.len().min(buf.remaining());
+                buf.put_slice(&pkt[..len]);
+                Poll::Ready(Ok(src))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(Error::from(ErrorKind::BrokenPipe))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_send_to(
+        &self,
+        _: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        self.network.route(buf, self.local, target);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.local)
+    }
+}
+
+impl ChannelSocket {
+    fn send(&self, pkt: &[u8], target: SocketAddr) {
+        self.network.route(pkt, self.local, target);
+    }
+
+    fn recv(&self) -> Datagram {
+        self.rx.lock().unwrap().try_recv().unwrap()
+    }
+}
+
+#[tokio::test]
+async fn round_trip_over_channels() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 1500);
+    let client = network.bind("10.0.0.2:5000");
+    let addr = socket.get_ref().local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::DomainAddress(b"example.com".to_vec(), 53));
+
+    client.send(&encode_udp_with(b"query", &header), addr);
+
+    let (pkt, recv_header, src) = socket.recv_from().await.unwrap();
+    assert_eq!(&pkt[..], b"query");
+    assert_eq!(recv_header.address, header.address);
+    assert_eq!(src, client.local);
+
+    let reply = UdpHeader::new(0, Address::SocketAddress("1.1.1.1:53".parse().unwrap()));
+    assert_eq!(socket.send_to(b"answer", &reply, src).await.unwrap(), 6);
+    assert_eq!(client.recv(), (encode_udp_with(b"answer", &reply), addr));
+
+    assert_eq!(socket.stats().snapshot().downlink_packets, 1);
+}
+
+#[tokio::test]
+async fn other_sources_are_filtered() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 1500);
+    let client = network.bind("10.0.0.2:5000");
+    let intruder = network.bind("10.0.0.3:5000");
+    let addr = socket.get_ref().local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress("1.2.3.4:5".parse().unwrap()));
+
+    socket.learn_client(client.local.ip(), ClientMatch::Strict);
+
+    intruder.send(&encode_udp_with(b"spoofed", &header), addr);
+    client.send(&encode_udp_with(b"genuine", &header), addr);
+
+    let (pkt, _, src) = socket.recv_from().await.unwrap();
+    assert_eq!(&pkt[..], b"genuine");
+    assert_eq!(src, client.local);
+    assert_eq!(socket.expected_client(), Some(client.local));
+    assert_eq!(socket.filtered_count(), 1);
+}
+
+#[tokio::test]
+async fn oversize_is_detected_without_original_length() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 64);
+    let client = network.bind("10.0.0.2:5000");
+    let addr = socket.get_ref().local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress("1.2.3.4:5".parse().unwrap()));
+
+    client.send(&encode_udp_with(&[0; 4096], &header), addr);
+    client.send(&encode_udp_with(b"small", &header), addr);
+
+    let Err((Socks5Error::Io(err), Some(_))) = socket.recv_from().await else {
+        panic!("expected a truncated datagram");
+    };
+    let truncated = Truncated::from_io_error(&err).unwrap();
+    assert_eq!(truncated.max, 64);
+
+    let (pkt, _, _) = socket.recv_from_valid().await.unwrap();
+    assert_eq!(&pkt[..], b"small");
+    assert_eq!(socket.stats().snapshot().dropped_oversize, 1);
+}
+
+#[tokio::test]
+async fn socket_options_are_not_supported_without_udp_socket() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 1500);
+
+    assert_eq!(
+        socket.local_addr().unwrap(),
+        "10.0.0.1:1080".parse().unwrap()
+    );
+
+    let err = socket.set_recv_buffer_size(1 << 20).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+    assert_eq!(socket.ttl().unwrap_err().kind(), ErrorKind::Unsupported);
+}
+
+#[tokio::test]
+async fn connected_send_is_not_supported_by_default() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 1500);
+    let header = UdpHeader::new(0, Address::SocketAddress("1.2.3.4:5".parse().unwrap()));
+
+    let err = socket.send(b"payload", &header).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NotConnected);
+}
+
+#[tokio::test]
+async fn connection_resets_are_skipped_by_recv_from_valid() {
+    let network = Network::default();
+    let socket = AssociatedUdpSocket::new(network.bind("10.0.0.1:1080"), 1500);
+    let client = network.bind("10.0.0.2:5000");
+    let addr = socket.get_ref().local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress("1.2.3.4:5".parse().unwrap()));
+
+    // as reported on Windows after a datagram hit a closed port
+    *socket.get_ref().error.lock().unwrap() = Some(ErrorKind::ConnectionReset);
+    client.send(&encode_udp_with(b"payload", &header), addr);
+
+    let (pkt, _, src) = socket.recv_from_valid().await.unwrap();
+    assert_eq!(&pkt[..], b"payload");
+    assert_eq!(src, client.local);
+    assert_eq!(socket.stats().snapshot().connection_resets, 1);
+
+    // other errors are still returned
+    *socket.get_ref().error.lock().unwrap() = Some(ErrorKind::NetworkDown);
+    let err = socket.recv_from_valid().await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::NetworkDown);
+}
@@ -0,0 +1,75 @@
+use socks5_server::{
+    auth::NoAuth,
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response,
+    },
+    Command, Server,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Associates with `declared` as the client UDP endpoint, returning what the server saw as the declared address and the expected client of the socket from `reply_with_socket()`.
+async fn associate(declared: Address) -> (Address, Option<SocketAddr>) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    let client = tokio::spawn(async move {
+        let mut stream = TcpStream::connect(server_addr).await.unwrap();
+        HandshakeRequest::new(vec![Method::NONE])
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        HandshakeResponse::read_from(&mut stream).await.unwrap();
+        Request::new(ProtoCommand::Associate, declared)
+            .write_to(&mut stream)
+            .await
+            .unwrap();
+        Response::read_from(&mut stream).await.unwrap();
+        stream
+    });
+
+    let (conn, _) = server.accept().await.unwrap();
+    let (conn, _) = conn.authenticate().await.unwrap();
+
+    let Command::Associate(associate, addr) = conn.wait().await.unwrap() else {
+        unreachable!()
+    };
+
+    let declared = associate.client_declared_addr().clone();
+    assert_eq!(declared, addr);
+
+    let (_associate, socket) = associate.reply_with_socket(None, 1500).await.unwrap();
+    let _stream = client.await.unwrap();
+
+    (declared, socket.expected_client())
+}
+
+#[tokio::test]
+async fn declared_endpoint_is_enforced() {
+    let declared = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let (addr, expected) = associate(Address::SocketAddress(declared)).await;
+
+    assert_eq!(addr, Address::SocketAddress(declared));
+    assert_eq!(expected, Some(declared));
+}
+
+#[tokio::test]
+async fn unknown_endpoint_is_learned() {
+    let (addr, expected) = associate(Address::unspecified()).await;
+
+    assert_eq!(addr, Address::unspecified());
+    assert_eq!(expected, None);
+}
+
+#[tokio::test]
+async fn endpoint_behind_nat_is_learned() {
+    let declared = SocketAddr::from(([192, 168, 1, 5], 5000));
+    let (addr, expected) = associate(Address::SocketAddress(declared)).await;
+
+    assert_eq!(addr, Address::SocketAddress(declared));
+    assert_eq!(expected, None);
+}
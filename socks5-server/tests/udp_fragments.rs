@@ -0,0 +1,141 @@
+mod common;
+
+use bytes::BytesMut;
+use common::encode_udp_with;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, DropReason};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::UdpSocket;
+
+fn header(frag: u8) -> UdpHeader {
+    UdpHeader::new(
+        frag,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    )
+}
+
+#[tokio::test]
+async fn fragments_are_dropped_by_default() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let hook = dropped.clone();
+    socket.on_dropped_fragment(move |header, src| hook.lock().unwrap().push((header.frag, src)));
+
+    assert!(!socket.fragments_allowed());
+
+    peer.send_to(&encode_udp_with(b"first half", &header(1)), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp_with(b"whole", &header(0)), addr)
+        .await
+        .unwrap();
+
+    let (pkt, recv_header, _) = socket.recv_from().await.unwrap();
+    assert_eq!(&pkt[..], b"whole");
+    assert_eq!(recv_header.frag, 0);
+
+    assert_eq!(socket.stats().dropped(DropReason::Fragment), 1);
+    assert_eq!(*dropped.lock().unwrap(), [(1, peer.local_addr().unwrap())]);
+}
+
+#[tokio::test]
+async fn fragments_are_delivered_when_allowed() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    socket.allow_fragments(true);
+
+    peer.send_to(&encode_udp_with(b"first half", &header(1)), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp_with(b"second half", &header(0x82)), addr)
+        .await
+        .unwrap();
+
+    let (pkt, recv_header, _) = socket.recv_from_valid().await.unwrap();
+    assert_eq!(&pkt[..], b"first half");
+    assert_eq!(recv_header.frag, 1);
+
+    let (pkt, recv_header, _) = socket.recv_from_valid().await.unwrap();
+    assert_eq!(&pkt[..], b"second half");
+    assert_eq!(recv_header.frag, 0x82);
+
+    assert_eq!(socket.stats().dropped(DropReason::Fragment), 0);
+}
+
+#[tokio::test]
+async fn fragments_are_dropped_from_batches() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    for (pkt, frag) in [(&b"a"[..], 0), (b"b", 1), (b"c", 0)] {
+        peer.send_to(&encode_udp_with(pkt, &header(frag)), addr)
+            .await
+            .unwrap();
+    }
+
+    let mut bufs = vec![BytesMut::new(); 4];
+    let mut payloads = Vec::new();
+
+    while payloads.len() < 2 {
+        let pkts = socket.recv_batch(&mut bufs).await.unwrap();
+
+        for (i, (_, range, _)) in pkts.into_iter().enumerate() {
+            payloads.push(bufs[i][range].to_vec());
+        }
+
+        bufs.iter_mut().for_each(BytesMut::clear);
+    }
+
+    assert_eq!(payloads, [&b"a"[..], b"c"]);
+    assert_eq!(socket.stats().dropped(DropReason::Fragment), 1);
+}
+
+#[tokio::test]
+async fn drop_hook_can_replace_itself() {
+    let socket = Arc::new(AssociatedUdpSocket::new(
+        UdpSocket::bind("127.0.0.1:0").await.unwrap(),
+        1500,
+    ));
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // the hook is called without the lock of the socket held, so it can call back into it
+    let dropped = Arc::new(Mutex::new(Vec::new()));
+    let (hook, weak) = (dropped.clone(), Arc::downgrade(&socket));
+    socket.on_drop(move |reason| {
+        hook.lock().unwrap().push(("first", reason));
+
+        let hook = hook.clone();
+        weak.upgrade()
+            .unwrap()
+            .on_drop(move |reason| hook.lock().unwrap().push(("second", reason)));
+    });
+
+    for _ in 0..2 {
+        peer.send_to(&encode_udp_with(b"fragment", &header(1)), addr)
+            .await
+            .unwrap();
+    }
+    peer.send_to(&encode_udp_with(b"whole", &header(0)), addr)
+        .await
+        .unwrap();
+
+    let (pkt, _, _) = socket.recv_from().await.unwrap();
+    assert_eq!(&pkt[..], b"whole");
+    assert_eq!(
+        *dropped.lock().unwrap(),
+        [
+            ("first", DropReason::Fragment),
+            ("second", DropReason::Fragment)
+        ]
+    );
+}
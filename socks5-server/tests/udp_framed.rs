@@ -0,0 +1,78 @@
+mod common;
+
+use bytes::Bytes;
+use common::encode_udp;
+use futures_util::{SinkExt, StreamExt};
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, PacketTooLarge};
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn stream_yields_packets() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let mut framed = socket.framed();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&encode_udp(b"hello", dst), addr)
+        .await
+        .unwrap();
+    peer.send_to(&[0xff; 4], addr).await.unwrap();
+    peer.send_to(&encode_udp(b"world", dst), addr)
+        .await
+        .unwrap();
+
+    let (header, pkt, src) = framed.next().await.unwrap().unwrap();
+    assert_eq!(header.address, Address::SocketAddress(dst));
+    assert_eq!(pkt, "hello");
+    assert_eq!(src, peer.local_addr().unwrap());
+
+    let (_, pkt, _) = framed.next().await.unwrap().unwrap();
+    assert_eq!(pkt, "world");
+    assert_eq!(framed.get_ref().stats().snapshot().dropped_malformed, 1);
+}
+
+#[tokio::test]
+async fn sink_sends_packets() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let mut framed = socket.framed();
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    framed
+        .send((header.clone(), Bytes::from_static(b"ping"), peer_addr))
+        .await
+        .unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], &encode_udp(b"ping", peer_addr)[..]);
+
+    let err = framed
+        .send((header, Bytes::from(vec![0; 70 * 1024]), peer_addr))
+        .await
+        .unwrap_err();
+    assert!(PacketTooLarge::from_io_error(&err).is_some());
+}
+
+#[tokio::test]
+async fn stream_items_echo_through_sink() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let mut framed = socket.framed();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&encode_udp(b"echo", dst), addr).await.unwrap();
+
+    let item = framed.next().await.unwrap().unwrap();
+    framed.send(item).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], &encode_udp(b"echo", dst)[..]);
+}
@@ -0,0 +1,188 @@
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{
+        run_relay, AssociatedUdpSocket, UdpRelayConfig, UdpRelayStats, UdpSocketOptions,
+    },
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(239, 255, 77, 77);
+
+/// Binds a socket joined to [`GROUP`] on the loopback interface, answering every datagram from its own unicast address, like an SSDP responder. Returns `None` if multicast is not supported on the loopback interface.
+async fn member() -> Option<SocketAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+    if let Err(err) = socket.join_multicast_v4(GROUP, Ipv4Addr::LOCALHOST) {
+        eprintln!("multicast is not supported on the loopback interface, skipping: {err}");
+        return None;
+    }
+
+    let port = socket.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    Some(SocketAddr::from((GROUP, port)))
+}
+
+/// Starts a relay with `config` and associates with it, returning the control connection and the relay address.
+async fn associate(config: UdpRelayConfig) -> (TcpStream, SocketAddr) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => run_relay(associate, config).await.unwrap(),
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    (stream, relay)
+}
+
+async fn send(client: &UdpSocket, relay: SocketAddr, dst: SocketAddr, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(dst)).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+}
+
+async fn recv(client: &UdpSocket) -> (Address, Vec<u8>) {
+    let mut buf = [0; 1500];
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    let mut pkt = &buf[..len];
+    let header = UdpHeader::read_from_buf(&mut pkt).unwrap();
+    (header.address, pkt.to_vec())
+}
+
+fn loopback_multicast() -> UdpSocketOptions {
+    UdpSocketOptions {
+        multicast_if_v4: Some(Ipv4Addr::LOCALHOST),
+        ..Default::default()
+    }
+}
+
+#[tokio::test]
+async fn associated_socket_joins_a_group() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("0.0.0.0:0").await.unwrap(), 1500);
+    if let Err(err) = socket.join_multicast_v4(GROUP, Ipv4Addr::LOCALHOST) {
+        eprintln!("multicast is not supported on the loopback interface, skipping: {err}");
+        return;
+    }
+
+    let group = SocketAddr::from((GROUP, socket.local_addr().unwrap().port()));
+
+    socket.set_multicast_loop(false).unwrap();
+    assert!(!socket.multicast_loop().unwrap());
+    socket.set_multicast_loop(true).unwrap();
+    assert!(socket.multicast_loop().unwrap());
+
+    socket.set_broadcast(true).unwrap();
+    assert!(socket.broadcast().unwrap());
+
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    loopback_multicast().apply(&sender).unwrap();
+    send(&sender, group, group, b"to the group").await;
+
+    let (pkt, header, src) = socket.recv_from().await.unwrap();
+    assert_eq!(pkt, &b"to the group"[..]);
+    assert_eq!(header.address, Address::SocketAddress(group));
+    assert_eq!(src, sender.local_addr().unwrap());
+
+    socket
+        .leave_multicast_v4(GROUP, Ipv4Addr::LOCALHOST)
+        .unwrap();
+}
+
+#[tokio::test]
+async fn replies_of_group_members_reach_the_client() {
+    let Some(group) = member().await else {
+        return;
+    };
+
+    let (_stream, relay) = associate(UdpRelayConfig {
+        allow_multicast: true,
+        restrict_return: true,
+        outbound_options: loopback_multicast(),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&client, relay, group, b"M-SEARCH").await;
+
+    // the member answers from its unicast address, which the client never sent to
+    let (addr, pkt) = recv(&client).await;
+    assert_eq!(
+        addr,
+        Address::SocketAddress(SocketAddr::from((Ipv4Addr::LOCALHOST, group.port())))
+    );
+    assert_eq!(pkt, b"M-SEARCH");
+}
+
+#[tokio::test]
+async fn multicast_is_denied_by_default() {
+    let Some(group) = member().await else {
+        return;
+    };
+
+    let stats = UdpRelayStats::new();
+    let (_stream, relay) = associate(UdpRelayConfig {
+        outbound_options: loopback_multicast(),
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&client, relay, group, b"M-SEARCH").await;
+
+    // datagrams are handled in order, so the group was denied once the member answers this
+    let member = SocketAddr::from((Ipv4Addr::LOCALHOST, group.port()));
+    send(&client, relay, member, b"unicast").await;
+
+    let (addr, pkt) = recv(&client).await;
+    assert_eq!(addr, Address::SocketAddress(member));
+    assert_eq!(pkt, b"unicast");
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.dropped_denied, 1);
+    assert_eq!(stats.uplink_packets, 1);
+}
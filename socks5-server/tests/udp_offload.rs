@@ -0,0 +1,168 @@
+#![cfg(target_os = "linux")]
+
+mod common;
+
+use bytes::BytesMut;
+use common::encode_udp_with;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, PacketTooLarge};
+use std::{io::ErrorKind, net::SocketAddr};
+use tokio::net::UdpSocket;
+
+async fn recv(peer: &UdpSocket) -> Vec<u8> {
+    let mut buf = vec![0; 2048];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    buf.truncate(len);
+    buf
+}
+
+#[tokio::test]
+async fn segments_are_sent_as_separate_datagrams() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    );
+
+    if socket.max_gso_segments() == 1 {
+        eprintln!("UDP_SEGMENT is not supported, testing the fallback");
+    }
+
+    // more segments than fit in one GSO send, with a shorter last one
+    let segment_size = 100;
+    let pkts = (0..100 * segment_size + 42)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+
+    let sent = socket
+        .send_segments_to(&pkts, segment_size, &header, peer_addr)
+        .await
+        .unwrap();
+    assert_eq!(sent, pkts.len());
+
+    for pkt in pkts.chunks(segment_size) {
+        assert_eq!(recv(&peer).await, encode_udp_with(pkt, &header));
+    }
+
+    assert_eq!(socket.stats().snapshot().downlink_packets, 101);
+}
+
+#[tokio::test]
+async fn segment_size_is_checked() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer_addr = SocketAddr::from(([127, 0, 0, 1], 9));
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    let err = socket
+        .send_segments_to(b"payload", 0, &header, peer_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    socket.set_max_send_pkt_size(header.serialized_len() + 4);
+    let err = socket
+        .send_segments_to(b"12345678", 5, &header, peer_addr)
+        .await
+        .unwrap_err();
+    assert!(PacketTooLarge::from_io_error(&err).is_some());
+}
+
+#[tokio::test]
+async fn coalesced_datagrams_are_split() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let sender = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let header = UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    );
+
+    // loopback only hands GSO sends to a GRO socket in one piece
+    let offload = socket.set_gro(true).is_ok() && sender.max_gso_segments() > 1;
+
+    if offload {
+        assert!(socket.gro().unwrap());
+    } else {
+        eprintln!("UDP_GRO or UDP_SEGMENT is not supported, testing without them");
+    }
+
+    let segment_size = 64;
+    let pkts = (0..32 * segment_size)
+        .map(|i| (i / segment_size) as u8)
+        .collect::<Vec<_>>();
+
+    sender
+        .send_segments_to(&pkts, segment_size, &header, addr)
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    let mut largest = 0;
+    let mut buf = BytesMut::new();
+
+    while received.len() < pkts.len() {
+        let (segments, src) = socket.recv_segments_from(&mut buf).await.unwrap();
+        assert_eq!(src, sender.get_ref().local_addr().unwrap());
+        largest = largest.max(segments.len());
+
+        for (pkt_header, range) in segments {
+            assert_eq!(pkt_header.address, header.address);
+            received.extend_from_slice(&buf[range]);
+        }
+
+        buf.clear();
+    }
+
+    assert_eq!(received, pkts);
+
+    if offload {
+        assert!(largest > 1);
+    }
+}
+
+#[tokio::test]
+async fn malformed_datagrams_are_dropped() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 64);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let header = UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    );
+
+    let _ = socket.set_gro(true);
+
+    peer.send_to(&encode_udp_with(b"first", &header), addr)
+        .await
+        .unwrap();
+    peer.send_to(b"\x00\x00\x00\x09garbage", addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp_with(&[0; 128], &header), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp_with(b"second", &header), addr)
+        .await
+        .unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = BytesMut::new();
+
+    while received.len() < 2 {
+        let (segments, _) = socket.recv_segments_from(&mut buf).await.unwrap();
+
+        for (_, range) in segments {
+            received.push(buf[range].to_vec());
+        }
+
+        buf.clear();
+    }
+
+    assert_eq!(received, [&b"first"[..], b"second"]);
+
+    let stats = socket.stats().snapshot();
+    assert_eq!(stats.dropped_malformed, 1);
+    assert_eq!(stats.dropped_oversize, 1);
+}
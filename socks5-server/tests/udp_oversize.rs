@@ -0,0 +1,141 @@
+mod common;
+
+use bytes::BytesMut;
+use common::encode_udp;
+use socks5_proto::{Address, Error as Socks5Error, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, PacketTooLarge, Truncated};
+use std::{io::ErrorKind, net::SocketAddr};
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn send_rejects_packet_too_large() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    let pkt = vec![0; 70 * 1024];
+    let err = socket.send_to(&pkt, &header, peer_addr).await.unwrap_err();
+    let too_large = PacketTooLarge::from_io_error(&err).unwrap();
+
+    assert_eq!(too_large.len, header.serialized_len() + pkt.len());
+    assert_eq!(too_large.max, 65507);
+    assert_eq!(socket.stats().snapshot().dropped_oversize, 1);
+}
+
+#[tokio::test]
+async fn send_max_is_configurable() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    socket.set_max_send_pkt_size(header.serialized_len() + 4);
+    assert_eq!(
+        socket.send_to(b"fits", &header, peer_addr).await.unwrap(),
+        4
+    );
+
+    let err = socket
+        .send_to(b"too long", &header, peer_addr)
+        .await
+        .unwrap_err();
+    assert!(PacketTooLarge::from_io_error(&err).is_some());
+
+    let pkts = [
+        (&b"ok"[..], header.clone(), peer_addr),
+        (&b"too long"[..], header.clone(), peer_addr),
+    ];
+    assert_eq!(socket.send_batch(&pkts).await.unwrap(), 1);
+    assert!(socket.send_batch(&pkts[1..]).await.is_err());
+
+    let mut buf = [0; 1500];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[header.serialized_len()..len], b"fits");
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[header.serialized_len()..len], b"ok");
+}
+
+#[tokio::test]
+async fn send_rejects_domain_too_long() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::DomainAddress(vec![b'a'; 256], 443));
+
+    let err = socket
+        .send_to(b"ping", &header, peer_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let mut buf = [0; AssociatedUdpSocket::MAX_HEADER_LEN + 4];
+    let headroom = buf.len() - 4;
+    let err = socket
+        .send_to_with_headroom(&mut buf, headroom, &header, peer_addr)
+        .await
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+    let ok = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+    let pkts = [
+        (&b"ok"[..], ok, peer_addr),
+        (&b"ping"[..], header, peer_addr),
+    ];
+    assert_eq!(socket.send_batch(&pkts).await.unwrap(), 1);
+    let err = socket.send_batch(&pkts[1..]).await.unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn recv_reports_truncated() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let large = encode_udp(&vec![0; 60 * 1024], dst);
+    peer.send_to(&large, addr).await.unwrap();
+    peer.send_to(&encode_udp(b"small", dst), addr)
+        .await
+        .unwrap();
+
+    let mut buf = BytesMut::new();
+    let Err((Socks5Error::Io(err), Some(range))) = socket.recv_from_buf(&mut buf).await else {
+        panic!("expected a truncated datagram");
+    };
+    let truncated = Truncated::from_io_error(&err).unwrap();
+
+    assert_eq!(truncated.max, 1500);
+    assert!(range.len() < large.len());
+    if cfg!(target_os = "linux") {
+        assert_eq!(truncated.len, Some(large.len()));
+    }
+
+    buf.clear();
+    let (_, range, _) = socket.recv_from_buf(&mut buf).await.unwrap();
+    assert_eq!(&buf[range], b"small");
+    assert_eq!(socket.stats().snapshot().dropped_oversize, 1);
+}
+
+#[tokio::test]
+async fn recv_batch_drops_truncated() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    peer.send_to(&encode_udp(&vec![0; 60 * 1024], dst), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp(b"small", dst), addr)
+        .await
+        .unwrap();
+
+    let mut bufs = vec![BytesMut::new(); 4];
+    let pkts = socket.recv_batch(&mut bufs).await.unwrap();
+
+    assert_eq!(pkts.len(), 1);
+    assert_eq!(&bufs[0][pkts[0].1.clone()], b"small");
+    assert_eq!(socket.stats().snapshot().dropped_oversize, 1);
+}
@@ -0,0 +1,241 @@
+mod common;
+
+use common::encode_udp;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{
+        run_relay, RateLimit, RateLimiter, UdpRelayConfig, UdpRelayStats, UdpRelayStatsSnapshot,
+    },
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response,
+    },
+    Command, Server,
+};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task,
+    time::{self, Instant},
+};
+
+/// 100 datagrams per second, with a burst of 10
+const LIMIT: RateLimit = RateLimit {
+    packets_per_sec: Some(100),
+    bytes_per_sec: None,
+    burst: Duration::from_millis(100),
+};
+
+/// Each tick offers 2 datagrams, so 200 datagrams per second, twice the limit
+const TICK: Duration = Duration::from_millis(10);
+const TICKS: u64 = 100;
+
+/// The burst of 10, plus one datagram per tick after the first
+const PASSED: u64 = 10 + (TICKS - 1);
+const DROPPED: u64 = 2 * TICKS - PASSED;
+
+#[tokio::test(start_paused = true)]
+async fn limiter_drops_over_the_rate() {
+    let mut limiter = RateLimiter::new(&LIMIT);
+    let start = Instant::now();
+    let mut passed = 0;
+
+    for tick in 0..TICKS as u32 {
+        let now = start + TICK * tick;
+
+        for _ in 0..2 {
+            passed += u64::from(limiter.try_acquire(1200, now));
+        }
+    }
+
+    assert_eq!(passed, PASSED);
+}
+
+#[tokio::test(start_paused = true)]
+async fn limiter_enforces_both_packets_and_bytes() {
+    let mut limiter = RateLimiter::new(&RateLimit {
+        packets_per_sec: Some(1000),
+        bytes_per_sec: Some(100_000),
+        burst: Duration::from_millis(10),
+    });
+    let now = Instant::now();
+
+    // the byte bucket holds 1000 bytes, the packet bucket 10 datagrams
+    assert!(limiter.try_acquire(600, now));
+    assert!(!limiter.try_acquire(600, now));
+
+    // a datagram over the byte limit takes no packet token
+    for _ in 0..9 {
+        assert!(limiter.try_acquire(0, now));
+    }
+    assert!(!limiter.try_acquire(0, now));
+
+    assert!(limiter.try_acquire(600, now + Duration::from_millis(10)));
+}
+
+#[tokio::test]
+async fn unlimited_passes_everything() {
+    let mut limiter = RateLimiter::new(&RateLimit::default());
+    let now = Instant::now();
+
+    assert!(RateLimit::default().is_unlimited());
+    assert!((0..10_000).all(|_| limiter.try_acquire(65507, now)));
+}
+
+/// Starts a relay with `config` and associates with it, returning the control connection and the relay address.
+async fn associate(config: UdpRelayConfig) -> (TcpStream, SocketAddr) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => {
+                run_relay(associate, config).await.unwrap();
+            }
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    (stream, relay)
+}
+
+/// Lets the relay task run until `done` holds for its statistics, without advancing the paused clock.
+async fn settle(stats: &UdpRelayStats, done: impl Fn(&UdpRelayStatsSnapshot) -> bool) {
+    while !done(&stats.snapshot()) {
+        task::yield_now().await;
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn relay_drops_uplink_over_the_rate() {
+    let stats = UdpRelayStats::new();
+    let (_stream, relay) = associate(UdpRelayConfig {
+        idle_timeout: None,
+        uplink_limit: LIMIT,
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dst = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let pkt = encode_udp(b"payload", dst.local_addr().unwrap());
+
+    for tick in 1..=TICKS {
+        client.send_to(&pkt, relay).await.unwrap();
+        client.send_to(&pkt, relay).await.unwrap();
+
+        settle(&stats, |stats| {
+            stats.uplink_packets + stats.dropped_rate_limited == 2 * tick
+        })
+        .await;
+
+        time::advance(TICK).await;
+    }
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.uplink_packets, PASSED);
+    assert_eq!(stats.dropped_rate_limited, DROPPED);
+}
+
+#[tokio::test(start_paused = true)]
+async fn relay_drops_downlink_over_the_rate() {
+    let stats = UdpRelayStats::new();
+    let (_stream, relay) = associate(UdpRelayConfig {
+        idle_timeout: None,
+        downlink_limit: LIMIT,
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dst = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let pkt = encode_udp(b"ping", dst.local_addr().unwrap());
+    let mut buf = [0; 1500];
+
+    for tick in 1..=TICKS {
+        client.send_to(&pkt, relay).await.unwrap();
+        let (_, outbound) = dst.recv_from(&mut buf).await.unwrap();
+
+        dst.send_to(b"pong", outbound).await.unwrap();
+        dst.send_to(b"pong", outbound).await.unwrap();
+
+        settle(&stats, |stats| {
+            stats.downlink_packets + stats.dropped_rate_limited == 2 * tick
+        })
+        .await;
+
+        time::advance(TICK).await;
+    }
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.uplink_packets, TICKS);
+    assert_eq!(stats.downlink_packets, PASSED);
+    assert_eq!(stats.dropped_rate_limited, DROPPED);
+}
+
+#[tokio::test(start_paused = true)]
+async fn rate_limited_datagrams_take_no_destination() {
+    let stats = UdpRelayStats::new();
+    let (_stream, relay) = associate(UdpRelayConfig {
+        idle_timeout: None,
+        // a single datagram per 10ms
+        uplink_limit: RateLimit {
+            packets_per_sec: Some(100),
+            bytes_per_sec: None,
+            burst: TICK,
+        },
+        max_destinations: Some(2),
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut dsts = Vec::new();
+
+    for _ in 0..3 {
+        dsts.push(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+    }
+
+    let encoded = |idx: usize| encode_udp(b"payload", dsts[idx].local_addr().unwrap());
+
+    client.send_to(&encoded(0), relay).await.unwrap();
+    settle(&stats, |stats| stats.uplink_packets == 1).await;
+
+    client.send_to(&encoded(1), relay).await.unwrap();
+    settle(&stats, |stats| stats.dropped_rate_limited == 1).await;
+
+    // the second destination was never tracked, so the third one still fits
+    time::advance(TICK).await;
+    client.send_to(&encoded(2), relay).await.unwrap();
+    settle(&stats, |stats| stats.uplink_packets == 2).await;
+
+    let mut buf = [0; 1500];
+    let (len, _) = dsts[2].recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], b"payload");
+    assert_eq!(stats.snapshot().dropped_denied, 0);
+}
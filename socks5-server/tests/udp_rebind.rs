@@ -0,0 +1,200 @@
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{
+        run_relay, DropReason, RebindPolicy, RelayResult, UdpRelayConfig, UdpRelayStats,
+    },
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinHandle,
+    time,
+};
+
+/// Sending to the limited broadcast address without `SO_BROADCAST` fails with `EACCES`, which stands in for the errors of a flapping interface, as the policy of the tests rebinds on it.
+const BROADCAST: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), 9);
+
+fn policy(max_attempts: u32, strict: bool) -> RebindPolicy {
+    RebindPolicy {
+        errors: vec![ErrorKind::PermissionDenied],
+        max_attempts,
+        backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(10),
+        strict,
+    }
+}
+
+async fn echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+/// Starts a relay with `config` and associates with it, returning the relay task, the control connection and the relay address.
+async fn associate(config: UdpRelayConfig) -> (JoinHandle<RelayResult>, TcpStream, SocketAddr) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    let relay_task = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => run_relay(associate, config).await,
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    (relay_task, stream, relay)
+}
+
+async fn send(client: &UdpSocket, relay: SocketAddr, dst: SocketAddr, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(dst)).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+}
+
+async fn roundtrip(client: &UdpSocket, relay: SocketAddr, dst: SocketAddr, pkt: &[u8]) {
+    send(client, relay, dst, pkt).await;
+
+    let mut buf = [0; 1500];
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    let mut reply = &buf[..len];
+    UdpHeader::read_from_buf(&mut reply).unwrap();
+    assert_eq!(reply, pkt);
+}
+
+#[tokio::test]
+async fn failing_socket_is_rebound() {
+    let echo = echo().await;
+    let stats = UdpRelayStats::new();
+    let (_relay_task, _stream, relay) = associate(UdpRelayConfig {
+        rebind: Some(policy(2, true)),
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    roundtrip(&client, relay, echo, b"before").await;
+
+    // datagrams going through the fresh socket reset the rebinds in a row, so this never exhausts the policy
+    for _ in 0..3 {
+        send(&client, relay, BROADCAST, b"fails").await;
+        roundtrip(&client, relay, echo, b"after").await;
+    }
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.socket_rebinds, 3);
+    assert_eq!(stats.dropped_unresolved, 3);
+    assert_eq!(stats.uplink_packets, 4);
+}
+
+#[tokio::test]
+async fn strict_policy_tears_down_once_exhausted() {
+    let (relay_task, _stream, relay) = associate(UdpRelayConfig {
+        rebind: Some(policy(1, true)),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&client, relay, BROADCAST, b"rebound").await;
+    send(&client, relay, BROADCAST, b"exhausted").await;
+
+    let err = relay_task.await.unwrap().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+}
+
+#[tokio::test]
+async fn other_errors_only_drop_the_datagram() {
+    let echo = echo().await;
+    let stats = UdpRelayStats::new();
+    let (_relay_task, _stream, relay) = associate(UdpRelayConfig {
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&client, relay, BROADCAST, b"fails").await;
+    roundtrip(&client, relay, echo, b"after").await;
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.socket_rebinds, 0);
+    assert_eq!(stats.dropped_unresolved, 1);
+}
+
+#[tokio::test]
+async fn lenient_policy_falls_back_to_the_other_family() {
+    if UdpSocket::bind("[::]:0").await.is_err() {
+        eprintln!("IPv6 is not available, skipping");
+        return;
+    }
+
+    let echo = echo().await;
+    let stats = UdpRelayStats::new();
+    let (_relay_task, _stream, relay) = associate(UdpRelayConfig {
+        rebind: Some(policy(1, false)),
+        stats: Some(stats.clone()),
+        ..Default::default()
+    })
+    .await;
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&client, relay, BROADCAST, b"rebound").await;
+    send(&client, relay, BROADCAST, b"exhausted").await;
+
+    // datagrams already routed to the IPv4 socket are dropped with it, so wait for it to be given up first
+    while stats.dropped(DropReason::Unresolved) < 2 {
+        time::sleep(Duration::from_millis(1)).await;
+    }
+
+    // the IPv4 socket is given up, so this goes through the dual-stack IPv6 socket as a v4-mapped address
+    roundtrip(&client, relay, echo, b"after").await;
+
+    let stats = stats.snapshot();
+    assert_eq!(stats.socket_rebinds, 1);
+    assert_eq!(stats.dropped_unresolved, 2);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::encode_udp;
+use socks5_proto::{Address, Error as Socks5Error};
+use socks5_server::connection::associate::{AssociatedUdpSocket, Truncated};
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+
+#[tokio::test]
+async fn invalid_datagrams_are_skipped() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 64);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    peer.send_to(&encode_udp(b"first", dst), addr)
+        .await
+        .unwrap();
+    peer.send_to(b"\x00\x00\x00\x09garbage", addr)
+        .await
+        .unwrap();
+    peer.send_to(b"\x00", addr).await.unwrap();
+    peer.send_to(&encode_udp(b"second", dst), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp(&[0; 128], dst), addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp(b"third", dst), addr)
+        .await
+        .unwrap();
+
+    let mut invalid = Vec::new();
+
+    for expected in [&b"first"[..], b"second", b"third"] {
+        let (pkt, header, src) = socket
+            .recv_from_valid_with(|err, src| invalid.push((err, src)))
+            .await
+            .unwrap();
+
+        assert_eq!(&pkt[..], expected);
+        assert_eq!(header.address, Address::SocketAddress(dst));
+        assert_eq!(src, peer_addr);
+    }
+
+    assert_eq!(invalid.len(), 3);
+    assert!(invalid.iter().all(|(_, src)| *src == peer_addr));
+    assert!(
+        matches!(&invalid[2].0, Socks5Error::Io(err) if Truncated::from_io_error(err).is_some())
+    );
+
+    let stats = socket.stats().snapshot();
+    assert_eq!(stats.dropped_malformed, 2);
+    assert_eq!(stats.dropped_oversize, 1);
+}
+
+#[tokio::test]
+async fn recv_from_keeps_working_after_an_error() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let dst = SocketAddr::from(([1, 2, 3, 4], 5));
+
+    peer.send_to(b"\x00\x00\x00\x09garbage", addr)
+        .await
+        .unwrap();
+    peer.send_to(&encode_udp(b"valid", dst), addr)
+        .await
+        .unwrap();
+
+    let (_, raw) = socket.recv_from().await.unwrap_err();
+    assert_eq!(raw.unwrap(), b"\x00\x00\x00\x09garbage");
+
+    let (pkt, _, _) = socket.recv_from().await.unwrap();
+    assert_eq!(&pkt[..], b"valid");
+}
@@ -0,0 +1,126 @@
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, UdpRelayConfig},
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+async fn echo(addr: &str) -> Option<SocketAddr> {
+    let socket = UdpSocket::bind(addr).await.ok()?;
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    Some(addr)
+}
+
+/// Starts a relay on `127.0.0.1` and associates with it, returning the control connection and the relay address.
+async fn associate() -> (TcpStream, SocketAddr) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => {
+                run_relay(associate, UdpRelayConfig::default())
+                    .await
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    (stream, relay)
+}
+
+async fn roundtrip(client: &UdpSocket, relay: SocketAddr, dst: SocketAddr, pkt: &[u8]) -> Address {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(dst)).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    let mut reply = &buf[..len];
+    let header = UdpHeader::read_from_buf(&mut reply).unwrap();
+    assert_eq!(reply, pkt);
+
+    header.address
+}
+
+#[tokio::test]
+async fn forwards_to_both_families() {
+    let Some(v6) = echo("[::1]:0").await else {
+        eprintln!("IPv6 is not available, skipping");
+        return;
+    };
+    let v4 = echo("127.0.0.1:0").await.unwrap();
+
+    let (_stream, relay) = associate().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    assert_eq!(
+        roundtrip(&client, relay, v4, b"to v4").await,
+        Address::SocketAddress(v4)
+    );
+    assert_eq!(
+        roundtrip(&client, relay, v6, b"to v6").await,
+        Address::SocketAddress(v6)
+    );
+    assert_eq!(
+        roundtrip(&client, relay, v4, b"to v4 again").await,
+        Address::SocketAddress(v4)
+    );
+}
+
+#[tokio::test]
+async fn replies_from_v4_mapped_destinations_carry_ipv4() {
+    let v4 = echo("127.0.0.1:0").await.unwrap();
+    let mapped = SocketAddr::new(Ipv4Addr::LOCALHOST.to_ipv6_mapped().into(), v4.port());
+
+    let (_stream, relay) = associate().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    assert_eq!(
+        roundtrip(&client, relay, mapped, b"mapped").await,
+        Address::SocketAddress(v4)
+    );
+}
@@ -0,0 +1,190 @@
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, DestinationPolicy, RelayClose, RelayEvent, UdpRelayConfig},
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+async fn echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+async fn roundtrip(client: &UdpSocket, relay: SocketAddr, dst: SocketAddr, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(dst)).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    let mut reply = &buf[..len];
+    UdpHeader::read_from_buf(&mut reply).unwrap();
+    assert_eq!(reply, pkt);
+}
+
+#[tokio::test]
+async fn association_events_are_reported() {
+    let echo = echo().await;
+    let denied = SocketAddr::from(([127, 0, 0, 1], 9));
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let log = events.clone();
+
+    let config = UdpRelayConfig {
+        destination_policy: DestinationPolicy::Filter(Arc::new(move |dst| *dst != denied)),
+        observer: Some(Arc::new(move |event: &RelayEvent<'_>| {
+            let event = match event {
+                RelayEvent::Dropped(reason) => format!("{}({reason:?})", event.name()),
+                RelayEvent::Closed(close) => format!("{}({close:?})", event.name()),
+                event => event.name().to_owned(),
+            };
+
+            log.lock().unwrap().push(event);
+        })),
+        ..Default::default()
+    };
+
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    let relay_task = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => run_relay(associate, config).await.unwrap(),
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    roundtrip(&client, relay, echo, b"first").await;
+
+    client.send_to(&[0, 0, 0, 0xff], relay).await.unwrap();
+
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(denied)).write_to_buf(&mut buf);
+    buf.extend_from_slice(b"denied");
+    client.send_to(&buf, relay).await.unwrap();
+
+    // datagrams are handled in order, so both drops are reported once this comes back
+    roundtrip(&client, relay, echo, b"second").await;
+
+    drop(stream);
+    let session = relay_task.await.unwrap();
+    assert_eq!(session.close, RelayClose::ClientClosed);
+
+    assert_eq!(
+        *events.lock().unwrap(),
+        [
+            "udp_association_started",
+            "udp_client_learned",
+            "udp_destination_added",
+            "udp_datagram_dropped(Malformed)",
+            "udp_datagram_dropped(Denied)",
+            "udp_association_closed(ClientClosed)",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn started_event_carries_the_endpoints() {
+    let started = Arc::new(Mutex::new(None));
+    let log = started.clone();
+
+    let config = UdpRelayConfig {
+        observer: Some(Arc::new(move |event: &RelayEvent<'_>| {
+            if let RelayEvent::Started {
+                peer,
+                declared,
+                relay,
+            } = event
+            {
+                *log.lock().unwrap() = Some((*peer, (*declared).clone(), *relay));
+            }
+        })),
+        ..Default::default()
+    };
+
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    let relay_task = tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => run_relay(associate, config).await.unwrap(),
+            _ => unreachable!(),
+        }
+    });
+
+    let declared = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::SocketAddress(declared))
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    let peer = stream.local_addr().unwrap();
+    drop(stream);
+    relay_task.await.unwrap();
+
+    assert_eq!(
+        *started.lock().unwrap(),
+        Some((peer, Address::SocketAddress(declared), relay))
+    );
+}
@@ -0,0 +1,545 @@
+mod common;
+
+use common::encode_udp;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{
+        AssociatedUdpSocket, DatagramSocket, RateLimit, RelayClose, SharedMapping, UdpRelayConfig,
+        UdpRelayManager, UdpRelayStats,
+    },
+    proto::{Address, Command as ProtoCommand, Reply, UdpHeader},
+    Command,
+};
+use std::{
+    collections::HashMap,
+    future::poll_fn,
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::ReadBuf,
+    net::UdpSocket,
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    task::{self, JoinHandle},
+    time,
+};
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+/// An in-memory network routing datagrams between the [`ChannelSocket`]s bound on it
+#[derive(Clone, Default)]
+struct Network {
+    routes: Arc<Mutex<HashMap<SocketAddr, UnboundedSender<Datagram>>>>,
+}
+
+impl Network {
+    fn bind(&self, addr: SocketAddr) -> ChannelSocket {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.routes.lock().unwrap().insert(addr, tx);
+
+        ChannelSocket {
+            local: addr,
+            rx: Mutex::new(rx),
+            network: self.clone(),
+        }
+    }
+
+    fn route(&self, pkt: &[u8], src: SocketAddr, target: SocketAddr) {
+        // like UDP, datagrams to nowhere are silently lost
+        if let Some(tx) = self.routes.lock().unwrap().get(&target) {
+            let _ = tx.send((pkt.to_vec(), src));
+        }
+    }
+
+    /// Binds a destination echoing every datagram back to its source.
+    fn echo(&self, addr: SocketAddr) {
+        let socket = self.bind(addr);
+
+        tokio::spawn(async move {
+            loop {
+                let (pkt, src) = socket.recv().await;
+                socket.send(&pkt, src);
+            }
+        });
+    }
+}
+
+struct ChannelSocket {
+    local: SocketAddr,
+    rx: Mutex<UnboundedReceiver<Datagram>>,
+    network: Network,
+}
+
+impl DatagramSocket for ChannelSocket {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>> {
+        match self.rx.lock().unwrap().poll_recv(cx) {
+            Poll::Ready(Some((pkt, src))) => {
+                let len = pkt.len().min(buf.remaining());
+                buf.put_slice(&pkt[..len]);
+                Poll::Ready(Ok(src))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(Error::from(ErrorKind::BrokenPipe))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn poll_send_to(
+        &self,
+        _: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        self.network.route(buf, self.local, target);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        Ok(self.local)
+    }
+}
+
+impl ChannelSocket {
+    fn send(&self, pkt: &[u8], target: SocketAddr) {
+        self.network.route(pkt, self.local, target);
+    }
+
+    async fn recv(&self) -> Datagram {
+        poll_fn(|cx| self.rx.lock().unwrap().poll_recv(cx))
+            .await
+            .unwrap()
+    }
+
+    fn try_recv(&self) -> Option<Datagram> {
+        self.rx.lock().unwrap().try_recv().ok()
+    }
+}
+
+fn addr(ip: [u8; 4], port: u16) -> SocketAddr {
+    SocketAddr::from((ip, port))
+}
+
+fn decode(pkt: &[u8]) -> (Address, Vec<u8>) {
+    let mut pkt = pkt;
+    let header = UdpHeader::read_from_buf(&mut pkt).unwrap();
+    (header.address, pkt.to_vec())
+}
+
+/// A manager over a pool of `size` sockets, running in the background
+fn manager(
+    network: &Network,
+    size: u8,
+    mapping: SharedMapping,
+) -> (UdpRelayManager<ChannelSocket>, Vec<SocketAddr>) {
+    let pool = (0..size)
+        .map(|idx| network.bind(addr([10, 0, 0, 1], 40000 + u16::from(idx))))
+        .collect::<Vec<_>>();
+    let addrs = pool.iter().map(|socket| socket.local).collect();
+
+    let manager = UdpRelayManager::new(pool, mapping).unwrap();
+    let run = manager.clone();
+    tokio::spawn(async move { run.run().await });
+
+    (manager, addrs)
+}
+
+/// A simulated client talking through an association of the manager
+struct Client {
+    socket: ChannelSocket,
+    relay: SocketAddr,
+    stats: UdpRelayStats,
+    close: Option<oneshot::Sender<()>>,
+    task: JoinHandle<RelayClose>,
+}
+
+impl Client {
+    /// Starts the association of client `id`, waiting until it is registered so associations are started in order.
+    async fn start(network: &Network, manager: &UdpRelayManager<ChannelSocket>, id: u16) -> Self {
+        Self::start_with(network, manager, id, UdpRelayConfig::default()).await
+    }
+
+    /// Starts the association of client `id` like [`Client::start()`], relayed according to `config`.
+    async fn start_with(
+        network: &Network,
+        manager: &UdpRelayManager<ChannelSocket>,
+        id: u16,
+        mut config: UdpRelayConfig,
+    ) -> Self {
+        let [hi, lo] = id.to_be_bytes();
+        let relay = addr([10, 0, hi, lo], 1080);
+        let socket = network.bind(addr([10, 2, hi, lo], 5000));
+
+        let stats = UdpRelayStats::new();
+        config.stats = Some(stats.clone());
+
+        let (close, closed) = oneshot::channel();
+        let client = AssociatedUdpSocket::new(network.bind(relay), 1500);
+        let associations = manager.stats().associations;
+        let relay_manager = manager.clone();
+
+        let task = tokio::spawn(async move {
+            relay_manager
+                .relay(client, config, async move {
+                    let _ = closed.await;
+                    Ok(())
+                })
+                .await
+                .unwrap()
+                .close
+        });
+
+        while manager.stats().associations == associations {
+            task::yield_now().await;
+        }
+
+        Self {
+            socket,
+            relay,
+            stats,
+            close: Some(close),
+            task,
+        }
+    }
+
+    fn send(&self, pkt: &[u8], dst: SocketAddr) {
+        self.socket.send(&encode_udp(pkt, dst), self.relay);
+    }
+
+    async fn recv(&self) -> (Address, Vec<u8>) {
+        let (pkt, src) = time::timeout(Duration::from_secs(5), self.socket.recv())
+            .await
+            .unwrap();
+        assert_eq!(src, self.relay);
+        decode(&pkt)
+    }
+
+    async fn close(mut self) {
+        self.close.take().unwrap().send(()).unwrap();
+        assert_eq!(self.task.await.unwrap(), RelayClose::ClientClosed);
+    }
+}
+
+/// Yields until `f` holds, so datagrams in flight are handled.
+async fn until(mut f: impl FnMut() -> bool) {
+    time::timeout(Duration::from_secs(5), async {
+        while !f() {
+            task::yield_now().await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn hundreds_of_clients_share_a_small_pool() {
+    const CLIENTS: u16 = 300;
+    const DESTINATIONS: u16 = 50;
+
+    let network = Network::default();
+    let (manager, _) = manager(&network, 8, SharedMapping::PerDestination);
+
+    let destinations = (0..DESTINATIONS)
+        .map(|idx| addr([10, 1, 0, idx as u8], 7))
+        .collect::<Vec<_>>();
+
+    for dst in &destinations {
+        network.echo(*dst);
+    }
+
+    let mut clients = Vec::new();
+
+    for id in 0..CLIENTS {
+        clients.push(Client::start(&network, &manager, id).await);
+    }
+
+    assert_eq!(manager.stats().associations, usize::from(CLIENTS));
+
+    // 6 clients per destination, less than the 8 sockets they can be sent from
+    for (id, client) in clients.iter().enumerate() {
+        client.send(
+            format!("client {id}").as_bytes(),
+            destinations[id % destinations.len()],
+        );
+    }
+
+    for (id, client) in clients.iter().enumerate() {
+        let (from, pkt) = client.recv().await;
+        assert_eq!(
+            from,
+            Address::SocketAddress(destinations[id % destinations.len()])
+        );
+        assert_eq!(pkt, format!("client {id}").as_bytes());
+
+        // the reply may arrive before the relay recorded sending it
+        until(|| client.stats.snapshot().downlink_packets == 1).await;
+        let stats = client.stats.snapshot();
+        assert_eq!(stats.uplink_packets, 1);
+        assert_eq!(stats.dropped_denied, 0);
+    }
+
+    let stats = manager.stats();
+    assert_eq!(stats.claims, usize::from(CLIENTS));
+    assert_eq!(stats.conflicts, 0);
+    assert_eq!(stats.unmatched, 0);
+
+    for client in clients {
+        client.close().await;
+    }
+
+    let stats = manager.stats();
+    assert_eq!((stats.associations, stats.claims), (0, 0));
+}
+
+#[tokio::test]
+async fn conflicting_clients_are_denied_until_the_claim_is_released() {
+    let network = Network::default();
+    let (manager, pool) = manager(&network, 2, SharedMapping::PerDestination);
+    let dst = network.bind(addr([10, 1, 0, 1], 7));
+
+    let first = Client::start(&network, &manager, 0).await;
+    let second = Client::start(&network, &manager, 1).await;
+    let third = Client::start(&network, &manager, 2).await;
+
+    // the sockets of the pool are claimed in order
+    for (client, socket) in [(&first, pool[0]), (&second, pool[1])] {
+        client.send(b"ping", dst.local);
+        let (pkt, src) = dst.recv().await;
+        assert_eq!((pkt.as_slice(), src), (&b"ping"[..], socket));
+
+        dst.send(b"pong", src);
+        assert_eq!(client.recv().await.1, b"pong");
+    }
+
+    // every socket has the destination claimed by another client
+    third.send(b"ping", dst.local);
+    until(|| third.stats.snapshot().dropped_denied == 1).await;
+    assert!(dst.try_recv().is_none());
+    assert_eq!(manager.stats().conflicts, 1);
+
+    // a client keeps its socket for a destination
+    second.send(b"again", dst.local);
+    assert_eq!(dst.recv().await.1, pool[1]);
+
+    first.close().await;
+
+    third.send(b"ping", dst.local);
+    let (_, src) = dst.recv().await;
+    assert_eq!(src, pool[0]);
+
+    dst.send(b"pong", src);
+    assert_eq!(third.recv().await.1, b"pong");
+    assert!(second.socket.try_recv().is_none());
+}
+
+#[tokio::test]
+async fn rate_limited_datagrams_do_not_claim_their_destination() {
+    let network = Network::default();
+    let (manager, pool) = manager(&network, 1, SharedMapping::PerDestination);
+    let dst = network.bind(addr([10, 1, 0, 1], 7));
+
+    // 4 bytes per second, a burst of which never fits the datagram
+    let limited = Client::start_with(
+        &network,
+        &manager,
+        0,
+        UdpRelayConfig {
+            uplink_limit: RateLimit {
+                packets_per_sec: None,
+                bytes_per_sec: Some(4),
+                burst: Duration::from_secs(1),
+            },
+            ..Default::default()
+        },
+    )
+    .await;
+    let other = Client::start(&network, &manager, 1).await;
+
+    limited.send(b"too large", dst.local);
+    until(|| limited.stats.snapshot().dropped_rate_limited == 1).await;
+    assert!(dst.try_recv().is_none());
+    assert_eq!(manager.stats().claims, 0);
+
+    // the only socket of the pool is still free for the destination
+    other.send(b"ping", dst.local);
+    let (pkt, src) = dst.recv().await;
+    assert_eq!((pkt.as_slice(), src), (&b"ping"[..], pool[0]));
+    assert_eq!(other.stats.snapshot().dropped_denied, 0);
+    assert_eq!(manager.stats().conflicts, 0);
+}
+
+#[tokio::test]
+async fn pinned_clients_keep_their_socket_for_all_destinations() {
+    let network = Network::default();
+    let (manager, pool) = manager(&network, 2, SharedMapping::Pinned);
+    let dsts = [
+        network.bind(addr([10, 1, 0, 1], 7)),
+        network.bind(addr([10, 1, 0, 2], 7)),
+    ];
+
+    // pinned round-robin, to the sockets 0, 1 and 0 again
+    let clients = [
+        Client::start(&network, &manager, 0).await,
+        Client::start(&network, &manager, 1).await,
+        Client::start(&network, &manager, 2).await,
+    ];
+
+    for dst in &dsts {
+        clients[0].send(b"ping", dst.local);
+        assert_eq!(dst.recv().await.1, pool[0]);
+
+        clients[1].send(b"ping", dst.local);
+        assert_eq!(dst.recv().await.1, pool[1]);
+    }
+
+    // the destination is claimed on its socket, while the other socket is free
+    clients[2].send(b"ping", dsts[0].local);
+    until(|| clients[2].stats.snapshot().dropped_denied == 1).await;
+    assert!(dsts[0].try_recv().is_none());
+    assert_eq!(manager.stats().conflicts, 1);
+
+    dsts[1].send(b"pong", pool[0]);
+    assert_eq!(
+        clients[0].recv().await,
+        (Address::SocketAddress(dsts[1].local), b"pong".to_vec())
+    );
+}
+
+#[tokio::test]
+async fn unclaimed_return_traffic_is_dropped() {
+    let network = Network::default();
+    let (manager, pool) = manager(&network, 1, SharedMapping::PerDestination);
+    let dst = network.bind(addr([10, 1, 0, 1], 7));
+    let other_port = network.bind(addr([10, 1, 0, 1], 8));
+
+    let client = Client::start(&network, &manager, 0).await;
+
+    // before the client sent anything
+    dst.send(b"early", pool[0]);
+    until(|| manager.stats().unmatched == 1).await;
+
+    client.send(b"ping", dst.local);
+    dst.recv().await;
+
+    // the claim is for the port the client sent to only
+    other_port.send(b"other", pool[0]);
+    until(|| manager.stats().unmatched == 2).await;
+
+    dst.send(b"pong", pool[0]);
+    assert_eq!(client.recv().await.1, b"pong");
+    assert!(client.socket.try_recv().is_none());
+
+    // nor is the traffic relayed once the client is gone
+    client.close().await;
+    dst.send(b"late", pool[0]);
+    until(|| manager.stats().unmatched == 3).await;
+}
+
+#[tokio::test]
+async fn expired_claims_are_taken_over() {
+    let network = Network::default();
+    let (manager, pool) = manager(&network, 1, SharedMapping::PerDestination);
+    let dst = network.bind(addr([10, 1, 0, 1], 7));
+
+    let first = Client::start(&network, &manager, 0).await;
+    let second = Client::start(&network, &manager, 1).await;
+
+    first.send(b"ping", dst.local);
+    dst.recv().await;
+
+    second.send(b"ping", dst.local);
+    until(|| manager.stats().conflicts == 1).await;
+
+    time::pause();
+    time::advance(UdpRelayConfig::default().destination_timeout).await;
+    time::resume();
+
+    second.send(b"ping", dst.local);
+    assert_eq!(dst.recv().await.1, pool[0]);
+
+    dst.send(b"pong", pool[0]);
+    assert_eq!(second.recv().await.1, b"pong");
+    assert!(first.socket.try_recv().is_none());
+}
+
+#[test]
+fn empty_pool_is_rejected() {
+    let err = UdpRelayManager::<ChannelSocket>::new(Vec::new(), SharedMapping::Pinned).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[tokio::test]
+async fn associations_are_relayed_over_udp() {
+    let manager = UdpRelayManager::bind(2, SharedMapping::PerDestination)
+        .await
+        .unwrap();
+    let run = manager.clone();
+    tokio::spawn(async move { run.run().await });
+
+    let relay_manager = manager.clone();
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let manager = relay_manager.clone();
+
+        async move {
+            let (conn, _) = conn.authenticate().await.unwrap();
+
+            match conn.wait().await.unwrap() {
+                Command::Associate(associate, _) => {
+                    manager
+                        .run_relay(associate, UdpRelayConfig::default())
+                        .await
+                        .unwrap();
+                }
+                _ => unreachable!(),
+            }
+        }
+    })
+    .await;
+    let echo_addr = common::udp_echo(IpAddr::V4(Ipv4Addr::LOCALHOST)).await;
+
+    let mut controls = Vec::new();
+
+    // both talk to the same destination, each from its own socket of the pool
+    for pkt in [&b"first"[..], b"second"] {
+        let (control, resp) = common::Client::no_auth_request(
+            server,
+            ProtoCommand::Associate,
+            Address::unspecified(),
+        )
+        .await;
+        assert_eq!(resp.reply, Reply::Succeeded);
+
+        let Address::SocketAddress(relay) = resp.address else {
+            unreachable!()
+        };
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(&encode_udp(pkt, echo_addr), relay)
+            .await
+            .unwrap();
+
+        let mut buf = [0; 1500];
+        let (len, _) = common::timeout(client.recv_from(&mut buf)).await.unwrap();
+        assert_eq!(
+            decode(&buf[..len]),
+            (Address::SocketAddress(echo_addr), pkt.to_vec())
+        );
+
+        controls.push(control);
+    }
+
+    assert_eq!(manager.stats().associations, 2);
+    assert_eq!(manager.stats().conflicts, 0);
+
+    drop(controls);
+    until(|| manager.stats().associations == 0).await;
+}
@@ -0,0 +1,143 @@
+use async_trait::async_trait;
+use bytes::BytesMut;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{run_relay, ReturnAddress, UdpRelayConfig},
+    dns::Resolver,
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Request, Response, UdpHeader,
+    },
+    Command, Server,
+};
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+
+const DOMAIN: &[u8] = b"echo.test";
+
+/// Resolves [`DOMAIN`] to `127.0.0.1`, without depending on the system resolver.
+struct StaticResolver;
+
+#[async_trait]
+impl Resolver for StaticResolver {
+    async fn resolve(&self, name: &str) -> Result<Vec<IpAddr>, Error> {
+        if name.as_bytes() == DOMAIN {
+            Ok(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)])
+        } else {
+            Err(Error::new(ErrorKind::NotFound, "unknown domain"))
+        }
+    }
+}
+
+async fn echo() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0; 1500];
+
+        loop {
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            socket.send_to(&buf[..len], src).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+/// Starts a relay with `return_address` and associates with it, returning the control connection and the relay address.
+async fn associate(return_address: ReturnAddress) -> (TcpStream, SocketAddr) {
+    let server = Server::new(
+        TcpListener::bind("127.0.0.1:0").await.unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+    let server_addr = server.local_addr().unwrap();
+
+    let config = UdpRelayConfig {
+        return_address,
+        resolver: Arc::new(StaticResolver),
+        ..Default::default()
+    };
+
+    tokio::spawn(async move {
+        let (conn, _) = server.accept().await.unwrap();
+        let (conn, _) = conn.authenticate().await.unwrap();
+
+        match conn.wait().await.unwrap() {
+            Command::Associate(associate, _) => {
+                run_relay(associate, config).await.unwrap();
+            }
+            _ => unreachable!(),
+        }
+    });
+
+    let mut stream = TcpStream::connect(server_addr).await.unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+    HandshakeResponse::read_from(&mut stream).await.unwrap();
+    Request::new(ProtoCommand::Associate, Address::unspecified())
+        .write_to(&mut stream)
+        .await
+        .unwrap();
+
+    let Address::SocketAddress(relay) = Response::read_from(&mut stream).await.unwrap().address
+    else {
+        unreachable!()
+    };
+
+    (stream, relay)
+}
+
+async fn roundtrip(client: &UdpSocket, relay: SocketAddr, dst: Address, pkt: &[u8]) -> Address {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, dst).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    client.send_to(&buf, relay).await.unwrap();
+
+    let mut buf = [0; 1500];
+    let (len, _) = client.recv_from(&mut buf).await.unwrap();
+    let mut reply = &buf[..len];
+    let header = UdpHeader::read_from_buf(&mut reply).unwrap();
+    assert_eq!(reply, pkt);
+
+    header.address
+}
+
+#[tokio::test]
+async fn source_address_is_returned_by_default() {
+    let echo = echo().await;
+    let (_stream, relay) = associate(ReturnAddress::default()).await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let dst = Address::DomainAddress(DOMAIN.to_vec(), echo.port());
+    assert_eq!(
+        roundtrip(&client, relay, dst, b"domain").await,
+        Address::SocketAddress(echo)
+    );
+}
+
+#[tokio::test]
+async fn requested_address_is_echoed() {
+    let echo = echo().await;
+    let (_stream, relay) = associate(ReturnAddress::Requested).await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let dst = Address::DomainAddress(DOMAIN.to_vec(), echo.port());
+    assert_eq!(roundtrip(&client, relay, dst.clone(), b"domain").await, dst);
+
+    // the most recently used address of the destination is echoed
+    assert_eq!(
+        roundtrip(&client, relay, Address::SocketAddress(echo), b"literal").await,
+        Address::SocketAddress(echo)
+    );
+    assert_eq!(
+        roundtrip(&client, relay, dst.clone(), b"domain again").await,
+        dst
+    );
+}
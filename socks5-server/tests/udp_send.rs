@@ -0,0 +1,117 @@
+mod common;
+
+use common::encode_udp_with;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::{AssociatedUdpSocket, DatagramSocket};
+use std::{
+    io::Error,
+    net::SocketAddr,
+    task::{Context, Poll},
+};
+use tokio::{io::ReadBuf, net::UdpSocket};
+
+/// A `UdpSocket` without vectored sends, so packets go through the scratch buffer of the socket
+struct Copying(UdpSocket);
+
+impl DatagramSocket for Copying {
+    fn poll_recv_from(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<SocketAddr, Error>> {
+        self.0.poll_recv_from(cx, buf)
+    }
+
+    fn poll_send_to(
+        &self,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+        target: SocketAddr,
+    ) -> Poll<Result<usize, Error>> {
+        self.0.poll_send_to(cx, buf, target)
+    }
+
+    fn local_addr(&self) -> Result<SocketAddr, Error> {
+        self.0.local_addr()
+    }
+}
+
+async fn recv(peer: &UdpSocket) -> Vec<u8> {
+    let mut buf = vec![0; 2048];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    buf.truncate(len);
+    buf
+}
+
+#[tokio::test]
+async fn all_send_paths_are_byte_identical() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let copying =
+        AssociatedUdpSocket::new(Copying(UdpSocket::bind("127.0.0.1:0").await.unwrap()), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+
+    #[cfg(unix)]
+    assert!(socket.get_ref().is_write_vectored());
+    assert!(!copying.get_ref().is_write_vectored());
+
+    let headers = [
+        UdpHeader::new(
+            0,
+            Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+        ),
+        UdpHeader::new(0, Address::SocketAddress("[::1]:53".parse().unwrap())),
+        UdpHeader::new(0, Address::DomainAddress(b"example.com".to_vec(), 443)),
+    ];
+
+    for header in &headers {
+        // payloads of growing and shrinking sizes, so the scratch buffer is reused with stale bytes in it
+        for pkt in [&b"a longer payload"[..], b"short", b""] {
+            let expected = encode_udp_with(pkt, header);
+
+            assert_eq!(
+                socket.send_to(pkt, header, peer_addr).await.unwrap(),
+                pkt.len()
+            );
+            assert_eq!(recv(&peer).await, expected);
+
+            assert_eq!(
+                copying.send_to(pkt, header, peer_addr).await.unwrap(),
+                pkt.len()
+            );
+            assert_eq!(recv(&peer).await, expected);
+
+            let headroom = AssociatedUdpSocket::MAX_HEADER_LEN;
+            let mut buf = vec![0xff; headroom];
+            buf.extend_from_slice(pkt);
+
+            let sent = socket
+                .send_to_with_headroom(&mut buf, headroom, header, peer_addr)
+                .await
+                .unwrap();
+            assert_eq!(sent, pkt.len());
+            assert_eq!(recv(&peer).await, expected);
+        }
+    }
+
+    assert_eq!(socket.stats().snapshot().downlink_packets, 18);
+    assert_eq!(copying.stats().snapshot().downlink_packets, 9);
+}
+
+#[tokio::test]
+async fn small_headroom_falls_back_to_copying() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+
+    let mut buf = b"xxpayload".to_vec();
+    let sent = socket
+        .send_to_with_headroom(&mut buf, 2, &header, peer_addr)
+        .await
+        .unwrap();
+
+    assert_eq!(sent, 7);
+    assert_eq!(recv(&peer).await, encode_udp_with(b"payload", &header));
+    assert_eq!(buf, b"xxpayload");
+}
@@ -0,0 +1,99 @@
+#![cfg(target_os = "linux")]
+
+use socks5_server::connection::associate::{AssociatedUdpSocket, UdpSocketOptions};
+use tokio::net::UdpSocket;
+
+const DSCP_EF: u32 = 46 << 2;
+
+#[tokio::test]
+async fn tos_and_ttl_v4() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+
+    socket.set_tos(DSCP_EF).unwrap();
+    socket.set_ttl(7).unwrap();
+
+    assert_eq!(socket.tos().unwrap(), DSCP_EF);
+    assert_eq!(socket.ttl().unwrap(), 7);
+    assert_eq!(socket.get_ref().ttl().unwrap(), 7);
+}
+
+#[tokio::test]
+async fn tclass_and_hop_limit_v6() {
+    let Ok(socket) = UdpSocket::bind("[::1]:0").await else {
+        eprintln!("IPv6 is not available, skipping");
+        return;
+    };
+    let socket = AssociatedUdpSocket::new(socket, 1500);
+
+    socket.set_tos(DSCP_EF).unwrap();
+    socket.set_ttl(9).unwrap();
+
+    assert_eq!(socket.tos().unwrap(), DSCP_EF);
+    assert_eq!(socket.ttl().unwrap(), 9);
+}
+
+#[tokio::test]
+async fn options_apply_only_what_is_set() {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let default_ttl = socket.ttl().unwrap();
+
+    let options = UdpSocketOptions {
+        tos: Some(DSCP_EF),
+        ttl: None,
+        ..Default::default()
+    };
+    options.apply(&socket).unwrap();
+
+    let socket = AssociatedUdpSocket::new(socket, 1500);
+    assert_eq!(socket.tos().unwrap(), DSCP_EF);
+    assert_eq!(socket.ttl().unwrap(), default_ttl);
+}
+
+/// Linux doubles the requested buffer size to account for bookkeeping, after capping it at the `sysctl` limit
+fn adjusted(requested: usize, limit: &str) -> usize {
+    let max = std::fs::read_to_string(format!("/proc/sys/net/core/{limit}"))
+        .unwrap()
+        .trim()
+        .parse::<usize>()
+        .unwrap();
+
+    requested.min(max) * 2
+}
+
+#[tokio::test]
+async fn buffer_sizes_round_trip() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+
+    socket.set_recv_buffer_size(64 * 1024).unwrap();
+    socket.set_send_buffer_size(48 * 1024).unwrap();
+
+    assert_eq!(
+        socket.recv_buffer_size().unwrap(),
+        adjusted(64 * 1024, "rmem_max")
+    );
+    assert_eq!(
+        socket.send_buffer_size().unwrap(),
+        adjusted(48 * 1024, "wmem_max")
+    );
+}
+
+#[tokio::test]
+async fn broadcast_and_local_addr() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+
+    assert!(!socket.broadcast().unwrap());
+    socket.set_broadcast(true).unwrap();
+    assert!(socket.broadcast().unwrap());
+
+    assert_eq!(
+        socket.local_addr().unwrap(),
+        socket.get_ref().local_addr().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn invalid_value_is_an_error() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    assert!(socket.set_ttl(0).is_err());
+    assert!(socket.set_ttl(256).is_err());
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use bytes::BytesMut;
+use common::Client;
+use socks5_server::{
+    auth::NoAuth,
+    connection::associate::{
+        run_relay, UdpRelayConfig, UdpSocketOptions, UdpSocketPool, UdpSocketPoolStats,
+    },
+    proto::{Address, Command as ProtoCommand, Reply, UdpHeader},
+    Command,
+};
+use std::{
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time};
+
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+async fn pool(size: usize) -> UdpSocketPool {
+    UdpSocketPool::new(LOCALHOST, size, UdpSocketOptions::default())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn checkouts_and_returns_are_accounted() {
+    let pool = pool(2).await;
+
+    assert_eq!(
+        pool.stats(),
+        UdpSocketPoolStats {
+            size: 2,
+            idle: 2,
+            binds: 2,
+            ..UdpSocketPoolStats::default()
+        }
+    );
+
+    let a = pool.checkout().await.unwrap();
+    let b = pool.checkout().await.unwrap();
+    assert_eq!(a.local_addr().unwrap().ip(), LOCALHOST);
+
+    let stats = pool.stats();
+    assert_eq!((stats.idle, stats.in_use, stats.checkouts), (0, 2, 2));
+
+    let port = a.local_addr().unwrap().port();
+    drop(a);
+
+    let stats = pool.stats();
+    assert_eq!((stats.idle, stats.in_use), (1, 1));
+
+    // the returned socket is handed out again instead of binding a new one
+    let a = pool.checkout().await.unwrap();
+    assert_eq!(a.local_addr().unwrap().port(), port);
+
+    drop((a, b));
+
+    let stats = pool.stats();
+    assert_eq!(
+        (stats.idle, stats.in_use, stats.checkouts, stats.binds),
+        (2, 0, 3, 2)
+    );
+}
+
+#[tokio::test]
+async fn exhausted_pool_waits_for_a_return() {
+    let pool = pool(1).await;
+    let socket = pool.checkout().await.unwrap();
+
+    let waiting = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.checkout().await.unwrap().local_addr().unwrap() }
+    });
+
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+    assert_eq!(pool.stats().waits, 1);
+
+    let addr = socket.local_addr().unwrap();
+    drop(socket);
+
+    assert_eq!(common::timeout(waiting).await.unwrap(), addr);
+    assert_eq!(pool.stats().binds, 1);
+}
+
+#[tokio::test]
+async fn discarded_socket_is_replaced() {
+    let pool = pool(1).await;
+
+    pool.checkout().await.unwrap().discard();
+
+    let stats = pool.stats();
+    assert_eq!((stats.idle, stats.in_use, stats.discarded), (0, 0, 1));
+
+    pool.checkout().await.unwrap();
+    assert_eq!(pool.stats().binds, 2);
+}
+
+#[tokio::test]
+async fn datagrams_received_while_idle_are_discarded() {
+    let pool = pool(1).await;
+    let addr = pool.checkout().await.unwrap().local_addr().unwrap();
+
+    let peer = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+    peer.send_to(b"stale", addr).await.unwrap();
+    time::sleep(Duration::from_millis(50)).await;
+
+    let socket = pool.checkout().await.unwrap();
+    assert_eq!(socket.local_addr().unwrap(), addr);
+
+    let mut buf = [0; 16];
+    let err = socket.try_recv_from(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::WouldBlock);
+}
+
+#[tokio::test]
+async fn relay_sends_through_a_pooled_socket() {
+    let pool = pool(1).await;
+    let pooled = pool.checkout().await.unwrap().local_addr().unwrap();
+
+    let config = UdpRelayConfig {
+        outbound_pool: Some(pool.clone()),
+        ..UdpRelayConfig::default()
+    };
+
+    let server = common::serve(Arc::new(NoAuth) as Arc<_>, move |conn| {
+        let config = config.clone();
+
+        async move {
+            let (conn, ()) = conn.authenticate().await.unwrap();
+
+            let Command::Associate(associate, _) = conn.wait().await.unwrap() else {
+                unreachable!()
+            };
+
+            let _ = run_relay(associate, config).await;
+        }
+    })
+    .await;
+
+    // the target answers with the address it saw the datagram from
+    let target = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+    let target_addr = target.local_addr().unwrap();
+
+    let (client, resp) =
+        Client::no_auth_request(server, ProtoCommand::Associate, Address::unspecified()).await;
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    let Address::SocketAddress(relay) = resp.address else {
+        unreachable!()
+    };
+
+    let socket = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+    let mut pkt = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(target_addr)).write_to_buf(&mut pkt);
+    pkt.extend_from_slice(b"hello");
+    socket.send_to(&pkt, relay).await.unwrap();
+
+    let mut buf = [0; 16];
+    let (len, src) = common::timeout(target.recv_from(&mut buf)).await.unwrap();
+    assert_eq!(&buf[..len], b"hello");
+    assert_eq!(src, pooled);
+    assert_eq!(pool.stats().in_use, 1);
+
+    // the socket goes back to the pool once the association ends
+    drop(client);
+    common::timeout(async {
+        while pool.stats().in_use != 0 {
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert_eq!(pool.stats().idle, 1);
+}
@@ -0,0 +1,68 @@
+use bytes::BytesMut;
+use socks5_proto::{Address, UdpHeader};
+use socks5_server::connection::associate::AssociatedUdpSocket;
+use std::time::Duration;
+use tokio::{net::UdpSocket, time};
+
+#[tokio::test]
+async fn send_while_recv_is_pending() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let (recv, send) = socket.split();
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+
+    let receiver = tokio::spawn(async move {
+        let (pkt, header, src) = recv.recv_from().await.unwrap();
+        (pkt, header, src, recv)
+    });
+
+    // make sure the receive half is blocked before sending
+    time::sleep(Duration::from_millis(50)).await;
+    assert!(!receiver.is_finished());
+
+    let header = UdpHeader::new(0, Address::SocketAddress(peer_addr));
+    let sender = send.clone();
+    let sent = tokio::spawn(async move { sender.send_to(b"out", &header, peer_addr).await })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(sent, 3);
+
+    let mut buf = [0; 1500];
+    let len = peer.recv(&mut buf).await.unwrap();
+    assert!(buf[..len].ends_with(b"out"));
+    assert!(!receiver.is_finished());
+
+    let mut pkt = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(peer_addr)).write_to_buf(&mut pkt);
+    pkt.extend_from_slice(b"in");
+    peer.send_to(&pkt, addr).await.unwrap();
+
+    let (pkt, _, src, recv) = receiver.await.unwrap();
+    assert_eq!(&pkt[..], b"in");
+    assert_eq!(src, peer_addr);
+
+    let socket = recv.reunite(send).unwrap();
+    assert_eq!(socket.get_ref().local_addr().unwrap(), addr);
+}
+
+#[tokio::test]
+async fn reunite_fails_with_clones_or_foreign_halves() {
+    let a = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let b = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+
+    let (recv_a, send_a) = a.split();
+    let (recv_b, send_b) = b.split();
+
+    let err = recv_a.reunite(send_b).unwrap_err();
+    let (recv_a, send_b) = (err.0, err.1);
+
+    let clone = send_a.clone();
+    let err = recv_a.reunite(send_a).unwrap_err();
+    drop(clone);
+    assert!(err.0.reunite(err.1).is_ok());
+
+    assert!(send_b.reunite(recv_b).is_ok());
+}
@@ -0,0 +1,147 @@
+use bytes::BytesMut;
+use socks5_server::{
+    connection::associate::{AssociatedUdpSocket, ClientMatch},
+    proto::{Address, UdpHeader},
+};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+const DST: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 53);
+
+async fn pair() -> (AssociatedUdpSocket, UdpSocket, SocketAddr) {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    (socket, peer, addr)
+}
+
+async fn send(peer: &UdpSocket, addr: SocketAddr, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(0, Address::SocketAddress(DST)).write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    peer.send_to(&buf, addr).await.unwrap();
+}
+
+#[tokio::test]
+async fn nothing_ready_is_none() {
+    let (socket, _peer, _) = pair().await;
+    let mut buf = BytesMut::new();
+
+    assert!(socket.try_recv_from().unwrap().is_none());
+    assert!(socket.try_recv_from_buf(&mut buf).unwrap().is_none());
+    assert!(buf.is_empty());
+}
+
+#[tokio::test]
+async fn ready_datagrams_are_drained() {
+    let (socket, peer, addr) = pair().await;
+    let peer_addr = peer.local_addr().unwrap();
+
+    for pkt in [&b"one"[..], b"two", b"three"] {
+        send(&peer, addr, pkt).await;
+    }
+
+    socket.get_ref().readable().await.unwrap();
+
+    let mut buf = BytesMut::new();
+    let mut pkts = Vec::new();
+
+    while let Some((header, range, src)) = socket.try_recv_from_buf(&mut buf).unwrap() {
+        assert_eq!(header.address, Address::SocketAddress(DST));
+        assert_eq!(src, peer_addr);
+        pkts.push(buf.split().freeze().slice(range));
+    }
+
+    assert_eq!(pkts, [&b"one"[..], b"two", b"three"]);
+
+    // readiness was cleared by the last attempt, so the next datagram is waited for again
+    send(&peer, addr, b"four").await;
+    socket.get_ref().readable().await.unwrap();
+
+    let (pkt, _, _) = socket.try_recv_from().unwrap().unwrap();
+    assert_eq!(pkt, &b"four"[..]);
+    assert!(socket.try_recv_from().unwrap().is_none());
+}
+
+#[tokio::test]
+async fn malformed_datagram_is_returned_with_its_range() {
+    let (socket, peer, addr) = pair().await;
+
+    peer.send_to(&[0, 0, 0, 0xff, 1, 2], addr).await.unwrap();
+    send(&peer, addr, b"valid").await;
+    socket.get_ref().readable().await.unwrap();
+
+    let mut buf = BytesMut::new();
+    let (_, range) = socket.try_recv_from_buf(&mut buf).unwrap_err();
+    assert_eq!(&buf[range.unwrap()], [0, 0, 0, 0xff, 1, 2]);
+
+    buf.clear();
+    let (_, range, _) = socket.try_recv_from_buf(&mut buf).unwrap().unwrap();
+    assert_eq!(&buf[range], b"valid");
+}
+
+#[tokio::test]
+async fn datagrams_of_other_sources_are_skipped() {
+    let (socket, peer, addr) = pair().await;
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.set_expected_client(client.local_addr().unwrap(), ClientMatch::Strict);
+
+    send(&peer, addr, b"filtered").await;
+    send(&client, addr, b"accepted").await;
+    socket.get_ref().readable().await.unwrap();
+
+    let (pkt, _, src) = socket.try_recv_from().unwrap().unwrap();
+    assert_eq!(pkt, &b"accepted"[..]);
+    assert_eq!(src, client.local_addr().unwrap());
+    assert_eq!(socket.filtered_count(), 1);
+
+    // only a filtered datagram is ready, so it is dropped and nothing is returned
+    send(&peer, addr, b"filtered").await;
+    socket.get_ref().readable().await.unwrap();
+    assert!(socket.try_recv_from().unwrap().is_none());
+    assert_eq!(socket.filtered_count(), 2);
+}
+
+#[tokio::test]
+async fn packet_is_sent_without_waiting() {
+    let (socket, peer, _) = pair().await;
+    let header = UdpHeader::new(0, Address::SocketAddress(DST));
+
+    socket.get_ref().writable().await.unwrap();
+    let len = socket
+        .try_send_to(b"hello", &header, peer.local_addr().unwrap())
+        .unwrap()
+        .unwrap();
+    assert_eq!(len, 5);
+
+    let mut buf = [0; 1500];
+    let (len, _) = peer.recv_from(&mut buf).await.unwrap();
+    let mut pkt = &buf[..len];
+    assert_eq!(
+        UdpHeader::read_from_buf(&mut pkt).unwrap().address,
+        Address::SocketAddress(DST)
+    );
+    assert_eq!(pkt, b"hello");
+
+    assert_eq!(socket.stats().snapshot().downlink_packets, 1);
+}
+
+#[tokio::test]
+async fn split_halves_try_io() {
+    let (socket, peer, addr) = pair().await;
+    let (recv, send_half) = socket.split();
+    let header = UdpHeader::new(0, Address::SocketAddress(DST));
+
+    assert!(recv.try_recv_from().unwrap().is_none());
+
+    send(&peer, addr, b"ping").await;
+    recv.get_ref().readable().await.unwrap();
+    let (pkt, _, src) = recv.try_recv_from().unwrap().unwrap();
+    assert_eq!(pkt, &b"ping"[..]);
+
+    send_half.get_ref().writable().await.unwrap();
+    assert_eq!(
+        send_half.try_send_to(b"pong", &header, src).unwrap(),
+        Some(4)
+    );
+}
@@ -0,0 +1,168 @@
+use bytes::BytesMut;
+use socks5_server::{
+    connection::associate::{AssociatedUdpSocket, ClientMatch, UdpAssociationTable},
+    proto::{Address, UdpHeader},
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+/// Returns the v4-mapped IPv6 form of `addr`, as a dual-stack socket reports it.
+fn mapped(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), addr.port()),
+        IpAddr::V6(_) => addr,
+    }
+}
+
+/// Binds a dual-stack socket, returning it with the IPv4 address to reach it on, or `None` if IPv6 is not available.
+async fn dual_stack() -> Option<(AssociatedUdpSocket, SocketAddr)> {
+    let Ok(socket) = UdpSocket::bind("[::]:0").await else {
+        eprintln!("IPv6 is not available, skipping");
+        return None;
+    };
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, socket.local_addr().unwrap().port()));
+    Some((AssociatedUdpSocket::new(socket, 1500), addr))
+}
+
+async fn send(peer: &UdpSocket, addr: SocketAddr, pkt: &[u8]) {
+    let mut buf = BytesMut::new();
+    UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    )
+    .write_to_buf(&mut buf);
+    buf.extend_from_slice(pkt);
+    peer.send_to(&buf, addr).await.unwrap();
+}
+
+#[tokio::test]
+async fn ipv4_client_is_matched_on_a_dual_stack_socket() {
+    let Some((socket, addr)) = dual_stack().await else {
+        return;
+    };
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+
+    // recorded in IPv4 form, e.g. from the TCP connection, while the socket reports the v4-mapped form
+    socket.set_expected_client(peer_addr, ClientMatch::Strict);
+    send(&peer, addr, b"mapped").await;
+
+    let (pkt, _, src) = socket.recv_from().await.unwrap();
+    assert_eq!(pkt, &b"mapped"[..]);
+    assert_eq!(src, mapped(peer_addr));
+    assert_eq!(socket.filtered_count(), 0);
+}
+
+#[tokio::test]
+async fn mapped_client_is_matched_on_an_ipv4_socket() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.local_addr().unwrap();
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer_addr = peer.local_addr().unwrap();
+
+    // recorded from e.g. a dual-stack TCP listener
+    socket.set_expected_client(mapped(peer_addr), ClientMatch::Strict);
+    send(&peer, addr, b"plain").await;
+
+    let (pkt, _, src) = socket.recv_from().await.unwrap();
+    assert_eq!(pkt, &b"plain"[..]);
+    assert_eq!(src, peer_addr);
+    assert_eq!(socket.filtered_count(), 0);
+}
+
+#[tokio::test]
+async fn rebinding_client_is_followed_across_forms() {
+    let Some((socket, addr)) = dual_stack().await else {
+        return;
+    };
+
+    let old = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let new = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    socket.set_expected_client(
+        old.local_addr().unwrap(),
+        ClientMatch::Rebind {
+            min_interval: Duration::ZERO,
+        },
+    );
+
+    // the same endpoint in the other form is not a rebinding
+    send(&old, addr, b"old").await;
+    socket.recv_from().await.unwrap();
+    assert_eq!(socket.stats().snapshot().client_rebinds, 0);
+
+    send(&new, addr, b"new").await;
+    let (pkt, _, _) = socket.recv_from().await.unwrap();
+    assert_eq!(pkt, &b"new"[..]);
+    assert_eq!(
+        socket.expected_client(),
+        Some(mapped(new.local_addr().unwrap()))
+    );
+    assert_eq!(socket.stats().snapshot().client_rebinds, 1);
+    assert_eq!(socket.filtered_count(), 0);
+}
+
+#[tokio::test]
+async fn mapped_unspecified_declaration_is_learned() {
+    let Some((socket, addr)) = dual_stack().await else {
+        return;
+    };
+
+    let declared = Address::SocketAddress(mapped(SocketAddr::from(([0, 0, 0, 0], 5000))));
+    socket.set_declared_client(
+        &declared,
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        ClientMatch::Strict,
+    );
+    assert_eq!(socket.expected_client(), None);
+
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    send(&peer, addr, b"learned").await;
+
+    let (_, _, src) = socket.recv_from().await.unwrap();
+    assert_eq!(src, mapped(peer.local_addr().unwrap()));
+    assert_eq!(socket.expected_client(), Some(src));
+}
+
+#[test]
+fn client_match_compares_canonical_forms() {
+    let v4 = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let other_port = SocketAddr::from(([127, 0, 0, 1], 5001));
+    let v6 = SocketAddr::from((Ipv6Addr::LOCALHOST, 5000));
+
+    assert!(ClientMatch::Strict.matches(v4, mapped(v4)));
+    assert!(ClientMatch::Strict.matches(mapped(v4), v4));
+    assert!(!ClientMatch::Strict.matches(v4, mapped(other_port)));
+    assert!(ClientMatch::SameIpAnyPort.matches(v4, mapped(other_port)));
+
+    // `::1` is not v4-mapped, so it stays distinct from `127.0.0.1`
+    assert!(!ClientMatch::Strict.matches(v4, v6));
+    assert!(!ClientMatch::SameIpAnyPort.matches(v4, v6));
+}
+
+#[test]
+fn table_entries_are_found_in_either_form() {
+    let table = UdpAssociationTable::<u8>::new(16, Duration::from_secs(60), Some(1));
+    let client = SocketAddr::from(([127, 0, 0, 1], 5000));
+    let dst = SocketAddr::from(([192, 0, 2, 1], 53));
+
+    assert!(table.insert(client, dst, 1));
+
+    assert_eq!(table.get(mapped(client), mapped(dst)), Some(1));
+    assert!(table.contains(mapped(client), dst));
+    assert_eq!(table.destinations(mapped(client)), 1);
+
+    // the same destination in the other form is not a new one counting towards the cap
+    assert!(table.insert(mapped(client), mapped(dst), 2));
+    assert_eq!(table.len(), 1);
+    assert_eq!(table.get(client, dst), Some(2));
+
+    assert_eq!(table.remove_client(mapped(client)), 1);
+    assert!(table.is_empty());
+}
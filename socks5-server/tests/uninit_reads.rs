@@ -0,0 +1,84 @@
+use bytes::{BufMut, BytesMut};
+use socks5_server::{
+    connection::associate::AssociatedUdpSocket,
+    proto::{
+        handshake::{password::Request as PasswordRequest, Method, Request as HandshakeRequest},
+        Address, Request, UdpHeader,
+    },
+};
+use std::{io::ErrorKind, net::SocketAddr};
+use tokio::{
+    io::{self, AsyncWriteExt},
+    net::UdpSocket,
+    task,
+};
+
+/// Writes `bytes` to a pipe one byte at a time, so every read of the other end is a partial one.
+fn trickle(bytes: Vec<u8>) -> io::DuplexStream {
+    let (mut tx, rx) = io::duplex(1);
+
+    tokio::spawn(async move {
+        for byte in bytes {
+            tx.write_all(&[byte]).await.unwrap();
+            task::yield_now().await;
+        }
+    });
+
+    rx
+}
+
+#[tokio::test]
+async fn partial_reads_fill_the_whole_field() {
+    let mut buf = BytesMut::new();
+    PasswordRequest::new(b"user".to_vec(), b"a longer password".to_vec()).write_to_buf(&mut buf);
+
+    let req = PasswordRequest::read_from(&mut trickle(buf.to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(req.username, b"user");
+    assert_eq!(req.password, b"a longer password");
+
+    let mut buf = BytesMut::new();
+    HandshakeRequest::new(vec![Method::NONE, Method::PASSWORD]).write_to_buf(&mut buf);
+
+    let req = HandshakeRequest::read_from(&mut trickle(buf.to_vec()))
+        .await
+        .unwrap();
+    assert_eq!(req.methods, [Method::NONE, Method::PASSWORD]);
+}
+
+#[tokio::test]
+async fn truncated_field_is_an_eof() {
+    // a domain address of 11 bytes, cut after 3 of them
+    let mut buf = vec![0x05, 0x01, 0x00, 0x03, 11];
+    buf.extend_from_slice(b"exa");
+
+    let err = Request::read_from(&mut trickle(buf)).await.unwrap_err();
+    assert_eq!(std::io::Error::from(err).kind(), ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn stale_capacity_is_not_exposed() {
+    let socket = AssociatedUdpSocket::new(UdpSocket::bind("127.0.0.1:0").await.unwrap(), 1500);
+    let addr = socket.get_ref().local_addr().unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    // a reused buffer whose capacity still holds the bytes of a larger datagram
+    let mut buf = BytesMut::with_capacity(2048);
+    buf.put_bytes(0xaa, 2048);
+    buf.clear();
+
+    let mut pkt = BytesMut::new();
+    UdpHeader::new(
+        0,
+        Address::SocketAddress(SocketAddr::from(([1, 2, 3, 4], 5))),
+    )
+    .write_to_buf(&mut pkt);
+    pkt.extend_from_slice(b"short");
+    peer.send_to(&pkt, addr).await.unwrap();
+
+    let (_, range, _) = socket.recv_from_buf(&mut buf).await.unwrap();
+    assert_eq!(buf.len(), pkt.len());
+    assert_eq!(&buf[..], &pkt[..]);
+    assert_eq!(&buf[range], b"short");
+}
@@ -0,0 +1,103 @@
+#![cfg(unix)]
+
+use socks5_server::{
+    auth::NoAuth,
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Reply, Request, Response,
+    },
+    Command, Server,
+};
+use std::{
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    process,
+    sync::Arc,
+};
+use tokio::{
+    io::{self, AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+};
+
+/// Returns a fresh socket path in the temporary directory, unique to this process and `name`.
+fn socket_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("socks5-server-{}-{name}.sock", process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[tokio::test]
+async fn connect_over_a_unix_socket() {
+    let path = socket_path("connect");
+    let server = Server::new(
+        UnixListener::bind(&path).unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+
+    let task = tokio::spawn(async move {
+        let (conn, peer) = server.accept().await.unwrap();
+        assert_eq!(peer, SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)));
+        assert!(conn.peer_addr().unwrap().is_unnamed());
+
+        let (conn, ()) = conn.authenticate().await.unwrap();
+        let Command::Connect(connect, dst) = conn.wait().await.unwrap() else {
+            unreachable!()
+        };
+        assert_eq!(dst, Address::DomainAddress(b"example.com".to_vec(), 443));
+
+        let connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+        assert!(connect.local_addr().unwrap().as_pathname().is_some());
+
+        let (mut rx, mut tx) = io::split(connect);
+        let _ = io::copy(&mut rx, &mut tx).await;
+    });
+
+    let mut client = UnixStream::connect(&path).await.unwrap();
+
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut client)
+        .await
+        .unwrap();
+    let resp = HandshakeResponse::read_from(&mut client).await.unwrap();
+    assert_eq!(resp.method, Method::NONE);
+
+    Request::new(
+        ProtoCommand::Connect,
+        Address::DomainAddress(b"example.com".to_vec(), 443),
+    )
+    .write_to(&mut client)
+    .await
+    .unwrap();
+    let resp = Response::read_from(&mut client).await.unwrap();
+    assert_eq!(resp.reply, Reply::Succeeded);
+
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0; 5];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+
+    drop(client);
+    task.await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn local_addr_is_the_one_of_the_listener() {
+    let path = socket_path("local-addr");
+    let server = Server::new(
+        UnixListener::bind(&path).unwrap(),
+        Arc::new(NoAuth) as Arc<_>,
+    );
+
+    let err = server.local_addr().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Unsupported);
+
+    let addr = server.get_ref().local_addr().unwrap();
+    assert_eq!(addr.as_pathname(), Some(path.as_path()));
+
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use common::Client;
+use socks5_server::{
+    auth::{NoAuth, Password},
+    proto::{
+        handshake::{
+            password::{Request as PasswordRequest, Response as PasswordResponse},
+            Method, Request as HandshakeRequest, Response as HandshakeResponse,
+        },
+        Address, Command as ProtoCommand, Reply, Request, Response,
+    },
+    tap::{Direction, HexDumpTap, WireTap},
+    Auth, Command, Server,
+};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{io, net::TcpListener};
+
+type Captured = Arc<Mutex<Vec<(Direction, Vec<u8>)>>>;
+
+/// Returns a tap recording every chunk it sees.
+fn recorder() -> (WireTap, Captured) {
+    let captured = Captured::default();
+    let log = captured.clone();
+    let tap = WireTap::new(move |direction, bytes: &[u8]| {
+        log.lock().unwrap().push((direction, bytes.to_vec()))
+    });
+
+    (tap, captured)
+}
+
+/// Starts a server tapped by `tap`, replying `Succeeded` to `Connect` commands and echoing the payload back.
+async fn serve<A: Send + 'static>(
+    auth: Arc<dyn Auth<Output = A> + Send + Sync>,
+    tap: WireTap,
+) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut server = Server::new(listener, auth);
+    server.set_wire_tap(tap);
+    let addr = server.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((conn, _)) = server.accept().await {
+            tokio::spawn(async move {
+                let (conn, _) = conn.authenticate().await.unwrap();
+                let Command::Connect(connect, _) = conn.wait().await.unwrap() else {
+                    unreachable!()
+                };
+
+                let connect = connect
+                    .reply(Reply::Succeeded, Address::unspecified())
+                    .await
+                    .unwrap();
+
+                let (mut rx, mut tx) = io::split(connect);
+                let _ = io::copy(&mut rx, &mut tx).await;
+            });
+        }
+    });
+
+    addr
+}
+
+fn encode(write: impl FnOnce(&mut bytes::BytesMut)) -> Vec<u8> {
+    let mut buf = bytes::BytesMut::new();
+    write(&mut buf);
+    buf.to_vec()
+}
+
+fn request() -> Vec<u8> {
+    let dst = Address::DomainAddress(b"example.com".to_vec(), 443);
+    encode(|buf| Request::new(ProtoCommand::Connect, dst).write_to_buf(buf))
+}
+
+fn reply() -> Vec<u8> {
+    encode(|buf| Response::new(Reply::Succeeded, Address::unspecified()).write_to_buf(buf))
+}
+
+#[tokio::test]
+async fn tap_sees_the_exact_negotiation_bytes() {
+    let (tap, captured) = recorder();
+    let addr = serve(Arc::new(NoAuth) as Arc<_>, tap).await;
+
+    // the handshake, the request and the first payload bytes pipelined in one segment
+    let handshake = encode(|buf| HandshakeRequest::new(vec![Method::NONE]).write_to_buf(buf));
+    let mut client = Client::connect(addr).await;
+    client
+        .write(&[handshake.clone(), request(), b"hello".to_vec()].concat())
+        .await;
+
+    assert_eq!(client.read(2).await, [0x05, 0x00]);
+    assert_eq!(client.read(10).await, reply());
+    assert_eq!(client.read(5).await, b"hello");
+
+    // the relayed payload is not captured, in either direction
+    client.write(b"world").await;
+    assert_eq!(client.read(5).await, b"world");
+
+    assert_eq!(
+        *captured.lock().unwrap(),
+        [
+            (Direction::Inbound, handshake),
+            (Direction::Outbound, vec![0x05, 0x00]),
+            (Direction::Inbound, request()),
+            (Direction::Outbound, reply()),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn credentials_are_only_captured_if_asked() {
+    let sub_negotiation =
+        encode(|buf| PasswordRequest::new(b"user".to_vec(), b"secret".to_vec()).write_to_buf(buf));
+    let success = encode(|buf| PasswordResponse::new(true).write_to_buf(buf));
+
+    for capture_credentials in [false, true] {
+        let (tap, captured) = recorder();
+        let auth = Arc::new(Password::new(b"user".to_vec(), b"secret".to_vec()));
+        let addr = serve(auth as Arc<_>, tap.capture_credentials(capture_credentials)).await;
+
+        let mut client = Client::connect(addr).await;
+        assert_eq!(
+            client.handshake(&[Method::PASSWORD]).await,
+            Method::PASSWORD
+        );
+        assert!(client.password(b"user", b"secret").await);
+        let resp = client
+            .request(
+                ProtoCommand::Connect,
+                Address::DomainAddress(b"example.com".to_vec(), 443),
+            )
+            .await;
+        assert_eq!(resp.reply, Reply::Succeeded);
+
+        let handshake =
+            encode(|buf| HandshakeRequest::new(vec![Method::PASSWORD]).write_to_buf(buf));
+        let chosen = encode(|buf| HandshakeResponse::new(Method::PASSWORD).write_to_buf(buf));
+
+        let mut expected = vec![
+            (Direction::Inbound, handshake),
+            (Direction::Outbound, chosen),
+        ];
+
+        if capture_credentials {
+            expected.push((Direction::Inbound, sub_negotiation.clone()));
+            expected.push((Direction::Outbound, success.clone()));
+        }
+
+        expected.push((Direction::Inbound, request()));
+        expected.push((Direction::Outbound, reply()));
+
+        assert_eq!(*captured.lock().unwrap(), expected);
+    }
+}
+
+#[tokio::test]
+async fn connection_tap_replaces_the_server_one() {
+    let (server_tap, server_captured) = recorder();
+    let (conn_tap, conn_captured) = recorder();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_wire_tap(server_tap);
+    let addr = server.local_addr().unwrap();
+
+    let task = tokio::spawn(async move {
+        let (mut conn, _) = server.accept().await.unwrap();
+        conn.set_wire_tap(conn_tap);
+
+        let (mut conn, ()) = conn.authenticate().await.unwrap();
+        conn.clear_wire_tap();
+        let _ = conn.wait().await.unwrap();
+    });
+
+    let mut client = Client::connect(addr).await;
+    assert_eq!(client.handshake(&[Method::NONE]).await, Method::NONE);
+    client.write(&request()).await;
+    task.await.unwrap();
+
+    assert!(server_captured.lock().unwrap().is_empty());
+    assert_eq!(
+        *conn_captured.lock().unwrap(),
+        [
+            (Direction::Inbound, vec![0x05, 0x01, 0x00]),
+            (Direction::Outbound, vec![0x05, 0x00]),
+        ]
+    );
+}
+
+#[test]
+fn hex_dump_format() {
+    let dump = HexDumpTap::new(std::io::sink()).with_label("127.0.0.1:5000");
+
+    assert_eq!(
+        dump.format(Direction::Inbound, &[0x05, 0x01, 0x00]),
+        "127.0.0.1:5000 < 00000000  05 01 00                                          |...|\n"
+    );
+
+    let mut req = vec![0x05, 0x01, 0x00, 0x03, 11];
+    req.extend_from_slice(b"example.com");
+    req.extend_from_slice(&[0x01, 0xbb]);
+
+    assert_eq!(
+        HexDumpTap::new(std::io::sink()).format(Direction::Outbound, &req),
+        "> 00000000  05 01 00 03 0b 65 78 61  6d 70 6c 65 2e 63 6f 6d  |.....example.com|\n\
+         > 00000010  01 bb                                             |..|\n"
+    );
+}
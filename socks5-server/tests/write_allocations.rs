@@ -0,0 +1,117 @@
+use socks5_server::{
+    auth::NoAuth,
+    proto::{
+        handshake::{Method, Request as HandshakeRequest, Response as HandshakeResponse},
+        Address, Command as ProtoCommand, Reply, Request, Response,
+    },
+    Command, Server,
+};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    future::Future,
+    net::Ipv4Addr,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Counts the allocations of the current thread, as the tests run in parallel.
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Awaits `fut`, returning its output alongside the number of allocations it made.
+async fn allocations<F: Future>(fut: F) -> (F::Output, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let output = fut.await;
+    (output, ALLOCATIONS.with(Cell::get) - before)
+}
+
+/// Returns a client that completed the handshake and sent a request of `command` to `server`, alongside the command accepted.
+async fn command(server: &Server<()>, command: ProtoCommand) -> (TcpStream, Command) {
+    let mut client = TcpStream::connect(server.local_addr().unwrap())
+        .await
+        .unwrap();
+    HandshakeRequest::new(vec![Method::NONE])
+        .write_to(&mut client)
+        .await
+        .unwrap();
+    Request::new(command, Address::unspecified())
+        .write_to(&mut client)
+        .await
+        .unwrap();
+
+    let (conn, _) = server.accept().await.unwrap();
+    let (conn, ()) = conn.authenticate().await.unwrap();
+    let command = conn.wait().await.unwrap();
+
+    let resp = HandshakeResponse::read_from(&mut client).await.unwrap();
+    assert_eq!(resp.method, Method::NONE);
+
+    (client, command)
+}
+
+async fn assert_replies_do_not_allocate(server: &Server<()>) {
+    let (mut client, Command::Connect(connect, _)) = command(server, ProtoCommand::Connect).await
+    else {
+        unreachable!()
+    };
+    let (res, count) = allocations(connect.reply(Reply::Succeeded, Address::unspecified())).await;
+    res.unwrap();
+    assert_eq!(count, 0);
+    Response::read_from(&mut client).await.unwrap();
+
+    let (mut client, Command::Bind(bind, _)) = command(server, ProtoCommand::Bind).await else {
+        unreachable!()
+    };
+    let (res, count) = allocations(bind.reply(Reply::Succeeded, Address::unspecified())).await;
+    assert_eq!(count, 0);
+    let (res, count) =
+        allocations(res.unwrap().reply(Reply::Succeeded, Address::unspecified())).await;
+    res.unwrap();
+    assert_eq!(count, 0);
+    Response::read_from(&mut client).await.unwrap();
+    Response::read_from(&mut client).await.unwrap();
+
+    let (mut client, Command::Associate(associate, _)) =
+        command(server, ProtoCommand::Associate).await
+    else {
+        unreachable!()
+    };
+    let (res, count) = allocations(associate.reply(Reply::Succeeded, Address::unspecified())).await;
+    res.unwrap();
+    assert_eq!(count, 0);
+    Response::read_from(&mut client).await.unwrap();
+}
+
+#[tokio::test]
+async fn replies_do_not_allocate() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    assert_replies_do_not_allocate(&server).await;
+}
+
+#[tokio::test]
+async fn replies_under_a_setup_deadline_do_not_allocate() {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+    let mut server = Server::new(listener, Arc::new(NoAuth) as Arc<_>);
+    server.set_session_setup_deadline(Duration::from_secs(10));
+    assert_replies_do_not_allocate(&server).await;
+}